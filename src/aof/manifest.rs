@@ -0,0 +1,122 @@
+use std::fmt::Display;
+
+/// Describes which files make up the append only file. There is at most one base,
+/// which is a snapshot of the dataset, and then the incremental files with every
+/// write since, which are replayed in order on top of it.
+/// https://redis.io/docs/latest/operate/oss_and_stack/management/persistence/#append-only-file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    pub base: Option<ManifestFile>,
+    pub incrs: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestFile {
+    pub name: String,
+    pub seq: u64,
+}
+
+impl Manifest {
+    pub fn parse(contents: &str) -> Result<Self, anyhow::Error> {
+        let mut manifest = Manifest::default();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let mut name: Option<&str> = None;
+            let mut seq: Option<u64> = None;
+            let mut file_type: Option<&str> = None;
+
+            for pair in parts.chunks(2) {
+                match pair {
+                    ["file", value] => name = Some(value),
+                    ["seq", value] => seq = Some(value.parse::<u64>()?),
+                    ["type", value] => file_type = Some(value),
+                    // Unknown keys are ignored so newer manifests can still be read
+                    [_, _] => {}
+                    _ => anyhow::bail!("Invalid AOF manifest line: {}", line),
+                }
+            }
+
+            let (name, seq, file_type) = match (name, seq, file_type) {
+                (Some(name), Some(seq), Some(file_type)) => (name, seq, file_type),
+                _ => anyhow::bail!("Invalid AOF manifest line: {}", line),
+            };
+
+            let file = ManifestFile {
+                name: name.to_string(),
+                seq,
+            };
+            match file_type {
+                "b" if manifest.base.is_some() => {
+                    anyhow::bail!("Found duplicate base file information")
+                }
+                "b" => manifest.base = Some(file),
+                "i" => manifest.incrs.push(file),
+                // History files are left over from a rewrite and waiting to be deleted
+                "h" => {}
+                other => anyhow::bail!("Unknown AOF file type: {}", other),
+            }
+        }
+
+        manifest.incrs.sort_by_key(|file| file.seq);
+        Ok(manifest)
+    }
+
+    pub fn next_base_seq(&self) -> u64 {
+        self.base.as_ref().map(|base| base.seq + 1).unwrap_or(1)
+    }
+
+    pub fn next_incr_seq(&self) -> u64 {
+        self.incrs.last().map(|incr| incr.seq + 1).unwrap_or(1)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &ManifestFile> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+}
+
+impl Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(base) = &self.base {
+            writeln!(f, "file {} seq {} type b", base.name, base.seq)?;
+        }
+        for incr in self.incrs.iter() {
+            writeln!(f, "file {} seq {} type i", incr.name, incr.seq)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_round_trip() {
+        let contents = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+                        file appendonly.aof.1.incr.aof seq 1 type h\n\
+                        file appendonly.aof.3.incr.aof seq 3 type i\n\
+                        file appendonly.aof.2.incr.aof seq 2 type i\n";
+
+        let manifest = Manifest::parse(contents).unwrap();
+        assert_eq!(manifest.base.as_ref().unwrap().seq, 2);
+        assert_eq!(manifest.incrs.len(), 2);
+        assert_eq!(manifest.incrs[0].name, "appendonly.aof.2.incr.aof");
+        assert_eq!(manifest.next_base_seq(), 3);
+        assert_eq!(manifest.next_incr_seq(), 4);
+
+        assert_eq!(Manifest::parse(&manifest.to_string()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn manifest_rejects_bad_lines() {
+        assert!(Manifest::parse("file appendonly.aof.1.base.rdb seq 1").is_err());
+        assert!(Manifest::parse("file a seq 1 type b\nfile b seq 2 type b").is_err());
+    }
+}
@@ -0,0 +1,636 @@
+mod manifest;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::Config;
+use crate::data::{Database, Snapshot};
+use crate::utils::FrameLimits;
+use crate::{commands, encoding, extension, request, utils};
+
+pub use manifest::{Manifest, ManifestFile};
+
+const RDB_PREAMBLE: &[u8] = b"REDIS";
+
+/// The append only file. Like redis 7 it is made up of several files in its own
+/// directory: a base snapshot of the dataset and incremental files that write
+/// commands are appended to as they are applied, all listed in a manifest.
+/// Rewrites switch to a new incremental file and write a new base in the background,
+/// so nothing that came in during the rewrite ever has to be copied.
+pub struct Aof(Arc<Mutex<AofState>>);
+
+impl Clone for Aof {
+    fn clone(&self) -> Self {
+        Aof(self.0.clone())
+    }
+}
+
+pub struct AofState {
+    dir: PathBuf,
+    file_name: String,
+    manifest: Manifest,
+    // The incremental file being appended to, only open when appendonly is enabled
+    file: Option<File>,
+    // The database the commands appended to the file last selected, `None` when the
+    // next command has to select its database whichever one it is
+    selected_db: Option<usize>,
+    rewriting: bool,
+}
+
+/// What `not-redis-check-aof` found in one of the files making up the AOF.
+#[derive(Debug, PartialEq)]
+pub struct AofCheck {
+    /// How many commands could be read before the end of the file or the corruption.
+    pub commands: usize,
+    pub corruption: Option<AofCorruption>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AofCorruption {
+    /// Where the first entry that couldn't be read starts. Everything before it is valid.
+    pub offset: u64,
+    pub reason: String,
+    /// Whether truncating the file at `offset` leaves a loadable file. An unreadable
+    /// RDB preamble can't be fixed that way.
+    pub fixable: bool,
+}
+
+#[derive(Debug)]
+pub struct AofStatus {
+    pub enabled: bool,
+    pub rewriting: bool,
+    pub current_size: u64,
+    pub base_size: u64,
+}
+
+impl AofState {
+    /// Appends a write made in database `db`, selecting it first if the file
+    /// was in another one.
+    pub fn append(&mut self, db: usize, command: &[u8]) -> Result<(), anyhow::Error> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+
+        if self.selected_db != Some(db) {
+            let select = encoding::encode_string_array(&["SELECT", &db.to_string()]);
+            file.write_all(select.as_bytes())
+                .context("Writing to the append only file")?;
+            self.selected_db = Some(db);
+        }
+        file.write_all(command)
+            .context("Writing to the append only file")
+    }
+
+    /// Flushes everything appended so far to disk.
+    pub fn sync(&mut self) -> Result<(), anyhow::Error> {
+        match self.file.as_mut() {
+            Some(file) => file.sync_all().context("Syncing the append only file"),
+            None => Ok(()),
+        }
+    }
+
+    fn path(&self, file: &ManifestFile) -> PathBuf {
+        self.dir.join(&file.name)
+    }
+
+    /// Starts a new incremental file and records it in the manifest so every
+    /// write from now on lands in it.
+    fn open_next_incr(&mut self) -> Result<(), anyhow::Error> {
+        let seq = self.manifest.next_incr_seq();
+        let incr = ManifestFile {
+            name: format!("{}.{}.incr.aof", self.file_name, seq),
+            seq,
+        };
+
+        let file = open_for_append(&self.path(&incr))?;
+        self.manifest.incrs.push(incr);
+        write_manifest(&self.dir, &self.file_name, &self.manifest)?;
+        self.file = Some(file);
+        // Every file is replayed from the first database
+        self.selected_db = Some(0);
+
+        Ok(())
+    }
+}
+
+impl Aof {
+    pub fn disabled() -> Self {
+        Aof::new(PathBuf::new(), String::new(), Manifest::default(), None)
+    }
+
+    /// Opens the append only file for writing. If there isn't one yet, the current
+    /// dataset becomes its base so nothing loaded from the RDB file is lost.
+    pub fn open(config: &Config, database: &Database) -> Result<Self, anyhow::Error> {
+        let dir = config.aof_dir();
+        let file_name = config.append_file_name().to_string();
+
+        if !config.append_only {
+            let manifest = read_manifest(&dir, &file_name)?.unwrap_or_default();
+            return Ok(Aof::new(dir, file_name, manifest, None));
+        }
+
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Creating append only directory {}", dir.display()))?;
+
+        let manifest = match read_manifest(&dir, &file_name)? {
+            Some(manifest) => manifest,
+            None => create_manifest(config, database)?,
+        };
+
+        let mut state = AofState {
+            dir,
+            file_name,
+            manifest,
+            file: None,
+            // Whatever the last file ended up selecting isn't known
+            selected_db: None,
+            rewriting: false,
+        };
+
+        match state.manifest.incrs.last() {
+            Some(incr) => state.file = Some(open_for_append(&state.path(incr))?),
+            None => state.open_next_incr()?,
+        }
+
+        Ok(Aof(Arc::new(Mutex::new(state))))
+    }
+
+    fn new(dir: PathBuf, file_name: String, manifest: Manifest, file: Option<File>) -> Self {
+        let state = AofState {
+            dir,
+            file_name,
+            manifest,
+            file,
+            selected_db: None,
+            rewriting: false,
+        };
+
+        Aof(Arc::new(Mutex::new(state)))
+    }
+
+    /// Write commands hold the lock while they are applied and appended so
+    /// a rewrite never takes its snapshot in between the two.
+    pub async fn lock(&self) -> MutexGuard<'_, AofState> {
+        self.0.lock().await
+    }
+
+    pub async fn status(&self) -> AofStatus {
+        let state = self.0.lock().await;
+        let size = |file: &ManifestFile| {
+            fs::metadata(state.path(file))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0)
+        };
+
+        AofStatus {
+            enabled: state.file.is_some(),
+            rewriting: state.rewriting,
+            current_size: state.manifest.files().map(size).sum(),
+            base_size: state.manifest.base.as_ref().map(size).unwrap_or(0),
+        }
+    }
+
+    /// Like redis, an automatic rewrite happens once the file is at least `min_size`
+    /// and has grown by `percentage` since the last rewrite.
+    pub async fn should_rewrite(&self, percentage: u64, min_size: u64) -> bool {
+        let status = self.status().await;
+        if !status.enabled || status.rewriting || percentage == 0 {
+            return false;
+        }
+
+        if status.current_size < min_size {
+            return false;
+        }
+
+        let base_size = status.base_size.max(1);
+        let growth = status.current_size.saturating_sub(base_size) * 100 / base_size;
+        growth >= percentage
+    }
+
+    /// Starts rewriting the file from the current dataset in the background. Only
+    /// taking the snapshot and switching to a new incremental file hold the lock;
+    /// the snapshot is serialized and written out on a blocking thread.
+    pub async fn rewrite(&self, database: &Database, compress: bool) -> Result<(), anyhow::Error> {
+        let (snapshot, base, first_incr_seq) = {
+            let mut state = self.0.lock().await;
+            if state.rewriting {
+                anyhow::bail!("ERR Background append only file rewriting already in progress");
+            }
+
+            fs::create_dir_all(&state.dir).with_context(|| {
+                format!("Creating append only directory {}", state.dir.display())
+            })?;
+
+            let snapshot = database.snapshot()?;
+            if state.file.is_some() {
+                state.open_next_incr()?;
+            }
+
+            let seq = state.manifest.next_base_seq();
+            let base = ManifestFile {
+                name: format!("{}.{}.base.rdb", state.file_name, seq),
+                seq,
+            };
+            let first_incr_seq = state.manifest.incrs.last().map(|incr| incr.seq);
+            state.rewriting = true;
+
+            (snapshot, base, first_incr_seq)
+        };
+
+        let aof = self.clone();
+        database.tasks().spawn("AOF rewrite", async move {
+            let result = aof
+                .finish_rewrite(snapshot, compress, base, first_incr_seq)
+                .await;
+            match result {
+                Ok(_) => println!("Background AOF rewrite finished successfully"),
+                Err(e) => eprintln!("Background AOF rewrite failed: {}", e),
+            }
+            aof.0.lock().await.rewriting = false;
+        });
+
+        Ok(())
+    }
+
+    async fn finish_rewrite(
+        &self,
+        snapshot: Snapshot,
+        compress: bool,
+        base: ManifestFile,
+        first_incr_seq: Option<u64>,
+    ) -> Result<(), anyhow::Error> {
+        let (dir, file_name) = {
+            let state = self.0.lock().await;
+            (state.dir.clone(), state.file_name.clone())
+        };
+
+        let temp_path = dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let base_path = dir.join(&base.name);
+        tokio::task::spawn_blocking(move || -> Result<(), anyhow::Error> {
+            write_and_sync(&temp_path, &snapshot.to_rdb(compress)?)?;
+            fs::rename(&temp_path, &base_path).with_context(|| {
+                format!(
+                    "Renaming {} to {}",
+                    temp_path.display(),
+                    base_path.display()
+                )
+            })
+        })
+        .await??;
+
+        // The new base replaces the old one along with every incremental file that
+        // was written before the rewrite started.
+        let mut state = self.0.lock().await;
+        let mut manifest = state.manifest.clone();
+        manifest.base = Some(base);
+        manifest
+            .incrs
+            .retain(|incr| first_incr_seq.is_some_and(|seq| incr.seq >= seq));
+        write_manifest(&dir, &file_name, &manifest)?;
+
+        let replaced: Vec<PathBuf> = state
+            .manifest
+            .files()
+            .filter(|file| !manifest.files().any(|kept| kept == *file))
+            .map(|file| state.path(file))
+            .collect();
+        state.manifest = manifest;
+        drop(state);
+
+        for path in replaced {
+            if let Err(e) = fs::remove_file(&path) {
+                eprintln!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rebuilds the database from the append only file if there is one: the base
+/// followed by every incremental file in the manifest. Single file AOFs from
+/// before the manifest existed are read as well.
+pub fn load(config: &Config) -> Result<Option<Database>, anyhow::Error> {
+    let dir = config.aof_dir();
+    let file_name = config.append_file_name();
+
+    let manifest = match read_manifest(&dir, file_name)? {
+        Some(manifest) => manifest,
+        None => {
+            let legacy_path = config.aof_path();
+            if !legacy_path.exists() {
+                return Ok(None);
+            }
+
+            let database = load_base(read_file(&legacy_path)?)?;
+            database.clear_dirty();
+            return Ok(Some(database));
+        }
+    };
+
+    let database = match &manifest.base {
+        Some(base) => load_base(read_file(&dir.join(&base.name))?)?,
+        None => Database::new(),
+    };
+
+    for incr in manifest.incrs.iter() {
+        let contents = read_file(&dir.join(&incr.name))?;
+        replay_commands(&database, &contents)?;
+    }
+
+    database.clear_dirty();
+    Ok(Some(database))
+}
+
+// Without a manifest there is either nothing to start from, in which case the
+// dataset becomes the base, or a single file AOF which becomes the base as is.
+fn create_manifest(config: &Config, database: &Database) -> Result<Manifest, anyhow::Error> {
+    let dir = config.aof_dir();
+    let file_name = config.append_file_name();
+    let legacy_path = config.aof_path();
+
+    let base = if legacy_path.exists() {
+        let base = ManifestFile {
+            name: format!("{}.1.base.aof", file_name),
+            seq: 1,
+        };
+        fs::rename(&legacy_path, dir.join(&base.name))
+            .with_context(|| format!("Moving {} into {}", legacy_path.display(), dir.display()))?;
+        base
+    } else {
+        let base = ManifestFile {
+            name: format!("{}.1.base.rdb", file_name),
+            seq: 1,
+        };
+        write_and_sync(
+            &dir.join(&base.name),
+            &database.to_rdb(config.rdb_compression)?,
+        )?;
+        base
+    };
+
+    let manifest = Manifest {
+        base: Some(base),
+        incrs: vec![],
+    };
+    write_manifest(&dir, file_name, &manifest)?;
+
+    Ok(manifest)
+}
+
+fn manifest_path(dir: &Path, file_name: &str) -> PathBuf {
+    dir.join(format!("{}.manifest", file_name))
+}
+
+fn read_manifest(dir: &Path, file_name: &str) -> Result<Option<Manifest>, anyhow::Error> {
+    let path = manifest_path(dir, file_name);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Reading AOF manifest {}", path.display()))?;
+    Manifest::parse(&contents).map(Some)
+}
+
+// The manifest is what makes a rewrite take effect, so it's replaced atomically.
+fn write_manifest(dir: &Path, file_name: &str, manifest: &Manifest) -> Result<(), anyhow::Error> {
+    let path = manifest_path(dir, file_name);
+    let temp_path = dir.join(format!("temp-{}.manifest", file_name));
+
+    write_and_sync(&temp_path, manifest.to_string().as_bytes())?;
+    fs::rename(&temp_path, &path)
+        .with_context(|| format!("Renaming {} to {}", temp_path.display(), path.display()))
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, anyhow::Error> {
+    fs::read(path).with_context(|| format!("Reading append only file {}", path.display()))
+}
+
+/// A base is either an RDB file or a file of commands, which may start with an RDB preamble.
+fn load_base(contents: Vec<u8>) -> Result<Database, anyhow::Error> {
+    let mut cursor = Cursor::new(contents);
+    let database = if cursor.get_ref().starts_with(RDB_PREAMBLE) {
+        Database::from_rdb(&mut cursor).context("Reading the AOF base")?
+    } else {
+        Database::new()
+    };
+
+    let position = cursor.position() as usize;
+    replay_commands(&database, &cursor.get_ref()[position..])?;
+
+    Ok(database)
+}
+
+/// Checks a single AOF file, base or incremental, the way loading it would read it:
+/// an optional RDB preamble and then commands the server knows how to parse.
+pub fn check_file(contents: Vec<u8>) -> AofCheck {
+    let mut cursor = Cursor::new(contents);
+    if cursor.get_ref().starts_with(RDB_PREAMBLE) {
+        if let Err(e) = Database::from_rdb(&mut cursor) {
+            return AofCheck {
+                commands: 0,
+                corruption: Some(AofCorruption {
+                    offset: cursor.position(),
+                    reason: format!("Unreadable RDB preamble: {:#}", e),
+                    fixable: false,
+                }),
+            };
+        }
+    }
+
+    let preamble_len = cursor.position();
+    let contents = cursor.get_ref();
+    let mut cursor = Cursor::new(&contents[..]);
+    cursor.set_position(preamble_len);
+    let mut commands = 0;
+
+    while (cursor.position() as usize) < contents.len() {
+        let offset = cursor.position();
+        let reason = match utils::read_frame(&mut cursor, &FrameLimits::unlimited()) {
+            Ok(Some(frame)) => match request::parse_request(frame.data) {
+                Ok(_) => {
+                    commands += 1;
+                    continue;
+                }
+                Err(e) => format!("Unreadable command: {}", e),
+            },
+            Ok(None) => "Truncated command".to_string(),
+            Err(e) => format!("Unreadable command: {}", e),
+        };
+
+        return AofCheck {
+            commands,
+            corruption: Some(AofCorruption {
+                offset,
+                reason,
+                fixable: true,
+            }),
+        };
+    }
+
+    AofCheck {
+        commands,
+        corruption: None,
+    }
+}
+
+fn replay_commands(database: &Database, commands: &[u8]) -> Result<(), anyhow::Error> {
+    let mut cursor = Cursor::new(commands);
+    let mut selected = database.clone();
+
+    while (cursor.position() as usize) < commands.len() {
+        let frame = match utils::read_frame(&mut cursor, &FrameLimits::unlimited()) {
+            Ok(Some(frame)) => frame,
+            // The server may have died halfway through appending the last command.
+            Ok(None) => {
+                eprintln!("Ignoring truncated command at the end of the AOF");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Ignoring unreadable command at the end of the AOF: {}", e);
+                break;
+            }
+        };
+
+        let command = request::parse_request(frame.data)
+            .context("Bad file format reading the append only file")?;
+        match command {
+            request::Command::Select(index) => selected = database.select(index),
+            command => replay(&selected, command),
+        }
+    }
+
+    Ok(())
+}
+
+// Commands that failed when they were first run fail the same way here,
+// so their results are ignored.
+fn replay(database: &Database, command: request::Command) {
+    let _ = match command {
+        request::Command::Set(set_command) => commands::set_value(database, set_command),
+        request::Command::MSet(pairs) => commands::set_multiple(database, pairs),
+        request::Command::MSetNx(pairs) => commands::set_multiple_if_none_exist(database, pairs),
+        request::Command::Del(keys) => commands::delete_keys(database, keys),
+        request::Command::Unlink(keys) => commands::unlink_keys(database, keys),
+        request::Command::FlushDb(asynchronously) => commands::flush(database, asynchronously),
+        request::Command::FlushAll(asynchronously) => commands::flush_all(database, asynchronously),
+        request::Command::SwapDb(first, second) => {
+            commands::swap_databases(database, first, second)
+        }
+        request::Command::Move(key, index) => commands::move_key(database, key, index),
+        request::Command::GetDel(key) => commands::get_delete_key(database, key),
+        request::Command::GetEx(key, expiry) => commands::update_expiration(database, key, expiry),
+        request::Command::Expire(key, expiration, condition) => {
+            commands::expire(database, key, expiration, condition)
+        }
+        request::Command::Persist(key) => commands::persist(database, key),
+        request::Command::Xadd(command) => commands::add_stream(database, command),
+        request::Command::Append(key, value) => commands::append_value(database, key, value),
+        request::Command::SetRange(key, offset, value) => {
+            commands::set_range(database, key, offset, value)
+        }
+        request::Command::Incr(key) => commands::increment_value_by_int(database, key, 1),
+        request::Command::IncrBy(key, amount) => {
+            commands::increment_value_by_int(database, key, amount)
+        }
+        request::Command::IncrByFloat(key, amount) => {
+            commands::increment_value_by_float(database, key, amount)
+        }
+        request::Command::Decr(key) => commands::increment_value_by_int(database, key, -1),
+        request::Command::DecrBy(key, amount) => {
+            commands::increment_value_by_int(database, key, -amount)
+        }
+        request::Command::LPush(key, values) => {
+            commands::push(database, key, values, request::ListEnd::Left)
+        }
+        request::Command::RPush(key, values) => {
+            commands::push(database, key, values, request::ListEnd::Right)
+        }
+        request::Command::LPop(key, count) => {
+            commands::pop(database, key, count, request::ListEnd::Left)
+        }
+        request::Command::RPop(key, count) => {
+            commands::pop(database, key, count, request::ListEnd::Right)
+        }
+        request::Command::BLPop(keys, _) => {
+            commands::blocking_pop(database, keys, request::ListEnd::Left)
+        }
+        request::Command::BRPop(keys, _) => {
+            commands::blocking_pop(database, keys, request::ListEnd::Right)
+        }
+        request::Command::LMove(command) | request::Command::BLMove(command, _) => {
+            commands::move_list_value(database, command)
+        }
+        request::Command::LMPop(command) | request::Command::BLMPop(command, _) => {
+            commands::pop_multiple(database, command)
+        }
+        request::Command::LInsert(key, position, pivot, value) => {
+            commands::insert_into_list(database, key, position, pivot, value)
+        }
+        request::Command::LSet(key, index, value) => {
+            commands::set_list_index(database, key, index, value)
+        }
+        request::Command::LRem(key, count, value) => {
+            commands::remove_from_list(database, key, count, value)
+        }
+        request::Command::LTrim(key, start, end) => commands::trim_list(database, key, start, end),
+        request::Command::HSet(key, pairs) => commands::set_hash_fields(database, key, pairs),
+        request::Command::HDel(key, fields) => commands::delete_hash_fields(database, key, fields),
+        request::Command::Restore(command) | request::Command::RestoreAsking(command) => {
+            commands::restore(database, command)
+        }
+        request::Command::Import(export, replace) => {
+            commands::import_dataset(database, export, replace)
+        }
+        request::Command::JsonSet(command) => commands::json_set(database, command),
+        request::Command::JsonDel(key, path) => commands::json_delete(database, key, path),
+        request::Command::JsonNumIncrBy(key, path, increment) => {
+            commands::json_increment(database, key, path, increment)
+        }
+        request::Command::JsonArrAppend(key, path, values) => {
+            commands::json_append(database, key, path, values)
+        }
+        request::Command::BfReserve(command) => commands::bf_reserve(database, command),
+        request::Command::BfAdd(key, item) => commands::bf_add(database, key, item),
+        request::Command::BfMAdd(key, items) => commands::bf_madd(database, key, items),
+        request::Command::CfReserve(command) => commands::cf_reserve(database, command),
+        request::Command::CfAdd(key, item) => commands::cf_add(database, key, item, false),
+        request::Command::CfAddNx(key, item) => commands::cf_add(database, key, item, true),
+        request::Command::CfDel(key, item) => commands::cf_delete(database, key, item),
+        request::Command::CmsInitByDim(key, width, depth)
+        | request::Command::CmsInitByProb(key, width, depth) => {
+            commands::cms_init(database, key, width, depth)
+        }
+        request::Command::CmsIncrBy(key, increments) => {
+            commands::cms_increment(database, key, increments)
+        }
+        request::Command::CmsMerge(command) => commands::cms_merge(database, command),
+        request::Command::TopkReserve(command) => commands::topk_reserve(database, command),
+        request::Command::TopkAdd(key, items) => commands::topk_add(database, key, items),
+        request::Command::TsAdd(command) => commands::ts_add(database, command),
+        request::Command::Extension(name, args) => extension::call(database, name, args),
+        _ => Ok(vec![]),
+    };
+}
+
+fn open_for_append(path: &Path) -> Result<File, anyhow::Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Opening append only file {}", path.display()))
+}
+
+fn write_and_sync(path: &Path, contents: &[u8]) -> Result<(), anyhow::Error> {
+    let mut file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    Ok(())
+}
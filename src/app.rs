@@ -1,27 +1,153 @@
-use tokio::net::TcpListener;
-use tokio::sync::broadcast::Sender;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpListener, TcpSocket};
+use tokio::runtime::Builder;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+
+use crate::cluster::bus;
 use crate::data::Database;
 use crate::server::RedisServer;
-use crate::{stream, transmission};
+use crate::systemd::{self, Supervised};
+use crate::telemetry::LogTarget;
+use crate::{encoding, stream};
+
+/// Counts a connection towards `maxclients` for as long as it is alive.
+struct ClientGuard(Arc<AtomicUsize>);
+
+impl ClientGuard {
+    fn new(count: Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        ClientGuard(count)
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 pub async fn run(
     address: &str,
     database: Database,
     redis_server: RedisServer,
-    tx: Sender<transmission::Transmission>,
 ) -> Result<(), anyhow::Error> {
-    let listener = TcpListener::bind(&address)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to bind to address {}: {}", address, e))?;
+    let io_threads = redis_server.read().await.config.io_threads;
+    let connected_clients = Arc::new(AtomicUsize::new(0));
+
+    let listener = if io_threads > 1 {
+        bind_reuse_port(address).await?
+    } else {
+        TcpListener::bind(&address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind to address {}: {}", address, e))?
+    };
+
+    // With port 0 the OS picked a port, which the other accept loops have to share
+    let bound_port = listener.local_addr()?.port();
+    let address = match address.rsplit_once(':') {
+        Some((host, port)) if port != bound_port.to_string() => {
+            redis_server.set_port(bound_port).await;
+            format!("{}:{}", host, bound_port)
+        }
+        _ => address.to_string(),
+    };
+
+    // The first accept loop stays on this task, every other one gets a thread of its own.
+    for core in 1..io_threads {
+        spawn_io_thread(
+            core,
+            address.to_string(),
+            database.clone(),
+            redis_server.clone(),
+            connected_clients.clone(),
+        )
+        .await?;
+    }
     println!("Listening on {}", address);
 
-    while let Ok((stream, _)) = listener.accept().await {
+    let tasks = redis_server.tasks().await;
+    tasks.spawn(
+        "scheduled saves",
+        save_on_schedule(database.clone(), redis_server.clone()),
+    );
+    tasks.spawn(
+        "replica link checks",
+        check_replica_links(redis_server.clone()),
+    );
+    tasks.spawn(
+        "expiration propagation",
+        propagate_expirations(database.clone(), redis_server.clone()),
+    );
+    tasks.spawn(
+        "automatic AOF rewrites",
+        rewrite_aof_on_growth(database.clone(), redis_server.clone()),
+    );
+
+    if redis_server.is_cluster_enabled().await {
+        start_cluster_bus(&address, redis_server.clone()).await?;
+    }
+
+    if redis_server.read().await.config.supervised == Supervised::Systemd {
+        if let Err(e) = systemd::notify_ready() {
+            eprintln!("Failed to notify systemd: {}", e);
+        }
+    }
+
+    accept_loop(listener, database, redis_server, connected_clients).await
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    database: Database,
+    redis_server: RedisServer,
+    connected_clients: Arc<AtomicUsize>,
+) -> Result<(), anyhow::Error> {
+    loop {
+        let (mut stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(_) => break,
+            },
+            _ = redis_server.shutdown_requested() => break,
+        };
+
+        let (max_clients, is_allowed) = {
+            let server = redis_server.read().await;
+            (
+                server.config.max_clients,
+                server.config.is_allowed(peer.ip()),
+            )
+        };
+        if !is_allowed {
+            println!("Refusing connection from {}", peer);
+            let message =
+                encoding::error_string("ERR connections from this address aren't allowed");
+            let _ = stream.write_all(message.as_bytes()).await;
+            continue;
+        }
+        if connected_clients.load(Ordering::SeqCst) >= max_clients {
+            let message = encoding::error_string("ERR max number of clients reached");
+            let _ = stream.write_all(message.as_bytes()).await;
+            continue;
+        }
+
+        // Like redis, replies go out as soon as they're written. With Nagle a
+        // pipeline answered in two writes waits on the client's delayed ACK.
+        let _ = stream.set_nodelay(true);
+
         let database = database.clone();
         let redis_server = redis_server.clone();
-        let sender = tx.clone();
+        let client = ClientGuard::new(connected_clients.clone());
         tokio::spawn(async move {
-            match stream::handle_stream(stream, database, redis_server, sender).await {
+            let _client = client;
+            match stream::handle_stream(stream, database, redis_server).await {
                 Ok(_) => {}
                 Err(e) => {
                     eprintln!("Error handling stream: {}", e);
@@ -32,3 +158,251 @@ pub async fn run(
 
     Ok(())
 }
+
+/// Listens for the other nodes of the cluster and starts gossiping with them.
+async fn start_cluster_bus(address: &str, redis_server: RedisServer) -> Result<(), anyhow::Error> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Invalid address {}", address))?;
+    let cluster_port = redis_server.read().await.config.cluster_port;
+    let bus_address = format!("{}:{}", host, bus::bus_port(port.parse()?, cluster_port)?);
+
+    let listener = TcpListener::bind(&bus_address)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to bind the cluster bus to {}: {}", bus_address, e))?;
+    let bus_port = listener.local_addr()?.port();
+    redis_server
+        .with_cluster(|cluster| {
+            cluster.set_bus_port(bus_port);
+            Ok(())
+        })
+        .await?;
+    println!("Cluster bus listening on {}:{}", host, bus_port);
+
+    let tasks = redis_server.tasks().await;
+    tasks.spawn("cluster bus", bus::listen(listener, redis_server.clone()));
+    tasks.spawn("cluster gossip", bus::gossip(redis_server));
+
+    Ok(())
+}
+
+/// Binds a listener with SO_REUSEPORT so several of them can share the port
+/// and the kernel spreads incoming connections between them.
+async fn bind_reuse_port(address: &str) -> Result<TcpListener, anyhow::Error> {
+    let socket_address = lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Unable to resolve address {}", address))?;
+
+    let socket = match socket_address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+    socket
+        .bind(socket_address)
+        .map_err(|e| anyhow::anyhow!("Failed to bind to address {}: {}", address, e))?;
+
+    let listener = socket.listen(1024)?;
+    Ok(listener)
+}
+
+/// Starts an accept loop on its own OS thread with a single threaded runtime,
+/// pinned to `core`. Resolves once the listener is bound so bind errors are
+/// reported on startup rather than lost in the thread.
+async fn spawn_io_thread(
+    core: usize,
+    address: String,
+    database: Database,
+    redis_server: RedisServer,
+    connected_clients: Arc<AtomicUsize>,
+) -> Result<(), anyhow::Error> {
+    let (bound_tx, bound_rx) = oneshot::channel::<Result<(), anyhow::Error>>();
+
+    thread::Builder::new()
+        .name(format!("io-thread-{}", core))
+        .spawn(move || {
+            if let Err(e) = pin_to_core(core) {
+                eprintln!("Unable to pin io thread {} to a core: {}", core, e);
+            }
+
+            let runtime = match Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = bound_tx.send(Err(e.into()));
+                    return;
+                }
+            };
+
+            runtime.block_on(async move {
+                let listener = match bind_reuse_port(&address).await {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        let _ = bound_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = bound_tx.send(Ok(()));
+
+                if let Err(e) =
+                    accept_loop(listener, database, redis_server, connected_clients).await
+                {
+                    eprintln!("Error in io thread {}: {}", core, e);
+                }
+            });
+        })?;
+
+    bound_rx.await?
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) -> Result<(), anyhow::Error> {
+    let cores = thread::available_parallelism()?.get();
+
+    // SAFETY: cpu_set_t is a plain bitmask that is valid when zeroed, and
+    // sched_setaffinity with pid 0 only affects the calling thread.
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core % cores, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+
+    if result != 0 {
+        anyhow::bail!(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core: usize) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+pub async fn shutdown_signal() -> Result<(), anyhow::Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {},
+    };
+
+    println!("Shutting down");
+    Ok(())
+}
+
+/// Checks the `save` rules every second and writes a new RDB file
+/// as soon as one of them is satisfied.
+async fn save_on_schedule(database: Database, redis_server: RedisServer) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let config = redis_server.read().await.config.clone();
+        let changes = database.dirty();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let seconds_since_save = now.saturating_sub(database.last_save());
+
+        let is_due = config
+            .save
+            .iter()
+            .any(|rule| rule.is_due(changes, seconds_since_save));
+        if !is_due {
+            continue;
+        }
+
+        if database.is_background_saving() {
+            continue;
+        }
+
+        println!("{} changes since the last save, saving...", changes);
+        if let Err(e) = database.background_save(config.rdb_path(), config.rdb_compression) {
+            eprintln!("Unable to start a background save: {}", e);
+        }
+    }
+}
+
+/// Checks every second for replicas whose link has died, and pings the ones
+/// that haven't been sent anything for a while.
+async fn check_replica_links(redis_server: RedisServer) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+        redis_server.evict_dead_replicas().await;
+        redis_server.ping_idle_replicas().await;
+    }
+}
+
+/// Writes a DEL to the AOF and the replicas for every key that expires,
+/// so neither of them has to expire keys on its own.
+async fn propagate_expirations(database: Database, redis_server: RedisServer) {
+    let mut expired_keys = database.expired_keys();
+
+    while let Some((db, key)) = expired_keys.recv().await {
+        let command = encoding::encode_string_array(&["DEL", &key]);
+
+        if let Err(e) = redis_server.propagate_write(db, command.as_bytes()).await {
+            eprintln!("Unable to propagate the expiration of {}: {}", key, e);
+        }
+    }
+}
+
+/// Checks every second whether the AOF has grown enough since the
+/// last rewrite to be rewritten automatically.
+async fn rewrite_aof_on_growth(database: Database, redis_server: RedisServer) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let (aof, config) = {
+            let server = redis_server.read().await;
+            (server.aof.clone(), server.config.clone())
+        };
+
+        let should_rewrite = aof
+            .should_rewrite(
+                config.auto_aof_rewrite_percentage,
+                config.auto_aof_rewrite_min_size,
+            )
+            .await;
+        if !should_rewrite {
+            continue;
+        }
+
+        println!("Starting automatic rewriting of AOF");
+        if let Err(e) = aof.rewrite(&database, config.rdb_compression).await {
+            eprintln!("Automatic AOF rewrite failed to start: {}", e);
+        }
+    }
+}
+
+pub async fn reload_on_hangup(
+    redis_server: RedisServer,
+    log_target: LogTarget,
+) -> Result<(), anyhow::Error> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        if let Err(e) = redis_server.reload_config().await {
+            tracing::error!("Failed to reload config, keeping the current one: {}", e);
+            continue;
+        }
+
+        let logfile = redis_server.read().await.config.logfile.clone();
+        if let Err(e) = log_target.set_file(logfile.as_deref()) {
+            tracing::error!("Failed to switch log file: {}", e);
+            continue;
+        }
+
+        tracing::info!("Reloaded config");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::time::{Duration, Instant};
+
+use clap::{Parser, ValueEnum};
+use rand::Rng;
+
+use not_redis::client::{Client, Pipeline};
+use not_redis::resp::Value;
+
+/// Load generator in the spirit of redis-benchmark. Runs each test in turn over a
+/// number of concurrent connections and reports throughput and latency.
+#[derive(Debug, Parser)]
+#[command(name = "not-redis-benchmark", version)]
+struct Args {
+    /// Server hostname
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Server port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// Number of parallel connections
+    #[arg(short, long, default_value_t = 50)]
+    clients: usize,
+
+    /// Total number of requests per test
+    #[arg(short = 'n', long, default_value_t = 100_000)]
+    requests: usize,
+
+    /// Number of requests sent together in a pipeline
+    #[arg(short = 'P', long, default_value_t = 1)]
+    pipeline: usize,
+
+    /// Size in bytes of the values written by SET and XADD
+    #[arg(short, long, default_value_t = 3)]
+    data_size: usize,
+
+    /// Spread keys randomly over this many keys instead of using a single one
+    #[arg(short, long)]
+    keyspace_len: Option<usize>,
+
+    /// Tests to run
+    #[arg(short, long, value_delimiter = ',', default_values_t = [Test::Set, Test::Get, Test::Incr, Test::Xadd])]
+    tests: Vec<Test>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+enum Test {
+    Set,
+    Get,
+    Incr,
+    Xadd,
+}
+
+impl std::fmt::Display for Test {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Test::Set => "set",
+            Test::Get => "get",
+            Test::Incr => "incr",
+            Test::Xadd => "xadd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Test {
+    fn command(&self, key: &str, value: &str) -> Vec<String> {
+        let args: Vec<&str> = match self {
+            Test::Set => vec!["SET", key, value],
+            Test::Get => vec!["GET", key],
+            Test::Incr => vec!["INCR", key],
+            Test::Xadd => vec!["XADD", key, "*", "field", value],
+        };
+        args.into_iter().map(String::from).collect()
+    }
+
+    fn key_prefix(&self) -> &'static str {
+        match self {
+            Test::Set | Test::Get => "key",
+            Test::Incr => "counter",
+            Test::Xadd => "stream",
+        }
+    }
+}
+
+/// How long every pipeline took to come back, along with how many requests were in it.
+type Samples = Vec<(Duration, usize)>;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+    if args.clients == 0 || args.pipeline == 0 {
+        anyhow::bail!("--clients and --pipeline must be at least 1");
+    }
+
+    let address = format!("{}:{}", args.host, args.port);
+    let value = "x".repeat(args.data_size);
+
+    for test in args.tests.iter() {
+        let started = Instant::now();
+
+        let mut connections = Vec::with_capacity(args.clients);
+        for client in 0..args.clients {
+            // Spread the requests as evenly as possible over the connections
+            let requests =
+                args.requests / args.clients + usize::from(client < args.requests % args.clients);
+            let mut connection = Client::connect(&address).await?;
+            let (test, value, pipeline, keyspace_len) =
+                (*test, value.clone(), args.pipeline, args.keyspace_len);
+
+            connections.push(tokio::spawn(async move {
+                run_client(
+                    &mut connection,
+                    test,
+                    &value,
+                    requests,
+                    pipeline,
+                    keyspace_len,
+                )
+                .await
+            }));
+        }
+
+        let mut samples = Samples::new();
+        for connection in connections {
+            samples.extend(connection.await??);
+        }
+
+        report(*test, &args, started.elapsed(), samples);
+    }
+
+    Ok(())
+}
+
+async fn run_client(
+    client: &mut Client,
+    test: Test,
+    value: &str,
+    requests: usize,
+    pipeline_len: usize,
+    keyspace_len: Option<usize>,
+) -> Result<Samples, anyhow::Error> {
+    let mut samples = Samples::with_capacity(requests / pipeline_len + 1);
+    let mut remaining = requests;
+
+    while remaining > 0 {
+        let batch = remaining.min(pipeline_len);
+        let mut pipeline = Pipeline::new();
+        for _ in 0..batch {
+            let key = match keyspace_len {
+                Some(len) => format!(
+                    "{}:{:012}",
+                    test.key_prefix(),
+                    rand::thread_rng().gen_range(0..len.max(1))
+                ),
+                None => format!("{}:{:012}", test.key_prefix(), 0),
+            };
+            pipeline.add(&test.command(&key, value));
+        }
+
+        let sent = Instant::now();
+        let replies = client.pipeline(&pipeline).await?;
+        samples.push((sent.elapsed(), batch));
+
+        if let Some(Value::Error(message)) = replies.iter().find(|r| matches!(r, Value::Error(_))) {
+            anyhow::bail!("{} failed: {}", test, message);
+        }
+        remaining -= batch;
+    }
+
+    Ok(samples)
+}
+
+fn report(test: Test, args: &Args, elapsed: Duration, samples: Samples) {
+    // Like redis-benchmark, every request in a pipeline is counted as taking as
+    // long as the whole pipeline did.
+    let mut latencies: Vec<Duration> = samples
+        .into_iter()
+        .flat_map(|(latency, requests)| std::iter::repeat_n(latency, requests))
+        .collect();
+    latencies.sort();
+
+    let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+    println!("====== {} ======", test.to_string().to_uppercase());
+    println!(
+        "  {} requests completed in {:.2} seconds",
+        latencies.len(),
+        elapsed.as_secs_f64()
+    );
+    println!("  {} parallel clients", args.clients);
+    println!("  {} bytes payload", args.data_size);
+    println!("  {} requests per pipeline", args.pipeline);
+    println!();
+    println!("  throughput: {:.2} requests per second", throughput);
+    println!(
+        "  latency (msec): p50={:.3} p95={:.3} p99={:.3} max={:.3}",
+        millis(percentile(&latencies, 50.0)),
+        millis(percentile(&latencies, 95.0)),
+        millis(percentile(&latencies, 99.0)),
+        millis(latencies.last().copied().unwrap_or_default()),
+    );
+    println!();
+}
+
+/// The latency `pct` percent of requests came in under, from latencies sorted
+/// from fastest to slowest.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+
+use not_redis::aof::{check_file, AofCheck, Manifest};
+
+/// Checks an append only file the way the server would load it, in the spirit
+/// of redis-check-aof, and can cut off whatever can't be read.
+#[derive(Debug, Parser)]
+#[command(name = "not-redis-check-aof", version)]
+struct Args {
+    /// A single AOF file, or the manifest of a multi part AOF
+    file: PathBuf,
+
+    /// Truncate the file to the last command that could be read. Only the last
+    /// file of a multi part AOF can be fixed this way.
+    #[arg(long)]
+    fix: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    match check(&args) {
+        Ok(true) => println!("AOF is valid"),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            println!("AOF check failed: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns whether the AOF is valid, after fixing it if that was asked for.
+fn check(args: &Args) -> Result<bool, anyhow::Error> {
+    let files = match args.file.extension().is_some_and(|ext| ext == "manifest") {
+        true => manifest_files(&args.file)?,
+        false => vec![args.file.clone()],
+    };
+
+    for (i, path) in files.iter().enumerate() {
+        let contents = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        let len = contents.len();
+        let AofCheck {
+            commands,
+            corruption,
+        } = check_file(contents);
+
+        let Some(corruption) = corruption else {
+            println!("{}: {} commands, OK", path.display(), commands);
+            continue;
+        };
+
+        println!(
+            "{}: {} at offset {} of {}, after {} valid commands",
+            path.display(),
+            corruption.reason,
+            corruption.offset,
+            len,
+            commands
+        );
+
+        let is_last = i == files.len() - 1;
+        if !args.fix {
+            if corruption.fixable && is_last {
+                println!(
+                    "Run with --fix to truncate the file to {} bytes",
+                    corruption.offset
+                );
+            }
+            return Ok(false);
+        }
+        if !corruption.fixable || !is_last {
+            println!("{} can't be fixed by truncating it", path.display());
+            return Ok(false);
+        }
+
+        truncate(path, corruption.offset)?;
+        println!(
+            "Truncated {} to {} bytes, dropping {} bytes",
+            path.display(),
+            corruption.offset,
+            len as u64 - corruption.offset
+        );
+    }
+
+    Ok(true)
+}
+
+/// The files the manifest lists, in the order they're loaded in.
+fn manifest_files(path: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading AOF manifest {}", path.display()))?;
+    let manifest = Manifest::parse(&contents)?;
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    Ok(manifest.files().map(|file| dir.join(&file.name)).collect())
+}
+
+fn truncate(path: &Path, len: u64) -> Result<(), anyhow::Error> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Opening {}", path.display()))?;
+    file.set_len(len)?;
+    file.sync_all()?;
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use not_redis::data::{inspect_rdb, RdbChecksum, RdbSummary};
+use not_redis::utils::current_unix_timestamp;
+
+/// Checks an RDB file the way the server would load it, in the spirit of
+/// redis-check-rdb, and summarizes what's in it.
+#[derive(Debug, Parser)]
+#[command(name = "not-redis-check-rdb", version)]
+struct Args {
+    /// RDB file to check
+    #[arg(default_value = "dump.rdb")]
+    file: PathBuf,
+
+    /// List every key along with its type and expiration
+    #[arg(long)]
+    keys: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    println!("Checking RDB file {}", args.file.display());
+
+    let summary = match std::fs::read(&args.file)
+        .map_err(anyhow::Error::from)
+        .and_then(inspect_rdb)
+    {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("RDB check failed: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    print_summary(&summary, args.keys);
+
+    if let RdbChecksum::Mismatch { expected, computed } = summary.checksum {
+        println!(
+            "RDB check failed: checksum mismatch, expected {:016x} but computed {:016x}",
+            expected, computed
+        );
+        std::process::exit(1);
+    }
+    println!("RDB looks OK");
+}
+
+fn print_summary(summary: &RdbSummary, list_keys: bool) {
+    println!("RDB version {}", summary.version);
+    for aux in summary.aux_fields.iter() {
+        println!("AUX FIELD {} = '{}'", aux.key, aux.value);
+    }
+
+    let mut databases: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+    for key in summary.keys.iter() {
+        *databases.entry(key.db).or_default() += 1;
+        *types.entry(key.key_type).or_default() += 1;
+    }
+
+    let now = current_unix_timestamp().unwrap_or_default() as u64;
+    let expires = summary.keys.iter().filter_map(|key| key.expires_at);
+    let (with_expiration, expired) = expires.fold((0, 0), |(total, expired), at| {
+        (total + 1, expired + usize::from(at <= now))
+    });
+
+    println!("Keys: {}", summary.keys.len());
+    for (db, keys) in databases {
+        println!("  db{}: {} keys", db, keys);
+    }
+    for (key_type, keys) in types {
+        println!("  {}: {}", key_type, keys);
+    }
+    println!(
+        "  with an expiration: {} ({} already expired)",
+        with_expiration, expired
+    );
+
+    if list_keys {
+        for key in summary.keys.iter() {
+            let expiration = match key.expires_at {
+                Some(at) if at <= now => format!("expired at {}", at),
+                Some(at) => format!("expires at {}", at),
+                None => "no expiration".to_string(),
+            };
+            println!(
+                "  db{} {} ({}, {})",
+                key.db, key.key, key.key_type, expiration
+            );
+        }
+    }
+
+    match summary.checksum {
+        RdbChecksum::Missing => println!("Checksum: none, the file predates version 5"),
+        RdbChecksum::Disabled => println!("Checksum: disabled"),
+        RdbChecksum::Valid(checksum) => println!("Checksum: {:016x} OK", checksum),
+        RdbChecksum::Mismatch { .. } => {}
+    }
+}
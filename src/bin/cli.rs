@@ -0,0 +1,347 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use clap::Parser;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use not_redis::client::{Client, Pipeline};
+use not_redis::resp::Value;
+
+const HISTORY_FILE: &str = ".not_redis_cli_history";
+// Commands read in --pipe mode are sent on in batches of this many
+const PIPE_BATCH: usize = 1000;
+
+/// Command line client in the spirit of redis-cli. Runs the command it's given,
+/// or starts a prompt when there isn't one.
+#[derive(Debug, Parser)]
+#[command(name = "not-redis-cli", version)]
+struct Args {
+    /// Server hostname
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Server port
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+
+    /// Password to AUTH with before running any commands
+    #[arg(short = 'a', long = "pass", value_name = "PASSWORD")]
+    password: Option<String>,
+
+    /// Send the commands on standard input, either RESP or one per line, and
+    /// only report how many replies and errors came back
+    #[arg(long)]
+    pipe: bool,
+
+    /// Command to run instead of starting a prompt
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+    let address = format!("{}:{}", args.host, args.port);
+
+    let mut client = Client::connect(&address)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not connect to {}: {}", address, e))?;
+    if let Some(password) = &args.password {
+        let reply = client.command(&["AUTH", password]).await?;
+        if let Value::Error(message) = reply {
+            anyhow::bail!("AUTH failed: {}", message);
+        }
+    }
+
+    if args.pipe {
+        return pipe(&mut client).await;
+    }
+
+    if !args.command.is_empty() {
+        let reply = client.command(&args.command).await?;
+        println!("{}", format_value(&reply));
+        if matches!(reply, Value::Error(_)) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    repl(&mut client, &address).await
+}
+
+async fn repl(client: &mut Client, address: &str) -> Result<(), anyhow::Error> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(history) = &history {
+        // There's no history the first time around
+        let _ = editor.load_history(history);
+    }
+
+    let prompt = format!("{}> ", address);
+    loop {
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let args = match split_args(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                println!("Invalid argument(s): {}", e);
+                continue;
+            }
+        };
+        let Some(name) = args.first() else {
+            continue;
+        };
+        editor.add_history_entry(line.as_str())?;
+        if name.eq_ignore_ascii_case("quit") || name.eq_ignore_ascii_case("exit") {
+            break;
+        }
+
+        match client.command(&args).await {
+            Ok(reply) => println!("{}", format_value(&reply)),
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Could not save history to {}: {}", history.display(), e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn pipe(client: &mut Client) -> Result<(), anyhow::Error> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let commands = read_commands(&input)?;
+
+    let (mut replies, mut errors) = (0, 0);
+    for batch in commands.chunks(PIPE_BATCH) {
+        let mut pipeline = Pipeline::new();
+        for command in batch {
+            pipeline.add(command);
+        }
+
+        for reply in client.pipeline(&pipeline).await? {
+            replies += 1;
+            if let Value::Error(message) = reply {
+                eprintln!("{}", message);
+                errors += 1;
+            }
+        }
+    }
+
+    println!(
+        "All data transferred. errors: {}, replies: {}",
+        errors, replies
+    );
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The commands in `input`, which is either RESP arrays like the server reads
+/// or one command per line like the prompt takes.
+fn read_commands(input: &[u8]) -> Result<Vec<Vec<String>>, anyhow::Error> {
+    let mut commands = Vec::new();
+
+    if input.first() != Some(&b'*') {
+        let input = std::str::from_utf8(input)?;
+        for line in input.lines() {
+            let args = split_args(line).map_err(|e| anyhow::anyhow!("{}: {}", e, line))?;
+            if !args.is_empty() {
+                commands.push(args);
+            }
+        }
+        return Ok(commands);
+    }
+
+    let mut consumed = 0;
+    while consumed < input.len() {
+        let Some((value, len)) = Value::parse(&input[consumed..])? else {
+            anyhow::bail!("Incomplete command at the end of the input");
+        };
+        consumed += len;
+
+        let Value::Array(items) = value else {
+            anyhow::bail!("Expected every command to be an array");
+        };
+        let args = items
+            .iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| anyhow::anyhow!("Expected commands made of UTF-8 bulk strings"))?;
+        commands.push(args);
+    }
+
+    Ok(commands)
+}
+
+fn history_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Splits a line into arguments the way redis-cli does. Arguments are separated
+/// by whitespace and may be quoted, with escapes allowed in double quotes.
+fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        match first {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next().ok_or("unbalanced quotes")? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or("unbalanced quotes")? {
+                            'n' => arg.push('\n'),
+                            'r' => arg.push('\r'),
+                            't' => arg.push('\t'),
+                            'x' => {
+                                let hex: String = chars.by_ref().take(2).collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .map_err(|_| "invalid \\x escape")?;
+                                arg.push(byte as char);
+                            }
+                            c => arg.push(c),
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next().ok_or("unbalanced quotes")? {
+                        '\'' => break,
+                        '\\' if chars.peek() == Some(&'\'') => arg.push(chars.next().unwrap()),
+                        c => arg.push(c),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+                args.push(arg);
+                continue;
+            }
+        }
+
+        // A closing quote has to end the argument
+        if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err("closing quote must be followed by a space");
+        }
+        args.push(arg);
+    }
+}
+
+/// Formats a reply the way redis-cli prints it, with nested arrays indented
+/// under their position in the parent.
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::SimpleString(value) => value.clone(),
+        Value::Error(message) => format!("(error) {}", message),
+        Value::Integer(value) => format!("(integer) {}", value),
+        Value::BulkString(value) => quote(value),
+        Value::Null | Value::NullArray => "(nil)".to_string(),
+        Value::Array(items) if items.is_empty() => "(empty array)".to_string(),
+        Value::Array(items) => {
+            let width = items.len().to_string().len();
+            let mut lines = Vec::new();
+            for (i, item) in items.iter().enumerate() {
+                let prefix = format!("{:>width$}) ", i + 1);
+                let indent = " ".repeat(prefix.len());
+                for (j, line) in format_value(item).lines().enumerate() {
+                    match j {
+                        0 => lines.push(format!("{}{}", prefix, line)),
+                        _ => lines.push(format!("{}{}", indent, line)),
+                    }
+                }
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+fn quote(value: &[u8]) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for &byte in value {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_split_like_redis_cli() {
+        assert_eq!(
+            split_args(r#"  set "hello world" 'it\'s' "a\nb\x41"  "#).unwrap(),
+            vec!["set", "hello world", "it's", "a\nbA"]
+        );
+        assert_eq!(split_args("   ").unwrap(), Vec::<String>::new());
+        assert!(split_args(r#"set "oops"#).is_err());
+        assert!(split_args(r#"set "a"b"#).is_err());
+    }
+
+    #[test]
+    fn replies_are_printed_like_redis_cli() {
+        assert_eq!(format_value(&Value::ok()), "OK");
+        assert_eq!(format_value(&Value::Integer(3)), "(integer) 3");
+        assert_eq!(format_value(&Value::from("a\"b\n")), r#""a\"b\n""#);
+        assert_eq!(format_value(&Value::Null), "(nil)");
+        assert_eq!(format_value(&Value::Array(vec![])), "(empty array)");
+
+        let nested = Value::Array(vec![
+            Value::Array(vec![Value::from("1-0"), Value::bulk_array(&["f", "v"])]),
+            Value::from("last"),
+        ]);
+        assert_eq!(
+            format_value(&nested),
+            "1) 1) \"1-0\"\n   2) 1) \"f\"\n      2) \"v\"\n2) \"last\""
+        );
+    }
+
+    #[test]
+    fn piped_input_can_be_resp_or_lines() {
+        let resp = Value::bulk_array(&["SET", "a b", "1"]).encode();
+        assert_eq!(read_commands(&resp).unwrap(), vec![vec!["SET", "a b", "1"]]);
+
+        let lines = b"SET \"a b\" 1\n\nGET x\n";
+        assert_eq!(
+            read_commands(lines).unwrap(),
+            vec![vec!["SET", "a b", "1"], vec!["GET", "x"]]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+/// Clients blocked on keys, e.g. by XREAD BLOCK, waiting for a write to one of them.
+/// A write only has to look up its own key, so it costs nothing when nobody is
+/// waiting on it no matter how many clients are blocked on other keys.
+#[derive(Debug, Default)]
+pub struct BlockedKeys {
+    waiters: Mutex<HashMap<String, Vec<Waiter>>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    notify: Arc<Notify>,
+}
+
+impl BlockedKeys {
+    /// Registers a client as waiting on every one of the keys. It stays registered
+    /// until the returned handle is dropped.
+    pub fn block_on(self: &Arc<Self>, keys: Vec<String>) -> BlockedClient {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let notify = Arc::new(Notify::new());
+
+        let mut waiters = self.waiters.lock().unwrap();
+        for key in keys.iter() {
+            waiters.entry(key.clone()).or_default().push(Waiter {
+                id,
+                notify: notify.clone(),
+            });
+        }
+
+        BlockedClient {
+            registry: self.clone(),
+            id,
+            keys,
+            notify,
+        }
+    }
+
+    /// Wakes every client blocked on the key. They check for themselves whether
+    /// the write gave them what they were waiting for. Nothing is queued per write,
+    /// so a burst of writes can't leave a slow client behind: however many there
+    /// were, it's woken once and reads everything it missed from the keyspace.
+    pub fn signal(&self, key: &str) {
+        let waiters = self.waiters.lock().unwrap();
+        if let Some(waiters) = waiters.get(key) {
+            for waiter in waiters {
+                waiter.notify.notify_one();
+            }
+        }
+    }
+
+    /// Wakes every blocked client, for when a whole database changes at once.
+    pub fn signal_all(&self) {
+        let waiters = self.waiters.lock().unwrap();
+        for waiter in waiters.values().flatten() {
+            waiter.notify.notify_one();
+        }
+    }
+
+    fn unblock(&self, id: u64, keys: &[String]) {
+        let mut waiters = self.waiters.lock().unwrap();
+        for key in keys {
+            if let Some(key_waiters) = waiters.get_mut(key) {
+                key_waiters.retain(|waiter| waiter.id != id);
+                if key_waiters.is_empty() {
+                    waiters.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// A client's registration in `BlockedKeys`, removed once this is dropped.
+pub struct BlockedClient {
+    registry: Arc<BlockedKeys>,
+    id: u64,
+    keys: Vec<String>,
+    notify: Arc<Notify>,
+}
+
+impl BlockedClient {
+    /// Waits for a write to any of the keys. A write that happened since the
+    /// last call, even before the first one, returns straight away.
+    pub async fn wait(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for BlockedClient {
+    fn drop(&mut self) {
+        self.registry.unblock(self.id, &self.keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{timeout, Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn only_clients_blocked_on_the_key_are_woken_up() {
+        let registry = Arc::new(BlockedKeys::default());
+        let first = registry.block_on(vec!["a".to_string(), "b".to_string()]);
+        let second = registry.block_on(vec!["c".to_string()]);
+
+        registry.signal("b");
+        timeout(Duration::from_millis(100), first.wait())
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), second.wait())
+            .await
+            .is_err());
+
+        drop(first);
+        drop(second);
+        assert!(registry.waiters.lock().unwrap().is_empty());
+    }
+}
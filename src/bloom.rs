@@ -0,0 +1,297 @@
+//! Scalable Bloom filters, like the ones the RedisBloom module adds. A filter is
+//! sized for its capacity and error rate, and once it holds that many items a
+//! larger one with a tighter error rate is stacked on top of it. Items are only
+//! added to the newest filter but looked up in all of them, which keeps the error
+//! rate of the whole chain within the one that was asked for.
+
+use std::f64::consts::LN_2;
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+use crate::utils::item_hashes;
+
+/// What BF.ADD creates a filter with when the key doesn't exist.
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const DEFAULT_CAPACITY: u64 = 100;
+pub const DEFAULT_EXPANSION: u64 = 2;
+// Each filter's error rate is this fraction of the previous one's, so the sum
+// over the whole chain never exceeds the error rate of the first
+const TIGHTENING_RATIO: f64 = 0.5;
+// Matches the default proto-max-bulk-len, so no single filter is larger than a
+// value a client could send
+const MAX_FILTER_BYTES: u64 = 512 * 1024 * 1024;
+// Long before this many filters the tightened error rates get too small for a
+// double to tell apart, so chains are capped here and loaded ones held to it
+const MAX_FILTERS: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+    capacity: u64,
+    error_rate: f64,
+    items: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: u64, error_rate: f64) -> Result<Self, RedisError> {
+        // The optimal number of bits per item, and of hashes, for the error rate
+        let bits_per_item = -error_rate.ln() / LN_2.powi(2);
+        let bytes = (capacity as f64 * bits_per_item / 8.0).ceil();
+        if bytes > MAX_FILTER_BYTES as f64 {
+            return Err(RedisError::custom(
+                "ERR Insufficient memory to create filter",
+            ));
+        }
+
+        Ok(BloomFilter {
+            bits: vec![0; (bytes as usize).max(1)],
+            hashes: (LN_2 * bits_per_item).ceil() as u32,
+            capacity,
+            error_rate,
+            items: 0,
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.items >= self.capacity
+    }
+
+    /// The bits an item maps to, from two hashes of it combined the way
+    /// Kirsch and Mitzenmacher describe.
+    fn positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        let positions: Vec<usize> = self.positions(hash).collect();
+        for bit in positions {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+        self.items += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalableBloomFilter {
+    /// Oldest first. The first one is only created once it's needed.
+    filters: Vec<BloomFilter>,
+    capacity: u64,
+    error_rate: f64,
+    /// How many times larger each filter is than the last, `None` for a filter
+    /// that can't grow.
+    expansion: Option<u64>,
+}
+
+impl Default for ScalableBloomFilter {
+    fn default() -> Self {
+        ScalableBloomFilter {
+            filters: vec![],
+            capacity: DEFAULT_CAPACITY,
+            error_rate: DEFAULT_ERROR_RATE,
+            expansion: Some(DEFAULT_EXPANSION),
+        }
+    }
+}
+
+impl ScalableBloomFilter {
+    pub fn new(capacity: u64, error_rate: f64, expansion: Option<u64>) -> Result<Self, RedisError> {
+        let mut filter = ScalableBloomFilter {
+            filters: vec![],
+            capacity,
+            error_rate,
+            expansion,
+        };
+        filter.grow()?;
+
+        Ok(filter)
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        let hash = item_hashes(item);
+        self.filters.iter().any(|filter| filter.contains(hash))
+    }
+
+    /// Returns whether the item is new, that is whether it wasn't in the filter
+    /// already or mistaken for an item that was.
+    pub fn add(&mut self, item: &str) -> Result<bool, RedisError> {
+        let hash = item_hashes(item);
+        if self.filters.iter().any(|filter| filter.contains(hash)) {
+            return Ok(false);
+        }
+
+        if self.filters.last().is_none_or(BloomFilter::is_full) {
+            self.grow()?;
+        }
+        let filter = self.filters.last_mut().expect("a filter was just added");
+        filter.insert(hash);
+
+        Ok(true)
+    }
+
+    fn grow(&mut self) -> Result<(), RedisError> {
+        let (capacity, error_rate) = match (self.filters.last(), self.expansion) {
+            (None, _) => (self.capacity, self.error_rate * TIGHTENING_RATIO),
+            (Some(_), None) => return Err(RedisError::custom("ERR non scaling filter is full")),
+            _ if self.filters.len() as u64 >= MAX_FILTERS => {
+                return Err(RedisError::custom(
+                    "ERR Insufficient memory to create filter",
+                ))
+            }
+            (Some(last), Some(expansion)) => (
+                last.capacity.saturating_mul(expansion),
+                last.error_rate * TIGHTENING_RATIO,
+            ),
+        };
+
+        self.filters.push(BloomFilter::new(capacity, error_rate)?);
+        Ok(())
+    }
+}
+
+/// Saved under RedisBloom's type name, though the fields are laid out this
+/// server's own way: the chain's settings, then for every filter its settings
+/// followed by its bits.
+impl ModuleValue for ScalableBloomFilter {
+    const TYPE_NAME: &'static str = "MBbloom--";
+    const ENCODING_VERSION: u16 = 0;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::Bloom(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::Bloom(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::Bloom(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        let mut fields = vec![
+            ModuleField::Uint(self.capacity),
+            ModuleField::Double(self.error_rate),
+            ModuleField::Uint(self.expansion.unwrap_or(0)),
+            ModuleField::Uint(self.filters.len() as u64),
+        ];
+        for filter in self.filters.iter() {
+            fields.extend([
+                ModuleField::Uint(filter.capacity),
+                ModuleField::Double(filter.error_rate),
+                ModuleField::Uint(filter.hashes as u64),
+                ModuleField::Uint(filter.items),
+                ModuleField::String(filter.bits.clone()),
+            ]);
+        }
+
+        fields
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [capacity, error_rate, expansion, num_filters, rest @ ..] = fields else {
+            anyhow::bail!("Bloom filter is missing its settings");
+        };
+        let num_filters = num_filters.as_uint()?;
+        if num_filters > MAX_FILTERS {
+            anyhow::bail!("Bloom filter has too many filters");
+        }
+        if rest.len() as u64 != num_filters * 5 {
+            anyhow::bail!("Bloom filter has the wrong number of fields");
+        }
+
+        let filters = rest
+            .chunks(5)
+            .map(|filter| {
+                let bits = filter[4].as_bytes()?;
+                if bits.is_empty() || bits.len() as u64 > MAX_FILTER_BYTES {
+                    anyhow::bail!("Bloom filter has the wrong number of bits");
+                }
+                Ok(BloomFilter {
+                    capacity: filter[0].as_uint()?,
+                    error_rate: filter[1].as_double()?,
+                    hashes: filter[2].as_uint()? as u32,
+                    items: filter[3].as_uint()?,
+                    bits: bits.to_vec(),
+                })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        Ok(ScalableBloomFilter {
+            filters,
+            capacity: capacity.as_uint()?,
+            error_rate: error_rate.as_double()?,
+            expansion: Some(expansion.as_uint()?).filter(|expansion| *expansion > 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_grow_and_stay_within_their_error_rate() {
+        let mut filter = ScalableBloomFilter::new(100, 0.01, Some(2)).unwrap();
+        let mut added = 0;
+        for i in 0..1000 {
+            added += filter.add(&format!("item-{}", i)).unwrap() as usize;
+        }
+
+        // Any item mistaken for one that was already there isn't added
+        assert!(added > 990);
+        assert!((0..1000).all(|i| filter.contains(&format!("item-{}", i))));
+        assert_eq!(filter.filters.len(), 4);
+        assert!(!filter.add("item-0").unwrap());
+
+        // The error rate is 1%, give or take the luck of the draw
+        let false_positives = (0..10000)
+            .filter(|i| filter.contains(&format!("other-{}", i)))
+            .count();
+        assert!(false_positives < 150, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn non_scaling_filters_fill_up() {
+        let mut filter = ScalableBloomFilter::new(10, 0.001, None).unwrap();
+        for i in 0..10 {
+            assert!(filter.add(&i.to_string()).unwrap());
+        }
+        assert_eq!(
+            filter.add("10").unwrap_err().to_string(),
+            "ERR non scaling filter is full"
+        );
+        assert!(filter.add("3").is_ok_and(|added| !added));
+    }
+
+    #[test]
+    fn filters_survive_being_saved() {
+        let mut filter = ScalableBloomFilter::default();
+        for i in 0..300 {
+            filter.add(&i.to_string()).unwrap();
+        }
+
+        let saved = ScalableBloomFilter::from_fields(&filter.to_fields()).unwrap();
+        assert_eq!(saved, filter);
+        assert!(ScalableBloomFilter::from_fields(&filter.to_fields()[..6]).is_err());
+
+        // Too many filters to count the fields of
+        let mut fields = filter.to_fields();
+        fields[3] = ModuleField::Uint(u64::MAX);
+        assert!(ScalableBloomFilter::from_fields(&fields).is_err());
+    }
+}
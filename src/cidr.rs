@@ -0,0 +1,109 @@
+//! CIDR blocks for the ip-allowlist and ip-denylist options, which decide who
+//! may connect at all before a client gets as far as AUTH.
+
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Takes `address/prefix-length`, or an address on its own for just that address.
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| anyhow::anyhow!("'{}' isn't an IP address", address))?;
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => match prefix_len.parse::<u8>() {
+                Ok(prefix_len) if prefix_len <= max_len => prefix_len,
+                _ => anyhow::bail!("'{}' isn't a valid prefix length", prefix_len),
+            },
+            None => max_len,
+        };
+
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// IPv4 clients reaching an IPv6 socket show up as IPv4 mapped addresses,
+    /// which match IPv4 blocks.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(&network.octets(), &address.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], address: &[u8], prefix_len: u8) -> bool {
+    let (whole_bytes, extra_bits) = (prefix_len as usize / 8, prefix_len % 8);
+    if network[..whole_bytes] != address[..whole_bytes] {
+        return false;
+    }
+    if extra_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - extra_bits);
+    network[whole_bytes] & mask == address[whole_bytes] & mask
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// A space separated list of blocks, as the options take them.
+pub fn parse_list(value: &str) -> Result<Vec<Cidr>, anyhow::Error> {
+    value.split_whitespace().map(Cidr::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn blocks_contain_the_addresses_under_their_prefix() {
+        let block = Cidr::parse("10.1.0.0/20").unwrap();
+        assert!(block.contains(ip("10.1.15.255")));
+        assert!(!block.contains(ip("10.1.16.0")));
+        assert!(block.contains(ip("::ffff:10.1.2.3")));
+
+        let single = Cidr::parse("192.168.0.7").unwrap();
+        assert_eq!(single.to_string(), "192.168.0.7/32");
+        assert!(single.contains(ip("192.168.0.7")));
+        assert!(!single.contains(ip("192.168.0.8")));
+
+        let v6 = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains(ip("2001:db8:ffff::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+        assert!(!v6.contains(ip("10.0.0.1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("localhost").is_err());
+    }
+}
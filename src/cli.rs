@@ -0,0 +1,175 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::config::{parse_io_threads, Config};
+use crate::request::ConfigKey;
+use crate::server::{Address, ServerBuilder};
+use crate::systemd::Supervised;
+
+/// The command line the server is started with. Like redis-server, options given
+/// here override the ones in the config file.
+#[derive(Debug, Parser)]
+#[command(
+    name = "not-redis",
+    version,
+    about = "A redis compatible key value store"
+)]
+pub struct Args {
+    /// Config file to read before applying the other options
+    pub config_file: Option<PathBuf>,
+
+    /// Port to listen on, 0 to let the OS pick one
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Address to listen on
+    #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1")]
+    pub bind: String,
+
+    /// Directory the RDB and append only files are kept in
+    #[arg(long)]
+    pub dir: Option<String>,
+
+    /// Name of the RDB file in the data directory
+    #[arg(long, value_name = "FILE_NAME")]
+    pub dbfilename: Option<String>,
+
+    /// Replicate the master at this address, either as two arguments or one quoted "<host> <port>"
+    #[arg(long, num_args = 1..=2, value_names = ["HOST", "PORT"])]
+    pub replicaof: Option<Vec<String>>,
+
+    /// Password clients have to AUTH with before running commands
+    #[arg(long, value_name = "PASSWORD")]
+    pub requirepass: Option<String>,
+
+    /// Whether to log writes to the append only file (yes or no)
+    #[arg(long, value_name = "yes|no")]
+    pub appendonly: Option<String>,
+
+    /// Memory limit, e.g. 100mb, or 0 for no limit
+    #[arg(long, value_name = "BYTES")]
+    pub maxmemory: Option<String>,
+
+    /// File to log to instead of standard output
+    #[arg(long, value_name = "FILE")]
+    pub logfile: Option<String>,
+
+    /// Supervision to notify of startup and shutdown
+    #[arg(long, value_name = "no|systemd|auto", value_parser = Supervised::parse)]
+    pub supervised: Option<Supervised>,
+
+    /// Whether to run as a cluster node (yes or no)
+    #[arg(long, value_name = "yes|no")]
+    pub cluster_enabled: Option<String>,
+
+    /// Port of the cluster bus, by default the port plus 10000
+    #[arg(long, value_name = "PORT")]
+    pub cluster_port: Option<String>,
+
+    /// Number of databases clients can SELECT
+    #[arg(long, value_name = "COUNT")]
+    pub databases: Option<String>,
+
+    /// Number of threads accepting and serving connections
+    #[arg(long, value_name = "COUNT", value_parser = parse_io_threads)]
+    pub io_threads: Option<usize>,
+}
+
+impl Args {
+    /// Reads the config file, applies the options on top of it, and hands back a
+    /// builder for the server they describe.
+    pub fn into_builder(self) -> Result<ServerBuilder, anyhow::Error> {
+        let mut config = match &self.config_file {
+            Some(path) => Config::from_file(path.clone())?,
+            None => Config::new(None, None),
+        };
+
+        let settings = [
+            (ConfigKey::Dir, self.dir),
+            (ConfigKey::Dbfilename, self.dbfilename),
+            (ConfigKey::Requirepass, self.requirepass),
+            (ConfigKey::Appendonly, self.appendonly),
+            (ConfigKey::Maxmemory, self.maxmemory),
+            (ConfigKey::Logfile, self.logfile),
+            (ConfigKey::ClusterEnabled, self.cluster_enabled),
+            (ConfigKey::ClusterPort, self.cluster_port),
+            (ConfigKey::Databases, self.databases),
+        ];
+        for (key, value) in settings {
+            if let Some(value) = value {
+                config
+                    .set(&key, &value)
+                    .map_err(|_| invalid_value(&key, &value))?;
+            }
+        }
+        if let Some(supervised) = self.supervised {
+            config.supervised = supervised;
+        }
+        if let Some(io_threads) = self.io_threads {
+            config.io_threads = io_threads;
+        }
+
+        let mut builder = ServerBuilder::new().config(config).host(self.bind);
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let Some(replica_of) = self.replicaof {
+            builder = builder.replica_of(parse_replica_of(&replica_of)?);
+        }
+
+        Ok(builder)
+    }
+}
+
+fn parse_replica_of(values: &[String]) -> Result<Address, anyhow::Error> {
+    let parts: Vec<&str> = values.iter().flat_map(|v| v.split_whitespace()).collect();
+    let [host, port] = parts[..] else {
+        anyhow::bail!("usage --replicaof <host> <port>");
+    };
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| anyhow::anyhow!("invalid value '{}' for --replicaof port", port))?;
+
+    Ok(Address::new(host.to_string(), port))
+}
+
+fn invalid_value(key: &ConfigKey, value: &str) -> anyhow::Error {
+    anyhow::anyhow!("invalid value '{}' for '--{}'", value, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("not-redis").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn replicaof_takes_the_address_as_one_or_two_arguments() {
+        for args in [
+            &["--replicaof", "localhost", "6380"][..],
+            &["--replicaof", "localhost 6380"][..],
+        ] {
+            let replicaof = parse(args).unwrap().replicaof.unwrap();
+            let address = parse_replica_of(&replicaof).unwrap();
+            assert_eq!(address.name(), "localhost:6380");
+        }
+
+        let replicaof = parse(&["--replicaof", "localhost"]).unwrap().replicaof;
+        assert!(parse_replica_of(&replicaof.unwrap()).is_err());
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert!(parse(&["--port", "70000"]).is_err());
+        assert!(parse(&["--io-threads", "0"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+
+        let args = parse(&["--maxmemory", "lots"]).unwrap();
+        assert!(args.into_builder().is_err());
+        let args = parse(&["--appendonly", "maybe"]).unwrap();
+        assert!(args.into_builder().is_err());
+    }
+}
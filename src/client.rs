@@ -0,0 +1,176 @@
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::errors::RedisError;
+use crate::resp::Value;
+
+/// A connection to a server speaking RESP, this one or any other. Commands are
+/// sent as arrays of bulk strings and replies come back as `Value`s. The typed
+/// helpers turn error replies into a `RedisError`.
+#[derive(Debug)]
+pub struct Client {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+/// Commands sent to the server in one go, with the replies read back together.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Value>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<S: AsRef<str>>(&mut self, args: &[S]) -> &mut Self {
+        self.commands.push(Value::bulk_array(args));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+impl Client {
+    pub async fn connect(address: impl ToSocketAddrs) -> Result<Self, anyhow::Error> {
+        let stream = TcpStream::connect(address).await?;
+        // Commands are written whole, there's nothing to gain from batching small writes.
+        stream.set_nodelay(true)?;
+        Ok(Client {
+            stream,
+            buffer: BytesMut::with_capacity(4 * 1024),
+        })
+    }
+
+    /// Sends a command and returns whatever the server replied, errors included.
+    pub async fn command<S: AsRef<str>>(&mut self, args: &[S]) -> Result<Value, anyhow::Error> {
+        self.stream
+            .write_all(&Value::bulk_array(args).encode())
+            .await?;
+        self.read_value().await
+    }
+
+    /// Sends every command in the pipeline before reading any of the replies.
+    pub async fn pipeline(&mut self, pipeline: &Pipeline) -> Result<Vec<Value>, anyhow::Error> {
+        let mut request = BytesMut::new();
+        for command in pipeline.commands.iter() {
+            command.encode_into(&mut request);
+        }
+        self.stream.write_all(&request).await?;
+
+        let mut replies = Vec::with_capacity(pipeline.len());
+        for _ in 0..pipeline.len() {
+            replies.push(self.read_value().await?);
+        }
+
+        Ok(replies)
+    }
+
+    pub async fn ping(&mut self) -> Result<String, anyhow::Error> {
+        let reply = self.checked_command(&["PING"]).await?;
+        expect_string(reply, "PING")?.ok_or_else(|| unexpected_reply("PING"))
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        let reply = self.checked_command(&["GET", key]).await?;
+        expect_string(reply, "GET")
+    }
+
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        self.checked_command(&["SET", key, value]).await?;
+        Ok(())
+    }
+
+    /// Returns how many of the keys were deleted.
+    pub async fn del(&mut self, keys: &[&str]) -> Result<i64, anyhow::Error> {
+        let args: Vec<&str> = std::iter::once("DEL").chain(keys.iter().copied()).collect();
+        let reply = self.checked_command(&args).await?;
+        expect_integer(reply, "DEL")
+    }
+
+    pub async fn incr(&mut self, key: &str) -> Result<i64, anyhow::Error> {
+        let reply = self.checked_command(&["INCR", key]).await?;
+        expect_integer(reply, "INCR")
+    }
+
+    pub async fn incr_by(&mut self, key: &str, increment: i64) -> Result<i64, anyhow::Error> {
+        let reply = self
+            .checked_command(&["INCRBY", key, &increment.to_string()])
+            .await?;
+        expect_integer(reply, "INCRBY")
+    }
+
+    pub async fn keys(&mut self, pattern: &str) -> Result<Vec<String>, anyhow::Error> {
+        match self.checked_command(&["KEYS", pattern]).await? {
+            Value::Array(keys) => keys
+                .into_iter()
+                .map(|key| expect_string(key, "KEYS")?.ok_or_else(|| unexpected_reply("KEYS")))
+                .collect(),
+            _ => Err(unexpected_reply("KEYS")),
+        }
+    }
+
+    /// Adds an entry to a stream and returns its id. `id` is `*` to have one generated.
+    pub async fn xadd(
+        &mut self,
+        key: &str,
+        id: &str,
+        fields: &[(&str, &str)],
+    ) -> Result<String, anyhow::Error> {
+        let mut args = vec!["XADD", key, id];
+        for (field, value) in fields {
+            args.push(field);
+            args.push(value);
+        }
+        let reply = self.checked_command(&args).await?;
+        expect_string(reply, "XADD")?.ok_or_else(|| unexpected_reply("XADD"))
+    }
+
+    async fn checked_command(&mut self, args: &[&str]) -> Result<Value, anyhow::Error> {
+        match self.command(args).await? {
+            Value::Error(message) => Err(RedisError::from_reply(message).into()),
+            reply => Ok(reply),
+        }
+    }
+
+    async fn read_value(&mut self) -> Result<Value, anyhow::Error> {
+        loop {
+            if let Some((value, len)) = Value::parse(&self.buffer)? {
+                self.buffer.advance(len);
+                return Ok(value);
+            }
+
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                anyhow::bail!("Connection closed by the server");
+            }
+        }
+    }
+}
+
+fn expect_string(reply: Value, command: &str) -> Result<Option<String>, anyhow::Error> {
+    match reply {
+        Value::Null => Ok(None),
+        Value::SimpleString(value) => Ok(Some(value)),
+        Value::BulkString(value) => Ok(Some(String::from_utf8(value.to_vec())?)),
+        _ => Err(unexpected_reply(command)),
+    }
+}
+
+fn expect_integer(reply: Value, command: &str) -> Result<i64, anyhow::Error> {
+    match reply {
+        Value::Integer(value) => Ok(value),
+        _ => Err(unexpected_reply(command)),
+    }
+}
+
+fn unexpected_reply(command: &str) -> anyhow::Error {
+    anyhow::anyhow!("Unexpected reply to {}", command)
+}
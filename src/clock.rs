@@ -0,0 +1,109 @@
+//! Where a `Database` gets the time from when it works out and enforces expirations.
+//! It's the system clock unless an embedder swaps in another one, such as a
+//! `MockClock` that tests move forward by hand instead of sleeping until keys expire.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// The unix timestamp in milliseconds.
+    fn now(&self) -> u128;
+
+    /// Resolves once `now` has reached the unix timestamp in milliseconds.
+    fn sleep_until(&self, deadline: u128) -> Sleep;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> u128 {
+        (**self).now()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> Sleep {
+        (**self).sleep_until(deadline)
+    }
+}
+
+/// The real time, which is what a database uses by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> Sleep {
+        let wait = deadline.saturating_sub(self.now());
+        Box::pin(sleep(Duration::from_millis(wait as u64)))
+    }
+}
+
+/// A clock that only moves when it's told to. Expiry timers that come due when
+/// it's moved run as soon as the runtime gets to them.
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<watch::Sender<u128>>);
+
+impl MockClock {
+    /// Starts at the unix timestamp in milliseconds.
+    pub fn new(now: u128) -> Self {
+        MockClock(Arc::new(watch::Sender::new(now)))
+    }
+
+    /// Starts at the current time, so deadlines given as unix timestamps still make sense.
+    pub fn starting_now() -> Self {
+        Self::new(SystemClock.now())
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.0.send_modify(|now| *now += by.as_millis());
+    }
+
+    /// Moves the clock to the unix timestamp in milliseconds, backwards if need be.
+    pub fn set(&self, now: u128) {
+        self.0.send_replace(now);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u128 {
+        *self.0.borrow()
+    }
+
+    fn sleep_until(&self, deadline: u128) -> Sleep {
+        let mut now = self.0.subscribe();
+        Box::pin(async move {
+            // The sender lives as long as the clock, which outlives the database's timers
+            let _ = now.wait_for(|now| *now >= deadline).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_clocks_wake_sleepers_once_moved_past_their_deadline() {
+        let clock = MockClock::new(1_000);
+        let timer = tokio::spawn(clock.sleep_until(1_500));
+
+        clock.advance(Duration::from_millis(400));
+        tokio::task::yield_now().await;
+        assert!(!timer.is_finished());
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(clock.now(), 1_500);
+        timer.await.unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! The cluster bus, which nodes use to tell each other about themselves and
+//! the slots they serve. Instead of redis' binary format the messages are RESP
+//! arrays, sent on a port of their own.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+use crate::connection::FrameReader;
+use crate::encoding;
+use crate::server::{Address, RedisServer};
+use crate::utils::Frame;
+
+use super::ClusterNode;
+
+/// How often every known node is pinged.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a node gets to answer before its link is dropped.
+const BUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusMessageKind {
+    /// Asks the receiver to add the sender to the nodes it knows.
+    Meet,
+    Ping,
+    /// The answer to a MEET or PING.
+    Pong,
+}
+
+impl BusMessageKind {
+    fn name(&self) -> &'static str {
+        match self {
+            BusMessageKind::Meet => "MEET",
+            BusMessageKind::Ping => "PING",
+            BusMessageKind::Pong => "PONG",
+        }
+    }
+}
+
+/// What a node sends over the bus: itself, the slots it serves and the other
+/// nodes it knows about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusMessage {
+    pub kind: BusMessageKind,
+    pub sender: ClusterNode,
+    pub current_epoch: u64,
+    pub slots: Vec<usize>,
+    pub gossip: Vec<ClusterNode>,
+}
+
+impl BusMessage {
+    /// The message as a RESP array: the kind, the current epoch, the sender,
+    /// its slots as ranges like `0-99,200`, then every gossiped node.
+    pub fn encode(&self) -> String {
+        let mut args = vec![self.kind.name().to_string(), self.current_epoch.to_string()];
+        args.extend(node_fields(&self.sender));
+        args.push(encode_slots(&self.slots));
+        for node in self.gossip.iter() {
+            args.extend(node_fields(node));
+        }
+
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        encoding::encode_string_array(&args)
+    }
+
+    pub fn parse(data: &[String]) -> Result<Self, anyhow::Error> {
+        let [kind, current_epoch, rest @ ..] = data else {
+            anyhow::bail!("Cluster bus message is too short");
+        };
+        let kind = match kind.as_str() {
+            "MEET" => BusMessageKind::Meet,
+            "PING" => BusMessageKind::Ping,
+            "PONG" => BusMessageKind::Pong,
+            _ => anyhow::bail!("Unknown cluster bus message {}", kind),
+        };
+
+        if rest.len() < 6 || (rest.len() - 6) % 5 != 0 {
+            anyhow::bail!("Cluster bus message has the wrong number of fields");
+        }
+        let sender = parse_node(&rest[..5])?;
+        let slots = parse_slots(&rest[5])?;
+        let gossip = rest[6..]
+            .chunks(5)
+            .map(parse_node)
+            .collect::<Result<_, _>>()?;
+
+        Ok(BusMessage {
+            kind,
+            sender,
+            current_epoch: current_epoch.parse()?,
+            slots,
+            gossip,
+        })
+    }
+}
+
+fn node_fields(node: &ClusterNode) -> [String; 5] {
+    [
+        node.id.clone(),
+        node.address.host().to_string(),
+        node.address.port().to_string(),
+        node.bus_port.to_string(),
+        node.config_epoch.to_string(),
+    ]
+}
+
+fn parse_node(fields: &[String]) -> Result<ClusterNode, anyhow::Error> {
+    Ok(ClusterNode {
+        id: fields[0].clone(),
+        address: Address::new(fields[1].clone(), fields[2].parse()?),
+        bus_port: fields[3].parse()?,
+        config_epoch: fields[4].parse()?,
+    })
+}
+
+fn encode_slots(slots: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for slot in slots.iter().copied() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == slot => *end = slot,
+            _ => ranges.push((slot, slot)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_slots(slots: &str) -> Result<Vec<usize>, anyhow::Error> {
+    let mut parsed = vec![];
+    for range in slots.split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end) = (super::parse_slot(start)?, super::parse_slot(end)?);
+        parsed.extend(start..=end);
+    }
+
+    Ok(parsed)
+}
+
+/// The port the cluster bus listens on: `cluster-port`, or like redis the
+/// client port plus 10000 when it isn't set.
+pub fn bus_port(port: u16, cluster_port: u16) -> Result<u16, anyhow::Error> {
+    match cluster_port {
+        0 => port
+            .checked_add(10000)
+            .context("The cluster bus port is out of range, set cluster-port"),
+        port => Ok(port),
+    }
+}
+
+/// Answers the messages other nodes send to this one.
+pub async fn listen(listener: TcpListener, server: RedisServer) {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Error accepting a cluster bus connection: {}", e);
+                    continue;
+                }
+            },
+            _ = server.shutdown_requested() => return,
+        };
+
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_link(stream, peer.ip(), server).await {
+                eprintln!("Cluster bus link with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_link(
+    stream: TcpStream,
+    peer: IpAddr,
+    server: RedisServer,
+) -> Result<(), anyhow::Error> {
+    let _ = stream.set_nodelay(true);
+    let mut link = FrameReader::new(stream);
+
+    while let Some(frame) = link.read_frame().await? {
+        let mut message = BusMessage::parse(&frame_items(frame))?;
+        // A node listening on every interface can be reached where it connected from
+        if message
+            .sender
+            .address
+            .host()
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_unspecified())
+        {
+            message.sender.address = Address::new(peer.to_string(), message.sender.address.port());
+        }
+
+        let pong = server
+            .with_cluster(|cluster| {
+                cluster.handle_message(&message);
+                Ok(cluster.message(BusMessageKind::Pong))
+            })
+            .await?;
+        link.get_mut().write_all(pong.encode().as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// CLUSTER MEET: introduces this node to the one listening on `address`, which
+/// from then on gossips with it like any other node.
+pub async fn meet(server: &RedisServer, address: &Address) -> Result<(), anyhow::Error> {
+    let meet = server
+        .with_cluster(|cluster| Ok(cluster.message(BusMessageKind::Meet)))
+        .await?;
+
+    let mut link = connect(address).await?;
+    let pong = exchange(&mut link, &meet).await?;
+
+    server
+        .with_cluster(|cluster| {
+            cluster.add_node(pong.sender.clone());
+            cluster.handle_message(&pong);
+            Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Pings every known node, keeping a link open to each of them, and takes in
+/// whatever they answer with.
+pub async fn gossip(server: RedisServer) {
+    let mut links: HashMap<String, FrameReader<TcpStream>> = HashMap::new();
+    let mut interval = interval(GOSSIP_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = server.shutdown_requested() => return,
+        }
+
+        let Ok((ping, peers)) = server
+            .with_cluster(|cluster| {
+                Ok((
+                    cluster.message(BusMessageKind::Ping),
+                    cluster.peers().to_vec(),
+                ))
+            })
+            .await
+        else {
+            return;
+        };
+
+        for peer in peers {
+            // A link that fails is dropped and opened again on the next round
+            let mut link = match links.remove(&peer.id) {
+                Some(link) => link,
+                None => {
+                    let address = Address::new(peer.address.host().to_string(), peer.bus_port);
+                    match connect(&address).await {
+                        Ok(link) => link,
+                        Err(_) => continue,
+                    }
+                }
+            };
+            let Ok(pong) = exchange(&mut link, &ping).await else {
+                continue;
+            };
+            links.insert(peer.id, link);
+
+            let _ = server
+                .with_cluster(|cluster| {
+                    cluster.handle_message(&pong);
+                    Ok(())
+                })
+                .await;
+        }
+    }
+}
+
+async fn connect(address: &Address) -> Result<FrameReader<TcpStream>, anyhow::Error> {
+    let stream = timeout(BUS_TIMEOUT, TcpStream::connect(address.name()))
+        .await
+        .with_context(|| format!("Timed out connecting to {}", address.name()))?
+        .with_context(|| format!("Connecting to {}", address.name()))?;
+    let _ = stream.set_nodelay(true);
+
+    Ok(FrameReader::new(stream))
+}
+
+async fn exchange(
+    link: &mut FrameReader<TcpStream>,
+    message: &BusMessage,
+) -> Result<BusMessage, anyhow::Error> {
+    let reply = timeout(BUS_TIMEOUT, async {
+        link.get_mut()
+            .write_all(message.encode().as_bytes())
+            .await?;
+        link.read_frame().await
+    })
+    .await
+    .context("Timed out waiting for a cluster bus reply")??
+    .context("The cluster bus link was closed")?;
+
+    BusMessage::parse(&frame_items(reply))
+}
+
+/// The strings of the array a frame holds, without their lengths.
+fn frame_items(frame: Frame) -> Vec<String> {
+    frame.data.into_iter().skip(2).step_by(2).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_survive_the_bus() {
+        let node = |id: &str, port: u16, config_epoch: u64| ClusterNode {
+            id: id.to_string(),
+            address: Address::new("127.0.0.1".to_string(), port),
+            bus_port: port + 10000,
+            config_epoch,
+        };
+        let message = BusMessage {
+            kind: BusMessageKind::Meet,
+            sender: node("a", 7000, 3),
+            current_epoch: 5,
+            slots: (0..100).chain([200]).chain(300..302).collect(),
+            gossip: vec![node("b", 7001, 1), node("c", 7002, 0)],
+        };
+
+        let encoded = message.encode();
+        let (value, _) = crate::resp::Value::parse(encoded.as_bytes())
+            .unwrap()
+            .unwrap();
+        let crate::resp::Value::Array(items) = value else {
+            panic!("Expected an array");
+        };
+        let data: Vec<String> = items
+            .iter()
+            .map(|item| item.as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(data[2..7].join(" "), "a 127.0.0.1 7000 17000 3");
+        assert_eq!(data[7], "0-99,200,300-301");
+
+        assert_eq!(BusMessage::parse(&data).unwrap(), message);
+        assert!(BusMessage::parse(&data[..8]).is_ok());
+        assert!(BusMessage::parse(&data[..9]).is_err());
+    }
+}
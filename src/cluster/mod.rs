@@ -0,0 +1,693 @@
+use std::collections::HashMap;
+
+use crate::errors::RedisError;
+use crate::request::SetSlotAction;
+use crate::resp::Value;
+use crate::server::{generate_random_sha1_hex, Address};
+
+pub mod bus;
+
+pub use bus::{BusMessage, BusMessageKind};
+
+/// How many hash slots the keyspace is split into.
+pub const CLUSTER_SLOTS: usize = 16384;
+
+/// A node of the cluster, this one included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterNode {
+    pub id: String,
+    pub address: Address,
+    /// The port the node's cluster bus listens on.
+    pub bus_port: u16,
+    /// The epoch of the node's claim on its slots. When two nodes claim the
+    /// same slot, the one with the higher epoch gets it.
+    pub config_epoch: u64,
+}
+
+/// This node's view of the cluster: the nodes it knows about and which of them
+/// serves each hash slot. A slot that isn't served by anyone is unassigned,
+/// and the cluster is only up once every slot is served.
+#[derive(Debug)]
+pub struct Cluster {
+    // This node always comes first
+    nodes: Vec<ClusterNode>,
+    // For every slot, the index of the node serving it
+    slots: Vec<Option<usize>>,
+    // The highest epoch any node has told us about
+    current_epoch: u64,
+    // Slots this node is handing over, with the node they're going to
+    migrating: HashMap<usize, usize>,
+    // Slots this node is taking over, with the node they're coming from
+    importing: HashMap<usize, usize>,
+}
+
+/// A run of consecutive slots served by the same node, both ends inclusive.
+#[derive(Debug, PartialEq)]
+pub struct SlotRange {
+    pub start: usize,
+    pub end: usize,
+    node: usize,
+}
+
+impl Cluster {
+    pub fn new(address: Address) -> Self {
+        let myself = ClusterNode {
+            id: generate_random_sha1_hex(),
+            address,
+            bus_port: 0,
+            config_epoch: 0,
+        };
+
+        Cluster {
+            nodes: vec![myself],
+            slots: vec![None; CLUSTER_SLOTS],
+            current_epoch: 0,
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[0]
+    }
+
+    /// Records the port the server ended up listening on.
+    pub fn set_address(&mut self, address: Address) {
+        self.nodes[0].address = address;
+    }
+
+    pub fn set_bus_port(&mut self, bus_port: u16) {
+        self.nodes[0].bus_port = bus_port;
+    }
+
+    /// Adds a node, or updates its address if it's already known.
+    pub fn add_node(&mut self, node: ClusterNode) {
+        match self.nodes.iter_mut().find(|known| known.id == node.id) {
+            Some(known) => {
+                known.address = node.address;
+                known.bus_port = node.bus_port;
+            }
+            None => self.nodes.push(node),
+        }
+    }
+
+    pub fn node(&self, id: &str) -> Option<&ClusterNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// Every node but this one.
+    pub fn peers(&self) -> &[ClusterNode] {
+        &self.nodes[1..]
+    }
+
+    fn index_of(&self, id: &str) -> Result<usize, RedisError> {
+        self.nodes
+            .iter()
+            .position(|node| node.id == id)
+            .ok_or_else(|| RedisError::Custom(format!("ERR I don't know about node {}", id)))
+    }
+
+    /// The node serving a slot, if anyone is.
+    pub fn owner(&self, slot: usize) -> Option<&ClusterNode> {
+        self.slots[slot].map(|node| &self.nodes[node])
+    }
+
+    pub fn is_mine(&self, slot: usize) -> bool {
+        self.slots[slot] == Some(0)
+    }
+
+    /// CLUSTER ADDSLOTS: starts serving the slots on this node. Like redis either
+    /// every slot is added or none are.
+    pub fn add_slots(&mut self, slots: &[usize]) -> Result<(), RedisError> {
+        check_distinct(slots)?;
+        if let Some(slot) = slots.iter().find(|slot| self.slots[**slot].is_some()) {
+            return Err(RedisError::Custom(format!(
+                "ERR Slot {} is already busy",
+                slot
+            )));
+        }
+
+        for slot in slots {
+            self.slots[*slot] = Some(0);
+        }
+        Ok(())
+    }
+
+    /// CLUSTER DELSLOTS: forgets who serves the slots, whichever node it was.
+    pub fn del_slots(&mut self, slots: &[usize]) -> Result<(), RedisError> {
+        check_distinct(slots)?;
+        if let Some(slot) = slots.iter().find(|slot| self.slots[**slot].is_none()) {
+            return Err(RedisError::Custom(format!(
+                "ERR Slot {} is already unassigned",
+                slot
+            )));
+        }
+
+        for slot in slots {
+            self.slots[*slot] = None;
+        }
+        Ok(())
+    }
+
+    /// Declares that a known node serves a slot, whoever served it before.
+    pub fn assign_slot(&mut self, slot: usize, node_id: &str) -> Result<(), RedisError> {
+        let node = self.index_of(node_id)?;
+        self.slots[slot] = Some(node);
+        Ok(())
+    }
+
+    /// CLUSTER SETSLOT, the steps of moving a slot from one node to another.
+    /// `keys_in_slot` is how many keys this node still holds in the slot, which
+    /// it can't give away while there are any left.
+    pub fn set_slot(
+        &mut self,
+        slot: usize,
+        action: &SetSlotAction,
+        keys_in_slot: usize,
+    ) -> Result<(), RedisError> {
+        match action {
+            SetSlotAction::Migrating(node_id) => {
+                if !self.is_mine(slot) {
+                    return Err(RedisError::Custom(format!(
+                        "ERR I'm not the owner of hash slot {}",
+                        slot
+                    )));
+                }
+                let node = self.index_of(node_id)?;
+                self.migrating.insert(slot, node);
+            }
+            SetSlotAction::Importing(node_id) => {
+                if self.is_mine(slot) {
+                    return Err(RedisError::Custom(format!(
+                        "ERR I'm already the owner of hash slot {}",
+                        slot
+                    )));
+                }
+                let node = self.index_of(node_id)?;
+                self.importing.insert(slot, node);
+            }
+            SetSlotAction::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+            SetSlotAction::Node(node_id) => {
+                let node = self.index_of(node_id)?;
+                if self.is_mine(slot) && node != 0 && keys_in_slot > 0 {
+                    return Err(RedisError::Custom(format!("ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.", slot)));
+                }
+                self.migrating.remove(&slot);
+
+                // Taking over an imported slot needs a new epoch so the claim
+                // wins over the old owner's once the other nodes hear of it.
+                if node == 0 && self.importing.remove(&slot).is_some() {
+                    self.bump_epoch();
+                }
+                self.slots[slot] = Some(node);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where a command touching keys in `slot` has to go, like redis: MOVED to
+    /// the node serving the slot, or ASK the node it's being migrated to when
+    /// some of the keys have already left. A client that sent ASKING can use a
+    /// slot this node is still importing.
+    pub fn redirect(
+        &self,
+        slot: usize,
+        keys_missing: bool,
+        asking: bool,
+    ) -> Result<(), RedisError> {
+        if asking && self.importing.contains_key(&slot) {
+            return Ok(());
+        }
+
+        match self.slots[slot] {
+            None => Err(RedisError::custom("CLUSTERDOWN Hash slot not served")),
+            Some(0) => match self.migrating.get(&slot) {
+                Some(target) if keys_missing => Err(redirection("ASK", slot, &self.nodes[*target])),
+                _ => Ok(()),
+            },
+            Some(owner) => Err(redirection("MOVED", slot, &self.nodes[owner])),
+        }
+    }
+
+    pub fn slots_assigned(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Every run of slots served by one node, in slot order.
+    pub fn slot_ranges(&self) -> Vec<SlotRange> {
+        let mut ranges: Vec<SlotRange> = vec![];
+
+        for (slot, node) in self.slots.iter().enumerate() {
+            let Some(node) = *node else {
+                continue;
+            };
+
+            match ranges.last_mut() {
+                Some(range) if range.node == node && range.end + 1 == slot => range.end = slot,
+                _ => ranges.push(SlotRange {
+                    start: slot,
+                    end: slot,
+                    node,
+                }),
+            }
+        }
+
+        ranges
+    }
+
+    /// The reply to CLUSTER INFO.
+    pub fn info(&self) -> String {
+        let assigned = self.slots_assigned();
+        let state = if assigned == CLUSTER_SLOTS {
+            "ok"
+        } else {
+            "fail"
+        };
+        let size = (0..self.nodes.len())
+            .filter(|node| self.slots.contains(&Some(*node)))
+            .count();
+
+        [
+            ("cluster_state", state.to_string()),
+            ("cluster_slots_assigned", assigned.to_string()),
+            ("cluster_slots_ok", assigned.to_string()),
+            ("cluster_slots_pfail", "0".to_string()),
+            ("cluster_slots_fail", "0".to_string()),
+            ("cluster_known_nodes", self.nodes.len().to_string()),
+            ("cluster_size", size.to_string()),
+            ("cluster_current_epoch", self.current_epoch.to_string()),
+            ("cluster_my_epoch", self.myself().config_epoch.to_string()),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{}:{}\r\n", key, value))
+        .collect()
+    }
+
+    /// The reply to CLUSTER SLOTS: every range with the address and id of the
+    /// node serving it.
+    pub fn slots_value(&self) -> Value {
+        let ranges = self
+            .slot_ranges()
+            .into_iter()
+            .map(|range| {
+                let node = &self.nodes[range.node];
+                Value::Array(vec![
+                    Value::Integer(range.start as i64),
+                    Value::Integer(range.end as i64),
+                    Value::Array(vec![
+                        Value::from(node.address.host()),
+                        Value::Integer(node.address.port() as i64),
+                        Value::from(node.id.as_str()),
+                        Value::Array(vec![]),
+                    ]),
+                ])
+            })
+            .collect();
+
+        Value::Array(ranges)
+    }
+
+    /// The reply to CLUSTER SHARDS: for every node, the ranges it serves and how
+    /// to reach it. `my_offset` is this node's replication offset.
+    pub fn shards_value(&self, my_offset: u64) -> Value {
+        let ranges = self.slot_ranges();
+
+        let shards = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let slots = ranges
+                    .iter()
+                    .filter(|range| range.node == i)
+                    .flat_map(|range| [range.start as i64, range.end as i64])
+                    .map(Value::Integer)
+                    .collect();
+                let offset = if i == 0 { my_offset } else { 0 };
+
+                let node = Value::Array(vec![
+                    Value::from("id"),
+                    Value::from(node.id.as_str()),
+                    Value::from("port"),
+                    Value::Integer(node.address.port() as i64),
+                    Value::from("ip"),
+                    Value::from(node.address.host()),
+                    Value::from("endpoint"),
+                    Value::from(node.address.host()),
+                    Value::from("role"),
+                    Value::from("master"),
+                    Value::from("replication-offset"),
+                    Value::Integer(offset as i64),
+                    Value::from("health"),
+                    Value::from("online"),
+                ]);
+
+                Value::Array(vec![
+                    Value::from("slots"),
+                    Value::Array(slots),
+                    Value::from("nodes"),
+                    Value::Array(vec![node]),
+                ])
+            })
+            .collect();
+
+        Value::Array(shards)
+    }
+
+    /// The reply to CLUSTER NODES, a line per node in the format redis uses.
+    pub fn nodes_description(&self) -> String {
+        let ranges = self.slot_ranges();
+
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let flags = if i == 0 { "myself,master" } else { "master" };
+                let mut line = format!(
+                    "{} {}:{}@{} {} - 0 0 {} connected",
+                    node.id,
+                    node.address.host(),
+                    node.address.port(),
+                    node.bus_port,
+                    flags,
+                    node.config_epoch
+                );
+
+                for range in ranges.iter().filter(|range| range.node == i) {
+                    match range.start == range.end {
+                        true => line.push_str(&format!(" {}", range.start)),
+                        false => line.push_str(&format!(" {}-{}", range.start, range.end)),
+                    }
+                }
+                if i == 0 {
+                    let mut open: Vec<String> =
+                        self.migrating
+                            .iter()
+                            .map(|(slot, to)| format!(" [{}->-{}]", slot, self.nodes[*to].id))
+                            .chain(self.importing.iter().map(|(slot, from)| {
+                                format!(" [{}-<-{}]", slot, self.nodes[*from].id)
+                            }))
+                            .collect();
+                    open.sort();
+                    line.extend(open);
+                }
+
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    /// What this node tells the others about itself and the nodes it knows.
+    pub fn message(&self, kind: BusMessageKind) -> BusMessage {
+        let slots = (0..CLUSTER_SLOTS).filter(|slot| self.is_mine(*slot));
+
+        BusMessage {
+            kind,
+            sender: self.myself().clone(),
+            current_epoch: self.current_epoch,
+            slots: slots.collect(),
+            gossip: self.peers().to_vec(),
+        }
+    }
+
+    /// Takes in what another node told us about itself: its address, epoch and
+    /// slots, as well as any node it knows of that we don't. Only a MEET
+    /// introduces the sender itself.
+    pub fn handle_message(&mut self, message: &BusMessage) {
+        let sender = &message.sender;
+        if sender.id == self.myself().id {
+            return;
+        }
+        if message.kind == BusMessageKind::Meet {
+            self.add_node(sender.clone());
+        }
+        let Ok(index) = self.index_of(&sender.id) else {
+            return;
+        };
+
+        self.nodes[index] = sender.clone();
+        self.current_epoch = self.current_epoch.max(message.current_epoch);
+        self.resolve_epoch_collision(index);
+
+        for slot in message.slots.iter().copied() {
+            let wins = match self.slots[slot] {
+                Some(owner) if owner == index => false,
+                Some(owner) => self.nodes[owner].config_epoch < sender.config_epoch,
+                None => true,
+            };
+            // A slot being imported is claimed with SETSLOT NODE rather than taken back
+            if !wins || self.importing.contains_key(&slot) {
+                continue;
+            }
+
+            if self.is_mine(slot) {
+                self.migrating.remove(&slot);
+            }
+            self.slots[slot] = Some(index);
+        }
+
+        for node in message.gossip.iter() {
+            if self.index_of(&node.id).is_err() {
+                self.nodes.push(ClusterNode {
+                    config_epoch: 0,
+                    ..node.clone()
+                });
+            }
+        }
+    }
+
+    /// Moves this node to an epoch nobody has used yet.
+    fn bump_epoch(&mut self) {
+        self.current_epoch += 1;
+        self.nodes[0].config_epoch = self.current_epoch;
+    }
+
+    /// Like redis, two nodes with the same config epoch can't settle which one
+    /// a slot belongs to, so the one with the greater id moves on to a new epoch.
+    fn resolve_epoch_collision(&mut self, sender: usize) {
+        let (myself, sender) = (&self.nodes[0], &self.nodes[sender]);
+        if sender.config_epoch == myself.config_epoch && sender.id < myself.id {
+            self.bump_epoch();
+        }
+    }
+}
+
+fn redirection(kind: &str, slot: usize, node: &ClusterNode) -> RedisError {
+    RedisError::Custom(format!("{} {} {}", kind, slot, node.address.name()))
+}
+
+/// Parses a slot number the way CLUSTER ADDSLOTS and friends take them.
+pub fn parse_slot(slot: &str) -> Result<usize, RedisError> {
+    match slot.parse::<usize>() {
+        Ok(slot) if slot < CLUSTER_SLOTS => Ok(slot),
+        _ => Err(RedisError::custom("ERR Invalid or out of range slot")),
+    }
+}
+
+/// The hash slot a key belongs to. Like redis, when the key has a non empty
+/// `{hash tag}` only the tag is hashed, so keys sharing a tag share a slot.
+pub fn key_hash_slot(key: &[u8]) -> usize {
+    let key = hash_tag(key).unwrap_or(key);
+    crc16(key) as usize & (CLUSTER_SLOTS - 1)
+}
+
+/// Fails with CROSSSLOT unless every key hashes to the same slot.
+pub fn check_same_slot(keys: &[&str]) -> Result<(), RedisError> {
+    let mut slots = keys.iter().map(|key| key_hash_slot(key.as_bytes()));
+    match slots.next() {
+        Some(first) if slots.any(|slot| slot != first) => Err(RedisError::CrossSlot),
+        _ => Ok(()),
+    }
+}
+
+/// What's between the first `{` and the first `}` after it, if that isn't empty.
+fn hash_tag(key: &[u8]) -> Option<&[u8]> {
+    let start = key.iter().position(|b| *b == b'{')? + 1;
+    let len = key[start..].iter().position(|b| *b == b'}')?;
+    (len > 0).then(|| &key[start..start + len])
+}
+
+/// CRC16 as redis cluster uses it: the XMODEM variant, polynomial 0x1021 with
+/// no initial value or final XOR.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => (crc << 1) ^ 0x1021,
+        })
+    })
+}
+
+fn check_distinct(slots: &[usize]) -> Result<(), RedisError> {
+    let mut seen = vec![false; CLUSTER_SLOTS];
+    for slot in slots {
+        if std::mem::replace(&mut seen[*slot], true) {
+            return Err(RedisError::Custom(format!(
+                "ERR Slot {} specified multiple times",
+                slot
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_grouped_into_ranges_per_node() {
+        let mut cluster = Cluster::new(Address::new("127.0.0.1".to_string(), 7000));
+        cluster.add_node(node("other", 7001));
+
+        let slots: Vec<usize> = (0..100).chain(200..300).collect();
+        cluster.add_slots(&slots).unwrap();
+        for slot in 100..200 {
+            cluster.assign_slot(slot, "other").unwrap();
+        }
+
+        let ranges: Vec<(usize, usize, &str)> = cluster
+            .slot_ranges()
+            .iter()
+            .map(|range| {
+                (
+                    range.start,
+                    range.end,
+                    cluster.nodes[range.node].id.as_str(),
+                )
+            })
+            .collect();
+        let my_id = cluster.myself().id.as_str();
+        assert_eq!(
+            ranges,
+            vec![(0, 99, my_id), (100, 199, "other"), (200, 299, my_id)]
+        );
+        assert!(cluster.is_mine(250));
+        assert_eq!(cluster.owner(150).unwrap().id, "other");
+        assert!(cluster.owner(300).is_none());
+        assert!(cluster.info().contains("cluster_state:fail\r\n"));
+        assert!(cluster.info().contains("cluster_size:2\r\n"));
+    }
+
+    #[test]
+    fn adding_or_removing_slots_is_all_or_nothing() {
+        let mut cluster = Cluster::new(Address::new("127.0.0.1".to_string(), 7000));
+        cluster.add_slots(&[1, 2]).unwrap();
+
+        assert!(cluster.add_slots(&[3, 2]).is_err());
+        assert!(cluster.add_slots(&[4, 4]).is_err());
+        assert_eq!(cluster.slots_assigned(), 2);
+
+        assert!(cluster.del_slots(&[1, 3]).is_err());
+        assert_eq!(cluster.slots_assigned(), 2);
+        cluster.del_slots(&[1, 2]).unwrap();
+        assert_eq!(cluster.slots_assigned(), 0);
+
+        assert!(parse_slot("16384").is_err());
+        assert_eq!(parse_slot("16383").unwrap(), 16383);
+    }
+
+    #[test]
+    fn keys_are_hashed_like_redis() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        assert_ne!(key_hash_slot(b"foo{}{bar}"), key_hash_slot(b"bar"));
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+        assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+
+        assert!(check_same_slot(&["{a}1", "{a}2", "a"]).is_ok());
+        assert!(check_same_slot(&["foo", "bar"]).is_err());
+        assert!(check_same_slot(&[]).is_ok());
+    }
+
+    fn node(id: &str, port: u16) -> ClusterNode {
+        ClusterNode {
+            id: id.to_string(),
+            address: Address::new("127.0.0.1".to_string(), port),
+            bus_port: port + 10000,
+            config_epoch: 0,
+        }
+    }
+
+    #[test]
+    fn the_claim_with_the_higher_epoch_wins_a_slot() {
+        let mut cluster = Cluster::new(Address::new("127.0.0.1".to_string(), 7000));
+        cluster.add_slots(&[1, 2]).unwrap();
+        cluster.bump_epoch();
+
+        let mut other = node("other", 7001);
+        let message = |sender: &ClusterNode, slots: Vec<usize>| BusMessage {
+            kind: BusMessageKind::Ping,
+            sender: sender.clone(),
+            current_epoch: sender.config_epoch,
+            slots,
+            gossip: vec![node("third", 7002)],
+        };
+
+        // Nodes have to be met before anything they say is taken in
+        cluster.handle_message(&message(&other, vec![2, 3]));
+        assert!(cluster.node("other").is_none());
+
+        cluster.handle_message(&BusMessage {
+            kind: BusMessageKind::Meet,
+            ..message(&other, vec![2, 3])
+        });
+        assert!(cluster.is_mine(2));
+        assert_eq!(cluster.owner(3).unwrap().id, "other");
+        assert!(cluster.node("third").is_some());
+
+        other.config_epoch = 2;
+        cluster.handle_message(&message(&other, vec![2, 3]));
+        assert_eq!(cluster.owner(2).unwrap().id, "other");
+        assert!(cluster.is_mine(1));
+        assert!(cluster.info().contains("cluster_current_epoch:2\r\n"));
+    }
+
+    #[test]
+    fn slots_are_redirected_while_they_move() {
+        let mut cluster = Cluster::new(Address::new("127.0.0.1".to_string(), 7000));
+        cluster.add_node(node("other", 7001));
+        cluster.add_slots(&[1]).unwrap();
+        cluster.assign_slot(2, "other").unwrap();
+
+        assert!(cluster.redirect(1, true, false).is_ok());
+        assert_eq!(
+            cluster.redirect(2, false, false).unwrap_err().to_string(),
+            "MOVED 2 127.0.0.1:7001"
+        );
+        assert!(cluster.redirect(3, false, false).is_err());
+
+        let migrating = SetSlotAction::Migrating("other".to_string());
+        cluster.set_slot(1, &migrating, 1).unwrap();
+        assert!(cluster.redirect(1, false, false).is_ok());
+        assert_eq!(
+            cluster.redirect(1, true, false).unwrap_err().to_string(),
+            "ASK 1 127.0.0.1:7001"
+        );
+
+        let to_other = SetSlotAction::Node("other".to_string());
+        assert!(cluster.set_slot(1, &to_other, 1).is_err());
+        cluster.set_slot(1, &to_other, 0).unwrap();
+        assert_eq!(cluster.owner(1).unwrap().id, "other");
+
+        let importing = SetSlotAction::Importing("other".to_string());
+        cluster.set_slot(2, &importing, 0).unwrap();
+        assert!(cluster.redirect(2, false, true).is_ok());
+        assert!(cluster.redirect(2, false, false).is_err());
+
+        let myself = SetSlotAction::Node(cluster.myself().id.clone());
+        cluster.set_slot(2, &myself, 0).unwrap();
+        assert!(cluster.is_mine(2));
+        assert_eq!(cluster.myself().config_epoch, 1);
+    }
+}
@@ -0,0 +1,236 @@
+//! Count-min sketches, like the ones the RedisBloom module adds. A sketch is a
+//! grid of counters, `depth` rows of `width` each, and an item bumps one counter
+//! in every row. Other items landing on the same counters only ever push them
+//! up, so the smallest of an item's counters is the closest to its true count
+//! and never below it.
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+use crate::utils::item_hashes;
+
+// Like for the filters, no sketch is larger than a value a client could send
+const MAX_SKETCH_BYTES: u64 = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CountMinSketch {
+    width: u64,
+    depth: u64,
+    /// Row after row.
+    counters: Vec<u64>,
+    /// The sum of every increment, which is also the sum of every row.
+    count: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: u64, depth: u64) -> Result<Self, RedisError> {
+        let size = width.saturating_mul(depth);
+        if size.saturating_mul(8) > MAX_SKETCH_BYTES {
+            return Err(RedisError::custom("ERR CMS: Insufficient memory"));
+        }
+
+        Ok(CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; size as usize],
+            count: 0,
+        })
+    }
+
+    /// The dimensions of a sketch whose estimates are at most `error` times the
+    /// total count too high, with a chance of `probability` of being further off.
+    pub fn dimensions_for(error: f64, probability: f64) -> (u64, u64) {
+        let width = (2.0 / error).ceil() as u64;
+        let depth = (probability.ln() / 0.5f64.ln()).ceil() as u64;
+        (width, depth.max(1))
+    }
+
+    /// The index of the item's counter in every row.
+    fn positions(&self, item: &str) -> impl Iterator<Item = usize> {
+        let (h1, h2) = item_hashes(item);
+        let width = self.width;
+        (0..self.depth).map(move |row| {
+            let column = h1.wrapping_add(row.wrapping_mul(h2)) % width;
+            (row * width + column) as usize
+        })
+    }
+
+    pub fn query(&self, item: &str) -> u64 {
+        self.positions(item)
+            .map(|position| self.counters[position])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Returns the item's count after the increment. Nothing is changed if any
+    /// of its counters would overflow.
+    pub fn increment(&mut self, item: &str, by: u64) -> Result<u64, RedisError> {
+        let overflow = || RedisError::custom("ERR CMS: INCRBY overflow");
+        let positions: Vec<usize> = self.positions(item).collect();
+        let count = self.count.checked_add(by).ok_or_else(overflow)?;
+        if positions
+            .iter()
+            .any(|position| self.counters[*position].checked_add(by).is_none())
+        {
+            return Err(overflow());
+        }
+
+        for position in positions {
+            self.counters[position] += by;
+        }
+        self.count = count;
+
+        Ok(self.query(item))
+    }
+
+    /// Replaces the counters with the weighted sum of the sources', which must
+    /// all have the same dimensions. The sketch may be one of the sources, so
+    /// they're passed as copies.
+    pub fn merge(&mut self, sources: &[(CountMinSketch, u64)]) -> Result<(), RedisError> {
+        if sources
+            .iter()
+            .any(|(source, _)| source.width != self.width || source.depth != self.depth)
+        {
+            return Err(RedisError::custom("ERR CMS: width/depth is not equal"));
+        }
+
+        let overflow = || RedisError::custom("ERR CMS: MERGE overflow");
+        let weighted_sum = |value: &dyn Fn(&CountMinSketch) -> u64| {
+            sources.iter().try_fold(0u64, |sum, (source, weight)| {
+                value(source)
+                    .checked_mul(*weight)
+                    .and_then(|value| sum.checked_add(value))
+                    .ok_or_else(overflow)
+            })
+        };
+
+        let counters = (0..self.counters.len())
+            .map(|i| weighted_sum(&|source| source.counters[i]))
+            .collect::<Result<_, _>>()?;
+        self.count = weighted_sum(&|source| source.count)?;
+        self.counters = counters;
+
+        Ok(())
+    }
+}
+
+/// Saved under RedisBloom's type name, with the counters packed into a single
+/// string of little endian integers.
+impl ModuleValue for CountMinSketch {
+    const TYPE_NAME: &'static str = "CMSk-TYPE";
+    const ENCODING_VERSION: u16 = 0;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::CountMinSketch(sketch) => Some(sketch),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::CountMinSketch(sketch) => Some(sketch),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::CountMinSketch(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        let counters = self
+            .counters
+            .iter()
+            .flat_map(|counter| counter.to_le_bytes())
+            .collect();
+
+        vec![
+            ModuleField::Uint(self.width),
+            ModuleField::Uint(self.depth),
+            ModuleField::Uint(self.count),
+            ModuleField::String(counters),
+        ]
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [width, depth, count, counters] = fields else {
+            anyhow::bail!("Count-min sketch has the wrong number of fields");
+        };
+        let (width, depth) = (width.as_uint()?, depth.as_uint()?);
+        let counters = counters.as_bytes()?;
+        let bytes = width
+            .checked_mul(depth)
+            .and_then(|size| size.checked_mul(8))
+            .filter(|bytes| *bytes <= MAX_SKETCH_BYTES)
+            .ok_or_else(|| anyhow::anyhow!("Count-min sketch is too big"))?;
+        if width == 0 || depth == 0 || counters.len() as u64 != bytes {
+            anyhow::bail!("Count-min sketch has the wrong number of counters");
+        }
+
+        Ok(CountMinSketch {
+            width,
+            depth,
+            counters: counters
+                .chunks(8)
+                .map(|counter| u64::from_le_bytes(counter.try_into().expect("chunks of 8")))
+                .collect(),
+            count: count.as_uint()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_never_underestimated() {
+        let mut sketch = CountMinSketch::new(200, 5).unwrap();
+        let count = |i: u64| i % 10 + 1;
+        for i in 0..1000 {
+            sketch.increment(&i.to_string(), count(i)).unwrap();
+        }
+
+        assert!((0..1000).all(|i| sketch.query(&i.to_string()) >= count(i)));
+        assert_eq!(sketch.count, (0..1000).map(count).sum::<u64>());
+
+        let saved = CountMinSketch::from_fields(&sketch.to_fields()).unwrap();
+        assert_eq!(saved, sketch);
+    }
+
+    #[test]
+    fn overflowing_increments_change_nothing() {
+        let mut sketch = CountMinSketch::new(10, 2).unwrap();
+        sketch.increment("a", u64::MAX - 1).unwrap();
+        assert_eq!(
+            sketch.increment("a", 2).unwrap_err().to_string(),
+            "ERR CMS: INCRBY overflow"
+        );
+        assert_eq!(sketch.query("a"), u64::MAX - 1);
+    }
+
+    #[test]
+    fn sketches_merge_with_weights() {
+        let mut first = CountMinSketch::new(50, 3).unwrap();
+        first.increment("a", 2).unwrap();
+        let mut second = CountMinSketch::new(50, 3).unwrap();
+        second.increment("a", 1).unwrap();
+        second.increment("b", 4).unwrap();
+
+        let sources = [(first.clone(), 3), (second, 1)];
+        first.merge(&sources).unwrap();
+        assert_eq!(first.query("a"), 7);
+        assert_eq!(first.query("b"), 4);
+        assert_eq!(first.count, 11);
+
+        let other = CountMinSketch::new(10, 3).unwrap();
+        assert!(first.merge(&[(other, 1)]).is_err());
+    }
+
+    #[test]
+    fn dimensions_come_from_the_error_and_probability() {
+        assert_eq!(CountMinSketch::dimensions_for(0.001, 0.01), (2000, 7));
+        assert_eq!(CountMinSketch::dimensions_for(0.5, 0.9), (4, 1));
+    }
+}
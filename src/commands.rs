@@ -1,311 +1,1695 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::sync::broadcast::{Receiver, Sender};
+use bytes::Bytes;
 
+use crate::bloom::ScalableBloomFilter;
+use crate::client::{Client, Pipeline};
+use crate::cluster::{bus, key_hash_slot};
+use crate::cms::CountMinSketch;
+use crate::cuckoo::ScalableCuckooFilter;
+use crate::errors::RedisError;
+use crate::json::{self, JsonPath};
+use crate::object::StringValue;
 use crate::request::{
-    self, CommandExpiration, SetCommand, XAddCommand, XRangeCommand, XReadCommand,
+    self, CommandExpiration, ExpireCondition, LMPopCommand, LMoveCommand, ListEnd, ListPosition,
+    ObjectCommand, SetCommand, XAddCommand, XRangeCommand, XReadCommand,
 };
-use crate::{data, encoding, server, transmission};
+use crate::resp::Value;
+use crate::session::Session;
+use crate::timeseries::TimeSeries;
+use crate::topk::TopK;
+use crate::utils::current_unix_timestamp;
+use crate::{data, encoding, export, namespace, server};
 
-pub fn pong(body: Option<String>) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+pub fn pong(body: Option<String>) -> Result<Vec<Value>, RedisError> {
     let response = match body {
-        Some(body) => encoding::simple_string(&body),
-        None => encoding::simple_string("PONG"),
-    }
-    .as_bytes()
-    .to_vec();
-    let response = vec![response];
+        Some(body) => Value::SimpleString(body),
+        None => Value::simple("PONG"),
+    };
+
+    Ok(vec![response])
+}
 
-    Ok(response)
+pub fn echo_response(body: String) -> Result<Vec<Value>, RedisError> {
+    Ok(vec![Value::from(body)])
 }
 
-pub fn echo_response(body: String) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = encoding::bulk_string(&body).as_bytes().to_vec();
-    let response = vec![response];
+pub fn get_value(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let value = database.get(&key)?;
 
-    Ok(response)
+    Ok(vec![Value::from(value)])
 }
 
-pub fn get_value(database: &data::Database, key: String) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let value = database.get(&key);
-    let response = match value {
-        Ok(v) => match v {
-            Some(v) => encoding::bulk_string(&v),
-            None => encoding::empty_string(),
-        },
-        Err(v) => encoding::error_string(&v.to_string()),
-    }
-    .as_bytes()
-    .to_vec();
+pub fn get_multiple(
+    database: &data::Database,
+    keys: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let values = database.get_multiple(&keys);
+
+    Ok(vec![Value::from(values)])
+}
+
+pub fn set_multiple(
+    database: &data::Database,
+    pairs: Vec<(String, String)>,
+) -> Result<Vec<Value>, RedisError> {
+    database.set_multiple(pairs)?;
+
+    Ok(vec![Value::ok()])
+}
+
+pub fn set_multiple_if_none_exist(
+    database: &data::Database,
+    pairs: Vec<(String, String)>,
+) -> Result<Vec<Value>, RedisError> {
+    let set = database.set_multiple_if_none_exist(pairs)?;
+
+    Ok(vec![Value::Integer(set as i64)])
+}
+
+pub fn count_existing(
+    database: &data::Database,
+    keys: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let count = database.count_existing(&keys);
+
+    Ok(vec![Value::Integer(count as i64)])
+}
 
-    let response = vec![response];
+pub fn touch(database: &data::Database, keys: Vec<String>) -> Result<Vec<Value>, RedisError> {
+    let touched = database.touch(&keys);
 
-    Ok(response)
+    Ok(vec![Value::Integer(touched as i64)])
 }
 
-pub async fn get_info(server: &server::RedisServer) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+pub fn random_key(database: &data::Database) -> Result<Vec<Value>, RedisError> {
+    let key = database.random_key()?;
+
+    Ok(vec![Value::from(key.as_deref())])
+}
+
+pub fn get_range(
+    database: &data::Database,
+    key: String,
+    start: i64,
+    end: i64,
+) -> Result<Vec<Value>, RedisError> {
+    let value = database.get_range(&key, start, end)?;
+
+    Ok(vec![Value::from(value)])
+}
+
+pub async fn get_info(
+    server: &server::RedisServer,
+    database: &data::Database,
+) -> Result<Vec<Value>, RedisError> {
     let server = server.read().await;
     let role = match server.role {
         server::ServerRole::Master(..) => "master",
-        server::ServerRole::Slave => "slave",
+        server::ServerRole::Slave(..) => "slave",
     };
 
     let mut map = HashMap::new();
 
-    let master_replid = server.replication.id.as_str();
-    let master_repl_offset = server.replication.offset.to_string();
+    let replicas: Vec<(String, String)> = match &server.role {
+        server::ServerRole::Master(replicas) => replicas
+            .iter()
+            .enumerate()
+            .map(|(i, replica)| (format!("slave{}", i), replica.info()))
+            .collect(),
+        server::ServerRole::Slave(..) => vec![],
+    };
+    let connected_slaves = replicas.len().to_string();
+    map.insert("connected_slaves", connected_slaves.as_str());
+    for (name, info) in replicas.iter() {
+        map.insert(name, info);
+    }
+
+    // Replicas report where they are in their master's replication stream
+    let replication = match &server.role {
+        server::ServerRole::Slave(link) => link
+            .replication()
+            .unwrap_or_else(|| server.replication.clone()),
+        server::ServerRole::Master(..) => server.replication.clone(),
+    };
+    let master_replid = replication.id.as_str();
+    let master_repl_offset = replication.offset.to_string();
+
+    let master_link = match &server.role {
+        server::ServerRole::Slave(link) => Some((
+            link.master().host().to_string(),
+            link.master().port().to_string(),
+            if link.is_up() { "up" } else { "down" },
+        )),
+        server::ServerRole::Master(..) => None,
+    };
+    if let Some((host, port, status)) = &master_link {
+        map.insert("master_host", host);
+        map.insert("master_port", port);
+        map.insert("master_link_status", status);
+    }
 
     map.insert("role", role);
     map.insert("master_replid", master_replid);
     map.insert("master_repl_offset", &master_repl_offset);
+    let replica_desyncs = server.replica_desyncs.to_string();
+    map.insert("replica_desyncs", &replica_desyncs);
+
+    let changes_since_last_save = database.dirty().to_string();
+    let last_save_time = database.last_save().to_string();
+    map.insert("rdb_changes_since_last_save", &changes_since_last_save);
+    map.insert("rdb_last_save_time", &last_save_time);
+
+    let bgsave_in_progress = (database.is_background_saving() as u8).to_string();
+    let last_bgsave_status = match database.last_background_save_ok() {
+        true => "ok",
+        false => "err",
+    };
+    map.insert("rdb_bgsave_in_progress", &bgsave_in_progress);
+    map.insert("rdb_last_bgsave_status", last_bgsave_status);
+
+    let aof_status = server.aof.status().await;
+    let aof_enabled = (aof_status.enabled as u8).to_string();
+    let aof_rewrite_in_progress = (aof_status.rewriting as u8).to_string();
+    let aof_current_size = aof_status.current_size.to_string();
+    let aof_base_size = aof_status.base_size.to_string();
+    map.insert("aof_enabled", &aof_enabled);
+    map.insert("aof_rewrite_in_progress", &aof_rewrite_in_progress);
+    map.insert("aof_current_size", &aof_current_size);
+    map.insert("aof_base_size", &aof_base_size);
+
+    let cluster_enabled = (server.cluster.is_some() as u8).to_string();
+    map.insert("cluster_enabled", &cluster_enabled);
 
-    let response = encoding::bulk_string_from_hashmap(&map).as_bytes().to_vec();
-    let response = vec![response];
+    let info = map
+        .iter()
+        .map(|(key, value)| format!("{}:{}\r\n", key, value))
+        .collect::<String>();
 
-    Ok(response)
+    Ok(vec![Value::from(info)])
 }
 
-pub async fn perform_psync(server: &server::RedisServer) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let repl_id = &server.read().await.replication.id;
-    let encoded = encoding::simple_string(&format!("FULLRESYNC {} 0", repl_id));
+/// Answers PSYNC with either the part of the replication stream the replica missed,
+/// when it asks to continue from somewhere we still have, or a snapshot of the
+/// whole dataset. The snapshot isn't a RESP value, so it's handed back as the bytes
+/// to send after the reply. Also returns the offset the replica is synced up to.
+/// A replica that was dropped for being out of sync always gets the snapshot.
+pub async fn perform_psync(
+    server: &server::RedisServer,
+    database: &data::Database,
+    replication_id: String,
+    offset: request::PsyncOffset,
+    replica: Option<server::Address>,
+) -> Result<(Value, Option<Bytes>, u64), RedisError> {
+    let (replication, compress) = {
+        let server = server.read().await;
+        (server.replication.clone(), server.config.rdb_compression)
+    };
+    let needs_full_resync = match &replica {
+        Some(address) => server.take_full_resync(address).await,
+        None => false,
+    };
+
+    if let request::PsyncOffset::Offset(offset) = offset {
+        if replication_id == replication.id
+            && !needs_full_resync
+            && server.backlog_since(offset).await.is_some()
+        {
+            return Ok((Value::simple("CONTINUE"), None, offset));
+        }
+    }
 
-    // TODO: Get the actual database
-    let empty_rdb = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
-    let empty_rdb = hex::decode(empty_rdb)?;
-    let rdb_sync = encoding::encode_rdb(empty_rdb);
+    let replication = server.start_full_resync().await;
+    let reply = Value::simple(format!(
+        "FULLRESYNC {} {}",
+        replication.id, replication.offset
+    ));
 
-    Ok(vec![encoded.as_bytes().to_vec(), rdb_sync])
+    let rdb = database.to_rdb(compress)?;
+    let rdb_sync = encoding::encode_rdb(rdb);
+
+    Ok((reply, Some(Bytes::from(rdb_sync)), replication.offset))
 }
 
 pub fn replica_confirm(
     repl: request::ReplicationCommand,
     size: usize,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+) -> Result<Vec<Value>, RedisError> {
     let response = match repl {
-        request::ReplicationCommand::Ack => {
-            encoding::encode_string_array(&["REPLCONF", "ACK", &size.to_string()])
-                .as_bytes()
-                .to_vec()
+        request::ReplicationCommand::GetAck => {
+            Value::bulk_array(&["REPLCONF", "ACK", &size.to_string()])
         }
-        _ => encoding::okay_string().as_bytes().to_vec(),
+        _ => Value::ok(),
     };
-    let response = vec![response];
 
-    Ok(response)
+    Ok(vec![response])
 }
 
 pub fn set_value(
     database: &data::Database,
     set_command: SetCommand,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let result = match database.set_value(
+) -> Result<Vec<Value>, RedisError> {
+    let result = database.set_value(
         set_command.key,
         set_command.value,
         set_command.get_old_value,
         set_command.overwrite,
         set_command.expires,
-    ) {
-        Ok(v) => v,
-        Err(e) => encoding::error_string(&e.to_string()),
-    };
+    )?;
 
-    let response = result.as_bytes().to_vec();
-    let response = vec![response];
-
-    Ok(response)
+    Ok(vec![result])
 }
 
-pub fn delete_keys(
-    database: &data::Database,
-    keys: Vec<String>,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+pub fn delete_keys(database: &data::Database, keys: Vec<String>) -> Result<Vec<Value>, RedisError> {
     let count = database.remove_multiple(keys);
 
-    let response = encoding::encode_integer(count as i64).as_bytes().to_vec();
-    let response = vec![response];
+    Ok(vec![Value::Integer(count as i64)])
+}
+
+pub fn unlink_keys(database: &data::Database, keys: Vec<String>) -> Result<Vec<Value>, RedisError> {
+    let count = database.unlink(keys);
 
-    Ok(response)
+    Ok(vec![Value::Integer(count as i64)])
+}
+
+pub fn flush(database: &data::Database, asynchronously: bool) -> Result<Vec<Value>, RedisError> {
+    database.flush(asynchronously);
+
+    Ok(vec![Value::ok()])
+}
+
+pub fn flush_all(
+    database: &data::Database,
+    asynchronously: bool,
+) -> Result<Vec<Value>, RedisError> {
+    database.flush_all(asynchronously);
+
+    Ok(vec![Value::ok()])
 }
 
 pub fn update_expiration(
     database: &data::Database,
     key: String,
     expiration: CommandExpiration,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.update_expiration(&key, expiration) {
-        Ok(v) => v,
-        Err(e) => encoding::error_string(&e.to_string()),
+) -> Result<Vec<Value>, RedisError> {
+    let value = database.update_expiration(&key, expiration)?;
+
+    Ok(vec![Value::from(value)])
+}
+
+pub fn expire(
+    database: &data::Database,
+    key: String,
+    expiration: CommandExpiration,
+    condition: ExpireCondition,
+) -> Result<Vec<Value>, RedisError> {
+    let expires_at = match expiration {
+        CommandExpiration::After(duration) => database.now() + duration.as_millis(),
+        CommandExpiration::At(deadline) => deadline,
+        // Only SET and GETEX leave a TTL be or take it away
+        CommandExpiration::Keep | CommandExpiration::Persist => return Err(RedisError::Syntax),
     };
-    let response = response.as_bytes().to_vec();
-    let response = vec![response];
+    let changed = database.set_expiration(&key, expires_at, condition)?;
 
-    Ok(response)
+    Ok(vec![Value::Integer(changed as i64)])
 }
 
-pub fn get_delete_key(
+pub fn persist(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let persisted = database.persist(&key)?;
+
+    Ok(vec![Value::Integer(persisted as i64)])
+}
+
+/// TTL and PTTL, in units of `millis_per_unit`. A key that doesn't exist is -2
+/// and one that never expires is -1.
+pub fn time_to_live(
     database: &data::Database,
     key: String,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.get_remove(&key) {
-        Ok(v) => match v {
-            Some(v) => v,
-            None => encoding::empty_string(),
-        },
-        Err(e) => encoding::error_string(&e.to_string()),
-    }
-    .as_bytes()
-    .to_vec();
+    millis_per_unit: u128,
+) -> Result<Vec<Value>, RedisError> {
+    let ttl = match database.expiration(&key) {
+        None => -2,
+        Some(None) => -1,
+        Some(Some(expires_at)) => {
+            let remaining = expires_at.saturating_sub(database.now());
+            // Rounded to the nearest unit, like redis does
+            ((remaining + millis_per_unit / 2) / millis_per_unit) as i64
+        }
+    };
+
+    Ok(vec![Value::Integer(ttl)])
+}
 
-    let response = vec![response];
+pub fn get_delete_key(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let value = database.get_remove(&key)?;
 
-    Ok(response)
+    Ok(vec![Value::from(value)])
 }
 
 pub async fn transmit_wait(
     server: &server::RedisServer,
     num_replicas: usize,
     timeout: u64,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+) -> Result<Vec<Value>, RedisError> {
     let num_respondents = server.perform_wait(num_replicas, timeout).await?;
-    let response = encoding::encode_integer(num_respondents as i64)
-        .as_bytes()
-        .to_vec();
-    let response = vec![response];
 
-    Ok(response)
+    Ok(vec![Value::Integer(num_respondents as i64)])
 }
 
 pub async fn view_config(
     server: &server::RedisServer,
     config_command: request::ConfigCommand,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let read = server.read().await;
-    let (key, val) = match config_command {
+) -> Result<Vec<Value>, RedisError> {
+    let response = match config_command {
         request::ConfigCommand::Get(key) => {
-            let config_option = match &key {
-                request::ConfigKey::Dir => read.config.dir.clone(),
-                request::ConfigKey::Dbfilename => read.config.db_file_name.clone(),
-            }
-            .unwrap_or_else(|| String::from(""));
-
-            (key.to_string(), config_option)
+            let val = server.read().await.config.get(&key);
+            Value::bulk_array(&[key.to_string(), val])
+        }
+        request::ConfigCommand::Set(key, value) => {
+            server.set_config(key, value).await?;
+            Value::ok()
         }
     };
 
-    let response = encoding::encode_string_array(&[&key, &val])
-        .as_bytes()
-        .to_vec();
-    let response = vec![response];
-    Ok(response)
+    Ok(vec![response])
 }
 
-pub fn get_keys(
-    database: &data::Database,
-    _key_group: String,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+pub fn get_keys(database: &data::Database, _key_group: String) -> Result<Vec<Value>, RedisError> {
     // TODO: Handle empty key group
     let keys = database.keys()?;
-    let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
-    let response = encoding::encode_string_array(keys.as_slice())
-        .as_bytes()
-        .to_vec();
+    let keys = keys.iter().map(|k| Value::from(k.as_ref())).collect();
+
+    Ok(vec![Value::Array(keys)])
+}
+
+pub fn get_type(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let data_type = database.get_type(&key).unwrap_or("none");
 
-    let responses = vec![response];
-    Ok(responses)
+    Ok(vec![Value::from(data_type)])
 }
 
-pub fn get_type(database: &data::Database, key: String) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let value = database.get_type(&key);
-    let response = match value {
-        Some(data_type) => data_type,
-        None => encoding::bulk_string("none"),
+/// HELP for a container command: how to call it, then the usage of each of its
+/// subcommands with their descriptions indented below.
+pub fn help(name: &str) -> Result<Vec<Value>, RedisError> {
+    let spec = request::CommandSpec::lookup(name)
+        .ok_or_else(|| RedisError::Custom(format!("ERR unknown command '{}'", name)))?;
+    let help = request::SubcommandHelp {
+        usage: "HELP",
+        description: &["Print this help."],
+    };
+
+    let mut lines = vec![format!(
+        "{} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        name.to_ascii_uppercase()
+    )];
+    for subcommand in spec.subcommands.iter().chain([&help]) {
+        lines.push(subcommand.usage.to_string());
+        lines.extend(
+            subcommand
+                .description
+                .iter()
+                .map(|line| format!("    {}", line)),
+        );
     }
-    .as_bytes()
-    .to_vec();
 
-    let responses = vec![response];
-    Ok(responses)
+    Ok(vec![Value::Array(
+        lines.into_iter().map(Value::SimpleString).collect(),
+    )])
+}
+
+pub fn inspect_object(
+    database: &data::Database,
+    command: ObjectCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let response = match command {
+        ObjectCommand::Chunks(key) => match database.list_chunk_sizes(&key)? {
+            Some(sizes) => Value::Array(
+                sizes
+                    .into_iter()
+                    .map(|size| Value::Integer(size as i64))
+                    .collect(),
+            ),
+            None => Value::Null,
+        },
+        ObjectCommand::Encoding(key) => Value::from(database.get_encoding(&key)),
+        // Values are never shared between keys
+        ObjectCommand::RefCount(key) => match database.count_existing(&[key]) {
+            0 => Value::Null,
+            _ => Value::Integer(1),
+        },
+        ObjectCommand::IdleTime(key) => match database.last_accessed(&key) {
+            Some(accessed_at) => {
+                let idle = database.now().saturating_sub(accessed_at) / 1000;
+                Value::Integer(idle as i64)
+            }
+            None => Value::Null,
+        },
+        ObjectCommand::Freq(key) => match database.access_frequency(&key) {
+            Some(frequency) => Value::Integer(frequency as i64),
+            None => Value::Null,
+        },
+    };
+
+    Ok(vec![response])
 }
 
 pub fn add_stream(
     database: &data::Database,
     command: XAddCommand,
-    sender: Sender<transmission::Transmission>,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.add_stream(command, sender) {
-        Err(e) => encoding::error_string(&e.to_string()),
-        Ok(stream_id) => encoding::bulk_string(&stream_id),
-    }
-    .as_bytes()
-    .to_vec();
-
-    let responses = vec![response];
+) -> Result<Vec<Value>, RedisError> {
+    let stream_id = database.add_stream(command)?;
 
-    Ok(responses)
+    Ok(vec![Value::from(stream_id)])
 }
 
 pub fn get_stream_range(
     database: &data::Database,
     command: XRangeCommand,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.read_from_stream(command.key, command.start, command.end) {
-        Err(e) => encoding::error_string(&e.to_string()),
-        Ok(v) => v,
-    }
-    .as_bytes()
-    .to_vec();
+) -> Result<Vec<Value>, RedisError> {
+    let response = database.read_from_stream(command.key, command.start, command.end)?;
 
-    let responses = vec![response];
-    Ok(responses)
+    Ok(vec![response])
 }
 
 pub async fn read_streams(
     database: &data::Database,
     command: XReadCommand,
-    receiver: Receiver<transmission::Transmission>,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database
-        .read_from_streams(command.block, command.streams, receiver)
-        .await
-    {
-        Err(e) => encoding::error_string(&e.to_string()),
-        Ok(v) => v,
-    }
-    .as_bytes()
-    .to_vec();
+) -> Result<Vec<Value>, RedisError> {
+    let response = database
+        .read_from_streams(command.block, command.streams)
+        .await?;
+
+    Ok(vec![response])
+}
+
+pub fn append_value(
+    database: &data::Database,
+    key: String,
+    value: String,
+) -> Result<Vec<Value>, RedisError> {
+    let len = database.append(&key, &value)?;
+
+    Ok(vec![Value::Integer(len as i64)])
+}
+
+pub fn set_range(
+    database: &data::Database,
+    key: String,
+    offset: usize,
+    value: String,
+) -> Result<Vec<Value>, RedisError> {
+    let len = database.set_range(&key, offset, &value)?;
 
-    let responses = vec![response];
-    Ok(responses)
+    Ok(vec![Value::Integer(len as i64)])
 }
 
 pub fn increment_value_by_int(
     database: &data::Database,
     key: String,
     adjustment: i64,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.adjust_value_by_int(&key, adjustment) {
-        Ok(value) => value,
-        Err(e) => encoding::error_string(&e.to_string()),
-    }
-    .as_bytes()
-    .to_vec();
+) -> Result<Vec<Value>, RedisError> {
+    let response = match database.adjust_value_by_int(&key, adjustment)? {
+        StringValue::Int(value) => Value::Integer(value),
+        StringValue::Raw(value) => Value::from(value),
+    };
+
+    Ok(vec![response])
+}
+
+pub fn push(
+    database: &data::Database,
+    key: String,
+    values: Vec<String>,
+    end: ListEnd,
+) -> Result<Vec<Value>, RedisError> {
+    let len = database.push(&key, values, end)?;
+
+    Ok(vec![Value::Integer(len as i64)])
+}
+
+/// Without a count this replies with the popped value, and with one with an array
+/// of them, like redis.
+pub fn pop(
+    database: &data::Database,
+    key: String,
+    count: Option<usize>,
+    end: ListEnd,
+) -> Result<Vec<Value>, RedisError> {
+    let popped = database.pop(&key, count.unwrap_or(1), end)?;
+
+    let response = match (popped, count) {
+        (None, Some(_)) => Value::NullArray,
+        (popped, Some(_)) => Value::from(popped),
+        (popped, None) => Value::from(popped.and_then(|popped| popped.into_iter().next())),
+    };
+
+    Ok(vec![response])
+}
+
+/// Pops straight away, since a client's blocking pop has already waited for a list
+/// by the time it's applied. Replies with the key and the value, or a null array if
+/// none of the keys held a list.
+pub fn blocking_pop(
+    database: &data::Database,
+    keys: Vec<String>,
+    end: ListEnd,
+) -> Result<Vec<Value>, RedisError> {
+    let response = match database.pop_first(&keys, 1, end)? {
+        Some((key, popped)) => Value::from([vec![key], popped].concat()),
+        None => Value::NullArray,
+    };
+
+    Ok(vec![response])
+}
+
+/// Replies with the value moved, or null if there was nothing to move. Like a
+/// blocking pop, BLMOVE has already waited for its source when it's applied.
+pub fn move_list_value(
+    database: &data::Database,
+    command: LMoveCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let value = database.move_list_value(
+        &command.source,
+        &command.destination,
+        command.from,
+        command.to,
+    )?;
+
+    Ok(vec![Value::from(value)])
+}
+
+/// Replies with the key popped from and the values, or a null array if none of the
+/// keys held a list. Like BLPOP, BLMPOP has already waited for a list when it's applied.
+pub fn pop_multiple(
+    database: &data::Database,
+    command: LMPopCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let response = match database.pop_first(&command.keys, command.count, command.end)? {
+        Some((key, popped)) => Value::Array(vec![Value::from(key), Value::from(popped)]),
+        None => Value::NullArray,
+    };
+
+    Ok(vec![response])
+}
+
+pub fn list_len(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let len = database.list_len(&key)?;
+
+    Ok(vec![Value::Integer(len as i64)])
+}
+
+pub fn list_range(
+    database: &data::Database,
+    key: String,
+    start: i64,
+    end: i64,
+) -> Result<Vec<Value>, RedisError> {
+    let values = database.list_range(&key, start, end)?;
+
+    Ok(vec![Value::from(values)])
+}
+
+/// Replies with the new length, -1 if the pivot isn't in the list or 0 if there's
+/// no list.
+pub fn insert_into_list(
+    database: &data::Database,
+    key: String,
+    position: ListPosition,
+    pivot: String,
+    value: String,
+) -> Result<Vec<Value>, RedisError> {
+    let len = database
+        .insert_into_list(&key, position, &pivot, value)?
+        .map_or(-1, |len| len as i64);
+
+    Ok(vec![Value::Integer(len)])
+}
+
+pub fn set_list_index(
+    database: &data::Database,
+    key: String,
+    index: i64,
+    value: String,
+) -> Result<Vec<Value>, RedisError> {
+    database.set_list_index(&key, index, value)?;
+
+    Ok(vec![Value::ok()])
+}
+
+pub fn remove_from_list(
+    database: &data::Database,
+    key: String,
+    count: i64,
+    value: String,
+) -> Result<Vec<Value>, RedisError> {
+    let removed = database.remove_from_list(&key, count, &value)?;
+
+    Ok(vec![Value::Integer(removed as i64)])
+}
+
+pub fn trim_list(
+    database: &data::Database,
+    key: String,
+    start: i64,
+    end: i64,
+) -> Result<Vec<Value>, RedisError> {
+    database.trim_list(&key, start, end)?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// Replies with how many of the fields are new.
+pub fn set_hash_fields(
+    database: &data::Database,
+    key: String,
+    pairs: Vec<(String, String)>,
+) -> Result<Vec<Value>, RedisError> {
+    let added = database.set_hash_fields(&key, pairs)?;
 
-    let responses = vec![response];
-    Ok(responses)
+    Ok(vec![Value::Integer(added as i64)])
+}
+
+pub fn get_hash_field(
+    database: &data::Database,
+    key: String,
+    field: String,
+) -> Result<Vec<Value>, RedisError> {
+    let value = database.read_hash(&key, |hash| hash.get(&field).cloned())?;
+
+    Ok(vec![Value::from(value.flatten())])
+}
+
+/// Replies with how many of the fields were removed.
+pub fn delete_hash_fields(
+    database: &data::Database,
+    key: String,
+    fields: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let removed = database.delete_hash_fields(&key, &fields)?;
+
+    Ok(vec![Value::Integer(removed as i64)])
+}
+
+pub fn get_all_hash_fields(
+    database: &data::Database,
+    key: String,
+) -> Result<Vec<Value>, RedisError> {
+    let value = database
+        .read_hash(&key, |hash| encoding::field_values_value(hash.iter()))?
+        .unwrap_or(Value::Array(vec![]));
+
+    Ok(vec![value])
+}
+
+pub fn hash_field_exists(
+    database: &data::Database,
+    key: String,
+    field: String,
+) -> Result<Vec<Value>, RedisError> {
+    let exists = database
+        .read_hash(&key, |hash| hash.get(&field).is_some())?
+        .unwrap_or(false);
+
+    Ok(vec![Value::Integer(exists as i64)])
+}
+
+pub fn hash_len(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let len = database.read_hash(&key, |hash| hash.len())?.unwrap_or(0);
+
+    Ok(vec![Value::Integer(len as i64)])
+}
+
+pub fn hash_keys(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let fields = database
+        .read_hash(&key, |hash| {
+            hash.iter()
+                .map(|(field, _)| field.clone())
+                .collect::<Vec<_>>()
+        })?
+        .unwrap_or_default();
+
+    Ok(vec![Value::from(fields)])
+}
+
+pub fn hash_values(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let values = database
+        .read_hash(&key, |hash| {
+            hash.iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        })?
+        .unwrap_or_default();
+
+    Ok(vec![Value::from(values)])
+}
+
+/// Replies with the value of each field, null for those the hash doesn't have.
+pub fn get_hash_fields(
+    database: &data::Database,
+    key: String,
+    fields: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let values = database
+        .read_hash(&key, |hash| {
+            fields
+                .iter()
+                .map(|field| hash.get(field).cloned())
+                .collect::<Vec<_>>()
+        })?
+        .unwrap_or_else(|| vec![None; fields.len()]);
+
+    Ok(vec![Value::from(values)])
 }
 
 pub fn increment_value_by_float(
     database: &data::Database,
     key: String,
     adjustment: f64,
-) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-    let response = match database.adjust_value_by_float(&key, adjustment) {
-        Ok(value) => value,
-        Err(e) => encoding::error_string(&e.to_string()),
+) -> Result<Vec<Value>, RedisError> {
+    let value = database.adjust_value_by_float(&key, adjustment)?;
+
+    Ok(vec![Value::from(value)])
+}
+
+pub async fn save_database(
+    database: &data::Database,
+    server: &server::RedisServer,
+) -> Result<Vec<Value>, RedisError> {
+    let config = server.read().await.config.clone();
+    database.save(&config.rdb_path(), config.rdb_compression)?;
+
+    Ok(vec![Value::ok()])
+}
+
+pub fn last_save(database: &data::Database) -> Result<Vec<Value>, RedisError> {
+    Ok(vec![Value::Integer(database.last_save() as i64)])
+}
+
+/// EXPORT: the whole dataset as JSON, see `export`.
+pub fn export_dataset(database: &data::Database) -> Result<Vec<Value>, RedisError> {
+    let export = export::export(database)?;
+
+    Ok(vec![Value::from(export.to_string())])
+}
+
+/// HOTKEYS: the keys clients have accessed most lately, each with its access
+/// count, which halves every minute the key isn't touched.
+pub fn hot_keys(database: &data::Database, count: usize) -> Result<Vec<Value>, RedisError> {
+    let keys = database
+        .hot_keys()
+        .top(count)
+        .into_iter()
+        .map(|(key, count)| {
+            Value::Array(vec![Value::from(key), Value::Integer(count.round() as i64)])
+        })
+        .collect();
+
+    Ok(vec![Value::Array(keys)])
+}
+
+/// IMPORT: creates the keys in what EXPORT returned and replies with how many there were.
+pub fn import_dataset(
+    database: &data::Database,
+    export: serde_json::Value,
+    replace: bool,
+) -> Result<Vec<Value>, RedisError> {
+    let imported = export::import(database, &export, replace)?;
+
+    Ok(vec![Value::Integer(imported as i64)])
+}
+
+pub async fn background_save(
+    database: &data::Database,
+    server: &server::RedisServer,
+) -> Result<Vec<Value>, RedisError> {
+    let config = server.read().await.config.clone();
+    database.background_save(config.rdb_path(), config.rdb_compression)?;
+
+    Ok(vec![Value::simple("Background saving started")])
+}
+
+pub async fn shutdown(
+    database: &data::Database,
+    server: &server::RedisServer,
+    save: request::ShutdownSave,
+) -> Result<Vec<Value>, RedisError> {
+    if let Err(e) = server.prepare_shutdown(database, save).await {
+        eprintln!("Error trying to shut down: {}", e);
+        return Err(RedisError::custom(
+            "ERR Errors trying to SHUTDOWN. Check logs.",
+        ));
+    }
+
+    server.request_shutdown(database).await;
+    Ok(vec![])
+}
+
+/// Authenticates the connection against `requirepass`. The default user is the only
+/// one there is, so it's the only one a client can log in as.
+pub async fn authenticate(
+    server: &server::RedisServer,
+    session: &mut Session,
+    user: Option<String>,
+    password: String,
+) -> Result<Vec<Value>, RedisError> {
+    let Some(requirepass) = server.read().await.config.requirepass.clone() else {
+        return Err(RedisError::custom(
+            "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+        ));
+    };
+
+    let user = user.unwrap_or_else(|| "default".to_string());
+    if user != "default" || password != requirepass {
+        return Err(RedisError::custom(
+            "WRONGPASS invalid username-password pair or user is disabled.",
+        ));
+    }
+
+    session.user = user;
+    session.authenticated = true;
+    Ok(vec![Value::ok()])
+}
+
+pub fn swap_databases(
+    database: &data::Database,
+    first: usize,
+    second: usize,
+) -> Result<Vec<Value>, RedisError> {
+    database.swap(first, second);
+
+    Ok(vec![Value::ok()])
+}
+
+pub fn move_key(
+    database: &data::Database,
+    key: String,
+    index: usize,
+) -> Result<Vec<Value>, RedisError> {
+    if index == database.index() {
+        return Err(RedisError::custom(
+            "ERR source and destination objects are the same",
+        ));
     }
-    .as_bytes()
-    .to_vec();
+    let moved = database.move_key(&key, index);
+
+    Ok(vec![Value::Integer(moved as i64)])
+}
+
+/// Switches the connection to one of the numbered databases. Like redis, a
+/// cluster only has the first one.
+pub async fn select_database(
+    server: &server::RedisServer,
+    session: &mut Session,
+    index: usize,
+) -> Result<Vec<Value>, RedisError> {
+    let server = server.read().await;
+    if server.cluster.is_some() && index != 0 {
+        return Err(RedisError::custom(
+            "ERR SELECT is not allowed in cluster mode",
+        ));
+    }
+    if index >= server.config.databases {
+        return Err(RedisError::custom("ERR DB index is out of range"));
+    }
+
+    session.db = index;
+    Ok(vec![Value::ok()])
+}
+
+/// Switches the connection to a namespace, made the first time it's selected.
+pub fn select_namespace(session: &mut Session, name: String) -> Result<Vec<Value>, RedisError> {
+    session.namespace = match name == namespace::DEFAULT_NAMESPACE {
+        true => None,
+        false => Some(name),
+    };
+
+    Ok(vec![Value::ok()])
+}
+
+pub async fn rewrite_append_only_file(
+    database: &data::Database,
+    server: &server::RedisServer,
+) -> Result<Vec<Value>, RedisError> {
+    let (aof, compress) = {
+        let server = server.read().await;
+        (server.aof.clone(), server.config.rdb_compression)
+    };
+
+    aof.rewrite(database, compress).await?;
+
+    Ok(vec![Value::simple(
+        "Background append only file rewriting started",
+    )])
+}
+
+pub async fn cluster(
+    database: &data::Database,
+    server: &server::RedisServer,
+    command: request::ClusterCommand,
+) -> Result<Vec<Value>, RedisError> {
+    if let request::ClusterCommand::Meet(host, port, bus_port) = command {
+        let bus_port = bus_port
+            .or_else(|| port.checked_add(10000))
+            .ok_or_else(|| {
+                RedisError::Custom(format!("ERR Invalid base port specified: {}", port))
+            })?;
+        bus::meet(server, &server::Address::new(host.clone(), bus_port))
+            .await
+            .map_err(|e| {
+                RedisError::Custom(format!("ERR Unable to meet {}:{}: {}", host, port, e))
+            })?;
+        return Ok(vec![Value::ok()]);
+    }
+
+    let my_offset = server.read().await.replication.offset;
+    // Only the commands about the keys in a slot need to look through them
+    let keys_in_slot = match &command {
+        request::ClusterCommand::SetSlot(slot, _)
+        | request::ClusterCommand::CountKeysInSlot(slot)
+        | request::ClusterCommand::GetKeysInSlot(slot, _) => keys_in_slot(database, *slot)?,
+        _ => vec![],
+    };
+
+    let reply = server
+        .with_cluster(|cluster| {
+            let reply = match command {
+                request::ClusterCommand::Info => Value::from(cluster.info()),
+                request::ClusterCommand::MyId => Value::from(cluster.myself().id.as_str()),
+                request::ClusterCommand::Slots => cluster.slots_value(),
+                request::ClusterCommand::Shards => cluster.shards_value(my_offset),
+                request::ClusterCommand::KeySlot(key) => {
+                    Value::Integer(key_hash_slot(key.as_bytes()) as i64)
+                }
+                request::ClusterCommand::AddSlots(slots) => {
+                    cluster.add_slots(&slots)?;
+                    Value::ok()
+                }
+                request::ClusterCommand::DelSlots(slots) => {
+                    cluster.del_slots(&slots)?;
+                    Value::ok()
+                }
+                request::ClusterCommand::Nodes => Value::from(cluster.nodes_description()),
+                request::ClusterCommand::SetSlot(slot, action) => {
+                    cluster.set_slot(slot, &action, keys_in_slot.len())?;
+                    Value::ok()
+                }
+                request::ClusterCommand::CountKeysInSlot(_) => {
+                    Value::Integer(keys_in_slot.len() as i64)
+                }
+                request::ClusterCommand::GetKeysInSlot(_, count) => {
+                    Value::bulk_array(&keys_in_slot[..count.min(keys_in_slot.len())])
+                }
+                request::ClusterCommand::Meet(..) => unreachable!("MEET is handled above"),
+            };
+            Ok(reply)
+        })
+        .await?;
+
+    Ok(vec![reply])
+}
+
+fn keys_in_slot(database: &data::Database, slot: usize) -> Result<Vec<Arc<str>>, RedisError> {
+    let mut keys = database.keys()?;
+    keys.retain(|key| key_hash_slot(key.as_bytes()) == slot);
+    Ok(keys)
+}
+
+/// ASKING: lets the next command use a slot this node is still importing.
+pub async fn asking(
+    server: &server::RedisServer,
+    session: &mut Session,
+) -> Result<Vec<Value>, RedisError> {
+    server.with_cluster(|_| Ok(())).await?;
+    session.asking = true;
+
+    Ok(vec![Value::ok()])
+}
+
+/// The key's value serialized by DUMP, hex encoded so it can be given back to
+/// RESTORE as it is.
+pub fn dump(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let payload = database.dump(&key).map(hex::encode);
+
+    Ok(vec![Value::from(payload)])
+}
+
+/// RESTORE and RESTORE-ASKING.
+pub fn restore(
+    database: &data::Database,
+    command: request::RestoreCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let payload = hex::decode(&command.payload)
+        .map_err(|_| RedisError::custom("ERR DUMP payload version or checksum are wrong"))?;
+    let expires_at = match (command.ttl, command.absttl) {
+        (0, _) => None,
+        (at, true) => Some(at as u128),
+        (ttl, false) => Some(database.now() + ttl as u128),
+    };
+
+    database.restore(&command.key, &payload, expires_at, command.replace)?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// MIGRATE: recreates the keys on another node with RESTORE-ASKING, then
+/// deletes them here unless they're only being copied. What gets persisted and
+/// replicated is the DEL, since running MIGRATE again would go to the other node.
+pub async fn migrate(
+    database: &data::Database,
+    server: &server::RedisServer,
+    command: request::MigrateCommand,
+) -> Result<Vec<Value>, RedisError> {
+    if server.is_read_only().await {
+        return Err(RedisError::Readonly);
+    }
+    if command.db != 0 {
+        return Err(RedisError::custom("ERR DB index is out of range"));
+    }
+
+    let mut restores = Pipeline::new();
+    let mut keys = vec![];
+    for key in command.keys.iter() {
+        let Some(payload) = database.dump(key) else {
+            continue;
+        };
+        // A key about to expire still needs a TTL, 0 would make it live forever
+        let ttl = database
+            .ttl(key)
+            .map_or(0, |ttl| ttl.as_millis().max(1))
+            .to_string();
+        let payload = hex::encode(payload);
+
+        let mut args = vec!["RESTORE-ASKING", key, &ttl, &payload];
+        if command.replace {
+            args.push("REPLACE");
+        }
+        restores.add(&args);
+        keys.push(key.clone());
+    }
+    if keys.is_empty() {
+        return Ok(vec![Value::simple("NOKEY")]);
+    }
+
+    // Like redis, a timeout of 0 means a second
+    let timeout = match command.timeout {
+        0 => Duration::from_secs(1),
+        timeout => Duration::from_millis(timeout),
+    };
+    let address = format!("{}:{}", command.host, command.port);
+    let replies = tokio::time::timeout(timeout, async {
+        let mut client = Client::connect(&address).await?;
+        if let Some((user, password)) = &command.auth {
+            let args: Vec<&str> = ["AUTH"]
+                .into_iter()
+                .chain(user.as_deref())
+                .chain([password.as_str()])
+                .collect();
+            if let Value::Error(e) = client.command(&args).await? {
+                anyhow::bail!(e);
+            }
+        }
+        client.pipeline(&restores).await
+    })
+    .await;
+    let replies = match replies {
+        Ok(Ok(replies)) => replies,
+        Ok(Err(e)) => {
+            return Err(RedisError::Custom(format!(
+                "IOERR error or timeout reading to target instance: {}",
+                e
+            )))
+        }
+        Err(_) => {
+            return Err(RedisError::custom(
+                "IOERR error or timeout reading to target instance",
+            ))
+        }
+    };
+
+    let mut moved = vec!["DEL"];
+    let mut error = None;
+    for (key, reply) in keys.iter().zip(replies) {
+        match reply {
+            Value::Error(e) => {
+                error.get_or_insert(e);
+            }
+            _ => moved.push(key.as_str()),
+        }
+    }
+
+    if !command.copy && moved.len() > 1 {
+        database.remove_multiple(moved[1..].iter().map(|key| key.to_string()).collect());
+        server
+            .propagate_write(
+                database.index(),
+                encoding::encode_string_array(&moved).as_bytes(),
+            )
+            .await?;
+    }
+
+    match error {
+        Some(e) => Err(RedisError::Custom(format!(
+            "ERR Target instance replied with error: {}",
+            e
+        ))),
+        None => Ok(vec![Value::ok()]),
+    }
+}
+
+pub fn json_set(
+    database: &data::Database,
+    command: request::JsonSetCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let request::JsonSetCommand {
+        key,
+        path,
+        value,
+        overwrite,
+    } = command;
+
+    let set =
+        database.update_module(
+            &key,
+            |document: &mut Option<serde_json::Value>| match document {
+                Some(document) => json::set(document, &path, value, &overwrite),
+                None if !path.is_root() => Err(RedisError::custom(
+                    "ERR new objects must be created at the root",
+                )),
+                None if matches!(overwrite, request::SetOverride::OnlyOverwrite) => Ok(false),
+                None => {
+                    *document = Some(value);
+                    Ok(true)
+                }
+            },
+        )?;
+
+    match set {
+        true => Ok(vec![Value::ok()]),
+        false => Ok(vec![Value::Null]),
+    }
+}
+
+pub fn json_get(
+    database: &data::Database,
+    key: String,
+    paths: Vec<JsonPath>,
+) -> Result<Vec<Value>, RedisError> {
+    let reply = database
+        .read_module(&key, |document| json::get_paths(document, &paths))?
+        .transpose()?;
+
+    Ok(vec![Value::from(reply.map(|reply| reply.to_string()))])
+}
+
+/// JSON.DEL: removes the values at the path, or the whole key for the root.
+pub fn json_delete(
+    database: &data::Database,
+    key: String,
+    path: JsonPath,
+) -> Result<Vec<Value>, RedisError> {
+    let deleted = database.update_module(&key, |document: &mut Option<serde_json::Value>| {
+        let Some(existing) = document else {
+            return Ok(0);
+        };
+        match path.is_root() {
+            true => {
+                *document = None;
+                Ok(1)
+            }
+            false => Ok(json::delete(existing, &path)),
+        }
+    })?;
+
+    Ok(vec![Value::Integer(deleted as i64)])
+}
+
+pub fn json_increment(
+    database: &data::Database,
+    key: String,
+    path: JsonPath,
+    increment: serde_json::Number,
+) -> Result<Vec<Value>, RedisError> {
+    let reply =
+        database.update_module(
+            &key,
+            |document: &mut Option<serde_json::Value>| match document {
+                Some(document) => json::increment(document, &path, &increment),
+                None => Err(missing_json_key()),
+            },
+        )?;
+
+    Ok(vec![Value::from(reply.to_string())])
+}
+
+pub fn json_append(
+    database: &data::Database,
+    key: String,
+    path: JsonPath,
+    values: Vec<serde_json::Value>,
+) -> Result<Vec<Value>, RedisError> {
+    let lengths =
+        database.update_module(
+            &key,
+            |document: &mut Option<serde_json::Value>| match document {
+                Some(document) => json::append(document, &path, &values),
+                None => Err(missing_json_key()),
+            },
+        )?;
+    let mut lengths = lengths
+        .into_iter()
+        .map(|length| Value::from(length.map(|length| length as i64)));
+
+    match path.is_legacy() {
+        true => Ok(vec![lengths.next().unwrap_or(Value::Null)]),
+        false => Ok(vec![Value::Array(lengths.collect())]),
+    }
+}
+
+fn missing_json_key() -> RedisError {
+    RedisError::custom("ERR could not perform this operation on a key that doesn't exist")
+}
+
+pub fn bf_reserve(
+    database: &data::Database,
+    command: request::BfReserveCommand,
+) -> Result<Vec<Value>, RedisError> {
+    database.update_module(&command.key, |filter: &mut Option<ScalableBloomFilter>| {
+        if filter.is_some() {
+            return Err(RedisError::custom("ERR item exists"));
+        }
+        *filter = Some(ScalableBloomFilter::new(
+            command.capacity,
+            command.error_rate,
+            command.expansion,
+        )?);
+        Ok(())
+    })?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// BF.ADD, which creates a filter with the default settings if there isn't one.
+/// Replies 1 if the item was added, 0 if it may have been there already.
+pub fn bf_add(
+    database: &data::Database,
+    key: String,
+    item: String,
+) -> Result<Vec<Value>, RedisError> {
+    let added = database.update_module(&key, |filter: &mut Option<ScalableBloomFilter>| {
+        filter
+            .get_or_insert_with(ScalableBloomFilter::default)
+            .add(&item)
+    })?;
+
+    Ok(vec![Value::Integer(added as i64)])
+}
+
+/// BF.MADD: BF.ADD for every item, where an item that doesn't fit in a full
+/// filter gets an error without failing the others.
+pub fn bf_madd(
+    database: &data::Database,
+    key: String,
+    items: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let replies = database.update_module(&key, |filter: &mut Option<ScalableBloomFilter>| {
+        let filter = filter.get_or_insert_with(ScalableBloomFilter::default);
+        Ok(items
+            .iter()
+            .map(|item| match filter.add(item) {
+                Ok(added) => Value::Integer(added as i64),
+                Err(e) => e.to_value(),
+            })
+            .collect())
+    })?;
+
+    Ok(vec![Value::Array(replies)])
+}
+
+pub fn bf_exists(
+    database: &data::Database,
+    key: String,
+    item: String,
+) -> Result<Vec<Value>, RedisError> {
+    let exists = database
+        .read_module(&key, |filter: &ScalableBloomFilter| filter.contains(&item))?
+        .unwrap_or(false);
+
+    Ok(vec![Value::Integer(exists as i64)])
+}
+
+pub fn cf_reserve(
+    database: &data::Database,
+    command: request::CfReserveCommand,
+) -> Result<Vec<Value>, RedisError> {
+    database.update_module(&command.key, |filter: &mut Option<ScalableCuckooFilter>| {
+        if filter.is_some() {
+            return Err(RedisError::custom("ERR item exists"));
+        }
+        *filter = Some(ScalableCuckooFilter::new(
+            command.capacity,
+            command.bucket_size,
+            command.max_iterations,
+            command.expansion,
+        )?);
+        Ok(())
+    })?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// CF.ADD and CF.ADDNX, which create a filter with the default settings if
+/// there isn't one. CF.ADDNX replies 0 if the item seems to be there already.
+pub fn cf_add(
+    database: &data::Database,
+    key: String,
+    item: String,
+    only_if_missing: bool,
+) -> Result<Vec<Value>, RedisError> {
+    let added = database.update_module(&key, |filter: &mut Option<ScalableCuckooFilter>| {
+        let filter = filter.get_or_insert_with(ScalableCuckooFilter::default);
+        match only_if_missing {
+            true => filter.add_if_missing(&item),
+            false => filter.add(&item).map(|_| true),
+        }
+    })?;
+
+    Ok(vec![Value::Integer(added as i64)])
+}
+
+pub fn cf_delete(
+    database: &data::Database,
+    key: String,
+    item: String,
+) -> Result<Vec<Value>, RedisError> {
+    let deleted =
+        database.update_module(
+            &key,
+            |filter: &mut Option<ScalableCuckooFilter>| match filter {
+                Some(filter) => Ok(filter.delete(&item)),
+                None => Err(RedisError::custom("ERR Not found")),
+            },
+        )?;
+
+    Ok(vec![Value::Integer(deleted as i64)])
+}
+
+/// CF.EXISTS and CF.COUNT, the first only ever replying 0 or 1.
+pub fn cf_count(
+    database: &data::Database,
+    key: String,
+    item: String,
+    only_exists: bool,
+) -> Result<Vec<Value>, RedisError> {
+    let count = database
+        .read_module(&key, |filter: &ScalableCuckooFilter| match only_exists {
+            true => filter.contains(&item) as u64,
+            false => filter.count(&item),
+        })?
+        .unwrap_or(0);
+
+    Ok(vec![Value::Integer(count as i64)])
+}
+
+/// CMS.INITBYDIM and CMS.INITBYPROB, which differ only in how the client
+/// gives the dimensions.
+pub fn cms_init(
+    database: &data::Database,
+    key: String,
+    width: u64,
+    depth: u64,
+) -> Result<Vec<Value>, RedisError> {
+    database.update_module(&key, |sketch: &mut Option<CountMinSketch>| {
+        if sketch.is_some() {
+            return Err(RedisError::custom("ERR CMS: key already exists"));
+        }
+        *sketch = Some(CountMinSketch::new(width, depth)?);
+        Ok(())
+    })?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// CMS.INCRBY, which replies with every item's count after its increment. The
+/// increments are applied all or nothing.
+pub fn cms_increment(
+    database: &data::Database,
+    key: String,
+    increments: Vec<(String, u64)>,
+) -> Result<Vec<Value>, RedisError> {
+    let counts = database.update_module(&key, |sketch: &mut Option<CountMinSketch>| {
+        let sketch = sketch.as_mut().ok_or_else(missing_cms_key)?;
+        let mut incremented = sketch.clone();
+        let counts = increments
+            .iter()
+            .map(|(item, by)| incremented.increment(item, *by))
+            .collect::<Result<Vec<_>, _>>()?;
+        *sketch = incremented;
+        Ok(counts)
+    })?;
+
+    let counts = counts
+        .into_iter()
+        .map(|count| Value::Integer(count as i64))
+        .collect();
+    Ok(vec![Value::Array(counts)])
+}
+
+pub fn cms_query(
+    database: &data::Database,
+    key: String,
+    items: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let counts = database
+        .read_module(&key, |sketch: &CountMinSketch| {
+            items
+                .iter()
+                .map(|item| Value::Integer(sketch.query(item) as i64))
+                .collect()
+        })?
+        .ok_or_else(missing_cms_key)?;
+
+    Ok(vec![Value::Array(counts)])
+}
+
+/// CMS.MERGE, which overwrites the destination, a sketch that must already
+/// exist, with the weighted sum of the sources.
+pub fn cms_merge(
+    database: &data::Database,
+    command: request::CmsMergeCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let sources = command
+        .sources
+        .into_iter()
+        .map(|(key, weight)| {
+            let source = database
+                .read_module(&key, |sketch: &CountMinSketch| sketch.clone())?
+                .ok_or_else(missing_cms_key)?;
+            Ok((source, weight))
+        })
+        .collect::<Result<Vec<_>, RedisError>>()?;
+
+    database.update_module(
+        &command.destination,
+        |sketch: &mut Option<CountMinSketch>| {
+            sketch.as_mut().ok_or_else(missing_cms_key)?.merge(&sources)
+        },
+    )?;
+
+    Ok(vec![Value::ok()])
+}
+
+fn missing_cms_key() -> RedisError {
+    RedisError::custom("ERR CMS: key does not exist")
+}
+
+pub fn topk_reserve(
+    database: &data::Database,
+    command: request::TopkReserveCommand,
+) -> Result<Vec<Value>, RedisError> {
+    database.update_module(&command.key, |topk: &mut Option<TopK>| {
+        if topk.is_some() {
+            return Err(RedisError::custom("ERR TopK: key already exists"));
+        }
+        *topk = Some(TopK::new(
+            command.k,
+            command.width,
+            command.depth,
+            command.decay,
+        )?);
+        Ok(())
+    })?;
+
+    Ok(vec![Value::ok()])
+}
+
+/// TOPK.ADD, which replies with the item each one pushed out of the top k, or
+/// nil where none was.
+pub fn topk_add(
+    database: &data::Database,
+    key: String,
+    items: Vec<String>,
+) -> Result<Vec<Value>, RedisError> {
+    let expelled = database.update_module(&key, |topk: &mut Option<TopK>| {
+        let topk = topk.as_mut().ok_or_else(missing_topk_key)?;
+        Ok(items
+            .iter()
+            .map(|item| match topk.add(item) {
+                Some(expelled) => Value::from(expelled),
+                None => Value::Null,
+            })
+            .collect())
+    })?;
+
+    Ok(vec![Value::Array(expelled)])
+}
+
+/// TOPK.QUERY and TOPK.COUNT, the first replying whether each item is in the
+/// top k and the second with its estimated count.
+pub fn topk_query(
+    database: &data::Database,
+    key: String,
+    items: Vec<String>,
+    counts: bool,
+) -> Result<Vec<Value>, RedisError> {
+    let replies = database
+        .read_module(&key, |topk: &TopK| {
+            items
+                .iter()
+                .map(|item| match counts {
+                    true => Value::Integer(topk.count(item) as i64),
+                    false => Value::Integer(topk.contains(item) as i64),
+                })
+                .collect()
+        })?
+        .ok_or_else(missing_topk_key)?;
+
+    Ok(vec![Value::Array(replies)])
+}
+
+pub fn topk_list(
+    database: &data::Database,
+    key: String,
+    with_counts: bool,
+) -> Result<Vec<Value>, RedisError> {
+    let items = database
+        .read_module(&key, TopK::list)?
+        .ok_or_else(missing_topk_key)?;
+
+    let reply = items
+        .into_iter()
+        .flat_map(|(item, count)| match with_counts {
+            true => vec![Value::from(item), Value::Integer(count as i64)],
+            false => vec![Value::from(item)],
+        })
+        .collect();
+    Ok(vec![Value::Array(reply)])
+}
+
+fn missing_topk_key() -> RedisError {
+    RedisError::custom("ERR TopK: key does not exist")
+}
+
+/// TS.ADD, which creates the series with the retention and labels it was given
+/// if it doesn't exist.
+pub fn ts_add(
+    database: &data::Database,
+    command: request::TsAddCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let timestamp = match command.timestamp {
+        Some(timestamp) => timestamp,
+        None => current_unix_timestamp()? as u64,
+    };
+
+    database.update_module(&command.key, |series: &mut Option<TimeSeries>| {
+        series
+            .get_or_insert_with(|| TimeSeries::new(command.retention, command.labels))
+            .add(timestamp, command.value)
+    })?;
+
+    Ok(vec![Value::Integer(timestamp as i64)])
+}
+
+/// TS.GET, the newest sample, or an empty array for a series without any.
+pub fn ts_get(database: &data::Database, key: String) -> Result<Vec<Value>, RedisError> {
+    let last = database
+        .read_module(&key, TimeSeries::last)?
+        .ok_or_else(missing_series_key)?;
+
+    let reply = match last {
+        Some(sample) => sample_to_value(sample),
+        None => Value::Array(vec![]),
+    };
+    Ok(vec![reply])
+}
+
+pub fn ts_range(
+    database: &data::Database,
+    command: request::TsRangeCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let samples = database
+        .read_module(&command.key, |series: &TimeSeries| {
+            series.range(command.range, command.aggregation)
+        })?
+        .ok_or_else(missing_series_key)?;
+
+    Ok(vec![samples_to_value(samples)])
+}
+
+/// TS.MRANGE, the range of every series whose labels match the filters, ordered
+/// by key. Each reply is the key, its labels if asked for, and its samples.
+pub fn ts_mrange(
+    database: &data::Database,
+    command: request::TsMRangeCommand,
+) -> Result<Vec<Value>, RedisError> {
+    let mut keys = database.keys()?;
+    keys.sort();
+
+    let mut replies = vec![];
+    for key in keys {
+        // Keys holding something other than a time series are skipped
+        let Ok(Some(Some((labels, samples)))) =
+            database.read_module(&key, |series: &TimeSeries| {
+                series.matches(&command.filters).then(|| {
+                    (
+                        series.labels().to_vec(),
+                        series.range(command.range.clone(), command.aggregation),
+                    )
+                })
+            })
+        else {
+            continue;
+        };
+
+        let labels = match command.with_labels {
+            true => labels
+                .into_iter()
+                .map(|(label, value)| Value::bulk_array(&[label, value]))
+                .collect(),
+            false => vec![],
+        };
+        replies.push(Value::Array(vec![
+            Value::from(key.to_string()),
+            Value::Array(labels),
+            samples_to_value(samples),
+        ]));
+    }
+
+    Ok(vec![Value::Array(replies)])
+}
+
+fn sample_to_value((timestamp, value): (u64, f64)) -> Value {
+    Value::Array(vec![
+        Value::Integer(timestamp as i64),
+        Value::from(value.to_string()),
+    ])
+}
+
+fn samples_to_value(samples: Vec<(u64, f64)>) -> Value {
+    Value::Array(samples.into_iter().map(sample_to_value).collect())
+}
 
-    let responses = vec![response];
-    Ok(responses)
+fn missing_series_key() -> RedisError {
+    RedisError::custom("ERR TSDB: the key does not exist")
 }
@@ -0,0 +1,676 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::cidr::{self, Cidr};
+use crate::namespace::NamespaceLimits;
+use crate::ratelimit::{RateLimitPolicy, RateLimits};
+use crate::request::ConfigKey;
+use crate::systemd::Supervised;
+use crate::utils::FrameLimits;
+
+const DEFAULT_MAX_CLIENTS: usize = 10000;
+const DEFAULT_DB_FILE_NAME: &str = "dump.rdb";
+const DEFAULT_APPEND_FILE_NAME: &str = "appendonly.aof";
+const DEFAULT_APPEND_DIR_NAME: &str = "appendonlydir";
+const DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE: u64 = 100;
+const DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_PROTO_MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+const DEFAULT_PROTO_MAX_MULTIBULK_LEN: u64 = 1024 * 1024;
+const DEFAULT_PROTO_MAX_INLINE_LEN: u64 = 64 * 1024;
+const DEFAULT_REPL_PING_REPLICA_PERIOD: u64 = 10;
+const DEFAULT_REPL_TIMEOUT: u64 = 60;
+const DEFAULT_DATABASES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SaveRule {
+    /// A rule is due once at least `changes` writes happened and more than
+    /// `seconds` have gone by since the last save.
+    pub fn is_due(&self, changes: u64, seconds_since_save: u64) -> bool {
+        changes >= self.changes.max(1) && seconds_since_save > self.seconds
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub dir: Option<String>,
+    pub db_file_name: Option<String>,
+    pub supervised: Supervised,
+    pub config_file: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub logfile: Option<String>,
+    pub save: Vec<SaveRule>,
+    pub max_clients: usize,
+    /// Writes are refused once the dataset takes up more bytes than this, measured
+    /// with `Database::dataset_memory`. 0 for no limit.
+    pub max_memory: u64,
+    /// The password clients have to AUTH with, if any.
+    pub requirepass: Option<String>,
+    /// When not empty, only clients connecting from these blocks are accepted.
+    pub ip_allowlist: Vec<Cidr>,
+    /// Clients connecting from these blocks are turned away, even if they're allowed.
+    pub ip_denylist: Vec<Cidr>,
+    /// How many commands a client may send each second, 0 for no limit.
+    pub client_max_commands_per_second: u64,
+    /// How many bytes of requests a client may send each second, 0 for no limit.
+    pub client_max_bytes_per_second: u64,
+    pub client_rate_limit_policy: RateLimitPolicy,
+    pub namespace_max_keys: u64,
+    pub namespace_max_memory: u64,
+    pub io_threads: usize,
+    pub rdb_compression: bool,
+    pub append_only: bool,
+    pub append_file_name: Option<String>,
+    pub append_dir_name: Option<String>,
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+    pub replica_read_only: bool,
+    /// Whether a replica keeps answering from its possibly outdated dataset while
+    /// its link with the master is down, rather than refusing with MASTERDOWN.
+    pub replica_serve_stale_data: bool,
+    /// Seconds the replication stream can be quiet before the master sends a PING.
+    pub repl_ping_replica_period: u64,
+    /// Seconds either end of a replication link waits to hear from the other
+    /// before giving up on it.
+    pub repl_timeout: u64,
+    pub proto_max_bulk_len: u64,
+    pub proto_max_multibulk_len: u64,
+    pub proto_max_inline_len: u64,
+    pub cluster_enabled: bool,
+    /// The port of the cluster bus, 0 meaning the client port plus 10000.
+    pub cluster_port: u16,
+    /// How many numbered databases connections can SELECT.
+    pub databases: usize,
+}
+
+impl Config {
+    pub fn new(dir: Option<String>, db_file_name: Option<String>) -> Self {
+        Config {
+            dir,
+            db_file_name,
+            supervised: Supervised::No,
+            config_file: None,
+            port: None,
+            logfile: None,
+            save: vec![],
+            max_clients: DEFAULT_MAX_CLIENTS,
+            max_memory: 0,
+            requirepass: None,
+            ip_allowlist: vec![],
+            ip_denylist: vec![],
+            client_max_commands_per_second: 0,
+            client_max_bytes_per_second: 0,
+            client_rate_limit_policy: RateLimitPolicy::Reject,
+            namespace_max_keys: 0,
+            namespace_max_memory: 0,
+            io_threads: 1,
+            rdb_compression: true,
+            append_only: false,
+            append_file_name: None,
+            append_dir_name: None,
+            auto_aof_rewrite_percentage: DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE,
+            auto_aof_rewrite_min_size: DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE,
+            replica_read_only: true,
+            replica_serve_stale_data: true,
+            repl_ping_replica_period: DEFAULT_REPL_PING_REPLICA_PERIOD,
+            repl_timeout: DEFAULT_REPL_TIMEOUT,
+            proto_max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
+            proto_max_multibulk_len: DEFAULT_PROTO_MAX_MULTIBULK_LEN,
+            proto_max_inline_len: DEFAULT_PROTO_MAX_INLINE_LEN,
+            cluster_enabled: false,
+            cluster_port: 0,
+            databases: DEFAULT_DATABASES,
+        }
+    }
+
+    pub fn from_file(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let mut config = Config::new(None, None);
+
+        for (name, value) in read_directives(&path)? {
+            match name.as_str() {
+                "port" => {
+                    let port = value.parse::<u16>().context("Parsing port as u16")?;
+                    config.port = Some(port);
+                }
+                "supervised" => config.supervised = Supervised::parse(&value)?,
+                "io-threads" => config.io_threads = parse_io_threads(&value)?,
+                _ => {
+                    let key = ConfigKey::parse(&name).ok_or_else(|| {
+                        anyhow::anyhow!("Bad directive or wrong number of arguments: {}", name)
+                    })?;
+                    config.set(&key, &value)?;
+                }
+            }
+        }
+
+        config.config_file = Some(path);
+        Ok(config)
+    }
+
+    /// How big a request from a client is allowed to get.
+    pub fn frame_limits(&self) -> FrameLimits {
+        FrameLimits {
+            max_bulk_len: self.proto_max_bulk_len as usize,
+            max_multibulk_len: self.proto_max_multibulk_len as usize,
+            max_inline_len: self.proto_max_inline_len as usize,
+        }
+    }
+
+    pub fn repl_ping_replica_period(&self) -> Duration {
+        Duration::from_secs(self.repl_ping_replica_period)
+    }
+
+    pub fn repl_timeout(&self) -> Duration {
+        Duration::from_secs(self.repl_timeout)
+    }
+
+    /// Where the RDB file is written to. Like redis, we default to `dump.rdb`
+    /// in the working directory.
+    pub fn rdb_path(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or(".");
+        let file_name = self.db_file_name.as_deref().unwrap_or(DEFAULT_DB_FILE_NAME);
+        PathBuf::from(dir).join(file_name)
+    }
+
+    /// Where a single file append only file from before the manifest existed lives.
+    pub fn aof_path(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or(".");
+        PathBuf::from(dir).join(self.append_file_name())
+    }
+
+    /// The directory the append only file's parts and manifest are kept in,
+    /// `appendonlydir` in the data directory by default.
+    pub fn aof_dir(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or(".");
+        let dir_name = self
+            .append_dir_name
+            .as_deref()
+            .unwrap_or(DEFAULT_APPEND_DIR_NAME);
+        PathBuf::from(dir).join(dir_name)
+    }
+
+    /// The prefix every file making up the append only file starts with.
+    pub fn append_file_name(&self) -> &str {
+        self.append_file_name
+            .as_deref()
+            .unwrap_or(DEFAULT_APPEND_FILE_NAME)
+    }
+
+    pub fn get(&self, key: &ConfigKey) -> String {
+        match key {
+            ConfigKey::Dir => self.dir.clone().unwrap_or_default(),
+            ConfigKey::Dbfilename => self.db_file_name.clone().unwrap_or_default(),
+            ConfigKey::Logfile => self.logfile.clone().unwrap_or_default(),
+            ConfigKey::Save => self
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<String>>()
+                .join(" "),
+            ConfigKey::Maxclients => self.max_clients.to_string(),
+            ConfigKey::Maxmemory => self.max_memory.to_string(),
+            ConfigKey::Requirepass => self.requirepass.clone().unwrap_or_default(),
+            ConfigKey::IpAllowlist => join_blocks(&self.ip_allowlist),
+            ConfigKey::IpDenylist => join_blocks(&self.ip_denylist),
+            ConfigKey::ClientMaxCommandsPerSecond => {
+                self.client_max_commands_per_second.to_string()
+            }
+            ConfigKey::ClientMaxBytesPerSecond => self.client_max_bytes_per_second.to_string(),
+            ConfigKey::ClientRateLimitPolicy => self.client_rate_limit_policy.name().to_string(),
+            ConfigKey::NamespaceMaxKeys => self.namespace_max_keys.to_string(),
+            ConfigKey::NamespaceMaxMemory => self.namespace_max_memory.to_string(),
+            ConfigKey::Rdbcompression => yes_or_no(self.rdb_compression),
+            ConfigKey::Appendonly => yes_or_no(self.append_only),
+            ConfigKey::Appendfilename => self.append_file_name().to_string(),
+            ConfigKey::Appenddirname => self
+                .append_dir_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_APPEND_DIR_NAME.to_string()),
+            ConfigKey::AutoAofRewritePercentage => self.auto_aof_rewrite_percentage.to_string(),
+            ConfigKey::AutoAofRewriteMinSize => self.auto_aof_rewrite_min_size.to_string(),
+            ConfigKey::ReplicaReadOnly => yes_or_no(self.replica_read_only),
+            ConfigKey::ReplicaServeStaleData => yes_or_no(self.replica_serve_stale_data),
+            ConfigKey::ReplPingReplicaPeriod => self.repl_ping_replica_period.to_string(),
+            ConfigKey::ReplTimeout => self.repl_timeout.to_string(),
+            ConfigKey::ProtoMaxBulkLen => self.proto_max_bulk_len.to_string(),
+            ConfigKey::ProtoMaxMultibulkLen => self.proto_max_multibulk_len.to_string(),
+            ConfigKey::ProtoMaxInlineLen => self.proto_max_inline_len.to_string(),
+            ConfigKey::ClusterEnabled => yes_or_no(self.cluster_enabled),
+            ConfigKey::ClusterPort => self.cluster_port.to_string(),
+            ConfigKey::Databases => self.databases.to_string(),
+        }
+    }
+
+    /// Validates and applies a single option. This is what CONFIG SET uses, so anything
+    /// that can be reloaded from the config file goes through here as well.
+    pub fn set(&mut self, key: &ConfigKey, value: &str) -> Result<(), anyhow::Error> {
+        match key {
+            ConfigKey::Dir => self.dir = non_empty(value),
+            ConfigKey::Dbfilename => {
+                if value.contains('/') {
+                    return Err(invalid_argument(
+                        key,
+                        "dbfilename can't be a path, just a filename",
+                    ));
+                }
+                self.db_file_name = non_empty(value);
+            }
+            ConfigKey::Logfile => self.logfile = non_empty(value),
+            ConfigKey::Save => {
+                self.save =
+                    parse_save_rules(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::Maxclients => {
+                let max_clients = value.parse::<usize>().map_err(|_| {
+                    invalid_argument(key, "argument couldn't be parsed into an integer")
+                })?;
+                if max_clients == 0 {
+                    return Err(invalid_argument(
+                        key,
+                        "argument must be between 1 and 4294967295 inclusive",
+                    ));
+                }
+                self.max_clients = max_clients;
+            }
+            ConfigKey::Maxmemory => {
+                self.max_memory =
+                    parse_memory(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::Requirepass => self.requirepass = non_empty(value),
+            ConfigKey::IpAllowlist => {
+                self.ip_allowlist =
+                    cidr::parse_list(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::IpDenylist => {
+                self.ip_denylist =
+                    cidr::parse_list(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ClientMaxCommandsPerSecond => {
+                self.client_max_commands_per_second = value.parse::<u64>().map_err(|_| {
+                    invalid_argument(key, "argument couldn't be parsed into an integer")
+                })?
+            }
+            ConfigKey::ClientMaxBytesPerSecond => {
+                self.client_max_bytes_per_second =
+                    parse_memory(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ClientRateLimitPolicy => {
+                self.client_rate_limit_policy = RateLimitPolicy::parse(value)
+                    .map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::NamespaceMaxKeys => {
+                self.namespace_max_keys = value.parse::<u64>().map_err(|_| {
+                    invalid_argument(key, "argument couldn't be parsed into an integer")
+                })?
+            }
+            ConfigKey::NamespaceMaxMemory => {
+                self.namespace_max_memory =
+                    parse_memory(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::Rdbcompression => {
+                self.rdb_compression =
+                    parse_yes_or_no(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::Appendonly => {
+                self.append_only =
+                    parse_yes_or_no(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::Appendfilename => {
+                if value.contains('/') {
+                    return Err(invalid_argument(
+                        key,
+                        "appendfilename can't be a path, just a filename",
+                    ));
+                }
+                self.append_file_name = non_empty(value);
+            }
+            ConfigKey::Appenddirname => {
+                if value.contains('/') {
+                    return Err(invalid_argument(
+                        key,
+                        "appenddirname can't be a path, just a dirname",
+                    ));
+                }
+                self.append_dir_name = non_empty(value);
+            }
+            ConfigKey::AutoAofRewritePercentage => {
+                self.auto_aof_rewrite_percentage = value.parse::<u64>().map_err(|_| {
+                    invalid_argument(key, "argument couldn't be parsed into an integer")
+                })?
+            }
+            ConfigKey::AutoAofRewriteMinSize => {
+                self.auto_aof_rewrite_min_size =
+                    parse_memory(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ReplicaReadOnly => {
+                self.replica_read_only =
+                    parse_yes_or_no(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ReplicaServeStaleData => {
+                self.replica_serve_stale_data =
+                    parse_yes_or_no(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ReplPingReplicaPeriod => {
+                self.repl_ping_replica_period = parse_seconds(key, value)?
+            }
+            ConfigKey::ReplTimeout => self.repl_timeout = parse_seconds(key, value)?,
+            ConfigKey::ProtoMaxBulkLen => {
+                self.proto_max_bulk_len = parse_protocol_limit(key, value, 1024 * 1024)?
+            }
+            ConfigKey::ProtoMaxMultibulkLen => {
+                self.proto_max_multibulk_len = parse_protocol_limit(key, value, 1)?
+            }
+            ConfigKey::ProtoMaxInlineLen => {
+                self.proto_max_inline_len = parse_protocol_limit(key, value, 1024)?
+            }
+            ConfigKey::ClusterEnabled => {
+                self.cluster_enabled =
+                    parse_yes_or_no(value).map_err(|e| invalid_argument(key, &e.to_string()))?
+            }
+            ConfigKey::ClusterPort => {
+                self.cluster_port = value
+                    .parse()
+                    .map_err(|_| invalid_argument(key, "argument must be a port number"))?
+            }
+            ConfigKey::Databases => {
+                self.databases = match value.parse::<usize>() {
+                    Ok(databases) if (1..=i32::MAX as usize).contains(&databases) => databases,
+                    _ => {
+                        return Err(invalid_argument(
+                            key,
+                            "argument must be between 1 and 2147483647 inclusive",
+                        ))
+                    }
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn namespace_limits(&self) -> NamespaceLimits {
+        NamespaceLimits {
+            max_keys: self.namespace_max_keys,
+            max_memory: self.namespace_max_memory,
+        }
+    }
+
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            commands_per_second: self.client_max_commands_per_second,
+            bytes_per_second: self.client_max_bytes_per_second,
+            policy: self.client_rate_limit_policy,
+        }
+    }
+
+    /// Whether a client connecting from the address may stay connected.
+    pub fn is_allowed(&self, address: IpAddr) -> bool {
+        let allowed = self.ip_allowlist.is_empty()
+            || self
+                .ip_allowlist
+                .iter()
+                .any(|block| block.contains(address));
+        allowed && !self.ip_denylist.iter().any(|block| block.contains(address))
+    }
+
+    /// CONFIG SET, which unlike the config file can't touch immutable options.
+    pub fn set_at_runtime(&mut self, key: &ConfigKey, value: &str) -> Result<(), anyhow::Error> {
+        if !key.is_mutable() {
+            return Err(invalid_argument(key, "can't set immutable config"));
+        }
+
+        self.set(key, value)
+    }
+
+    /// Re-reads the config file the server was started with. Every reloadable directive
+    /// is validated before any of them are applied so a bad file leaves the running
+    /// configuration untouched.
+    pub fn reload(&self) -> Result<Self, anyhow::Error> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Server was not started with a config file"))?;
+
+        let mut config = self.clone();
+        // Directives that are missing from the file go back to their defaults.
+        let defaults = Config::new(None, None);
+        config.logfile = defaults.logfile;
+        config.save = defaults.save;
+        config.max_clients = defaults.max_clients;
+        config.max_memory = defaults.max_memory;
+        config.requirepass = defaults.requirepass;
+        config.ip_allowlist = defaults.ip_allowlist;
+        config.ip_denylist = defaults.ip_denylist;
+        config.client_max_commands_per_second = defaults.client_max_commands_per_second;
+        config.client_max_bytes_per_second = defaults.client_max_bytes_per_second;
+        config.client_rate_limit_policy = defaults.client_rate_limit_policy;
+        config.namespace_max_keys = defaults.namespace_max_keys;
+        config.namespace_max_memory = defaults.namespace_max_memory;
+        config.rdb_compression = defaults.rdb_compression;
+        config.auto_aof_rewrite_percentage = defaults.auto_aof_rewrite_percentage;
+        config.auto_aof_rewrite_min_size = defaults.auto_aof_rewrite_min_size;
+        config.replica_read_only = defaults.replica_read_only;
+        config.replica_serve_stale_data = defaults.replica_serve_stale_data;
+        config.repl_ping_replica_period = defaults.repl_ping_replica_period;
+        config.repl_timeout = defaults.repl_timeout;
+        config.proto_max_bulk_len = defaults.proto_max_bulk_len;
+        config.proto_max_multibulk_len = defaults.proto_max_multibulk_len;
+        config.proto_max_inline_len = defaults.proto_max_inline_len;
+
+        for (name, value) in read_directives(path)? {
+            match ConfigKey::parse(&name) {
+                Some(key) if key.is_reloadable() => config.set(&key, &value)?,
+                // Anything else only takes effect on restart
+                _ => {}
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn read_directives(path: &Path) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Reading config file {}", path.display()))?;
+
+    let mut directives: Vec<(String, String)> = vec![];
+    let mut save_rules: Option<Vec<String>> = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, value) = match line.split_once(char::is_whitespace) {
+            Some((name, value)) => (name.to_ascii_lowercase(), unquote(value.trim())),
+            None => anyhow::bail!("Bad directive or wrong number of arguments: {}", line),
+        };
+
+        // Like redis, every save line adds to the list of rules
+        // and `save ""` clears whatever came before it.
+        if name == "save" {
+            let rules = save_rules.get_or_insert_with(Vec::new);
+            if value.is_empty() {
+                rules.clear();
+            } else {
+                rules.push(value);
+            }
+            continue;
+        }
+
+        directives.push((name, value));
+    }
+
+    if let Some(rules) = save_rules {
+        directives.push(("save".to_string(), rules.join(" ")));
+    }
+
+    Ok(directives)
+}
+
+pub fn parse_io_threads(value: &str) -> Result<usize, anyhow::Error> {
+    match value.parse::<usize>() {
+        Ok(threads) if threads > 0 => Ok(threads),
+        _ => anyhow::bail!("io-threads must be a positive integer, got {}", value),
+    }
+}
+
+fn parse_yes_or_no(value: &str) -> Result<bool, anyhow::Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => anyhow::bail!("argument must be 'yes' or 'no'"),
+    }
+}
+
+fn yes_or_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn join_blocks(blocks: &[Cidr]) -> String {
+    blocks
+        .iter()
+        .map(Cidr::to_string)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn unquote(value: &str) -> String {
+    let is_quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+
+    if is_quoted {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn invalid_argument(key: &ConfigKey, reason: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+        key,
+        reason
+    )
+}
+
+fn parse_seconds(key: &ConfigKey, value: &str) -> Result<u64, anyhow::Error> {
+    match value.parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(seconds),
+        _ => Err(invalid_argument(
+            key,
+            "argument must be a positive number of seconds",
+        )),
+    }
+}
+
+fn parse_protocol_limit(key: &ConfigKey, value: &str, minimum: u64) -> Result<u64, anyhow::Error> {
+    let limit = parse_memory(value).map_err(|e| invalid_argument(key, &e.to_string()))?;
+    if limit < minimum {
+        return Err(invalid_argument(
+            key,
+            &format!("argument must be at least {}", minimum),
+        ));
+    }
+
+    Ok(limit)
+}
+
+fn parse_save_rules(value: &str) -> Result<Vec<SaveRule>, anyhow::Error> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if !parts.len().is_multiple_of(2) {
+        anyhow::bail!("Invalid save parameters");
+    }
+
+    parts
+        .chunks(2)
+        .map(|pair| {
+            let seconds = pair[0].parse::<u64>();
+            let changes = pair[1].parse::<u64>();
+            match (seconds, changes) {
+                (Ok(seconds), Ok(changes)) => Ok(SaveRule { seconds, changes }),
+                _ => anyhow::bail!("Invalid save parameters"),
+            }
+        })
+        .collect()
+}
+
+pub fn parse_memory(value: &str) -> Result<u64, anyhow::Error> {
+    let value = value.to_ascii_lowercase();
+    let units: [(&str, u64); 7] = [
+        ("gb", 1024 * 1024 * 1024),
+        ("mb", 1024 * 1024),
+        ("kb", 1024),
+        ("g", 1000 * 1000 * 1000),
+        ("m", 1000 * 1000),
+        ("k", 1000),
+        ("b", 1),
+    ];
+
+    let (amount, multiplier) = units
+        .iter()
+        .find_map(|(suffix, multiplier)| {
+            value
+                .strip_suffix(suffix)
+                .map(|amount| (amount, *multiplier))
+        })
+        .unwrap_or((value.as_str(), 1));
+
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| anyhow::anyhow!("argument must be a memory value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_memory_units() {
+        assert_eq!(parse_memory("100").unwrap(), 100);
+        assert_eq!(parse_memory("1kb").unwrap(), 1024);
+        assert_eq!(parse_memory("2MB").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_memory("1g").unwrap(), 1_000_000_000);
+        assert!(parse_memory("lots").is_err());
+    }
+
+    #[test]
+    fn parses_save_rules() {
+        let rules = parse_save_rules("900 1 300 10").unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                SaveRule {
+                    seconds: 900,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 10
+                }
+            ]
+        );
+        assert!(parse_save_rules("").unwrap().is_empty());
+        assert!(parse_save_rules("900").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::Cursor;
+
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::utils::{self, Frame, FrameLimits};
+
+const INITIAL_BUFFER_SIZE: usize = 4 * 1024;
+
+/// Reads RESP frames off a connection. Whatever arrives is accumulated in a buffer
+/// until a whole frame is there, so a command split across several reads is parsed
+/// the same as one that arrived at once, and anything after it is kept for the next frame.
+pub struct FrameReader<R> {
+    reader: R,
+    buffer: BytesMut,
+    limits: FrameLimits,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_limits(reader, FrameLimits::unlimited())
+    }
+
+    /// Rejects frames bigger than the limits with a `ProtocolError`.
+    pub fn with_limits(reader: R, limits: FrameLimits) -> Self {
+        FrameReader {
+            reader,
+            buffer: BytesMut::with_capacity(INITIAL_BUFFER_SIZE),
+            limits,
+        }
+    }
+
+    /// Waits for the next complete frame. Returns `None` once the peer closes the
+    /// connection between frames.
+    ///
+    /// Cancel safe: a frame that is only partially received stays in the buffer.
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>, anyhow::Error> {
+        loop {
+            if let Some(frame) = self.buffered_frame()? {
+                return Ok(Some(frame));
+            }
+
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+
+                anyhow::bail!("Connection closed in the middle of a frame");
+            }
+        }
+    }
+
+    /// Takes the next frame if it has already been received in full, without
+    /// waiting on the connection.
+    pub fn buffered_frame(&mut self) -> Result<Option<Frame>, anyhow::Error> {
+        let mut cursor = Cursor::new(&self.buffer[..]);
+        let frame = utils::read_frame(&mut cursor, &self.limits)?;
+
+        if let Some(frame) = &frame {
+            self.buffer.advance(frame.bytes_processed);
+        }
+
+        Ok(frame)
+    }
+
+    /// Reads whatever has arrived into the buffer, for `read_frame` or
+    /// `buffered_frame` to take later, and returns false once the connection is
+    /// closed.
+    pub async fn fill_buffer(&mut self) -> Result<bool, anyhow::Error> {
+        Ok(self.reader.read_buf(&mut self.buffer).await? > 0)
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Hands back the underlying connection. Anything still buffered is dropped.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_split_across_reads_are_put_back_together() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut reader = FrameReader::new(server);
+
+        let writer = tokio::spawn(async move {
+            for chunk in [
+                "*2\r\n$4\r\nECHO",
+                "\r\n$2\r",
+                "\nhi\r\n*1\r\n$4\r\nPING\r\n",
+            ] {
+                client.write_all(chunk.as_bytes()).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let frame = reader.read_frame().await.unwrap().unwrap();
+        assert_eq!(frame.data, vec!["*2", "$4", "ECHO", "$2", "hi"]);
+        assert_eq!(&frame.raw[..], b"*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n");
+
+        let frame = reader.read_frame().await.unwrap().unwrap();
+        assert_eq!(frame.data, vec!["*1", "$4", "PING"]);
+
+        writer.await.unwrap();
+        assert!(reader.read_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn filling_the_buffer_keeps_frames_for_later() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut reader = FrameReader::new(server);
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        assert!(reader.fill_buffer().await.unwrap());
+        drop(client);
+        assert!(!reader.fill_buffer().await.unwrap());
+
+        let frame = reader.buffered_frame().unwrap().unwrap();
+        assert_eq!(frame.data, vec!["*1", "$4", "PING"]);
+    }
+}
@@ -0,0 +1,429 @@
+//! Cuckoo filters, like the ones the RedisBloom module adds. Every item is kept
+//! as a one byte fingerprint in one of two buckets, the second found from the
+//! first and the fingerprint alone, so a full bucket can make room by moving a
+//! fingerprint to its other bucket. Unlike Bloom filters items can be deleted
+//! again. Once the newest filter can't make room a larger one is added.
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+use crate::utils::item_hashes;
+
+/// What CF.ADD creates a filter with when the key doesn't exist.
+pub const DEFAULT_CAPACITY: u64 = 1024;
+pub const DEFAULT_BUCKET_SIZE: u64 = 2;
+pub const DEFAULT_MAX_ITERATIONS: u64 = 20;
+pub const DEFAULT_EXPANSION: u64 = 1;
+// Like for Bloom filters, no single filter is larger than a value a client could send
+const MAX_FILTER_BYTES: u64 = 512 * 1024 * 1024;
+// How long a chain can grow, which loaded filters are held to as well
+const MAX_FILTERS: u64 = 65536;
+// An empty slot. Fingerprints are never 0.
+const EMPTY: u8 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+struct CuckooFilter {
+    /// `bucket_size` slots per bucket, one after the other.
+    slots: Vec<u8>,
+    /// Always a power of two, so the alternate bucket of the alternate bucket
+    /// is the one a fingerprint started in.
+    num_buckets: u64,
+}
+
+impl CuckooFilter {
+    fn new(num_buckets: u64, bucket_size: u64) -> Result<Self, RedisError> {
+        let bytes = num_buckets.saturating_mul(bucket_size);
+        if bytes > MAX_FILTER_BYTES {
+            return Err(RedisError::custom(
+                "ERR Insufficient memory to create filter",
+            ));
+        }
+
+        Ok(CuckooFilter {
+            slots: vec![EMPTY; bytes as usize],
+            num_buckets,
+        })
+    }
+
+    fn bucket_size(&self) -> usize {
+        self.slots.len() / self.num_buckets as usize
+    }
+
+    /// The two buckets an item can be in.
+    fn buckets(&self, (_, h2): (u64, u64), fingerprint: u8) -> (u64, u64) {
+        let first = h2 & (self.num_buckets - 1);
+        (first, self.alternate(first, fingerprint))
+    }
+
+    fn alternate(&self, bucket: u64, fingerprint: u8) -> u64 {
+        (bucket ^ (fingerprint as u64).wrapping_mul(0x5bd1e995)) & (self.num_buckets - 1)
+    }
+
+    fn bucket(&self, bucket: u64) -> &[u8] {
+        let size = self.bucket_size();
+        &self.slots[bucket as usize * size..(bucket as usize + 1) * size]
+    }
+
+    fn bucket_mut(&mut self, bucket: u64) -> &mut [u8] {
+        let size = self.bucket_size();
+        &mut self.slots[bucket as usize * size..(bucket as usize + 1) * size]
+    }
+
+    fn place(&mut self, bucket: u64, fingerprint: u8) -> bool {
+        match self
+            .bucket_mut(bucket)
+            .iter_mut()
+            .find(|slot| **slot == EMPTY)
+        {
+            Some(slot) => {
+                *slot = fingerprint;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes room for the fingerprint by moving others to their alternate
+    /// bucket, giving up after `max_iterations` moves. The fingerprint moved
+    /// each time is picked from the hash rather than at random, so every
+    /// replica ends up with the same filter.
+    fn insert(&mut self, hash: (u64, u64), fingerprint: u8, max_iterations: u64) -> bool {
+        let (first, second) = self.buckets(hash, fingerprint);
+        if self.place(first, fingerprint) || self.place(second, fingerprint) {
+            return true;
+        }
+
+        let size = self.bucket_size();
+        let mut moved = vec![];
+        let mut carried = fingerprint;
+        let mut bucket = first;
+        for i in 0..max_iterations {
+            let slot = bucket as usize * size + ((hash.0 ^ i) % size as u64) as usize;
+            std::mem::swap(&mut carried, &mut self.slots[slot]);
+            moved.push(slot);
+
+            bucket = self.alternate(bucket, carried);
+            if self.place(bucket, carried) {
+                return true;
+            }
+        }
+
+        // Put everything back where it was so no fingerprint is lost
+        for slot in moved.into_iter().rev() {
+            std::mem::swap(&mut carried, &mut self.slots[slot]);
+        }
+        false
+    }
+
+    fn count(&self, hash: (u64, u64), fingerprint: u8) -> u64 {
+        let (first, second) = self.buckets(hash, fingerprint);
+        let in_bucket = |bucket| {
+            self.bucket(bucket)
+                .iter()
+                .filter(|slot| **slot == fingerprint)
+                .count() as u64
+        };
+
+        match first == second {
+            true => in_bucket(first),
+            false => in_bucket(first) + in_bucket(second),
+        }
+    }
+
+    fn remove(&mut self, hash: (u64, u64), fingerprint: u8) -> bool {
+        let (first, second) = self.buckets(hash, fingerprint);
+        for bucket in [first, second] {
+            if let Some(slot) = self
+                .bucket_mut(bucket)
+                .iter_mut()
+                .find(|slot| **slot == fingerprint)
+            {
+                *slot = EMPTY;
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+fn fingerprint((h1, _): (u64, u64)) -> u8 {
+    (h1 % 255 + 1) as u8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalableCuckooFilter {
+    /// Oldest first. The first one is only created once it's needed.
+    filters: Vec<CuckooFilter>,
+    capacity: u64,
+    bucket_size: u64,
+    max_iterations: u64,
+    /// How many times more buckets each filter has than the last, rounded up to
+    /// a power of two. 0 for a filter that can't grow.
+    expansion: u64,
+    items: u64,
+    deleted: u64,
+}
+
+impl Default for ScalableCuckooFilter {
+    fn default() -> Self {
+        ScalableCuckooFilter {
+            filters: vec![],
+            capacity: DEFAULT_CAPACITY,
+            bucket_size: DEFAULT_BUCKET_SIZE,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            expansion: DEFAULT_EXPANSION,
+            items: 0,
+            deleted: 0,
+        }
+    }
+}
+
+impl ScalableCuckooFilter {
+    pub fn new(
+        capacity: u64,
+        bucket_size: u64,
+        max_iterations: u64,
+        expansion: u64,
+    ) -> Result<Self, RedisError> {
+        let mut filter = ScalableCuckooFilter {
+            capacity,
+            bucket_size,
+            max_iterations,
+            expansion,
+            ..Default::default()
+        };
+        filter.grow()?;
+
+        Ok(filter)
+    }
+
+    pub fn add(&mut self, item: &str) -> Result<(), RedisError> {
+        let hash = item_hashes(item);
+        let fingerprint = fingerprint(hash);
+
+        let inserted = match self.filters.last_mut() {
+            Some(filter) => filter.insert(hash, fingerprint, self.max_iterations),
+            None => false,
+        };
+        if !inserted {
+            self.grow()?;
+            let filter = self.filters.last_mut().expect("a filter was just added");
+            if !filter.insert(hash, fingerprint, self.max_iterations) {
+                return Err(RedisError::custom("ERR Filter is full"));
+            }
+        }
+        self.items += 1;
+
+        Ok(())
+    }
+
+    /// Adds the item only if it doesn't seem to be there already, and returns
+    /// whether it was added.
+    pub fn add_if_missing(&mut self, item: &str) -> Result<bool, RedisError> {
+        if self.contains(item) {
+            return Ok(false);
+        }
+
+        self.add(item).map(|_| true)
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.count(item) > 0
+    }
+
+    /// How many times the item, or one with the same fingerprint, was added.
+    pub fn count(&self, item: &str) -> u64 {
+        let hash = item_hashes(item);
+        let fingerprint = fingerprint(hash);
+        self.filters
+            .iter()
+            .map(|filter| filter.count(hash, fingerprint))
+            .sum()
+    }
+
+    /// Removes one copy of the item, looking in the newest filters first.
+    pub fn delete(&mut self, item: &str) -> bool {
+        let hash = item_hashes(item);
+        let fingerprint = fingerprint(hash);
+        let removed = self
+            .filters
+            .iter_mut()
+            .rev()
+            .any(|filter| filter.remove(hash, fingerprint));
+        if removed {
+            self.items -= 1;
+            self.deleted += 1;
+        }
+
+        removed
+    }
+
+    fn grow(&mut self) -> Result<(), RedisError> {
+        let num_buckets = match (self.filters.last(), self.expansion) {
+            (None, _) => self.capacity.div_ceil(self.bucket_size).next_power_of_two(),
+            (Some(_), 0) => return Err(RedisError::custom("ERR Filter is full")),
+            _ if self.filters.len() as u64 >= MAX_FILTERS => {
+                return Err(RedisError::custom("ERR Filter is full"))
+            }
+            (Some(last), expansion) => last
+                .num_buckets
+                .saturating_mul(expansion.next_power_of_two()),
+        };
+
+        self.filters
+            .push(CuckooFilter::new(num_buckets, self.bucket_size)?);
+        Ok(())
+    }
+}
+
+/// Saved under RedisBloom's type name with the fields laid out this server's
+/// own way: the settings, then every filter's bucket count and slots.
+impl ModuleValue for ScalableCuckooFilter {
+    const TYPE_NAME: &'static str = "MBbloomCF";
+    const ENCODING_VERSION: u16 = 0;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::Cuckoo(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::Cuckoo(filter) => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::Cuckoo(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        let mut fields = vec![
+            ModuleField::Uint(self.capacity),
+            ModuleField::Uint(self.bucket_size),
+            ModuleField::Uint(self.max_iterations),
+            ModuleField::Uint(self.expansion),
+            ModuleField::Uint(self.items),
+            ModuleField::Uint(self.deleted),
+            ModuleField::Uint(self.filters.len() as u64),
+        ];
+        for filter in self.filters.iter() {
+            fields.extend([
+                ModuleField::Uint(filter.num_buckets),
+                ModuleField::String(filter.slots.clone()),
+            ]);
+        }
+
+        fields
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [capacity, bucket_size, max_iterations, expansion, items, deleted, num_filters, rest @ ..] =
+            fields
+        else {
+            anyhow::bail!("Cuckoo filter is missing its settings");
+        };
+        // The same bounds CF.RESERVE enforces
+        let bucket_size = bucket_size.as_uint()?;
+        if !(1..=255).contains(&bucket_size) {
+            anyhow::bail!("Cuckoo filter has a bucket size of {}", bucket_size);
+        }
+        let num_filters = num_filters.as_uint()?;
+        if num_filters > MAX_FILTERS {
+            anyhow::bail!("Cuckoo filter has too many filters");
+        }
+        if rest.len() as u64 != num_filters * 2 {
+            anyhow::bail!("Cuckoo filter has the wrong number of fields");
+        }
+
+        let filters = rest
+            .chunks(2)
+            .map(|filter| {
+                let num_buckets = filter[0].as_uint()?;
+                let slots = filter[1].as_bytes()?.to_vec();
+                let bytes = num_buckets
+                    .checked_mul(bucket_size)
+                    .filter(|bytes| *bytes <= MAX_FILTER_BYTES)
+                    .ok_or_else(|| anyhow::anyhow!("Cuckoo filter is too big"))?;
+                if !num_buckets.is_power_of_two() || slots.len() as u64 != bytes {
+                    anyhow::bail!("Cuckoo filter has the wrong number of slots");
+                }
+                Ok(CuckooFilter { slots, num_buckets })
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        Ok(ScalableCuckooFilter {
+            filters,
+            capacity: capacity.as_uint()?,
+            bucket_size,
+            max_iterations: max_iterations.as_uint()?,
+            expansion: expansion.as_uint()?,
+            items: items.as_uint()?,
+            deleted: deleted.as_uint()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_can_be_added_counted_and_deleted() {
+        let mut filter = ScalableCuckooFilter::new(1000, 2, 20, 1).unwrap();
+        for i in 0..1000 {
+            filter.add(&i.to_string()).unwrap();
+        }
+        filter.add("7").unwrap();
+
+        assert!((0..1000).all(|i| filter.contains(&i.to_string())));
+        assert!(filter.count("7") >= 2);
+        assert!(!filter.add_if_missing("7").unwrap());
+
+        assert!(filter.delete("7"));
+        assert!(filter.delete("7"));
+        assert_eq!(filter.items, 999);
+        assert!((0..1000)
+            .filter(|i| *i != 7)
+            .all(|i| filter.contains(&i.to_string())));
+
+        let saved = ScalableCuckooFilter::from_fields(&filter.to_fields()).unwrap();
+        assert_eq!(saved, filter);
+
+        // Filter counts and sizes that overflow when counting the slots
+        let mut fields = filter.to_fields();
+        fields[6] = ModuleField::Uint((1 << 63) + 1);
+        assert!(ScalableCuckooFilter::from_fields(&fields).is_err());
+        fields = filter.to_fields();
+        fields[7] = ModuleField::Uint(1 << 63);
+        assert!(ScalableCuckooFilter::from_fields(&fields).is_err());
+        fields = filter.to_fields();
+        fields[1] = ModuleField::Uint(0);
+        assert!(ScalableCuckooFilter::from_fields(&fields).is_err());
+    }
+
+    #[test]
+    fn filters_grow_unless_they_cant_expand() {
+        let mut filter = ScalableCuckooFilter::new(8, 2, 10, 2).unwrap();
+        for i in 0..100 {
+            filter.add(&i.to_string()).unwrap();
+        }
+        assert!(filter.filters.len() > 1);
+        assert!((0..100).all(|i| filter.contains(&i.to_string())));
+
+        let mut filter = ScalableCuckooFilter::new(8, 2, 10, 0).unwrap();
+        let full = (0..100).find_map(|i| filter.add(&i.to_string()).err());
+        assert_eq!(full.unwrap().to_string(), "ERR Filter is full");
+        // Whatever was added before it filled up is still there
+        assert_eq!(
+            (0..filter.items)
+                .filter(|i| filter.contains(&i.to_string()))
+                .count() as u64,
+            filter.items
+        );
+    }
+}
@@ -1,21 +1,39 @@
-use std::collections::HashMap;
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io::{Cursor, Read};
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::io::{Cursor, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockWriteGuard};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
-use tokio::spawn;
-use tokio::sync::broadcast::{Receiver, Sender};
-use tokio::task::JoinHandle;
-use tokio::time::{sleep, timeout, Instant};
-
-use crate::encoding::{empty_string, okay_string};
-use crate::errors::{wrong_type, wrong_type_str};
-use crate::request::{self, CommandExpiration, SetOverride};
+use rand::Rng;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, timeout_at, Instant};
+
+use crate::blocking::BlockedKeys;
+use crate::bloom::ScalableBloomFilter;
+use crate::clock::{Clock, SystemClock};
+use crate::cms::CountMinSketch;
+use crate::cuckoo::ScalableCuckooFilter;
+use crate::encoding::{ListpackEntry, ModuleField};
+use crate::errors::RedisError;
+use crate::extension;
+use crate::hooks::{KeyEvent, KeyHooks};
+use crate::hotkeys::HotKeys;
+use crate::keyspace::SegmentedMap;
+use crate::object::{overwrite_string, string_range, RedisHash, RedisList, RedisSet, StringValue};
+use crate::request::{self, CommandExpiration, ExpireCondition, SetOverride};
+use crate::resp::Value;
+use crate::tasks::TaskSupervisor;
+use crate::timeseries::TimeSeries;
+use crate::topk::TopK;
 use crate::utils::current_unix_timestamp;
-use crate::{encoding, transmission, utils};
+use crate::{encoding, utils};
 
 // https://rdb.fnordig.de/file_format.html
 #[derive(PartialEq, Debug)]
@@ -41,6 +59,18 @@ impl OpCode {
             other => OpCode::Other(other),
         }
     }
+
+    fn to_byte(&self) -> u8 {
+        match self {
+            OpCode::Eof => 0xFF,
+            OpCode::SelectDB => 0xFE,
+            OpCode::ExpireTime => 0xFD,
+            OpCode::ExpireTimeMS => 0xFC,
+            OpCode::ResizeDb => 0xFB,
+            OpCode::Aux => 0xFA,
+            OpCode::Other(other) => *other,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -51,6 +81,26 @@ enum AuxField {
     MemoryUsed,
 }
 
+impl AuxField {
+    fn name(&self) -> &'static str {
+        match self {
+            AuxField::RedisVersion => "redis-ver",
+            AuxField::RedisBits => "redis-bits",
+            AuxField::CreationTime => "ctime",
+            AuxField::MemoryUsed => "used-mem",
+        }
+    }
+}
+
+const RDB_VERSION: &str = "0011";
+const REDIS_VERSION: &str = "7.2.0";
+// Matches redis' default stream-node-max-entries
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+// Like redis' default proto-max-bulk-len, which is as long as SETRANGE may make a string
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
 #[allow(dead_code)]
 #[derive(PartialEq, Debug)]
 enum ValueType {
@@ -59,12 +109,17 @@ enum ValueType {
     Set = 2,
     SortedSet = 3,
     Hash = 4,
+    SortedSet2 = 5,
+    Module2 = 7,
     Zipmap = 9,
     Ziplist = 10,
     Intset = 11,
     SortedSetZiplist = 12,
     HashmapZiplist = 13,
     ListQuicklist = 14,
+    StreamListpacks = 15,
+    StreamListpacks2 = 19,
+    StreamListpacks3 = 21,
 }
 
 impl ValueType {
@@ -75,12 +130,17 @@ impl ValueType {
             2 => Self::Set,
             3 => Self::SortedSet,
             4 => Self::Hash,
+            5 => Self::SortedSet2,
+            7 => Self::Module2,
             9 => Self::Zipmap,
             10 => Self::Ziplist,
             11 => Self::Intset,
             12 => Self::SortedSetZiplist,
             13 => Self::HashmapZiplist,
             14 => Self::ListQuicklist,
+            15 => Self::StreamListpacks,
+            19 => Self::StreamListpacks2,
+            21 => Self::StreamListpacks3,
             val => anyhow::bail!("Unrecognized value type: {}", val),
         };
 
@@ -88,7 +148,451 @@ impl ValueType {
     }
 }
 
-pub struct Database(Arc<RwLock<HashMap<String, DatabaseItem>>>);
+/// What a key's access frequency starts at, so a new key isn't the first to go.
+const INITIAL_FREQUENCY: u8 = 5;
+/// How much harder each access makes it to raise the frequency, as redis's
+/// `lfu-log-factor`.
+const FREQUENCY_LOG_FACTOR: f64 = 10.0;
+/// How long a key has to go without being accessed for its frequency to drop by one,
+/// as redis's `lfu-decay-time`.
+const FREQUENCY_DECAY_MS: u64 = 60_000;
+
+/// A value along with when it expires, so a key of any type can have a TTL, and
+/// when it was last accessed and how often.
+#[derive(Debug)]
+struct Entry {
+    item: DatabaseItem,
+    // Unix timestamp in milliseconds
+    expires_at: Option<u128>,
+    // Shared so a copy of the keyspace can still cancel the expiration
+    timer: Option<Arc<AbortHandle>>,
+    // Unix timestamp in milliseconds. Reads only hold the keyspace's read lock,
+    // so they update it in place.
+    accessed_at: AtomicU64,
+    // A logarithmic count of accesses, decaying while the key goes unused, like
+    // redis's LFU counter
+    frequency: AtomicU8,
+    // The bytes the keyspace counts for the key, as of when it was last measured.
+    // Measuring only needs the entry, so a copy a snapshot shares is updated in place.
+    size: AtomicUsize,
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Entry {
+            item: self.item.clone(),
+            expires_at: self.expires_at,
+            timer: self.timer.clone(),
+            accessed_at: AtomicU64::new(self.accessed_at.load(Ordering::Relaxed)),
+            frequency: AtomicU8::new(self.frequency.load(Ordering::Relaxed)),
+            size: AtomicUsize::new(self.size.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Entry {
+    fn new(item: DatabaseItem, now: u128) -> Self {
+        Entry {
+            item,
+            expires_at: None,
+            timer: None,
+            accessed_at: AtomicU64::new(now as u64),
+            frequency: AtomicU8::new(INITIAL_FREQUENCY),
+            size: AtomicUsize::new(0),
+        }
+    }
+
+    /// The access frequency as of `now`, having decayed since the last access.
+    fn frequency(&self, now: u64) -> u8 {
+        let idle = now.saturating_sub(self.accessed_at.load(Ordering::Relaxed));
+        let decay = (idle / FREQUENCY_DECAY_MS).min(u8::MAX as u64) as u8;
+        self.frequency.load(Ordering::Relaxed).saturating_sub(decay)
+    }
+
+    /// Counts an access at `now`. The frequency only goes up with a probability
+    /// that falls the higher it already is, so it takes a great many accesses
+    /// to saturate it.
+    fn record_access(&self, now: u64) {
+        let mut frequency = self.frequency(now);
+        if frequency < u8::MAX {
+            let base = frequency.saturating_sub(INITIAL_FREQUENCY) as f64;
+            let probability = 1.0 / (base * FREQUENCY_LOG_FACTOR + 1.0);
+            if rand::thread_rng().gen::<f64>() < probability {
+                frequency += 1;
+            }
+        }
+        self.frequency.store(frequency, Ordering::Relaxed);
+        self.accessed_at.store(now, Ordering::Relaxed);
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+}
+
+/// Keys are reference counted so listing them, or copying the keyspace for a
+/// snapshot, doesn't copy every key. The keyspace is segmented so growing it
+/// never rehashes every key in one go.
+///
+/// A key's deadline is kept with its value. Putting a new value in a key's place
+/// drops the deadline the old one had, as does removing the key, while changing
+/// the value where it is keeps it.
+///
+/// The keyspace has the database's clock to stamp keys with when they're set.
+///
+/// Once something, like a memory limit, asks how many bytes its keys take up, it
+/// goes through every key to find out and from then on keeps count. Keys that are
+/// set or changed are only measured again the next time the count is asked for,
+/// so writes don't pay for it themselves.
+#[derive(Debug, Clone)]
+struct Keyspace {
+    entries: SegmentedMap<Entry>,
+    clock: Arc<dyn Clock>,
+    // The sum of every entry's size
+    memory: usize,
+    // Keys set or changed since they were last measured. `None` until the keyspace
+    // is first measured, as there's no count to keep up to date before then.
+    unmeasured: Option<HashSet<Arc<str>>>,
+}
+
+impl Keyspace {
+    fn new(clock: Arc<dyn Clock>) -> Self {
+        Keyspace {
+            entries: SegmentedMap::new(),
+            clock,
+            memory: 0,
+            unmeasured: None,
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&self, key: &str) -> Option<&DatabaseItem> {
+        self.entries.get(key).map(|entry| &entry.item)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut DatabaseItem> {
+        let entry = self.entries.get_mut(key)?;
+        if let Some(unmeasured) = &mut self.unmeasured {
+            if !unmeasured.contains(key) {
+                unmeasured.insert(Arc::from(key));
+            }
+        }
+        Some(&mut entry.item)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the value the key had before, if any.
+    fn insert(&mut self, key: Arc<str>, item: DatabaseItem) -> Option<DatabaseItem> {
+        let entry = Entry::new(item, self.clock.now());
+        let mut replaced = self.insert_entry(key, entry)?;
+        replaced.stop_timer();
+        Some(replaced.item)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<DatabaseItem> {
+        let mut removed = self.take(key)?;
+        removed.stop_timer();
+        Some(removed.item)
+    }
+
+    /// Removes the key, leaving it to the caller to stop its timer and drop its value.
+    fn take(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.memory -= entry.size.load(Ordering::Relaxed);
+        Some(entry)
+    }
+
+    /// Puts a key taken from another keyspace in this one as it was, except for
+    /// its timer, which the caller starts again for this keyspace.
+    fn put(&mut self, key: Arc<str>, mut entry: Entry) {
+        entry.stop_timer();
+        entry.size = AtomicUsize::new(0);
+        if let Some(mut replaced) = self.insert_entry(key, entry) {
+            replaced.stop_timer();
+        }
+    }
+
+    /// Inserts an entry that hasn't been counted yet, and returns the one it replaced.
+    fn insert_entry(&mut self, key: Arc<str>, entry: Entry) -> Option<Entry> {
+        if let Some(unmeasured) = &mut self.unmeasured {
+            unmeasured.insert(Arc::clone(&key));
+        }
+        let replaced = self.entries.insert(key, entry)?;
+        self.memory -= replaced.size.load(Ordering::Relaxed);
+        Some(replaced)
+    }
+
+    /// The bytes every key takes up, once the ones set or changed since the last
+    /// time are measured. Keys are measured as their length and that of their value
+    /// as DUMP would serialize it, while lists add up the sizes their chunks keep
+    /// track of instead.
+    fn used_memory(&mut self) -> usize {
+        let measure = |key: &str, entry: &Entry| {
+            let size = key.len()
+                + match &entry.item {
+                    DatabaseItem::List(list) => list.memory_usage(),
+                    item => dump_item(item).len(),
+                };
+            entry.size.store(size, Ordering::Relaxed);
+            size
+        };
+
+        match self.unmeasured.replace(HashSet::new()) {
+            None => {
+                self.memory = self
+                    .entries
+                    .iter()
+                    .map(|(key, entry)| measure(key, entry))
+                    .sum();
+            }
+            Some(unmeasured) => {
+                for key in unmeasured {
+                    if let Some(entry) = self.entries.get(&key) {
+                        let before = entry.size.load(Ordering::Relaxed);
+                        self.memory = self.memory - before + measure(&key, entry);
+                    }
+                }
+            }
+        }
+
+        self.memory
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.entries.keys()
+    }
+
+    /// Every key along with its value and deadline.
+    fn entries(&self) -> impl Iterator<Item = (&Arc<str>, &DatabaseItem, Option<u128>)> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| (key, &entry.item, entry.expires_at))
+    }
+
+    /// A key picked at random from those that haven't expired by `now`. Keys whose
+    /// timer just hasn't run yet are passed over, up to a point, like redis does.
+    fn random_key(&self, now: u128) -> Option<Arc<str>> {
+        for _ in 0..100 {
+            let (key, entry) = self.entries.random_entry()?;
+            if entry.expires_at.is_none_or(|expires_at| expires_at > now) {
+                return Some(Arc::clone(key));
+            }
+        }
+        None
+    }
+
+    /// Marks the key as accessed just now. Returns whether it exists.
+    fn touch(&self, key: &str) -> bool {
+        match self.entries.get(key) {
+            Some(entry) => {
+                entry.record_access(self.clock.now() as u64);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The unix timestamp in milliseconds the key was last accessed at.
+    fn accessed_at(&self, key: &str) -> Option<u128> {
+        let entry = self.entries.get(key)?;
+        Some(entry.accessed_at.load(Ordering::Relaxed) as u128)
+    }
+
+    /// How often the key has been accessed lately, on redis's logarithmic scale.
+    fn frequency(&self, key: &str) -> Option<u8> {
+        let entry = self.entries.get(key)?;
+        Some(entry.frequency(self.clock.now() as u64))
+    }
+
+    /// The unix timestamp in milliseconds the key expires at.
+    fn expires_at(&self, key: &str) -> Option<u128> {
+        self.entries.get(key).and_then(|entry| entry.expires_at)
+    }
+
+    /// Gives the key a new deadline, or none, stopping the timer for the old one.
+    /// Returns whether the key exists.
+    fn set_expires_at(&mut self, key: &str, expires_at: Option<u128>) -> bool {
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.stop_timer();
+                entry.expires_at = expires_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Hands the key the timer that removes it at `expires_at`, unless the key's
+    /// deadline has changed since, in which case the timer is stopped.
+    fn set_timer(&mut self, key: &str, expires_at: u128, timer: AbortHandle) {
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.expires_at == Some(expires_at) => {
+                entry.stop_timer();
+                entry.timer = Some(Arc::new(timer));
+            }
+            _ => timer.abort(),
+        }
+    }
+
+    /// For a keyspace that's being thrown away, as its timers would still remove
+    /// keys from the database it came from.
+    fn stop_timers(&self) {
+        for entry in self.entries.values() {
+            if let Some(timer) = &entry.timer {
+                timer.abort();
+            }
+        }
+    }
+}
+
+/// The keyspace lives behind an `Arc` so a snapshot only has to clone the pointer.
+/// Writers go through `Arc::make_mut`, which copies the keyspace's segment table the
+/// first time it is changed while a snapshot still holds on to the old one, and then
+/// only the segments that are actually written to. Those share their values with
+/// the snapshot until a value is itself changed.
+///
+/// A `Database` works on one of the numbered databases, the one picked with
+/// `select`, and shares everything else with the handles on the others.
+#[derive(Clone)]
+pub struct Database {
+    keyspace: KeyspaceLock,
+    save_status: Arc<SaveStatus>,
+    expirations: Arc<Expirations>,
+    blocked: Arc<BlockedKeys>,
+    tasks: TaskSupervisor,
+    hooks: Arc<KeyHooks>,
+    hot_keys: Arc<HotKeys>,
+    clock: Arc<dyn Clock>,
+    keyspaces: Arc<Keyspaces>,
+    index: usize,
+}
+
+type KeyspaceLock = Arc<RwLock<Arc<Keyspace>>>;
+
+/// The keyspace of every numbered database, by index. A database is only made
+/// the first time it's selected, so the ones that are never used cost nothing.
+#[derive(Debug)]
+struct Keyspaces(RwLock<BTreeMap<usize, KeyspaceLock>>);
+
+impl Keyspaces {
+    fn new(first: KeyspaceLock) -> Self {
+        Keyspaces(RwLock::new(BTreeMap::from([(0, first)])))
+    }
+
+    fn get_or_insert(&self, index: usize, clock: &Arc<dyn Clock>) -> KeyspaceLock {
+        if let Some(keyspace) = self.0.read().unwrap().get(&index) {
+            return Arc::clone(keyspace);
+        }
+
+        let mut keyspaces = self.0.write().unwrap();
+        let keyspace = keyspaces
+            .entry(index)
+            .or_insert_with(|| Arc::new(RwLock::new(Arc::new(Keyspace::new(clock.clone())))));
+        Arc::clone(keyspace)
+    }
+
+    /// Every database that has been made, in order.
+    fn all(&self) -> Vec<(usize, KeyspaceLock)> {
+        let keyspaces = self.0.read().unwrap();
+        keyspaces
+            .iter()
+            .map(|(index, keyspace)| (*index, Arc::clone(keyspace)))
+            .collect()
+    }
+}
+
+/// A point in time view of every database, which can be serialized afterwards
+/// without holding up anything that writes to the dataset in the meantime.
+pub struct Snapshot {
+    // Only the databases that have been made, by index
+    keyspaces: Vec<(usize, Arc<Keyspace>)>,
+    changes: u64,
+    generation: u64,
+    // Unix timestamp in milliseconds
+    taken_at: u128,
+}
+
+impl Snapshot {
+    /// The dataset as an RDB file, leaving out the keys that had expired by the
+    /// time the snapshot was taken.
+    pub fn to_rdb(&self, compress: bool) -> Result<Vec<u8>, anyhow::Error> {
+        serialize(&self.keyspaces, compress, self.taken_at)
+    }
+}
+
+struct KeyspaceGuard<'a>(RwLockWriteGuard<'a, Arc<Keyspace>>);
+
+type KeyspaceLockResult<'a> =
+    Result<KeyspaceGuard<'a>, PoisonError<RwLockWriteGuard<'a, Arc<Keyspace>>>>;
+
+impl Deref for KeyspaceGuard<'_> {
+    type Target = Keyspace;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for KeyspaceGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+/// Tracks how much has changed since the dataset was last persisted,
+/// which the save rules use to decide when to write a new RDB file.
+#[derive(Debug)]
+struct SaveStatus {
+    changes: AtomicU64,
+    // Unix timestamp in seconds
+    last_save: AtomicU64,
+    snapshots: AtomicU64,
+    // Held for the duration of a save so two saves never share the temp file,
+    // along with the snapshot that was last written.
+    saving: Mutex<u64>,
+    background_save: AtomicBool,
+    last_background_save_failed: AtomicBool,
+}
+
+impl SaveStatus {
+    fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        SaveStatus {
+            changes: AtomicU64::new(0),
+            last_save: AtomicU64::new(now),
+            snapshots: AtomicU64::new(0),
+            saving: Mutex::new(0),
+            background_save: AtomicBool::new(false),
+            last_background_save_failed: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Keys removed by their expiry timer are reported here, along with the
+/// database they were in, so the master can send an explicit DEL to its
+/// replicas and the AOF.
+#[derive(Debug, Default)]
+struct Expirations {
+    // Replicas wait for the master's DEL instead of expiring keys themselves
+    passive: AtomicBool,
+    listener: Mutex<Option<UnboundedSender<(usize, String)>>>,
+}
 
 impl Default for Database {
     fn default() -> Self {
@@ -100,52 +604,255 @@ impl Database {
     pub fn new() -> Self {
         // If we persist data to a database, we can fetch the data on initialization
         // Create a process that runs every so often to store hashmap data in a more permanent database
-        Self(Arc::new(RwLock::new(HashMap::new())))
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let keyspace = Arc::new(RwLock::new(Arc::new(Keyspace::new(clock.clone()))));
+        Self {
+            keyspace: keyspace.clone(),
+            save_status: Arc::new(SaveStatus::new()),
+            expirations: Arc::new(Expirations::default()),
+            blocked: Arc::new(BlockedKeys::default()),
+            tasks: TaskSupervisor::new(),
+            hooks: Arc::new(KeyHooks::default()),
+            hot_keys: Arc::new(HotKeys::default()),
+            clock,
+            keyspaces: Arc::new(Keyspaces::new(keyspace)),
+            index: 0,
+        }
+    }
+
+    /// The numbered database `index`, which is made empty the first time it's
+    /// selected. It shares the clock, the save status and everything else that
+    /// isn't a key with this one.
+    pub fn select(&self, index: usize) -> Database {
+        let mut database = self.clone();
+        database.keyspace = self.keyspaces.get_or_insert(index, &self.clock);
+        database.index = index;
+        database
+    }
+
+    /// The number of the database this works on.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Has the dataset tell the time, and so when keys expire, by `clock`. It's
+    /// meant for a database that was only just made, as timers that are already
+    /// running stay on the clock they were started with.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        for (_, keyspace) in self.keyspaces.all() {
+            KeyspaceGuard(keyspace.write().unwrap()).clock = self.clock.clone();
+        }
+        self
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// The unix timestamp in milliseconds, by the database's clock.
+    pub(crate) fn now(&self) -> u128 {
+        self.clock.now()
+    }
+
+    /// Expiry timers, background saves and anything else running on the dataset's behalf.
+    pub fn tasks(&self) -> &TaskSupervisor {
+        &self.tasks
+    }
+
+    /// How often clients have accessed each key lately.
+    pub fn hot_keys(&self) -> &HotKeys {
+        &self.hot_keys
+    }
+
+    /// Makes room for at least `additional` more keys.
+    pub fn reserve(&self, additional: usize) -> Result<(), anyhow::Error> {
+        let mut keyspace = self
+            .write_keyspace()
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        keyspace.reserve(additional);
+        Ok(())
     }
 
-    pub fn get(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
-        let database = self.0.read().unwrap();
+    /// The number of changes since the last successful save.
+    pub fn dirty(&self) -> u64 {
+        self.save_status.changes.load(Ordering::SeqCst)
+    }
+
+    /// The unix time in seconds of the last successful save.
+    pub fn last_save(&self) -> u64 {
+        self.save_status.last_save.load(Ordering::SeqCst)
+    }
+
+    /// Used once a freshly loaded dataset matches what's on disk.
+    pub fn clear_dirty(&self) {
+        self.save_status.changes.store(0, Ordering::SeqCst);
+    }
+
+    fn write_keyspace(&self) -> KeyspaceLockResult<'_> {
+        self.keyspace.write().map(KeyspaceGuard)
+    }
+
+    fn mark_dirty(&self, changes: u64) {
+        self.save_status
+            .changes
+            .fetch_add(changes, Ordering::SeqCst);
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>, RedisError> {
+        let database = self.keyspace.read().unwrap();
         let item = database.get(key);
 
         let data = match item {
-            Some(DatabaseItem::Stream(_)) => anyhow::bail!(wrong_type_str()),
             Some(DatabaseItem::String(redis_string)) => Some(redis_string.data.to_string()),
+            Some(_) => return Err(RedisError::WrongType),
             None => None,
         };
 
         Ok(data)
     }
 
-    pub fn get_type(&self, key: &str) -> Option<String> {
-        let database = self.0.read().unwrap();
-        database.get(key).map(|v| v.data_type())
+    /// The strings at each of the keys, read under one lock. Keys that don't exist
+    /// or don't hold strings come back as `None`.
+    pub fn get_multiple<S: AsRef<str>>(&self, keys: &[S]) -> Vec<Option<String>> {
+        let database = self.keyspace.read().unwrap();
+        keys.iter()
+            .map(|key| match database.get(key.as_ref()) {
+                Some(DatabaseItem::String(redis_string)) => Some(redis_string.data()),
+                _ => None,
+            })
+            .collect()
     }
 
-    pub fn set(&self, key: String, mut value: RedisString) -> Result<(), anyhow::Error> {
-        let duration = value.duration;
+    /// Marks the keys as accessed just now, under one read lock. Returns how many
+    /// of them exist, counting a key given more than once each time.
+    pub fn touch<S: AsRef<str>>(&self, keys: &[S]) -> usize {
+        let database = self.keyspace.read().unwrap();
+        keys.iter()
+            .filter(|key| database.touch(key.as_ref()))
+            .count()
+    }
 
-        if let Some(dur) = duration {
-            let database = self.clone();
-            let key = key.to_string();
-            let join_handle = spawn(async move {
-                sleep(dur).await;
-                database.remove(&key);
-            });
+    /// The unix timestamp in milliseconds the key was last accessed at, by the
+    /// database's clock, or when it was set if it hasn't been since.
+    pub fn last_accessed(&self, key: &str) -> Option<u128> {
+        self.keyspace.read().unwrap().accessed_at(key)
+    }
+
+    /// How often the key has been accessed lately, starting at 5 for a new key and
+    /// growing logarithmically, or `None` if there's no such key.
+    pub fn access_frequency(&self, key: &str) -> Option<u8> {
+        self.keyspace.read().unwrap().frequency(key)
+    }
+
+    /// How many of the keys exist, under one read lock. A key given more than once
+    /// is counted each time.
+    pub fn count_existing<S: AsRef<str>>(&self, keys: &[S]) -> usize {
+        let database = self.keyspace.read().unwrap();
+        keys.iter()
+            .filter(|key| database.contains_key(key.as_ref()))
+            .count()
+    }
 
-            value.set_cancellation(join_handle);
+    /// The bytes of the string at `key` from `start` to `end`, both inclusive. Negative
+    /// offsets count back from the end of the string, and the range is clamped to it.
+    /// A key that doesn't exist reads as an empty string.
+    pub fn get_range(&self, key: &str, start: i64, end: i64) -> Result<String, RedisError> {
+        let Some(data) = self.get(key)? else {
+            return Ok(String::new());
         };
 
-        let database_item = DatabaseItem::String(value);
-        self.0
-            .write()
-            .map_err(|e| anyhow::anyhow!("{}", e))?
-            .insert(key.to_string(), database_item);
+        let range = byte_range(data.len(), start, end);
+        let data = string_range(&data, range).ok_or(RedisError::SplitCharacter)?;
+        Ok(data.to_string())
+    }
+
+    pub fn get_type(&self, key: &str) -> Option<&'static str> {
+        let database = self.keyspace.read().unwrap();
+        database.get(key).map(|v| v.type_name())
+    }
+
+    pub fn get_encoding(&self, key: &str) -> Option<&'static str> {
+        let database = self.keyspace.read().unwrap();
+        database.get(key).map(|v| v.encoding())
+    }
+
+    /// The unix timestamp in milliseconds the key expires at, `Some(None)` if it
+    /// never does and `None` if there's no such key.
+    pub fn expiration(&self, key: &str) -> Option<Option<u128>> {
+        let database = self.keyspace.read().unwrap();
+        database.contains_key(key).then(|| database.expires_at(key))
+    }
+
+    /// Sets the key to the string, expiring at the unix timestamp in milliseconds if given.
+    pub fn set(
+        &self,
+        key: String,
+        value: RedisString,
+        expires_at: Option<u128>,
+    ) -> Result<(), RedisError> {
+        {
+            let mut db = self.write_keyspace()?;
+            db.insert(Arc::from(key.as_str()), DatabaseItem::String(value));
+            db.set_expires_at(&key, expires_at);
+        }
+        self.mark_dirty(1);
+
+        self.hooks.notify(&key, KeyEvent::Set);
+        if let Some(expires_at) = expires_at {
+            self.schedule_expiry(key, expires_at);
+        }
 
         Ok(())
     }
 
-    fn set_item(&self, key: String, item: DatabaseItem) -> Option<DatabaseItem> {
-        self.0.write().unwrap().insert(key, item)
+    /// Sets each key to its string under one lock, so no one sees some of them set
+    /// and not the others. Any expirations the keys had are dropped.
+    pub fn set_multiple(&self, pairs: Vec<(String, String)>) -> Result<(), RedisError> {
+        self.insert_strings(pairs, false).map(|_| ())
+    }
+
+    /// Like `set_multiple`, but only if none of the keys exist. Returns whether they
+    /// were set.
+    pub fn set_multiple_if_none_exist(
+        &self,
+        pairs: Vec<(String, String)>,
+    ) -> Result<bool, RedisError> {
+        self.insert_strings(pairs, true)
+    }
+
+    fn insert_strings(
+        &self,
+        pairs: Vec<(String, String)>,
+        only_if_none_exist: bool,
+    ) -> Result<bool, RedisError> {
+        let mut keys = Vec::with_capacity(pairs.len());
+        {
+            let mut db = self.write_keyspace()?;
+            // Checked under the same lock as the keys are set, so no one can set
+            // one of them in between
+            if only_if_none_exist && pairs.iter().any(|(key, _)| db.contains_key(key)) {
+                return Ok(false);
+            }
+            for (key, value) in pairs {
+                let item = DatabaseItem::String(RedisString::new(value));
+                db.insert(Arc::from(key.as_str()), item);
+                keys.push(key);
+            }
+        }
+        self.mark_dirty(keys.len() as u64);
+
+        for key in keys.iter() {
+            self.hooks.notify(key, KeyEvent::Set);
+        }
+
+        Ok(true)
+    }
+
+    fn set_item(&self, key: String, item: DatabaseItem, expires_at: Option<u128>) {
+        let mut db = self.write_keyspace().unwrap();
+        db.insert(Arc::from(key.as_str()), item);
+        db.set_expires_at(&key, expires_at);
     }
 
     pub fn set_value(
@@ -155,16 +862,15 @@ impl Database {
         return_old_value: bool,
         overwrites: SetOverride,
         expires: CommandExpiration,
-    ) -> Result<String, anyhow::Error> {
-        let mut db = self.0.write().map_err(|e| anyhow::anyhow!("{}", e))?;
+    ) -> Result<Value, RedisError> {
+        let mut db = self.write_keyspace()?;
 
-        let item = db.get_mut(&key);
-        let item = match item {
-            Some(DatabaseItem::String(redis_string)) => Some(redis_string),
+        let existing = match db.get(key.as_str()) {
+            Some(DatabaseItem::String(redis_string)) => Some(redis_string.data()),
             None => None,
             _ => {
                 if return_old_value {
-                    anyhow::bail!(wrong_type_str())
+                    return Err(RedisError::WrongType);
                 } else {
                     None
                 }
@@ -172,175 +878,191 @@ impl Database {
         };
 
         let return_data = if return_old_value {
-            item.as_ref().map(|i| i.data()).unwrap_or_else(empty_string)
+            Value::from(existing.clone())
         } else {
-            okay_string()
+            Value::ok()
         };
 
-        let duration = match expires {
-            CommandExpiration::None => None,
-            CommandExpiration::Other => {
-                if let Some(i) = &item {
-                    i.duration
-                } else {
-                    None
-                }
-            }
-            CommandExpiration::Expiry(duration) => Some(duration),
+        let keep_ttl = expires == CommandExpiration::Keep;
+        let expires_at = match expires {
+            CommandExpiration::Keep => db.expires_at(&key),
+            CommandExpiration::Persist => None,
+            CommandExpiration::After(duration) => Some(self.now() + duration.as_millis()),
+            CommandExpiration::At(deadline) => Some(deadline),
         };
 
-        match (overwrites, item.is_some()) {
+        let should_set = matches!(
+            (overwrites, existing.is_some()),
             (SetOverride::Normal, _)
-            | (SetOverride::OnlyOverwrite, true)
-            | (SetOverride::NeverOverwrite, false) => {
-                let mut value = RedisString::new(value, duration);
-                // TODO: Cancel old cancellation process
-
-                if let Some(item) = item {
-                    item.abort_deletion_process();
+                | (SetOverride::OnlyOverwrite, true)
+                | (SetOverride::NeverOverwrite, false)
+        );
+        if !should_set {
+            // The value stays, but the expiration is still updated
+            if existing.is_some() && !keep_ttl {
+                db.set_expires_at(&key, expires_at);
+                drop(db);
+
+                if let Some(expires_at) = expires_at {
+                    self.schedule_expiry(key, expires_at);
                 }
-
-                if let Some(dur) = duration {
-                    let database = self.clone();
-                    let key_copy = key.clone();
-                    let process = spawn(async move {
-                        sleep(dur).await;
-                        database.remove(&key_copy);
-                    });
-
-                    value.set_cancellation(process);
-                };
-
-                db.insert(key.to_string(), DatabaseItem::String(value));
             }
-            (_, true) => {
-                let item = item.unwrap();
-
-                item.abort_deletion_process();
-
-                if let Some(dur) = duration {
-                    let database = self.clone();
-                    let key_copy = key.clone();
-                    let process = spawn(async move {
-                        sleep(dur).await;
-                        database.remove(&key_copy);
-                    });
+            return Ok(return_data);
+        }
 
-                    item.set_cancellation(process);
-                };
+        let value = DatabaseItem::String(RedisString::new(value));
+        match db.get_mut(&key) {
+            // Replaced where it is, the running timer is still good for the same deadline
+            Some(item) if keep_ttl => *item = value,
+            _ => {
+                db.insert(Arc::from(key.as_str()), value);
+                db.set_expires_at(&key, expires_at);
             }
-            _ => {}
-        };
+        }
+        self.mark_dirty(1);
+        drop(db);
+
+        self.hooks.notify(&key, KeyEvent::Set);
+        if let (Some(expires_at), false) = (expires_at, keep_ttl) {
+            self.schedule_expiry(key, expires_at);
+        }
 
         Ok(return_data)
     }
 
-    pub fn add_stream(
-        &self,
-        command: request::XAddCommand,
-        sender: Sender<transmission::Transmission>,
-    ) -> Result<String, anyhow::Error> {
-        let mut database = self.0.write().unwrap();
+    /// Starts the timer that removes `key` at `expires_at`. Spawning happens
+    /// outside the keyspace lock, which is only taken again briefly to hand the
+    /// timer to the key. If the key was changed in the meantime the timer is
+    /// dropped, and a timer that fires anyway leaves a key with a different
+    /// deadline alone.
+    fn schedule_expiry(&self, key: String, expires_at: u128) {
+        let database = self.clone();
+        let timer_key = key.clone();
+        let timer = self.clock.sleep_until(expires_at);
+        let process = self.tasks.spawn("expiry", async move {
+            timer.await;
+            database.expire(&timer_key, expires_at);
+        });
+
+        self.write_keyspace()
+            .unwrap()
+            .set_timer(&key, expires_at, process);
+    }
 
+    pub fn add_stream(&self, command: request::XAddCommand) -> Result<String, RedisError> {
         let ms_time = match command.ms_time {
-            request::XAddNumber::Autogenerate => current_unix_timestamp()?,
-            request::XAddNumber::Predetermined(val) => val as u128,
+            request::XAddNumber::Autogenerate => None,
+            request::XAddNumber::Predetermined(val) => Some(val as u128),
         };
 
-        match database.get_mut(&command.stream_key) {
+        let stream_id = self.insert_stream_entry(
+            &command.stream_key,
+            ms_time,
+            command.sequence_number,
+            command.data,
+        )?;
+
+        // Blocked readers are only woken up once the lock is released.
+        self.blocked.signal(&command.stream_key);
+        self.hooks.notify(&command.stream_key, KeyEvent::Xadd);
+
+        Ok(stream_id.to_string())
+    }
+
+    /// Appends an entry to a stream, creating it if need be, and returns its id.
+    /// Without a millisecond time the current one is used, read under the lock so
+    /// concurrent writers can't generate ids behind the top of the stream.
+    fn insert_stream_entry(
+        &self,
+        key: &str,
+        ms_time: Option<u128>,
+        sequence_number: request::XAddNumber,
+        items: Vec<RedisStreamItem>,
+    ) -> Result<StreamId, RedisError> {
+        let mut database = self.write_keyspace().unwrap();
+
+        match database.get_mut(key) {
             None => {
-                let sequence_number = match (command.sequence_number, ms_time) {
+                let ms_time = match ms_time {
+                    Some(ms_time) => ms_time,
+                    None => self.now(),
+                };
+                let sequence_number = match (sequence_number, ms_time) {
                     (request::XAddNumber::Autogenerate, 0) => 1,
                     (request::XAddNumber::Autogenerate, _) => 0,
                     (request::XAddNumber::Predetermined(val), _) => val,
                 };
 
                 if ms_time == 0 && sequence_number == 0 {
-                    return Err(anyhow::anyhow!(
-                        "ERR The ID specified in XADD must be greater than 0-0"
+                    return Err(RedisError::custom(
+                        "ERR The ID specified in XADD must be greater than 0-0",
                     ));
                 }
 
-                let mut max_sequence_for_ms_time = HashMap::new();
-                max_sequence_for_ms_time.insert(ms_time, sequence_number);
-
                 let inner_redis_stream = InnerRedisStream {
-                    items: command.data.clone(),
+                    items,
                     ms_time,
                     sequence_number,
                 };
 
-                broadcast_xadd(
-                    &command.stream_key,
-                    ms_time,
-                    sequence_number,
-                    command.data,
-                    sender,
-                )?;
-
-                let stream_id = inner_redis_stream.stream_id();
+                let stream_id = inner_redis_stream.id();
 
-                let redis_stream = RedisStream(vec![inner_redis_stream]);
+                let redis_stream = RedisStream::new(inner_redis_stream);
                 let item = DatabaseItem::Stream(redis_stream);
-                database.insert(command.stream_key, item);
+                database.insert(Arc::from(key), item);
+                self.mark_dirty(1);
 
                 Ok(stream_id)
             }
             Some(database_item) => match database_item {
                 DatabaseItem::Stream(ref mut existing_stream) => {
-                    let latest_inner = existing_stream.0.last().ok_or_else(|| {
-                        anyhow::anyhow!("Streams should always have at lest one datapoint")
-                    })?;
+                    let (last_ms_time, last_sequence_number) = existing_stream.last_id;
 
-                    let sequence_number =
-                        determine_sequence_number(command.sequence_number, ms_time, latest_inner);
+                    // Like redis, a clock that went backwards keeps generating ids
+                    // after the last one instead of failing.
+                    let ms_time = match ms_time {
+                        Some(ms_time) => ms_time,
+                        None => self.now().max(last_ms_time),
+                    };
+                    let sequence_number = determine_sequence_number(
+                        sequence_number,
+                        ms_time,
+                        existing_stream.last_id,
+                    );
 
                     if ms_time == 0 && sequence_number == 0 {
-                        return Err(anyhow::anyhow!(
-                            "ERR The ID specified in XADD must be greater than 0-0"
+                        return Err(RedisError::custom(
+                            "ERR The ID specified in XADD must be greater than 0-0",
                         ));
                     }
 
                     // Either the millisecond time or the sequence number
                     // must be greater than the last entry.
                     let is_okay = match ms_time {
-                        ms_time if ms_time < latest_inner.ms_time => false,
-                        ms_time if ms_time == latest_inner.ms_time => {
-                            sequence_number > latest_inner.sequence_number
+                        ms_time if ms_time < last_ms_time => false,
+                        ms_time if ms_time == last_ms_time => {
+                            sequence_number > last_sequence_number
                         }
                         _ => true,
                     };
 
                     if !is_okay {
-                        return Err(anyhow::anyhow!(
-                            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
-                        ));
+                        return Err(RedisError::custom("ERR The ID specified in XADD is equal or smaller than the target stream top item"));
                     }
 
                     let inner_redis_stream = InnerRedisStream {
-                        items: command.data.clone(),
+                        items,
                         ms_time,
                         sequence_number,
                     };
 
-                    // We want to transmit all the original values of the item so we have to clone them.
-                    // The alternative is to clone all items for the database in the streams before the block
-                    // (if present) occurs. This way we only have to clone a few items.
-
-                    broadcast_xadd(
-                        &command.stream_key,
-                        ms_time,
-                        sequence_number,
-                        command.data,
-                        sender,
-                    )?;
-
-                    let stream_id = inner_redis_stream.stream_id();
-                    existing_stream.0.push(inner_redis_stream);
+                    let stream_id = inner_redis_stream.id();
+                    existing_stream.push(inner_redis_stream);
+                    self.mark_dirty(1);
 
                     Ok(stream_id)
                 }
-                _ => Err(wrong_type()),
+                _ => Err(RedisError::WrongType),
             },
         }
     }
@@ -350,139 +1072,277 @@ impl Database {
         key: String,
         start: request::XRangeNumber,
         end: request::XRangeNumber,
-    ) -> Result<String, anyhow::Error> {
-        let database = self.0.read().unwrap();
-        let stream = match database.get(&key) {
-            None => return Ok(empty_string()),
+    ) -> Result<Value, RedisError> {
+        let database = self.keyspace.read().unwrap();
+        let stream = match database.get(key.as_str()) {
+            None => return Ok(Value::Null),
             Some(item) => match &item {
-                DatabaseItem::String(_) => anyhow::bail!(wrong_type_str()),
                 DatabaseItem::Stream(stream) => stream,
+                _ => return Err(RedisError::WrongType),
             },
         };
 
-        let mut inner_streams: Vec<&InnerRedisStream> = vec![];
-        let mut has_started: bool = false;
+        let inner_streams = entries_in_range(stream, &start, &end);
+        Ok(encoding::stream_value(inner_streams.as_slice()))
+    }
 
-        // TODO: Refactor this not to be such a mess - maybe function calls
-        for entry in stream.0.iter() {
-            if !has_started {
-                match start {
-                    request::XRangeNumber::Unspecified => {
-                        has_started = true;
-                    }
-                    request::XRangeNumber::Specified(ms_time, sequence_number) => match entry {
-                        entry if entry.ms_time < ms_time => continue,
-                        // If we are at the ms_time but not yet at the sequence number then ignore.
-                        entry
-                            if entry.ms_time == ms_time
-                                && entry.sequence_number < sequence_number =>
-                        {
-                            continue
-                        }
-                        _ => {
-                            has_started = true;
-                        }
-                    },
-                }
-            }
+    pub async fn read_from_streams(
+        &self,
+        block: Option<request::XReadBlock>,
+        read_command_streams: Vec<request::XReadCommandStream>,
+    ) -> Result<Value, RedisError> {
+        let block = match block {
+            None => return read_streams_sync(self, read_command_streams),
+            Some(block) => block,
+        };
 
-            match end {
-                request::XRangeNumber::Unspecified => {}
-                request::XRangeNumber::Specified(ms_time, sequence_number) => match entry {
-                    entry if entry.ms_time > ms_time => break,
-                    // Exceeding the sequence_number only matters if we are already at the
-                    // end's ms_time.
-                    entry
-                        if entry.ms_time == ms_time && entry.sequence_number > sequence_number =>
+        // Only entries added while the client is blocked count
+        let read_command_streams = self.skip_existing_entries(read_command_streams)?;
+        let keys = read_command_streams.iter().map(|s| s.key.clone()).collect();
+        let blocked = self.blocked.block_on(keys);
+
+        match block {
+            // Everything added before the timeout is returned together
+            request::XReadBlock::Limited(wait) => {
+                sleep(Duration::from_millis(wait)).await;
+                Ok(self
+                    .read_new_entries(&read_command_streams)?
+                    .unwrap_or(Value::Null))
+            }
+            request::XReadBlock::Unlimited => loop {
+                if let Some(entries) = self.read_new_entries(&read_command_streams)? {
+                    return Ok(entries);
+                }
+                blocked.wait().await;
+            },
+        }
+    }
+
+    /// Moves the start of every stream up to its last entry, so only entries
+    /// added from now on are read.
+    fn skip_existing_entries(
+        &self,
+        read_command_streams: Vec<request::XReadCommandStream>,
+    ) -> Result<Vec<request::XReadCommandStream>, RedisError> {
+        let database = self.keyspace.read().unwrap();
+
+        read_command_streams
+            .into_iter()
+            .map(|command_stream| {
+                let last_id = match database.get(command_stream.key.as_str()) {
+                    Some(DatabaseItem::Stream(stream)) => stream.last_id,
+                    Some(_) => return Err(RedisError::WrongType),
+                    None => return Ok(command_stream),
+                };
+
+                let start = match command_stream.start {
+                    request::XReadNumber::Specified(ms_time, sequence_number)
+                        if (ms_time, sequence_number) > last_id =>
                     {
-                        break
+                        command_stream.start
                     }
-                    _ => {}
-                },
+                    _ => request::XReadNumber::Specified(last_id.0, last_id.1),
+                };
+
+                Ok(request::XReadCommandStream {
+                    key: command_stream.key,
+                    start,
+                })
+            })
+            .collect()
+    }
+
+    /// Reads the entries after the start of each stream, leaving out the streams
+    /// without any. Returns `None` if there are none at all.
+    fn read_new_entries(
+        &self,
+        read_command_streams: &[request::XReadCommandStream],
+    ) -> Result<Option<Value>, RedisError> {
+        let database = self.keyspace.read().unwrap();
+
+        let mut streams: Vec<ReadStreamItem> = vec![];
+        for command_stream in read_command_streams.iter() {
+            let stream = match database.get(command_stream.key.as_str()) {
+                Some(DatabaseItem::Stream(stream)) => stream,
+                Some(_) => return Err(RedisError::WrongType),
+                None => continue,
+            };
+
+            let entries: Vec<&InnerRedisStream> = stream
+                .entries
+                .iter()
+                .filter(|entry| {
+                    stream_entry_greater_than_start(
+                        entry.ms_time,
+                        entry.sequence_number,
+                        &command_stream.start,
+                    )
+                })
+                .collect();
+
+            if !entries.is_empty() {
+                streams.push(ReadStreamItem {
+                    streams: entries,
+                    key: command_stream.key.to_string(),
+                });
             }
+        }
 
-            inner_streams.push(entry);
+        if streams.is_empty() {
+            return Ok(None);
         }
 
-        let encoded = encoding::encode_stream(inner_streams.as_slice());
-        Ok(encoded)
+        Ok(Some(encoding::streams_value(streams)))
     }
 
-    pub async fn read_from_streams(
-        &self,
-        block: Option<request::XReadBlock>,
-        read_command_streams: Vec<request::XReadCommandStream>,
-        receiver: Receiver<transmission::Transmission>,
-    ) -> Result<String, anyhow::Error> {
-        match block {
-            None => read_streams_sync(self, read_command_streams),
-            Some(request::XReadBlock::Unlimited) => {
-                read_streams_until_xadd(read_command_streams, receiver).await
-            }
-            Some(request::XReadBlock::Limited(wait)) => {
-                read_streams_after_limited_wait(wait, read_command_streams, receiver).await
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.write_keyspace().unwrap().remove(key);
+        if removed.is_some() {
+            self.mark_dirty(1);
+            self.hooks.notify(key, KeyEvent::Delete);
+        }
+
+        removed.is_none()
+    }
+
+    /// Called once a key's expiry timer fires. Replicas leave the key alone
+    /// until the master's DEL arrives.
+    fn expire(&self, key: &str, deadline: u128) {
+        if self.expirations.passive.load(Ordering::SeqCst) {
+            return;
+        }
+
+        {
+            let mut db = self.write_keyspace().unwrap();
+            // The key may have been set again since this timer was started
+            if db.expires_at(key) != Some(deadline) {
+                return;
             }
+            db.remove(key);
+        }
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Expire);
+
+        if let Some(listener) = self.expirations.listener.lock().unwrap().as_ref() {
+            let _ = listener.send((self.index, key.to_string()));
         }
     }
 
-    pub fn remove(&self, key: &str) -> bool {
-        self.0.write().unwrap().remove(key).is_none()
+    /// Stops keys from being expired locally, for when they are
+    /// removed through the replication stream instead.
+    pub fn set_passive_expiry(&self, passive: bool) {
+        self.expirations.passive.store(passive, Ordering::SeqCst);
+    }
+
+    /// Every key removed by its expiry timer from now on is sent to the receiver,
+    /// along with the number of the database it was in.
+    pub fn expired_keys(&self) -> UnboundedReceiver<(usize, String)> {
+        let (tx, rx) = unbounded_channel();
+        *self.expirations.listener.lock().unwrap() = Some(tx);
+        rx
     }
 
     pub fn update_expiration(
         &self,
         key: &str,
         expiration: CommandExpiration,
-    ) -> Result<String, anyhow::Error> {
-        let mut db = self.0.write().map_err(|e| anyhow::anyhow!("{}", e))?;
+    ) -> Result<Option<String>, RedisError> {
+        let mut db = self.write_keyspace()?;
 
-        if let Some(item) = db.get_mut(key) {
+        if let Some(item) = db.get(key) {
             match item {
                 DatabaseItem::String(item) => {
                     let data = item.data();
 
-                    item.abort_deletion_process();
-
-                    let duration = match expiration {
-                        CommandExpiration::None => None,
-                        CommandExpiration::Other => None,
-                        CommandExpiration::Expiry(duration) => Some(duration),
+                    let expires_at = match expiration {
+                        // The running timer, if any, is left to fire
+                        CommandExpiration::Keep => return Ok(Some(data)),
+                        CommandExpiration::Persist => None,
+                        CommandExpiration::After(duration) => {
+                            Some(self.now() + duration.as_millis())
+                        }
+                        CommandExpiration::At(deadline) => Some(deadline),
                     };
+                    db.set_expires_at(key, expires_at);
+                    self.mark_dirty(1);
+                    drop(db);
 
-                    if let Some(duration) = duration {
-                        let database = self.clone();
-                        let key = key.to_string();
-                        let join_handle = spawn(async move {
-                            sleep(duration).await;
-                            database.remove(&key);
-                        });
-
-                        item.set_cancellation(join_handle);
+                    if let Some(expires_at) = expires_at {
+                        self.schedule_expiry(key.to_string(), expires_at);
                     }
 
-                    Ok(data)
+                    Ok(Some(data))
                 }
-                _ => anyhow::bail!(wrong_type_str()),
+                _ => Err(RedisError::WrongType),
             }
         } else {
-            Ok(empty_string())
+            Ok(None)
         }
     }
 
-    pub fn get_remove(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
-        let mut db = self.0.write().map_err(|e| anyhow::anyhow!("{}", e))?;
+    /// Has the key expire at the unix timestamp in milliseconds, if it exists and
+    /// `condition` allows it. A deadline that has already passed deletes the key.
+    /// Returns whether it did either.
+    pub fn set_expiration(
+        &self,
+        key: &str,
+        expires_at: u128,
+        condition: ExpireCondition,
+    ) -> Result<bool, RedisError> {
+        let deleted = {
+            let mut db = self.write_keyspace()?;
+            if !db.contains_key(key) || !condition.allows(db.expires_at(key), expires_at) {
+                return Ok(false);
+            }
+            if expires_at <= self.now() {
+                db.remove(key);
+                true
+            } else {
+                db.set_expires_at(key, Some(expires_at));
+                false
+            }
+        };
+        self.mark_dirty(1);
+
+        if deleted {
+            self.hooks.notify(key, KeyEvent::Delete);
+        } else {
+            self.schedule_expiry(key.to_string(), expires_at);
+        }
 
-        if let Some(item) = db.get_mut(key) {
+        Ok(true)
+    }
+
+    /// Takes away the key's TTL, stopping the timer that would remove it. Returns
+    /// whether it had one.
+    pub fn persist(&self, key: &str) -> Result<bool, RedisError> {
+        {
+            let mut db = self.write_keyspace()?;
+            if db.expires_at(key).is_none() {
+                return Ok(false);
+            }
+            db.set_expires_at(key, None);
+        }
+        self.mark_dirty(1);
+
+        Ok(true)
+    }
+
+    pub fn get_remove(&self, key: &str) -> Result<Option<String>, RedisError> {
+        let mut db = self.write_keyspace()?;
+
+        if let Some(item) = db.get(key) {
             match item {
                 DatabaseItem::String(item) => {
                     let data = item.data();
 
-                    item.abort_deletion_process();
-
                     db.remove(key);
+                    drop(db);
+                    self.mark_dirty(1);
+                    self.hooks.notify(key, KeyEvent::Delete);
                     Ok(Some(data))
                 }
-                _ => anyhow::bail!(wrong_type_str()),
+                _ => Err(RedisError::WrongType),
             }
         } else {
             Ok(None)
@@ -490,221 +1350,1574 @@ impl Database {
     }
 
     pub fn remove_multiple(&self, keys: Vec<String>) -> usize {
-        let mut db = self.0.write().unwrap();
-        keys.iter().fold(0, |acc, key| {
-            if let Some(item) = db.get_mut(key) {
-                item.clean_up();
-                db.remove(key);
-                acc + 1
+        let mut db = self.write_keyspace().unwrap();
+        let removed: Vec<&String> = keys
+            .iter()
+            .filter(|key| db.remove(key.as_str()).is_some())
+            .collect();
+        drop(db);
+        self.mark_dirty(removed.len() as u64);
+
+        for key in removed.iter() {
+            self.hooks.notify(key, KeyEvent::Delete);
+        }
+        removed.len()
+    }
+
+    /// Like `remove_multiple`, but the keys' values are dropped, and their timers
+    /// stopped, on a blocking task, so a large value doesn't hold up the caller.
+    pub fn unlink(&self, keys: Vec<String>) -> usize {
+        let mut db = self.write_keyspace().unwrap();
+        let mut unlinked = vec![];
+        let mut entries = vec![];
+        for key in keys {
+            if let Some(entry) = db.take(&key) {
+                unlinked.push(key);
+                entries.push(entry);
+            }
+        }
+        drop(db);
+        self.mark_dirty(unlinked.len() as u64);
+
+        for key in unlinked.iter() {
+            self.hooks.notify(key, KeyEvent::Delete);
+        }
+        if !entries.is_empty() {
+            self.tasks.spawn_blocking("unlink", move || {
+                for mut entry in entries {
+                    entry.stop_timer();
+                }
+            });
+        }
+        unlinked.len()
+    }
+
+    /// Moves the key to database `index` with its deadline, unless it's already there.
+    /// Returns whether it was moved.
+    pub fn move_key(&self, key: &str, index: usize) -> bool {
+        let destination = self.select(index);
+        if index == self.index {
+            return false;
+        }
+
+        let expires_at = {
+            // Locked in order, like everything that holds more than one keyspace
+            let (mut source, mut target) = if self.index < index {
+                let source = KeyspaceGuard(self.keyspace.write().unwrap());
+                (source, KeyspaceGuard(destination.keyspace.write().unwrap()))
             } else {
-                acc
+                let target = KeyspaceGuard(destination.keyspace.write().unwrap());
+                (KeyspaceGuard(self.keyspace.write().unwrap()), target)
+            };
+            if target.contains_key(key) {
+                return false;
             }
-        })
+            let Some(entry) = source.take(key) else {
+                return false;
+            };
+            let expires_at = entry.expires_at;
+            target.put(Arc::from(key), entry);
+            expires_at
+        };
+
+        if let Some(expires_at) = expires_at {
+            destination.schedule_expiry(key.to_string(), expires_at);
+        }
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Delete);
+        self.hooks.notify(key, KeyEvent::Set);
+        self.blocked.signal(key);
+        true
+    }
+
+    /// Exchanges the keys of two databases, so connections that had selected one
+    /// of them now see what was in the other. Keys keep their deadlines, their
+    /// timers starting again for the database they're in now.
+    pub fn swap(&self, first: usize, second: usize) {
+        if first == second {
+            return;
+        }
+
+        let (low, high) = (first.min(second), first.max(second));
+        let low = self.select(low);
+        let high = self.select(high);
+        {
+            let mut low_keyspace = low.keyspace.write().unwrap();
+            let mut high_keyspace = high.keyspace.write().unwrap();
+            std::mem::swap(&mut *low_keyspace, &mut *high_keyspace);
+        }
+
+        low.restart_timers();
+        high.restart_timers();
+        self.mark_dirty(1);
+        // Clients blocked in either database may now have what they're waiting for
+        self.blocked.signal_all();
+    }
+
+    /// Starts the expiry timer of every key with a deadline over, for keys that
+    /// have just been moved here from another database.
+    fn restart_timers(&self) {
+        let deadlines: Vec<(String, u128)> = {
+            let keyspace = self.keyspace.read().unwrap();
+            keyspace.stop_timers();
+            keyspace
+                .entries()
+                .filter_map(|(key, _, expires_at)| Some((key.to_string(), expires_at?)))
+                .collect()
+        };
+
+        for (key, expires_at) in deadlines {
+            self.schedule_expiry(key, expires_at);
+        }
+    }
+
+    /// Removes every key in this database, stopping their expiry timers so none of
+    /// them fire on the empty keyspace that takes their place. With `asynchronously`,
+    /// the old keyspace is freed on a blocking task rather than before returning.
+    /// Returns how many keys there were.
+    pub fn flush(&self, asynchronously: bool) -> usize {
+        self.flush_keyspace(&self.keyspace, asynchronously)
+    }
+
+    /// Like `flush`, for every database.
+    pub fn flush_all(&self, asynchronously: bool) -> usize {
+        self.keyspaces
+            .all()
+            .iter()
+            .map(|(_, keyspace)| self.flush_keyspace(keyspace, asynchronously))
+            .sum()
+    }
+
+    fn flush_keyspace(&self, keyspace: &KeyspaceLock, asynchronously: bool) -> usize {
+        let empty = Arc::new(Keyspace::new(self.clock.clone()));
+        let previous = std::mem::replace(&mut *keyspace.write().unwrap(), empty);
+        previous.stop_timers();
+
+        let flushed = previous.len();
+        self.mark_dirty(flushed as u64);
+        for key in previous.keys() {
+            self.hooks.notify(key, KeyEvent::Delete);
+        }
+        if asynchronously {
+            self.tasks.spawn_blocking("flush", move || drop(previous));
+        }
+        flushed
     }
 
-    pub fn adjust_value_by_int(&self, key: &str, adjustment: i64) -> Result<String, anyhow::Error> {
-        let mut db = self.0.write().unwrap();
+    /// Returns the new value, which stays a float if it was one.
+    pub fn adjust_value_by_int(
+        &self,
+        key: &str,
+        adjustment: i64,
+    ) -> Result<StringValue, RedisError> {
+        let mut db = self.write_keyspace().unwrap();
         let value = match db.get_mut(key) {
             Some(item) => match item {
                 DatabaseItem::String(redis_string) => {
-                    let value = if redis_string.data.find('.').is_some() {
-                        adjust_float_value_by_int(&redis_string.data, adjustment)
-                    } else {
-                        adjust_int_value_by_int(&redis_string.data, adjustment)
-                    }?;
+                    let value = match &redis_string.data {
+                        StringValue::Int(value) => value
+                            .checked_add(adjustment)
+                            .map(StringValue::Int)
+                            .ok_or_else(|| {
+                                RedisError::custom("ERR increment or decrement would overflow")
+                            })?,
+                        StringValue::Raw(data) if data.contains('.') => {
+                            StringValue::new(adjust_float_value_by_int(data, adjustment)?)
+                        }
+                        StringValue::Raw(_) => return Err(RedisError::NotAnInteger),
+                    };
 
-                    redis_string.data.clone_from(&value);
+                    redis_string.data = value.clone();
 
                     Ok(value)
                 }
-                _ => Err(anyhow::anyhow!(wrong_type_str())
-                    .context(format!("Item at key {} is not a string", key))),
+                _ => Err(RedisError::WrongType),
             },
             None => {
-                let data = RedisString::new(adjustment.to_string(), None);
-                db.insert(key.to_string(), DatabaseItem::String(data));
-                Ok(adjustment.to_string())
+                let data = RedisString::new(adjustment.to_string());
+                db.insert(Arc::from(key), DatabaseItem::String(data));
+                Ok(StringValue::Int(adjustment))
             }
         }?;
+        drop(db);
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Set);
 
-        let encoded = if value.find('.').is_some() {
-            encoding::bulk_string(&value)
-        } else {
-            encoding::encode_integer(value.parse::<i64>().unwrap())
-        };
-        Ok(encoded)
+        Ok(value)
     }
 
-    pub fn adjust_value_by_float(
-        &self,
-        key: &str,
-        adjustment: f64,
-    ) -> Result<String, anyhow::Error> {
-        let mut db = self.0.write().unwrap();
+    pub fn adjust_value_by_float(&self, key: &str, adjustment: f64) -> Result<String, RedisError> {
+        let mut db = self.write_keyspace().unwrap();
         let value = match db.get_mut(key) {
             Some(item) => match item {
                 DatabaseItem::String(redis_string) => {
-                    let value = if redis_string.data.find('.').is_some() {
-                        adjust_float_value_by_float(&redis_string.data, adjustment)
-                    } else {
-                        adjust_int_value_by_float(&redis_string.data, adjustment)
-                    }?;
+                    let value = match &redis_string.data {
+                        StringValue::Int(value) => (*value as f64 + adjustment).to_string(),
+                        StringValue::Raw(data) if data.contains('.') => {
+                            adjust_float_value_by_float(data, adjustment)?
+                        }
+                        StringValue::Raw(_) => return Err(RedisError::NotAnInteger),
+                    };
 
-                    redis_string.data.clone_from(&value);
+                    redis_string.data = StringValue::new(value.clone());
 
                     Ok(value)
                 }
-                _ => Err(anyhow::anyhow!(wrong_type_str())
-                    .context(format!("Item at key {} is not a string", key))),
+                _ => Err(RedisError::WrongType),
             },
             None => {
-                let redis_string = RedisString::new(adjustment.to_string(), None);
-                db.insert(key.to_string(), DatabaseItem::String(redis_string));
+                let redis_string = RedisString::new(adjustment.to_string());
+                db.insert(Arc::from(key), DatabaseItem::String(redis_string));
                 Ok(adjustment.to_string())
             }
         }?;
+        drop(db);
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Set);
+
+        Ok(value)
+    }
+
+    /// Adds `value` to the end of the string at `key`, creating it if need be, and
+    /// returns the new length in bytes. The key keeps its expiration.
+    pub fn append(&self, key: &str, value: &str) -> Result<usize, RedisError> {
+        let mut db = self.write_keyspace().unwrap();
+        let len = match db.get_mut(key) {
+            Some(DatabaseItem::String(redis_string)) => {
+                let mut data = redis_string.data();
+                data.push_str(value);
+                let len = data.len();
+                redis_string.data = StringValue::new(data);
+                len
+            }
+            Some(_) => return Err(RedisError::WrongType),
+            None => {
+                let redis_string = RedisString::new(value.to_string());
+                db.insert(Arc::from(key), DatabaseItem::String(redis_string));
+                value.len()
+            }
+        };
+        drop(db);
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Set);
+
+        Ok(len)
+    }
+
+    /// Writes `value` over the string at `key` from `offset` bytes in, padding it with
+    /// zero bytes first if it's shorter than that, and returns the new length. A
+    /// missing key is created unless there's nothing to write. The key keeps its
+    /// expiration. Strings are kept as UTF-8, so writing over part of a character is
+    /// an error.
+    pub fn set_range(&self, key: &str, offset: usize, value: &str) -> Result<usize, RedisError> {
+        if offset.saturating_add(value.len()) > MAX_STRING_LEN {
+            return Err(RedisError::custom(
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+            ));
+        }
+
+        let mut db = self.write_keyspace().unwrap();
+        let current = match db.get(key) {
+            Some(DatabaseItem::String(redis_string)) => redis_string.data(),
+            Some(_) => return Err(RedisError::WrongType),
+            None => String::new(),
+        };
+        if value.is_empty() {
+            return Ok(current.len());
+        }
+
+        let data = overwrite_string(&current, offset, value).ok_or(RedisError::SplitCharacter)?;
+        let len = data.len();
+
+        match db.get_mut(key) {
+            Some(DatabaseItem::String(redis_string)) => redis_string.data = StringValue::new(data),
+            _ => {
+                let redis_string = RedisString::new(data);
+                db.insert(Arc::from(key), DatabaseItem::String(redis_string));
+            }
+        }
+        drop(db);
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Set);
+
+        Ok(len)
+    }
+
+    /// Pushes the values onto one end of the list at `key` one after the other,
+    /// creating it if need be, and returns the new length. The key keeps its
+    /// expiration.
+    pub fn push(
+        &self,
+        key: &str,
+        values: Vec<String>,
+        end: request::ListEnd,
+    ) -> Result<usize, RedisError> {
+        let mut db = self.write_keyspace()?;
+        let len = match db.get_mut(key) {
+            Some(DatabaseItem::List(list)) => {
+                push_values(list, values, end);
+                list.len()
+            }
+            Some(_) => return Err(RedisError::WrongType),
+            None => {
+                let mut list = RedisList::default();
+                push_values(&mut list, values, end);
+                let len = list.len();
+                db.insert(Arc::from(key), DatabaseItem::List(list));
+                len
+            }
+        };
+        drop(db);
+        self.mark_dirty(1);
+        // Blocked pops are only woken up once the lock is released.
+        self.blocked.signal(key);
+        self.hooks.notify(key, KeyEvent::Set);
+
+        Ok(len)
+    }
+
+    /// Pops up to `count` values off one end of the list at `key`, in the order
+    /// they were popped, or `None` if there's no such key. A list left empty is
+    /// removed.
+    pub fn pop(
+        &self,
+        key: &str,
+        count: usize,
+        end: request::ListEnd,
+    ) -> Result<Option<Vec<String>>, RedisError> {
+        self.update_list(key, |list| {
+            let popped: Vec<String> = std::iter::from_fn(|| match end {
+                request::ListEnd::Left => list.pop_front(),
+                request::ListEnd::Right => list.pop_back(),
+            })
+            .take(count)
+            .collect();
+            let changed = !popped.is_empty();
+            Ok((popped, changed))
+        })
+    }
+
+    /// Pops up to `count` values off the first of the keys holding a list, returning
+    /// them with the key, or `None` if none of them do.
+    pub fn pop_first(
+        &self,
+        keys: &[String],
+        count: usize,
+        end: request::ListEnd,
+    ) -> Result<Option<(String, Vec<String>)>, RedisError> {
+        for key in keys {
+            match self.pop(key, count, end)? {
+                Some(popped) if !popped.is_empty() => return Ok(Some((key.clone(), popped))),
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Pops a value off one end of the list at `source` and pushes it onto one end of
+    /// the list at `destination`, creating it if need be, under one lock. Returns the
+    /// value, or `None` if there's no source. The source and destination can be the
+    /// same list, to rotate it.
+    pub fn move_list_value(
+        &self,
+        source: &str,
+        destination: &str,
+        from: request::ListEnd,
+        to: request::ListEnd,
+    ) -> Result<Option<String>, RedisError> {
+        let mut db = self.write_keyspace()?;
+        match db.get(source) {
+            Some(DatabaseItem::List(_)) => {}
+            Some(_) => return Err(RedisError::WrongType),
+            None => return Ok(None),
+        }
+        if !matches!(db.get(destination), None | Some(DatabaseItem::List(_))) {
+            return Err(RedisError::WrongType);
+        }
+
+        let Some(DatabaseItem::List(list)) = db.get_mut(source) else {
+            return Ok(None);
+        };
+        let value = match from {
+            request::ListEnd::Left => list.pop_front(),
+            request::ListEnd::Right => list.pop_back(),
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        // A list rotated onto itself is kept, and with it its expiration
+        let source_event = match list.is_empty() && source != destination {
+            true => {
+                db.remove(source);
+                KeyEvent::Delete
+            }
+            false => KeyEvent::Set,
+        };
+
+        match db.get_mut(destination) {
+            Some(DatabaseItem::List(list)) => push_values(list, vec![value.clone()], to),
+            _ => {
+                let list = RedisList::from_iter([value.clone()]);
+                db.insert(Arc::from(destination), DatabaseItem::List(list));
+            }
+        }
+        drop(db);
+        self.mark_dirty(1);
+        self.blocked.signal(destination);
+        self.hooks.notify(source, source_event);
+        self.hooks.notify(destination, KeyEvent::Set);
+
+        Ok(Some(value))
+    }
+
+    /// Waits until one of the keys holds a list or the deadline passes, and returns
+    /// whether one does.
+    pub async fn wait_for_list(
+        &self,
+        keys: &[String],
+        deadline: Option<Instant>,
+    ) -> Result<bool, RedisError> {
+        let blocked = self.blocked.block_on(keys.to_vec());
+        loop {
+            if self.has_list(keys)? {
+                return Ok(true);
+            }
+            match deadline {
+                Some(deadline) => {
+                    if timeout_at(deadline, blocked.wait()).await.is_err() {
+                        return self.has_list(keys);
+                    }
+                }
+                None => blocked.wait().await,
+            }
+        }
+    }
+
+    /// Whether one of the keys holds a list. They're checked in order, like a pop
+    /// would, so a key holding something else before the first list is an error.
+    pub fn has_list(&self, keys: &[String]) -> Result<bool, RedisError> {
+        let database = self.keyspace.read()?;
+        for key in keys {
+            match database.get(key) {
+                Some(DatabaseItem::List(_)) => return Ok(true),
+                Some(_) => return Err(RedisError::WrongType),
+                None => {}
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Inserts `value` next to the first occurrence of `pivot` in the list at `key`
+    /// and returns the new length, or `None` if the pivot isn't in the list. A
+    /// missing key is left alone and has a length of 0.
+    pub fn insert_into_list(
+        &self,
+        key: &str,
+        position: request::ListPosition,
+        pivot: &str,
+        value: String,
+    ) -> Result<Option<usize>, RedisError> {
+        let len = self.update_list(key, |list| {
+            let Some(index) = list.position(pivot) else {
+                return Ok((None, false));
+            };
+            let index = match position {
+                request::ListPosition::Before => index,
+                request::ListPosition::After => index + 1,
+            };
+            list.insert(index, value);
+            Ok((Some(list.len()), true))
+        })?;
+
+        Ok(len.unwrap_or(Some(0)))
+    }
+
+    /// Replaces the value at `index` in the list at `key`, counting back from the
+    /// end if it's negative.
+    pub fn set_list_index(&self, key: &str, index: i64, value: String) -> Result<(), RedisError> {
+        self.update_list(key, |list| {
+            let index = match index < 0 {
+                true => list.len() as i64 + index,
+                false => index,
+            };
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| list.set(index, value))
+                .ok_or_else(|| RedisError::custom("ERR index out of range"))?;
+            Ok(((), true))
+        })?
+        .ok_or(RedisError::NoSuchKey)
+    }
+
+    /// Removes up to `count` occurrences of `value` from the list at `key`, from the
+    /// head if `count` is positive, the tail if it's negative and all of them if it's
+    /// 0, and returns how many were removed.
+    pub fn remove_from_list(
+        &self,
+        key: &str,
+        count: i64,
+        value: &str,
+    ) -> Result<usize, RedisError> {
+        let removed = self.update_list(key, |list| {
+            let limit = match count {
+                0 => usize::MAX,
+                count => count.unsigned_abs().try_into().unwrap_or(usize::MAX),
+            };
+            let removed = list.remove_matching(value, limit, count < 0);
+            Ok((removed, removed > 0))
+        })?;
+
+        Ok(removed.unwrap_or(0))
+    }
+
+    /// Trims the list at `key` down to the values from `start` to `end`, both
+    /// inclusive and counted like LRANGE, removing it if none are left.
+    pub fn trim_list(&self, key: &str, start: i64, end: i64) -> Result<(), RedisError> {
+        self.update_list(key, |list| {
+            let len = list.len();
+            let range = byte_range(len, start, end);
+            list.trim(range.start, range.end);
+            Ok(((), list.len() < len))
+        })?;
+
+        Ok(())
+    }
+
+    /// Calls `f` with the list at `key`, or returns `None` if there's no such key.
+    /// Along with its result `f` says whether it changed the list, and a list it
+    /// leaves empty is removed.
+    fn update_list<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut RedisList) -> Result<(T, bool), RedisError>,
+    ) -> Result<Option<T>, RedisError> {
+        let mut db = self.write_keyspace()?;
+        let list = match db.get_mut(key) {
+            Some(DatabaseItem::List(list)) => list,
+            Some(_) => return Err(RedisError::WrongType),
+            None => return Ok(None),
+        };
+
+        let (result, changed) = f(list)?;
+        let event = match list.is_empty() {
+            true => {
+                db.remove(key);
+                KeyEvent::Delete
+            }
+            false => KeyEvent::Set,
+        };
+        drop(db);
+        if changed {
+            self.mark_dirty(1);
+            self.hooks.notify(key, event);
+        }
+
+        Ok(Some(result))
+    }
+
+    /// The length of the list at `key`, which is 0 if there's no such key.
+    pub fn list_len(&self, key: &str) -> Result<usize, RedisError> {
+        let database = self.keyspace.read()?;
+        match database.get(key) {
+            Some(DatabaseItem::List(list)) => Ok(list.len()),
+            Some(_) => Err(RedisError::WrongType),
+            None => Ok(0),
+        }
+    }
+
+    /// How many bytes of values each chunk of the list at `key` holds, front to back,
+    /// or `None` if there's no such key.
+    pub fn list_chunk_sizes(&self, key: &str) -> Result<Option<Vec<usize>>, RedisError> {
+        let database = self.keyspace.read()?;
+        match database.get(key) {
+            Some(DatabaseItem::List(list)) => Ok(Some(list.chunk_sizes().collect())),
+            Some(_) => Err(RedisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// The values of the list at `key` from `start` to `end`, both inclusive. Like
+    /// GETRANGE, negative indexes count back from the end and the range is clamped
+    /// to the list.
+    pub fn list_range(&self, key: &str, start: i64, end: i64) -> Result<Vec<String>, RedisError> {
+        let database = self.keyspace.read()?;
+        match database.get(key) {
+            Some(DatabaseItem::List(list)) => {
+                let range = byte_range(list.len(), start, end);
+                Ok(list.range(range.start, range.end).cloned().collect())
+            }
+            Some(_) => Err(RedisError::WrongType),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Sets the fields of the hash at `key` to their values, creating it if need be,
+    /// and returns how many of the fields are new. The key keeps its expiration.
+    pub fn set_hash_fields(
+        &self,
+        key: &str,
+        pairs: Vec<(String, String)>,
+    ) -> Result<usize, RedisError> {
+        let mut db = self.write_keyspace()?;
+        let hash = match db.get_mut(key) {
+            Some(DatabaseItem::Hash(hash)) => hash,
+            Some(_) => return Err(RedisError::WrongType),
+            None => {
+                db.insert(Arc::from(key), DatabaseItem::Hash(RedisHash::default()));
+                let Some(DatabaseItem::Hash(hash)) = db.get_mut(key) else {
+                    unreachable!("the hash was just inserted");
+                };
+                hash
+            }
+        };
+
+        let mut added = 0;
+        for (field, value) in pairs {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+        drop(db);
+        self.mark_dirty(1);
+        self.hooks.notify(key, KeyEvent::Set);
+
+        Ok(added)
+    }
+
+    /// Removes the fields from the hash at `key` and returns how many of them it had.
+    /// A hash left empty is removed.
+    pub fn delete_hash_fields(&self, key: &str, fields: &[String]) -> Result<usize, RedisError> {
+        let mut db = self.write_keyspace()?;
+        let hash = match db.get_mut(key) {
+            Some(DatabaseItem::Hash(hash)) => hash,
+            Some(_) => return Err(RedisError::WrongType),
+            None => return Ok(0),
+        };
+
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(field).is_some())
+            .count();
+        let event = match hash.is_empty() {
+            true => {
+                db.remove(key);
+                KeyEvent::Delete
+            }
+            false => KeyEvent::Set,
+        };
+        drop(db);
+        if removed > 0 {
+            self.mark_dirty(1);
+            self.hooks.notify(key, event);
+        }
+
+        Ok(removed)
+    }
+
+    /// Calls `f` with the hash at `key`, if there is one.
+    pub fn read_hash<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&RedisHash) -> T,
+    ) -> Result<Option<T>, RedisError> {
+        let database = self.keyspace.read()?;
+        match database.get(key) {
+            Some(DatabaseItem::Hash(hash)) => Ok(Some(f(hash))),
+            Some(_) => Err(RedisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Calls `f` with the value at the key, if there is one.
+    pub fn read_module<V: ModuleValue, T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&V) -> T,
+    ) -> Result<Option<T>, RedisError> {
+        let database = self.keyspace.read()?;
+        match database.get(key) {
+            Some(item) => V::from_item(item)
+                .map(f)
+                .ok_or(RedisError::WrongType)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Calls `f` with the value at the key, or `None` if the key doesn't exist.
+    /// Whatever value `f` leaves behind is stored, and if it leaves none the key
+    /// is removed. `f` shouldn't change the value when it fails.
+    pub fn update_module<V: ModuleValue, T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Option<V>) -> Result<T, RedisError>,
+    ) -> Result<T, RedisError> {
+        let mut db = self.write_keyspace()?;
+        let mut value = match db.get_mut(key) {
+            Some(item) => Some(std::mem::take(
+                V::from_item_mut(item).ok_or(RedisError::WrongType)?,
+            )),
+            None => None,
+        };
 
-        Ok(encoding::bulk_string(&value))
+        let existed = value.is_some();
+        let result = f(&mut value);
+        let changed = existed || value.is_some();
+        let event = match value {
+            Some(value) => {
+                db.insert(Arc::from(key), value.into_item());
+                KeyEvent::Set
+            }
+            None => {
+                db.remove(key);
+                KeyEvent::Delete
+            }
+        };
+        drop(db);
+        if result.is_ok() && changed {
+            self.mark_dirty(1);
+            self.hooks.notify(key, event);
+        }
+
+        result
+    }
+
+    pub fn random_key(&self) -> Result<Option<Arc<str>>, RedisError> {
+        let database = self.keyspace.read()?;
+        Ok(database.random_key(self.now()))
     }
 
-    pub fn keys(&self) -> Result<Vec<String>, anyhow::Error> {
-        // TODO: Figure out how to do this without cloning the keys
+    /// Every key in the keyspace. Only the reference counts are bumped,
+    /// the keys themselves aren't copied.
+    pub fn keys(&self) -> Result<Vec<Arc<str>>, RedisError> {
         let keys = {
-            let lock = self.0.read().map_err(|e| anyhow::anyhow!("{}", e))?;
-            lock.keys().map(|k| k.to_string()).collect()
+            let lock = self.keyspace.read()?;
+            lock.keys().cloned().collect()
         };
 
         Ok(keys)
     }
 
+    /// Calls `f` with every key that hasn't expired, its value and the unix timestamp
+    /// in milliseconds it expires at. It goes over a point in time view of the
+    /// keyspace, like a snapshot, so writes carry on meanwhile.
+    pub(crate) fn for_each_item(
+        &self,
+        mut f: impl FnMut(&str, &DatabaseItem, Option<u128>) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let keyspace = {
+            let database = self.keyspace.read().map_err(|e| anyhow::anyhow!("{}", e))?;
+            Arc::clone(&database)
+        };
+
+        let now = self.now();
+        for (key, item, expires_at) in keyspace.entries() {
+            if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                continue;
+            }
+            f(key, item, expires_at)?;
+        }
+
+        Ok(())
+    }
+
+    /// The key's value serialized the way DUMP does it: its RDB encoding followed
+    /// by the RDB version and a CRC64 of everything before it. The TTL isn't included.
+    pub fn dump(&self, key: &str) -> Option<Vec<u8>> {
+        let database = self.keyspace.read().unwrap();
+        Some(dump_item(database.get(key)?))
+    }
+
+    /// Creates the key from what `dump` serialized. Like RESTORE it fails if the
+    /// key already exists, unless it's meant to be replaced, and a deadline
+    /// that has already passed leaves the key deleted.
+    pub fn restore(
+        &self,
+        key: &str,
+        payload: &[u8],
+        expires_at: Option<u128>,
+        replace: bool,
+    ) -> Result<(), RedisError> {
+        let item = read_dump(payload)
+            .map_err(|_| RedisError::custom("ERR DUMP payload version or checksum are wrong"))?;
+        self.restore_item(key, item, expires_at, replace)
+    }
+
+    /// Like `restore`, with the value already read.
+    pub(crate) fn restore_item(
+        &self,
+        key: &str,
+        item: DatabaseItem,
+        expires_at: Option<u128>,
+        replace: bool,
+    ) -> Result<(), RedisError> {
+        let event = {
+            let mut db = self.write_keyspace()?;
+            if !replace && db.contains_key(key) {
+                return Err(RedisError::custom(
+                    "BUSYKEY Target key name already exists.",
+                ));
+            }
+
+            let now = self.now();
+            let (replaced, event) = match expires_at {
+                Some(expires_at) if expires_at <= now => (db.remove(key), KeyEvent::Delete),
+                _ => {
+                    let replaced = db.insert(Arc::from(key), item);
+                    db.set_expires_at(key, expires_at);
+                    (replaced, KeyEvent::Set)
+                }
+            };
+            // Restoring an already expired key over nothing changes nothing
+            (replaced.is_some() || event == KeyEvent::Set).then_some(event)
+        };
+        self.mark_dirty(1);
+        if let Some(event) = event {
+            self.hooks.notify(key, event);
+        }
+        // A restored list can be popped by whoever's waiting on it
+        if event == Some(KeyEvent::Set) {
+            self.blocked.signal(key);
+        }
+
+        if let Some(expires_at) = expires_at {
+            self.schedule_expiry(key.to_string(), expires_at);
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path, compress: bool) -> Result<(), anyhow::Error> {
+        let snapshot = self.snapshot()?;
+        self.write_snapshot(snapshot, path, compress)
+    }
+
+    /// Saves on a blocking thread so the caller can carry on straight away. The
+    /// snapshot is taken before returning so it holds everything written so far.
+    /// Only one background save can run at a time.
+    pub fn background_save(&self, path: PathBuf, compress: bool) -> Result<(), anyhow::Error> {
+        if self
+            .save_status
+            .background_save
+            .swap(true, Ordering::SeqCst)
+        {
+            anyhow::bail!("ERR Background save already in progress");
+        }
+
+        let snapshot = match self.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                self.save_status
+                    .background_save
+                    .store(false, Ordering::SeqCst);
+                return Err(e);
+            }
+        };
+
+        let database = self.clone();
+        self.tasks.spawn_blocking("background save", move || {
+            let result = database.write_snapshot(snapshot, &path, compress);
+            match &result {
+                Ok(_) => println!("Background saving terminated with success"),
+                Err(e) => eprintln!("Background saving error: {}", e),
+            }
+
+            database
+                .save_status
+                .last_background_save_failed
+                .store(result.is_err(), Ordering::SeqCst);
+            database
+                .save_status
+                .background_save
+                .store(false, Ordering::SeqCst);
+        });
+
+        Ok(())
+    }
+
+    pub fn is_background_saving(&self) -> bool {
+        self.save_status.background_save.load(Ordering::SeqCst)
+    }
+
+    pub fn last_background_save_ok(&self) -> bool {
+        !self
+            .save_status
+            .last_background_save_failed
+            .load(Ordering::SeqCst)
+    }
+
+    pub fn to_rdb(&self, compress: bool) -> Result<Vec<u8>, anyhow::Error> {
+        self.snapshot()?.to_rdb(compress)
+    }
+
+    /// Takes a point in time view of every database. The locks are only held long
+    /// enough to clone the pointers, so writes carry on while the snapshot is serialized.
+    pub fn snapshot(&self) -> Result<Snapshot, anyhow::Error> {
+        Ok(Snapshot {
+            keyspaces: self.keyspaces()?,
+            changes: self.dirty(),
+            generation: self.save_status.snapshots.fetch_add(1, Ordering::SeqCst) + 1,
+            taken_at: self.now(),
+        })
+    }
+
+    /// The keyspace of every database that has been made, all read under the
+    /// same locks so they're from the same point in time. The locks are taken
+    /// in order, like everything that holds more than one of them does.
+    fn keyspaces(&self) -> Result<Vec<(usize, Arc<Keyspace>)>, anyhow::Error> {
+        let locks = self.keyspaces.all();
+        let guards = locks
+            .iter()
+            .map(|(index, keyspace)| keyspace.read().map(|guard| (*index, guard)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        Ok(guards
+            .iter()
+            .map(|(index, keyspace)| (*index, Arc::clone(keyspace)))
+            .collect())
+    }
+
+    fn write_snapshot(
+        &self,
+        snapshot: Snapshot,
+        path: &Path,
+        compress: bool,
+    ) -> Result<(), anyhow::Error> {
+        let mut saved_generation = self
+            .save_status
+            .saving
+            .lock()
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        // A newer snapshot already made it to disk, writing this one would go back in time.
+        if snapshot.generation < *saved_generation {
+            return Ok(());
+        }
+
+        let rdb = serialize(&snapshot.keyspaces, compress, self.now())?;
+
+        // Write the snapshot next to the dump and rename it into place, so a crash
+        // halfway through a save leaves the previous dump untouched.
+        let temp_path = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+        if let Err(e) = write_synced(&temp_path, &rdb) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.context(format!("Writing RDB file {}", temp_path.display())));
+        }
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Renaming {} to {}", temp_path.display(), path.display()))?;
+        *saved_generation = snapshot.generation;
+
+        // Anything that changed while we were writing still needs to be saved next time.
+        self.save_status
+            .changes
+            .fetch_sub(snapshot.changes, Ordering::SeqCst);
+        let now = current_unix_timestamp()? / 1000;
+        self.save_status
+            .last_save
+            .store(now as u64, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Swaps the whole dataset, every database of it, for another one, like the
+    /// snapshot a master sends its replicas during a full resync.
+    pub fn replace_with(&self, other: &Database) -> Result<(), anyhow::Error> {
+        let mut incoming = other.keyspaces()?;
+        // Databases the other dataset doesn't have end up empty
+        for (index, _) in self.keyspaces.all() {
+            if !incoming
+                .iter()
+                .any(|(other_index, _)| *other_index == index)
+            {
+                incoming.push((index, Arc::new(Keyspace::new(self.clock.clone()))));
+            }
+        }
+
+        for (index, keyspace) in incoming {
+            let lock = self.keyspaces.get_or_insert(index, &self.clock);
+            let mut current = KeyspaceGuard(lock.write().map_err(|e| anyhow::anyhow!("{}", e))?);
+            let previous = std::mem::replace(&mut *current.0, keyspace);
+            // Keys set from now on go by this database's clock
+            current.clock = self.clock.clone();
+            drop(current);
+
+            previous.stop_timers();
+        }
+
+        Ok(())
+    }
+
     pub fn from_config(path: PathBuf) -> Result<Self, anyhow::Error> {
-        let database = Database::new();
         if !path.exists() {
-            return Ok(database);
+            return Ok(Database::new());
         }
 
         let contents = fs::read(path).context("Reading RDB file")?;
         let mut cursor = Cursor::new(contents);
+        Database::from_rdb(&mut cursor)
+    }
 
-        let mut magic_string: [u8; 5] = [0; 5];
-        cursor
-            .read_exact(&mut magic_string)
-            .context("Reading magic string")?;
-        let magic_string =
-            String::from_utf8(magic_string.to_vec()).context("Parsing magic string into utf8")?;
-        if &magic_string != "REDIS" {
-            anyhow::bail!(
-                "Expected REDIS magic string at beginning of RDB file, got {}",
-                magic_string
-            );
-        }
-
-        let mut version_number: [u8; 4] = [0; 4];
-        cursor
-            .read_exact(&mut version_number)
-            .context("Reading version number")?;
-        let version_number = String::from_utf8(version_number.to_vec())
-            .context("Parsing verison number into utf8")?;
-        str::parse::<usize>(&version_number)
-            .context("Version number cannot be parsed as an integer")?;
+    /// Reads an RDB payload up to and including its checksum, leaving the cursor
+    /// at whatever comes after it.
+    pub fn from_rdb(cursor: &mut Cursor<Vec<u8>>) -> Result<Self, anyhow::Error> {
+        let database = Database::new();
+        // Keys that come before any SELECTDB are in the first database
+        let mut selected = database.clone();
+        let version_number = read_rdb_header(cursor)?;
 
         loop {
-            let op_code = utils::read_next_byte(&mut cursor)?;
+            let op_code = utils::read_next_byte(cursor)?;
             match OpCode::from_byte(op_code) {
-                OpCode::Aux => parse_aux(&mut cursor)?,
-                OpCode::SelectDB => parse_select_db(&mut cursor)?,
-                OpCode::ResizeDb => parse_resize_db(&mut cursor)?,
+                OpCode::Aux => {
+                    parse_aux(cursor)?;
+                }
+                OpCode::SelectDB => {
+                    selected = database.select(parse_select_db(cursor)?);
+                }
+                OpCode::ResizeDb => {
+                    // Size the keyspace up front rather than growing it key by key
+                    let size = parse_resize_db(cursor)?;
+                    selected.reserve(size)?;
+                }
                 OpCode::ExpireTimeMS => {
-                    let database_item = parse_expire_time_ms(&mut cursor)?;
-                    if let Some((key, value)) = database_item {
-                        database.set_item(key, value);
+                    let database_item = parse_expire_time_ms(cursor)?;
+                    if let Some((key, value, expires_at)) = database_item {
+                        selected.set_item(key, value, Some(expires_at));
                     }
                 }
                 OpCode::ExpireTime => {
-                    let database_item = parse_expire_time_sec(&mut cursor)?;
-                    if let Some((key, value)) = database_item {
-                        database.set_item(key, value);
+                    let database_item = parse_expire_time_sec(cursor)?;
+                    if let Some((key, value, expires_at)) = database_item {
+                        selected.set_item(key, value, Some(expires_at));
                     }
                 }
                 OpCode::Other(value_type_byte) => {
                     let value_type = ValueType::from_byte(value_type_byte)?;
-                    let (key, value) = read_key_value_pair(value_type, None, &mut cursor)?;
-                    database.set_item(key, value);
+                    let (key, value) = read_key_value_pair(value_type, cursor)?;
+                    selected.set_item(key, value, None);
                 }
                 OpCode::Eof => break,
             }
         }
 
+        // Checksums were added in version 5
+        if version_number >= 5 {
+            let mut checksum: [u8; 8] = [0; 8];
+            cursor
+                .read_exact(&mut checksum)
+                .context("Reading checksum")?;
+        }
+
         Ok(database)
     }
 }
 
-impl Clone for Database {
+/// What an RDB file holds, as reported by `not-redis-check-rdb`.
+#[derive(Debug, Default)]
+pub struct RdbSummary {
+    pub version: usize,
+    pub aux_fields: Vec<AuxValue>,
+    pub keys: Vec<RdbKey>,
+    pub checksum: RdbChecksum,
+}
+
+#[derive(Debug)]
+pub struct RdbKey {
+    pub db: usize,
+    pub key: String,
+    pub key_type: &'static str,
+    /// Unix time in milliseconds, whether or not it has already passed.
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum RdbChecksum {
+    /// Files older than version 5 don't have one.
+    #[default]
+    Missing,
+    /// Written as zero when redis is configured with `rdbchecksum no`.
+    Disabled,
+    Valid(u64),
+    Mismatch {
+        expected: u64,
+        computed: u64,
+    },
+}
+
+/// Walks a whole RDB file with the same decoders used to load it, without
+/// dropping expired keys, and checks its checksum. Errors say at which offset
+/// the entry that couldn't be read starts.
+pub fn inspect_rdb(contents: Vec<u8>) -> Result<RdbSummary, anyhow::Error> {
+    let mut cursor = Cursor::new(contents);
+    let mut summary = RdbSummary {
+        version: read_rdb_header(&mut cursor)?,
+        ..Default::default()
+    };
+
+    let mut db = 0;
+    let mut expires_at = None;
+    loop {
+        let offset = cursor.position();
+        let op_code = utils::read_next_byte(&mut cursor)
+            .with_context(|| format!("Reading opcode at offset {}", offset))?;
+        let op_code = OpCode::from_byte(op_code);
+        if expires_at.is_some() && !matches!(op_code, OpCode::Other(_)) {
+            anyhow::bail!(
+                "Expiration time at offset {} isn't followed by a key",
+                offset
+            );
+        }
+
+        let entry = (|| -> Result<(), anyhow::Error> {
+            match op_code {
+                OpCode::Aux => summary.aux_fields.push(parse_aux(&mut cursor)?),
+                OpCode::SelectDB => db = parse_select_db(&mut cursor)?,
+                OpCode::ResizeDb => {
+                    parse_resize_db(&mut cursor)?;
+                }
+                OpCode::ExpireTimeMS => expires_at = Some(read_expire_time_ms(&mut cursor)?),
+                OpCode::ExpireTime => expires_at = Some(read_expire_time_sec(&mut cursor)?),
+                OpCode::Other(value_type_byte) => {
+                    let value_type = ValueType::from_byte(value_type_byte)?;
+                    let (key, item) = read_key_value_pair(value_type, &mut cursor)?;
+                    summary.keys.push(RdbKey {
+                        db,
+                        key,
+                        key_type: item.type_name(),
+                        expires_at: expires_at.take(),
+                    });
+                }
+                OpCode::Eof => {}
+            }
+            Ok(())
+        })();
+        entry.with_context(|| format!("Reading {:?} entry at offset {}", op_code, offset))?;
+
+        if op_code == OpCode::Eof {
+            break;
+        }
+    }
+
+    // Checksums were added in version 5
+    if summary.version >= 5 {
+        let end = cursor.position() as usize;
+        let mut checksum: [u8; 8] = [0; 8];
+        cursor
+            .read_exact(&mut checksum)
+            .with_context(|| format!("Reading checksum at offset {}", end))?;
+
+        let expected = u64::from_le_bytes(checksum);
+        let computed = encoding::crc64(0, &cursor.get_ref()[..end]);
+        summary.checksum = match expected {
+            0 => RdbChecksum::Disabled,
+            expected if expected == computed => RdbChecksum::Valid(expected),
+            expected => RdbChecksum::Mismatch { expected, computed },
+        };
+    }
+
+    Ok(summary)
+}
+
+/// Reads the magic string and returns the version number that follows it.
+fn read_rdb_header(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Error> {
+    let mut magic_string: [u8; 5] = [0; 5];
+    cursor
+        .read_exact(&mut magic_string)
+        .context("Reading magic string")?;
+    let magic_string =
+        String::from_utf8(magic_string.to_vec()).context("Parsing magic string into utf8")?;
+    if &magic_string != "REDIS" {
+        anyhow::bail!(
+            "Expected REDIS magic string at beginning of RDB file, got {}",
+            magic_string
+        );
+    }
+
+    let mut version_number: [u8; 4] = [0; 4];
+    cursor
+        .read_exact(&mut version_number)
+        .context("Reading version number")?;
+    let version_number =
+        String::from_utf8(version_number.to_vec()).context("Parsing verison number into utf8")?;
+    let version_number = str::parse::<usize>(&version_number)
+        .context("Version number cannot be parsed as an integer")?;
+
+    Ok(version_number)
+}
+
+/// Typed access for using the dataset as an embedded store, without RESP or the
+/// server in between. Keys with a TTL are removed by a timer, so they have to be
+/// set from inside a Tokio runtime.
+impl Database {
+    pub fn get_string(&self, key: &str) -> Result<Option<String>, RedisError> {
+        self.get(key)
+    }
+
+    pub fn set_string(&self, key: &str, value: impl Into<String>) -> Result<(), RedisError> {
+        self.set(key.to_string(), RedisString::new(value.into()), None)
+    }
+
+    pub fn set_with_ttl(
+        &self,
+        key: &str,
+        value: impl Into<String>,
+        ttl: Duration,
+    ) -> Result<(), RedisError> {
+        let expires_at = self.now() + ttl.as_millis();
+        self.set(
+            key.to_string(),
+            RedisString::new(value.into()),
+            Some(expires_at),
+        )
+    }
+
+    /// How much longer the key has to live, or `None` if it doesn't exist or never expires.
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        let expires_at = self.keyspace.read().unwrap().expires_at(key)?;
+        Some(Duration::from_millis(
+            expires_at.saturating_sub(self.now()) as u64
+        ))
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.keyspace.read().unwrap().contains_key(key)
+    }
+
+    /// The number of keys, counting any whose expiry timer hasn't run yet.
+    pub fn len(&self) -> usize {
+        self.keyspace.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Roughly how many bytes the selected database takes up. Only the keys written
+    /// since the last time are measured, under the write lock, rather than every key.
+    pub fn used_memory(&self) -> usize {
+        keyspace_memory(&self.keyspace)
+    }
+
+    /// Like `used_memory`, but for every database of the dataset together.
+    pub fn dataset_memory(&self) -> usize {
+        self.keyspaces
+            .all()
+            .iter()
+            .map(|(_, keyspace)| keyspace_memory(keyspace))
+            .sum()
+    }
+
+    /// Returns whether there was anything to delete.
+    pub fn delete(&self, key: &str) -> bool {
+        self.remove_multiple(vec![key.to_string()]) == 1
+    }
+
+    /// The name TYPE reports for the key, e.g. "string" or "stream".
+    pub fn key_type(&self, key: &str) -> Option<&'static str> {
+        self.keyspace
+            .read()
+            .unwrap()
+            .get(key)
+            .map(|item| item.type_name())
+    }
+
+    /// Calls the hook with the key whenever a key is given a value, by a command
+    /// or through this API, e.g. to keep a secondary index or cache in step.
+    /// Hooks run on the thread that made the change, after it's been made.
+    pub fn on_set(&self, hook: impl Fn(&str, KeyEvent) + Send + Sync + 'static) {
+        self.hooks.register(KeyEvent::Set, hook);
+    }
+
+    /// Calls the hook whenever a key is deleted, but not when it expires.
+    pub fn on_delete(&self, hook: impl Fn(&str, KeyEvent) + Send + Sync + 'static) {
+        self.hooks.register(KeyEvent::Delete, hook);
+    }
+
+    pub fn on_expire(&self, hook: impl Fn(&str, KeyEvent) + Send + Sync + 'static) {
+        self.hooks.register(KeyEvent::Expire, hook);
+    }
+
+    pub fn on_xadd(&self, hook: impl Fn(&str, KeyEvent) + Send + Sync + 'static) {
+        self.hooks.register(KeyEvent::Xadd, hook);
+    }
+
+    /// Appends an entry to a stream and returns its id. Without an id, one is
+    /// generated from the current time like `XADD key *`.
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: Vec<(String, String)>,
+    ) -> Result<StreamId, RedisError> {
+        let (ms_time, sequence_number) = match id {
+            Some(id) => (
+                Some(id.ms_time),
+                request::XAddNumber::Predetermined(id.sequence_number),
+            ),
+            None => (None, request::XAddNumber::Autogenerate),
+        };
+        let items = fields
+            .into_iter()
+            .map(|(key, value)| RedisStreamItem::new(key, value))
+            .collect();
+
+        let id = self.insert_stream_entry(key, ms_time, sequence_number, items)?;
+        self.blocked.signal(key);
+        self.hooks.notify(key, KeyEvent::Xadd);
+
+        Ok(id)
+    }
+
+    /// The entries of a stream between `start` and `end`, both inclusive. Leaving
+    /// either out is the same as `-` or `+` in XRANGE.
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: Option<StreamId>,
+        end: Option<StreamId>,
+    ) -> Result<Vec<StreamEntry>, RedisError> {
+        let to_range = |id: Option<StreamId>| match id {
+            Some(id) => request::XRangeNumber::Specified(id.ms_time, id.sequence_number),
+            None => request::XRangeNumber::Unspecified,
+        };
+
+        let database = self.keyspace.read().unwrap();
+        let stream = match database.get(key) {
+            None => return Ok(vec![]),
+            Some(DatabaseItem::Stream(stream)) => stream,
+            Some(_) => return Err(RedisError::WrongType),
+        };
+
+        let entries = entries_in_range(stream, &to_range(start), &to_range(end))
+            .into_iter()
+            .map(StreamEntry::from)
+            .collect();
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisString {
+    data: StringValue,
+}
+
+impl RedisString {
+    pub fn new(data: String) -> Self {
+        Self {
+            data: StringValue::new(data),
+        }
+    }
+
+    pub fn data(&self) -> String {
+        self.data.to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DatabaseItem {
+    String(RedisString),
+    Stream(RedisStream),
+    List(RedisList),
+    Set(RedisSet),
+    Hash(RedisHash),
+    SortedSet(RedisSortedSet),
+    Json(serde_json::Value),
+    Bloom(ScalableBloomFilter),
+    Cuckoo(ScalableCuckooFilter),
+    CountMinSketch(CountMinSketch),
+    TopK(TopK),
+    TimeSeries(TimeSeries),
+    /// A value of a type an extension added.
+    Custom(Box<dyn CustomValue>),
+}
+
+/// A value type that, like the types redis modules add, is saved as a list of
+/// fields. Commands read and change these values as a whole through
+/// `Database::read_module` and `Database::update_module`.
+pub trait ModuleValue: Sized + Default + Clone + fmt::Debug + Send + Sync + 'static {
+    /// The nine character name TYPE reports, which also identifies the type in RDB files.
+    const TYPE_NAME: &'static str;
+    const ENCODING_VERSION: u16;
+
+    /// The built in types have a variant of their own. By default a value is
+    /// kept as `DatabaseItem::Custom`, which is what the types extensions add use.
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::Custom(value) => value.as_any().downcast_ref(),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::Custom(value) => value.as_any_mut().downcast_mut(),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::Custom(Box::new(self))
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField>;
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error>;
+}
+
+/// What's left of a `ModuleValue` once the type is erased, so values of types
+/// the server doesn't know about can be kept in the keyspace and saved.
+pub trait CustomValue: fmt::Debug + Send + Sync {
+    fn type_name(&self) -> &'static str;
+    fn encoding_version(&self) -> u16;
+    fn to_fields(&self) -> Vec<ModuleField>;
+    fn clone_value(&self) -> Box<dyn CustomValue>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: ModuleValue> CustomValue for T {
+    fn type_name(&self) -> &'static str {
+        T::TYPE_NAME
+    }
+
+    fn encoding_version(&self) -> u16 {
+        T::ENCODING_VERSION
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        ModuleValue::to_fields(self)
+    }
+
+    fn clone_value(&self) -> Box<dyn CustomValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn CustomValue> {
     fn clone(&self) -> Self {
-        Database(self.0.clone())
+        self.clone_value()
+    }
+}
+
+impl DatabaseItem {
+    /// The name TYPE reports.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DatabaseItem::String(_) => "string",
+            DatabaseItem::Stream(_) => "stream",
+            DatabaseItem::List(_) => "list",
+            DatabaseItem::Set(_) => "set",
+            DatabaseItem::Hash(_) => "hash",
+            DatabaseItem::SortedSet(_) => "zset",
+            DatabaseItem::Json(_) => serde_json::Value::TYPE_NAME,
+            DatabaseItem::Bloom(_) => ScalableBloomFilter::TYPE_NAME,
+            DatabaseItem::Cuckoo(_) => ScalableCuckooFilter::TYPE_NAME,
+            DatabaseItem::CountMinSketch(_) => CountMinSketch::TYPE_NAME,
+            DatabaseItem::TopK(_) => TopK::TYPE_NAME,
+            DatabaseItem::TimeSeries(_) => TimeSeries::TYPE_NAME,
+            DatabaseItem::Custom(value) => value.type_name(),
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct RedisString {
-    data: String,
-    duration: Option<Duration>,
-    cancellation_process: Option<JoinHandle<()>>,
+    /// How the value is stored, as reported by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            DatabaseItem::String(redis_string) => redis_string.data.encoding(),
+            DatabaseItem::Stream(_) => "stream",
+            DatabaseItem::List(list) => list.encoding(),
+            DatabaseItem::Set(set) => set.encoding(),
+            DatabaseItem::Hash(hash) => hash.encoding(),
+            // Members are kept in a single sorted array
+            DatabaseItem::SortedSet(_) => "listpack",
+            // Like every module type
+            DatabaseItem::Json(_)
+            | DatabaseItem::Bloom(_)
+            | DatabaseItem::Cuckoo(_)
+            | DatabaseItem::CountMinSketch(_)
+            | DatabaseItem::TopK(_)
+            | DatabaseItem::TimeSeries(_)
+            | DatabaseItem::Custom(_) => "raw",
+        }
+    }
 }
 
-impl RedisString {
-    pub fn new(data: String, duration: Option<Duration>) -> Self {
-        Self {
-            data,
-            duration,
-            cancellation_process: None,
-        }
+/// Members ordered by score, ties broken by the member itself like redis does.
+#[derive(Debug, Default, Clone)]
+pub struct RedisSortedSet(Vec<(String, f64)>);
+
+impl RedisSortedSet {
+    pub fn insert(&mut self, member: String, score: f64) {
+        self.0.retain(|(existing, _)| existing != &member);
+        let position = self.0.partition_point(|(existing, existing_score)| {
+            existing_score
+                .total_cmp(&score)
+                .then_with(|| existing.cmp(&member))
+                .is_lt()
+        });
+        self.0.insert(position, (member, score));
     }
 
-    pub fn data(&self) -> String {
-        encoding::bulk_string(&self.data)
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
 
-    pub fn set_cancellation(&mut self, process: JoinHandle<()>) {
-        self.cancellation_process = Some(process);
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
-    pub fn abort_deletion_process(&mut self) {
-        if let Some(process) = &self.cancellation_process {
-            process.abort();
-            self.cancellation_process = None;
-        }
+    pub fn iter(&self) -> impl Iterator<Item = &(String, f64)> {
+        self.0.iter()
     }
 }
 
-#[derive(Debug)]
-pub enum DatabaseItem {
-    String(RedisString),
-    Stream(RedisStream),
+// TODO: Consider if this should be a btree
+#[derive(Debug, Clone)]
+pub struct RedisStream {
+    entries: Vec<InnerRedisStream>,
+    // The last ID that was added to the stream, which new IDs must be greater than
+    // even if the entry it belongs to has been removed.
+    last_id: (u128, usize),
 }
 
-impl DatabaseItem {
-    pub fn data_type(&self) -> String {
-        let data_type = match self {
-            DatabaseItem::String(_) => "string",
-            DatabaseItem::Stream(_) => "stream",
-        };
-        encoding::bulk_string(data_type)
+impl RedisStream {
+    fn new(entry: InnerRedisStream) -> Self {
+        RedisStream {
+            last_id: (entry.ms_time, entry.sequence_number),
+            entries: vec![entry],
+        }
     }
 
-    pub fn clean_up(&mut self) {
-        match self {
-            DatabaseItem::String(redis_string) => {
-                redis_string.abort_deletion_process();
-            }
-            DatabaseItem::Stream(_) => {}
+    fn push(&mut self, entry: InnerRedisStream) {
+        self.last_id = (entry.ms_time, entry.sequence_number);
+        self.entries.push(entry);
+    }
+
+    /// A stream that was saved elsewhere. The entries have to be in order, and the
+    /// last ID can't be before the last entry's.
+    pub(crate) fn from_entries(entries: Vec<InnerRedisStream>, last_id: StreamId) -> Self {
+        RedisStream {
+            entries,
+            last_id: (last_id.ms_time, last_id.sequence_number),
         }
     }
-}
 
-// TODO: Consider if this should be a btree
-#[derive(Debug)]
-pub struct RedisStream(Vec<InnerRedisStream>);
+    pub(crate) fn entries(&self) -> &[InnerRedisStream] {
+        &self.entries
+    }
+
+    pub(crate) fn last_id(&self) -> StreamId {
+        StreamId::new(self.last_id.0, self.last_id.1)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RedisStreamItem {
@@ -726,87 +2939,131 @@ pub struct InnerRedisStream {
 }
 
 impl InnerRedisStream {
+    pub fn id(&self) -> StreamId {
+        StreamId::new(self.ms_time, self.sequence_number)
+    }
+
     pub fn stream_id(&self) -> String {
-        format!("{}-{}", self.ms_time, self.sequence_number)
+        self.id().to_string()
     }
 }
 
-pub struct ReadStreamItem<'a> {
-    pub streams: Vec<&'a InnerRedisStream>,
-    pub key: String,
+/// The id of a stream entry, the millisecond time it was added at and a
+/// sequence number for entries added in the same millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms_time: u128,
+    pub sequence_number: usize,
+}
+
+impl StreamId {
+    pub fn new(ms_time: u128, sequence_number: usize) -> Self {
+        StreamId {
+            ms_time,
+            sequence_number,
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms_time, self.sequence_number)
+    }
 }
 
-pub struct TempReadStreamItem {
-    pub streams: Vec<InnerRedisStream>,
+/// A stream entry as handed out by `Database::xrange`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(String, String)>,
+}
+
+impl From<&InnerRedisStream> for StreamEntry {
+    fn from(entry: &InnerRedisStream) -> Self {
+        StreamEntry {
+            id: entry.id(),
+            fields: entry
+                .items
+                .iter()
+                .map(|item| (item.key.clone(), item.value.clone()))
+                .collect(),
+        }
+    }
+}
+
+pub struct ReadStreamItem<'a> {
+    pub streams: Vec<&'a InnerRedisStream>,
     pub key: String,
 }
 
 #[derive(Debug)]
 pub struct AuxValue {
-    #[allow(dead_code)]
-    key: String,
-    #[allow(dead_code)]
-    value: String,
+    pub key: String,
+    pub value: String,
 }
 
-fn parse_aux(cursor: &mut Cursor<Vec<u8>>) -> Result<(), anyhow::Error> {
+fn parse_aux(cursor: &mut Cursor<Vec<u8>>) -> Result<AuxValue, anyhow::Error> {
     let key = encoding::decode_rdb_string(cursor)?;
     let value = encoding::decode_rdb_string(cursor)?;
 
-    let _aux_value = AuxValue { key, value };
-
-    Ok(())
+    Ok(AuxValue { key, value })
 }
 
-fn parse_select_db(cursor: &mut Cursor<Vec<u8>>) -> Result<(), anyhow::Error> {
-    let _size = encoding::decode_rdb_int(cursor)?;
-
-    Ok(())
+/// Returns the number of the database the keys that follow belong to.
+fn parse_select_db(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Error> {
+    encoding::decode_rdb_int(cursor)
 }
 
-fn parse_resize_db(cursor: &mut Cursor<Vec<u8>>) -> Result<(), anyhow::Error> {
-    let _hash_table_size = encoding::decode_rdb_int(cursor)?;
+/// Returns the number of keys the file says the database holds.
+fn parse_resize_db(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Error> {
+    let hash_table_size = encoding::decode_rdb_int(cursor)?;
     let _expiry_table_size = encoding::decode_rdb_int(cursor)?;
 
-    Ok(())
+    Ok(hash_table_size)
 }
 
 fn parse_expire_time_ms(
     cursor: &mut Cursor<Vec<u8>>,
-) -> Result<Option<(String, DatabaseItem)>, anyhow::Error> {
-    let mut expire_time_ms: [u8; 8] = [0; 8];
-    cursor.read_exact(&mut expire_time_ms)?;
-    let expire_time_milliseconds = u64::from_le_bytes(expire_time_ms);
-    let expire_time_unix_timestamp = expire_time_milliseconds / 1000;
-
-    read_expirable_item(expire_time_unix_timestamp, cursor)
+) -> Result<Option<(String, DatabaseItem, u128)>, anyhow::Error> {
+    let expire_time_milliseconds = read_expire_time_ms(cursor)?;
+    read_expirable_item(expire_time_milliseconds, cursor)
 }
 
 fn parse_expire_time_sec(
     cursor: &mut Cursor<Vec<u8>>,
-) -> Result<Option<(String, DatabaseItem)>, anyhow::Error> {
+) -> Result<Option<(String, DatabaseItem, u128)>, anyhow::Error> {
+    let expire_time_milliseconds = read_expire_time_sec(cursor)?;
+    read_expirable_item(expire_time_milliseconds, cursor)
+}
+
+/// Both expiration opcodes are read as a unix time in milliseconds.
+fn read_expire_time_ms(cursor: &mut Cursor<Vec<u8>>) -> Result<u64, anyhow::Error> {
+    let mut expire_time_ms: [u8; 8] = [0; 8];
+    cursor.read_exact(&mut expire_time_ms)?;
+    Ok(u64::from_le_bytes(expire_time_ms))
+}
+
+fn read_expire_time_sec(cursor: &mut Cursor<Vec<u8>>) -> Result<u64, anyhow::Error> {
     let mut expire_time_seconds: [u8; 4] = [0; 4];
     cursor.read_exact(&mut expire_time_seconds)?;
-    let expire_time_seconds = u32::from_le_bytes(expire_time_seconds);
-
-    read_expirable_item(expire_time_seconds as u64, cursor)
+    Ok(u32::from_le_bytes(expire_time_seconds) as u64 * 1000)
 }
 
+/// The key, its value and its deadline, unless the deadline has already passed.
 fn read_expirable_item(
-    expire_time_unix_timestamp: u64,
+    expire_time_unix_timestamp_ms: u64,
     cursor: &mut Cursor<Vec<u8>>,
-) -> Result<Option<(String, DatabaseItem)>, anyhow::Error> {
-    let item_expiration = duration_to_item_expiration(expire_time_unix_timestamp);
-    let item_expires_in_future = item_expiration.is_some();
+) -> Result<Option<(String, DatabaseItem, u128)>, anyhow::Error> {
+    let expires_at = expire_time_unix_timestamp_ms as u128;
+    let item_expires_in_future = expires_at > current_unix_timestamp()?;
 
     let value_type_byte = utils::read_next_byte(cursor)?;
     let value_type = ValueType::from_byte(value_type_byte)?;
 
-    let item_data = read_key_value_pair(value_type, item_expiration, cursor)?;
+    let (key, item) = read_key_value_pair(value_type, cursor)?;
 
     if item_expires_in_future {
-        let data = Some(item_data);
-        Ok(data)
+        Ok(Some((key, item, expires_at)))
     } else {
         Ok(None)
     }
@@ -814,201 +3071,646 @@ fn read_expirable_item(
 
 fn read_key_value_pair(
     value_type: ValueType,
-    expire_time: Option<Duration>,
     cursor: &mut Cursor<Vec<u8>>,
 ) -> Result<(String, DatabaseItem), anyhow::Error> {
     let key = encoding::decode_rdb_string(cursor)?;
-    let value = match value_type {
-        ValueType::String => encoding::decode_rdb_string(cursor)?,
+    let database_item = read_value(value_type, cursor)?;
+
+    Ok((key, database_item))
+}
+
+fn rdb_version() -> u16 {
+    RDB_VERSION.parse().expect("the RDB version is a number")
+}
+
+pub(crate) fn dump_item(item: &DatabaseItem) -> Vec<u8> {
+    let mut payload = vec![value_type(item) as u8];
+    write_value(&mut payload, item, true);
+
+    payload.extend(rdb_version().to_le_bytes());
+    let checksum = encoding::crc64(0, &payload);
+    payload.extend(checksum.to_le_bytes());
+
+    payload
+}
+
+/// The value in a DUMP payload, once its version and checksum have been checked.
+pub(crate) fn read_dump(payload: &[u8]) -> Result<DatabaseItem, anyhow::Error> {
+    let Some(footer) = payload.len().checked_sub(10) else {
+        anyhow::bail!("DUMP payload is too short");
+    };
+    let (body, footer) = payload.split_at(footer);
+    let (version, checksum) = footer.split_at(2);
+
+    if u16::from_le_bytes(version.try_into()?) > rdb_version() {
+        anyhow::bail!("DUMP payload is from a newer RDB version");
+    }
+    let expected = u64::from_le_bytes(checksum.try_into()?);
+    if encoding::crc64(0, &payload[..payload.len() - 8]) != expected {
+        anyhow::bail!("DUMP payload checksum mismatch");
+    }
+
+    let mut cursor = Cursor::new(body.to_vec());
+    let value_type = ValueType::from_byte(utils::read_next_byte(&mut cursor)?)?;
+    let item = read_value(value_type, &mut cursor)?;
+    if cursor.position() as usize != body.len() {
+        anyhow::bail!("DUMP payload has trailing bytes");
+    }
+
+    Ok(item)
+}
+
+fn read_value(
+    value_type: ValueType,
+    cursor: &mut Cursor<Vec<u8>>,
+) -> Result<DatabaseItem, anyhow::Error> {
+    let database_item = match value_type {
+        ValueType::String => {
+            let value = encoding::decode_rdb_string(cursor)?;
+            DatabaseItem::String(RedisString::new(value))
+        }
+        ValueType::List => DatabaseItem::List(read_rdb_list(cursor)?.into_iter().collect()),
+        ValueType::Set => DatabaseItem::Set(read_rdb_list(cursor)?.into_iter().collect()),
+        ValueType::Hash => DatabaseItem::Hash(read_rdb_hash(cursor)?),
+        ValueType::SortedSet => DatabaseItem::SortedSet(read_rdb_sorted_set(cursor, false)?),
+        ValueType::SortedSet2 => DatabaseItem::SortedSet(read_rdb_sorted_set(cursor, true)?),
+        ValueType::StreamListpacks | ValueType::StreamListpacks2 | ValueType::StreamListpacks3 => {
+            DatabaseItem::Stream(read_rdb_stream(cursor, &value_type)?)
+        }
+        ValueType::Module2 => read_module_value(cursor)?,
         // TODO
         _ => anyhow::bail!("{:?} value type not supported", value_type),
     };
 
-    let redis_string = RedisString::new(value, expire_time);
-    let database_item = DatabaseItem::String(redis_string);
+    Ok(database_item)
+}
 
-    Ok((key, database_item))
+fn read_module_value(cursor: &mut Cursor<Vec<u8>>) -> Result<DatabaseItem, anyhow::Error> {
+    let (name, _, fields) = encoding::decode_module_value(cursor)?;
+    let item = match name.as_str() {
+        serde_json::Value::TYPE_NAME => serde_json::Value::from_fields(&fields)?.into_item(),
+        ScalableBloomFilter::TYPE_NAME => ScalableBloomFilter::from_fields(&fields)?.into_item(),
+        ScalableCuckooFilter::TYPE_NAME => ScalableCuckooFilter::from_fields(&fields)?.into_item(),
+        CountMinSketch::TYPE_NAME => CountMinSketch::from_fields(&fields)?.into_item(),
+        TopK::TYPE_NAME => TopK::from_fields(&fields)?.into_item(),
+        TimeSeries::TYPE_NAME => TimeSeries::from_fields(&fields)?.into_item(),
+        _ => extension::load_value(&name, &fields)?,
+    };
+
+    Ok(item)
+}
+
+// Lists and sets share an encoding: the number of elements followed by each one as a string.
+fn read_rdb_list(cursor: &mut Cursor<Vec<u8>>) -> Result<Vec<String>, anyhow::Error> {
+    let size = encoding::decode_rdb_int(cursor)?;
+    (0..size)
+        .map(|_| encoding::decode_rdb_string(cursor))
+        .collect()
+}
+
+fn read_rdb_hash(cursor: &mut Cursor<Vec<u8>>) -> Result<RedisHash, anyhow::Error> {
+    let size = encoding::decode_rdb_int(cursor)?;
+    let mut hash = RedisHash::default();
+    for _ in 0..size {
+        let field = encoding::decode_rdb_string(cursor)?;
+        let value = encoding::decode_rdb_string(cursor)?;
+        hash.insert(field, value);
+    }
+
+    Ok(hash)
+}
+
+// The original sorted set encoding stores scores as strings, the newer one
+// as little endian doubles.
+fn read_rdb_sorted_set(
+    cursor: &mut Cursor<Vec<u8>>,
+    binary_scores: bool,
+) -> Result<RedisSortedSet, anyhow::Error> {
+    let size = encoding::decode_rdb_int(cursor)?;
+    let mut sorted_set = RedisSortedSet::default();
+    for _ in 0..size {
+        let member = encoding::decode_rdb_string(cursor)?;
+        let score = if binary_scores {
+            let mut score: [u8; 8] = [0; 8];
+            cursor
+                .read_exact(&mut score)
+                .context("Reading sorted set score")?;
+            f64::from_le_bytes(score)
+        } else {
+            encoding::decode_rdb_double(cursor)?
+        };
+        sorted_set.insert(member, score);
+    }
+
+    Ok(sorted_set)
 }
 
-fn duration_to_item_expiration(expire_time_unix_timestamp: u64) -> Option<Duration> {
-    let now = SystemTime::now();
-    let duration_since_epoch = now.duration_since(UNIX_EPOCH).unwrap();
+// The inverse of `write_stream`. The newer encodings add a few counters to the stream
+// and its consumer groups which we read past since we don't keep track of them.
+fn read_rdb_stream(
+    cursor: &mut Cursor<Vec<u8>>,
+    value_type: &ValueType,
+) -> Result<RedisStream, anyhow::Error> {
+    let mut entries: Vec<InnerRedisStream> = vec![];
+
+    let num_nodes = encoding::decode_rdb_int(cursor)?;
+    for _ in 0..num_nodes {
+        let master_id = encoding::decode_rdb_raw_string(cursor)?;
+        let master_id = read_stream_id(&master_id)?;
+        let listpack = encoding::decode_rdb_raw_string(cursor)?;
+        let listpack = encoding::decode_listpack(&listpack)?;
+        entries.extend(read_stream_node(master_id, listpack)?);
+    }
 
-    // Convert the duration to seconds and return it as u64
-    let current_unicode_timestamp = duration_since_epoch.as_secs();
+    let _length = encoding::decode_rdb_int(cursor)?;
+    let last_ms_time = encoding::decode_rdb_int(cursor)? as u128;
+    let last_sequence_number = encoding::decode_rdb_int(cursor)?;
 
-    match expire_time_unix_timestamp.checked_sub(current_unicode_timestamp) {
-        Some(dur) => {
-            let duration = Duration::from_secs(dur);
-            Some(duration)
+    let is_v2_or_later = *value_type != ValueType::StreamListpacks;
+    if is_v2_or_later {
+        // First ID, max deleted entry ID and entries added
+        for _ in 0..5 {
+            encoding::decode_rdb_int(cursor)?;
         }
-        None => None,
     }
+
+    let num_groups = encoding::decode_rdb_int(cursor)?;
+    for _ in 0..num_groups {
+        skip_stream_consumer_group(cursor, value_type)?;
+    }
+
+    Ok(RedisStream {
+        entries,
+        last_id: (last_ms_time, last_sequence_number),
+    })
 }
 
-fn determine_sequence_number(
-    num: request::XAddNumber,
-    ms_time: u128,
-    latest_inner: &InnerRedisStream,
-) -> usize {
-    if let request::XAddNumber::Predetermined(val) = num {
-        return val;
+fn read_stream_id(id: &[u8]) -> Result<(u128, usize), anyhow::Error> {
+    if id.len() != 16 {
+        anyhow::bail!("Stream IDs are 16 bytes, got {}", id.len());
     }
 
-    let sequence_number = if latest_inner.ms_time < ms_time { 0 } else { 1 };
+    let ms_time = u64::from_be_bytes(id[..8].try_into()?);
+    let sequence_number = u64::from_be_bytes(id[8..].try_into()?);
 
-    if sequence_number == 0 && ms_time == 0 {
-        return 1;
+    Ok((ms_time as u128, sequence_number as usize))
+}
+
+fn read_stream_node(
+    (master_ms_time, master_sequence_number): (u128, usize),
+    listpack: Vec<ListpackEntry>,
+) -> Result<Vec<InnerRedisStream>, anyhow::Error> {
+    let mut listpack = listpack.into_iter();
+    let mut next = || {
+        listpack
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Stream listpack ended unexpectedly"))
+    };
+
+    let count = next()?.to_integer()?;
+    let deleted = next()?.to_integer()?;
+    let num_master_fields = next()?.to_integer()?;
+    let master_fields = (0..num_master_fields)
+        .map(|_| next().map(|field| field.to_string()))
+        .collect::<Result<Vec<String>, anyhow::Error>>()?;
+    // End of the master entry
+    next()?;
+
+    let mut entries = vec![];
+    for _ in 0..count + deleted {
+        let flags = next()?.to_integer()?;
+        let ms_time = master_ms_time as i64 + next()?.to_integer()?;
+        let sequence_number = master_sequence_number as i64 + next()?.to_integer()?;
+
+        let items = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok(RedisStreamItem::new(field.clone(), next()?.to_string())))
+                .collect::<Result<Vec<RedisStreamItem>, anyhow::Error>>()?
+        } else {
+            let num_fields = next()?.to_integer()?;
+            (0..num_fields)
+                .map(|_| {
+                    Ok(RedisStreamItem::new(
+                        next()?.to_string(),
+                        next()?.to_string(),
+                    ))
+                })
+                .collect::<Result<Vec<RedisStreamItem>, anyhow::Error>>()?
+        };
+        // lp-count
+        next()?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.push(InnerRedisStream {
+                items,
+                ms_time: ms_time as u128,
+                sequence_number: sequence_number as usize,
+            });
+        }
     }
 
-    sequence_number
+    Ok(entries)
 }
 
-fn broadcast_xadd(
-    key: &str,
-    ms_time: u128,
-    sequence_number: usize,
-    data: Vec<RedisStreamItem>,
-    sender: Sender<transmission::Transmission>,
+fn skip_stream_consumer_group(
+    cursor: &mut Cursor<Vec<u8>>,
+    value_type: &ValueType,
 ) -> Result<(), anyhow::Error> {
-    let transmission = transmission::XAddTransmission {
-        key: key.to_string(),
-        ms_time,
-        sequence_number,
-        data,
-    };
+    let _name = encoding::decode_rdb_raw_string(cursor)?;
+    // Last delivered ID
+    encoding::decode_rdb_int(cursor)?;
+    encoding::decode_rdb_int(cursor)?;
+    if *value_type != ValueType::StreamListpacks {
+        let _entries_read = encoding::decode_rdb_int(cursor)?;
+    }
+
+    // The pending entries list: an ID, the delivery time and the delivery count
+    let num_pending = encoding::decode_rdb_int(cursor)?;
+    for _ in 0..num_pending {
+        skip_bytes(cursor, 16 + 8)?;
+        encoding::decode_rdb_int(cursor)?;
+    }
+
+    let num_consumers = encoding::decode_rdb_int(cursor)?;
+    for _ in 0..num_consumers {
+        let _name = encoding::decode_rdb_raw_string(cursor)?;
+        // Seen time, and active time in the newest encoding
+        skip_bytes(cursor, 8)?;
+        if *value_type == ValueType::StreamListpacks3 {
+            skip_bytes(cursor, 8)?;
+        }
+
+        let num_pending = encoding::decode_rdb_int(cursor)?;
+        skip_bytes(cursor, num_pending * 16)?;
+    }
+
+    Ok(())
+}
 
-    sender
-        .send(transmission::Transmission::Xadd(transmission))
-        .map_err(|e| anyhow::anyhow!("{:?}", e.to_string()))?;
+fn skip_bytes(cursor: &mut Cursor<Vec<u8>>, len: usize) -> Result<(), anyhow::Error> {
+    let mut skipped = vec![0; len];
+    cursor
+        .read_exact(&mut skipped)
+        .context("Reading past stream consumer group")?;
 
     Ok(())
 }
 
-async fn read_streams_after_limited_wait(
-    wait: u64,
-    read_command_streams: Vec<request::XReadCommandStream>,
-    mut receiver: Receiver<transmission::Transmission>,
-) -> Result<String, anyhow::Error> {
-    let start = Instant::now();
-    let mut streams: Vec<TempReadStreamItem> = vec![];
-    let wait = Duration::from_millis(wait);
+/// Serializes a keyspace as an RDB file.
+/// Large strings are LZF compressed when `compress` is set, and keys that expired
+/// by `now`, a unix timestamp in milliseconds, are left out.
+fn serialize(
+    keyspaces: &[(usize, Arc<Keyspace>)],
+    compress: bool,
+    now: u128,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut rdb: Vec<u8> = format!("REDIS{}", RDB_VERSION).into();
+
+    let creation_time = (now / 1000).to_string();
+    let aux_fields = [
+        (AuxField::RedisVersion, REDIS_VERSION),
+        (AuxField::RedisBits, "64"),
+        (AuxField::CreationTime, creation_time.as_str()),
+        (AuxField::MemoryUsed, "0"),
+    ];
+    for (field, value) in aux_fields {
+        rdb.push(OpCode::Aux.to_byte());
+        rdb.extend(encoding::encode_rdb_string(field.name(), compress));
+        rdb.extend(encoding::encode_rdb_string(value, compress));
+    }
 
-    loop {
-        let elapsed = start.elapsed();
-        if elapsed > wait {
-            break;
+    for (index, keyspace) in keyspaces {
+        // Keys whose expiration task hasn't run yet are already dead.
+        let live_items: Vec<(&Arc<str>, &DatabaseItem, Option<u128>)> = keyspace
+            .entries()
+            .filter(|(_, _, expires_at)| expires_at.is_none_or(|at| at > now))
+            .collect();
+        if live_items.is_empty() {
+            continue;
         }
 
-        let result = timeout(wait - elapsed, receiver.recv()).await;
-        match result {
-            Ok(Err(e)) => anyhow::bail!(e),
-            Err(_) => break,
-            Ok(Ok(transmission)) => {
-                if let transmission::Transmission::Xadd(xadd) = transmission {
-                    if read_command_streams.iter().any(|s| {
-                        let is_valid_key = s.key == xadd.key;
-                        let is_valid_entry = stream_entry_greater_than_start(
-                            xadd.ms_time,
-                            xadd.sequence_number,
-                            &s.start,
-                        );
-
-                        is_valid_key && is_valid_entry
-                    }) {
-                        let inner_redis_stream = InnerRedisStream {
-                            ms_time: xadd.ms_time,
-                            sequence_number: xadd.sequence_number,
-                            items: xadd.data,
-                        };
-
-                        if let Some(read_stream_item) =
-                            streams.iter_mut().find(|rsi| rsi.key == xadd.key)
-                        {
-                            read_stream_item.streams.push(inner_redis_stream)
-                        } else {
-                            let read_stream_item = TempReadStreamItem {
-                                streams: vec![inner_redis_stream],
-                                key: xadd.key,
-                            };
-
-                            streams.push(read_stream_item);
-                        }
-                    }
-                }
+        let num_expires = live_items
+            .iter()
+            .filter(|(_, _, expires_at)| expires_at.is_some())
+            .count();
+
+        rdb.push(OpCode::SelectDB.to_byte());
+        rdb.extend(encoding::encode_rdb_length(*index));
+        rdb.push(OpCode::ResizeDb.to_byte());
+        rdb.extend(encoding::encode_rdb_length(live_items.len()));
+        rdb.extend(encoding::encode_rdb_length(num_expires));
+
+        for (key, item, expires_at) in live_items {
+            write_key_value_pair(&mut rdb, key, item, expires_at, compress);
+        }
+    }
+
+    rdb.push(OpCode::Eof.to_byte());
+
+    let checksum = encoding::crc64(0, &rdb);
+    rdb.extend(checksum.to_le_bytes());
+
+    Ok(rdb)
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), anyhow::Error> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+fn write_key_value_pair(
+    rdb: &mut Vec<u8>,
+    key: &str,
+    item: &DatabaseItem,
+    expires_at: Option<u128>,
+    compress: bool,
+) {
+    if let Some(expires_at) = expires_at {
+        rdb.push(OpCode::ExpireTimeMS.to_byte());
+        rdb.extend((expires_at as u64).to_le_bytes());
+    }
+
+    rdb.push(value_type(item) as u8);
+    rdb.extend(encoding::encode_rdb_string(key, compress));
+    write_value(rdb, item, compress);
+}
+
+fn value_type(item: &DatabaseItem) -> ValueType {
+    match item {
+        DatabaseItem::String(_) => ValueType::String,
+        DatabaseItem::Stream(_) => ValueType::StreamListpacks,
+        DatabaseItem::List(_) => ValueType::List,
+        DatabaseItem::Set(_) => ValueType::Set,
+        DatabaseItem::Hash(_) => ValueType::Hash,
+        DatabaseItem::SortedSet(_) => ValueType::SortedSet2,
+        DatabaseItem::Json(_)
+        | DatabaseItem::Bloom(_)
+        | DatabaseItem::Cuckoo(_)
+        | DatabaseItem::CountMinSketch(_)
+        | DatabaseItem::TopK(_)
+        | DatabaseItem::TimeSeries(_)
+        | DatabaseItem::Custom(_) => ValueType::Module2,
+    }
+}
+
+/// The value on its own, as it follows the type and key in an RDB file.
+fn write_value(rdb: &mut Vec<u8>, item: &DatabaseItem, compress: bool) {
+    match item {
+        DatabaseItem::String(redis_string) => {
+            rdb.extend(encoding::encode_rdb_string(
+                &redis_string.data.as_str(),
+                compress,
+            ));
+        }
+        DatabaseItem::Stream(stream) => write_stream(rdb, stream, compress),
+        DatabaseItem::List(list) => write_rdb_list(rdb, list.len(), list.iter(), compress),
+        DatabaseItem::Set(set) => write_rdb_list(rdb, set.len(), set.iter(), compress),
+        DatabaseItem::Hash(hash) => {
+            rdb.extend(encoding::encode_rdb_length(hash.len()));
+            for (field, value) in hash.iter() {
+                rdb.extend(encoding::encode_rdb_string(field, compress));
+                rdb.extend(encoding::encode_rdb_string(value, compress));
+            }
+        }
+        DatabaseItem::SortedSet(sorted_set) => {
+            rdb.extend(encoding::encode_rdb_length(sorted_set.len()));
+            for (member, score) in sorted_set.iter() {
+                rdb.extend(encoding::encode_rdb_string(member, compress));
+                rdb.extend(score.to_le_bytes());
             }
         }
+        DatabaseItem::Json(document) => write_module_value(rdb, document, compress),
+        DatabaseItem::Bloom(filter) => write_module_value(rdb, filter, compress),
+        DatabaseItem::Cuckoo(filter) => write_module_value(rdb, filter, compress),
+        DatabaseItem::CountMinSketch(sketch) => write_module_value(rdb, sketch, compress),
+        DatabaseItem::TopK(topk) => write_module_value(rdb, topk, compress),
+        DatabaseItem::TimeSeries(series) => write_module_value(rdb, series, compress),
+        DatabaseItem::Custom(value) => write_module_value(rdb, value.as_ref(), compress),
     }
+}
 
-    if streams.is_empty() {
-        let null_string = encoding::empty_string();
-        return Ok(null_string);
+fn write_module_value(rdb: &mut Vec<u8>, value: &dyn CustomValue, compress: bool) {
+    rdb.extend(encoding::encode_module_value(
+        value.type_name(),
+        value.encoding_version(),
+        &value.to_fields(),
+        compress,
+    ));
+}
+
+fn write_rdb_list<S: AsRef<str>>(
+    rdb: &mut Vec<u8>,
+    len: usize,
+    elements: impl Iterator<Item = S>,
+    compress: bool,
+) {
+    rdb.extend(encoding::encode_rdb_length(len));
+    for element in elements {
+        rdb.extend(encoding::encode_rdb_string(element.as_ref(), compress));
     }
+}
 
-    let streams = streams
-        .iter()
-        .map(|temp| ReadStreamItem {
-            streams: temp.streams.iter().collect(),
-            key: temp.key.to_string(),
-        })
-        .collect();
+// Streams are stored as a radix tree of listpacks keyed by the ID of the first entry
+// in each node. Every node starts with a master entry and the entries after it only
+// store their ID as a delta from it, and skip the field names if they are the same.
+// https://github.com/redis/redis/blob/unstable/src/t_stream.c
+fn write_stream(rdb: &mut Vec<u8>, stream: &RedisStream, compress: bool) {
+    let nodes = stream.entries.chunks(STREAM_NODE_MAX_ENTRIES);
+    rdb.extend(encoding::encode_rdb_length(nodes.len()));
 
-    let output = encoding::encode_streams(streams);
-    Ok(output)
+    for node in nodes {
+        let master = &node[0];
+        let mut master_id = (master.ms_time as u64).to_be_bytes().to_vec();
+        master_id.extend((master.sequence_number as u64).to_be_bytes());
+
+        let listpack = encoding::encode_listpack(&stream_node_entries(node));
+
+        rdb.extend(encoding::encode_rdb_raw_string(&master_id, compress));
+        rdb.extend(encoding::encode_rdb_raw_string(&listpack, compress));
+    }
+
+    let (last_ms_time, last_sequence_number) = stream.last_id;
+
+    rdb.extend(encoding::encode_rdb_length(stream.entries.len()));
+    rdb.extend(encoding::encode_rdb_length(last_ms_time as usize));
+    rdb.extend(encoding::encode_rdb_length(last_sequence_number));
+    // Consumer groups
+    rdb.extend(encoding::encode_rdb_length(0));
 }
 
-async fn read_streams_until_xadd(
-    read_command_streams: Vec<request::XReadCommandStream>,
-    mut receiver: Receiver<transmission::Transmission>,
-) -> Result<String, anyhow::Error> {
-    loop {
-        let result = receiver.recv().await;
-        match result {
-            Err(e) => anyhow::bail!(e),
-            Ok(transmission) => {
-                if let transmission::Transmission::Xadd(xadd) = transmission {
-                    if read_command_streams.iter().any(|s| {
-                        let is_valid_key = s.key == xadd.key;
-                        let is_valid_entry = stream_entry_greater_than_start(
-                            xadd.ms_time,
-                            xadd.sequence_number,
-                            &s.start,
-                        );
-
-                        is_valid_key && is_valid_entry
-                    }) {
-                        let inner_redis_stream = InnerRedisStream {
-                            ms_time: xadd.ms_time,
-                            sequence_number: xadd.sequence_number,
-                            items: xadd.data,
-                        };
-                        let read_stream_item = ReadStreamItem {
-                            key: xadd.key,
-                            streams: vec![&inner_redis_stream],
-                        };
-                        let output = encoding::encode_streams(vec![read_stream_item]);
-
-                        return Ok(output);
-                    }
+fn stream_node_entries(node: &[InnerRedisStream]) -> Vec<ListpackEntry> {
+    let master = &node[0];
+
+    let mut entries = vec![
+        ListpackEntry::Integer(node.len() as i64),
+        // Deleted entries
+        ListpackEntry::Integer(0),
+        ListpackEntry::Integer(master.items.len() as i64),
+    ];
+    for item in master.items.iter() {
+        entries.push(ListpackEntry::String(item.key.clone()));
+    }
+    // End of the master entry
+    entries.push(ListpackEntry::Integer(0));
+
+    for entry in node {
+        let has_same_fields = entry.items.len() == master.items.len()
+            && entry
+                .items
+                .iter()
+                .zip(master.items.iter())
+                .all(|(item, master_item)| item.key == master_item.key);
+
+        let flags = if has_same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        };
+        let ms_time_diff = entry.ms_time as i64 - master.ms_time as i64;
+        let sequence_number_diff = entry.sequence_number as i64 - master.sequence_number as i64;
+
+        entries.push(ListpackEntry::Integer(flags));
+        entries.push(ListpackEntry::Integer(ms_time_diff));
+        entries.push(ListpackEntry::Integer(sequence_number_diff));
+
+        let num_fields = entry.items.len() as i64;
+        // The number of listpack elements in the entry so it can be walked backwards
+        let lp_count = if has_same_fields {
+            for item in entry.items.iter() {
+                entries.push(ListpackEntry::String(item.value.clone()));
+            }
+            num_fields + 3
+        } else {
+            entries.push(ListpackEntry::Integer(num_fields));
+            for item in entry.items.iter() {
+                entries.push(ListpackEntry::String(item.key.clone()));
+                entries.push(ListpackEntry::String(item.value.clone()));
+            }
+            num_fields * 2 + 4
+        };
+        entries.push(ListpackEntry::Integer(lp_count));
+    }
+
+    entries
+}
+
+fn push_values(list: &mut RedisList, values: Vec<String>, end: request::ListEnd) {
+    match end {
+        request::ListEnd::Left => values.into_iter().for_each(|value| list.push_front(value)),
+        request::ListEnd::Right => list.extend(values),
+    }
+}
+
+fn keyspace_memory(keyspace: &KeyspaceLock) -> usize {
+    {
+        let keyspace = keyspace.read().unwrap();
+        if keyspace.unmeasured.as_ref().is_some_and(HashSet::is_empty) {
+            return keyspace.memory;
+        }
+    }
+
+    KeyspaceGuard(keyspace.write().unwrap()).used_memory()
+}
+
+/// Like redis, offsets below zero count back from the end, and then the range is
+/// clamped to the string, or list. It's empty if it ends before it starts.
+fn byte_range(len: usize, start: i64, end: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.min(len - 1);
+    if len == 0 || start > end {
+        return 0..0;
+    }
+
+    start as usize..end as usize + 1
+}
+
+/// The entries of a stream between `start` and `end`, both inclusive.
+fn entries_in_range<'a>(
+    stream: &'a RedisStream,
+    start: &request::XRangeNumber,
+    end: &request::XRangeNumber,
+) -> Vec<&'a InnerRedisStream> {
+    let mut inner_streams: Vec<&'a InnerRedisStream> = vec![];
+    let mut has_started: bool = false;
+
+    for entry in stream.entries.iter() {
+        if !has_started {
+            match *start {
+                request::XRangeNumber::Unspecified => {
+                    has_started = true;
                 }
+                request::XRangeNumber::Specified(ms_time, sequence_number) => match entry {
+                    entry if entry.ms_time < ms_time => continue,
+                    // If we are at the ms_time but not yet at the sequence number then ignore.
+                    entry
+                        if entry.ms_time == ms_time && entry.sequence_number < sequence_number =>
+                    {
+                        continue
+                    }
+                    _ => {
+                        has_started = true;
+                    }
+                },
             }
         }
+
+        match *end {
+            request::XRangeNumber::Unspecified => {}
+            request::XRangeNumber::Specified(ms_time, sequence_number) => match entry {
+                entry if entry.ms_time > ms_time => break,
+                // Exceeding the sequence_number only matters if we are already at the
+                // end's ms_time.
+                entry if entry.ms_time == ms_time && entry.sequence_number > sequence_number => {
+                    break
+                }
+                _ => {}
+            },
+        }
+
+        inner_streams.push(entry);
+    }
+
+    inner_streams
+}
+
+fn determine_sequence_number(
+    num: request::XAddNumber,
+    ms_time: u128,
+    (last_ms_time, last_sequence_number): (u128, usize),
+) -> usize {
+    if let request::XAddNumber::Predetermined(val) = num {
+        return val;
+    }
+
+    if ms_time == last_ms_time {
+        return last_sequence_number + 1;
     }
+
+    if ms_time == 0 {
+        return 1;
+    }
+
+    0
 }
 
 fn read_streams_sync(
     database: &Database,
     read_command_streams: Vec<request::XReadCommandStream>,
-) -> Result<String, anyhow::Error> {
-    let database = database.0.read().unwrap();
+) -> Result<Value, RedisError> {
+    let database = database.keyspace.read().unwrap();
 
     let mut streams: Vec<ReadStreamItem> = Vec::with_capacity(read_command_streams.len());
     for command_stream in read_command_streams.iter() {
-        let stream = match database.get(&command_stream.key) {
+        let stream = match database.get(command_stream.key.as_str()) {
             Some(item) => match &item {
                 DatabaseItem::Stream(stream) => stream,
                 _ => {
-                    anyhow::bail!(wrong_type_str());
+                    return Err(RedisError::WrongType);
                 }
             },
             None => continue,
@@ -1017,7 +3719,7 @@ fn read_streams_sync(
         let mut inner_streams: Vec<&InnerRedisStream> = vec![];
         let mut has_started: bool = false;
 
-        for entry in stream.0.iter() {
+        for entry in stream.entries.iter() {
             if !has_started {
                 has_started = stream_entry_greater_than_start(
                     entry.ms_time,
@@ -1041,9 +3743,9 @@ fn read_streams_sync(
     }
 
     let output = if streams.is_empty() {
-        empty_string()
+        Value::Null
     } else {
-        encoding::encode_streams(streams)
+        encoding::streams_value(streams)
     };
 
     Ok(output)
@@ -1070,42 +3772,20 @@ fn stream_entry_greater_than_start(
     }
 }
 
-fn adjust_float_value_by_int(data: &str, amount: i64) -> Result<String, anyhow::Error> {
+fn adjust_float_value_by_int(data: &str, amount: i64) -> Result<String, RedisError> {
     let value = data
         .parse::<f64>()
-        .map_err(|_| anyhow::anyhow!("ERR value is not a float or out of range"))?;
+        .map_err(|_| RedisError::custom("ERR value is not a float or out of range"))?;
 
     let value = value + amount as f64;
     Ok(value.to_string())
 }
 
-fn adjust_float_value_by_float(data: &str, amount: f64) -> Result<String, anyhow::Error> {
+fn adjust_float_value_by_float(data: &str, amount: f64) -> Result<String, RedisError> {
     let value = data
         .parse::<f64>()
-        .map_err(|_| anyhow::anyhow!("ERR value is not a float or out of range"))?;
+        .map_err(|_| RedisError::custom("ERR value is not a float or out of range"))?;
 
     let value = value + amount;
     Ok(value.to_string())
 }
-
-fn adjust_int_value_by_int(data: &str, amount: i64) -> Result<String, anyhow::Error> {
-    let value = data
-        .parse::<i64>()
-        .map_err(|_| anyhow::anyhow!("ERR value is not an integer or out of range"))?;
-
-    let value = value
-        .checked_add(amount)
-        .ok_or_else(|| anyhow::anyhow!("ERR increment or decrement would overflow"))?;
-
-    Ok(value.to_string())
-}
-
-fn adjust_int_value_by_float(data: &str, amount: f64) -> Result<String, anyhow::Error> {
-    let value = data
-        .parse::<i64>()
-        .map_err(|_| anyhow::anyhow!("ERR value is not an integer or out of range"))?;
-
-    let value = (value as f64) + amount;
-
-    Ok(value.to_string())
-}
@@ -1,4 +1,5 @@
 use crate::data;
+use crate::resp::Value;
 
 fn encode_string_array_length(size: usize) -> String {
     format!("*{}\r\n", size)
@@ -18,39 +19,53 @@ pub fn encode_string_array(input: &[&str]) -> String {
     result
 }
 
-pub fn encode_stream(stream: &[&data::InnerRedisStream]) -> String {
-    let mut output = encode_string_array_length(stream.len());
+/// Fields and their values one after the other in a flat array, like HGETALL
+/// replies with them.
+pub fn field_values_value<'a>(pairs: impl IntoIterator<Item = (&'a String, &'a String)>) -> Value {
+    let items = pairs
+        .into_iter()
+        .flat_map(|(field, value)| [Value::from(field.as_str()), Value::from(value.as_str())])
+        .collect();
 
-    for inner in stream.iter() {
-        let stream_id = inner.stream_id();
-        output.push_str(&encode_string_array_length(2));
-        output.push_str(&encode_string_array_item(&stream_id));
-
-        let mut stream_items: Vec<&str> = vec![];
-        for item in inner.items.iter() {
-            stream_items.push(item.key.as_str());
-            stream_items.push(item.value.as_str());
-        }
-
-        let encoded = encode_string_array(stream_items.as_slice());
-        output.push_str(&encoded)
-    }
-
-    output
+    Value::Array(items)
 }
 
-pub fn encode_streams(read_streams: Vec<data::ReadStreamItem>) -> String {
-    let mut output = encode_string_array_length(read_streams.len());
-
-    for item in read_streams.iter() {
-        output.push_str(&encode_string_array_length(2));
-        output.push_str(&encode_string_array_item(&item.key));
-
-        let stream_encode = encode_stream(&item.streams);
-        output.push_str(&stream_encode);
-    }
+/// The entries of a stream as XRANGE replies with them, each one its id
+/// followed by its fields and values.
+pub fn stream_value(stream: &[&data::InnerRedisStream]) -> Value {
+    let entries = stream
+        .iter()
+        .map(|inner| {
+            let fields = inner
+                .items
+                .iter()
+                .flat_map(|item| {
+                    [
+                        Value::from(item.key.as_str()),
+                        Value::from(item.value.as_str()),
+                    ]
+                })
+                .collect();
+            Value::Array(vec![Value::from(inner.stream_id()), Value::Array(fields)])
+        })
+        .collect();
+
+    Value::Array(entries)
+}
 
-    output
+/// The reply to XREAD, the new entries of every stream along with its key.
+pub fn streams_value(read_streams: Vec<data::ReadStreamItem>) -> Value {
+    let streams = read_streams
+        .iter()
+        .map(|item| {
+            Value::Array(vec![
+                Value::from(item.key.as_str()),
+                stream_value(&item.streams),
+            ])
+        })
+        .collect();
+
+    Value::Array(streams)
 }
 
 #[cfg(test)]
@@ -93,8 +108,9 @@ mod tests {
         };
 
         let stream = vec![&inner_1, &inner_2, &inner_3];
-        let got = encode_stream(stream.as_slice());
+        let got = stream_value(stream.as_slice()).encode();
 
+        let got = std::str::from_utf8(&got).unwrap();
         let got_items: Vec<&str> = got.split("\r\n").collect();
         let want_items: Vec<&str> = vec![
             "*3",
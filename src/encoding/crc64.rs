@@ -0,0 +1,25 @@
+// Redis uses the Jones polynomial in its reflected form with no final xor.
+// https://github.com/redis/redis/blob/unstable/src/crc64.c
+const POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+pub fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, byte| {
+        (0..8).fold(crc ^ u64::from(*byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64_matches_redis() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+}
@@ -0,0 +1,265 @@
+// https://github.com/antirez/listpack/blob/master/listpack.md
+const HEADER_SIZE: usize = 6;
+const END_BYTE: u8 = 0xFF;
+const UNKNOWN_NUM_ELEMENTS: u16 = u16::MAX;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ListpackEntry {
+    Integer(i64),
+    String(String),
+}
+
+impl ListpackEntry {
+    pub fn to_integer(&self) -> Result<i64, anyhow::Error> {
+        match self {
+            ListpackEntry::Integer(value) => Ok(*value),
+            ListpackEntry::String(value) => value
+                .parse::<i64>()
+                .map_err(|_| anyhow::anyhow!("Expected a listpack integer, got {}", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for ListpackEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListpackEntry::Integer(value) => write!(f, "{}", value),
+            ListpackEntry::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+pub fn encode_listpack(entries: &[ListpackEntry]) -> Vec<u8> {
+    let mut body: Vec<u8> = vec![];
+
+    for entry in entries {
+        let encoded = match entry {
+            ListpackEntry::Integer(value) => encode_integer(*value),
+            ListpackEntry::String(value) => encode_string(value.as_bytes()),
+        };
+        let backlen = encode_backlen(encoded.len());
+
+        body.extend(encoded);
+        body.extend(backlen);
+    }
+
+    let total_bytes = (HEADER_SIZE + body.len() + 1) as u32;
+    let num_elements = u16::try_from(entries.len()).unwrap_or(UNKNOWN_NUM_ELEMENTS);
+
+    let mut listpack = Vec::with_capacity(total_bytes as usize);
+    listpack.extend(total_bytes.to_le_bytes());
+    listpack.extend(num_elements.to_le_bytes());
+    listpack.extend(body);
+    listpack.push(END_BYTE);
+
+    listpack
+}
+
+fn encode_integer(value: i64) -> Vec<u8> {
+    match value {
+        // 7 bit unsigned integer: 0xxxxxxx
+        0..=127 => vec![value as u8],
+        // 13 bit signed integer: 110xxxxx yyyyyyyy
+        -4096..=4095 => {
+            let value = (value as u16) & 0x1FFF;
+            vec![0xC0 | (value >> 8) as u8, (value & 0xFF) as u8]
+        }
+        _ if i16::try_from(value).is_ok() => {
+            let mut encoded = vec![0xF1];
+            encoded.extend((value as i16).to_le_bytes());
+            encoded
+        }
+        -8_388_608..=8_388_607 => {
+            let mut encoded = vec![0xF2];
+            encoded.extend(&(value as i32).to_le_bytes()[..3]);
+            encoded
+        }
+        _ if i32::try_from(value).is_ok() => {
+            let mut encoded = vec![0xF3];
+            encoded.extend((value as i32).to_le_bytes());
+            encoded
+        }
+        _ => {
+            let mut encoded = vec![0xF4];
+            encoded.extend(value.to_le_bytes());
+            encoded
+        }
+    }
+}
+
+fn encode_string(value: &[u8]) -> Vec<u8> {
+    let len = value.len();
+    let mut encoded = match len {
+        // 6 bit length string: 10xxxxxx
+        0..=63 => vec![0x80 | len as u8],
+        // 12 bit length string: 1110xxxx yyyyyyyy
+        64..=4095 => vec![0xE0 | (len >> 8) as u8, (len & 0xFF) as u8],
+        _ => {
+            let mut encoded = vec![0xF0];
+            encoded.extend((len as u32).to_le_bytes());
+            encoded
+        }
+    };
+    encoded.extend(value);
+    encoded
+}
+
+pub fn decode_listpack(listpack: &[u8]) -> Result<Vec<ListpackEntry>, anyhow::Error> {
+    if listpack.len() < HEADER_SIZE + 1 {
+        anyhow::bail!("Listpack is too short: {} bytes", listpack.len());
+    }
+
+    let mut entries: Vec<ListpackEntry> = vec![];
+    let mut position = HEADER_SIZE;
+
+    loop {
+        let byte = *listpack
+            .get(position)
+            .ok_or_else(|| anyhow::anyhow!("Listpack is missing its end byte"))?;
+        if byte == END_BYTE {
+            break;
+        }
+
+        let (entry, encoded_len) = decode_entry(&listpack[position..])?;
+        entries.push(entry);
+        position += encoded_len + backlen_size(encoded_len);
+    }
+
+    Ok(entries)
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<(ListpackEntry, usize), anyhow::Error> {
+    let read = |start: usize, len: usize| -> Result<&[u8], anyhow::Error> {
+        bytes
+            .get(start..start + len)
+            .ok_or_else(|| anyhow::anyhow!("Listpack entry is truncated"))
+    };
+    let read_string = |start: usize, len: usize| -> Result<(ListpackEntry, usize), anyhow::Error> {
+        let value = String::from_utf8(read(start, len)?.to_vec())?;
+        Ok((ListpackEntry::String(value), start + len))
+    };
+
+    let byte = bytes[0];
+    match byte {
+        // 7 bit unsigned integer
+        0x00..=0x7F => Ok((ListpackEntry::Integer(byte as i64), 1)),
+        // 6 bit length string
+        0x80..=0xBF => read_string(1, (byte & 0x3F) as usize),
+        // 13 bit signed integer
+        0xC0..=0xDF => {
+            let low = read(1, 1)?[0];
+            let value = (((byte & 0x1F) as u16) << 8) | low as u16;
+            // Sign extend from 13 bits
+            let value = ((value << 3) as i16 >> 3) as i64;
+            Ok((ListpackEntry::Integer(value), 2))
+        }
+        // 12 bit length string
+        0xE0..=0xEF => {
+            let low = read(1, 1)?[0];
+            read_string(2, (((byte & 0x0F) as usize) << 8) | low as usize)
+        }
+        0xF0 => {
+            let len = read(1, 4)?;
+            read_string(
+                5,
+                u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize,
+            )
+        }
+        0xF1 => {
+            let value = read(1, 2)?;
+            let value = i16::from_le_bytes([value[0], value[1]]) as i64;
+            Ok((ListpackEntry::Integer(value), 3))
+        }
+        0xF2 => {
+            let value = read(1, 3)?;
+            // Shift into the top of an i32 so the sign is kept
+            let value = i32::from_le_bytes([0, value[0], value[1], value[2]]) >> 8;
+            Ok((ListpackEntry::Integer(value as i64), 4))
+        }
+        0xF3 => {
+            let value = read(1, 4)?;
+            let value = i32::from_le_bytes([value[0], value[1], value[2], value[3]]) as i64;
+            Ok((ListpackEntry::Integer(value), 5))
+        }
+        0xF4 => {
+            let value: [u8; 8] = read(1, 8)?.try_into()?;
+            Ok((ListpackEntry::Integer(i64::from_le_bytes(value)), 9))
+        }
+        other => anyhow::bail!("Unrecognized listpack encoding: {:#04x}", other),
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    encode_backlen(len).len()
+}
+
+// The backlen is stored so that it can be read right to left: the most significant
+// 7 bits come first and every byte but the first has its high bit set.
+fn encode_backlen(len: usize) -> Vec<u8> {
+    let mut groups: Vec<u8> = vec![(len & 127) as u8];
+    let mut remaining = len >> 7;
+    while remaining > 0 {
+        groups.push((remaining & 127) as u8);
+        remaining >>= 7;
+    }
+    groups.reverse();
+
+    for byte in groups.iter_mut().skip(1) {
+        *byte |= 128;
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_listpack() {
+        let got = encode_listpack(&[
+            ListpackEntry::Integer(1),
+            ListpackEntry::Integer(-1),
+            ListpackEntry::String("foo".to_string()),
+        ]);
+
+        let want: Vec<u8> = vec![
+            // total bytes and number of elements
+            17, 0, 0, 0, 3, 0, //
+            // 7 bit integer and its backlen
+            0x01, 1, //
+            // 13 bit integer -1 and its backlen
+            0xDF, 0xFF, 2, //
+            // 6 bit string and its backlen
+            0x83, b'f', b'o', b'o', 4, //
+            0xFF,
+        ];
+
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_decode_listpack_round_trip() {
+        let entries = vec![
+            ListpackEntry::Integer(1),
+            ListpackEntry::Integer(-1),
+            ListpackEntry::Integer(-3000),
+            ListpackEntry::Integer(20000),
+            ListpackEntry::Integer(-5_000_000),
+            ListpackEntry::Integer(1 << 30),
+            ListpackEntry::Integer(i64::MIN),
+            ListpackEntry::String("foo".to_string()),
+            ListpackEntry::String("a".repeat(200)),
+            ListpackEntry::String("b".repeat(5000)),
+        ];
+
+        let listpack = encode_listpack(&entries);
+        assert_eq!(decode_listpack(&listpack).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_encode_backlen() {
+        assert_eq!(encode_backlen(5), vec![5]);
+        assert_eq!(encode_backlen(500), vec![3, 128 | 116]);
+    }
+}
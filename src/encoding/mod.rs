@@ -1,11 +1,21 @@
 mod array;
+mod crc64;
 mod integer;
+mod listpack;
 mod rdb;
 mod strings;
+mod writer;
 
-pub use array::{encode_stream, encode_streams, encode_string_array};
+pub use array::{encode_string_array, field_values_value, stream_value, streams_value};
+pub use crc64::crc64;
 pub use integer::encode_integer;
-pub use rdb::{decode_rdb_int, decode_rdb_string, encode_rdb};
+pub use listpack::{decode_listpack, encode_listpack, ListpackEntry};
+pub use rdb::{
+    decode_module_value, decode_rdb_double, decode_rdb_int, decode_rdb_raw_string,
+    decode_rdb_string, encode_module_value, encode_rdb, encode_rdb_length, encode_rdb_raw_string,
+    encode_rdb_string, is_valid_module_type_name, ModuleField,
+};
 pub use strings::{
     bulk_string, bulk_string_from_hashmap, empty_string, error_string, okay_string, simple_string,
 };
+pub use writer::{put_array_len, put_bulk_string, put_integer, put_null, put_simple_string};
@@ -9,12 +9,133 @@ const LEADING_BYTE_LENGTH_ENCODING_RIGHT_SHIFT: u8 = 6;
 // Indicates how many bytes the special format will
 const LEADING_BYTE_MINUS_LENGTH_BIT_MASK: u8 = 0b0011_1111;
 
+const SIX_BIT_LENGTH: u8 = 0b0000_0000;
+const FOURTEEN_BIT_LENGTH: u8 = 0b0100_0000;
+const THIRTY_TWO_BIT_LENGTH: u8 = 0x80;
+const SIXTY_FOUR_BIT_LENGTH: u8 = 0x81;
+const SPECIAL_FORMAT: u8 = 0b1100_0000;
+const LZF_COMPRESSED_STRING: u8 = 0b11;
+const MIN_COMPRESSIBLE_STRING_LENGTH: usize = 20;
+
 pub fn encode_rdb(rdb_bytes: Vec<u8>) -> Vec<u8> {
     let mut vec: Vec<u8> = format!("${}\r\n", rdb_bytes.len()).into();
     vec.extend(rdb_bytes);
     vec
 }
 
+pub fn encode_rdb_length(length: usize) -> Vec<u8> {
+    match length {
+        0..=63 => vec![SIX_BIT_LENGTH | length as u8],
+        64..=16383 => vec![FOURTEEN_BIT_LENGTH | (length >> 8) as u8, length as u8],
+        _ => match u32::try_from(length) {
+            Ok(length) => {
+                let mut encoded = vec![THIRTY_TWO_BIT_LENGTH];
+                encoded.extend(length.to_be_bytes());
+                encoded
+            }
+            Err(_) => {
+                let mut encoded = vec![SIXTY_FOUR_BIT_LENGTH];
+                encoded.extend((length as u64).to_be_bytes());
+                encoded
+            }
+        },
+    }
+}
+
+/// Strings that are plain integers are stored with the integer encodings the same
+/// way redis does. Only non-negative values are used since the decoder reads them
+/// back unsigned.
+pub fn encode_rdb_string(value: &str, compress: bool) -> Vec<u8> {
+    if let Some(encoded) = encode_rdb_integer_string(value) {
+        return encoded;
+    }
+
+    encode_rdb_raw_string(value.as_bytes(), compress)
+}
+
+pub fn encode_rdb_raw_string(value: &[u8], compress: bool) -> Vec<u8> {
+    if compress {
+        if let Some(encoded) = encode_lzf_compressed_string(value) {
+            return encoded;
+        }
+    }
+
+    let mut encoded = encode_rdb_length(value.len());
+    encoded.extend(value);
+    encoded
+}
+
+// Like redis, short strings aren't worth compressing and we only keep the compressed
+// version if it saves at least a few bytes.
+fn encode_lzf_compressed_string(value: &[u8]) -> Option<Vec<u8>> {
+    if value.len() <= MIN_COMPRESSIBLE_STRING_LENGTH {
+        return None;
+    }
+
+    let compressed = lzf::compress(value).ok()?;
+    if compressed.len() + 4 > value.len() {
+        return None;
+    }
+
+    let mut encoded = vec![SPECIAL_FORMAT | LZF_COMPRESSED_STRING];
+    encoded.extend(encode_rdb_length(compressed.len()));
+    encoded.extend(encode_rdb_length(value.len()));
+    encoded.extend(compressed);
+    Some(encoded)
+}
+
+fn encode_rdb_integer_string(value: &str) -> Option<Vec<u8>> {
+    let integer = value.parse::<i64>().ok()?;
+    // Leading zeroes or a plus sign wouldn't survive the round trip
+    if integer.to_string() != value || integer < 0 {
+        return None;
+    }
+
+    let encoded = if let Ok(integer) = i8::try_from(integer) {
+        vec![SPECIAL_FORMAT, integer as u8]
+    } else if let Ok(integer) = i16::try_from(integer) {
+        let mut encoded = vec![SPECIAL_FORMAT | 0b01];
+        encoded.extend(integer.to_le_bytes());
+        encoded
+    } else if let Ok(integer) = i32::try_from(integer) {
+        let mut encoded = vec![SPECIAL_FORMAT | 0b10];
+        encoded.extend(integer.to_le_bytes());
+        encoded
+    } else {
+        return None;
+    };
+
+    Some(encoded)
+}
+
+/// Reads a string that isn't necessarily valid utf8, such as the listpacks streams are stored in.
+pub fn decode_rdb_raw_string(cursor: &mut Cursor<Vec<u8>>) -> Result<Vec<u8>, anyhow::Error> {
+    let val = match LengthEncoding::from_cursor(cursor)? {
+        LengthEncoding::OnlyThisByte(length)
+        | LengthEncoding::AndNextByte(length)
+        | LengthEncoding::ReadNextFourBytes(length) => {
+            read_bytes(length, cursor).context("Reading raw string")
+        }
+        LengthEncoding::SpecialFormatEncoding(byte) => {
+            let string_length_encoding = StringLengthEncoding::from_byte(byte)?;
+            match string_length_encoding {
+                StringLengthEncoding::EightBitInteger => {
+                    read_8_bit_integer_as_string(cursor).map(String::into_bytes)
+                }
+                StringLengthEncoding::SixteenBitInteger => {
+                    read_16_bit_integer_as_string(cursor).map(String::into_bytes)
+                }
+                StringLengthEncoding::ThirtyTwoBitInteger => {
+                    read_32_bit_integer_as_string(cursor).map(String::into_bytes)
+                }
+                StringLengthEncoding::CompressedString => read_lzf_compressed_bytes(cursor),
+            }
+        }
+    }?;
+
+    Ok(val)
+}
+
 pub fn decode_rdb_string(cursor: &mut Cursor<Vec<u8>>) -> Result<String, anyhow::Error> {
     let val = match LengthEncoding::from_cursor(cursor)? {
         LengthEncoding::OnlyThisByte(length) => read_known_length_string(length, cursor),
@@ -38,12 +159,9 @@ fn read_known_length_string(
     length: usize,
     cursor: &mut Cursor<Vec<u8>>,
 ) -> Result<String, anyhow::Error> {
-    let mut val = vec![0; length];
-    cursor
-        .read_exact(&mut val)
-        .context("Reading known length string")?;
+    let val = read_bytes(length, cursor).context("Reading known length string")?;
 
-    let result = String::from_utf8(val.to_vec())?;
+    let result = String::from_utf8(val)?;
 
     Ok(result)
 }
@@ -82,17 +200,38 @@ fn read_32_bit_integer_as_string(cursor: &mut Cursor<Vec<u8>>) -> Result<String,
 }
 
 fn read_lzf_compressed_string(cursor: &mut Cursor<Vec<u8>>) -> Result<String, anyhow::Error> {
+    let decompressed = read_lzf_compressed_bytes(cursor)?;
+    let decompressed = String::from_utf8(decompressed)?;
+    Ok(decompressed)
+}
+
+fn read_lzf_compressed_bytes(cursor: &mut Cursor<Vec<u8>>) -> Result<Vec<u8>, anyhow::Error> {
     let clen = read_compressed_len(cursor)?;
     let ulen = read_compressed_len(cursor)?;
 
-    let compressed_string = read_known_length_string(clen, cursor)?;
-    let decompressed = lzf::decompress(compressed_string.as_bytes(), ulen)
-        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    let compressed = read_bytes(clen, cursor).context("Reading compressed string")?;
+    let decompressed = lzf::decompress(&compressed, ulen).map_err(|e| anyhow::anyhow!("{}", e))?;
 
-    let decompressed = String::from_utf8(decompressed)?;
     Ok(decompressed)
 }
 
+/// Reads `length` bytes, checking first that there are that many left so a
+/// corrupt length doesn't make us allocate more than the whole file.
+fn read_bytes(length: usize, cursor: &mut Cursor<Vec<u8>>) -> Result<Vec<u8>, anyhow::Error> {
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if remaining < length as u64 {
+        anyhow::bail!(
+            "{} bytes were expected but only {} are left",
+            length,
+            remaining
+        );
+    }
+
+    let mut bytes = vec![0; length];
+    cursor.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
 fn read_compressed_len(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Error> {
     match LengthEncoding::from_cursor(cursor)? {
         LengthEncoding::OnlyThisByte(length) => Ok(length),
@@ -130,15 +269,24 @@ impl LengthEncoding {
                 let byte = utils::read_next_byte(cursor)
                     .context("Read next byte to determine length encoded size")?;
 
-                let length = u16::from(start_length) + u16::from(byte);
+                // The remaining six bits are the most significant part of a big endian u14
+                let length = (u16::from(start_length) << 8) | u16::from(byte);
 
                 Ok(Self::AndNextByte(length as usize))
             }
+            0b10 if byte == SIXTY_FOUR_BIT_LENGTH => {
+                let mut size_bytes: [u8; 8] = [0; 8];
+                cursor.read_exact(&mut size_bytes)?;
+
+                let length = u64::from_be_bytes(size_bytes);
+
+                Ok(Self::ReadNextFourBytes(length as usize))
+            }
             0b10 => {
                 let mut size_bytes: [u8; 4] = [0; 4];
                 cursor.read_exact(&mut size_bytes)?;
 
-                let length = u32::from_le_bytes(size_bytes);
+                let length = u32::from_be_bytes(size_bytes);
 
                 Ok(Self::ReadNextFourBytes(length as usize))
             }
@@ -168,6 +316,147 @@ impl StringLengthEncoding {
     }
 }
 
+// Doubles in the old sorted set encoding are a length byte followed by the score as a
+// string, with three lengths reserved for the values that can't be written that way.
+pub fn decode_rdb_double(cursor: &mut Cursor<Vec<u8>>) -> Result<f64, anyhow::Error> {
+    let length = utils::read_next_byte(cursor).context("Reading double length")?;
+    let value = match length {
+        253 => f64::NAN,
+        254 => f64::INFINITY,
+        255 => f64::NEG_INFINITY,
+        length => {
+            let value = read_known_length_string(length as usize, cursor)?;
+            str::parse::<f64>(&value).context("Parsing double")?
+        }
+    };
+
+    Ok(value)
+}
+
+// Module types are identified in RDB files by a 64 bit id: the nine character type
+// name, six bits per character, followed by ten bits of encoding version.
+const MODULE_TYPE_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Whether a module type can be saved under the name, which takes nine
+/// characters from the ones the module id can hold.
+pub fn is_valid_module_type_name(name: &str) -> bool {
+    name.len() == 9 && name.bytes().all(|c| MODULE_TYPE_CHARSET.contains(&c))
+}
+
+fn encode_module_id(name: &str, encoding_version: u16) -> u64 {
+    let id = name.bytes().fold(0u64, |id, c| {
+        let position = MODULE_TYPE_CHARSET
+            .iter()
+            .position(|allowed| *allowed == c)
+            .expect("module type names only use the allowed characters");
+        (id << 6) | position as u64
+    });
+
+    (id << 10) | u64::from(encoding_version & 1023)
+}
+
+/// The type name and encoding version in a module id.
+fn decode_module_id(id: u64) -> (String, u16) {
+    let name = (0..9)
+        .rev()
+        .map(|i| MODULE_TYPE_CHARSET[((id >> (10 + i * 6)) & 63) as usize] as char)
+        .collect();
+
+    (name, (id & 1023) as u16)
+}
+
+// Each field of a module value is preceded by its type, and the value ends with an EOF
+const MODULE_OPCODE_EOF: usize = 0;
+const MODULE_OPCODE_UINT: usize = 2;
+const MODULE_OPCODE_DOUBLE: usize = 4;
+const MODULE_OPCODE_STRING: usize = 5;
+
+/// What a module type saves its values as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleField {
+    Uint(u64),
+    Double(f64),
+    String(Vec<u8>),
+}
+
+impl ModuleField {
+    pub fn as_uint(&self) -> Result<u64, anyhow::Error> {
+        match self {
+            ModuleField::Uint(value) => Ok(*value),
+            other => anyhow::bail!("Expected an unsigned integer, found {:?}", other),
+        }
+    }
+
+    pub fn as_double(&self) -> Result<f64, anyhow::Error> {
+        match self {
+            ModuleField::Double(value) => Ok(*value),
+            other => anyhow::bail!("Expected a double, found {:?}", other),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8], anyhow::Error> {
+        match self {
+            ModuleField::String(value) => Ok(value),
+            other => anyhow::bail!("Expected a string, found {:?}", other),
+        }
+    }
+}
+
+/// A module value as it follows its key: the module id, then every field.
+pub fn encode_module_value(
+    name: &str,
+    encoding_version: u16,
+    fields: &[ModuleField],
+    compress: bool,
+) -> Vec<u8> {
+    let mut encoded = encode_rdb_length(encode_module_id(name, encoding_version) as usize);
+    for field in fields {
+        match field {
+            ModuleField::Uint(value) => {
+                encoded.extend(encode_rdb_length(MODULE_OPCODE_UINT));
+                encoded.extend(encode_rdb_length(*value as usize));
+            }
+            ModuleField::Double(value) => {
+                encoded.extend(encode_rdb_length(MODULE_OPCODE_DOUBLE));
+                encoded.extend(value.to_le_bytes());
+            }
+            ModuleField::String(value) => {
+                encoded.extend(encode_rdb_length(MODULE_OPCODE_STRING));
+                encoded.extend(encode_rdb_raw_string(value, compress));
+            }
+        }
+    }
+    encoded.extend(encode_rdb_length(MODULE_OPCODE_EOF));
+
+    encoded
+}
+
+/// The module type name, its encoding version and the fields of a module value.
+pub fn decode_module_value(
+    cursor: &mut Cursor<Vec<u8>>,
+) -> Result<(String, u16, Vec<ModuleField>), anyhow::Error> {
+    let (name, encoding_version) = decode_module_id(decode_rdb_int(cursor)? as u64);
+
+    let mut fields = vec![];
+    loop {
+        let field = match decode_rdb_int(cursor)? {
+            MODULE_OPCODE_EOF => break,
+            MODULE_OPCODE_UINT => ModuleField::Uint(decode_rdb_int(cursor)? as u64),
+            MODULE_OPCODE_DOUBLE => {
+                let mut bytes = [0; 8];
+                cursor.read_exact(&mut bytes)?;
+                ModuleField::Double(f64::from_le_bytes(bytes))
+            }
+            MODULE_OPCODE_STRING => ModuleField::String(decode_rdb_raw_string(cursor)?),
+            opcode => anyhow::bail!("Unknown module opcode {} in a {} value", opcode, name),
+        };
+        fields.push(field);
+    }
+
+    Ok((name, encoding_version, fields))
+}
+
 pub fn decode_rdb_int(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Error> {
     match LengthEncoding::from_cursor(cursor)? {
         LengthEncoding::OnlyThisByte(size) => Ok(size),
@@ -188,3 +477,83 @@ pub fn decode_rdb_int(cursor: &mut Cursor<Vec<u8>>) -> Result<usize, anyhow::Err
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_id_round_trip() {
+        let id = encode_module_id("ReJSON-RL", 3);
+        assert_eq!(decode_module_id(id), ("ReJSON-RL".to_string(), 3));
+
+        let mut cursor = Cursor::new(encode_rdb_length(id as usize));
+        assert_eq!(decode_rdb_int(&mut cursor).unwrap() as u64, id);
+    }
+
+    #[test]
+    fn test_module_value_round_trip() {
+        let fields = vec![
+            ModuleField::Uint(u64::MAX),
+            ModuleField::Double(0.01),
+            ModuleField::String(vec![0, 255, 7]),
+        ];
+        let encoded = encode_module_value("MBbloom--", 4, &fields, true);
+
+        let mut cursor = Cursor::new(encoded);
+        let decoded = decode_module_value(&mut cursor).unwrap();
+        assert_eq!(decoded, ("MBbloom--".to_string(), 4, fields));
+    }
+
+    #[test]
+    fn test_rdb_string_round_trip() {
+        let long = "a".repeat(300);
+        let longer = "b".repeat(20000);
+        for value in [
+            "",
+            "foo",
+            "12",
+            "-12",
+            "300",
+            "70000",
+            "007",
+            long.as_str(),
+            longer.as_str(),
+        ] {
+            for compress in [false, true] {
+                let mut cursor = Cursor::new(encode_rdb_string(value, compress));
+                assert_eq!(decode_rdb_string(&mut cursor).unwrap(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rdb_string_compression() {
+        let compressible = "abc".repeat(100);
+        let encoded = encode_rdb_string(&compressible, true);
+        assert_eq!(encoded[0], SPECIAL_FORMAT | LZF_COMPRESSED_STRING);
+        assert!(encoded.len() < compressible.len());
+
+        // Short strings are left alone
+        assert_eq!(
+            encode_rdb_string("foofoofoo", true),
+            encode_rdb_string("foofoofoo", false)
+        );
+    }
+
+    #[test]
+    fn test_rdb_length_round_trip() {
+        for length in [0, 63, 64, 16383, 16384, 1 << 31] {
+            let mut cursor = Cursor::new(encode_rdb_length(length));
+            assert_eq!(decode_rdb_int(&mut cursor).unwrap(), length);
+        }
+    }
+
+    #[test]
+    fn test_rdb_string_longer_than_what_is_left() {
+        let mut encoded = encode_rdb_length(1 << 31);
+        encoded.extend(b"short");
+        let mut cursor = Cursor::new(encoded);
+        assert!(decode_rdb_string(&mut cursor).is_err());
+    }
+}
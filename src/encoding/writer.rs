@@ -0,0 +1,58 @@
+use bytes::{BufMut, BytesMut};
+
+// Writers for building a reply straight into the buffer that is sent to the client,
+// so large replies are encoded once instead of going through a String per element.
+
+pub fn put_array_len(buf: &mut BytesMut, len: usize) {
+    put_line(buf, b'*', len.to_string().as_bytes());
+}
+
+pub fn put_bulk_string(buf: &mut BytesMut, value: &[u8]) {
+    put_line(buf, b'$', value.len().to_string().as_bytes());
+    buf.reserve(value.len() + 2);
+    buf.put_slice(value);
+    buf.put_slice(b"\r\n");
+}
+
+pub fn put_simple_string(buf: &mut BytesMut, value: &str) {
+    put_line(buf, b'+', value.as_bytes());
+}
+
+pub fn put_integer(buf: &mut BytesMut, value: i64) {
+    put_line(buf, b':', value.to_string().as_bytes());
+}
+
+pub fn put_null(buf: &mut BytesMut) {
+    buf.put_slice(b"$-1\r\n");
+}
+
+fn put_line(buf: &mut BytesMut, prefix: u8, line: &[u8]) {
+    buf.reserve(line.len() + 3);
+    buf.put_u8(prefix);
+    buf.put_slice(line);
+    buf.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{bulk_string, encode_integer, simple_string};
+
+    #[test]
+    fn writers_match_the_string_encoders() {
+        let mut buf = BytesMut::new();
+        put_array_len(&mut buf, 4);
+        put_bulk_string(&mut buf, b"hello");
+        put_simple_string(&mut buf, "OK");
+        put_integer(&mut buf, -12);
+        put_null(&mut buf);
+
+        let want = format!(
+            "*4\r\n{}{}{}$-1\r\n",
+            bulk_string("hello"),
+            simple_string("OK"),
+            encode_integer(-12)
+        );
+        assert_eq!(&buf[..], want.as_bytes());
+    }
+}
@@ -1,11 +1,144 @@
-pub fn wrong_type_str<'a>() -> &'a str {
-    "WRONGTYPE Operation against a key holding the wrong kind of value"
+use std::sync::PoisonError;
+
+use crate::resp::Value;
+
+/// Why a command failed. Every error a client can cause ends up as one of these,
+/// and `to_value` is the one place they're turned into the error sent back, so a
+/// failed command always gets an error reply and the connection carries on. Only a
+/// `ProtocolError` closes the connection.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RedisError {
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("READONLY You can't write against a read only replica")]
+    Readonly,
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+    #[error("MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.")]
+    MasterDown,
+    /// A GETRANGE or SETRANGE offset that falls inside a character.
+    #[error("ERR offset would split a multibyte character")]
+    SplitCharacter,
+    /// Anything else, with the message sent as is. Use `RedisError::custom` so
+    /// the message gets an error code.
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl RedisError {
+    /// An error with the given message. Messages that don't start with an error
+    /// code, like WRONGTYPE, are reported as a generic ERR.
+    pub fn custom(message: impl Into<String>) -> Self {
+        let message: String = message.into();
+        // Errors are sent as a single line
+        let message = message
+            .lines()
+            .map(str::trim_start)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let code = message.split_whitespace().next().unwrap_or_default();
+
+        let has_code = !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase());
+        if has_code {
+            RedisError::Custom(message)
+        } else {
+            RedisError::Custom(format!("ERR {}", message))
+        }
+    }
+
+    /// The error for an error reply read back from a server, e.g. by the client.
+    pub fn from_reply(message: String) -> Self {
+        [
+            RedisError::WrongType,
+            RedisError::NotAnInteger,
+            RedisError::Syntax,
+            RedisError::NoSuchKey,
+            RedisError::Readonly,
+            RedisError::MasterDown,
+        ]
+        .into_iter()
+        .find(|error| error.to_string() == message)
+        .unwrap_or(RedisError::Custom(message))
+    }
+
+    /// The error reply sent to the client.
+    pub fn to_value(&self) -> Value {
+        Value::Error(self.to_string())
+    }
+}
+
+/// Failures that aren't the client's doing, like an RDB file that couldn't be
+/// written, are passed on with their message.
+impl From<anyhow::Error> for RedisError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<RedisError>() {
+            Ok(error) => error,
+            Err(error) => RedisError::custom(error.to_string()),
+        }
+    }
 }
 
-pub fn wrong_type() -> anyhow::Error {
-    anyhow::anyhow!(wrong_type_str())
+impl<T> From<PoisonError<T>> for RedisError {
+    fn from(error: PoisonError<T>) -> Self {
+        RedisError::custom(error.to_string())
+    }
 }
 
-pub fn not_an_integer() -> anyhow::Error {
-    anyhow::anyhow!("ERR value is not an integer or out of range")
+pub fn unknown_command(name: &str, args: &[String]) -> RedisError {
+    let args = args
+        .iter()
+        .map(|arg| format!("'{}' ", arg))
+        .collect::<String>();
+    RedisError::Custom(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        name, args
+    ))
+}
+
+/// For a container command like CONFIG, which lists its subcommands with HELP.
+pub fn unknown_subcommand(command: &str, subcommand: &str) -> RedisError {
+    RedisError::Custom(format!(
+        "ERR unknown subcommand '{}'. Try {} HELP.",
+        subcommand,
+        command.to_ascii_uppercase()
+    ))
+}
+
+pub fn wrong_number_of_arguments(command: &str) -> RedisError {
+    RedisError::Custom(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+/// The client sent something that isn't valid RESP, or is bigger than we accept.
+/// There's no telling where the next command starts, so the connection is closed.
+#[derive(Debug, thiserror::Error)]
+#[error("Protocol error: {0}")]
+pub struct ProtocolError(pub String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_sent_with_an_error_code() {
+        assert_eq!(
+            RedisError::custom("something broke\n  badly").to_string(),
+            "ERR something broke badly"
+        );
+        assert_eq!(
+            RedisError::custom("NOPROTO unsupported protocol version").to_string(),
+            "NOPROTO unsupported protocol version"
+        );
+
+        let error = anyhow::Error::from(RedisError::WrongType).context("while doing something");
+        assert_eq!(RedisError::from(error), RedisError::WrongType);
+    }
 }
@@ -0,0 +1,286 @@
+//! The dataset as JSON, for looking at what's in it, seeding test fixtures or
+//! moving it to a server that can't load this one's RDB files. EXPORT writes out
+//! every key with its type, its expiration as a unix time in milliseconds and its
+//! value, and IMPORT reads the same back in.
+//!
+//! Values of the built in types are written as plain JSON. The module types,
+//! whose values are a tangle of counters and fingerprints, are written as the
+//! hex of their DUMP payload instead.
+
+use serde_json::{json, Map, Value};
+
+use crate::data::{
+    self, Database, DatabaseItem, InnerRedisStream, RedisSortedSet, RedisStream, RedisStreamItem,
+    StreamId,
+};
+use crate::errors::RedisError;
+use crate::object::{RedisHash, RedisSet};
+
+/// Bumped whenever an export changes in a way older servers can't import.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Every key that hasn't expired, ordered by key so exports can be diffed.
+pub fn export(database: &Database) -> Result<Value, anyhow::Error> {
+    let mut keys = vec![];
+    database.for_each_item(|key, item, expires_at| {
+        keys.push((key.to_string(), export_item(key, item, expires_at)));
+        Ok(())
+    })?;
+    keys.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(json!({
+        "version": FORMAT_VERSION,
+        "keys": keys.into_iter().map(|(_, key)| key).collect::<Vec<_>>(),
+    }))
+}
+
+fn export_item(key: &str, item: &DatabaseItem, expires_at: Option<u128>) -> Value {
+    let mut exported = Map::new();
+    exported.insert("key".into(), key.into());
+    exported.insert("type".into(), item.type_name().into());
+    exported.insert(
+        "expires_at".into(),
+        expires_at.map_or(Value::Null, |at| (at as u64).into()),
+    );
+
+    let value = match item {
+        DatabaseItem::String(redis_string) => redis_string.data().into(),
+        DatabaseItem::List(list) => list.iter().cloned().collect(),
+        DatabaseItem::Set(set) => {
+            let mut members: Vec<String> = set.iter().map(|member| member.into_owned()).collect();
+            members.sort();
+            members.into()
+        }
+        DatabaseItem::Hash(hash) => {
+            let mut fields: Vec<(&String, &String)> = hash.iter().collect();
+            fields.sort();
+            fields
+                .into_iter()
+                .map(|(field, value)| (field.clone(), Value::from(value.as_str())))
+                .collect::<Map<String, Value>>()
+                .into()
+        }
+        DatabaseItem::SortedSet(sorted_set) => sorted_set
+            .iter()
+            .map(|(member, score)| json!([member, export_score(*score)]))
+            .collect(),
+        DatabaseItem::Stream(stream) => json!({
+            "last_id": stream.last_id().to_string(),
+            "entries": stream
+                .entries()
+                .iter()
+                .map(|entry| {
+                    let fields: Vec<&str> = entry
+                        .items
+                        .iter()
+                        .flat_map(|item| [item.key.as_str(), item.value.as_str()])
+                        .collect();
+                    json!({ "id": entry.stream_id(), "fields": fields })
+                })
+                .collect::<Vec<_>>(),
+        }),
+        DatabaseItem::Json(value) => value.clone(),
+        _ => {
+            exported.insert("dump".into(), hex::encode(data::dump_item(item)).into());
+            return exported.into();
+        }
+    };
+    exported.insert("value".into(), value);
+
+    exported.into()
+}
+
+// JSON has no infinities, so those scores are written the way ZADD takes them
+fn export_score(score: f64) -> Value {
+    match score {
+        f64::INFINITY => "+inf".into(),
+        f64::NEG_INFINITY => "-inf".into(),
+        score => score.into(),
+    }
+}
+
+/// The keys in an export, for the slot checks in cluster mode. Anything that
+/// isn't an export has none, and is rejected when it's imported.
+pub fn keys(export: &Value) -> Vec<&str> {
+    export["keys"]
+        .as_array()
+        .map(|keys| keys.iter().filter_map(|key| key["key"].as_str()).collect())
+        .unwrap_or_default()
+}
+
+/// Creates the keys in an export and returns how many there were. Nothing is
+/// changed if any of them can't be read or, unless they're to be replaced, if
+/// any of them already exists.
+pub fn import(database: &Database, export: &Value, replace: bool) -> Result<usize, RedisError> {
+    let version = export["version"]
+        .as_u64()
+        .ok_or_else(|| invalid("no version"))?;
+    if version > FORMAT_VERSION {
+        return Err(invalid(&format!("version {} isn't supported", version)));
+    }
+    let keys = export["keys"]
+        .as_array()
+        .ok_or_else(|| invalid("no keys"))?;
+
+    let items = keys
+        .iter()
+        .map(import_item)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !replace && items.iter().any(|(key, ..)| database.exists(key)) {
+        return Err(RedisError::custom(
+            "BUSYKEY Target key name already exists.",
+        ));
+    }
+
+    let imported = items.len();
+    for (key, item, expires_at) in items {
+        database.restore_item(&key, item, expires_at, true)?;
+    }
+
+    Ok(imported)
+}
+
+fn import_item(exported: &Value) -> Result<(String, DatabaseItem, Option<u128>), RedisError> {
+    let key = exported["key"]
+        .as_str()
+        .ok_or_else(|| invalid("a key without a name"))?;
+    let invalid_key = |reason: &str| invalid(&format!("key '{}' {}", key, reason));
+    let type_name = exported["type"]
+        .as_str()
+        .ok_or_else(|| invalid_key("has no type"))?;
+    let expires_at = match &exported["expires_at"] {
+        Value::Null => None,
+        at => Some(
+            at.as_u64()
+                .ok_or_else(|| invalid_key("has an invalid expiration"))? as u128,
+        ),
+    };
+
+    let value = &exported["value"];
+    let item = match type_name {
+        "string" => {
+            let value = value
+                .as_str()
+                .ok_or_else(|| invalid_key("isn't a string"))?;
+            DatabaseItem::String(data::RedisString::new(value.to_string()))
+        }
+        "list" => DatabaseItem::List(
+            strings(value)
+                .ok_or_else(|| invalid_key("isn't a list"))?
+                .into_iter()
+                .collect(),
+        ),
+        "set" => DatabaseItem::Set(
+            strings(value)
+                .ok_or_else(|| invalid_key("isn't a set"))?
+                .into_iter()
+                .collect::<RedisSet>(),
+        ),
+        "hash" => {
+            let fields = value
+                .as_object()
+                .ok_or_else(|| invalid_key("isn't a hash"))?;
+            let mut hash = RedisHash::default();
+            for (field, value) in fields {
+                let value = value.as_str().ok_or_else(|| invalid_key("isn't a hash"))?;
+                hash.insert(field.clone(), value.to_string());
+            }
+            DatabaseItem::Hash(hash)
+        }
+        "zset" => {
+            let members = value
+                .as_array()
+                .ok_or_else(|| invalid_key("isn't a zset"))?;
+            let mut sorted_set = RedisSortedSet::default();
+            for member in members {
+                let (member, score) = match member.as_array().map(Vec::as_slice) {
+                    Some([member, score]) => (member.as_str(), import_score(score)),
+                    _ => (None, None),
+                };
+                let (Some(member), Some(score)) = (member, score) else {
+                    return Err(invalid_key("isn't a zset"));
+                };
+                sorted_set.insert(member.to_string(), score);
+            }
+            DatabaseItem::SortedSet(sorted_set)
+        }
+        "stream" => {
+            DatabaseItem::Stream(import_stream(value).ok_or_else(|| invalid_key("isn't a stream"))?)
+        }
+        "ReJSON-RL" if !value.is_null() => DatabaseItem::Json(value.clone()),
+        _ => {
+            let payload = exported["dump"]
+                .as_str()
+                .and_then(|dump| hex::decode(dump).ok())
+                .ok_or_else(|| invalid_key("has no value"))?;
+            let item = data::read_dump(&payload)
+                .map_err(|e| invalid_key(&format!("can't be restored: {}", e)))?;
+            if item.type_name() != type_name {
+                return Err(invalid_key("has a value of another type"));
+            }
+            item
+        }
+    };
+
+    Ok((key.to_string(), item, expires_at))
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|value| value.as_str().map(String::from))
+        .collect()
+}
+
+fn import_score(score: &Value) -> Option<f64> {
+    match score {
+        Value::String(score) => match score.as_str() {
+            "+inf" | "inf" => Some(f64::INFINITY),
+            "-inf" => Some(f64::NEG_INFINITY),
+            _ => None,
+        },
+        score => score.as_f64(),
+    }
+}
+
+fn import_stream(value: &Value) -> Option<RedisStream> {
+    let last_id = parse_stream_id(value["last_id"].as_str()?)?;
+    let mut entries: Vec<InnerRedisStream> = vec![];
+    for entry in value["entries"].as_array()? {
+        let id = parse_stream_id(entry["id"].as_str()?)?;
+        if entries.last().is_some_and(|last| last.id() >= id) {
+            return None;
+        }
+        let fields = strings(&entry["fields"])?;
+        if fields.len() % 2 != 0 {
+            return None;
+        }
+        let items = fields
+            .chunks(2)
+            .map(|field| RedisStreamItem::new(field[0].clone(), field[1].clone()))
+            .collect();
+        entries.push(InnerRedisStream {
+            items,
+            ms_time: id.ms_time,
+            sequence_number: id.sequence_number,
+        });
+    }
+    if entries.last().is_some_and(|last| last.id() > last_id) {
+        return None;
+    }
+
+    Some(RedisStream::from_entries(entries, last_id))
+}
+
+fn parse_stream_id(id: &str) -> Option<StreamId> {
+    let (ms_time, sequence_number) = id.split_once('-')?;
+    Some(StreamId::new(
+        ms_time.parse().ok()?,
+        sequence_number.parse().ok()?,
+    ))
+}
+
+fn invalid(reason: &str) -> RedisError {
+    RedisError::custom(format!("ERR Invalid export: {}", reason))
+}
@@ -0,0 +1,253 @@
+//! Extensions, this server's take on redis modules. A crate that embeds the
+//! server can add commands and value types of its own by passing an `Extension`
+//! to `ServerBuilder::extension`.
+//!
+//! Requests are parsed before they reach a server, and RDB files are read
+//! without one, so what extensions add is registered for the whole process
+//! rather than for the server that loaded them. Loading an extension a second
+//! time, e.g. for a second server in the same process, does nothing.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::bloom::ScalableBloomFilter;
+use crate::cms::CountMinSketch;
+use crate::cuckoo::ScalableCuckooFilter;
+use crate::data::{Database, DatabaseItem, ModuleValue};
+use crate::encoding::{self, ModuleField};
+use crate::errors::RedisError;
+use crate::request::{CommandFlags, CommandSpec, COMMANDS};
+use crate::resp::Value;
+use crate::timeseries::TimeSeries;
+use crate::topk::TopK;
+
+/// A set of commands and value types that's loaded as a whole.
+pub trait Extension: Send + Sync {
+    /// Identifies the extension, so it's only loaded once.
+    fn name(&self) -> &'static str;
+
+    fn commands(&self) -> Vec<Arc<dyn ExtensionCommand>>;
+
+    /// The value types the extension's commands store. Values of these types
+    /// are saved like any other, and can only be loaded again once the
+    /// extension is.
+    fn types(&self) -> Vec<ExtensionType> {
+        vec![]
+    }
+}
+
+impl<E: Extension + ?Sized> Extension for Box<E> {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn commands(&self) -> Vec<Arc<dyn ExtensionCommand>> {
+        (**self).commands()
+    }
+
+    fn types(&self) -> Vec<ExtensionType> {
+        (**self).types()
+    }
+}
+
+impl fmt::Debug for dyn Extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Extension").field(&self.name()).finish()
+    }
+}
+
+/// A command an extension adds. It's checked and run like a built in one:
+/// clients get an error if the arity doesn't match, and if the flags say it
+/// writes it's refused on a read only replica, appended to the AOF and
+/// replicated.
+pub trait ExtensionCommand: Send + Sync {
+    /// Lowercase, like the built in commands. Prefixing the names with the
+    /// extension's, like `json.get`, keeps them from clashing.
+    fn name(&self) -> &'static str;
+    /// Counts the name, and a negative arity is a minimum, like `CommandSpec::arity`.
+    fn arity(&self) -> i32;
+    fn flags(&self) -> CommandFlags;
+
+    /// The keys among the arguments, which in cluster mode have to be in a
+    /// slot this node serves.
+    fn keys<'a>(&self, _args: &'a [String]) -> Vec<&'a str> {
+        vec![]
+    }
+
+    /// Runs the command with its arguments, not counting the name. Values of
+    /// the extension's types are read and changed with `Database::read_module`
+    /// and `Database::update_module`.
+    fn call(&self, database: &Database, args: Vec<String>) -> Result<Value, RedisError>;
+
+    /// For a write whose effect depends on the time or on chance, the command
+    /// appended to the AOF and sent to replicas in its place: its arguments, name
+    /// first, which have the same effect wherever they're applied. Gets the
+    /// arguments and reply of the write that succeeded.
+    fn rewrite(&self, _args: &[String], _reply: &Value) -> Option<Vec<String>> {
+        None
+    }
+}
+
+/// A value type an extension adds. The type itself implements `ModuleValue`,
+/// leaving `from_item`, `from_item_mut` and `into_item` to their defaults.
+pub struct ExtensionType {
+    name: &'static str,
+    encoding_version: u16,
+    load: fn(&[ModuleField]) -> Result<DatabaseItem, anyhow::Error>,
+}
+
+impl ExtensionType {
+    pub fn of<T: ModuleValue>() -> Self {
+        ExtensionType {
+            name: T::TYPE_NAME,
+            encoding_version: T::ENCODING_VERSION,
+            load: |fields| Ok(T::from_fields(fields)?.into_item()),
+        }
+    }
+}
+
+struct RegisteredCommand {
+    spec: &'static CommandSpec,
+    command: Arc<dyn ExtensionCommand>,
+}
+
+struct Registry {
+    extensions: Vec<&'static str>,
+    commands: Vec<RegisteredCommand>,
+    types: Vec<ExtensionType>,
+}
+
+static REGISTRY: RwLock<Registry> = RwLock::new(Registry {
+    extensions: vec![],
+    commands: vec![],
+    types: vec![],
+});
+
+// The built in module types, which extensions can't reuse the names of
+const BUILT_IN_TYPES: &[&str] = &[
+    serde_json::Value::TYPE_NAME,
+    ScalableBloomFilter::TYPE_NAME,
+    ScalableCuckooFilter::TYPE_NAME,
+    CountMinSketch::TYPE_NAME,
+    TopK::TYPE_NAME,
+    TimeSeries::TYPE_NAME,
+];
+
+/// Registers the extension's commands and types. Nothing is registered if any
+/// of them clashes with a command or type that's already there.
+pub fn load(extension: &dyn Extension) -> Result<(), anyhow::Error> {
+    let mut registry = REGISTRY
+        .write()
+        .map_err(|_| anyhow::anyhow!("The extension registry is poisoned"))?;
+    if registry.extensions.contains(&extension.name()) {
+        return Ok(());
+    }
+
+    let commands = extension.commands();
+    for (i, command) in commands.iter().enumerate() {
+        let name = command.name();
+        let taken = COMMANDS
+            .iter()
+            .any(|spec| spec.name.eq_ignore_ascii_case(name))
+            || registry
+                .commands
+                .iter()
+                .any(|registered| registered.spec.name.eq_ignore_ascii_case(name))
+            || commands[..i]
+                .iter()
+                .any(|other| other.name().eq_ignore_ascii_case(name));
+        if taken {
+            anyhow::bail!("Command {} is already defined", name);
+        }
+        if command.arity() == 0 {
+            anyhow::bail!("Command {} has an arity of 0", name);
+        }
+    }
+
+    let types = extension.types();
+    for (i, added) in types.iter().enumerate() {
+        if !encoding::is_valid_module_type_name(added.name) || added.encoding_version > 1023 {
+            anyhow::bail!(
+                "Type {} needs a name of nine letters, digits, - or _ and an encoding version up to 1023",
+                added.name
+            );
+        }
+        let taken = BUILT_IN_TYPES.contains(&added.name)
+            || registry.types.iter().any(|other| other.name == added.name)
+            || types[..i].iter().any(|other| other.name == added.name);
+        if taken {
+            anyhow::bail!("Type {} is already defined", added.name);
+        }
+    }
+
+    println!(
+        "Loading extension {} with {} commands and {} types",
+        extension.name(),
+        commands.len(),
+        types.len()
+    );
+    registry.extensions.push(extension.name());
+    for command in commands {
+        // Registered once per process, so leaking the spec is fine
+        let spec = Box::leak(Box::new(CommandSpec::for_extension(
+            command.name(),
+            command.arity(),
+            command.flags(),
+        )));
+        registry.commands.push(RegisteredCommand { spec, command });
+    }
+    registry.types.extend(types);
+
+    Ok(())
+}
+
+/// The spec of a command an extension added.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    let registry = REGISTRY.read().ok()?;
+    registry
+        .commands
+        .iter()
+        .find(|registered| registered.spec.name.eq_ignore_ascii_case(name))
+        .map(|registered| registered.spec)
+}
+
+fn command(name: &str) -> Result<Arc<dyn ExtensionCommand>, RedisError> {
+    let registry = REGISTRY.read()?;
+    registry
+        .commands
+        .iter()
+        .find(|registered| registered.spec.name == name)
+        .map(|registered| registered.command.clone())
+        .ok_or_else(|| RedisError::custom(format!("ERR unknown command '{}'", name)))
+}
+
+pub fn keys<'a>(name: &str, args: &'a [String]) -> Vec<&'a str> {
+    match command(name) {
+        Ok(command) => command.keys(args),
+        Err(_) => vec![],
+    }
+}
+
+pub fn call(database: &Database, name: &str, args: Vec<String>) -> Result<Vec<Value>, RedisError> {
+    let command = command(name)?;
+    Ok(vec![command.call(database, args)?])
+}
+
+/// What an extension command is propagated as, if it rewrites itself.
+pub fn rewrite(name: &str, args: &[String], reply: &Value) -> Option<Vec<String>> {
+    command(name).ok()?.rewrite(args, reply)
+}
+
+/// Loads a value of a type an extension added.
+pub fn load_value(name: &str, fields: &[ModuleField]) -> Result<DatabaseItem, anyhow::Error> {
+    let registry = REGISTRY
+        .read()
+        .map_err(|_| anyhow::anyhow!("The extension registry is poisoned"))?;
+    let extension_type = registry
+        .types
+        .iter()
+        .find(|extension_type| extension_type.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Values of module type {} can't be loaded", name))?;
+
+    (extension_type.load)(fields)
+}
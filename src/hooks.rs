@@ -0,0 +1,48 @@
+use std::sync::{Arc, RwLock};
+
+/// What happened to a key, as the hooks an application embedding the store
+/// registers with `Database::on_set` and friends are told. Unlike keyspace
+/// notifications these never go over the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// The key was given a value, or its value was changed in place.
+    Set,
+    /// The key was removed by a command or through the API.
+    Delete,
+    /// The key's expiry timer removed it.
+    Expire,
+    /// An entry was appended to the stream at the key.
+    Xadd,
+}
+
+type Hook = Arc<dyn Fn(&str, KeyEvent) + Send + Sync>;
+
+/// The registered hooks, each for one kind of event. They're called once the
+/// change has been made and the keyspace lock released, so a hook can read the
+/// key it's told about, from whichever thread made the change.
+#[derive(Default)]
+pub struct KeyHooks {
+    hooks: RwLock<Vec<(KeyEvent, Hook)>>,
+}
+
+impl KeyHooks {
+    pub fn register(&self, event: KeyEvent, hook: impl Fn(&str, KeyEvent) + Send + Sync + 'static) {
+        self.hooks.write().unwrap().push((event, Arc::new(hook)));
+    }
+
+    pub fn notify(&self, key: &str, event: KeyEvent) {
+        // Cloned so a hook can register another without deadlocking
+        let hooks: Vec<Hook> = self
+            .hooks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(registered, _)| *registered == event)
+            .map(|(_, hook)| hook.clone())
+            .collect();
+
+        for hook in hooks {
+            hook(key, event);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Counts how often clients touch each key, for HOTKEYS. A key's count halves
+//! every `HALF_LIFE` it isn't accessed, so the keys at the top are the ones that
+//! have been busy lately rather than the ones that were busy once.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const HALF_LIFE: Duration = Duration::from_secs(60);
+// Past this many keys the quietest half is forgotten, so tracking doesn't grow
+// with the keyspace
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    count: f64,
+    updated_at: Instant,
+}
+
+impl Counter {
+    fn decayed(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        self.count * 0.5f64.powf(elapsed.as_secs_f64() / HALF_LIFE.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HotKeys {
+    counters: Mutex<HashMap<String, Counter>>,
+}
+
+impl HotKeys {
+    pub fn record<S: AsRef<str>>(&self, keys: &[S]) {
+        self.record_at(keys, Instant::now());
+    }
+
+    fn record_at<S: AsRef<str>>(&self, keys: &[S], now: Instant) {
+        if keys.is_empty() {
+            return;
+        }
+
+        let mut counters = self.counters.lock().unwrap();
+        for key in keys {
+            match counters.get_mut(key.as_ref()) {
+                Some(counter) => {
+                    counter.count = counter.decayed(now) + 1.0;
+                    counter.updated_at = now;
+                }
+                None => {
+                    let counter = Counter {
+                        count: 1.0,
+                        updated_at: now,
+                    };
+                    counters.insert(key.as_ref().to_string(), counter);
+                }
+            }
+        }
+
+        if counters.len() > MAX_TRACKED {
+            let mut kept: Vec<(String, Counter)> = counters.drain().collect();
+            kept.sort_by(|(_, a), (_, b)| b.decayed(now).total_cmp(&a.decayed(now)));
+            kept.truncate(MAX_TRACKED / 2);
+            counters.extend(kept);
+        }
+    }
+
+    /// The `count` most accessed keys with their decayed access counts, busiest first.
+    pub fn top(&self, count: usize) -> Vec<(String, f64)> {
+        self.top_at(count, Instant::now())
+    }
+
+    fn top_at(&self, count: usize, now: Instant) -> Vec<(String, f64)> {
+        let counters = self.counters.lock().unwrap();
+        let mut keys: Vec<(String, f64)> = counters
+            .iter()
+            .map(|(key, counter)| (key.clone(), counter.decayed(now)))
+            .collect();
+        keys.sort_by(|(a_key, a), (b_key, b)| b.total_cmp(a).then_with(|| a_key.cmp(b_key)));
+        keys.truncate(count);
+
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_decay_over_time() {
+        let hot_keys = HotKeys::default();
+        let start = Instant::now();
+        for _ in 0..8 {
+            hot_keys.record_at(&["old"], start);
+        }
+        hot_keys.record_at(&["new", "new", "new"], start + HALF_LIFE * 2);
+        hot_keys.record_at(&["other"], start + HALF_LIFE * 2);
+
+        let top = hot_keys.top_at(2, start + HALF_LIFE * 2);
+        assert_eq!(top, [("new".to_string(), 3.0), ("old".to_string(), 2.0)]);
+
+        let top = hot_keys.top_at(10, start + HALF_LIFE * 3);
+        assert_eq!(top[0], ("new".to_string(), 1.5));
+        assert_eq!(top.len(), 3);
+    }
+}
@@ -0,0 +1,548 @@
+//! JSON documents, like the RedisJSON module. Values in a document are addressed
+//! with a subset of JSONPath: `$` followed by any of `.name`, `['name']`, `[index]`,
+//! `[*]`, `.*` and `..name`. Paths that don't start with `$` use RedisJSON's legacy
+//! syntax, e.g. `.a.b` or `a[0]`, and only ever address a single value.
+
+use serde_json::{Number, Value as Json};
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+use crate::request::SetOverride;
+
+/// Documents are saved the way RedisJSON saves them, serialized in a single field.
+impl ModuleValue for Json {
+    const TYPE_NAME: &'static str = "ReJSON-RL";
+    const ENCODING_VERSION: u16 = 3;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::Json(document) => Some(document),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::Json(document) => Some(document),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::Json(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        vec![ModuleField::String(self.to_string().into_bytes())]
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [document] = fields else {
+            anyhow::bail!("Expected a single JSON document");
+        };
+
+        Ok(serde_json::from_slice(document.as_bytes()?)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    /// Negative indexes count from the end of the array.
+    Index(i64),
+    /// Every member of an object or element of an array.
+    Wildcard,
+    /// `..name` or `..*`: the selector applied to a value and all of its descendants.
+    Descendants(Box<Selector>),
+}
+
+/// Where a value is in a document. Ordered so that deleting in reverse order
+/// never moves an element that's yet to be deleted.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    source: String,
+    selectors: Vec<Selector>,
+    legacy: bool,
+}
+
+impl JsonPath {
+    /// The whole document, which is what commands default to without a path.
+    pub fn root() -> Self {
+        JsonPath {
+            source: ".".to_string(),
+            selectors: vec![],
+            legacy: true,
+        }
+    }
+
+    pub fn parse(path: &str) -> Result<Self, RedisError> {
+        let (rest, legacy) = match path.strip_prefix('$') {
+            Some(rest) => (rest.to_string(), false),
+            None if path == "." => (String::new(), true),
+            None if path.starts_with(['.', '[']) => (path.to_string(), true),
+            None => (format!(".{}", path), true),
+        };
+        let invalid = || RedisError::custom(format!("ERR invalid JSONPath '{}'", path));
+
+        let mut selectors = vec![];
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            let selector = match c {
+                '.' if chars.peek() == Some(&'.') => {
+                    chars.next();
+                    let selector = match chars.peek() {
+                        Some('[') => {
+                            chars.next();
+                            parse_bracket(&mut chars).ok_or_else(invalid)?
+                        }
+                        _ => parse_dotted(&mut chars).ok_or_else(invalid)?,
+                    };
+                    Selector::Descendants(Box::new(selector))
+                }
+                '.' => parse_dotted(&mut chars).ok_or_else(invalid)?,
+                '[' => parse_bracket(&mut chars).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            };
+            selectors.push(selector);
+        }
+
+        Ok(JsonPath {
+            source: path.to_string(),
+            selectors,
+            legacy,
+        })
+    }
+
+    /// Whether the path is in the legacy syntax, which commands answer with a
+    /// single value rather than one for every match.
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.selectors.is_empty()
+    }
+
+    fn missing(&self) -> RedisError {
+        RedisError::custom(format!("ERR Path '{}' does not exist", self.source))
+    }
+
+    /// Every value the path matches, in document order.
+    fn locate(&self, document: &Json) -> Vec<Vec<Step>> {
+        locate(document, &self.selectors)
+    }
+
+    /// The values the path matches.
+    pub fn matches<'a>(&self, document: &'a Json) -> Vec<&'a Json> {
+        self.locate(document)
+            .iter()
+            .filter_map(|location| get(document, location))
+            .collect()
+    }
+
+    /// The first value the path matches, for legacy paths.
+    fn first<'a>(&self, document: &'a Json) -> Result<&'a Json, RedisError> {
+        self.matches(document)
+            .into_iter()
+            .next()
+            .ok_or_else(|| self.missing())
+    }
+}
+
+fn parse_dotted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Selector> {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| *c != '.' && *c != '[') {
+        name.push(c);
+    }
+
+    match name.as_str() {
+        "" => None,
+        "*" => Some(Selector::Wildcard),
+        _ => Some(Selector::Key(name)),
+    }
+}
+
+/// What's between the brackets of `['name']`, `[0]` or `[*]`, the opening one
+/// already having been read.
+fn parse_bracket(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Selector> {
+    let selector = match chars.next()? {
+        '*' => Selector::Wildcard,
+        quote @ ('\'' | '"') => {
+            let mut name = String::new();
+            loop {
+                match chars.next()? {
+                    '\\' => name.push(chars.next()?),
+                    c if c == quote => break,
+                    c => name.push(c),
+                }
+            }
+            Selector::Key(name)
+        }
+        first => {
+            let mut index = first.to_string();
+            while let Some(c) = chars.next_if(|c| *c != ']') {
+                index.push(c);
+            }
+            Selector::Index(index.trim().parse().ok()?)
+        }
+    };
+
+    (chars.next()? == ']').then_some(selector)
+}
+
+fn locate(document: &Json, selectors: &[Selector]) -> Vec<Vec<Step>> {
+    let mut locations = vec![vec![]];
+    for selector in selectors {
+        locations = locations
+            .into_iter()
+            .flat_map(|location| {
+                let value = get(document, &location).expect("located values exist");
+                select(value, selector)
+                    .into_iter()
+                    .map(move |steps| [location.clone(), steps].concat())
+            })
+            .collect();
+    }
+
+    locations
+}
+
+/// Where the values a single selector matches are, relative to `value`.
+fn select(value: &Json, selector: &Selector) -> Vec<Vec<Step>> {
+    match (selector, value) {
+        (Selector::Key(key), Json::Object(object)) if object.contains_key(key) => {
+            vec![vec![Step::Key(key.clone())]]
+        }
+        (Selector::Index(index), Json::Array(array)) => {
+            let index = match *index < 0 {
+                true => array.len() as i64 + index,
+                false => *index,
+            };
+            match (0..array.len() as i64).contains(&index) {
+                true => vec![vec![Step::Index(index as usize)]],
+                false => vec![],
+            }
+        }
+        (Selector::Wildcard, Json::Object(object)) => object
+            .keys()
+            .map(|key| vec![Step::Key(key.clone())])
+            .collect(),
+        (Selector::Wildcard, Json::Array(array)) => (0..array.len())
+            .map(|index| vec![Step::Index(index)])
+            .collect(),
+        (Selector::Descendants(selector), _) => {
+            let mut locations = select(value, selector);
+            for (step, child) in children(value) {
+                for mut location in select(child, &Selector::Descendants(selector.clone())) {
+                    location.insert(0, step.clone());
+                    locations.push(location);
+                }
+            }
+            locations
+        }
+        _ => vec![],
+    }
+}
+
+fn children(value: &Json) -> Vec<(Step, &Json)> {
+    match value {
+        Json::Object(object) => object
+            .iter()
+            .map(|(key, child)| (Step::Key(key.clone()), child))
+            .collect(),
+        Json::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(index, child)| (Step::Index(index), child))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn get<'a>(document: &'a Json, location: &[Step]) -> Option<&'a Json> {
+    location
+        .iter()
+        .try_fold(document, |value, step| match (step, value) {
+            (Step::Key(key), Json::Object(object)) => object.get(key),
+            (Step::Index(index), Json::Array(array)) => array.get(*index),
+            _ => None,
+        })
+}
+
+fn get_mut<'a>(document: &'a mut Json, location: &[Step]) -> Option<&'a mut Json> {
+    location
+        .iter()
+        .try_fold(document, |value, step| match (step, value) {
+            (Step::Key(key), Json::Object(object)) => object.get_mut(key),
+            (Step::Index(index), Json::Array(array)) => array.get_mut(*index),
+            _ => None,
+        })
+}
+
+/// The name RedisJSON uses for the type of a value in its errors.
+fn type_name(value: &Json) -> &'static str {
+    match value {
+        Json::Null => "null",
+        Json::Bool(_) => "boolean",
+        Json::Number(number) if number.is_f64() => "number",
+        Json::Number(_) => "integer",
+        Json::String(_) => "string",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+    }
+}
+
+fn wrong_type(expected: &str, found: &Json) -> RedisError {
+    RedisError::custom(format!(
+        "ERR WRONGTYPE wrong type of path value - expected {} but found {}",
+        expected,
+        type_name(found)
+    ))
+}
+
+/// What JSON.GET replies with: the value for a legacy path, otherwise an array of
+/// every match. With several paths it's an object keyed by path, where every path
+/// is answered like a legacy one only if they all are.
+pub fn get_paths(document: &Json, paths: &[JsonPath]) -> Result<Json, RedisError> {
+    let legacy = paths.iter().all(JsonPath::is_legacy);
+    let reply = |path: &JsonPath| match legacy {
+        true => path.first(document).cloned(),
+        false => Ok(Json::Array(
+            path.matches(document).into_iter().cloned().collect(),
+        )),
+    };
+
+    match paths {
+        [path] => reply(path),
+        _ => paths
+            .iter()
+            .map(|path| Ok((path.source.clone(), reply(path)?)))
+            .collect::<Result<_, _>>()
+            .map(Json::Object),
+    }
+}
+
+/// Sets every value the path matches. When it matches nothing but names a
+/// member of objects that do exist, the member is added to them. Returns whether
+/// anything was set.
+pub fn set(
+    document: &mut Json,
+    path: &JsonPath,
+    value: Json,
+    overwrite: &SetOverride,
+) -> Result<bool, RedisError> {
+    let locations = path.locate(document);
+    if !locations.is_empty() {
+        if let SetOverride::NeverOverwrite = overwrite {
+            return Ok(false);
+        }
+        for location in locations {
+            if let Some(existing) = get_mut(document, &location) {
+                *existing = value.clone();
+            }
+        }
+        return Ok(true);
+    }
+
+    let (Some(Selector::Key(key)), SetOverride::Normal | SetOverride::NeverOverwrite) =
+        (path.selectors.last(), overwrite)
+    else {
+        return Ok(false);
+    };
+    let parents = locate(document, &path.selectors[..path.selectors.len() - 1]);
+    let mut added = false;
+    for parent in parents {
+        if let Some(Json::Object(object)) = get_mut(document, &parent) {
+            object.insert(key.clone(), value.clone());
+            added = true;
+        }
+    }
+
+    Ok(added)
+}
+
+/// Removes every value the path matches and returns how many there were.
+/// The root can't be removed from a document, the key is deleted instead.
+pub fn delete(document: &mut Json, path: &JsonPath) -> usize {
+    let mut locations = path.locate(document);
+    // Later elements go first so removing one doesn't shift the others
+    locations.sort();
+    locations.reverse();
+
+    let mut deleted = 0;
+    for location in locations {
+        let Some((last, parent)) = location.split_last() else {
+            continue;
+        };
+        let removed = match (get_mut(document, parent), last) {
+            (Some(Json::Object(object)), Step::Key(key)) => object.shift_remove(key).is_some(),
+            (Some(Json::Array(array)), Step::Index(index)) if *index < array.len() => {
+                array.remove(*index);
+                true
+            }
+            _ => false,
+        };
+        deleted += removed as usize;
+    }
+
+    deleted
+}
+
+/// Adds `by` to every number the path matches. Returns the new values,
+/// with null for the matches that aren't numbers, or for a legacy path the one
+/// value.
+pub fn increment(document: &mut Json, path: &JsonPath, by: &Number) -> Result<Json, RedisError> {
+    if path.is_legacy() {
+        let value = path.first(document)?;
+        if !value.is_number() {
+            return Err(wrong_type("a number", value));
+        }
+    }
+
+    let mut results = vec![];
+    for location in path.locate(document) {
+        let Some(Json::Number(number)) = get_mut(document, &location) else {
+            results.push(Json::Null);
+            continue;
+        };
+        let sum = match (number.as_i64(), by.as_i64()) {
+            (Some(a), Some(b)) => a.checked_add(b).map(Number::from),
+            _ => None,
+        };
+        let sum = match sum {
+            Some(sum) => sum,
+            None => {
+                let sum = number.as_f64().unwrap_or_default() + by.as_f64().unwrap_or_default();
+                Number::from_f64(sum)
+                    .ok_or_else(|| RedisError::custom("ERR result is not a number or infinite"))?
+            }
+        };
+        *number = sum.clone();
+        results.push(Json::Number(sum));
+    }
+
+    match path.is_legacy() {
+        true => Ok(results.swap_remove(0)),
+        false => Ok(Json::Array(results)),
+    }
+}
+
+/// Appends the values to every array the path matches and returns their new
+/// lengths, `None` for the matches that aren't arrays.
+pub fn append(
+    document: &mut Json,
+    path: &JsonPath,
+    values: &[Json],
+) -> Result<Vec<Option<usize>>, RedisError> {
+    if path.is_legacy() {
+        let value = path.first(document)?;
+        if !value.is_array() {
+            return Err(wrong_type("an array", value));
+        }
+    }
+
+    let mut lengths = vec![];
+    for location in path.locate(document) {
+        let length = match get_mut(document, &location) {
+            Some(Json::Array(array)) => {
+                array.extend(values.iter().cloned());
+                Some(array.len())
+            }
+            _ => None,
+        };
+        lengths.push(length);
+    }
+
+    if path.is_legacy() {
+        lengths.truncate(1);
+    }
+
+    Ok(lengths)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(document: &Json, path: &str) -> Vec<Json> {
+        let path = JsonPath::parse(path).unwrap();
+        path.matches(document).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn paths_select_values() {
+        let document = json!({
+            "a": 1,
+            "b": {"a": 2, "c": [3, 4, {"a": 5}]},
+            "the key": true,
+        });
+
+        assert_eq!(matches(&document, "$"), vec![document.clone()]);
+        assert_eq!(matches(&document, "$.a"), vec![json!(1)]);
+        assert_eq!(matches(&document, "$.b.c[1]"), vec![json!(4)]);
+        assert_eq!(matches(&document, "$.b.c[-1].a"), vec![json!(5)]);
+        assert_eq!(matches(&document, "$['the key']"), vec![json!(true)]);
+        assert_eq!(matches(&document, "$.b.c[*]").len(), 3);
+        assert_eq!(matches(&document, "$.*").len(), 3);
+        assert_eq!(
+            matches(&document, "$..a"),
+            vec![json!(1), json!(2), json!(5)]
+        );
+        assert!(matches(&document, "$.b.c[3]").is_empty());
+        assert!(matches(&document, "$.missing").is_empty());
+
+        // Legacy paths
+        assert_eq!(matches(&document, "."), vec![document.clone()]);
+        assert_eq!(matches(&document, ".b.a"), vec![json!(2)]);
+        assert_eq!(matches(&document, "b.c[0]"), vec![json!(3)]);
+
+        for invalid in ["$.", "$[", "$[1", "$['a]", "$..", "$a"] {
+            assert!(JsonPath::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn values_can_be_set_and_deleted() {
+        let mut document = json!({"a": [1, 2, 3], "b": {"a": 4}});
+        let path = |path: &str| JsonPath::parse(path).unwrap();
+
+        assert!(set(&mut document, &path("$..a"), json!(0), &SetOverride::Normal).unwrap());
+        assert_eq!(document, json!({"a": 0, "b": {"a": 0}}));
+
+        assert!(!set(
+            &mut document,
+            &path("$.c"),
+            json!(1),
+            &SetOverride::OnlyOverwrite
+        )
+        .unwrap());
+        assert!(set(
+            &mut document,
+            &path("$.b.c"),
+            json!([1, 2]),
+            &SetOverride::Normal
+        )
+        .unwrap());
+        assert!(!set(
+            &mut document,
+            &path("$.x.y"),
+            json!(1),
+            &SetOverride::Normal
+        )
+        .unwrap());
+        assert_eq!(document, json!({"a": 0, "b": {"a": 0, "c": [1, 2]}}));
+
+        assert_eq!(delete(&mut document, &path("$.b.c[*]")), 2);
+        assert_eq!(delete(&mut document, &path("$..a")), 2);
+        assert_eq!(document, json!({"b": {"c": []}}));
+    }
+}
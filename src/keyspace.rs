@@ -0,0 +1,327 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use rand::Rng;
+
+// How many segments an empty map has, so a small keyspace doesn't pay for many
+const INITIAL_SEGMENTS: usize = 16;
+// Once the map averages more keys than this per segment, the next one is split.
+// It bounds how many keys a single write ever has to rehash.
+const MAX_SEGMENT_LOAD: usize = 256;
+
+/// A map from keys to values split into segments by the hash of the key. It grows
+/// with linear hashing: whenever a new key takes the map past its load, the next
+/// segment in line is split in two, moving about half of its keys to a new segment
+/// at the end. Once every segment has been split the round starts over with twice
+/// as many. So as the keyspace grows, each write rehashes at most one segment's
+/// worth of keys under the write lock rather than every key at once, and each
+/// segment's own table stays small enough that growing it is just as quick.
+///
+/// Segments are reference counted, so cloning the map for a snapshot is cheap and
+/// a write afterwards only copies the segment it touches. Values are reference
+/// counted too, so copying a segment only copies pointers, and a value itself is
+/// only copied when it's changed while a snapshot still holds on to it.
+#[derive(Debug, Clone)]
+pub struct SegmentedMap<V> {
+    segments: Vec<Arc<HashMap<Arc<str>, Arc<V>>>>,
+    /// How many segments there were when the current round of splits started.
+    round: usize,
+    /// The next segment to split. The ones before it were already split this round.
+    next_split: usize,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<V: Clone> Default for SegmentedMap<V> {
+    fn default() -> Self {
+        SegmentedMap {
+            segments: (0..INITIAL_SEGMENTS)
+                .map(|_| Arc::new(HashMap::new()))
+                .collect(),
+            round: INITIAL_SEGMENTS,
+            next_split: 0,
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<V: Clone> SegmentedMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut map = Self::new();
+        map.reserve(capacity);
+        map
+    }
+
+    /// Makes room for at least `additional` more keys. The segments are split up
+    /// front as if the keys had been added, which is cheap while they're empty, like
+    /// when a dataset is about to be loaded, and then spread evenly over them.
+    pub fn reserve(&mut self, additional: usize) {
+        let len = self.len + additional;
+        while self.segments.len() * MAX_SEGMENT_LOAD < len {
+            self.split_next();
+        }
+
+        let per_segment = len.div_ceil(self.segments.len());
+        for segment in self.segments.iter_mut() {
+            let additional = per_segment.saturating_sub(segment.len());
+            if additional > segment.capacity() - segment.len() {
+                Arc::make_mut(segment).reserve(additional);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.segments.iter().map(|segment| segment.capacity()).sum()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.segments[self.segment_of(key)]
+            .get(key)
+            .map(Arc::as_ref)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let segment = self.segment_of(key);
+        Arc::make_mut(&mut self.segments[segment])
+            .get_mut(key)
+            .map(Arc::make_mut)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns the value the key had before, if any.
+    pub fn insert(&mut self, key: Arc<str>, value: V) -> Option<V> {
+        let segment = self.segment_of(&key);
+        let replaced = Arc::make_mut(&mut self.segments[segment])
+            .insert(key, Arc::new(value))
+            .map(Arc::unwrap_or_clone);
+        if replaced.is_none() {
+            self.len += 1;
+            if self.len > self.segments.len() * MAX_SEGMENT_LOAD {
+                self.split_next();
+            }
+        }
+
+        replaced
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let segment = self.segment_of(key);
+        // Don't copy a segment shared with a snapshot just to find out the key isn't there
+        if !self.segments[segment].contains_key(key) {
+            return None;
+        }
+        self.len -= 1;
+        Arc::make_mut(&mut self.segments[segment])
+            .remove(key)
+            .map(Arc::unwrap_or_clone)
+    }
+
+    /// Empties the map, which goes back to as many segments as a new one.
+    pub fn clear(&mut self) {
+        let hasher = self.hasher.clone();
+        *self = SegmentedMap {
+            hasher,
+            ..Self::new()
+        };
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.segments.iter().flat_map(|segment| segment.keys())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Arc<str>, &V)> {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.iter())
+            .map(|(key, value)| (key, value.as_ref()))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.values())
+            .map(Arc::as_ref)
+    }
+
+    /// An entry picked uniformly at random, or `None` if the map is empty. Only
+    /// the segment the entry is in is walked to find it.
+    pub fn random_entry(&self) -> Option<(&Arc<str>, &V)> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+
+        let mut index = rand::thread_rng().gen_range(0..len);
+        for segment in self.segments.iter() {
+            if index < segment.len() {
+                return segment
+                    .iter()
+                    .nth(index)
+                    .map(|(key, value)| (key, value.as_ref()));
+            }
+            index -= segment.len();
+        }
+        None
+    }
+
+    /// Segments that were already split this round share their keys with the ones
+    /// they were split into, so they're picked from twice as many.
+    fn segment_of(&self, key: &str) -> usize {
+        let hash = self.hasher.hash_one(key) as usize;
+        match hash % self.round {
+            segment if segment < self.next_split => hash % (self.round * 2),
+            segment => segment,
+        }
+    }
+
+    /// Splits the next segment in line, moving the keys that now belong in the
+    /// segment added at the end there.
+    fn split_next(&mut self) {
+        let split = self.next_split;
+        let added = self.segments.len();
+        let moved: HashMap<_, _> = Arc::make_mut(&mut self.segments[split])
+            .extract_if(|key, _| self.hasher.hash_one(key) as usize % (self.round * 2) == added)
+            .collect();
+        self.segments.push(Arc::new(moved));
+
+        self.next_split += 1;
+        if self.next_split == self.round {
+            self.round *= 2;
+            self.next_split = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_found_in_whichever_segment_they_land_in() {
+        let mut map = SegmentedMap::new();
+        for i in 0..1000 {
+            map.insert(Arc::from(i.to_string()), i);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.get("500"), Some(&500));
+
+        *map.get_mut("500").unwrap() = -1;
+        assert_eq!(map.remove("500"), Some(-1));
+        assert_eq!(map.remove("500"), None);
+        assert_eq!(map.keys().count(), 999);
+    }
+
+    #[test]
+    fn writes_after_a_clone_only_copy_the_segment_they_touch() {
+        let mut map = SegmentedMap::new();
+        for i in 0..1000 {
+            map.insert(Arc::from(i.to_string()), i);
+        }
+        let snapshot = map.clone();
+
+        map.insert(Arc::from("new"), 0);
+        let copied = map
+            .segments
+            .iter()
+            .zip(snapshot.segments.iter())
+            .filter(|(ours, theirs)| !Arc::ptr_eq(ours, theirs))
+            .count();
+        assert_eq!(copied, 1);
+        assert!(snapshot.get("new").is_none());
+
+        // Values are shared until they're changed
+        let segment = map.segment_of("500");
+        let shared = |map: &SegmentedMap<i32>, snapshot: &SegmentedMap<i32>| {
+            Arc::ptr_eq(
+                &map.segments[segment]["500"],
+                &snapshot.segments[segment]["500"],
+            )
+        };
+        let neighbour = (0..1000)
+            .map(|i| i.to_string())
+            .find(|key| key != "500" && map.segment_of(key) == segment)
+            .unwrap();
+        *map.get_mut(&neighbour).unwrap() = -1;
+        assert!(shared(&map, &snapshot));
+        *map.get_mut("500").unwrap() = -1;
+        assert!(!shared(&map, &snapshot));
+        assert_eq!(snapshot.get("500"), Some(&500));
+    }
+
+    #[test]
+    fn random_entries_can_come_from_any_segment() {
+        let mut map = SegmentedMap::new();
+        assert!(map.random_entry().is_none());
+
+        for i in 0..100 {
+            map.insert(Arc::from(i.to_string()), i);
+        }
+        let picked: std::collections::HashSet<i32> =
+            (0..1000).map(|_| *map.random_entry().unwrap().1).collect();
+        assert!(picked.len() > 50);
+    }
+
+    #[test]
+    fn reserving_spreads_capacity_over_every_segment() {
+        let mut map: SegmentedMap<u8> = SegmentedMap::new();
+        map.reserve(INITIAL_SEGMENTS * 100);
+        assert_eq!(map.segments.len(), INITIAL_SEGMENTS);
+        assert!(map.segments.iter().all(|segment| segment.capacity() >= 100));
+
+        // Room for more than the segments are meant to hold splits them up front
+        map.reserve(INITIAL_SEGMENTS * MAX_SEGMENT_LOAD * 3);
+        assert_eq!(map.segments.len(), INITIAL_SEGMENTS * 3);
+        assert!(map
+            .segments
+            .iter()
+            .all(|segment| segment.capacity() >= MAX_SEGMENT_LOAD));
+    }
+
+    #[test]
+    fn segments_are_split_one_at_a_time_as_the_map_grows() {
+        let mut map = SegmentedMap::new();
+        let snapshot = map.clone();
+        let keys = INITIAL_SEGMENTS * MAX_SEGMENT_LOAD * 5;
+        for i in 0..keys {
+            let segments = map.segments.len();
+            map.insert(Arc::from(i.to_string()), i);
+            assert!(map.segments.len() - segments <= 1);
+        }
+
+        // Past the first round of splits, into the second
+        assert_eq!(map.segments.len(), keys / MAX_SEGMENT_LOAD);
+        assert_eq!(map.round, INITIAL_SEGMENTS * 4);
+        assert_eq!(map.len(), keys);
+        assert!((0..keys).all(|i| map.get(&i.to_string()) == Some(&i)));
+        // The keys are spread out rather than left in the segments they started in
+        let largest = map.segments.iter().map(|segment| segment.len()).max();
+        assert!(largest.unwrap() < MAX_SEGMENT_LOAD * 2);
+        assert!(snapshot.is_empty());
+
+        for i in 0..keys / 2 {
+            assert_eq!(map.remove(&i.to_string()), Some(i));
+        }
+        assert_eq!(map.len(), keys - keys / 2);
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.segments.len(), INITIAL_SEGMENTS);
+    }
+}
@@ -1,11 +1,38 @@
+pub mod aof;
 pub mod app;
+pub mod blocking;
+pub mod bloom;
+pub mod cidr;
+pub mod cli;
+pub mod client;
+pub mod clock;
+pub mod cluster;
+pub mod cms;
 pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod cuckoo;
 pub mod data;
 pub mod encoding;
 pub mod errors;
+pub mod export;
+pub mod extension;
+pub mod hooks;
+pub mod hotkeys;
+pub mod json;
+pub mod keyspace;
+pub mod namespace;
+pub mod object;
+pub mod propagation;
+pub mod ratelimit;
 pub mod request;
+pub mod resp;
 pub mod server;
+pub mod session;
 pub mod stream;
+pub mod systemd;
+pub mod tasks;
 pub mod telemetry;
-pub mod transmission;
+pub mod timeseries;
+pub mod topk;
 pub mod utils;
@@ -1,14 +1,38 @@
-use tokio::sync::broadcast;
-
 use not_redis::app;
+use not_redis::request::ShutdownSave;
 use not_redis::server;
-use not_redis::transmission;
+use not_redis::systemd::{self, Supervised};
+use not_redis::telemetry;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let (tx, _) = broadcast::channel::<transmission::Transmission>(100);
     let (database, redis_server) = server::RedisServer::from_args().await?;
     let address = redis_server.address().await;
+    let (supervised, logfile) = {
+        let server = redis_server.read().await;
+        (server.config.supervised, server.config.logfile.clone())
+    };
+
+    let log_target = telemetry::LogTarget::new();
+    log_target.set_file(logfile.as_deref())?;
+    let subscriber =
+        telemetry::get_subscriber("not-redis".into(), "info".into(), log_target.clone());
+    telemetry::init_subscriber(subscriber);
+
+    tokio::spawn(app::reload_on_hangup(redis_server.clone(), log_target));
 
-    app::run(&address, database, redis_server, tx).await
+    // Biased so the signal handlers are installed before we start accepting connections.
+    tokio::select! {
+        biased;
+        result = app::shutdown_signal() => {
+            if supervised == Supervised::Systemd {
+                systemd::notify_stopping()?;
+            }
+            result?;
+            redis_server
+                .prepare_shutdown(&database, ShutdownSave::Default)
+                .await
+        }
+        result = app::run(&address, database.clone(), redis_server.clone()) => result,
+    }
 }
@@ -0,0 +1,52 @@
+//! Namespaces let tenants, such as the test environments of different projects,
+//! share one server without seeing each other's keys. A connection switches to one
+//! with SELECTNS and from then on every command it runs works on that namespace's
+//! own dataset. The default namespace is the server's dataset; the others only live
+//! in memory, so what's written to them isn't saved, appended to the AOF or
+//! replicated.
+
+use crate::data::Database;
+use crate::errors::RedisError;
+use crate::request::Command;
+
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// How far each namespace other than the default one may grow, 0 meaning it
+/// isn't limited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NamespaceLimits {
+    pub max_keys: u64,
+    /// Measured with `Database::used_memory`.
+    pub max_memory: u64,
+}
+
+impl NamespaceLimits {
+    /// Refuses a write that would take `database` past the limits. Writes that only
+    /// remove keys are always let through, so a full namespace can be cleared out.
+    pub fn check(&self, database: &Database, request: &Command) -> Result<(), RedisError> {
+        if request.only_removes_keys() {
+            return Ok(());
+        }
+
+        if self.max_keys > 0 {
+            let new_keys = request
+                .keys()
+                .iter()
+                .filter(|key| !database.exists(key))
+                .count();
+            if new_keys > 0 && (database.len() + new_keys) as u64 > self.max_keys {
+                return Err(RedisError::custom(
+                    "ERR command not allowed when the namespace has 'namespace-max-keys' keys",
+                ));
+            }
+        }
+
+        if self.max_memory > 0 && database.used_memory() as u64 > self.max_memory {
+            return Err(RedisError::custom(
+                "OOM command not allowed when used memory > 'namespace-max-memory'.",
+            ));
+        }
+
+        Ok(())
+    }
+}
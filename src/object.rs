@@ -0,0 +1,883 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::ops::Range;
+
+// Like redis, strings up to this length are reported as embstr
+const MAX_EMBSTR_LEN: usize = 44;
+// The defaults of redis' set-max-intset-entries, set-max-listpack-entries,
+// hash-max-listpack-entries and the matching -value settings
+const MAX_INTSET_ENTRIES: usize = 512;
+const MAX_LISTPACK_ENTRIES: usize = 128;
+const MAX_LISTPACK_VALUE: usize = 64;
+// Like redis' default list-max-listpack-size of -2, each chunk of a list holds up
+// to 8kb. It's also capped at a number of values so finding one in a chunk stays quick.
+const MAX_CHUNK_BYTES: usize = 8 * 1024;
+const MAX_CHUNK_ENTRIES: usize = 128;
+
+/// A string value. Strings that are integers are stored as one, so INCR and friends
+/// don't have to parse and format the value every time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringValue {
+    Int(i64),
+    Raw(String),
+}
+
+impl StringValue {
+    /// Only strings that read back exactly the same, e.g. no leading zeros or
+    /// plus sign, are stored as integers.
+    pub fn new(data: String) -> Self {
+        match data.parse::<i64>() {
+            Ok(value) if value.to_string() == data => StringValue::Int(value),
+            _ => StringValue::Raw(data),
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            StringValue::Int(value) => Some(*value),
+            StringValue::Raw(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            StringValue::Int(value) => Cow::Owned(value.to_string()),
+            StringValue::Raw(data) => Cow::Borrowed(data),
+        }
+    }
+
+    /// The name OBJECT ENCODING reports.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            StringValue::Int(_) => "int",
+            StringValue::Raw(data) if data.len() <= MAX_EMBSTR_LEN => "embstr",
+            StringValue::Raw(_) => "raw",
+        }
+    }
+}
+
+impl From<i64> for StringValue {
+    fn from(value: i64) -> Self {
+        StringValue::Int(value)
+    }
+}
+
+impl fmt::Display for StringValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringValue::Int(value) => write!(f, "{}", value),
+            StringValue::Raw(data) => write!(f, "{}", data),
+        }
+    }
+}
+
+/// The bytes of the string in `range`. Strings are kept as UTF-8, so GETRANGE and
+/// SETRANGE both work on bytes but refuse to start or end part of the way through
+/// a character: `None` if the range does, rather than part of one.
+pub fn string_range(data: &str, range: Range<usize>) -> Option<&str> {
+    data.get(range)
+}
+
+/// The string with `value` written over it from byte `offset` on, padded with
+/// zero bytes if it's shorter than that. `None` if the bytes overwritten start
+/// or end part of the way through a character, like for `string_range`.
+pub fn overwrite_string(data: &str, offset: usize, value: &str) -> Option<String> {
+    let end = offset + value.len();
+    let splits_character = |index: usize| index < data.len() && !data.is_char_boundary(index);
+    if splits_character(offset) || splits_character(end) {
+        return None;
+    }
+
+    let mut overwritten = String::with_capacity(data.len().max(end));
+    overwritten.push_str(&data[..offset.min(data.len())]);
+    overwritten.extend(std::iter::repeat_n('\0', offset.saturating_sub(data.len())));
+    overwritten.push_str(value);
+    if end < data.len() {
+        overwritten.push_str(&data[end..]);
+    }
+
+    Some(overwritten)
+}
+
+/// A set that starts out as a sorted array of integers or a plain array of members,
+/// and is only turned into a hash table once it grows past the listpack limits.
+#[derive(Debug, Clone)]
+pub enum RedisSet {
+    IntSet(Vec<i64>),
+    Listpack(Vec<String>),
+    HashTable(HashSet<String>),
+}
+
+impl Default for RedisSet {
+    fn default() -> Self {
+        RedisSet::IntSet(vec![])
+    }
+}
+
+impl RedisSet {
+    /// Returns false if the member was already in the set.
+    pub fn insert(&mut self, member: String) -> bool {
+        match self {
+            RedisSet::IntSet(members) => match StringValue::new(member) {
+                StringValue::Int(value) => match members.binary_search(&value) {
+                    Ok(_) => false,
+                    Err(_) if members.len() >= MAX_INTSET_ENTRIES => {
+                        self.convert_to_hash_table();
+                        self.insert(value.to_string())
+                    }
+                    Err(position) => {
+                        members.insert(position, value);
+                        true
+                    }
+                },
+                StringValue::Raw(member) => {
+                    let fits_listpack =
+                        members.len() < MAX_LISTPACK_ENTRIES && member.len() <= MAX_LISTPACK_VALUE;
+                    let members = members.iter().map(|value| value.to_string()).collect();
+                    *self = RedisSet::Listpack(members);
+                    if !fits_listpack {
+                        self.convert_to_hash_table();
+                    }
+                    self.insert(member)
+                }
+            },
+            RedisSet::Listpack(members) => {
+                if members.contains(&member) {
+                    return false;
+                }
+
+                if members.len() >= MAX_LISTPACK_ENTRIES || member.len() > MAX_LISTPACK_VALUE {
+                    self.convert_to_hash_table();
+                    return self.insert(member);
+                }
+
+                members.push(member);
+                true
+            }
+            RedisSet::HashTable(members) => members.insert(member),
+        }
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        match self {
+            RedisSet::IntSet(members) => member
+                .parse::<i64>()
+                .is_ok_and(|value| members.binary_search(&value).is_ok()),
+            RedisSet::Listpack(members) => members.iter().any(|existing| existing == member),
+            RedisSet::HashTable(members) => members.contains(member),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisSet::IntSet(members) => members.len(),
+            RedisSet::Listpack(members) => members.len(),
+            RedisSet::HashTable(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, str>> + '_> {
+        match self {
+            RedisSet::IntSet(members) => {
+                Box::new(members.iter().map(|value| Cow::Owned(value.to_string())))
+            }
+            RedisSet::Listpack(members) => Box::new(members.iter().map(Cow::from)),
+            RedisSet::HashTable(members) => Box::new(members.iter().map(Cow::from)),
+        }
+    }
+
+    /// The name OBJECT ENCODING reports.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            RedisSet::IntSet(_) => "intset",
+            RedisSet::Listpack(_) => "listpack",
+            RedisSet::HashTable(_) => "hashtable",
+        }
+    }
+
+    fn convert_to_hash_table(&mut self) {
+        let members = self.iter().map(|member| member.into_owned()).collect();
+        *self = RedisSet::HashTable(members);
+    }
+}
+
+impl FromIterator<String> for RedisSet {
+    fn from_iter<T: IntoIterator<Item = String>>(iter: T) -> Self {
+        let mut set = RedisSet::default();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+/// A hash kept as an array of field value pairs until it grows past the listpack limits.
+#[derive(Debug, Clone)]
+pub enum RedisHash {
+    Listpack(Vec<(String, String)>),
+    HashTable(HashMap<String, String>),
+}
+
+impl Default for RedisHash {
+    fn default() -> Self {
+        RedisHash::Listpack(vec![])
+    }
+}
+
+impl RedisHash {
+    /// Returns the previous value of the field, if it had one.
+    pub fn insert(&mut self, field: String, value: String) -> Option<String> {
+        match self {
+            RedisHash::Listpack(entries) => {
+                if let Some((_, existing)) = entries.iter_mut().find(|(f, _)| *f == field) {
+                    return Some(std::mem::replace(existing, value));
+                }
+
+                if entries.len() >= MAX_LISTPACK_ENTRIES
+                    || field.len() > MAX_LISTPACK_VALUE
+                    || value.len() > MAX_LISTPACK_VALUE
+                {
+                    let entries = std::mem::take(entries).into_iter().collect();
+                    *self = RedisHash::HashTable(entries);
+                    return self.insert(field, value);
+                }
+
+                entries.push((field, value));
+                None
+            }
+            RedisHash::HashTable(entries) => entries.insert(field, value),
+        }
+    }
+
+    /// Returns the value the field had, if it was in the hash.
+    pub fn remove(&mut self, field: &str) -> Option<String> {
+        match self {
+            RedisHash::Listpack(entries) => {
+                let position = entries.iter().position(|(existing, _)| existing == field)?;
+                Some(entries.remove(position).1)
+            }
+            RedisHash::HashTable(entries) => entries.remove(field),
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&String> {
+        match self {
+            RedisHash::Listpack(entries) => entries
+                .iter()
+                .find(|(existing, _)| existing == field)
+                .map(|(_, value)| value),
+            RedisHash::HashTable(entries) => entries.get(field),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisHash::Listpack(entries) => entries.len(),
+            RedisHash::HashTable(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&String, &String)> + '_> {
+        match self {
+            RedisHash::Listpack(entries) => {
+                Box::new(entries.iter().map(|(field, value)| (field, value)))
+            }
+            RedisHash::HashTable(entries) => Box::new(entries.iter()),
+        }
+    }
+
+    /// The name OBJECT ENCODING reports.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            RedisHash::Listpack(_) => "listpack",
+            RedisHash::HashTable(_) => "hashtable",
+        }
+    }
+}
+
+/// A list kept as a sequence of chunks, like redis' quicklist. Inserting or removing
+/// a value only moves the values in its chunk, and finding one skips whole chunks.
+/// Each chunk keeps track of how many bytes it holds. Neighbouring chunks that
+/// would fit in one are merged as values are removed.
+#[derive(Debug, Clone, Default)]
+pub struct RedisList {
+    chunks: VecDeque<ListChunk>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ListChunk {
+    values: VecDeque<String>,
+    bytes: usize,
+}
+
+impl ListChunk {
+    fn is_full(&self) -> bool {
+        self.values.len() >= MAX_CHUNK_ENTRIES || self.bytes >= MAX_CHUNK_BYTES
+    }
+
+    /// Whether the values of both chunks would fit in one that isn't full.
+    fn fits_with(&self, other: &ListChunk) -> bool {
+        self.values.len() + other.values.len() < MAX_CHUNK_ENTRIES
+            && self.bytes + other.bytes < MAX_CHUNK_BYTES
+    }
+}
+
+impl RedisList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, value: String) {
+        if self.chunks.front().is_none_or(ListChunk::is_full) {
+            self.chunks.push_front(ListChunk::default());
+        }
+        let chunk = &mut self.chunks[0];
+        chunk.bytes += value.len();
+        chunk.values.push_front(value);
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: String) {
+        if self.chunks.back().is_none_or(ListChunk::is_full) {
+            self.chunks.push_back(ListChunk::default());
+        }
+        let chunk = self.chunks.back_mut().expect("a chunk was just added");
+        chunk.bytes += value.len();
+        chunk.values.push_back(value);
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<String> {
+        self.remove_from(0, 0)
+    }
+
+    pub fn pop_back(&mut self) -> Option<String> {
+        let last = self.chunks.len().checked_sub(1)?;
+        let offset = self.chunks[last].values.len() - 1;
+        self.remove_from(last, offset)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&String> {
+        let (chunk, offset) = self.locate(index)?;
+        self.chunks[chunk].values.get(offset)
+    }
+
+    /// Replaces the value at `index`, returning the one it had.
+    pub fn set(&mut self, index: usize, value: String) -> Option<String> {
+        let (position, offset) = self.locate(index)?;
+        let chunk = &mut self.chunks[position];
+        chunk.bytes = chunk.bytes + value.len() - chunk.values[offset].len();
+        let replaced = std::mem::replace(&mut chunk.values[offset], value);
+        self.merge_around(position);
+        Some(replaced)
+    }
+
+    /// Inserts `value` so it ends up at `index`, which can be the length of the list
+    /// to add it to the end. A full chunk is split in two first, unless it's full
+    /// with a single value, which the new one then goes in a chunk of its own before.
+    pub fn insert(&mut self, index: usize, value: String) {
+        if index >= self.len {
+            return self.push_back(value);
+        }
+
+        let (mut chunk, mut offset) = self.locate(index).expect("the index is in the list");
+        let first = chunk;
+        if self.chunks[chunk].is_full() && self.chunks[chunk].values.len() == 1 {
+            self.chunks.insert(chunk, ListChunk::default());
+        } else if self.chunks[chunk].is_full() {
+            let half = self.chunks[chunk].values.len() / 2;
+            let values = self.chunks[chunk].values.split_off(half);
+            let bytes = values.iter().map(String::len).sum();
+            self.chunks[chunk].bytes -= bytes;
+            self.chunks.insert(chunk + 1, ListChunk { values, bytes });
+            if offset >= half {
+                chunk += 1;
+                offset -= half;
+            }
+        }
+
+        let chunk = &mut self.chunks[chunk];
+        chunk.bytes += value.len();
+        chunk.values.insert(offset, value);
+        self.len += 1;
+        // Either chunk of a split, or the new one, can fit with its other neighbour
+        self.merge_around(first + 1);
+        self.merge_around(first);
+    }
+
+    /// Where `value` first occurs in the list.
+    pub fn position(&self, value: &str) -> Option<usize> {
+        self.iter().position(|existing| existing == value)
+    }
+
+    /// Removes up to `limit` occurrences of `value`, starting from the back if
+    /// `from_back` is set, and returns how many were removed.
+    pub fn remove_matching(&mut self, value: &str, limit: usize, from_back: bool) -> usize {
+        let mut removed = 0;
+        let chunks: Vec<usize> = match from_back {
+            true => (0..self.chunks.len()).rev().collect(),
+            false => (0..self.chunks.len()).collect(),
+        };
+        for chunk in chunks {
+            let values = &self.chunks[chunk].values;
+            let mut matches: Vec<usize> = (0..values.len())
+                .filter(|offset| values[*offset] == value)
+                .collect();
+            if from_back {
+                matches.reverse();
+            }
+            matches.truncate(limit - removed);
+            // Removed from the back of the chunk so the other offsets stay put
+            matches.sort_unstable_by(|a, b| b.cmp(a));
+            let chunk = &mut self.chunks[chunk];
+            for offset in matches {
+                let value = chunk
+                    .values
+                    .remove(offset)
+                    .expect("the offset is in the chunk");
+                chunk.bytes -= value.len();
+                removed += 1;
+            }
+            if removed == limit {
+                break;
+            }
+        }
+
+        self.len -= removed;
+        self.chunks.retain(|chunk| !chunk.values.is_empty());
+        self.merge_all();
+        removed
+    }
+
+    /// Keeps only the values from `start` up to, but not including, `end`. Chunks
+    /// that fall outside the range are dropped whole.
+    pub fn trim(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len);
+        let start = start.min(end);
+
+        let mut from_back = self.len - end;
+        while let Some(chunk) = self.chunks.back_mut() {
+            if from_back == 0 {
+                break;
+            }
+            if chunk.values.len() <= from_back {
+                from_back -= chunk.values.len();
+                self.chunks.pop_back();
+                continue;
+            }
+            let kept = chunk.values.len() - from_back;
+            chunk.bytes -= chunk
+                .values
+                .drain(kept..)
+                .map(|value| value.len())
+                .sum::<usize>();
+            break;
+        }
+
+        let mut from_front = start;
+        while let Some(chunk) = self.chunks.front_mut() {
+            if from_front == 0 {
+                break;
+            }
+            if chunk.values.len() <= from_front {
+                from_front -= chunk.values.len();
+                self.chunks.pop_front();
+                continue;
+            }
+            chunk.bytes -= chunk
+                .values
+                .drain(..from_front)
+                .map(|value| value.len())
+                .sum::<usize>();
+            break;
+        }
+
+        self.len = end - start;
+        // Only the chunks at either end were cut into
+        self.merge_around(0);
+        if let Some(last) = self.chunks.len().checked_sub(1) {
+            self.merge_around(last);
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &String> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.values.iter())
+    }
+
+    /// The values from `start` up to, but not including, `end`, skipping straight to
+    /// the chunk `start` is in.
+    pub fn range(&self, start: usize, end: usize) -> impl Iterator<Item = &String> + '_ {
+        let (chunk, offset) = self.locate(start).unwrap_or((self.chunks.len(), 0));
+        self.chunks
+            .iter()
+            .skip(chunk)
+            .flat_map(|chunk| chunk.values.iter())
+            .skip(offset)
+            .take(end.saturating_sub(start))
+    }
+
+    /// How many bytes of values each chunk holds, front to back.
+    pub fn chunk_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.chunks.iter().map(|chunk| chunk.bytes)
+    }
+
+    /// How many bytes of values the whole list holds.
+    pub fn memory_usage(&self) -> usize {
+        self.chunk_sizes().sum()
+    }
+
+    /// The name OBJECT ENCODING reports. Like redis, a list that fits in a single
+    /// chunk is a listpack.
+    pub fn encoding(&self) -> &'static str {
+        match self.chunks.len() {
+            0 | 1 => "listpack",
+            _ => "quicklist",
+        }
+    }
+
+    /// The chunk the value at `index` is in and where it is in it, counting from
+    /// whichever end of the list is closer.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+
+        if index < self.len / 2 {
+            let mut index = index;
+            for (position, chunk) in self.chunks.iter().enumerate() {
+                if index < chunk.values.len() {
+                    return Some((position, index));
+                }
+                index -= chunk.values.len();
+            }
+        } else {
+            let mut from_back = self.len - 1 - index;
+            for (position, chunk) in self.chunks.iter().enumerate().rev() {
+                if from_back < chunk.values.len() {
+                    return Some((position, chunk.values.len() - 1 - from_back));
+                }
+                from_back -= chunk.values.len();
+            }
+        }
+
+        None
+    }
+
+    fn remove_from(&mut self, chunk: usize, offset: usize) -> Option<String> {
+        let values = &mut self.chunks.get_mut(chunk)?.values;
+        let value = values.remove(offset)?;
+        self.chunks[chunk].bytes -= value.len();
+        if self.chunks[chunk].values.is_empty() {
+            self.chunks.remove(chunk);
+        }
+        self.len -= 1;
+        // Either what's left of the chunk or the one that took its place
+        self.merge_around(chunk.min(self.chunks.len().saturating_sub(1)));
+        Some(value)
+    }
+
+    /// Merges the chunk at `position` with the ones either side of it, wherever they
+    /// fit together.
+    fn merge_around(&mut self, position: usize) {
+        if position + 1 < self.chunks.len() {
+            self.merge_into_previous(position + 1);
+        }
+        if position > 0 && position < self.chunks.len() {
+            self.merge_into_previous(position);
+        }
+    }
+
+    /// Merges every pair of neighbouring chunks that fit together.
+    fn merge_all(&mut self) {
+        let mut position = 1;
+        while position < self.chunks.len() {
+            if !self.merge_into_previous(position) {
+                position += 1;
+            }
+        }
+    }
+
+    /// Moves the values of the chunk at `position` onto the end of the one before it
+    /// if they fit there, returning whether they did.
+    fn merge_into_previous(&mut self, position: usize) -> bool {
+        if !self.chunks[position - 1].fits_with(&self.chunks[position]) {
+            return false;
+        }
+
+        let chunk = self
+            .chunks
+            .remove(position)
+            .expect("the chunk is in the list");
+        let previous = &mut self.chunks[position - 1];
+        previous.bytes += chunk.bytes;
+        previous.values.extend(chunk.values);
+        true
+    }
+}
+
+impl Extend<String> for RedisList {
+    fn extend<T: IntoIterator<Item = String>>(&mut self, iter: T) {
+        for value in iter {
+            self.push_back(value);
+        }
+    }
+}
+
+impl FromIterator<String> for RedisList {
+    fn from_iter<T: IntoIterator<Item = String>>(iter: T) -> Self {
+        let mut list = RedisList::default();
+        list.extend(iter);
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn string_ranges_keep_to_whole_characters() {
+        // 'é' takes bytes 3 and 4
+        let data = "café-olé";
+        assert_eq!(string_range(data, 0..5), Some("café"));
+        assert_eq!(string_range(data, 0..4), None);
+        assert_eq!(string_range(data, 4..6), None);
+
+        assert_eq!(overwrite_string(data, 3, "e"), None);
+        assert_eq!(overwrite_string(data, 4, "x"), None);
+        assert_eq!(overwrite_string(data, 2, "xy"), None);
+        assert_eq!(overwrite_string(data, 8, "x"), None);
+        assert_eq!(
+            overwrite_string(data, 3, "e!").as_deref(),
+            Some("cafe!-olé")
+        );
+        assert_eq!(
+            overwrite_string(data, 11, "x").as_deref(),
+            Some("café-olé\0x")
+        );
+    }
+
+    #[test]
+    fn only_canonical_integers_are_stored_as_integers() {
+        assert_eq!(StringValue::new("-42".to_string()), StringValue::Int(-42));
+        assert_eq!(StringValue::new("42".to_string()).encoding(), "int");
+        assert_eq!(StringValue::new("042".to_string()).encoding(), "embstr");
+        assert_eq!(StringValue::new("+1".to_string()).encoding(), "embstr");
+        assert_eq!(StringValue::new("a".repeat(45)).encoding(), "raw");
+    }
+
+    #[test]
+    fn sets_grow_out_of_their_compact_encodings() {
+        let mut set: RedisSet = ["3", "1", "2"].map(String::from).into_iter().collect();
+        assert_eq!(set.encoding(), "intset");
+        assert!(!set.insert("2".to_string()));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec!["1", "2", "3"]);
+
+        assert!(set.insert("a".to_string()));
+        assert_eq!(set.encoding(), "listpack");
+        assert!(set.contains("1") && set.contains("a"));
+
+        assert!(set.insert("b".repeat(MAX_LISTPACK_VALUE + 1)));
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), 5);
+    }
+
+    #[test]
+    fn lists_are_split_into_chunks_as_they_grow() {
+        let mut list: RedisList = (0..MAX_CHUNK_ENTRIES).map(|i| i.to_string()).collect();
+        assert_eq!(list.encoding(), "listpack");
+
+        list.push_front("front".to_string());
+        list.insert(MAX_CHUNK_ENTRIES / 2, "middle".to_string());
+        assert_eq!(list.encoding(), "quicklist");
+        assert_eq!(list.len(), MAX_CHUNK_ENTRIES + 2);
+        assert_eq!(list.get(0), Some(&"front".to_string()));
+        assert_eq!(list.get(MAX_CHUNK_ENTRIES / 2), Some(&"middle".to_string()));
+        assert_eq!(list.get(MAX_CHUNK_ENTRIES + 1), Some(&"127".to_string()));
+        assert_eq!(
+            list.memory_usage(),
+            list.iter().map(String::len).sum::<usize>()
+        );
+        // The chunk with just the front in it was merged into the first half of the split
+        assert_eq!(list.chunk_sizes().count(), 2);
+
+        assert_eq!(list.set(1, "zero".to_string()), Some("0".to_string()));
+        assert_eq!(list.remove_matching("middle", 1, true), 1);
+        list.trim(1, 4);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec!["zero", "1", "2"]);
+        assert_eq!(list.range(1, 10).collect::<Vec<_>>(), vec!["1", "2"]);
+        assert_eq!(list.memory_usage(), 6);
+        assert_eq!(list.encoding(), "listpack");
+    }
+
+    /// Every chunk has at least one value, and knows how many bytes they add up to.
+    /// No two neighbouring chunks would fit in one.
+    fn assert_chunks_are_sound(list: &RedisList) {
+        for (previous, chunk) in list.chunks.iter().zip(list.chunks.iter().skip(1)) {
+            assert!(!previous.fits_with(chunk));
+        }
+        for chunk in list.chunks.iter() {
+            assert!(!chunk.values.is_empty());
+            assert_eq!(
+                chunk.bytes,
+                chunk.values.iter().map(String::len).sum::<usize>()
+            );
+        }
+        let len: usize = list.chunks.iter().map(|chunk| chunk.values.len()).sum();
+        assert_eq!(list.len(), len);
+    }
+
+    #[test]
+    fn values_too_big_to_share_a_chunk_get_their_own() {
+        let big = "x".repeat(MAX_CHUNK_BYTES + 1);
+        let mut list: RedisList = std::iter::once(big.clone()).collect();
+
+        list.insert(0, "before".to_string());
+        assert_chunks_are_sound(&list);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec!["before", &big]);
+        assert_eq!(list.pop_front(), Some("before".to_string()));
+        assert_eq!(list.pop_front(), Some(big));
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn chunks_are_merged_as_values_are_removed() {
+        let mut list: RedisList = (0..MAX_CHUNK_ENTRIES * 8)
+            .map(|i| if i % 4 == 0 { "keep" } else { "drop" }.to_string())
+            .collect();
+        assert_eq!(list.chunk_sizes().count(), 8);
+
+        let removed = list.remove_matching("drop", usize::MAX, false);
+        assert_eq!(removed, MAX_CHUNK_ENTRIES * 6);
+        assert_chunks_are_sound(&list);
+        // A quarter of each chunk is left, and three of those fit in one
+        assert_eq!(list.chunk_sizes().count(), 3);
+
+        list.trim(1, list.len() - 1);
+        while list.len() > MAX_CHUNK_ENTRIES / 2 {
+            list.pop_back();
+            list.pop_front();
+        }
+        assert_chunks_are_sound(&list);
+        assert_eq!(list.encoding(), "listpack");
+    }
+
+    #[test]
+    fn lists_behave_like_a_deque() {
+        for seed in 0..8 {
+            check_against_a_deque(seed);
+        }
+    }
+
+    /// Applies the same random operations to a list and a `VecDeque`, with values big
+    /// enough to fill a chunk on their own now and then.
+    fn check_against_a_deque(seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut list = RedisList::default();
+        let mut expected = VecDeque::new();
+        for step in 0..5_000 {
+            let value = match rng.gen_range(0..20) {
+                0 => format!("{}{}", rng.gen_range(0..3), "x".repeat(MAX_CHUNK_BYTES)),
+                _ => rng.gen_range(0..50).to_string(),
+            };
+            let index = rng.gen_range(0..=expected.len());
+            match rng.gen_range(0..8) {
+                0 | 1 => {
+                    list.push_back(value.clone());
+                    expected.push_back(value);
+                }
+                2 => {
+                    list.push_front(value.clone());
+                    expected.push_front(value);
+                }
+                3 => {
+                    list.insert(index, value.clone());
+                    expected.insert(index, value);
+                }
+                4 => assert_eq!(list.pop_front(), expected.pop_front()),
+                5 => assert_eq!(list.pop_back(), expected.pop_back()),
+                6 => {
+                    let limit = rng.gen_range(1..=3);
+                    let removed = list.remove_matching(&value, limit, step % 2 == 0);
+                    let mut matches: Vec<usize> = (0..expected.len())
+                        .filter(|index| expected[*index] == value)
+                        .collect();
+                    if step % 2 == 0 {
+                        matches.reverse();
+                    }
+                    matches.truncate(limit);
+                    matches.sort_unstable_by(|a, b| b.cmp(a));
+                    for index in matches.iter() {
+                        expected.remove(*index);
+                    }
+                    assert_eq!(removed, matches.len());
+                }
+                _ if step % 100 == 0 => {
+                    let end = index + rng.gen_range(0..1_000);
+                    list.trim(index, end);
+                    expected.truncate(end);
+                    expected.drain(..index.min(expected.len()));
+                }
+                _ => {
+                    if let Some(existing) = expected.get_mut(index) {
+                        assert_eq!(list.set(index, value.clone()), Some(existing.clone()));
+                        *existing = value;
+                    }
+                }
+            }
+
+            assert_eq!(list.len(), expected.len(), "seed {}", seed);
+            assert_eq!(list.get(index), expected.get(index), "seed {}", seed);
+            assert_chunks_are_sound(&list);
+        }
+
+        assert!(list.iter().eq(expected.iter()));
+        assert!(list
+            .range(10, 20)
+            .eq(expected.range(10.min(expected.len())..20.min(expected.len()))));
+        assert_eq!(
+            list.memory_usage(),
+            expected.iter().map(String::len).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn hashes_grow_out_of_their_compact_encoding() {
+        let mut hash = RedisHash::default();
+        for i in 0..MAX_LISTPACK_ENTRIES {
+            hash.insert(i.to_string(), "value".to_string());
+        }
+        assert_eq!(hash.encoding(), "listpack");
+        assert_eq!(
+            hash.insert("0".to_string(), "new".to_string()),
+            Some("value".to_string())
+        );
+
+        hash.insert("last".to_string(), "value".to_string());
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.get("0"), Some(&"new".to_string()));
+        assert_eq!(hash.len(), MAX_LISTPACK_ENTRIES + 1);
+
+        assert_eq!(hash.remove("0"), Some("new".to_string()));
+        assert_eq!(hash.remove("0"), None);
+        assert_eq!(hash.len(), MAX_LISTPACK_ENTRIES);
+    }
+}
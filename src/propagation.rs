@@ -0,0 +1,374 @@
+//! Writes whose effect depends on when they run, like an expiry relative to now
+//! or an ID the server generates, are rewritten before they're appended to the
+//! AOF and sent to replicas. Replaying what's propagated then leaves a replica, or
+//! a server restarting from the AOF, with exactly the dataset the master has.
+
+use crate::encoding;
+use crate::extension;
+use crate::request::{Command, CommandExpiration, ListEnd, SetOverride, XAddNumber};
+use crate::resp::Value;
+
+/// Fixes what a write would otherwise work out from the clock when it's applied,
+/// so the master applies exactly the command it propagates. `now` is the unix
+/// timestamp in milliseconds by the database's clock.
+pub fn pin(command: Command, now: u128) -> Command {
+    match command {
+        Command::Set(mut set) => {
+            set.expires = pin_expiration(set.expires, now);
+            Command::Set(set)
+        }
+        Command::GetEx(key, expires) => Command::GetEx(key, pin_expiration(expires, now)),
+        Command::Expire(key, expires, condition) => {
+            Command::Expire(key, pin_expiration(expires, now), condition)
+        }
+        Command::Restore(mut restore) if restore.ttl > 0 && !restore.absttl => {
+            restore.ttl += now as u64;
+            restore.absttl = true;
+            Command::Restore(restore)
+        }
+        Command::TsAdd(mut add) if add.timestamp.is_none() => {
+            add.timestamp = Some(now as u64);
+            Command::TsAdd(add)
+        }
+        command => command,
+    }
+}
+
+fn pin_expiration(expires: CommandExpiration, now: u128) -> CommandExpiration {
+    match expires {
+        CommandExpiration::After(duration) => CommandExpiration::At(now + duration.as_millis()),
+        expires => expires,
+    }
+}
+
+/// What's propagated in place of a write. It's worked out before the write is
+/// applied, since applying it consumes the command, and finished with the reply.
+#[derive(Debug, PartialEq)]
+pub enum Rewrite {
+    /// The command goes out as the client sent it.
+    Verbatim,
+    /// The command goes out as these arguments, name first.
+    Command(Vec<String>),
+    /// INCRBYFLOAT sets the key to the value it replies with, keeping the TTL, so
+    /// replicas don't have to repeat the floating point math.
+    SetToReply(String),
+    /// XADD with an ID to generate adds the entry under the ID it replies with.
+    XaddWithReplyId(String, Vec<String>),
+    /// BLPOP, BRPOP and BLMPOP pop off the end of whichever list they reply with the
+    /// key of, so replaying them never has to wait. One that timed out changed
+    /// nothing, so nothing goes out.
+    PopFromReply(ListEnd),
+    /// BLMOVE goes out as the LMOVE it waited to make, these arguments, or not at
+    /// all if it timed out.
+    MoveFromReply(Vec<String>),
+    /// An extension command, which can rewrite itself once it has its reply.
+    Extension(&'static str, Vec<String>),
+}
+
+impl Rewrite {
+    /// Expects a command that went through `pin`.
+    pub fn of(command: &Command) -> Self {
+        match command {
+            Command::Set(set) => {
+                let CommandExpiration::At(deadline) = set.expires else {
+                    return Rewrite::Verbatim;
+                };
+                let mut args = vec![
+                    "SET".to_string(),
+                    set.key.clone(),
+                    set.value.clone(),
+                    "PXAT".to_string(),
+                    deadline.to_string(),
+                ];
+                match set.overwrite {
+                    SetOverride::Normal => {}
+                    SetOverride::NeverOverwrite => args.push("NX".to_string()),
+                    SetOverride::OnlyOverwrite => args.push("XX".to_string()),
+                }
+                if set.get_old_value {
+                    args.push("GET".to_string());
+                }
+                Rewrite::Command(args)
+            }
+            Command::GetEx(key, CommandExpiration::At(deadline)) => Rewrite::Command(vec![
+                "GETEX".to_string(),
+                key.clone(),
+                "PXAT".to_string(),
+                deadline.to_string(),
+            ]),
+            // EXPIRE, PEXPIRE and EXPIREAT all go out as PEXPIREAT
+            Command::Expire(key, CommandExpiration::At(deadline), condition) => {
+                let mut args = vec!["PEXPIREAT".to_string(), key.clone(), deadline.to_string()];
+                args.extend(condition.options().into_iter().map(String::from));
+                Rewrite::Command(args)
+            }
+            Command::Restore(restore) if restore.absttl => {
+                let mut args = vec![
+                    "RESTORE".to_string(),
+                    restore.key.clone(),
+                    restore.ttl.to_string(),
+                    restore.payload.clone(),
+                    "ABSTTL".to_string(),
+                ];
+                if restore.replace {
+                    args.push("REPLACE".to_string());
+                }
+                Rewrite::Command(args)
+            }
+            Command::IncrByFloat(key, _) => Rewrite::SetToReply(key.clone()),
+            Command::Xadd(add)
+                if matches!(add.ms_time, XAddNumber::Autogenerate)
+                    || matches!(add.sequence_number, XAddNumber::Autogenerate) =>
+            {
+                let fields = add
+                    .data
+                    .iter()
+                    .flat_map(|item| [item.key.clone(), item.value.clone()])
+                    .collect();
+                Rewrite::XaddWithReplyId(add.stream_key.clone(), fields)
+            }
+            Command::TsAdd(add) => {
+                let Some(timestamp) = add.timestamp else {
+                    return Rewrite::Verbatim;
+                };
+                let mut args = vec![
+                    "TS.ADD".to_string(),
+                    add.key.clone(),
+                    timestamp.to_string(),
+                    add.value.to_string(),
+                    "RETENTION".to_string(),
+                    add.retention.to_string(),
+                ];
+                if !add.labels.is_empty() {
+                    args.push("LABELS".to_string());
+                    for (label, value) in add.labels.iter() {
+                        args.extend([label.clone(), value.clone()]);
+                    }
+                }
+                Rewrite::Command(args)
+            }
+            Command::BLMove(command, _) => Rewrite::MoveFromReply(vec![
+                "LMOVE".to_string(),
+                command.source.clone(),
+                command.destination.clone(),
+                end_name(command.from).to_string(),
+                end_name(command.to).to_string(),
+            ]),
+            Command::BLPop(..) => Rewrite::PopFromReply(ListEnd::Left),
+            Command::BRPop(..) => Rewrite::PopFromReply(ListEnd::Right),
+            Command::BLMPop(command, _) => Rewrite::PopFromReply(command.end),
+            Command::Extension(name, args) => Rewrite::Extension(name, args.clone()),
+            _ => Rewrite::Verbatim,
+        }
+    }
+
+    /// What to propagate once the write has replied.
+    pub fn encode(self, replies: &[Value]) -> Propagation {
+        let blocked = matches!(self, Rewrite::PopFromReply(_) | Rewrite::MoveFromReply(_));
+        if blocked && matches!(replies.first(), Some(Value::Null | Value::NullArray)) {
+            return Propagation::Nothing;
+        }
+
+        match self.args(replies) {
+            Some(args) => {
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                Propagation::Rewritten(encoding::encode_string_array(&args))
+            }
+            None => Propagation::AsSent,
+        }
+    }
+
+    /// The arguments to propagate, name first, or `None` for the command the
+    /// client sent.
+    fn args(self, replies: &[Value]) -> Option<Vec<String>> {
+        let args = match self {
+            Rewrite::Verbatim => return None,
+            Rewrite::Command(args) | Rewrite::MoveFromReply(args) => args,
+            Rewrite::SetToReply(key) => vec![
+                "SET".to_string(),
+                key,
+                replies.first()?.as_str()?.to_string(),
+                "KEEPTTL".to_string(),
+            ],
+            Rewrite::XaddWithReplyId(key, fields) => {
+                let id = replies.first()?.as_str()?.to_string();
+                ["XADD".to_string(), key, id]
+                    .into_iter()
+                    .chain(fields)
+                    .collect()
+            }
+            Rewrite::PopFromReply(end) => {
+                let Some(Value::Array(popped)) = replies.first() else {
+                    return None;
+                };
+                let name = match end {
+                    ListEnd::Left => "LPOP",
+                    ListEnd::Right => "RPOP",
+                };
+                let mut args = vec![name.to_string(), popped.first()?.as_str()?.to_string()];
+                // BLMPOP replies with every value it popped
+                if let Some(Value::Array(values)) = popped.get(1) {
+                    args.push(values.len().to_string());
+                }
+                args
+            }
+            Rewrite::Extension(name, args) => extension::rewrite(name, &args, replies.first()?)?,
+        };
+
+        Some(args)
+    }
+}
+
+/// What goes to the AOF and to replicas for a write.
+#[derive(Debug, PartialEq)]
+pub enum Propagation {
+    /// The command as the client sent it.
+    AsSent,
+    Rewritten(String),
+    /// Nothing, as the write didn't change anything.
+    Nothing,
+}
+
+fn end_name(end: ListEnd) -> &'static str {
+    match end {
+        ListEnd::Left => "LEFT",
+        ListEnd::Right => "RIGHT",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request;
+    use crate::utils::current_unix_timestamp;
+
+    fn command(args: &[&str]) -> Command {
+        let raw = std::iter::once(format!("*{}", args.len()))
+            .chain(
+                args.iter()
+                    .flat_map(|arg| [format!("${}", arg.len()), arg.to_string()]),
+            )
+            .collect();
+        pin(
+            parse_request(raw).unwrap(),
+            current_unix_timestamp().unwrap(),
+        )
+    }
+
+    fn encoded(args: &[&str]) -> String {
+        encoding::encode_string_array(args)
+    }
+
+    #[test]
+    fn relative_expirations_become_deadlines() {
+        let before = current_unix_timestamp().unwrap();
+        let Propagation::Rewritten(rewritten) =
+            Rewrite::of(&command(&["set", "foo", "bar", "ex", "10", "nx"])).encode(&[Value::ok()])
+        else {
+            panic!("SET EX wasn't rewritten");
+        };
+        let after = current_unix_timestamp().unwrap();
+
+        let deadline = (before + 10_000..=after + 10_000)
+            .find(|deadline| {
+                rewritten == encoded(&["SET", "foo", "bar", "PXAT", &deadline.to_string(), "NX"])
+            })
+            .expect("the deadline is ten seconds from now");
+
+        let before = current_unix_timestamp().unwrap();
+        let Propagation::Rewritten(psetex) =
+            Rewrite::of(&command(&["psetex", "foo", "2500", "bar"])).encode(&[Value::ok()])
+        else {
+            panic!("PSETEX is rewritten");
+        };
+        let after = current_unix_timestamp().unwrap();
+        assert!((before + 2_500..=after + 2_500).any(|deadline| {
+            psetex == encoded(&["SET", "foo", "bar", "PXAT", &deadline.to_string()])
+        }));
+
+        let getex = Rewrite::of(&command(&["getex", "foo", "pxat", &deadline.to_string()]));
+        assert_eq!(
+            getex.encode(&[Value::from("bar")]),
+            Propagation::Rewritten(encoded(&["GETEX", "foo", "PXAT", &deadline.to_string()]))
+        );
+        assert_eq!(Rewrite::of(&command(&["getex", "foo"])), Rewrite::Verbatim);
+
+        let before = current_unix_timestamp().unwrap();
+        let Propagation::Rewritten(restore) =
+            Rewrite::of(&command(&["restore", "foo", "500", "00ff", "replace"]))
+                .encode(&[Value::ok()])
+        else {
+            panic!("RESTORE with a TTL is rewritten");
+        };
+        let after = current_unix_timestamp().unwrap();
+        assert!((before + 500..=after + 500).any(|deadline| {
+            let deadline = deadline.to_string();
+            restore == encoded(&["RESTORE", "foo", &deadline, "00ff", "ABSTTL", "REPLACE"])
+        }));
+        let restore = Rewrite::of(&command(&["restore", "foo", "0", "00ff"]));
+        assert_eq!(restore, Rewrite::Verbatim);
+
+        let before = current_unix_timestamp().unwrap();
+        let Propagation::Rewritten(expire) =
+            Rewrite::of(&command(&["expire", "foo", "10", "xx", "gt"]))
+                .encode(&[Value::Integer(1)])
+        else {
+            panic!("EXPIRE is rewritten");
+        };
+        let after = current_unix_timestamp().unwrap();
+        assert!((before + 10_000..=after + 10_000).any(|deadline| {
+            expire == encoded(&["PEXPIREAT", "foo", &deadline.to_string(), "XX", "GT"])
+        }));
+        let expire_at = Rewrite::of(&command(&["expireat", "foo", "1700000000"]));
+        assert_eq!(
+            expire_at.encode(&[Value::Integer(1)]),
+            Propagation::Rewritten(encoded(&["PEXPIREAT", "foo", "1700000000000"]))
+        );
+        assert_eq!(
+            Rewrite::of(&command(&["set", "foo", "bar"])),
+            Rewrite::Verbatim
+        );
+    }
+
+    #[test]
+    fn generated_values_come_from_the_reply() {
+        let incr = Rewrite::of(&command(&["incrbyfloat", "foo", "0.1"]));
+        assert_eq!(
+            incr.encode(&[Value::from("1.1")]),
+            Propagation::Rewritten(encoded(&["SET", "foo", "1.1", "KEEPTTL"]))
+        );
+
+        let xadd = Rewrite::of(&command(&["xadd", "stream", "*", "a", "1"]));
+        assert_eq!(
+            xadd.encode(&[Value::from("1700000000000-0")]),
+            Propagation::Rewritten(encoded(&["XADD", "stream", "1700000000000-0", "a", "1"]))
+        );
+        let xadd = Rewrite::of(&command(&["xadd", "stream", "1-1", "a", "1"]));
+        assert_eq!(xadd, Rewrite::Verbatim);
+
+        let brpop = Rewrite::of(&command(&["brpop", "first", "second", "0"]));
+        assert_eq!(
+            brpop.encode(&[Value::bulk_array(&["second", "a"])]),
+            Propagation::Rewritten(encoded(&["RPOP", "second"]))
+        );
+        let blmove = Rewrite::of(&command(&["blmove", "from", "to", "left", "right", "0"]));
+        assert_eq!(
+            blmove.encode(&[Value::from("a")]),
+            Propagation::Rewritten(encoded(&["LMOVE", "from", "to", "LEFT", "RIGHT"]))
+        );
+        let blmpop = Rewrite::of(&command(&[
+            "blmpop", "0", "2", "a", "b", "left", "count", "5",
+        ]));
+        assert_eq!(
+            blmpop.encode(&[Value::Array(vec![
+                Value::from("b"),
+                Value::bulk_array(&["1", "2"])
+            ])]),
+            Propagation::Rewritten(encoded(&["LPOP", "b", "2"]))
+        );
+        let blpop = Rewrite::of(&command(&["blpop", "first", "1"]));
+        assert_eq!(blpop.encode(&[Value::NullArray]), Propagation::Nothing);
+        let blmove = Rewrite::of(&command(&["blmove", "from", "to", "left", "right", "1"]));
+        assert_eq!(blmove.encode(&[Value::Null]), Propagation::Nothing);
+    }
+}
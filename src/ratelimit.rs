@@ -0,0 +1,191 @@
+//! Per client limits on how many commands and how many bytes of requests a
+//! connection may send each second, so one busy client can't starve the rest of
+//! a shared server.
+//!
+//! Each limit is a token bucket holding up to a second's worth of tokens. A
+//! command goes through as long as the buckets aren't empty, and may take them
+//! below zero, so a request larger than a whole second's allowance still gets
+//! through and the client then waits off the debt.
+
+use std::time::{Duration, Instant};
+
+use crate::errors::RedisError;
+
+/// What happens to a command sent while its client is over a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitPolicy {
+    /// Refused with a THROTTLED error.
+    Reject,
+    /// Held back until the client is under its limits again.
+    Delay,
+}
+
+impl RateLimitPolicy {
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        match value.to_ascii_lowercase().as_str() {
+            "reject" => Ok(RateLimitPolicy::Reject),
+            "delay" => Ok(RateLimitPolicy::Delay),
+            _ => anyhow::bail!("argument must be 'reject' or 'delay'"),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RateLimitPolicy::Reject => "reject",
+            RateLimitPolicy::Delay => "delay",
+        }
+    }
+}
+
+/// The limits from the config, 0 meaning there's none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    pub commands_per_second: u64,
+    pub bytes_per_second: u64,
+    pub policy: RateLimitPolicy,
+}
+
+impl RateLimits {
+    pub fn is_limited(&self) -> bool {
+        self.commands_per_second > 0 || self.bytes_per_second > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::INFINITY,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, rate: u64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let capacity = rate as f64;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * capacity).min(capacity);
+        self.refilled_at = now;
+    }
+
+    /// How long until the bucket has tokens again, if it's empty.
+    fn wait(&self, rate: u64) -> Option<Duration> {
+        match self.tokens > 0.0 {
+            true => None,
+            false => Some(Duration::from_secs_f64((-self.tokens + 1.0) / rate as f64)),
+        }
+    }
+}
+
+/// A client's buckets, kept in its session.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    commands: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            commands: TokenBucket::new(now),
+            bytes: TokenBucket::new(now),
+        }
+    }
+
+    /// Counts a command of `bytes` against the limits. If the client is over either
+    /// of them the command isn't counted, and the error is how long until it isn't.
+    pub fn check(&mut self, limits: &RateLimits, bytes: usize) -> Result<(), Duration> {
+        self.check_at(limits, bytes, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        limits: &RateLimits,
+        bytes: usize,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let buckets = [
+            (&mut self.commands, limits.commands_per_second, 1.0),
+            (&mut self.bytes, limits.bytes_per_second, bytes as f64),
+        ];
+        let mut wait = None;
+        let mut limited = vec![];
+        for (bucket, rate, cost) in buckets {
+            if rate == 0 {
+                continue;
+            }
+            bucket.refill(rate, now);
+            wait = wait.max(bucket.wait(rate));
+            limited.push((bucket, cost));
+        }
+
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+        for (bucket, cost) in limited {
+            bucket.tokens -= cost;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn throttled() -> RedisError {
+    RedisError::custom("THROTTLED Too many commands or bytes per second from this client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(commands_per_second: u64, bytes_per_second: u64) -> RateLimits {
+        RateLimits {
+            commands_per_second,
+            bytes_per_second,
+            policy: RateLimitPolicy::Reject,
+        }
+    }
+
+    #[test]
+    fn clients_get_a_seconds_worth_at_once() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        let limits = limits(4, 0);
+
+        for _ in 0..4 {
+            assert_eq!(limiter.check_at(&limits, 10, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_at(&limits, 10, start),
+            Err(Duration::from_millis(250))
+        );
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check_at(&limits, 10, later), Ok(()));
+        assert_eq!(limiter.check_at(&limits, 10, later), Ok(()));
+        assert!(limiter.check_at(&limits, 10, later).is_err());
+    }
+
+    #[test]
+    fn large_requests_go_through_then_are_paid_off() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new();
+        let limits = limits(0, 100);
+
+        assert_eq!(limiter.check_at(&limits, 350, start), Ok(()));
+        let wait = limiter.check_at(&limits, 1, start).unwrap_err();
+        assert!((wait.as_secs_f64() - 2.51).abs() < 1e-6);
+        assert_eq!(limiter.check_at(&limits, 1, start + wait), Ok(()));
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt::Display;
+use std::ops::RangeInclusive;
 use std::time::Duration;
 
-use anyhow::Context;
-
-use crate::{data::RedisStreamItem, errors::not_an_integer, utils::current_unix_timestamp};
+use crate::cluster::parse_slot;
+use crate::data::RedisStreamItem;
+use crate::errors::{unknown_command, unknown_subcommand, wrong_number_of_arguments, RedisError};
+use crate::json::JsonPath;
+use crate::timeseries::{Aggregation, LabelFilter};
 
 #[derive(Debug)]
 pub struct SetCommand {
@@ -21,11 +24,92 @@ pub enum SetOverride {
     OnlyOverwrite,
 }
 
-#[derive(Debug)]
+/// What a command does to the time to live of the key it writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommandExpiration {
-    None,
-    Expiry(Duration),
-    Other,
+    /// Leave it as it is: SET with KEEPTTL, or GETEX without options.
+    Keep,
+    /// Remove it: SET without options, or GETEX with PERSIST.
+    Persist,
+    /// Expire the key this long from now: EX and PX.
+    After(Duration),
+    /// Expire the key at a unix timestamp in milliseconds: EXAT and PXAT.
+    At(u128),
+}
+
+/// The options EXPIRE and the like only set the TTL under. XX can go with GT
+/// or LT, the rest are on their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpireCondition {
+    /// NX: the key has no TTL.
+    pub only_if_none: bool,
+    /// XX: the key has a TTL.
+    pub only_if_some: bool,
+    /// GT: the new TTL is later than the key's. A key without one never expires,
+    /// so nothing is later.
+    pub only_if_later: bool,
+    /// LT: the new TTL is earlier than the key's.
+    pub only_if_earlier: bool,
+}
+
+impl ExpireCondition {
+    fn parse(options: &[String]) -> Result<Self, RedisError> {
+        let mut condition = ExpireCondition::default();
+        for option in options {
+            match option.to_ascii_lowercase().as_str() {
+                "nx" => condition.only_if_none = true,
+                "xx" => condition.only_if_some = true,
+                "gt" => condition.only_if_later = true,
+                "lt" => condition.only_if_earlier = true,
+                _ => {
+                    return Err(RedisError::custom(format!(
+                        "ERR Unsupported option {}",
+                        option
+                    )))
+                }
+            }
+        }
+
+        if condition.only_if_none
+            && (condition.only_if_some || condition.only_if_later || condition.only_if_earlier)
+        {
+            return Err(RedisError::custom(
+                "ERR NX and XX, GT or LT options at the same time are not compatible",
+            ));
+        }
+        if condition.only_if_later && condition.only_if_earlier {
+            return Err(RedisError::custom(
+                "ERR GT and LT options at the same time are not compatible",
+            ));
+        }
+
+        Ok(condition)
+    }
+
+    /// The options as they're given to the command.
+    pub fn options(&self) -> Vec<&'static str> {
+        [
+            (self.only_if_none, "NX"),
+            (self.only_if_some, "XX"),
+            (self.only_if_later, "GT"),
+            (self.only_if_earlier, "LT"),
+        ]
+        .into_iter()
+        .filter_map(|(set, option)| set.then_some(option))
+        .collect()
+    }
+
+    /// Whether a key expiring at `current`, if at all, may be given `deadline`.
+    pub fn allows(&self, current: Option<u128>, deadline: u128) -> bool {
+        match current {
+            None => !self.only_if_some && !self.only_if_later,
+            Some(current) => {
+                !self.only_if_none
+                    && (!self.only_if_later || deadline > current)
+                    && (!self.only_if_earlier || deadline < current)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -33,10 +117,32 @@ pub enum Command {
     Ping(Option<String>),
     Echo(String),
     Set(SetCommand),
+    /// Keys and the strings to set them to.
+    MSet(Vec<(String, String)>),
+    /// Like MSet, but only if none of the keys exist.
+    MSetNx(Vec<(String, String)>),
     Get(String),
     GetDel(String),
     GetEx(String, CommandExpiration),
     Del(Vec<String>),
+    /// Like Del, but the values are freed in the background.
+    Unlink(Vec<String>),
+    /// Whether the old keys are freed on a background task.
+    FlushDb(bool),
+    FlushAll(bool),
+    /// The numbers of the two databases to exchange.
+    SwapDb(usize, usize),
+    /// The key and the number of the database to move it to.
+    Move(String, usize),
+    /// The key, when it's to expire and the options it's only to be set under.
+    Expire(String, CommandExpiration, ExpireCondition),
+    Ttl(String),
+    PTtl(String),
+    Persist(String),
+    MGet(Vec<String>),
+    Exists(Vec<String>),
+    RandomKey,
+    Touch(Vec<String>),
     Info,
     ReplConf(ReplicationCommand),
     Psync(String, PsyncOffset),
@@ -44,14 +150,711 @@ pub enum Command {
     Config(ConfigCommand),
     Keys(String),
     Type(String),
+    Object(ObjectCommand),
     Xadd(XAddCommand),
     Xrange(XRangeCommand),
     Xread(XReadCommand),
+    /// The key and what to add to the end of it.
+    Append(String, String),
+    /// The key, then the first and last offsets to read.
+    GetRange(String, i64, i64),
+    /// The key, the offset to write at and what to write there.
+    SetRange(String, usize, String),
     Incr(String),
     IncrBy(String, i64),
     IncrByFloat(String, f64),
     Decr(String),
     DecrBy(String, i64),
+    /// The key and the values to push onto the head of it, one after the other.
+    LPush(String, Vec<String>),
+    /// The key and the values to push onto the tail of it.
+    RPush(String, Vec<String>),
+    /// The key and how many values to pop, if a count was given.
+    LPop(String, Option<usize>),
+    RPop(String, Option<usize>),
+    /// The keys to pop from, the first holding a list first, and how long to wait
+    /// for one to, forever if `None`.
+    BLPop(Vec<String>, Option<Duration>),
+    BRPop(Vec<String>, Option<Duration>),
+    /// Also RPOPLPUSH, which always moves from the right to the left.
+    LMove(LMoveCommand),
+    /// What to move and how long to wait for a source, forever if `None`.
+    BLMove(LMoveCommand, Option<Duration>),
+    LMPop(LMPopCommand),
+    /// What to pop and how long to wait for a list, forever if `None`.
+    BLMPop(LMPopCommand, Option<Duration>),
+    LLen(String),
+    /// The key, then the first and last indexes to read.
+    LRange(String, i64, i64),
+    /// The key, where to insert relative to the pivot, the pivot and the value.
+    LInsert(String, ListPosition, String, String),
+    /// The key, the index to replace and the value to replace it with.
+    LSet(String, i64, String),
+    /// The key, how many occurrences to remove and from which end, and the value.
+    LRem(String, i64, String),
+    /// The key, then the first and last indexes to keep.
+    LTrim(String, i64, i64),
+    /// The key and the fields to set with their values.
+    HSet(String, Vec<(String, String)>),
+    /// The key and the field to read.
+    HGet(String, String),
+    /// The key and the fields to remove.
+    HDel(String, Vec<String>),
+    HGetAll(String),
+    /// The key and the field to look for.
+    HExists(String, String),
+    HLen(String),
+    HKeys(String),
+    HVals(String),
+    /// The key and the fields to read.
+    HMGet(String, Vec<String>),
+    Save,
+    BgSave,
+    LastSave,
+    BgRewriteAof,
+    Export,
+    /// How many of the busiest keys to list.
+    HotKeys(usize),
+    /// An export, and whether it replaces keys that already exist.
+    Import(serde_json::Value, bool),
+    Shutdown(ShutdownSave),
+    /// The username, if one was given, and the password.
+    Auth(Option<String>, String),
+    Cluster(ClusterCommand),
+    Asking,
+    /// The number of the database to switch the connection to.
+    Select(usize),
+    /// The namespace to switch the connection to.
+    SelectNs(String),
+    Dump(String),
+    Restore(RestoreCommand),
+    RestoreAsking(RestoreCommand),
+    Migrate(MigrateCommand),
+    JsonSet(JsonSetCommand),
+    JsonGet(String, Vec<JsonPath>),
+    JsonDel(String, JsonPath),
+    JsonNumIncrBy(String, JsonPath, serde_json::Number),
+    JsonArrAppend(String, JsonPath, Vec<serde_json::Value>),
+    BfReserve(BfReserveCommand),
+    BfAdd(String, String),
+    BfMAdd(String, Vec<String>),
+    BfExists(String, String),
+    CfReserve(CfReserveCommand),
+    CfAdd(String, String),
+    CfAddNx(String, String),
+    CfExists(String, String),
+    CfDel(String, String),
+    CfCount(String, String),
+    /// The sketch's width and depth, for CMS.INITBYPROB worked out from the
+    /// error and probability it was given.
+    CmsInitByDim(String, u64, u64),
+    CmsInitByProb(String, u64, u64),
+    CmsIncrBy(String, Vec<(String, u64)>),
+    CmsQuery(String, Vec<String>),
+    CmsMerge(CmsMergeCommand),
+    TopkReserve(TopkReserveCommand),
+    TopkAdd(String, Vec<String>),
+    TopkQuery(String, Vec<String>),
+    TopkCount(String, Vec<String>),
+    /// Whether to reply with the counts too.
+    TopkList(String, bool),
+    TsAdd(TsAddCommand),
+    TsGet(String),
+    TsRange(TsRangeCommand),
+    TsMRange(TsMRangeCommand),
+    /// A command an extension added, with its arguments.
+    Extension(&'static str, Vec<String>),
+    /// The HELP subcommand of a container command like CONFIG, with its name.
+    Help(&'static str),
+}
+
+impl Command {
+    /// The name the command is listed under in `COMMANDS`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Ping(..) => "ping",
+            Command::Echo(..) => "echo",
+            Command::Set(..) => "set",
+            Command::Get(..) => "get",
+            Command::GetDel(..) => "getdel",
+            Command::GetEx(..) => "getex",
+            Command::Del(..) => "del",
+            Command::Unlink(..) => "unlink",
+            Command::FlushDb(..) => "flushdb",
+            Command::FlushAll(..) => "flushall",
+            Command::SwapDb(..) => "swapdb",
+            Command::Move(..) => "move",
+            Command::Expire(..) => "expire",
+            Command::Ttl(..) => "ttl",
+            Command::PTtl(..) => "pttl",
+            Command::Persist(..) => "persist",
+            Command::MGet(..) => "mget",
+            Command::Exists(..) => "exists",
+            Command::RandomKey => "randomkey",
+            Command::Touch(..) => "touch",
+            Command::MSet(..) => "mset",
+            Command::MSetNx(..) => "msetnx",
+            Command::Info => "info",
+            Command::ReplConf(..) => "replconf",
+            Command::Psync(..) => "psync",
+            Command::Wait(..) => "wait",
+            Command::Config(..) => "config",
+            Command::Keys(..) => "keys",
+            Command::Type(..) => "type",
+            Command::Object(..) => "object",
+            Command::Xadd(..) => "xadd",
+            Command::Xrange(..) => "xrange",
+            Command::Xread(..) => "xread",
+            Command::Append(..) => "append",
+            Command::GetRange(..) => "getrange",
+            Command::SetRange(..) => "setrange",
+            Command::Incr(..) => "incr",
+            Command::IncrBy(..) => "incrby",
+            Command::IncrByFloat(..) => "incrbyfloat",
+            Command::Decr(..) => "decr",
+            Command::DecrBy(..) => "decrby",
+            Command::LPush(..) => "lpush",
+            Command::RPush(..) => "rpush",
+            Command::LPop(..) => "lpop",
+            Command::RPop(..) => "rpop",
+            Command::BLPop(..) => "blpop",
+            Command::BRPop(..) => "brpop",
+            Command::LMove(..) => "lmove",
+            Command::BLMove(..) => "blmove",
+            Command::LMPop(..) => "lmpop",
+            Command::BLMPop(..) => "blmpop",
+            Command::LLen(..) => "llen",
+            Command::LRange(..) => "lrange",
+            Command::LInsert(..) => "linsert",
+            Command::LSet(..) => "lset",
+            Command::LRem(..) => "lrem",
+            Command::LTrim(..) => "ltrim",
+            Command::HSet(..) => "hset",
+            Command::HGet(..) => "hget",
+            Command::HDel(..) => "hdel",
+            Command::HGetAll(..) => "hgetall",
+            Command::HExists(..) => "hexists",
+            Command::HLen(..) => "hlen",
+            Command::HKeys(..) => "hkeys",
+            Command::HVals(..) => "hvals",
+            Command::HMGet(..) => "hmget",
+            Command::Save => "save",
+            Command::BgSave => "bgsave",
+            Command::LastSave => "lastsave",
+            Command::Export => "export",
+            Command::HotKeys(..) => "hotkeys",
+            Command::Import(..) => "import",
+            Command::BgRewriteAof => "bgrewriteaof",
+            Command::Shutdown(..) => "shutdown",
+            Command::Auth(..) => "auth",
+            Command::Cluster(..) => "cluster",
+            Command::Asking => "asking",
+            Command::Select(..) => "select",
+            Command::SelectNs(..) => "selectns",
+            Command::Dump(..) => "dump",
+            Command::Restore(..) => "restore",
+            Command::RestoreAsking(..) => "restore-asking",
+            Command::Migrate(..) => "migrate",
+            Command::JsonSet(..) => "json.set",
+            Command::JsonGet(..) => "json.get",
+            Command::JsonDel(..) => "json.del",
+            Command::JsonNumIncrBy(..) => "json.numincrby",
+            Command::JsonArrAppend(..) => "json.arrappend",
+            Command::BfReserve(..) => "bf.reserve",
+            Command::BfAdd(..) => "bf.add",
+            Command::BfMAdd(..) => "bf.madd",
+            Command::BfExists(..) => "bf.exists",
+            Command::CfReserve(..) => "cf.reserve",
+            Command::CfAdd(..) => "cf.add",
+            Command::CfAddNx(..) => "cf.addnx",
+            Command::CfExists(..) => "cf.exists",
+            Command::CfDel(..) => "cf.del",
+            Command::CfCount(..) => "cf.count",
+            Command::CmsInitByDim(..) => "cms.initbydim",
+            Command::CmsInitByProb(..) => "cms.initbyprob",
+            Command::CmsIncrBy(..) => "cms.incrby",
+            Command::CmsQuery(..) => "cms.query",
+            Command::CmsMerge(..) => "cms.merge",
+            Command::TopkReserve(..) => "topk.reserve",
+            Command::TopkAdd(..) => "topk.add",
+            Command::TopkQuery(..) => "topk.query",
+            Command::TopkCount(..) => "topk.count",
+            Command::TopkList(..) => "topk.list",
+            Command::TsAdd(..) => "ts.add",
+            Command::TsGet(..) => "ts.get",
+            Command::TsRange(..) => "ts.range",
+            Command::TsMRange(..) => "ts.mrange",
+            Command::Extension(name, _) | Command::Help(name) => name,
+        }
+    }
+
+    pub fn spec(&self) -> &'static CommandSpec {
+        CommandSpec::lookup(self.name()).expect("every command is listed in COMMANDS")
+    }
+
+    /// Whether the command can change the dataset and needs to be persisted to the AOF.
+    pub fn is_write(&self) -> bool {
+        self.spec().flags.contains(CommandFlags::WRITE)
+    }
+
+    /// The keys the command reads or writes, which in cluster mode all have to
+    /// be in the same hash slot.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set(command) => vec![&command.key],
+            Command::Get(key)
+            | Command::GetDel(key)
+            | Command::GetEx(key, _)
+            | Command::Expire(key, ..)
+            | Command::Ttl(key)
+            | Command::PTtl(key)
+            | Command::Persist(key)
+            | Command::Move(key, _)
+            | Command::Type(key)
+            | Command::Object(
+                ObjectCommand::Chunks(key)
+                | ObjectCommand::Encoding(key)
+                | ObjectCommand::RefCount(key)
+                | ObjectCommand::IdleTime(key)
+                | ObjectCommand::Freq(key),
+            )
+            | Command::Append(key, _)
+            | Command::GetRange(key, ..)
+            | Command::SetRange(key, ..)
+            | Command::Incr(key)
+            | Command::IncrBy(key, _)
+            | Command::IncrByFloat(key, _)
+            | Command::Decr(key)
+            | Command::DecrBy(key, _)
+            | Command::LPush(key, _)
+            | Command::RPush(key, _)
+            | Command::LPop(key, _)
+            | Command::RPop(key, _)
+            | Command::LLen(key)
+            | Command::LRange(key, ..)
+            | Command::LInsert(key, ..)
+            | Command::LSet(key, ..)
+            | Command::LRem(key, ..)
+            | Command::LTrim(key, ..)
+            | Command::HSet(key, _)
+            | Command::HGet(key, _)
+            | Command::HDel(key, _)
+            | Command::HGetAll(key)
+            | Command::HExists(key, _)
+            | Command::HLen(key)
+            | Command::HKeys(key)
+            | Command::HVals(key)
+            | Command::HMGet(key, _)
+            | Command::JsonGet(key, _)
+            | Command::JsonDel(key, _)
+            | Command::JsonNumIncrBy(key, ..)
+            | Command::JsonArrAppend(key, ..)
+            | Command::BfAdd(key, _)
+            | Command::BfMAdd(key, _)
+            | Command::BfExists(key, _)
+            | Command::CfAdd(key, _)
+            | Command::CfAddNx(key, _)
+            | Command::CfExists(key, _)
+            | Command::CfDel(key, _)
+            | Command::CfCount(key, _)
+            | Command::CmsInitByDim(key, ..)
+            | Command::CmsInitByProb(key, ..)
+            | Command::CmsIncrBy(key, _)
+            | Command::CmsQuery(key, _)
+            | Command::TopkAdd(key, _)
+            | Command::TopkQuery(key, _)
+            | Command::TopkCount(key, _)
+            | Command::TopkList(key, _)
+            | Command::TsGet(key) => vec![key],
+            Command::Del(keys)
+            | Command::Unlink(keys)
+            | Command::MGet(keys)
+            | Command::Exists(keys)
+            | Command::Touch(keys)
+            | Command::BLPop(keys, _)
+            | Command::BRPop(keys, _) => keys.iter().map(String::as_str).collect(),
+            Command::MSet(pairs) | Command::MSetNx(pairs) => {
+                pairs.iter().map(|(key, _)| key.as_str()).collect()
+            }
+            Command::LMove(command) | Command::BLMove(command, _) => {
+                vec![&command.source, &command.destination]
+            }
+            Command::LMPop(command) | Command::BLMPop(command, _) => {
+                command.keys.iter().map(String::as_str).collect()
+            }
+            Command::Xadd(command) => vec![&command.stream_key],
+            Command::Xrange(command) => vec![&command.key],
+            Command::Dump(key) => vec![key],
+            Command::Restore(command) | Command::RestoreAsking(command) => vec![&command.key],
+            Command::JsonSet(command) => vec![&command.key],
+            Command::BfReserve(command) => vec![&command.key],
+            Command::CfReserve(command) => vec![&command.key],
+            Command::TopkReserve(command) => vec![&command.key],
+            Command::TsAdd(command) => vec![&command.key],
+            Command::TsRange(command) => vec![&command.key],
+            Command::CmsMerge(command) => std::iter::once(&command.destination)
+                .chain(command.sources.iter().map(|(source, _)| source))
+                .map(String::as_str)
+                .collect(),
+            Command::Migrate(command) => command.keys.iter().map(String::as_str).collect(),
+            Command::Import(export, _) => crate::export::keys(export),
+            Command::Extension(name, args) => crate::extension::keys(name, args),
+            Command::Xread(command) => command
+                .streams
+                .iter()
+                .map(|stream| stream.key.as_str())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Whether the command only ever removes keys, which memory limits always let
+    /// through so a full dataset can be cleared out.
+    pub fn only_removes_keys(&self) -> bool {
+        matches!(
+            self,
+            Command::Del(..) | Command::Unlink(..) | Command::GetDel(..)
+        )
+    }
+}
+
+/// What a command may do. Whether it's refused on a read only replica, persisted
+/// and replicated, or allowed to hold up the connection is decided from these
+/// rather than from the command itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandFlags(u8);
+
+impl CommandFlags {
+    pub const NONE: Self = Self(0);
+    /// Changes the dataset.
+    pub const WRITE: Self = Self(1);
+    /// Only reads the dataset.
+    pub const READONLY: Self = Self(1 << 1);
+    /// Manages the server rather than the data in it.
+    pub const ADMIN: Self = Self(1 << 2);
+    /// May wait on other clients or replicas before replying.
+    pub const BLOCKING: Self = Self(1 << 3);
+    /// Can be run before the client has authenticated.
+    pub const NO_AUTH: Self = Self(1 << 4);
+    /// Can be run on a replica whose link with its master is down, even when
+    /// replica-serve-stale-data is off.
+    pub const STALE: Self = Self(1 << 5);
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// How a command is parsed and checked before it's run.
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Like redis, the number of arguments including the command name. A negative
+    /// arity is a minimum, so -2 means at least one argument after the name.
+    pub arity: i32,
+    pub flags: CommandFlags,
+    /// `None` for the commands extensions add, which get their arguments as they are.
+    parse: Option<ParseFn>,
+    /// For a container command, what its HELP subcommand lists.
+    pub subcommands: &'static [SubcommandHelp],
+}
+
+/// A subcommand of a container command, as HELP describes it.
+pub struct SubcommandHelp {
+    /// The subcommand with its arguments, like `GET <parameter>`.
+    pub usage: &'static str,
+    pub description: &'static [&'static str],
+}
+
+impl SubcommandHelp {
+    const fn new(usage: &'static str, description: &'static [&'static str]) -> Self {
+        SubcommandHelp { usage, description }
+    }
+}
+
+type ParseFn = fn(Vec<String>) -> Result<Command, RedisError>;
+
+impl CommandSpec {
+    pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|spec| spec.name.eq_ignore_ascii_case(name))
+            .or_else(|| crate::extension::lookup(name))
+    }
+
+    pub(crate) fn for_extension(name: &'static str, arity: i32, flags: CommandFlags) -> Self {
+        CommandSpec {
+            name,
+            arity,
+            flags,
+            parse: None,
+            subcommands: &[],
+        }
+    }
+
+    /// Whether the command can be called with this many arguments, not counting its name.
+    pub fn accepts(&self, num_args: usize) -> bool {
+        let num_args = num_args as i64 + 1;
+        let arity = self.arity as i64;
+        if arity < 0 {
+            num_args >= -arity
+        } else {
+            num_args == arity
+        }
+    }
+}
+
+const fn spec(name: &'static str, arity: i32, flags: CommandFlags, parse: ParseFn) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        parse: Some(parse),
+        subcommands: &[],
+    }
+}
+
+/// A command whose first argument is a subcommand, and which answers HELP with
+/// the usage of each of them.
+const fn container(
+    name: &'static str,
+    arity: i32,
+    flags: CommandFlags,
+    parse: ParseFn,
+    subcommands: &'static [SubcommandHelp],
+) -> CommandSpec {
+    CommandSpec {
+        subcommands,
+        ..spec(name, arity, flags, parse)
+    }
+}
+
+const WRITE: CommandFlags = CommandFlags::WRITE;
+const READONLY: CommandFlags = CommandFlags::READONLY;
+const ADMIN: CommandFlags = CommandFlags::ADMIN;
+const BLOCKING: CommandFlags = CommandFlags::BLOCKING;
+const NONE: CommandFlags = CommandFlags::NONE;
+const NO_AUTH: CommandFlags = CommandFlags::NO_AUTH;
+const STALE: CommandFlags = CommandFlags::STALE;
+
+const CONFIG_HELP: &[SubcommandHelp] = &[
+    SubcommandHelp::new("GET <parameter>", &["Return the value of <parameter>."]),
+    SubcommandHelp::new(
+        "SET <parameter> <value>",
+        &["Set the configuration <parameter> to <value>."],
+    ),
+];
+
+const OBJECT_HELP: &[SubcommandHelp] = &[
+    SubcommandHelp::new(
+        "CHUNKS <key>",
+        &["Return how many bytes of values each chunk of the list at <key> holds, front to back."],
+    ),
+    SubcommandHelp::new(
+        "ENCODING <key>",
+        &[
+            "Return the kind of internal representation used in order to store the value",
+            "associated with a <key>.",
+        ],
+    ),
+    SubcommandHelp::new(
+        "FREQ <key>",
+        &["Return the access frequency index of the <key>. The returned integer is proportional to the logarithm of the recent access frequency of the key."],
+    ),
+    SubcommandHelp::new(
+        "IDLETIME <key>",
+        &["Return the idle time of the <key>, that is the approximated number of seconds elapsed since the last access to the key."],
+    ),
+    SubcommandHelp::new(
+        "REFCOUNT <key>",
+        &["Return the number of references of the value associated with the specified <key>."],
+    ),
+];
+
+const CLUSTER_HELP: &[SubcommandHelp] = &[
+    SubcommandHelp::new("ADDSLOTS <slot> [<slot> ...]", &["Assign slots to current node."]),
+    SubcommandHelp::new(
+        "ADDSLOTSRANGE <start slot> <end slot> [<start slot> <end slot> ...]",
+        &["Assign slots which are between <start-slot> and <end-slot> to current node."],
+    ),
+    SubcommandHelp::new("COUNTKEYSINSLOT <slot>", &["Return the number of keys in <slot>."]),
+    SubcommandHelp::new(
+        "DELSLOTS <slot> [<slot> ...]",
+        &["Delete slots information from current node."],
+    ),
+    SubcommandHelp::new(
+        "DELSLOTSRANGE <start slot> <end slot> [<start slot> <end slot> ...]",
+        &["Delete slots information which are between <start-slot> and <end-slot> from current node."],
+    ),
+    SubcommandHelp::new(
+        "GETKEYSINSLOT <slot> <count>",
+        &["Return key names stored by current node in a slot."],
+    ),
+    SubcommandHelp::new("INFO", &["Return information about the cluster."]),
+    SubcommandHelp::new("KEYSLOT <key>", &["Return the hash slot for <key>."]),
+    SubcommandHelp::new(
+        "MEET <ip> <port> [<bus-port>]",
+        &["Connect nodes into a working cluster."],
+    ),
+    SubcommandHelp::new("MYID", &["Return the node id."]),
+    SubcommandHelp::new(
+        "NODES",
+        &[
+            "Return cluster configuration seen by node. Output format:",
+            "<id> <ip:port> <flags> <master> <pings> <pongs> <epoch> <link> <slot> ...",
+        ],
+    ),
+    SubcommandHelp::new(
+        "SETSLOT <slot> (IMPORTING <node-id>|MIGRATING <node-id>|STABLE|NODE <node-id>)",
+        &["Set slot state."],
+    ),
+    SubcommandHelp::new(
+        "SHARDS",
+        &["Return information about slot range mappings and the nodes associated with them."],
+    ),
+    SubcommandHelp::new(
+        "SLOTS",
+        &[
+            "Return information about slots range mappings. Each range is made of:",
+            "start, end, master and replicas IP addresses, ports and ids",
+        ],
+    ),
+];
+
+/// Every command the server understands.
+pub const COMMANDS: &[CommandSpec] = &[
+    spec("ping", -1, NONE, parse_ping),
+    spec("echo", 2, NONE, parse_echo),
+    spec("set", -3, WRITE, parse_set),
+    spec("setex", 4, WRITE, parse_setex),
+    spec("psetex", 4, WRITE, parse_psetex),
+    spec("getset", 3, WRITE, parse_getset),
+    spec("get", 2, READONLY, parse_get),
+    spec("getdel", 2, WRITE, parse_get_delete),
+    spec("getex", -2, WRITE, parse_getex),
+    spec("del", -2, WRITE, parse_delete),
+    spec("unlink", -2, WRITE, parse_unlink),
+    spec("flushdb", -1, WRITE, parse_flushdb),
+    spec("flushall", -1, WRITE, parse_flushall),
+    spec("swapdb", 3, WRITE, parse_swapdb),
+    spec("move", 3, WRITE, parse_move),
+    spec("expire", -3, WRITE, parse_expire),
+    spec("pexpire", -3, WRITE, parse_pexpire),
+    spec("expireat", -3, WRITE, parse_expire_at),
+    spec("pexpireat", -3, WRITE, parse_pexpire_at),
+    spec("ttl", 2, READONLY, parse_ttl),
+    spec("pttl", 2, READONLY, parse_pttl),
+    spec("persist", 2, WRITE, parse_persist),
+    spec("info", -1, STALE, parse_info),
+    spec("replconf", -1, ADMIN, parse_replconf),
+    spec("psync", -3, ADMIN.union(BLOCKING), parse_psync),
+    spec("wait", 3, BLOCKING, parse_wait),
+    container("config", -2, ADMIN, parse_config, CONFIG_HELP),
+    spec("keys", 2, READONLY, parse_keys),
+    spec("type", 2, READONLY, parse_type),
+    container("object", -2, READONLY, parse_object, OBJECT_HELP),
+    spec("xadd", -5, WRITE, parse_xadd),
+    spec("xrange", -4, READONLY, parse_xrange),
+    spec("xread", -4, READONLY.union(BLOCKING), parse_xread),
+    spec("append", 3, WRITE, parse_append),
+    spec("getrange", 4, READONLY, parse_get_range),
+    spec("substr", 4, READONLY, parse_get_range),
+    spec("mget", -2, READONLY, parse_mget),
+    spec("exists", -2, READONLY, parse_exists),
+    spec("randomkey", 1, READONLY, parse_random_key),
+    spec("touch", -2, READONLY, parse_touch),
+    spec("mset", -3, WRITE, parse_mset),
+    spec("msetnx", -3, WRITE, parse_msetnx),
+    spec("setrange", 4, WRITE, parse_set_range),
+    spec("incr", 2, WRITE, parse_increment),
+    spec("incrby", 3, WRITE, parse_increment_by),
+    spec("incrbyfloat", 3, WRITE, parse_increment_by_float),
+    spec("decr", 2, WRITE, parse_decrement),
+    spec("decrby", 3, WRITE, parse_decrement_by),
+    spec("lpush", -3, WRITE, parse_lpush),
+    spec("rpush", -3, WRITE, parse_rpush),
+    spec("lpop", -2, WRITE, parse_lpop),
+    spec("rpop", -2, WRITE, parse_rpop),
+    spec("blpop", -3, WRITE.union(BLOCKING), parse_blpop),
+    spec("brpop", -3, WRITE.union(BLOCKING), parse_brpop),
+    spec("lmove", 5, WRITE, parse_lmove),
+    spec("rpoplpush", 3, WRITE, parse_rpoplpush),
+    spec("blmove", 6, WRITE.union(BLOCKING), parse_blmove),
+    spec("lmpop", -4, WRITE, parse_lmpop),
+    spec("blmpop", -5, WRITE.union(BLOCKING), parse_blmpop),
+    spec("llen", 2, READONLY, parse_llen),
+    spec("lrange", 4, READONLY, parse_lrange),
+    spec("linsert", 5, WRITE, parse_linsert),
+    spec("lset", 4, WRITE, parse_lset),
+    spec("lrem", 4, WRITE, parse_lrem),
+    spec("ltrim", 4, WRITE, parse_ltrim),
+    spec("hset", -4, WRITE, parse_hset),
+    spec("hget", 3, READONLY, parse_hget),
+    spec("hdel", -3, WRITE, parse_hdel),
+    spec("hgetall", 2, READONLY, parse_hgetall),
+    spec("hexists", 3, READONLY, parse_hexists),
+    spec("hlen", 2, READONLY, parse_hlen),
+    spec("hkeys", 2, READONLY, parse_hkeys),
+    spec("hvals", 2, READONLY, parse_hvals),
+    spec("hmget", -3, READONLY, parse_hmget),
+    spec("save", 1, ADMIN, parse_save),
+    spec("bgsave", -1, ADMIN, parse_bg_save),
+    spec("lastsave", 1, ADMIN, parse_last_save),
+    spec("bgrewriteaof", 1, ADMIN, parse_bg_rewrite_aof),
+    spec("export", 1, READONLY.union(ADMIN), parse_export),
+    spec("hotkeys", -1, ADMIN, parse_hot_keys),
+    spec("import", -2, WRITE.union(ADMIN), parse_import),
+    spec("shutdown", -1, ADMIN, parse_shutdown),
+    spec("auth", -2, NO_AUTH.union(STALE), parse_auth),
+    container("cluster", -2, ADMIN, parse_cluster, CLUSTER_HELP),
+    spec("asking", 1, NONE, parse_asking),
+    spec("select", 2, STALE, parse_select),
+    spec("selectns", 2, STALE, parse_select_namespace),
+    spec("dump", 2, READONLY, parse_dump),
+    spec("restore", -4, WRITE, parse_restore),
+    spec("restore-asking", -4, WRITE, parse_restore_asking),
+    spec("migrate", -6, BLOCKING, parse_migrate),
+    spec("json.set", -4, WRITE, parse_json_set),
+    spec("json.get", -2, READONLY, parse_json_get),
+    spec("json.del", -2, WRITE, parse_json_del),
+    spec("json.numincrby", 4, WRITE, parse_json_num_incr_by),
+    spec("json.arrappend", -4, WRITE, parse_json_arr_append),
+    spec("bf.reserve", -4, WRITE, parse_bf_reserve),
+    spec("bf.add", 3, WRITE, parse_bf_add),
+    spec("bf.madd", -3, WRITE, parse_bf_madd),
+    spec("bf.exists", 3, READONLY, parse_bf_exists),
+    spec("cf.reserve", -3, WRITE, parse_cf_reserve),
+    spec("cf.add", 3, WRITE, parse_cf_add),
+    spec("cf.addnx", 3, WRITE, parse_cf_add_nx),
+    spec("cf.exists", 3, READONLY, parse_cf_exists),
+    spec("cf.del", 3, WRITE, parse_cf_del),
+    spec("cf.count", 3, READONLY, parse_cf_count),
+    spec("cms.initbydim", 4, WRITE, parse_cms_init_by_dim),
+    spec("cms.initbyprob", 4, WRITE, parse_cms_init_by_prob),
+    spec("cms.incrby", -4, WRITE, parse_cms_incr_by),
+    spec("cms.query", -3, READONLY, parse_cms_query),
+    spec("cms.merge", -4, WRITE, parse_cms_merge),
+    spec("topk.reserve", -3, WRITE, parse_topk_reserve),
+    spec("topk.add", -3, WRITE, parse_topk_add),
+    spec("topk.query", -3, READONLY, parse_topk_query),
+    spec("topk.count", -3, READONLY, parse_topk_count),
+    spec("topk.list", -2, READONLY, parse_topk_list),
+    spec("ts.add", -4, WRITE, parse_ts_add),
+    spec("ts.get", 2, READONLY, parse_ts_get),
+    spec("ts.range", -4, READONLY, parse_ts_range),
+    spec("ts.mrange", -5, READONLY, parse_ts_mrange),
+];
+
+/// Whether SHUTDOWN should save the dataset before exiting. By default it only
+/// saves when save points are configured.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShutdownSave {
+    Default,
+    Save,
+    NoSave,
 }
 
 #[derive(Debug)]
@@ -92,6 +895,39 @@ pub enum XRangeNumber {
     Specified(u128, usize),
 }
 
+/// Which end of a list a command works on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
+pub struct LMoveCommand {
+    pub source: String,
+    pub destination: String,
+    /// The end of the source to pop from.
+    pub from: ListEnd,
+    /// The end of the destination to push onto.
+    pub to: ListEnd,
+}
+
+#[derive(Debug)]
+pub struct LMPopCommand {
+    /// The keys to pop from, the first holding a list first.
+    pub keys: Vec<String>,
+    pub end: ListEnd,
+    /// How many values to pop at most, 1 unless COUNT was given.
+    pub count: usize,
+}
+
+/// Where LINSERT puts the value relative to the pivot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListPosition {
+    Before,
+    After,
+}
+
 #[derive(Debug)]
 pub enum XAddNumber {
     Autogenerate,
@@ -106,15 +942,258 @@ pub struct XAddCommand {
     pub data: Vec<RedisStreamItem>,
 }
 
+#[derive(Debug)]
+pub enum ObjectCommand {
+    /// How many bytes each chunk of a list holds.
+    Chunks(String),
+    Encoding(String),
+    RefCount(String),
+    IdleTime(String),
+    Freq(String),
+}
+
+#[derive(Debug)]
+pub enum ClusterCommand {
+    Info,
+    MyId,
+    Slots,
+    Shards,
+    AddSlots(Vec<usize>),
+    DelSlots(Vec<usize>),
+    KeySlot(String),
+    /// The host and port of the node to meet, and the port of its cluster bus if
+    /// it isn't the port plus 10000.
+    Meet(String, u16, Option<u16>),
+    Nodes,
+    SetSlot(usize, SetSlotAction),
+    CountKeysInSlot(usize),
+    GetKeysInSlot(usize, usize),
+}
+
+/// The steps of moving a slot between nodes with CLUSTER SETSLOT, each but
+/// STABLE naming the other node.
+#[derive(Debug)]
+pub enum SetSlotAction {
+    Importing(String),
+    Migrating(String),
+    Stable,
+    Node(String),
+}
+
+/// JSON.SET, which like SET can be told to only create or only replace values.
+#[derive(Debug)]
+pub struct JsonSetCommand {
+    pub key: String,
+    pub path: JsonPath,
+    pub value: serde_json::Value,
+    pub overwrite: SetOverride,
+}
+
+#[derive(Debug)]
+pub struct BfReserveCommand {
+    pub key: String,
+    pub error_rate: f64,
+    pub capacity: u64,
+    /// `None` for a filter created with NONSCALING.
+    pub expansion: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct CfReserveCommand {
+    pub key: String,
+    pub capacity: u64,
+    pub bucket_size: u64,
+    pub max_iterations: u64,
+    /// 0 for a filter that can't grow.
+    pub expansion: u64,
+}
+
+#[derive(Debug)]
+pub struct CmsMergeCommand {
+    pub destination: String,
+    /// Every source with its weight, 1 unless WEIGHTS was given.
+    pub sources: Vec<(String, u64)>,
+}
+
+#[derive(Debug)]
+pub struct TopkReserveCommand {
+    pub key: String,
+    pub k: u64,
+    pub width: u64,
+    pub depth: u64,
+    pub decay: f64,
+}
+
+#[derive(Debug)]
+pub struct TsAddCommand {
+    pub key: String,
+    /// `None` for `*`, the time the sample is added.
+    pub timestamp: Option<u64>,
+    pub value: f64,
+    /// Only used when the series is created.
+    pub retention: u64,
+    pub labels: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct TsRangeCommand {
+    pub key: String,
+    pub range: RangeInclusive<u64>,
+    /// The aggregation and the bucket duration in milliseconds.
+    pub aggregation: Option<(Aggregation, u64)>,
+}
+
+#[derive(Debug)]
+pub struct TsMRangeCommand {
+    pub range: RangeInclusive<u64>,
+    pub aggregation: Option<(Aggregation, u64)>,
+    pub with_labels: bool,
+    pub filters: Vec<LabelFilter>,
+}
+
+/// RESTORE, and RESTORE-ASKING, which MIGRATE sends to create the keys on the
+/// other node.
+#[derive(Debug)]
+pub struct RestoreCommand {
+    pub key: String,
+    /// In milliseconds, 0 meaning the key doesn't expire.
+    pub ttl: u64,
+    /// What DUMP serialized, hex encoded like DUMP replies with it.
+    pub payload: String,
+    pub replace: bool,
+    /// Whether `ttl` is a unix timestamp rather than a duration.
+    pub absttl: bool,
+}
+
+#[derive(Debug)]
+pub struct MigrateCommand {
+    pub host: String,
+    pub port: u16,
+    pub keys: Vec<String>,
+    pub db: usize,
+    /// In milliseconds.
+    pub timeout: u64,
+    /// Leave the keys on this node as well.
+    pub copy: bool,
+    /// Overwrite keys that already exist on the other node.
+    pub replace: bool,
+    /// The username, if one was given, and the password for the other node.
+    pub auth: Option<(Option<String>, String)>,
+}
+
 #[derive(Debug)]
 pub enum ConfigCommand {
     Get(ConfigKey),
+    Set(ConfigKey, String),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ConfigKey {
     Dir,
     Dbfilename,
+    Logfile,
+    Save,
+    Maxclients,
+    Maxmemory,
+    Requirepass,
+    IpAllowlist,
+    IpDenylist,
+    ClientMaxCommandsPerSecond,
+    ClientMaxBytesPerSecond,
+    ClientRateLimitPolicy,
+    NamespaceMaxKeys,
+    NamespaceMaxMemory,
+    Rdbcompression,
+    Appendonly,
+    Appendfilename,
+    Appenddirname,
+    AutoAofRewritePercentage,
+    AutoAofRewriteMinSize,
+    ReplicaReadOnly,
+    ReplicaServeStaleData,
+    ReplPingReplicaPeriod,
+    ReplTimeout,
+    ProtoMaxBulkLen,
+    ProtoMaxMultibulkLen,
+    ProtoMaxInlineLen,
+    ClusterEnabled,
+    ClusterPort,
+    Databases,
+}
+
+impl ConfigKey {
+    pub fn parse(key: &str) -> Option<Self> {
+        match key.to_ascii_lowercase().as_str() {
+            "dir" => Some(Self::Dir),
+            "dbfilename" => Some(Self::Dbfilename),
+            "logfile" => Some(Self::Logfile),
+            "save" => Some(Self::Save),
+            "maxclients" => Some(Self::Maxclients),
+            "maxmemory" => Some(Self::Maxmemory),
+            "requirepass" => Some(Self::Requirepass),
+            "ip-allowlist" => Some(Self::IpAllowlist),
+            "ip-denylist" => Some(Self::IpDenylist),
+            "client-max-commands-per-second" => Some(Self::ClientMaxCommandsPerSecond),
+            "client-max-bytes-per-second" => Some(Self::ClientMaxBytesPerSecond),
+            "client-rate-limit-policy" => Some(Self::ClientRateLimitPolicy),
+            "namespace-max-keys" => Some(Self::NamespaceMaxKeys),
+            "namespace-max-memory" => Some(Self::NamespaceMaxMemory),
+            "rdbcompression" => Some(Self::Rdbcompression),
+            "appendonly" => Some(Self::Appendonly),
+            "appendfilename" => Some(Self::Appendfilename),
+            "appenddirname" => Some(Self::Appenddirname),
+            "auto-aof-rewrite-percentage" => Some(Self::AutoAofRewritePercentage),
+            "auto-aof-rewrite-min-size" => Some(Self::AutoAofRewriteMinSize),
+            "replica-read-only" | "slave-read-only" => Some(Self::ReplicaReadOnly),
+            "replica-serve-stale-data" | "slave-serve-stale-data" => {
+                Some(Self::ReplicaServeStaleData)
+            }
+            "repl-ping-replica-period" | "repl-ping-slave-period" => {
+                Some(Self::ReplPingReplicaPeriod)
+            }
+            "repl-timeout" => Some(Self::ReplTimeout),
+            "proto-max-bulk-len" => Some(Self::ProtoMaxBulkLen),
+            "proto-max-multibulk-len" => Some(Self::ProtoMaxMultibulkLen),
+            "proto-max-inline-len" => Some(Self::ProtoMaxInlineLen),
+            "cluster-enabled" => Some(Self::ClusterEnabled),
+            "cluster-port" => Some(Self::ClusterPort),
+            "databases" => Some(Self::Databases),
+            _ => None,
+        }
+    }
+
+    /// Whether a changed value in the config file is picked up on SIGHUP.
+    /// The data directory and files are only read on startup, as are cluster mode
+    /// and the number of databases.
+    pub fn is_reloadable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Dir
+                | Self::Dbfilename
+                | Self::Appendonly
+                | Self::Appendfilename
+                | Self::Appenddirname
+                | Self::ClusterEnabled
+                | Self::ClusterPort
+                | Self::Databases
+        )
+    }
+
+    /// Whether CONFIG SET can change the value. The append only file is
+    /// opened on startup so it can't be switched on, off or moved afterwards,
+    /// and a node can't join or leave cluster mode while it's running. Connections
+    /// may have selected any of the databases, so their number is fixed as well.
+    pub fn is_mutable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Appendonly
+                | Self::Appendfilename
+                | Self::Appenddirname
+                | Self::ClusterEnabled
+                | Self::ClusterPort
+                | Self::Databases
+        )
+    }
 }
 
 impl Display for ConfigKey {
@@ -122,6 +1201,34 @@ impl Display for ConfigKey {
         match self {
             Self::Dir => write!(f, "dir"),
             Self::Dbfilename => write!(f, "dbfilename"),
+            Self::Logfile => write!(f, "logfile"),
+            Self::Save => write!(f, "save"),
+            Self::Maxclients => write!(f, "maxclients"),
+            Self::Maxmemory => write!(f, "maxmemory"),
+            Self::Requirepass => write!(f, "requirepass"),
+            Self::IpAllowlist => write!(f, "ip-allowlist"),
+            Self::IpDenylist => write!(f, "ip-denylist"),
+            Self::ClientMaxCommandsPerSecond => write!(f, "client-max-commands-per-second"),
+            Self::ClientMaxBytesPerSecond => write!(f, "client-max-bytes-per-second"),
+            Self::ClientRateLimitPolicy => write!(f, "client-rate-limit-policy"),
+            Self::NamespaceMaxKeys => write!(f, "namespace-max-keys"),
+            Self::NamespaceMaxMemory => write!(f, "namespace-max-memory"),
+            Self::Rdbcompression => write!(f, "rdbcompression"),
+            Self::Appendonly => write!(f, "appendonly"),
+            Self::Appendfilename => write!(f, "appendfilename"),
+            Self::Appenddirname => write!(f, "appenddirname"),
+            Self::AutoAofRewritePercentage => write!(f, "auto-aof-rewrite-percentage"),
+            Self::AutoAofRewriteMinSize => write!(f, "auto-aof-rewrite-min-size"),
+            Self::ReplicaReadOnly => write!(f, "replica-read-only"),
+            Self::ReplicaServeStaleData => write!(f, "replica-serve-stale-data"),
+            Self::ReplPingReplicaPeriod => write!(f, "repl-ping-replica-period"),
+            Self::ReplTimeout => write!(f, "repl-timeout"),
+            Self::ProtoMaxBulkLen => write!(f, "proto-max-bulk-len"),
+            Self::ProtoMaxMultibulkLen => write!(f, "proto-max-multibulk-len"),
+            Self::ProtoMaxInlineLen => write!(f, "proto-max-inline-len"),
+            Self::ClusterEnabled => write!(f, "cluster-enabled"),
+            Self::ClusterPort => write!(f, "cluster-port"),
+            Self::Databases => write!(f, "databases"),
         }
     }
 }
@@ -130,49 +1237,39 @@ impl Display for ConfigKey {
 pub enum ReplicationCommand {
     ListeningPort(u16),
     Capabilities,
-    Ack,
+    GetAck,
+    Ack(u64),
 }
 
 #[derive(Debug)]
 pub enum PsyncOffset {
     None,
-    Offset(u16),
+    Offset(u64),
 }
 
 impl Command {
-    pub fn new(route: &str, body: Vec<String>) -> Result<Self, anyhow::Error> {
-        match route.to_ascii_lowercase().as_str() {
-            "ping" => parse_ping(body),
-            "echo" => parse_echo(body),
-            "set" => parse_set(body),
-            "get" => parse_get(body),
-            "getdel" => parse_get_delete(body),
-            "getex" => parse_getex(body),
-            "del" => parse_delete(body),
-            "info" => parse_info(body),
-            "replconf" => parse_replconf(body),
-            "psync" => parse_psync(body),
-            "wait" => parse_wait(body),
-            "config" => parse_config(body),
-            "keys" => parse_keys(body),
-            "type" => parse_type(body),
-            "xadd" => parse_xadd(body),
-            "xrange" => parse_xrange(body),
-            "xread" => parse_xread(body),
-            "incr" => parse_increment(body),
-            "incrby" => parse_increment_by(body),
-            "incrbyfloat" => parse_increment_by_float(body),
-            "decr" => parse_decrement(body),
-            "decrby" => parse_decrement_by(body),
-            _ => anyhow::bail!("unknown command: {}", route),
-        }
-    }
-}
-
-pub fn parse_request(raw_request: Vec<String>) -> Result<Command, anyhow::Error> {
+    pub fn new(route: &str, body: Vec<String>) -> Result<Self, RedisError> {
+        let spec = CommandSpec::lookup(route).ok_or_else(|| unknown_command(route, &body))?;
+        if !spec.accepts(body.len()) {
+            return Err(wrong_number_of_arguments(spec.name));
+        }
+        if let [subcommand] = body.as_slice() {
+            if !spec.subcommands.is_empty() && subcommand.eq_ignore_ascii_case("help") {
+                return Ok(Command::Help(spec.name));
+            }
+        }
+
+        match spec.parse {
+            Some(parse) => parse(body),
+            None => Ok(Command::Extension(spec.name, body)),
+        }
+    }
+}
+
+pub fn parse_request(raw_request: Vec<String>) -> Result<Command, RedisError> {
     let command_type = raw_request
         .get(2)
-        .ok_or_else(|| anyhow::anyhow!("missing route"))?;
+        .ok_or_else(|| RedisError::custom("missing route"))?;
 
     // Skip the route - then skip the size for every part
     let body = raw_request.iter().step_by(2).skip(2).cloned().collect();
@@ -180,43 +1277,35 @@ pub fn parse_request(raw_request: Vec<String>) -> Result<Command, anyhow::Error>
     Command::new(command_type, body)
 }
 
-fn parse_ping(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_ping(body: Vec<String>) -> Result<Command, RedisError> {
     let ping_message = match body {
         msg if msg.is_empty() => None,
         msg if msg.len() == 1 => Some(msg[0].clone()),
-        _ => anyhow::bail!("usage ping [message]"),
+        _ => return Err(RedisError::Syntax),
     };
 
     Ok(Command::Ping(ping_message))
 }
 
-fn parse_echo(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_echo(body: Vec<String>) -> Result<Command, RedisError> {
     let echo_message = match body {
-        msg if msg.is_empty() => anyhow::bail!("usage echo message"),
+        msg if msg.is_empty() => return Err(RedisError::Syntax),
         msg if msg.len() == 1 => msg[0].clone(),
-        _ => anyhow::bail!("usage echo message"),
+        _ => return Err(RedisError::Syntax),
     };
 
     Ok(Command::Echo(echo_message))
 }
 
-fn parse_set(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_set(body: Vec<String>) -> Result<Command, RedisError> {
     let mut body_iter = body.iter();
 
-    let set_explanation = "SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
-  EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]";
-    let key = body_iter
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Missing key: {}", set_explanation))?
-        .clone();
-    let value = body_iter
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Missing value: {}", set_explanation))?
-        .clone();
+    let key = body_iter.next().ok_or(RedisError::Syntax)?.clone();
+    let value = body_iter.next().ok_or(RedisError::Syntax)?.clone();
 
     let mut overwrite = SetOverride::Normal;
     let mut get_old_value = false;
-    let mut expires = CommandExpiration::None;
+    let mut expires = CommandExpiration::Persist;
 
     while let Some(item) = body_iter.next() {
         match item.to_ascii_lowercase().as_str() {
@@ -224,38 +1313,25 @@ fn parse_set(body: Vec<String>) -> Result<Command, anyhow::Error> {
             "nx" => overwrite = SetOverride::NeverOverwrite,
             "get" => get_old_value = true,
             "ex" => {
-                let amount = body_iter
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Missing EX seconds: {}", set_explanation))?;
+                let amount = body_iter.next().ok_or(RedisError::Syntax)?;
                 let duration = parse_expiry(amount, 1000)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::After(duration);
             }
             "px" => {
-                let amount = body_iter.next().ok_or_else(|| {
-                    anyhow::anyhow!("Missing PX milliseconds: {}", set_explanation)
-                })?;
+                let amount = body_iter.next().ok_or(RedisError::Syntax)?;
                 let duration = parse_expiry(amount, 1)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::After(duration);
             }
             "exat" => {
-                let time = body_iter.next().ok_or_else(|| {
-                    anyhow::anyhow!("Missing UNIX timestamp (in seconds): {}", set_explanation)
-                })?;
-                let duration = parse_expiry_at(time, 1000)?;
-                expires = CommandExpiration::Expiry(duration);
+                let time = body_iter.next().ok_or(RedisError::Syntax)?;
+                expires = CommandExpiration::At(parse_expiry_at(time, 1000)?);
             }
             "pxat" => {
-                let time = body_iter.next().ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "Missing UNIX timestamp (in milliseconds): {}",
-                        set_explanation
-                    )
-                })?;
-                let duration = parse_expiry_at(time, 1)?;
-                expires = CommandExpiration::Expiry(duration);
+                let time = body_iter.next().ok_or(RedisError::Syntax)?;
+                expires = CommandExpiration::At(parse_expiry_at(time, 1)?);
             }
-            "keepttl" => expires = CommandExpiration::Other,
-            _ => anyhow::bail!("unknown option: {}", item),
+            "keepttl" => expires = CommandExpiration::Keep,
+            _ => return Err(RedisError::Syntax),
         }
     }
 
@@ -270,209 +1346,1052 @@ fn parse_set(body: Vec<String>) -> Result<Command, anyhow::Error> {
     Ok(Command::Set(command))
 }
 
-fn parse_expiry(amount: &str, multiplier: u64) -> Result<Duration, anyhow::Error> {
-    let amount = str::parse::<u64>(amount)
-        .context("Parsing amount into number")
-        .map_err(|_| not_an_integer())?;
+/// GETSET is what SET with GET was before there was a GET option.
+fn parse_getset(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let command = SetCommand {
+        key: key.clone(),
+        value: value.clone(),
+        get_old_value: true,
+        overwrite: SetOverride::Normal,
+        expires: CommandExpiration::Persist,
+    };
+
+    Ok(Command::Set(command))
+}
+
+fn parse_setex(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_set_with_expiry(body, 1000, "setex")
+}
+
+fn parse_psetex(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_set_with_expiry(body, 1, "psetex")
+}
+
+/// SETEX and PSETEX are SET with EX or PX, except the expiry comes before the
+/// value and has to be there.
+fn parse_set_with_expiry(
+    body: Vec<String>,
+    multiplier: u64,
+    name: &str,
+) -> Result<Command, RedisError> {
+    let [key, amount, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let amount = str::parse::<i64>(amount).map_err(|_| RedisError::NotAnInteger)?;
+    let millis = u64::try_from(amount)
+        .ok()
+        .filter(|amount| *amount > 0)
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| {
+            RedisError::custom(format!("ERR invalid expire time in '{}' command", name))
+        })?;
+
+    let command = SetCommand {
+        key: key.clone(),
+        value: value.clone(),
+        get_old_value: false,
+        overwrite: SetOverride::Normal,
+        expires: CommandExpiration::After(Duration::from_millis(millis)),
+    };
+
+    Ok(Command::Set(command))
+}
+
+fn parse_expiry(amount: &str, multiplier: u64) -> Result<Duration, RedisError> {
+    let amount = str::parse::<u64>(amount).map_err(|_| RedisError::NotAnInteger)?;
     Ok(Duration::from_millis(amount * multiplier))
 }
 
-fn parse_expiry_at(time: &str, multiplier: u64) -> Result<Duration, anyhow::Error> {
+/// The deadline as a unix timestamp in milliseconds. One that has already
+/// passed is fine, the key just expires right away, which also keeps the
+/// command valid when it's replayed from the AOF later on.
+fn parse_expiry_at(time: &str, multiplier: u64) -> Result<u128, RedisError> {
     let time = str::parse::<u64>(time)
-        .map_err(|_| not_an_integer())?
+        .map_err(|_| RedisError::NotAnInteger)?
         .checked_mul(multiplier)
-        .ok_or_else(|| anyhow::anyhow!("ERR time is too large"))?;
-    let current_timestamp = current_unix_timestamp()? as u64;
+        .ok_or_else(|| RedisError::custom("ERR time is too large"))?;
 
-    let duration = time
-        .checked_sub(current_timestamp)
-        .ok_or_else(|| anyhow::anyhow!("ERR time is in the past"))?;
-
-    let duration = Duration::from_millis(duration);
-
-    Ok(duration)
+    Ok(time as u128)
 }
 
-fn parse_get(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_get(body: Vec<String>) -> Result<Command, RedisError> {
     let key = body
         .first()
-        .ok_or_else(|| anyhow::anyhow!("ERR missing key for GET command"))?
+        .ok_or_else(|| RedisError::custom("ERR missing key for GET command"))?
         .clone();
 
     Ok(Command::Get(key))
 }
 
-fn parse_info(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_info(body: Vec<String>) -> Result<Command, RedisError> {
     if body.len() != 1 {
-        anyhow::bail!("usage info replication")
+        return Err(RedisError::Syntax);
     }
 
     Ok(Command::Info)
 }
 
-fn parse_replconf(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_replconf(body: Vec<String>) -> Result<Command, RedisError> {
     if body.len() != 2 {
-        anyhow::bail!("usage REPLCONF [listening-port <port>] | [capa psync2]")
+        return Err(RedisError::Syntax);
     }
 
     let subcommand = body.first().unwrap();
     match subcommand.to_ascii_lowercase().as_str() {
         "listening-port" => {
-            let port: u16 = str::parse(body.get(1).unwrap()).context("Parsing port into number")?;
+            let port: u16 =
+                str::parse(body.get(1).unwrap()).map_err(|_| RedisError::NotAnInteger)?;
             Ok(Command::ReplConf(ReplicationCommand::ListeningPort(port)))
         }
         "capa" => {
             if body.get(1).unwrap() != "psync2" {
-                anyhow::bail!("capa command must be followed by psync2");
+                return Err(RedisError::custom(
+                    "capa command must be followed by psync2",
+                ));
             }
             Ok(Command::ReplConf(ReplicationCommand::Capabilities))
         }
         "getack" => {
             if body.get(1).unwrap() != "*" {
-                anyhow::bail!("gatack command must be followed by wildcard *");
+                return Err(RedisError::custom(
+                    "gatack command must be followed by wildcard *",
+                ));
             }
-            Ok(Command::ReplConf(ReplicationCommand::Ack))
+            Ok(Command::ReplConf(ReplicationCommand::GetAck))
         }
-        _ => anyhow::bail!("unknown subcommand: {}", subcommand),
+        "ack" => {
+            let offset = str::parse(body.get(1).unwrap()).map_err(|_| RedisError::NotAnInteger)?;
+            Ok(Command::ReplConf(ReplicationCommand::Ack(offset)))
+        }
+        _ => Err(RedisError::custom(format!(
+            "unknown subcommand: {}",
+            subcommand
+        ))),
     }
 }
 
-fn parse_psync(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_psync(body: Vec<String>) -> Result<Command, RedisError> {
     if body.len() != 2 {
-        anyhow::bail!("usage resync <replication-id> <offset>")
+        return Err(RedisError::Syntax);
     }
 
     let replication_id = body.first().unwrap().to_string();
     let offset = match body.get(1).unwrap().as_str() {
         "-1" => PsyncOffset::None,
         offset => {
-            let offset = str::parse(offset).context("Parsing offset into number")?;
+            let offset = str::parse(offset).map_err(|_| RedisError::NotAnInteger)?;
             PsyncOffset::Offset(offset)
         }
     };
 
     Ok(Command::Psync(replication_id, offset))
 }
-fn parse_wait(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let num_replicas = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage wait <numreplicas> <timeout>"))?;
+fn parse_wait(body: Vec<String>) -> Result<Command, RedisError> {
+    let num_replicas = body.first().ok_or(RedisError::Syntax)?;
 
-    let timeout = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("usage wait <numreplicas> <timeout>"))?;
+    let timeout = body.get(1).ok_or(RedisError::Syntax)?;
 
-    let num_replicas: usize = str::parse(num_replicas)?;
-    let timeout: u64 = str::parse(timeout)?;
+    let num_replicas: usize = str::parse(num_replicas).map_err(|_| RedisError::NotAnInteger)?;
+    let timeout: u64 = str::parse(timeout).map_err(|_| RedisError::NotAnInteger)?;
 
     let command = Command::Wait(num_replicas, timeout);
     Ok(command)
 }
 
-fn parse_config(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let get_cmd = body
+fn parse_config(body: Vec<String>) -> Result<Command, RedisError> {
+    let subcommand = body
         .first()
-        .ok_or_else(|| anyhow::anyhow!("ERR config must specify a command"))?;
+        .ok_or_else(|| RedisError::custom("ERR config must specify a command"))?;
 
-    if get_cmd.to_ascii_lowercase() != "get" {
-        anyhow::bail!("ERR only get commands supported for config for now");
-    }
+    let key = || {
+        let option = body
+            .get(1)
+            .ok_or_else(|| RedisError::custom("command must specify key"))?;
 
-    let get_option = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("command must specify key"))?;
+        ConfigKey::parse(option).ok_or_else(|| {
+            RedisError::custom(
+                "supported keys are dir, dbfilename, logfile, save, maxclients and maxmemory",
+            )
+        })
+    };
 
-    let key = match get_option.to_ascii_lowercase().as_str() {
-        "dir" => ConfigKey::Dir,
-        "dbfilename" => ConfigKey::Dbfilename,
-        _ => anyhow::bail!("supported keys are dir and dbfilename"),
+    let config_command = match subcommand.to_ascii_lowercase().as_str() {
+        "get" => ConfigCommand::Get(key()?),
+        "set" => {
+            let key = key()?;
+            let value = body.get(2).ok_or(RedisError::Syntax)?;
+            ConfigCommand::Set(key, value.to_string())
+        }
+        _ => return Err(unknown_subcommand("config", subcommand)),
     };
 
-    let config_command = ConfigCommand::Get(key);
     let command = Command::Config(config_command);
     Ok(command)
 }
 
-fn parse_keys(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    // TODO: Add handling for searching
-    // TODO: Add better error handling
-    let key_group = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("keys must specify a command"))?;
+fn parse_cluster(body: Vec<String>) -> Result<Command, RedisError> {
+    let subcommand = body[0].to_ascii_lowercase();
+    let args = &body[1..];
+
+    let cluster_command = match (subcommand.as_str(), args.len()) {
+        ("info", 0) => ClusterCommand::Info,
+        ("myid", 0) => ClusterCommand::MyId,
+        ("slots", 0) => ClusterCommand::Slots,
+        ("shards", 0) => ClusterCommand::Shards,
+        ("keyslot", 1) => ClusterCommand::KeySlot(args[0].clone()),
+        ("nodes", 0) => ClusterCommand::Nodes,
+        ("meet", 2 | 3) => {
+            let port = args[1].parse().map_err(|_| {
+                RedisError::Custom(format!("ERR Invalid base port specified: {}", args[1]))
+            })?;
+            let bus_port = args
+                .get(2)
+                .map(|bus_port| bus_port.parse())
+                .transpose()
+                .map_err(|_| {
+                    RedisError::Custom(format!("ERR Invalid bus port specified: {}", args[2]))
+                })?;
+            ClusterCommand::Meet(args[0].clone(), port, bus_port)
+        }
+        ("setslot", 2 | 3) => {
+            let slot = parse_slot(&args[0])?;
+            let action = match (args[1].to_ascii_lowercase().as_str(), args.get(2)) {
+                ("importing", Some(node)) => SetSlotAction::Importing(node.clone()),
+                ("migrating", Some(node)) => SetSlotAction::Migrating(node.clone()),
+                ("node", Some(node)) => SetSlotAction::Node(node.clone()),
+                ("stable", None) => SetSlotAction::Stable,
+                _ => return Err(RedisError::Syntax),
+            };
+            ClusterCommand::SetSlot(slot, action)
+        }
+        ("countkeysinslot", 1) => ClusterCommand::CountKeysInSlot(parse_slot(&args[0])?),
+        ("getkeysinslot", 2) => {
+            let count = args[1]
+                .parse()
+                .map_err(|_| RedisError::custom("ERR Invalid number of keys"))?;
+            ClusterCommand::GetKeysInSlot(parse_slot(&args[0])?, count)
+        }
+        ("addslots", 1..) => ClusterCommand::AddSlots(parse_slots(args)?),
+        ("delslots", 1..) => ClusterCommand::DelSlots(parse_slots(args)?),
+        ("addslotsrange", len) if len > 0 && len % 2 == 0 => {
+            ClusterCommand::AddSlots(parse_slot_ranges(args)?)
+        }
+        ("delslotsrange", len) if len > 0 && len % 2 == 0 => {
+            ClusterCommand::DelSlots(parse_slot_ranges(args)?)
+        }
+        (
+            "info" | "myid" | "slots" | "shards" | "keyslot" | "addslots" | "delslots"
+            | "addslotsrange" | "delslotsrange" | "nodes" | "meet" | "setslot" | "countkeysinslot"
+            | "getkeysinslot",
+            _,
+        ) => {
+            return Err(wrong_number_of_arguments(&format!(
+                "cluster|{}",
+                subcommand
+            )))
+        }
+        _ => return Err(unknown_subcommand("cluster", &body[0])),
+    };
 
-    if key_group != "*" {
-        anyhow::bail!(
-            "ERR Only * command supported for keys, received {}",
-            key_group
-        )
-    }
+    Ok(Command::Cluster(cluster_command))
+}
 
-    let command = Command::Keys(key_group.to_string());
-    Ok(command)
+fn parse_asking(_body: Vec<String>) -> Result<Command, RedisError> {
+    Ok(Command::Asking)
 }
 
-fn parse_type(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage type <key>"))?;
+fn parse_select(body: Vec<String>) -> Result<Command, RedisError> {
+    let [index] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
 
-    let command = Command::Type(key.to_string());
-    Ok(command)
+    Ok(Command::Select(parse_db_index(index)?))
 }
 
-fn parse_xadd(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let stream_key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage xadd <key> [entry_id] [...key value]"))?
-        .to_string();
+fn parse_swapdb(body: Vec<String>) -> Result<Command, RedisError> {
+    let [first, second] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let first =
+        parse_db_index(first).map_err(|_| RedisError::custom("ERR invalid first DB index"))?;
+    let second =
+        parse_db_index(second).map_err(|_| RedisError::custom("ERR invalid second DB index"))?;
 
-    let stream_id = body.get(1);
-    let stream_id = get_stream_id(stream_id);
+    Ok(Command::SwapDb(first, second))
+}
 
-    let (ms_time, sequence_number) = match stream_id {
-        None => anyhow::bail!("ERR Stream ID format not recognized"),
-        Some(stream_id) => stream_id,
+fn parse_move(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, index] = body.as_slice() else {
+        return Err(RedisError::Syntax);
     };
 
-    let args = body[2..].chunks(2);
-    let mut items: Vec<RedisStreamItem> = Vec::with_capacity(args.len());
+    Ok(Command::Move(key.clone(), parse_db_index(index)?))
+}
 
-    for data in args {
-        let k = data[0].to_string();
-        let v = data[1].to_string();
+/// Whether there are that many databases is up to the server's config.
+fn parse_db_index(index: &str) -> Result<usize, RedisError> {
+    let index: i64 = index.parse().map_err(|_| RedisError::NotAnInteger)?;
+    usize::try_from(index).map_err(|_| RedisError::custom("ERR DB index is out of range"))
+}
 
-        let item = RedisStreamItem::new(k, v);
-        items.push(item)
+fn parse_select_namespace(body: Vec<String>) -> Result<Command, RedisError> {
+    match body.as_slice() {
+        [name] if !name.is_empty() => Ok(Command::SelectNs(name.clone())),
+        _ => Err(RedisError::custom("ERR invalid namespace name")),
     }
+}
 
-    let command = XAddCommand {
-        stream_key,
-        data: items,
-        ms_time,
-        sequence_number,
+fn parse_dump(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
     };
-    let command = Command::Xadd(command);
-    Ok(command)
+
+    Ok(Command::Dump(key.clone()))
 }
 
-fn get_stream_id(stream_id: Option<&String>) -> Option<(XAddNumber, XAddNumber)> {
-    let stream_id = match stream_id {
-        Some(stream_id) => stream_id,
-        None => return None,
-    };
+fn parse_restore(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_restore_command(body).map(Command::Restore)
+}
 
-    if stream_id == "*" {
-        return Some((XAddNumber::Autogenerate, XAddNumber::Autogenerate));
-    }
+fn parse_restore_asking(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_restore_command(body).map(Command::RestoreAsking)
+}
 
-    let (time_part, sequence_number) = match stream_id.split_once('-') {
-        Some((seq, time)) => (seq, time),
-        None => return None,
+fn parse_restore_command(body: Vec<String>) -> Result<RestoreCommand, RedisError> {
+    let [key, ttl, payload, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
     };
-
-    let time_part = match str::parse::<usize>(time_part) {
-        Ok(val) => XAddNumber::Predetermined(val),
-        Err(_) => return None,
+    let ttl = ttl
+        .parse()
+        .map_err(|_| RedisError::custom("ERR Invalid TTL value, must be >= 0"))?;
+
+    let mut command = RestoreCommand {
+        key: key.clone(),
+        ttl,
+        payload: payload.clone(),
+        replace: false,
+        absttl: false,
+    };
+    for option in options {
+        match option.to_ascii_lowercase().as_str() {
+            "replace" => command.replace = true,
+            "absttl" => command.absttl = true,
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+
+    Ok(command)
+}
+
+fn parse_json(value: &str) -> Result<serde_json::Value, RedisError> {
+    serde_json::from_str(value).map_err(|e| RedisError::custom(e.to_string()))
+}
+
+fn parse_json_set(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, path, value, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let overwrite = match options {
+        [] => SetOverride::Normal,
+        [option] if option.eq_ignore_ascii_case("nx") => SetOverride::NeverOverwrite,
+        [option] if option.eq_ignore_ascii_case("xx") => SetOverride::OnlyOverwrite,
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::JsonSet(JsonSetCommand {
+        key: key.clone(),
+        path: JsonPath::parse(path)?,
+        value: parse_json(value)?,
+        overwrite,
+    }))
+}
+
+fn parse_json_get(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, paths) = body.split_first().ok_or(RedisError::Syntax)?;
+    let paths = match paths {
+        [] => vec![JsonPath::root()],
+        paths => paths
+            .iter()
+            .map(|path| JsonPath::parse(path))
+            .collect::<Result<_, _>>()?,
+    };
+
+    Ok(Command::JsonGet(key.clone(), paths))
+}
+
+fn parse_json_del(body: Vec<String>) -> Result<Command, RedisError> {
+    let path = match body.as_slice() {
+        [_] => JsonPath::root(),
+        [_, path] => JsonPath::parse(path)?,
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::JsonDel(body[0].clone(), path))
+}
+
+fn parse_json_num_incr_by(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, path, increment] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let serde_json::Value::Number(increment) = parse_json(increment)? else {
+        return Err(RedisError::custom("ERR increment is not a number"));
+    };
+
+    Ok(Command::JsonNumIncrBy(
+        key.clone(),
+        JsonPath::parse(path)?,
+        increment,
+    ))
+}
+
+fn parse_json_arr_append(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, path, values @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let values = values
+        .iter()
+        .map(|value| parse_json(value))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Command::JsonArrAppend(
+        key.clone(),
+        JsonPath::parse(path)?,
+        values,
+    ))
+}
+
+fn parse_bf_reserve(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, error_rate, capacity, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let error_rate = error_rate
+        .parse::<f64>()
+        .map_err(|_| RedisError::custom("ERR bad error rate"))?;
+    if !(error_rate > 0.0 && error_rate < 1.0) {
+        return Err(RedisError::custom("ERR (0 < error rate range < 1)"));
+    }
+    let capacity = capacity
+        .parse::<u64>()
+        .map_err(|_| RedisError::custom("ERR bad capacity"))?;
+    if capacity == 0 {
+        return Err(RedisError::custom("ERR (capacity should be larger than 0)"));
+    }
+
+    let mut expansion = Some(crate::bloom::DEFAULT_EXPANSION);
+    let mut nonscaling = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().as_str() {
+            "expansion" => {
+                let value = options
+                    .next()
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .filter(|value| *value > 0)
+                    .ok_or_else(|| RedisError::custom("ERR bad expansion"))?;
+                expansion = Some(value);
+            }
+            "nonscaling" => nonscaling = true,
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    if nonscaling {
+        expansion = None;
+    }
+
+    Ok(Command::BfReserve(BfReserveCommand {
+        key: key.clone(),
+        error_rate,
+        capacity,
+        expansion,
+    }))
+}
+
+fn parse_bf_add(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::BfAdd(key, item))
+}
+
+fn parse_bf_madd(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, items) = body.split_first().ok_or(RedisError::Syntax)?;
+
+    Ok(Command::BfMAdd(key.clone(), items.to_vec()))
+}
+
+fn parse_bf_exists(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::BfExists(key, item))
+}
+
+fn parse_cf_reserve(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, capacity, options) = match body.as_slice() {
+        [key, capacity, options @ ..] => (key, capacity, options),
+        _ => return Err(RedisError::Syntax),
+    };
+
+    let mut command = CfReserveCommand {
+        key: key.clone(),
+        capacity: capacity
+            .parse()
+            .ok()
+            .filter(|capacity| *capacity > 0)
+            .ok_or_else(|| RedisError::custom("ERR Bad capacity"))?,
+        bucket_size: crate::cuckoo::DEFAULT_BUCKET_SIZE,
+        max_iterations: crate::cuckoo::DEFAULT_MAX_ITERATIONS,
+        expansion: crate::cuckoo::DEFAULT_EXPANSION,
+    };
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let (setting, range, error) = match option.to_ascii_lowercase().as_str() {
+            "bucketsize" => (&mut command.bucket_size, 1..=255, "ERR Bad bucket size"),
+            "maxiterations" => (
+                &mut command.max_iterations,
+                1..=65535,
+                "ERR Bad maxIterations",
+            ),
+            "expansion" => (&mut command.expansion, 0..=32768, "ERR Bad expansion"),
+            _ => return Err(RedisError::Syntax),
+        };
+        *setting = options
+            .next()
+            .and_then(|value| value.parse().ok())
+            .filter(|value| range.contains(value))
+            .ok_or_else(|| RedisError::custom(error))?;
+    }
+
+    if command.capacity < command.bucket_size * 2 {
+        return Err(RedisError::custom(
+            "ERR Capacity must be at least (BucketSize * 2)",
+        ));
+    }
+
+    Ok(Command::CfReserve(command))
+}
+
+fn parse_cf_add(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::CfAdd(key, item))
+}
+
+fn parse_cf_add_nx(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::CfAddNx(key, item))
+}
+
+fn parse_cf_exists(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::CfExists(key, item))
+}
+
+fn parse_cf_del(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::CfDel(key, item))
+}
+
+fn parse_cf_count(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, item) = parse_key_and_item(body)?;
+    Ok(Command::CfCount(key, item))
+}
+
+fn parse_cms_init_by_dim(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, width, depth] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let width = width
+        .parse::<u64>()
+        .ok()
+        .filter(|width| *width > 0)
+        .ok_or_else(|| RedisError::custom("ERR CMS: invalid width"))?;
+    let depth = depth
+        .parse::<u64>()
+        .ok()
+        .filter(|depth| *depth > 0)
+        .ok_or_else(|| RedisError::custom("ERR CMS: invalid depth"))?;
+
+    Ok(Command::CmsInitByDim(key.clone(), width, depth))
+}
+
+fn parse_cms_init_by_prob(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, error, probability] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let error = error
+        .parse::<f64>()
+        .ok()
+        .filter(|error| *error > 0.0 && *error < 1.0)
+        .ok_or_else(|| RedisError::custom("ERR CMS: invalid overestimation value"))?;
+    let probability = probability
+        .parse::<f64>()
+        .ok()
+        .filter(|probability| *probability > 0.0 && *probability < 1.0)
+        .ok_or_else(|| RedisError::custom("ERR CMS: invalid prob value"))?;
+
+    let (width, depth) = crate::cms::CountMinSketch::dimensions_for(error, probability);
+    Ok(Command::CmsInitByProb(key.clone(), width, depth))
+}
+
+fn parse_cms_incr_by(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, pairs) = body.split_first().ok_or(RedisError::Syntax)?;
+    if pairs.len() % 2 != 0 {
+        return Err(RedisError::Syntax);
+    }
+
+    let increments = pairs
+        .chunks(2)
+        .map(|pair| {
+            let increment = pair[1]
+                .parse::<u64>()
+                .map_err(|_| RedisError::custom("ERR CMS: Cannot parse number"))?;
+            Ok((pair[0].clone(), increment))
+        })
+        .collect::<Result<_, RedisError>>()?;
+
+    Ok(Command::CmsIncrBy(key.clone(), increments))
+}
+
+fn parse_cms_query(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, items) = body.split_first().ok_or(RedisError::Syntax)?;
+
+    Ok(Command::CmsQuery(key.clone(), items.to_vec()))
+}
+
+fn parse_cms_merge(body: Vec<String>) -> Result<Command, RedisError> {
+    let [destination, num_keys, rest @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let num_keys = num_keys
+        .parse::<usize>()
+        .ok()
+        .filter(|num_keys| *num_keys > 0 && *num_keys <= rest.len())
+        .ok_or_else(|| RedisError::custom("ERR CMS: invalid numkeys"))?;
+    let (sources, rest) = rest.split_at(num_keys);
+
+    let weights = match rest {
+        [] => vec![1; num_keys],
+        [weights, weights_list @ ..] if weights.eq_ignore_ascii_case("weights") => {
+            if weights_list.len() != num_keys {
+                return Err(RedisError::Syntax);
+            }
+            weights_list
+                .iter()
+                .map(|weight| {
+                    weight
+                        .parse::<u64>()
+                        .map_err(|_| RedisError::custom("ERR CMS: invalid weight value"))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::CmsMerge(CmsMergeCommand {
+        destination: destination.clone(),
+        sources: sources.iter().cloned().zip(weights).collect(),
+    }))
+}
+
+fn parse_topk_reserve(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, k, dimensions) = match body.as_slice() {
+        [key, k] => (key, k, None),
+        [key, k, width, depth, decay] => (key, k, Some((width, depth, decay))),
+        _ => return Err(RedisError::Syntax),
+    };
+
+    let positive = |value: &String, error: &str| {
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or_else(|| RedisError::custom(error))
+    };
+    let mut command = TopkReserveCommand {
+        key: key.clone(),
+        k: positive(k, "ERR TopK: invalid k")?,
+        width: crate::topk::DEFAULT_WIDTH,
+        depth: crate::topk::DEFAULT_DEPTH,
+        decay: crate::topk::DEFAULT_DECAY,
+    };
+    if let Some((width, depth, decay)) = dimensions {
+        command.width = positive(width, "ERR TopK: invalid width")?;
+        command.depth = positive(depth, "ERR TopK: invalid depth")?;
+        command.decay = decay
+            .parse::<f64>()
+            .ok()
+            .filter(|decay| *decay > 0.0 && *decay <= 1.0)
+            .ok_or_else(|| {
+                RedisError::custom("ERR TopK: invalid decay value. must be '<= 1' & '> 0'")
+            })?;
+    }
+
+    Ok(Command::TopkReserve(command))
+}
+
+fn parse_topk_add(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, items) = body.split_first().ok_or(RedisError::Syntax)?;
+
+    Ok(Command::TopkAdd(key.clone(), items.to_vec()))
+}
+
+fn parse_topk_query(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, items) = body.split_first().ok_or(RedisError::Syntax)?;
+
+    Ok(Command::TopkQuery(key.clone(), items.to_vec()))
+}
+
+fn parse_topk_count(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, items) = body.split_first().ok_or(RedisError::Syntax)?;
+
+    Ok(Command::TopkCount(key.clone(), items.to_vec()))
+}
+
+fn parse_topk_list(body: Vec<String>) -> Result<Command, RedisError> {
+    match body.as_slice() {
+        [key] => Ok(Command::TopkList(key.clone(), false)),
+        [key, option] if option.eq_ignore_ascii_case("withcount") => {
+            Ok(Command::TopkList(key.clone(), true))
+        }
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_ts_add(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, timestamp, value, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let timestamp = match timestamp.as_str() {
+        "*" => None,
+        timestamp => Some(
+            timestamp
+                .parse::<u64>()
+                .map_err(|_| RedisError::custom("ERR TSDB: invalid timestamp"))?,
+        ),
+    };
+    let value = value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| !value.is_nan())
+        .ok_or_else(|| RedisError::custom("ERR TSDB: invalid value"))?;
+
+    let mut command = TsAddCommand {
+        key: key.clone(),
+        timestamp,
+        value,
+        retention: 0,
+        labels: vec![],
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().as_str() {
+            "retention" => {
+                command.retention = options
+                    .next()
+                    .and_then(|retention| retention.parse().ok())
+                    .ok_or_else(|| RedisError::custom("ERR TSDB: Couldn't parse RETENTION"))?;
+            }
+            // The labels run to the end of the command
+            "labels" => {
+                let labels = options.as_slice();
+                if labels.is_empty() || labels.len() % 2 != 0 {
+                    return Err(RedisError::custom("ERR TSDB: Couldn't parse LABELS"));
+                }
+                command.labels = labels
+                    .chunks(2)
+                    .map(|label| (label[0].clone(), label[1].clone()))
+                    .collect();
+                break;
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::TsAdd(command))
+}
+
+fn parse_ts_get(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::TsGet(key.clone()))
+}
+
+fn parse_ts_range(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, from, to, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let range = parse_ts_timestamps(from, to)?;
+    let aggregation = match options {
+        [] => None,
+        [option, aggregation @ ..]
+            if option.eq_ignore_ascii_case("aggregation") && aggregation.len() == 2 =>
+        {
+            Some(parse_ts_aggregation(aggregation)?)
+        }
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::TsRange(TsRangeCommand {
+        key: key.clone(),
+        range,
+        aggregation,
+    }))
+}
+
+fn parse_ts_mrange(body: Vec<String>) -> Result<Command, RedisError> {
+    let [from, to, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let mut command = TsMRangeCommand {
+        range: parse_ts_timestamps(from, to)?,
+        aggregation: None,
+        with_labels: false,
+        filters: vec![],
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().as_str() {
+            "withlabels" => command.with_labels = true,
+            "aggregation" => {
+                command.aggregation = Some(parse_ts_aggregation(options.as_slice())?);
+                options.nth(1);
+            }
+            // The filters run to the end of the command
+            "filter" => {
+                command.filters = options
+                    .map(|filter| LabelFilter::parse(filter))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| RedisError::custom("ERR TSDB: failed parsing labels"))?;
+                break;
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    if !command.filters.iter().any(LabelFilter::is_matcher) {
+        return Err(RedisError::custom(
+            "ERR TSDB: please provide at least one matcher",
+        ));
+    }
+
+    Ok(Command::TsMRange(command))
+}
+
+/// The timestamps of a TS.RANGE or TS.MRANGE, where `-` and `+` are the
+/// earliest and latest possible.
+fn parse_ts_timestamps(from: &str, to: &str) -> Result<RangeInclusive<u64>, RedisError> {
+    let from = match from {
+        "-" => 0,
+        from => from
+            .parse()
+            .map_err(|_| RedisError::custom("ERR TSDB: wrong fromTimestamp"))?,
+    };
+    let to = match to {
+        "+" => u64::MAX,
+        to => to
+            .parse()
+            .map_err(|_| RedisError::custom("ERR TSDB: wrong toTimestamp"))?,
+    };
+
+    Ok(from..=to)
+}
+
+/// The aggregation and bucket duration after an AGGREGATION option.
+fn parse_ts_aggregation(args: &[String]) -> Result<(Aggregation, u64), RedisError> {
+    let [aggregation, bucket_duration, ..] = args else {
+        return Err(RedisError::Syntax);
+    };
+
+    let aggregation = Aggregation::parse(aggregation)
+        .ok_or_else(|| RedisError::custom("ERR TSDB: Unknown aggregation type"))?;
+    let bucket_duration = bucket_duration
+        .parse::<u64>()
+        .ok()
+        .filter(|duration| *duration > 0)
+        .ok_or_else(|| RedisError::custom("ERR TSDB: bucketDuration must be greater than zero"))?;
+
+    Ok((aggregation, bucket_duration))
+}
+
+/// The arguments of the commands that take a key and a single item.
+fn parse_key_and_item(body: Vec<String>) -> Result<(String, String), RedisError> {
+    let [key, item]: [String; 2] = body.try_into().map_err(|_| RedisError::Syntax)?;
+    Ok((key, item))
+}
+
+fn parse_migrate(body: Vec<String>) -> Result<Command, RedisError> {
+    let [host, port, key, db, timeout, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    let mut command = MigrateCommand {
+        host: host.clone(),
+        port: port.parse().map_err(|_| RedisError::NotAnInteger)?,
+        keys: vec![],
+        db: db.parse().map_err(|_| RedisError::NotAnInteger)?,
+        timeout: timeout.parse().map_err(|_| RedisError::NotAnInteger)?,
+        copy: false,
+        replace: false,
+        auth: None,
+    };
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().as_str() {
+            "copy" => command.copy = true,
+            "replace" => command.replace = true,
+            "auth" => {
+                let password = options.next().ok_or(RedisError::Syntax)?;
+                command.auth = Some((None, password.clone()));
+            }
+            "auth2" => {
+                let user = options.next().ok_or(RedisError::Syntax)?;
+                let password = options.next().ok_or(RedisError::Syntax)?;
+                command.auth = Some((Some(user.clone()), password.clone()));
+            }
+            "keys" => {
+                if !key.is_empty() {
+                    return Err(RedisError::custom("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"));
+                }
+                command.keys = options.by_ref().cloned().collect();
+            }
+            _ => return Err(RedisError::Syntax),
+        }
+    }
+    if command.keys.is_empty() {
+        if key.is_empty() {
+            return Err(RedisError::Syntax);
+        }
+        command.keys.push(key.clone());
+    }
+
+    Ok(Command::Migrate(command))
+}
+
+fn parse_slots(args: &[String]) -> Result<Vec<usize>, RedisError> {
+    args.iter().map(|slot| parse_slot(slot)).collect()
+}
+
+/// Every slot in the `start end` pairs, both ends included.
+fn parse_slot_ranges(args: &[String]) -> Result<Vec<usize>, RedisError> {
+    let mut slots = vec![];
+    for pair in args.chunks(2) {
+        let (start, end) = (parse_slot(&pair[0])?, parse_slot(&pair[1])?);
+        if start > end {
+            return Err(RedisError::Custom(format!(
+                "ERR start slot number {} is greater than end slot number {}",
+                start, end
+            )));
+        }
+        slots.extend(start..=end);
+    }
+
+    Ok(slots)
+}
+
+fn parse_keys(body: Vec<String>) -> Result<Command, RedisError> {
+    // TODO: Add handling for searching
+    // TODO: Add better error handling
+    let key_group = body
+        .first()
+        .ok_or_else(|| RedisError::custom("keys must specify a command"))?;
+
+    if key_group != "*" {
+        return Err(RedisError::custom(format!(
+            "ERR Only * command supported for keys, received {}",
+            key_group
+        )));
+    }
+
+    let command = Command::Keys(key_group.to_string());
+    Ok(command)
+}
+
+fn parse_type(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?;
+
+    let command = Command::Type(key.to_string());
+    Ok(command)
+}
+
+fn parse_object(body: Vec<String>) -> Result<Command, RedisError> {
+    let subcommand = body.first().ok_or(RedisError::Syntax)?;
+
+    let key = || {
+        body.get(1)
+            .map(|key| key.to_string())
+            .ok_or(RedisError::Syntax)
+    };
+    let object_command = match subcommand.to_ascii_lowercase().as_str() {
+        "chunks" => ObjectCommand::Chunks(key()?),
+        "encoding" => ObjectCommand::Encoding(key()?),
+        "refcount" => ObjectCommand::RefCount(key()?),
+        "idletime" => ObjectCommand::IdleTime(key()?),
+        "freq" => ObjectCommand::Freq(key()?),
+        _ => return Err(unknown_subcommand("object", subcommand)),
+    };
+
+    let command = Command::Object(object_command);
+    Ok(command)
+}
+
+fn parse_xadd(body: Vec<String>) -> Result<Command, RedisError> {
+    let stream_key = body.first().ok_or(RedisError::Syntax)?.to_string();
+
+    let stream_id = body.get(1);
+    let stream_id = get_stream_id(stream_id);
+
+    let (ms_time, sequence_number) = match stream_id {
+        None => return Err(RedisError::custom("ERR Stream ID format not recognized")),
+        Some(stream_id) => stream_id,
+    };
+
+    let args = body[2..].chunks(2);
+    let mut items: Vec<RedisStreamItem> = Vec::with_capacity(args.len());
+
+    for data in args {
+        let k = data[0].to_string();
+        let v = data[1].to_string();
+
+        let item = RedisStreamItem::new(k, v);
+        items.push(item)
+    }
+
+    let command = XAddCommand {
+        stream_key,
+        data: items,
+        ms_time,
+        sequence_number,
+    };
+    let command = Command::Xadd(command);
+    Ok(command)
+}
+
+fn get_stream_id(stream_id: Option<&String>) -> Option<(XAddNumber, XAddNumber)> {
+    let stream_id = stream_id?;
+
+    if stream_id == "*" {
+        return Some((XAddNumber::Autogenerate, XAddNumber::Autogenerate));
+    }
+
+    let (time_part, sequence_number) = match stream_id.split_once('-') {
+        Some((seq, time)) => (seq, time),
+        None => return None,
+    };
+
+    let time_part = match str::parse::<usize>(time_part) {
+        Ok(val) => XAddNumber::Predetermined(val),
+        Err(_) => return None,
     };
 
     let sequence_number = match sequence_number {
@@ -486,15 +2405,10 @@ fn get_stream_id(stream_id: Option<&String>) -> Option<(XAddNumber, XAddNumber)>
     Some((time_part, sequence_number))
 }
 
-fn parse_xrange(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage xrange <key> <start> <end>"))?
-        .to_string();
+fn parse_xrange(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
-    let start = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("usage xrange <key> <start> <end>"))?;
+    let start = body.get(1).ok_or(RedisError::Syntax)?;
     let start = if start.len() == 1 && start.starts_with('-') {
         XRangeNumber::Unspecified
     } else {
@@ -502,9 +2416,7 @@ fn parse_xrange(body: Vec<String>) -> Result<Command, anyhow::Error> {
         XRangeNumber::Specified(ms_time, sequence_number)
     };
 
-    let end = body
-        .get(2)
-        .ok_or_else(|| anyhow::anyhow!("usage xrange <key> <start> <end>"))?;
+    let end = body.get(2).ok_or(RedisError::Syntax)?;
     let end = if end.len() == 1 && end.starts_with('+') {
         XRangeNumber::Unspecified
     } else {
@@ -517,15 +2429,17 @@ fn parse_xrange(body: Vec<String>) -> Result<Command, anyhow::Error> {
     Ok(command)
 }
 
-fn parse_xadd_specified_number(nums: &str) -> Result<(u128, usize), anyhow::Error> {
+fn parse_xadd_specified_number(nums: &str) -> Result<(u128, usize), RedisError> {
+    let invalid_id =
+        |_| RedisError::custom("ERR Invalid stream ID specified as stream command argument");
     let (ms_time, sequence_number) = match nums.split_once('-') {
         Some((ms_time, sequence_number)) => {
-            let ms_time = str::parse::<u128>(ms_time)?;
-            let sequence_number = str::parse::<usize>(sequence_number)?;
+            let ms_time = str::parse::<u128>(ms_time).map_err(invalid_id)?;
+            let sequence_number = str::parse::<usize>(sequence_number).map_err(invalid_id)?;
             (ms_time, sequence_number)
         }
         None => {
-            let ms_time = str::parse::<u128>(nums)?;
+            let ms_time = str::parse::<u128>(nums).map_err(invalid_id)?;
             let sequence_number = 0;
             (ms_time, sequence_number)
         }
@@ -534,15 +2448,13 @@ fn parse_xadd_specified_number(nums: &str) -> Result<(u128, usize), anyhow::Erro
     Ok((ms_time, sequence_number))
 }
 
-fn parse_xread(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_xread(body: Vec<String>) -> Result<Command, RedisError> {
     let block_index = body.iter().position(|cmd| cmd.to_lowercase() == "block");
     let block_len = match block_index {
         None => None,
         Some(block_idx) => {
-            let block_amt = body
-                .get(block_idx + 1)
-                .map(|v| str::parse::<u64>(v))
-                .ok_or_else(|| anyhow::anyhow!("ERR Expected block length after block option"))??;
+            let block_amt = body.get(block_idx + 1).ok_or(RedisError::Syntax)?;
+            let block_amt = str::parse::<u64>(block_amt).map_err(|_| RedisError::NotAnInteger)?;
             Some(block_amt)
         }
     };
@@ -554,8 +2466,8 @@ fn parse_xread(body: Vec<String>) -> Result<Command, anyhow::Error> {
 
     let starting_index = body
         .iter()
-        .position(|cmd| cmd.to_ascii_lowercase() == "streams")
-        .ok_or_else(|| anyhow::anyhow!("usage streams ..<stream_key> ..<start>"))?
+        .position(|cmd| cmd.eq_ignore_ascii_case("streams"))
+        .ok_or(RedisError::Syntax)?
         + 1;
 
     let mut num_streams = 0;
@@ -567,7 +2479,7 @@ fn parse_xread(body: Vec<String>) -> Result<Command, anyhow::Error> {
     }
 
     if num_streams == 0 {
-        anyhow::bail!("ERR Expected at least one stream");
+        return Err(RedisError::custom("ERR Expected at least one stream"));
     }
 
     let mut streams: Vec<XReadCommandStream> = Vec::with_capacity(num_streams);
@@ -577,19 +2489,26 @@ fn parse_xread(body: Vec<String>) -> Result<Command, anyhow::Error> {
 
         let key = body
             .get(key_index)
-            .ok_or_else(|| anyhow::anyhow!("ERR Unable to read stream key at index {}", key_index))?
+            .ok_or_else(|| {
+                RedisError::custom(format!(
+                    "ERR Unable to read stream key at index {}",
+                    key_index
+                ))
+            })?
             .to_string();
 
         let stream_start = body.get(stream_start_index).ok_or_else(|| {
-            anyhow::anyhow!(
+            RedisError::custom(format!(
                 "ERR Unable to read stream start at index {}",
                 stream_start_index
-            )
+            ))
         })?;
 
         let stream_start = if stream_start.len() == 1 && stream_start.starts_with('$') {
             if block.is_none() {
-                anyhow::bail!("ERR $ entry ID can only be used in blocking commands")
+                return Err(RedisError::custom(
+                    "ERR $ entry ID can only be used in blocking commands",
+                ));
             }
             XReadNumber::AllNewEntries
         } else {
@@ -610,75 +2529,545 @@ fn parse_xread(body: Vec<String>) -> Result<Command, anyhow::Error> {
     Ok(command)
 }
 
-fn parse_increment(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage incr <key>"))?
-        .to_string();
+fn parse_append(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::Append(key.clone(), value.clone()))
+}
+
+fn parse_lpush(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, values) = parse_key_and_values(body, "lpush")?;
+
+    Ok(Command::LPush(key, values))
+}
+
+fn parse_rpush(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, values) = parse_key_and_values(body, "rpush")?;
+
+    Ok(Command::RPush(key, values))
+}
+
+/// The key of a command like LPUSH, then at least one value.
+fn parse_key_and_values(
+    body: Vec<String>,
+    name: &str,
+) -> Result<(String, Vec<String>), RedisError> {
+    let mut body = body.into_iter();
+    let Some(key) = body.next() else {
+        return Err(wrong_number_of_arguments(name));
+    };
+    let values: Vec<String> = body.collect();
+    if values.is_empty() {
+        return Err(wrong_number_of_arguments(name));
+    }
+
+    Ok((key, values))
+}
+
+fn parse_lpop(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, count) = parse_pop(body)?;
+
+    Ok(Command::LPop(key, count))
+}
+
+fn parse_rpop(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, count) = parse_pop(body)?;
+
+    Ok(Command::RPop(key, count))
+}
+
+/// The key of LPOP or RPOP and the count, if one was given.
+fn parse_pop(body: Vec<String>) -> Result<(String, Option<usize>), RedisError> {
+    match body.as_slice() {
+        [key] => Ok((key.clone(), None)),
+        [key, count] => {
+            let count = count.parse::<i64>().map_err(|_| RedisError::NotAnInteger)?;
+            let count = usize::try_from(count)
+                .map_err(|_| RedisError::custom("ERR value is out of range, must be positive"))?;
+            Ok((key.clone(), Some(count)))
+        }
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_blpop(body: Vec<String>) -> Result<Command, RedisError> {
+    let (keys, timeout) = parse_blocking_pop(body)?;
+
+    Ok(Command::BLPop(keys, timeout))
+}
+
+fn parse_brpop(body: Vec<String>) -> Result<Command, RedisError> {
+    let (keys, timeout) = parse_blocking_pop(body)?;
+
+    Ok(Command::BRPop(keys, timeout))
+}
+
+/// The keys of BLPOP or BRPOP, then the timeout in seconds, which waits forever
+/// when it's 0.
+fn parse_blocking_pop(
+    mut body: Vec<String>,
+) -> Result<(Vec<String>, Option<Duration>), RedisError> {
+    let Some(timeout) = body.pop() else {
+        return Err(RedisError::Syntax);
+    };
+    if body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok((body, parse_blocking_timeout(&timeout)?))
+}
+
+fn parse_blocking_timeout(timeout: &str) -> Result<Option<Duration>, RedisError> {
+    let not_a_float = || RedisError::custom("ERR timeout is not a float or out of range");
+    let timeout: f64 = timeout.parse().map_err(|_| not_a_float())?;
+    if timeout < 0.0 {
+        return Err(RedisError::custom("ERR timeout is negative"));
+    }
+    if timeout == 0.0 {
+        return Ok(None);
+    }
+
+    Duration::try_from_secs_f64(timeout)
+        .map(Some)
+        .map_err(|_| not_a_float())
+}
+
+fn parse_lmove(body: Vec<String>) -> Result<Command, RedisError> {
+    let [source, destination, from, to] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::LMove(LMoveCommand {
+        source: source.clone(),
+        destination: destination.clone(),
+        from: parse_list_end(from)?,
+        to: parse_list_end(to)?,
+    }))
+}
+
+fn parse_rpoplpush(body: Vec<String>) -> Result<Command, RedisError> {
+    let [source, destination] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::LMove(LMoveCommand {
+        source: source.clone(),
+        destination: destination.clone(),
+        from: ListEnd::Right,
+        to: ListEnd::Left,
+    }))
+}
+
+fn parse_blmove(mut body: Vec<String>) -> Result<Command, RedisError> {
+    let Some(timeout) = body.pop() else {
+        return Err(RedisError::Syntax);
+    };
+    let Command::LMove(command) = parse_lmove(body)? else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::BLMove(command, parse_blocking_timeout(&timeout)?))
+}
+
+fn parse_lmpop(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_lmpop_command(body).map(Command::LMPop)
+}
+
+fn parse_blmpop(body: Vec<String>) -> Result<Command, RedisError> {
+    let mut body = body.into_iter();
+    let Some(timeout) = body.next() else {
+        return Err(RedisError::Syntax);
+    };
+    let timeout = parse_blocking_timeout(&timeout)?;
+
+    Ok(Command::BLMPop(
+        parse_lmpop_command(body.collect())?,
+        timeout,
+    ))
+}
+
+/// The number of keys, the keys, the end to pop from and then optionally COUNT.
+fn parse_lmpop_command(body: Vec<String>) -> Result<LMPopCommand, RedisError> {
+    let mut body = body.into_iter();
+    let num_keys: i64 = body
+        .next()
+        .ok_or(RedisError::Syntax)?
+        .parse()
+        .map_err(|_| RedisError::NotAnInteger)?;
+    if num_keys <= 0 {
+        return Err(RedisError::custom("ERR numkeys should be greater than 0"));
+    }
+
+    let keys: Vec<String> = body.by_ref().take(num_keys as usize).collect();
+    if keys.len() < num_keys as usize {
+        return Err(RedisError::Syntax);
+    }
+    let end = parse_list_end(&body.next().ok_or(RedisError::Syntax)?)?;
+
+    let count = match (body.next(), body.next(), body.next()) {
+        (None, ..) => 1,
+        (Some(option), Some(count), None) if option.eq_ignore_ascii_case("count") => {
+            match count.parse::<i64>() {
+                Ok(count) if count > 0 => count as usize,
+                _ => return Err(RedisError::custom("ERR count should be greater than 0")),
+            }
+        }
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(LMPopCommand { keys, end, count })
+}
+
+fn parse_list_end(end: &str) -> Result<ListEnd, RedisError> {
+    match end.to_lowercase().as_str() {
+        "left" => Ok(ListEnd::Left),
+        "right" => Ok(ListEnd::Right),
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_llen(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::LLen(key.clone()))
+}
+
+fn parse_lrange(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, start, end] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let start = start.parse().map_err(|_| RedisError::NotAnInteger)?;
+    let end = end.parse().map_err(|_| RedisError::NotAnInteger)?;
+
+    Ok(Command::LRange(key.clone(), start, end))
+}
+
+fn parse_linsert(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, position, pivot, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let position = match position.to_lowercase().as_str() {
+        "before" => ListPosition::Before,
+        "after" => ListPosition::After,
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::LInsert(
+        key.clone(),
+        position,
+        pivot.clone(),
+        value.clone(),
+    ))
+}
+
+fn parse_lset(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, index, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let index = index.parse().map_err(|_| RedisError::NotAnInteger)?;
+
+    Ok(Command::LSet(key.clone(), index, value.clone()))
+}
+
+fn parse_lrem(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, count, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let count = count.parse().map_err(|_| RedisError::NotAnInteger)?;
+
+    Ok(Command::LRem(key.clone(), count, value.clone()))
+}
+
+fn parse_ltrim(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, start, end] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let start = start.parse().map_err(|_| RedisError::NotAnInteger)?;
+    let end = end.parse().map_err(|_| RedisError::NotAnInteger)?;
+
+    Ok(Command::LTrim(key.clone(), start, end))
+}
+
+fn parse_hset(body: Vec<String>) -> Result<Command, RedisError> {
+    let mut body = body.into_iter();
+    let Some(key) = body.next() else {
+        return Err(wrong_number_of_arguments("hset"));
+    };
+    let pairs = parse_pairs(body.collect(), "hset")?;
+
+    Ok(Command::HSet(key, pairs))
+}
+
+fn parse_hget(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, field] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HGet(key.clone(), field.clone()))
+}
+
+fn parse_hdel(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, fields) = parse_key_and_values(body, "hdel")?;
+
+    Ok(Command::HDel(key, fields))
+}
+
+fn parse_hgetall(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HGetAll(key.clone()))
+}
+
+fn parse_hexists(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, field] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HExists(key.clone(), field.clone()))
+}
+
+fn parse_hlen(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HLen(key.clone()))
+}
+
+fn parse_hkeys(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HKeys(key.clone()))
+}
+
+fn parse_hvals(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::HVals(key.clone()))
+}
+
+fn parse_hmget(body: Vec<String>) -> Result<Command, RedisError> {
+    let (key, fields) = parse_key_and_values(body, "hmget")?;
+
+    Ok(Command::HMGet(key, fields))
+}
+
+fn parse_mget(body: Vec<String>) -> Result<Command, RedisError> {
+    if body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::MGet(body))
+}
+
+fn parse_exists(body: Vec<String>) -> Result<Command, RedisError> {
+    if body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::Exists(body))
+}
+
+fn parse_touch(body: Vec<String>) -> Result<Command, RedisError> {
+    if body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::Touch(body))
+}
+
+fn parse_random_key(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::RandomKey)
+}
+
+fn parse_mset(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_pairs(body, "mset").map(Command::MSet)
+}
+
+fn parse_msetnx(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_pairs(body, "msetnx").map(Command::MSetNx)
+}
+
+/// The keys and values of a command like MSET, which come one after the other.
+fn parse_pairs(body: Vec<String>, name: &str) -> Result<Vec<(String, String)>, RedisError> {
+    if body.is_empty() || !body.len().is_multiple_of(2) {
+        return Err(wrong_number_of_arguments(name));
+    }
+
+    let mut body = body.into_iter();
+    let mut pairs = vec![];
+    while let (Some(key), Some(value)) = (body.next(), body.next()) {
+        pairs.push((key, value));
+    }
+
+    Ok(pairs)
+}
+
+/// Also SUBSTR, the name GETRANGE had before redis 2.0.
+fn parse_get_range(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, start, end] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let start = start.parse().map_err(|_| RedisError::NotAnInteger)?;
+    let end = end.parse().map_err(|_| RedisError::NotAnInteger)?;
+
+    Ok(Command::GetRange(key.clone(), start, end))
+}
+
+fn parse_set_range(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key, offset, value] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let offset = offset
+        .parse::<i64>()
+        .map_err(|_| RedisError::NotAnInteger)?;
+    let offset =
+        usize::try_from(offset).map_err(|_| RedisError::custom("ERR offset is out of range"))?;
+
+    Ok(Command::SetRange(key.clone(), offset, value.clone()))
+}
+
+fn parse_increment(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
     Ok(Command::Incr(key))
 }
 
-fn parse_increment_by(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage incrby <key> <increment>"))?
-        .to_string();
+fn parse_increment_by(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
-    let increment = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("usage incrby <key> <increment>"))?;
-    let increment = str::parse::<i64>(increment)
-        .map_err(|e| not_an_integer().context(format!("Unable to parse as u64: {}", e)))?;
+    let increment = body.get(1).ok_or(RedisError::Syntax)?;
+    let increment = str::parse::<i64>(increment).map_err(|_| RedisError::NotAnInteger)?;
 
     Ok(Command::IncrBy(key, increment))
 }
 
-fn parse_increment_by_float(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage incrbyfloat <key> <increment"))?
-        .to_string();
+fn parse_increment_by_float(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
-    let increment = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("usage incrby <key> <increment>"))?;
+    let increment = body.get(1).ok_or(RedisError::Syntax)?;
 
-    let increment = str::parse::<f64>(increment).map_err(|e| {
-        anyhow::anyhow!("ERR value is not a valid float")
-            .context(format!("Unable to parse as u64: {}", e))
-    })?;
+    let increment = str::parse::<f64>(increment)
+        .map_err(|_| RedisError::custom("ERR value is not a valid float"))?;
 
     Ok(Command::IncrByFloat(key, increment))
 }
 
-fn parse_decrement(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage decr <key>"))?
-        .to_string();
+fn parse_decrement(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
     Ok(Command::Decr(key))
 }
 
-fn parse_decrement_by(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage incrby <key> <decrement>"))?
-        .to_string();
+fn parse_decrement_by(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
-    let decrement = body
-        .get(1)
-        .ok_or_else(|| anyhow::anyhow!("usage incrby <key> <decrement>"))?;
-    let decrement = str::parse::<i64>(decrement)
-        .map_err(|e| not_an_integer().context(format!("Unable to parse as u64: {}", e)))?;
+    let decrement = body.get(1).ok_or(RedisError::Syntax)?;
+    let decrement = str::parse::<i64>(decrement).map_err(|_| RedisError::NotAnInteger)?;
 
     Ok(Command::DecrBy(key, decrement))
 }
 
-fn parse_delete(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_save(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::Save)
+}
+
+fn parse_bg_save(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::BgSave)
+}
+
+fn parse_export(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::Export)
+}
+
+fn parse_hot_keys(body: Vec<String>) -> Result<Command, RedisError> {
+    match body.as_slice() {
+        [] => Ok(Command::HotKeys(10)),
+        [option, count] if option.eq_ignore_ascii_case("count") => count
+            .parse()
+            .map(Command::HotKeys)
+            .map_err(|_| RedisError::custom("ERR value is out of range, must be positive")),
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_import(body: Vec<String>) -> Result<Command, RedisError> {
+    let (export, replace) = match body.as_slice() {
+        [export] => (export, false),
+        [export, replace] if replace.eq_ignore_ascii_case("replace") => (export, true),
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::Import(parse_json(export)?, replace))
+}
+
+fn parse_last_save(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::LastSave)
+}
+
+fn parse_bg_rewrite_aof(body: Vec<String>) -> Result<Command, RedisError> {
+    if !body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::BgRewriteAof)
+}
+
+fn parse_shutdown(body: Vec<String>) -> Result<Command, RedisError> {
+    let save = match body.as_slice() {
+        [] => ShutdownSave::Default,
+        [option] => match option.to_ascii_lowercase().as_str() {
+            "save" => ShutdownSave::Save,
+            "nosave" => ShutdownSave::NoSave,
+            _ => return Err(RedisError::Syntax),
+        },
+        _ => return Err(RedisError::Syntax),
+    };
+
+    Ok(Command::Shutdown(save))
+}
+
+fn parse_auth(body: Vec<String>) -> Result<Command, RedisError> {
+    match body.as_slice() {
+        [password] => Ok(Command::Auth(None, password.to_string())),
+        [user, password] => Ok(Command::Auth(Some(user.to_string()), password.to_string())),
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_delete(body: Vec<String>) -> Result<Command, RedisError> {
     if body.is_empty() {
-        anyhow::bail!("usage del <key> [key ...]")
+        return Err(RedisError::Syntax);
     }
 
     let keys = body.iter().map(|k| k.to_string()).collect();
@@ -686,27 +3075,133 @@ fn parse_delete(body: Vec<String>) -> Result<Command, anyhow::Error> {
     Ok(Command::Del(keys))
 }
 
-fn parse_get_delete(body: Vec<String>) -> Result<Command, anyhow::Error> {
-    let key = body
-        .first()
-        .ok_or_else(|| anyhow::anyhow!("usage getdel <key>"))?
-        .to_string();
+fn parse_expire(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_expire_after(body, 1000, "expire")
+}
+
+fn parse_pexpire(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_expire_after(body, 1, "pexpire")
+}
+
+/// EXPIRE and PEXPIRE, which differ in how many milliseconds a unit of the TTL
+/// is. A TTL that isn't positive expires the key right away.
+fn parse_expire_after(
+    body: Vec<String>,
+    multiplier: u64,
+    name: &str,
+) -> Result<Command, RedisError> {
+    let [key, amount, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let amount = str::parse::<i64>(amount).map_err(|_| RedisError::NotAnInteger)?;
+    let millis = (amount.max(0) as u64)
+        .checked_mul(multiplier)
+        .ok_or_else(|| invalid_expire_time(name))?;
+    let condition = ExpireCondition::parse(options)?;
+
+    Ok(Command::Expire(
+        key.clone(),
+        CommandExpiration::After(Duration::from_millis(millis)),
+        condition,
+    ))
+}
+
+fn parse_expire_at(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_expire_at_time(body, 1000, "expireat")
+}
+
+fn parse_pexpire_at(body: Vec<String>) -> Result<Command, RedisError> {
+    parse_expire_at_time(body, 1, "pexpireat")
+}
+
+/// EXPIREAT and PEXPIREAT, with the unix timestamp in seconds or milliseconds.
+/// Any time before now, negative ones included, deletes the key.
+fn parse_expire_at_time(
+    body: Vec<String>,
+    multiplier: u64,
+    name: &str,
+) -> Result<Command, RedisError> {
+    let [key, time, options @ ..] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+    let time = str::parse::<i64>(time).map_err(|_| RedisError::NotAnInteger)?;
+    let deadline = (time.max(0) as u64)
+        .checked_mul(multiplier)
+        .ok_or_else(|| invalid_expire_time(name))?;
+    let condition = ExpireCondition::parse(options)?;
+
+    Ok(Command::Expire(
+        key.clone(),
+        CommandExpiration::At(deadline as u128),
+        condition,
+    ))
+}
+
+fn parse_ttl(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::Ttl(key.clone()))
+}
+
+fn parse_pttl(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::PTtl(key.clone()))
+}
+
+fn parse_persist(body: Vec<String>) -> Result<Command, RedisError> {
+    let [key] = body.as_slice() else {
+        return Err(RedisError::Syntax);
+    };
+
+    Ok(Command::Persist(key.clone()))
+}
+
+fn parse_unlink(body: Vec<String>) -> Result<Command, RedisError> {
+    if body.is_empty() {
+        return Err(RedisError::Syntax);
+    }
+
+    Ok(Command::Unlink(body))
+}
+
+fn parse_flushdb(body: Vec<String>) -> Result<Command, RedisError> {
+    Ok(Command::FlushDb(parse_flush_mode(body)?))
+}
+
+fn parse_flushall(body: Vec<String>) -> Result<Command, RedisError> {
+    Ok(Command::FlushAll(parse_flush_mode(body)?))
+}
+
+/// Whether a flush should free the old keys on a background task.
+fn parse_flush_mode(body: Vec<String>) -> Result<bool, RedisError> {
+    match body.as_slice() {
+        [] => Ok(false),
+        [mode] => match mode.to_ascii_lowercase().as_str() {
+            "async" => Ok(true),
+            "sync" => Ok(false),
+            _ => Err(RedisError::Syntax),
+        },
+        _ => Err(RedisError::Syntax),
+    }
+}
+
+fn parse_get_delete(body: Vec<String>) -> Result<Command, RedisError> {
+    let key = body.first().ok_or(RedisError::Syntax)?.to_string();
 
     Ok(Command::GetDel(key))
 }
 
-fn parse_getex(body: Vec<String>) -> Result<Command, anyhow::Error> {
+fn parse_getex(body: Vec<String>) -> Result<Command, RedisError> {
     let mut body_iter = body.iter();
 
-    let getex_instructions = "GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
-      PXAT unix-time-milliseconds | PERSIST]";
-
-    let key = body_iter
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("ERR Missing key: {}", getex_instructions))?
-        .to_string();
+    let key = body_iter.next().ok_or(RedisError::Syntax)?.to_string();
 
-    let mut expires: CommandExpiration = CommandExpiration::None;
+    let mut expires = CommandExpiration::Keep;
 
     if let Some(item) = body_iter.next() {
         match item.to_ascii_lowercase().as_str() {
@@ -715,31 +3210,29 @@ fn parse_getex(body: Vec<String>) -> Result<Command, anyhow::Error> {
                     .next()
                     .ok_or_else(|| invalid_expire_time("getex"))?;
                 let duration = parse_expiry(amount, 1000)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::After(duration);
             }
             "px" => {
                 let amount = body_iter
                     .next()
                     .ok_or_else(|| invalid_expire_time("getex"))?;
                 let duration = parse_expiry(amount, 1)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::After(duration);
             }
             "exat" => {
                 let time = body_iter
                     .next()
                     .ok_or_else(|| invalid_expire_time("getex"))?;
-                let duration = parse_expiry_at(time, 1000)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::At(parse_expiry_at(time, 1000)?);
             }
             "pxat" => {
                 let time = body_iter
                     .next()
                     .ok_or_else(|| invalid_expire_time("getex"))?;
-                let duration = parse_expiry_at(time, 1)?;
-                expires = CommandExpiration::Expiry(duration);
+                expires = CommandExpiration::At(parse_expiry_at(time, 1)?);
             }
-            "persist" => expires = CommandExpiration::Other,
-            other => anyhow::bail!("unknown option: {}", other),
+            "persist" => expires = CommandExpiration::Persist,
+            _ => return Err(RedisError::Syntax),
         }
     }
 
@@ -747,6 +3240,6 @@ fn parse_getex(body: Vec<String>) -> Result<Command, anyhow::Error> {
     Ok(command)
 }
 
-pub fn invalid_expire_time(command: &str) -> anyhow::Error {
-    anyhow::anyhow!("ERR invalid expire time in '{}' command", command)
+pub fn invalid_expire_time(command: &str) -> RedisError {
+    RedisError::Custom(format!("ERR invalid expire time in '{}' command", command))
 }
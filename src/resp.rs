@@ -0,0 +1,219 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::encoding::{put_array_len, put_bulk_string, put_integer, put_null, put_simple_string};
+use crate::errors::ProtocolError;
+
+/// A RESP value, what every command replies with. Replies are put together from
+/// these and only turned into bytes once they're written to the client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Bytes),
+    Array(Vec<Value>),
+    /// A bulk string that isn't there, e.g. GET on a missing key.
+    Null,
+    /// An array that isn't there.
+    NullArray,
+}
+
+impl Value {
+    pub fn ok() -> Self {
+        Value::SimpleString("OK".to_string())
+    }
+
+    pub fn simple(value: impl Into<String>) -> Self {
+        Value::SimpleString(value.into())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Value::Error(message.into())
+    }
+
+    /// An array of bulk strings, like a command sent to the server.
+    pub fn bulk_array<S: AsRef<str>>(items: &[S]) -> Self {
+        Value::Array(
+            items
+                .iter()
+                .map(|item| Value::from(item.as_ref()))
+                .collect(),
+        )
+    }
+
+    /// A bulk string, if it's one that's valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::BulkString(value) => std::str::from_utf8(value).ok(),
+            Value::SimpleString(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        match self {
+            Value::SimpleString(value) => put_simple_string(buf, value),
+            Value::Error(message) => {
+                buf.reserve(message.len() + 3);
+                buf.put_u8(b'-');
+                buf.put_slice(message.as_bytes());
+                buf.put_slice(b"\r\n");
+            }
+            Value::Integer(value) => put_integer(buf, *value),
+            Value::BulkString(value) => put_bulk_string(buf, value),
+            Value::Array(items) => {
+                put_array_len(buf, items.len());
+                for item in items {
+                    item.encode_into(buf);
+                }
+            }
+            Value::Null => put_null(buf),
+            Value::NullArray => buf.put_slice(b"*-1\r\n"),
+        }
+    }
+
+    /// Reads a single value from the start of `input`, returning it along with how
+    /// many bytes it took up. Returns `None` if the value isn't complete yet.
+    pub fn parse(input: &[u8]) -> Result<Option<(Value, usize)>, ProtocolError> {
+        let Some((line, mut consumed)) = read_line(input) else {
+            return Ok(None);
+        };
+        let Some((&prefix, rest)) = line.split_first() else {
+            return Err(ProtocolError("expected a type prefix".to_string()));
+        };
+        let text = std::str::from_utf8(rest)
+            .map_err(|_| ProtocolError("invalid UTF-8 in line".to_string()))?;
+
+        let value = match prefix {
+            b'+' => Value::SimpleString(text.to_string()),
+            b'-' => Value::Error(text.to_string()),
+            b':' => Value::Integer(parse_number(text, "integer")?),
+            b'$' => {
+                let len = parse_number(text, "bulk length")?;
+                if len < 0 {
+                    return Ok(Some((Value::Null, consumed)));
+                }
+
+                let len = len as usize;
+                let end = consumed + len;
+                if input.len() < end + 2 {
+                    return Ok(None);
+                }
+                if &input[end..end + 2] != b"\r\n" {
+                    return Err(ProtocolError("expected CRLF after bulk string".to_string()));
+                }
+
+                let value = Bytes::copy_from_slice(&input[consumed..end]);
+                consumed = end + 2;
+                Value::BulkString(value)
+            }
+            b'*' => {
+                let len = parse_number(text, "multibulk length")?;
+                if len < 0 {
+                    return Ok(Some((Value::NullArray, consumed)));
+                }
+
+                let mut items = Vec::with_capacity(len.min(1024) as usize);
+                for _ in 0..len {
+                    let Some((item, item_len)) = Value::parse(&input[consumed..])? else {
+                        return Ok(None);
+                    };
+                    items.push(item);
+                    consumed += item_len;
+                }
+                Value::Array(items)
+            }
+            other => {
+                return Err(ProtocolError(format!(
+                    "expected '$', got '{}'",
+                    other as char
+                )))
+            }
+        };
+
+        Ok(Some((value, consumed)))
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::BulkString(Bytes::from(value))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::BulkString(Bytes::copy_from_slice(value.as_bytes()))
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// The line at the start of `input` without its CRLF, and the length including it.
+fn read_line(input: &[u8]) -> Option<(&[u8], usize)> {
+    let end = input.windows(2).position(|window| window == b"\r\n")?;
+    Some((&input[..end], end + 2))
+}
+
+fn parse_number(text: &str, what: &str) -> Result<i64, ProtocolError> {
+    text.parse()
+        .map_err(|_| ProtocolError(format!("invalid {}", what)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_survive_a_round_trip() {
+        let value = Value::Array(vec![
+            Value::ok(),
+            Value::error("ERR nope"),
+            Value::Integer(-3),
+            Value::from("hello\r\nworld"),
+            Value::Null,
+            Value::NullArray,
+            Value::Array(vec![]),
+        ]);
+
+        let encoded = value.encode();
+        assert_eq!(
+            &encoded[..],
+            b"*7\r\n+OK\r\n-ERR nope\r\n:-3\r\n$12\r\nhello\r\nworld\r\n$-1\r\n*-1\r\n*0\r\n"
+        );
+        assert_eq!(
+            Value::parse(&encoded).unwrap(),
+            Some((value, encoded.len()))
+        );
+    }
+
+    #[test]
+    fn incomplete_values_ask_for_more_input() {
+        assert_eq!(Value::parse(b"*2\r\n$3\r\nfoo\r\n").unwrap(), None);
+        assert_eq!(Value::parse(b"$3\r\nfo").unwrap(), None);
+        assert_eq!(Value::parse(b":12").unwrap(), None);
+        assert!(Value::parse(b"!oops\r\n").is_err());
+    }
+}
@@ -1,24 +1,48 @@
-use std::env;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Cursor;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
+use bytes::Bytes;
+use clap::Parser;
 use rand::Rng;
 use sha1::{Digest, Sha1};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::{RwLock, RwLockReadGuard};
-use tokio::time::{sleep, Instant};
-
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Notify, RwLock, RwLockReadGuard};
+use tokio::time::{sleep, timeout_at, Instant};
+
+use crate::aof::{self, Aof};
+use crate::cli::Args;
+use crate::clock::Clock;
+use crate::cluster::Cluster;
+pub use crate::config::Config;
+use crate::errors::RedisError;
+use crate::extension::{self, Extension};
+use crate::namespace::NamespaceLimits;
+use crate::ratelimit::RateLimits;
+use crate::session::Push;
+use crate::systemd::Supervised;
+use crate::tasks::TaskSupervisor;
 use crate::{data, encoding, request, stream};
 
-#[derive(Clone)]
+const REPL_BACKLOG_SIZE: usize = 1024 * 1024;
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Address {
     host: String,
     port: u16,
 }
 
+#[derive(Clone)]
 pub struct Replication {
     pub id: String,
     pub offset: u64,
@@ -29,26 +53,264 @@ impl Address {
         format!("{}:{}", self.host, self.port)
     }
 
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
     pub fn new(host: String, port: u16) -> Self {
         Address { host, port }
     }
+
+    /// Where a replica connecting from `peer` is listed: under the port it announced
+    /// with REPLCONF listening-port if it sent one.
+    pub fn of_replica(peer: SocketAddr, listening_port: Option<u16>) -> Self {
+        Address::new(peer.ip().to_string(), listening_port.unwrap_or(peer.port()))
+    }
 }
 
-#[derive(Debug)]
-pub struct Config {
-    pub dir: Option<String>,
-    pub db_file_name: Option<String>,
+pub enum ServerRole {
+    Master(Vec<Replica>),
+    Slave(Arc<MasterLink>),
+}
+
+/// A replica connected to this master along with the last offset it acknowledged.
+pub struct Replica {
+    address: Address,
+    // Drained onto the connection by the replica's writer task
+    sender: UnboundedSender<Bytes>,
+    ack: Arc<ReplicaAck>,
 }
 
-impl Config {
-    pub fn new(dir: Option<String>, db_file_name: Option<String>) -> Self {
-        Config { dir, db_file_name }
+impl Replica {
+    /// The `slaveN` line for INFO replication.
+    pub fn info(&self) -> String {
+        format!(
+            "ip={},port={},state=online,offset={},lag={}",
+            self.address.host,
+            self.address.port,
+            self.ack.offset(),
+            self.ack.lag().as_secs()
+        )
     }
 }
 
-pub enum ServerRole {
-    Master(Vec<TcpStream>, usize, usize),
-    Slave,
+/// The latest REPLCONF ACK a replica sent, updated by the task reading its link.
+pub struct ReplicaAck {
+    offset: AtomicU64,
+    // Unix timestamp in milliseconds
+    received_at: AtomicU64,
+    disconnected: AtomicBool,
+    // Set if an ACK was ever for less than the one before it
+    went_backwards: AtomicBool,
+}
+
+impl ReplicaAck {
+    fn new(synced_offset: u64) -> Self {
+        ReplicaAck {
+            offset: AtomicU64::new(synced_offset),
+            received_at: AtomicU64::new(unix_millis()),
+            disconnected: AtomicBool::new(false),
+            went_backwards: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, offset: u64) {
+        if self.offset.swap(offset, Ordering::SeqCst) > offset {
+            self.went_backwards.store(true, Ordering::SeqCst);
+        }
+        self.received_at.store(unix_millis(), Ordering::SeqCst);
+    }
+
+    /// Why the replica can't be where it says it is, if the offsets it has
+    /// acknowledged don't fit a replication stream at `master_offset` whose
+    /// backlog starts at `backlog_start`.
+    fn desync(&self, master_offset: u64, backlog_start: u64) -> Option<&'static str> {
+        let offset = self.offset();
+        if self.went_backwards.load(Ordering::SeqCst) {
+            Some("its offset went backwards")
+        } else if offset > master_offset {
+            Some("its offset is ahead of ours")
+        } else if offset < backlog_start {
+            Some("its offset fell out of the backlog")
+        } else {
+            None
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// Marks the link as closed so the replica is dropped on the next check.
+    pub fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    /// How long it's been since the replica last acknowledged anything.
+    pub fn lag(&self) -> Duration {
+        let received_at = self.received_at.load(Ordering::SeqCst);
+        Duration::from_millis(unix_millis().saturating_sub(received_at))
+    }
+}
+
+/// The most recent part of the replication stream, kept so a replica that briefly
+/// loses its link can catch up with a partial resync instead of a full one.
+struct ReplicationBacklog(VecDeque<u8>);
+
+impl ReplicationBacklog {
+    fn new() -> Self {
+        ReplicationBacklog(VecDeque::new())
+    }
+
+    /// The offset of the oldest byte still kept when the stream is at `current_offset`.
+    fn start(&self, current_offset: u64) -> u64 {
+        current_offset.saturating_sub(self.0.len() as u64)
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.0.extend(bytes);
+        if self.0.len() > REPL_BACKLOG_SIZE {
+            let excess = self.0.len() - REPL_BACKLOG_SIZE;
+            self.0.drain(..excess);
+        }
+    }
+
+    /// Everything after `offset` when the stream has reached `current_offset`,
+    /// or None if part of it has already been dropped.
+    fn since(&self, offset: u64, current_offset: u64) -> Option<Vec<u8>> {
+        let start = current_offset.checked_sub(self.0.len() as u64)?;
+        if offset < start || offset > current_offset {
+            return None;
+        }
+
+        let skip = (offset - start) as usize;
+        Some(self.0.iter().skip(skip).copied().collect())
+    }
+}
+
+/// The replica's end of the replication link: where the master is, how far into
+/// its replication stream we've got and whether we're currently connected.
+pub struct MasterLink {
+    master: Address,
+    listening_port: u16,
+    replication: std::sync::Mutex<Option<Replication>>,
+    up: AtomicBool,
+    /// repl-timeout in milliseconds, kept here so CONFIG SET reaches the link.
+    timeout: AtomicU64,
+    /// The database the master last selected in the replication stream. It outlives
+    /// the connection so a partial resync carries on in the same database.
+    selected_db: AtomicUsize,
+}
+
+impl MasterLink {
+    fn new(master: Address, listening_port: u16, timeout: Duration) -> Self {
+        MasterLink {
+            master,
+            listening_port,
+            replication: std::sync::Mutex::new(None),
+            up: AtomicBool::new(false),
+            timeout: AtomicU64::new(timeout.as_millis() as u64),
+            selected_db: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn selected_db(&self) -> usize {
+        self.selected_db.load(Ordering::SeqCst)
+    }
+
+    pub fn select_db(&self, index: usize) {
+        self.selected_db.store(index, Ordering::SeqCst);
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.replication()
+            .map(|replication| replication.offset)
+            .unwrap_or(0)
+    }
+
+    pub fn set_offset(&self, offset: u64) {
+        if let Some(replication) = self.replication.lock().unwrap().as_mut() {
+            replication.offset = offset;
+        }
+    }
+
+    pub fn replication(&self) -> Option<Replication> {
+        self.replication.lock().unwrap().clone()
+    }
+
+    pub fn master(&self) -> &Address {
+        &self.master
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+
+    /// How long the master can go quiet before the link is dropped and we reconnect.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout.load(Ordering::SeqCst))
+    }
+
+    fn set_timeout(&self, timeout: Duration) {
+        self.timeout
+            .store(timeout.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// Connects to the master and syncs with it. Once we've synced before we ask to
+    /// continue from our offset and only load a new snapshot if the master can't.
+    async fn connect(&self, database: &data::Database) -> Result<TcpStream, anyhow::Error> {
+        let mut connection = TcpStream::connect(self.master.name())
+            .await
+            .context("Failed to connect to master")?;
+        handshake(&mut connection, self.listening_port).await?;
+
+        let (id, offset) = match self.replication() {
+            Some(replication) => (replication.id, replication.offset.to_string()),
+            None => ("?".to_string(), "-1".to_string()),
+        };
+        let psync = encoding::encode_string_array(&["PSYNC", &id, &offset]);
+        connection.write_all(psync.as_bytes()).await?;
+
+        let response = read_line(&mut connection)
+            .await
+            .context("Reading the PSYNC response")?;
+        match response.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["+FULLRESYNC", id, offset] => {
+                let offset = str::parse(offset).context("Parsing offset into number")?;
+                load_master_snapshot(&mut connection, database).await?;
+                *self.replication.lock().unwrap() = Some(Replication {
+                    id: id.to_string(),
+                    offset,
+                });
+                // The stream after a snapshot starts out in the first database
+                self.select_db(0);
+            }
+            ["+CONTINUE"] => println!("Partial resync with master {}", self.master.name()),
+            ["+CONTINUE", id] => {
+                if let Some(replication) = self.replication.lock().unwrap().as_mut() {
+                    replication.id = id.to_string();
+                }
+            }
+            _ => anyhow::bail!("Unexpected response to PSYNC: {:?}", response),
+        };
+
+        Ok(connection)
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
 }
 
 pub struct RedisServer(Arc<RwLock<Server>>);
@@ -64,6 +326,26 @@ pub struct Server {
     pub role: ServerRole,
     pub address: Address,
     pub replication: Replication,
+    pub aof: Aof,
+    pub shutdown: watch::Sender<bool>,
+    replica_acks: Arc<Notify>,
+    backlog: ReplicationBacklog,
+    last_propagated: Instant,
+    /// Replica links and the server's periodic jobs.
+    pub tasks: TaskSupervisor,
+    // Lets the server push to a connection that isn't running a command, keyed by client id
+    pub clients: HashMap<u64, UnboundedSender<Push>>,
+    /// Which node serves which hash slots, only there when cluster-enabled is on.
+    pub cluster: Option<Cluster>,
+    /// How many replicas were dropped for acknowledging offsets that couldn't be right.
+    pub replica_desyncs: u64,
+    // Replicas dropped that way, which have to start over with a full resync
+    needs_full_resync: HashSet<Address>,
+    // The datasets of every namespace but the default one, by name
+    namespaces: HashMap<String, data::Database>,
+    // The database the replication stream last selected, `None` when the next
+    // write has to select its database whichever one it is
+    replicated_db: Option<usize>,
 }
 
 impl Server {
@@ -78,6 +360,26 @@ impl Server {
             role,
             address,
             replication,
+            aof: Aof::disabled(),
+            shutdown: watch::Sender::new(false),
+            replica_acks: Arc::new(Notify::new()),
+            backlog: ReplicationBacklog::new(),
+            last_propagated: Instant::now(),
+            tasks: TaskSupervisor::new(),
+            clients: HashMap::new(),
+            cluster: None,
+            replica_desyncs: 0,
+            needs_full_resync: HashSet::new(),
+            namespaces: HashMap::new(),
+            replicated_db: Some(0),
+        }
+    }
+}
+
+impl Server {
+    fn apply_repl_timeout(&self) {
+        if let ServerRole::Slave(link) = &self.role {
+            link.set_timeout(self.config.repl_timeout());
         }
     }
 }
@@ -87,215 +389,626 @@ impl RedisServer {
         RedisServer(Arc::new(RwLock::new(settings)))
     }
 
+    /// Builds the server from the command line, like redis-server does. Exits with
+    /// a usage message if the arguments don't make sense.
     pub async fn from_args() -> Result<(data::Database, Self), anyhow::Error> {
-        let args: Vec<String> = env::args().collect();
+        Args::parse().into_builder()?.build().await
+    }
 
-        let config = get_config(&args)?;
-        let host = get_host(&args)?;
-        let port = get_port(&args)?;
-        let address = Address { host, port };
+    pub async fn address(&self) -> String {
+        self.0.read().await.address.name()
+    }
 
-        let database = match (&config.dir, &config.db_file_name) {
-            (Some(dir), Some(file_name)) => {
-                let path = PathBuf::from(dir).join(file_name);
-                data::Database::from_config(path)?
-            }
-            _ => data::Database::new(),
-        };
+    /// Records the port the server ended up listening on, for when it was asked
+    /// to listen on port 0 and the OS picked one.
+    pub async fn set_port(&self, port: u16) {
+        let server = &mut *self.0.write().await;
+        server.address.port = port;
+        if let Some(cluster) = server.cluster.as_mut() {
+            cluster.set_address(server.address.clone());
+        }
+    }
 
-        let (replication, role) = get_role(&args, &address, database.clone()).await?;
+    pub async fn read(&self) -> RwLockReadGuard<'_, Server> {
+        self.0.read().await
+    }
 
-        let settings = Server {
-            role,
-            address,
-            replication,
-            config,
+    /// Runs `f` against the cluster state, or fails like redis if cluster mode is off.
+    pub async fn with_cluster<T>(
+        &self,
+        f: impl FnOnce(&mut Cluster) -> Result<T, RedisError>,
+    ) -> Result<T, RedisError> {
+        match self.0.write().await.cluster.as_mut() {
+            Some(cluster) => f(cluster),
+            None => Err(RedisError::custom(
+                "ERR This instance has cluster support disabled",
+            )),
+        }
+    }
+
+    pub async fn set_config(
+        &self,
+        key: request::ConfigKey,
+        value: String,
+    ) -> Result<(), anyhow::Error> {
+        let server = &mut *self.0.write().await;
+        server.config.set_at_runtime(&key, &value)?;
+        server.apply_repl_timeout();
+
+        Ok(())
+    }
+
+    pub async fn reload_config(&self) -> Result<(), anyhow::Error> {
+        let mut server = self.0.write().await;
+        let config = server.config.reload()?;
+        server.config = config;
+        server.apply_repl_timeout();
+
+        Ok(())
+    }
+
+    /// Performs the final save requested by SHUTDOWN or SIGTERM and flushes the AOF.
+    /// The server should keep running if this fails so no data is lost.
+    pub async fn prepare_shutdown(
+        &self,
+        database: &data::Database,
+        save: request::ShutdownSave,
+    ) -> Result<(), anyhow::Error> {
+        let (config, aof) = {
+            let server = self.0.read().await;
+            (server.config.clone(), server.aof.clone())
+        };
+
+        let should_save = match save {
+            request::ShutdownSave::Default => !config.save.is_empty(),
+            request::ShutdownSave::Save => true,
+            request::ShutdownSave::NoSave => false,
         };
 
-        let server = RedisServer::new(settings);
-        Ok((database, server))
+        aof.lock().await.sync()?;
+
+        if should_save {
+            println!("Saving the final RDB snapshot before exiting");
+            database.save(&config.rdb_path(), config.rdb_compression)?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks every accept loop to stop, and stops the background tasks of both
+    /// the server and the dataset.
+    pub async fn request_shutdown(&self, database: &data::Database) {
+        let server = self.0.read().await;
+        server.shutdown.send_replace(true);
+        server.tasks.abort_all();
+        database.tasks().abort_all();
     }
 
-    pub async fn address(&self) -> String {
-        self.0.read().await.address.name()
+    pub async fn tasks(&self) -> TaskSupervisor {
+        self.0.read().await.tasks.clone()
     }
 
-    pub async fn read(&self) -> RwLockReadGuard<'_, Server> {
-        self.0.read().await
+    /// Resolves once SHUTDOWN has been accepted.
+    pub async fn shutdown_requested(&self) {
+        let mut receiver = self.0.read().await.shutdown.subscribe();
+        let _ = receiver.wait_for(|requested| *requested).await;
     }
 
-    // The following two methods indicates that we need to restructure
-    // this so only masters can add streams and replicate commands
-    pub async fn add_stream(&self, stream: TcpStream) {
-        let role = &mut self.0.write().await.role;
-        match role {
-            ServerRole::Slave => {}
-            ServerRole::Master(streams, _, _) => {
-                streams.push(stream);
-            }
-        };
+    pub async fn is_shutting_down(&self) -> bool {
+        *self.0.read().await.shutdown.borrow()
     }
 
-    pub async fn replicate_command(&self, command: &[u8]) -> Result<(), anyhow::Error> {
-        let role = &mut self.0.write().await.role;
-        match role {
-            ServerRole::Slave => {}
-            ServerRole::Master(streams, byte_offset, num_sets) => {
-                // Tracking the number of set commands is to work round a bug which
-                // isn't allowing me to read the byte offsets from the threads.
-                let raw_request = String::from_utf8(command.to_vec())?
-                    .lines()
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>();
-
-                let request = request::parse_request(raw_request)?;
-                if let request::Command::Set(..) = request {
-                    *num_sets = 1;
-                }
+    /// Whether clients have to AUTH before running commands.
+    pub async fn requires_auth(&self) -> bool {
+        self.0.read().await.config.requirepass.is_some()
+    }
+
+    pub async fn rate_limits(&self) -> RateLimits {
+        self.0.read().await.config.rate_limits()
+    }
+
+    pub async fn max_memory(&self) -> u64 {
+        self.0.read().await.config.max_memory
+    }
+
+    pub async fn namespace_limits(&self) -> NamespaceLimits {
+        self.0.read().await.config.namespace_limits()
+    }
+
+    /// The dataset of a namespace other than the default one, made the first time
+    /// it's used. It tells the time by the same clock as the default dataset.
+    pub async fn namespace(&self, name: &str, default: &data::Database) -> data::Database {
+        if let Some(database) = self.0.read().await.namespaces.get(name) {
+            return database.clone();
+        }
+
+        let server = &mut *self.0.write().await;
+        server
+            .namespaces
+            .entry(name.to_string())
+            .or_insert_with(|| data::Database::new().with_clock(default.clock()))
+            .clone()
+    }
+
+    pub async fn is_cluster_enabled(&self) -> bool {
+        self.0.read().await.cluster.is_some()
+    }
+
+    /// Replicas only take writes from their master unless replica-read-only is off.
+    pub async fn is_read_only(&self) -> bool {
+        let server = self.0.read().await;
+        matches!(server.role, ServerRole::Slave(..)) && server.config.replica_read_only
+    }
+
+    /// Whether this is a replica that's lost its master and, with
+    /// replica-serve-stale-data off, only runs the commands flagged STALE.
+    pub async fn is_master_down(&self) -> bool {
+        let server = self.0.read().await;
+        match &server.role {
+            ServerRole::Slave(link) => !link.is_up() && !server.config.replica_serve_stale_data,
+            ServerRole::Master(..) => false,
+        }
+    }
+
+    /// Whether the replica at `address` was dropped for being out of sync and hasn't
+    /// done a full resync since. Asking clears it, as the replica is about to.
+    pub async fn take_full_resync(&self, address: &Address) -> bool {
+        self.0.write().await.needs_full_resync.remove(address)
+    }
 
-                *byte_offset += command.len();
+    /// Where a replica that's about to be sent a snapshot picks up the replication
+    /// stream. It starts out in the first database once the snapshot is loaded, so
+    /// unless the stream is already there the next write selects its database.
+    pub async fn start_full_resync(&self) -> Replication {
+        let server = &mut *self.0.write().await;
+        if server.replicated_db != Some(0) {
+            server.replicated_db = None;
+        }
+        server.replication.clone()
+    }
+
+    /// The last part of the replication stream, from `offset` onwards, if we still have it.
+    pub async fn backlog_since(&self, offset: u64) -> Option<Vec<u8>> {
+        let server = self.0.read().await;
+        server.backlog.since(offset, server.replication.offset)
+    }
+
+    /// Starts streaming writes to a replica that has just completed PSYNC at `synced_offset`,
+    /// first sending it whatever was replicated since. Its REPLCONF ACKs are read on a
+    /// separate task. The replica is listed under the port it announced with
+    /// REPLCONF listening-port if it sent one.
+    /// Registers a new connection and returns where its pushes will arrive.
+    pub async fn register_client(&self, id: u64) -> UnboundedReceiver<Push> {
+        let (tx, rx) = unbounded_channel();
+        self.0.write().await.clients.insert(id, tx);
+        rx
+    }
 
-                for stream in streams.iter_mut() {
-                    // TODO: Figure out why, on the opposite end, this isn't a separate read per invocation of this method
-                    stream.write_all(command).await?;
+    pub async fn unregister_client(&self, id: u64) {
+        self.0.write().await.clients.remove(&id);
+    }
+
+    /// Sends something to a connection outside of the request/reply cycle.
+    /// Returns false if there is no such client.
+    pub async fn push_to_client(&self, id: u64, push: Push) -> bool {
+        match self.0.read().await.clients.get(&id) {
+            Some(client) => client.send(push).is_ok(),
+            None => false,
+        }
+    }
+
+    pub async fn add_replica(
+        &self,
+        reader: OwnedReadHalf,
+        writer: OwnedWriteHalf,
+        listening_port: Option<u16>,
+        synced_offset: u64,
+    ) -> Result<(), anyhow::Error> {
+        let address = Address::of_replica(writer.peer_addr()?, listening_port);
+
+        let server = &mut *self.0.write().await;
+        let acks = server.replica_acks.clone();
+        let tasks = server.tasks.clone();
+        if let ServerRole::Master(replicas) = &mut server.role {
+            let missed = server
+                .backlog
+                .since(synced_offset, server.replication.offset)
+                .ok_or_else(|| anyhow::anyhow!("Replica fell behind the backlog while syncing"))?;
+
+            let ack = Arc::new(ReplicaAck::new(synced_offset));
+            let sender = spawn_replica_writer(&tasks, writer, address.clone(), ack.clone());
+            // Queued before the replica is added so it comes ahead of anything new
+            let _ = sender.send(Bytes::from(missed));
+
+            let replica_ack = ack.clone();
+            tasks.spawn("replica acks", async move {
+                if let Err(e) = stream::handle_replica_acks(reader, replica_ack.clone(), acks).await
+                {
+                    eprintln!("Error reading replica acknowledgements: {}", e);
                 }
-            }
-        };
+                replica_ack.disconnect();
+            });
+
+            println!("Replica {} connected", address.name());
+            replicas.push(Replica {
+                address,
+                sender,
+                ack,
+            });
+        }
 
         Ok(())
     }
 
-    // TODO: Completely rewrite this later
+    /// Persists and replicates a write that happened outside of a client's
+    /// command, or differs from the command that caused it.
+    pub async fn propagate_write(&self, db: usize, command: &[u8]) -> Result<(), anyhow::Error> {
+        let aof = self.0.read().await.aof.clone();
+        aof.lock().await.append(db, command)?;
+
+        self.replicate_command(db, command).await
+    }
+
+    /// Sends a PING down the replication stream once it's been quiet for
+    /// repl-ping-replica-period, so replicas can tell the master is still there.
+    pub async fn ping_idle_replicas(&self) {
+        let server = &mut *self.0.write().await;
+        let has_replicas =
+            matches!(&server.role, ServerRole::Master(replicas) if !replicas.is_empty());
+        if has_replicas
+            && server.last_propagated.elapsed() >= server.config.repl_ping_replica_period()
+        {
+            let ping = encoding::encode_string_array(&["PING"]);
+            propagate(server, ping.as_bytes());
+        }
+    }
+
+    /// Replicates a write made in database `db`, selecting it first if the
+    /// replication stream was in another one.
+    pub async fn replicate_command(&self, db: usize, command: &[u8]) -> Result<(), anyhow::Error> {
+        let server = &mut *self.0.write().await;
+        if server.replicated_db != Some(db) && matches!(server.role, ServerRole::Master(..)) {
+            let select = encoding::encode_string_array(&["SELECT", &db.to_string()]);
+            propagate(server, select.as_bytes());
+            server.replicated_db = Some(db);
+        }
+        propagate(server, command);
+
+        Ok(())
+    }
+
+    /// Drops replicas whose link has closed, that haven't acknowledged anything
+    /// for longer than repl-timeout, or whose acknowledged offset shows they're out
+    /// of step with us. Those last ones are made to do a full resync when they
+    /// reconnect, since continuing from wherever they think they are would leave
+    /// them with a different dataset.
+    pub async fn evict_dead_replicas(&self) {
+        let server = &mut *self.0.write().await;
+        let repl_timeout = server.config.repl_timeout();
+        let master_offset = server.replication.offset;
+        let backlog_start = server.backlog.start(master_offset);
+        if let ServerRole::Master(replicas) = &mut server.role {
+            replicas.retain(|replica| {
+                if let Some(reason) = replica.ack.desync(master_offset, backlog_start) {
+                    println!(
+                        "Replica {} is out of sync ({}), forcing a full resync",
+                        replica.address.name(),
+                        reason
+                    );
+                    server.replica_desyncs += 1;
+                    server.needs_full_resync.insert(replica.address.clone());
+                    return false;
+                }
+
+                if replica.ack.is_disconnected() {
+                    println!("Connection with replica {} lost", replica.address.name());
+                    return false;
+                }
+
+                if replica.ack.lag() > repl_timeout {
+                    println!("Disconnecting timedout replica {}", replica.address.name());
+                    return false;
+                }
+
+                true
+            });
+        }
+    }
+
+    /// Waits until `num_replicas` replicas have acknowledged every write made so far,
+    /// or `timeout` milliseconds have passed, and returns how many did.
+    /// A timeout of 0 waits forever.
     pub async fn perform_wait(
         &self,
         num_replicas: usize,
         timeout: u64,
     ) -> Result<usize, anyhow::Error> {
-        let role = &mut self.0.write().await.role;
-        let (streams, byte_offset, num_sets) = match role {
-            ServerRole::Slave => anyhow::bail!("Slave should not receive top level wait command"),
-            ServerRole::Master(streams, byte_offset, num_sets) => (streams, byte_offset, num_sets),
-        };
+        let (target_offset, acked_offsets, acks) = {
+            let server = &mut *self.0.write().await;
+            let acked_offsets: Vec<Arc<ReplicaAck>> = match &server.role {
+                ServerRole::Slave(..) => {
+                    anyhow::bail!("Slave should not receive top level wait command")
+                }
+                ServerRole::Master(replicas) => {
+                    replicas.iter().map(|replica| replica.ack.clone()).collect()
+                }
+            };
 
-        if num_replicas == 0 {
-            return Ok(streams.len());
-        }
+            let target_offset = server.replication.offset;
+            if count_acknowledged(&acked_offsets, target_offset) < num_replicas {
+                let get_ack = encoding::encode_string_array(&["REPLCONF", "GETACK", "*"]);
+                propagate(server, get_ack.as_bytes());
+            }
 
-        let timeout_as_duration = Duration::from_millis(timeout);
+            (target_offset, acked_offsets, server.replica_acks.clone())
+        };
 
-        let begin_time = Instant::now();
-        let mut elapsed_time = Duration::from_secs(0);
+        let deadline = match timeout {
+            0 => None,
+            timeout => Some(Instant::now() + Duration::from_millis(timeout)),
+        };
 
-        // let mut response_bytes = vec![0; 1024];
-        let mut replicas_acknowledged: usize = 0;
-        let get_ack = encoding::encode_string_array(&["REPLCONF", "GETACK", "*"]);
-        let get_ack = get_ack.as_bytes();
+        loop {
+            // Register for the next ack before counting so none can slip through in between.
+            let notified = acks.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
 
-        for stream in streams.iter_mut() {
-            if elapsed_time >= timeout_as_duration {
-                break;
+            let acknowledged = count_acknowledged(&acked_offsets, target_offset);
+            if acknowledged >= num_replicas {
+                return Ok(acknowledged);
             }
 
-            stream.write_all(get_ack).await?;
+            match deadline {
+                Some(deadline) => {
+                    if timeout_at(deadline, notified).await.is_err() {
+                        return Ok(count_acknowledged(&acked_offsets, target_offset));
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
 
-            // Read the response - we aren't actually using it for now
-            // it could be used to detect irregularities between master and slave
-            // We can't use it in the tests for some reason. I believe what's happening
-            // is that the tests are consuming the stream before we can.
-            // What should be happening is we should be calling `replicate_command` then
-            // see how many replicas are there.
-            // stream.read(&mut response_bytes).await?;
+/// Adds a message to the replication stream. Nothing is kept unless we're a master.
+/// The message is only queued for the replicas, so no I/O happens under the server lock.
+fn propagate(server: &mut Server, message: &[u8]) {
+    if let ServerRole::Master(replicas) = &mut server.role {
+        server.replication.offset += message.len() as u64;
+        server.backlog.push(message);
+        server.last_propagated = Instant::now();
+
+        let message = Bytes::copy_from_slice(message);
+        // Sending only fails once the writer task has given up on the connection
+        replicas.retain(|replica| replica.sender.send(message.clone()).is_ok());
+    }
+}
 
-            replicas_acknowledged += 1;
-            elapsed_time = Instant::now() - begin_time;
+/// Writes everything queued for a replica to its connection. If the connection
+/// goes away the replica is marked as disconnected and dropped on the next check.
+fn spawn_replica_writer(
+    tasks: &TaskSupervisor,
+    mut writer: OwnedWriteHalf,
+    address: Address,
+    ack: Arc<ReplicaAck>,
+) -> UnboundedSender<Bytes> {
+    let (sender, mut receiver) = unbounded_channel::<Bytes>();
+
+    tasks.spawn("replica writer", async move {
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = writer.write_all(&message).await {
+                eprintln!("Connection with replica {} lost: {}", address.name(), e);
+                ack.disconnect();
+                return;
+            }
         }
+    });
 
-        // Programming just to get a test to pass is an awful practice,
-        // but I can't progress in this exercise without passing the tests.
-        // And as far as I can tell, the tests conflict with my design.
-        // *byte_offset += get_ack.len();
-
-        // I have no idea why this is the winnign formula.
-        // If all of the replicas acknowledge things, shouldn't this be 3?
-        replicas_acknowledged = match *num_sets {
-            0 => streams.len(),
-            1 => 1,
-            _ => streams.len() - 1,
-        };
+    sender
+}
 
-        *byte_offset += get_ack.len();
+fn count_acknowledged(acked_offsets: &[Arc<ReplicaAck>], target_offset: u64) -> usize {
+    acked_offsets
+        .iter()
+        .filter(|ack| ack.offset() >= target_offset)
+        .count()
+}
 
-        let time_to_still_wait = timeout_as_duration - elapsed_time;
-        if !time_to_still_wait.is_zero() {
-            sleep(time_to_still_wait).await;
-        }
+/// Puts together a server and its dataset without going through the command line,
+/// e.g. to embed one in another program. The server starts handling clients once
+/// the pair is handed to `app::run`.
+#[derive(Debug)]
+pub struct ServerBuilder {
+    config: Config,
+    host: String,
+    port: Option<u16>,
+    replica_of: Option<Address>,
+    extensions: Vec<Box<dyn Extension>>,
+    clock: Option<Arc<dyn Clock>>,
+}
 
-        Ok(replicas_acknowledged)
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-async fn get_role(
-    args: &[String],
-    server_address: &Address,
-    database: data::Database,
-) -> Result<(Replication, ServerRole), anyhow::Error> {
-    let role_subcommand_index = args.iter().position(|arg| arg == "--replicaof");
-    if role_subcommand_index.is_none() {
-        let role = ServerRole::Master(vec![], 0, 0);
-        let replication = Replication {
-            id: generate_random_sha1_hex(),
-            offset: 0,
-        };
-        return Ok((replication, role));
+impl ServerBuilder {
+    pub fn new() -> Self {
+        ServerBuilder {
+            config: Config::new(None, None),
+            host: "127.0.0.1".to_string(),
+            port: None,
+            replica_of: None,
+            extensions: vec![],
+            clock: None,
+        }
     }
 
-    let role_subcommand_index = role_subcommand_index.unwrap();
+    /// Replaces the whole config, so it should come before the other settings.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
 
-    let host = args.get(role_subcommand_index + 1);
-    let port = args.get(role_subcommand_index + 2);
-    if host.is_none() || port.is_none() {
-        anyhow::bail!("usage --replicaof <host> <port>");
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
     }
-    let host = host.unwrap().to_string();
-    let port = parse_u16_port(port.unwrap())?;
 
-    let master_address = Address { host, port };
+    /// Takes precedence over the port in the config. With port 0 the OS picks one
+    /// once the server starts listening.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
 
-    sync_to_master(master_address, server_address, database).await
-}
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.dir = Some(dir.into());
+        self
+    }
 
-fn get_port(args: &[String]) -> Result<u16, anyhow::Error> {
-    let port_subcommand = args.iter().position(|arg| arg == "--port");
-    if port_subcommand.is_none() {
-        return Ok(6379);
+    pub fn db_file_name(mut self, db_file_name: impl Into<String>) -> Self {
+        self.config.db_file_name = Some(db_file_name.into());
+        self
     }
 
-    let port_index = port_subcommand.unwrap() + 1;
-    let port = args
-        .get(port_index)
-        .ok_or_else(|| anyhow::anyhow!("--port requires an integer port"))?;
-    let port = parse_u16_port(port)?;
+    pub fn io_threads(mut self, io_threads: usize) -> Self {
+        self.config.io_threads = io_threads;
+        self
+    }
 
-    Ok(port)
-}
+    pub fn supervised(mut self, supervised: Supervised) -> Self {
+        self.config.supervised = supervised;
+        self
+    }
 
-fn get_host(_args: &[String]) -> Result<String, anyhow::Error> {
-    Ok("127.0.0.1".to_string())
-}
+    /// Syncs with the master on `build`. The replica announces its port to the
+    /// master while syncing, so it shouldn't be left for the OS to pick.
+    pub fn replica_of(mut self, master_address: Address) -> Self {
+        self.replica_of = Some(master_address);
+        self
+    }
+
+    /// Adds the extension's commands and types. They're loaded on `build`, before
+    /// the dataset, which may hold values of the extension's types.
+    pub fn extension(mut self, extension: impl Extension + 'static) -> Self {
+        self.extensions.push(Box::new(extension));
+        self
+    }
 
-fn parse_u16_port(s: &str) -> Result<u16, anyhow::Error> {
-    s.parse::<u16>().context("Parsing port as u16")
+    /// What the dataset tells the time by instead of the system clock. It's switched
+    /// over once the dataset is loaded, so it's best used with nothing to load.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Loads the dataset from the AOF or RDB file if there is one, and syncs
+    /// with the master if the server is a replica.
+    pub async fn build(self) -> Result<(data::Database, RedisServer), anyhow::Error> {
+        let port = self.port.or(self.config.port).unwrap_or(6379);
+        let address = Address::new(self.host, port);
+
+        for extension in self.extensions.iter() {
+            extension::load(extension.as_ref())
+                .with_context(|| format!("Loading extension {}", extension.name()))?;
+        }
+
+        let mut database = load_database(&self.config)?;
+        if let Some(clock) = self.clock {
+            database = database.with_clock(clock);
+        }
+        let aof = Aof::open(&self.config, &database)?;
+
+        let (replication, role) = match self.replica_of {
+            Some(master_address) => {
+                let repl_timeout = self.config.repl_timeout();
+                sync_to_master(master_address, &address, repl_timeout, database.clone()).await?
+            }
+            None => {
+                let replication = Replication {
+                    id: generate_random_sha1_hex(),
+                    offset: 0,
+                };
+                (replication, ServerRole::Master(vec![]))
+            }
+        };
+
+        let cluster = self
+            .config
+            .cluster_enabled
+            .then(|| Cluster::new(address.clone()));
+        let mut settings = Server::new(self.config, role, address, replication);
+        settings.aof = aof;
+        settings.cluster = cluster;
+
+        Ok((database, RedisServer::new(settings)))
+    }
 }
 
 pub async fn sync_to_master(
     master_address: Address,
     server_address: &Address,
+    timeout: Duration,
     database: data::Database,
 ) -> Result<(Replication, ServerRole), anyhow::Error> {
-    let mut connection = TcpStream::connect(master_address.name())
-        .await
-        .context("Failed to connect to master")?;
+    database.set_passive_expiry(true);
+
+    let link = Arc::new(MasterLink::new(
+        master_address,
+        server_address.port,
+        timeout,
+    ));
+    let connection = link.connect(&database).await?;
+    let replication = link
+        .replication()
+        .ok_or_else(|| anyhow::anyhow!("Master didn't send a replication id"))?;
+
+    let master_link = maintain_master_link(link.clone(), connection, database.clone());
+    database.tasks().spawn("master link", master_link);
+
+    Ok((replication, ServerRole::Slave(link)))
+}
 
+/// Applies the master's replication stream and, whenever the link drops, reconnects
+/// with an exponential backoff.
+async fn maintain_master_link(
+    link: Arc<MasterLink>,
+    mut connection: TcpStream,
+    database: data::Database,
+) {
+    loop {
+        link.up.store(true, Ordering::SeqCst);
+        if let Err(e) =
+            stream::handle_replica_stream(connection, database.clone(), link.clone()).await
+        {
+            eprintln!("Error handling stream: {}", e);
+        }
+        link.up.store(false, Ordering::SeqCst);
+        println!("Connection with master {} lost", link.master.name());
+
+        let mut delay = MIN_RECONNECT_DELAY;
+        connection = loop {
+            sleep(delay).await;
+            match link.connect(&database).await {
+                Ok(connection) => break connection,
+                Err(e) => {
+                    eprintln!(
+                        "Unable to reconnect to master {}: {}",
+                        link.master.name(),
+                        e
+                    );
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        };
+        println!("Reconnected to master {}", link.master.name());
+    }
+}
+
+async fn handshake(connection: &mut TcpStream, listening_port: u16) -> Result<(), anyhow::Error> {
     let ping = encoding::encode_string_array(&["ping"]);
     connection.write_all(ping.as_bytes()).await?;
 
@@ -307,11 +1020,8 @@ pub async fn sync_to_master(
         anyhow::bail!("Received unexpected response: {}", response);
     }
 
-    let repl_conf = encoding::encode_string_array(&[
-        "REPLCONF",
-        "listening-port",
-        &server_address.port.to_string(),
-    ]);
+    let repl_conf =
+        encoding::encode_string_array(&["REPLCONF", "listening-port", &listening_port.to_string()]);
     connection.write_all(repl_conf.as_bytes()).await?;
 
     let bytes_read = connection.read(&mut bytes).await?;
@@ -327,82 +1037,45 @@ pub async fn sync_to_master(
         anyhow::bail!("Failed to set psync2 capability");
     }
 
-    let psync = encoding::encode_string_array(&["PSYNC", "?", "-1"]);
-    connection.write_all(psync.as_bytes()).await?;
+    Ok(())
+}
 
-    let mut header = vec![0; 11];
-    connection
-        .read(&mut header)
+/// Reads the RDB that follows a FULLRESYNC and replaces the dataset with it.
+async fn load_master_snapshot(
+    connection: &mut TcpStream,
+    database: &data::Database,
+) -> Result<(), anyhow::Error> {
+    let size = read_line(connection)
         .await
-        .context("Reading header from response")?;
-    let header = String::from_utf8(header.to_vec())?;
-
-    if header != "+FULLRESYNC" {
-        anyhow::bail!("Unexpected response header: {:?}", header);
-    }
+        .context("Reading the RDB size")?;
+    let size: usize = str::parse(size[1..].trim())?;
 
-    let mut id = vec![0; 41];
+    let mut rdb = vec![0; size];
     connection
-        .read(&mut id)
+        .read_exact(&mut rdb)
         .await
-        .context("Reading master replica id from response")?;
-
-    let id = String::from_utf8(id[1..].to_vec())?;
-
-    let mut offset = vec![0; 2];
-    let _ = connection.read(&mut offset).await?;
-
-    let offset = String::from_utf8(offset[1..].to_vec())?;
-    let offset = str::parse(&offset).context("Parsing offset into number")?;
-
-    let mut crlf = vec![0; 2];
-    let _ = connection.read(&mut crlf).await?;
-    if crlf != b"\r\n" {
-        anyhow::bail!("Expected CRLF after initial psync response");
-    }
+        .context("Reading the RDB sent by the master")?;
 
-    let replication = Replication {
-        id: id.to_string(),
-        offset,
-    };
+    let snapshot = data::Database::from_rdb(&mut Cursor::new(rdb))
+        .context("Loading the RDB sent by the master")?;
+    database.replace_with(&snapshot)
+}
 
-    let mut size: Vec<u8> = vec![];
-    let mut next_byte = vec![0; 1];
+/// Reads a single CRLF terminated line, leaving whatever follows it unread.
+async fn read_line(connection: &mut TcpStream) -> Result<String, anyhow::Error> {
+    let mut line: Vec<u8> = vec![];
 
     loop {
-        let _ = connection.read(&mut next_byte).await?;
-
-        let byte_read = *next_byte
-            .first()
-            .ok_or_else(|| anyhow::anyhow!("Expected byte"))?;
-
-        if byte_read == b'\n' {
+        let byte = connection.read_u8().await?;
+        if byte == b'\n' {
             break;
         }
 
-        size.push(byte_read);
+        line.push(byte);
     }
 
-    let size = String::from_utf8(size)?;
-    let size: usize = str::parse(size[1..].trim())?;
-
-    let mut rdb = vec![0; size];
-    let _bytes_read = connection.read(&mut rdb).await?;
-
-    // TODO: Parse RDB
-
-    let role = ServerRole::Slave;
-
-    tokio::spawn(async move {
-        match stream::handle_replica_stream(connection, database).await {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error handling stream: {}", e);
-            }
-        }
-    });
-
-    Ok((replication, role))
+    let line = String::from_utf8(line)?;
+    Ok(line.trim_end_matches('\r').to_string())
 }
 
 pub fn generate_random_sha1_hex() -> String {
@@ -416,29 +1089,20 @@ pub fn generate_random_sha1_hex() -> String {
     hex::encode(hash_bytes)
 }
 
-fn get_config(args: &[String]) -> Result<Config, anyhow::Error> {
-    let dir_index = args.iter().position(|a| a == "--dir");
-    let dir = match dir_index {
-        None => None,
-        Some(index) => {
-            let dir = args
-                .get(index + 1)
-                .ok_or_else(|| anyhow::anyhow!("usage --dir <dirname>"))?;
-            Some(dir.to_string())
+/// Like redis, the AOF takes priority over the RDB file when it's enabled
+/// since it's the more up to date of the two.
+pub fn load_database(config: &Config) -> Result<data::Database, anyhow::Error> {
+    if config.append_only {
+        if let Some(database) = aof::load(config)? {
+            return Ok(database);
         }
-    };
-
-    let file_name_index = args.iter().position(|a| a == "--dbfilename");
-    let db_file_name = match file_name_index {
-        None => None,
-        Some(index) => {
-            let file_name = args
-                .get(index + 1)
-                .ok_or_else(|| anyhow::anyhow!("usage --dbfilename <file_name>"))?;
-            Some(file_name.to_string())
-        }
-    };
+    }
 
-    let config = Config { dir, db_file_name };
-    Ok(config)
+    match (&config.dir, &config.db_file_name) {
+        (Some(dir), Some(file_name)) => {
+            let path = PathBuf::from(dir).join(file_name);
+            data::Database::from_config(path)
+        }
+        _ => Ok(data::Database::new()),
+    }
 }
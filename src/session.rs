@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::ratelimit::RateLimiter;
+use crate::request::Command;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Something the server sends to a connection without it having asked,
+/// such as a pub/sub message.
+#[derive(Debug)]
+pub enum Push {
+    /// Written to the client as is.
+    Message(Vec<u8>),
+    /// Closes the connection, e.g. for CLIENT KILL.
+    Close,
+}
+
+/// Everything the server knows about a single client connection. It lives for as
+/// long as the connection does and is handed to every command it runs.
+#[derive(Debug)]
+pub struct Session {
+    /// Unique for the lifetime of the process, as reported by CLIENT ID.
+    pub id: u64,
+    /// Set with CLIENT SETNAME.
+    pub name: Option<String>,
+    /// The database picked with SELECT.
+    pub db: usize,
+    /// The namespace picked with SELECTNS, `None` for the default one.
+    pub namespace: Option<String>,
+    /// The user the connection authenticated as.
+    pub user: String,
+    /// Whether the client has sent the right password with AUTH.
+    pub authenticated: bool,
+    /// Commands queued after MULTI, waiting for EXEC. `None` outside of a transaction.
+    pub transaction: Option<Vec<Command>>,
+    /// Keys passed to WATCH, checked when the transaction is executed.
+    pub watched_keys: HashSet<String>,
+    /// Channels the client subscribed to.
+    pub subscriptions: HashSet<String>,
+    /// The RESP version negotiated with HELLO.
+    pub protocol: u8,
+    /// The port a replica announced with REPLCONF listening-port.
+    pub listening_port: Option<u16>,
+    /// The offset a replica was synced to by PSYNC.
+    pub synced_offset: u64,
+    /// Set by ASKING, lets the next command use a slot this node is importing.
+    pub asking: bool,
+    /// What the client has used of its client-max-* allowances.
+    pub rate_limiter: RateLimiter,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst),
+            name: None,
+            db: 0,
+            namespace: None,
+            user: "default".to_string(),
+            authenticated: false,
+            transaction: None,
+            watched_keys: HashSet::new(),
+            subscriptions: HashSet::new(),
+            protocol: 2,
+            listening_port: None,
+            synced_offset: 0,
+            asking: false,
+            rate_limiter: RateLimiter::new(),
+        }
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_session_gets_its_own_id() {
+        let first = Session::new();
+        let second = Session::new();
+
+        assert_ne!(first.id, second.id);
+        assert_eq!(second.db, 0);
+        assert_eq!(second.protocol, 2);
+    }
+}
@@ -1,71 +1,352 @@
-use std::io::Cursor;
+use std::sync::Arc;
 
 use anyhow::Context;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use bytes::BytesMut;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
 use tokio::net::TcpStream;
-use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{MutexGuard, Notify};
+use tokio::time::{interval, sleep, sleep_until, Duration, Instant, MissedTickBehavior};
 
-use crate::{commands, data, encoding, request, server, transmission, utils};
+use crate::aof::{Aof, AofState};
+use crate::connection::FrameReader;
+use crate::errors::RedisError;
+use crate::ratelimit::{self, RateLimitPolicy};
+use crate::resp::Value;
+use crate::session::{Push, Session};
+use crate::{cluster, commands, data, errors, extension, propagation, request, server};
+
+const REPLICA_ACK_PERIOD: Duration = Duration::from_secs(1);
+// Once this much is waiting to be written it's sent on, rather than letting a
+// pipeline of large replies pile up in memory.
+const MAX_BUFFERED_REPLY: usize = 16 * 1024;
 
 #[derive(PartialEq, Debug)]
 enum CommandType {
     Psync,
+    Shutdown,
     ToReplicate,
     Other,
 }
 
 pub async fn handle_stream(
-    mut stream: TcpStream,
+    stream: TcpStream,
     database: data::Database,
     server: server::RedisServer,
-    sender: Sender<transmission::Transmission>,
 ) -> Result<(), anyhow::Error> {
-    let mut buf = [0; 512];
+    let session = Session::new();
+    let id = session.id;
+    let pushes = server.register_client(id).await;
 
-    loop {
-        let bytes_read = stream.read(&mut buf).await?;
-        let command = &buf[..bytes_read];
+    let result = serve_client(stream, session, pushes, database, server.clone()).await;
+    server.unregister_client(id).await;
 
-        if bytes_read == 0 {
-            return Ok(());
-        }
+    result
+}
 
-        let raw_request = String::from_utf8(command.to_vec())?
-            .lines()
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+async fn serve_client(
+    stream: TcpStream,
+    mut session: Session,
+    mut pushes: UnboundedReceiver<Push>,
+    database: data::Database,
+    server: server::RedisServer,
+) -> Result<(), anyhow::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let limits = server.read().await.config.frame_limits();
+    let mut connection = FrameReader::with_limits(reader, limits);
+    let aof = server.read().await.aof.clone();
+    // Replies to pipelined commands are held back until every command that arrived
+    // with them has run, then written in one go.
+    let mut replies = BytesMut::new();
 
-        let request = match request::parse_request(raw_request) {
+    loop {
+        let frame = match connection.buffered_frame() {
+            Ok(frame) => frame,
+            Err(e) => return reject_frame(&mut writer, &mut replies, e).await,
+        };
+        let frame = match frame {
+            Some(frame) => frame,
+            None => {
+                flush_replies(&mut writer, &mut replies).await?;
+                tokio::select! {
+                    frame = connection.read_frame() => match frame {
+                        Ok(None) => return Ok(()),
+                        Ok(Some(frame)) => frame,
+                        Err(e) => return reject_frame(&mut writer, &mut replies, e).await,
+                    },
+                    Some(push) = pushes.recv() => {
+                        match push {
+                            Push::Message(message) => write_to_stream(&mut writer, &message).await?,
+                            Push::Close => return Ok(()),
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        let command = &frame.raw[..];
+
+        let request = match request::parse_request(frame.data)
+            .map(|request| propagation::pin(request, database.now()))
+        {
             Err(e) => {
-                let message = e.to_string();
-                write_to_stream(&mut stream, message.as_bytes()).await?;
+                e.to_value().encode_into(&mut replies);
                 continue;
             }
             Ok(v) => v,
         };
 
+        if let request::Command::ReplConf(request::ReplicationCommand::ListeningPort(port)) =
+            &request
+        {
+            session.listening_port = Some(*port);
+        }
+
+        // What a replica sends back over its link isn't rationed like a client's commands
+        let is_replication = matches!(
+            request,
+            request::Command::ReplConf(..) | request::Command::Psync(..)
+        );
+        let limits = server.rate_limits().await;
+        if limits.is_limited() && !is_replication {
+            let throttled = loop {
+                match session.rate_limiter.check(&limits, command.len()) {
+                    Ok(()) => break false,
+                    Err(_) if limits.policy == RateLimitPolicy::Reject => break true,
+                    Err(wait) => {
+                        flush_replies(&mut writer, &mut replies).await?;
+                        sleep(wait).await;
+                    }
+                }
+            };
+            if throttled {
+                ratelimit::throttled().to_value().encode_into(&mut replies);
+                continue;
+            }
+        }
+
+        let flags = request.spec().flags;
+        let is_write = flags.contains(request::CommandFlags::WRITE);
+
+        if !session.authenticated
+            && !flags.contains(request::CommandFlags::NO_AUTH)
+            && server.requires_auth().await
+        {
+            RedisError::custom("NOAUTH Authentication required.")
+                .to_value()
+                .encode_into(&mut replies);
+            continue;
+        }
+
+        // Only the default namespace is persisted and replicated
+        let database = match &session.namespace {
+            Some(name) => server.namespace(name, &database).await,
+            None => database.clone(),
+        }
+        .select(session.db);
+        let is_persisted = is_write && session.namespace.is_none();
+        if is_write && session.namespace.is_some() {
+            let limits = server.namespace_limits().await;
+            if let Err(e) = limits.check(&database, &request) {
+                e.to_value().encode_into(&mut replies);
+                continue;
+            }
+        }
+        if is_persisted && !request.only_removes_keys() {
+            let max_memory = server.max_memory().await;
+            if max_memory > 0 && database.dataset_memory() as u64 > max_memory {
+                RedisError::custom("OOM command not allowed when used memory > 'maxmemory'.")
+                    .to_value()
+                    .encode_into(&mut replies);
+                continue;
+            }
+        }
+
+        if is_write && server.is_read_only().await {
+            RedisError::Readonly.to_value().encode_into(&mut replies);
+            continue;
+        }
+
+        if !flags.contains(request::CommandFlags::STALE) && server.is_master_down().await {
+            RedisError::MasterDown.to_value().encode_into(&mut replies);
+            continue;
+        }
+
+        let asking = std::mem::take(&mut session.asking);
+        if let Err(e) = check_cluster_slot(&server, &database, &request, asking).await {
+            e.to_value().encode_into(&mut replies);
+            continue;
+        }
+        if let Err(e) = check_db_indexes(&server, &request).await {
+            e.to_value().encode_into(&mut replies);
+            continue;
+        }
+        let keys = request.keys();
+        database.hot_keys().record(&keys);
+        // Only reads count as accessing a key, and looking at how a key is stored
+        // shouldn't make it look busy
+        if flags.contains(request::CommandFlags::READONLY)
+            && !matches!(request, request::Command::Object(..))
+        {
+            database.touch(&keys);
+        }
+
         let command_type = match &request {
-            request::Command::Get(_) | request::Command::Set(..) => CommandType::ToReplicate,
+            _ if is_persisted => CommandType::ToReplicate,
             request::Command::Psync(..) => CommandType::Psync,
+            request::Command::Shutdown(..) => CommandType::Shutdown,
             _ => CommandType::Other,
         };
 
-        let sender = sender.clone();
-        let receiver = sender.subscribe();
+        // Don't hold back the replies to commands pipelined ahead of one that may wait a while
+        if flags.contains(request::CommandFlags::BLOCKING) {
+            flush_replies(&mut writer, &mut replies).await?;
+        }
 
+        let blocked_on = match &request {
+            request::Command::BLPop(keys, timeout) | request::Command::BRPop(keys, timeout) => {
+                Some((keys.as_slice(), *timeout))
+            }
+            request::Command::BLMPop(command, timeout) => Some((command.keys.as_slice(), *timeout)),
+            request::Command::BLMove(command, timeout) => {
+                Some((std::slice::from_ref(&command.source), *timeout))
+            }
+            _ => None,
+        };
+        let aof_state = match blocked_on {
+            // A client that's gone by the time a list turns up mustn't take a value
+            // off it, as there'd be nobody to give it to
+            Some((keys, timeout)) => tokio::select! {
+                aof_state = wait_for_list(&database, &aof, is_persisted, keys, timeout) => aof_state,
+                result = wait_for_disconnect(&mut connection, &mut pushes, &mut writer) => {
+                    return result;
+                }
+            },
+            None if is_persisted => Ok(Some(aof.lock().await)),
+            None => Ok(None),
+        };
+        let mut aof_state = match aof_state {
+            Ok(aof_state) => aof_state,
+            Err(e) => {
+                e.to_value().encode_into(&mut replies);
+                continue;
+            }
+        };
+
+        let rewrite = match is_write {
+            true => propagation::Rewrite::of(&request),
+            false => propagation::Rewrite::Verbatim,
+        };
+        // The snapshot a full resync sends after its reply
+        let mut snapshot = None;
         let command_responses = match request {
             request::Command::Ping(body) => commands::pong(body),
             request::Command::Echo(body) => commands::echo_response(body),
             request::Command::Get(key) => commands::get_value(&database, key),
-            request::Command::Set(set_command) => commands::set_value(&database, set_command),
-            request::Command::Del(keys) => commands::delete_keys(&database, keys),
-            request::Command::GetDel(key) => commands::get_delete_key(&database, key),
-            request::Command::GetEx(key, expiry) => {
-                commands::update_expiration(&database, key, expiry)
+            request::Command::MGet(keys) => commands::get_multiple(&database, keys),
+            request::Command::Exists(keys) => commands::count_existing(&database, keys),
+            request::Command::RandomKey => commands::random_key(&database),
+            request::Command::Touch(keys) => commands::touch(&database, keys),
+            request::Command::Dump(key) => commands::dump(&database, key),
+            request::Command::GetRange(key, start, end) => {
+                commands::get_range(&database, key, start, end)
+            }
+            request::Command::LLen(key) => commands::list_len(&database, key),
+            request::Command::LRange(key, start, end) => {
+                commands::list_range(&database, key, start, end)
             }
-            request::Command::Info => commands::get_info(&server).await,
+            request::Command::HGet(key, field) => commands::get_hash_field(&database, key, field),
+            request::Command::HGetAll(key) => commands::get_all_hash_fields(&database, key),
+            request::Command::HExists(key, field) => {
+                commands::hash_field_exists(&database, key, field)
+            }
+            request::Command::HLen(key) => commands::hash_len(&database, key),
+            request::Command::HKeys(key) => commands::hash_keys(&database, key),
+            request::Command::HVals(key) => commands::hash_values(&database, key),
+            request::Command::HMGet(key, fields) => {
+                commands::get_hash_fields(&database, key, fields)
+            }
+            request::Command::Ttl(key) => commands::time_to_live(&database, key, 1000),
+            request::Command::PTtl(key) => commands::time_to_live(&database, key, 1),
+            request @ (request::Command::Set(..)
+            | request::Command::MSet(..)
+            | request::Command::MSetNx(..)
+            | request::Command::Del(..)
+            | request::Command::Unlink(..)
+            | request::Command::FlushDb(..)
+            | request::Command::FlushAll(..)
+            | request::Command::SwapDb(..)
+            | request::Command::Move(..)
+            | request::Command::GetDel(..)
+            | request::Command::GetEx(..)
+            | request::Command::Expire(..)
+            | request::Command::Persist(..)
+            | request::Command::Xadd(..)
+            | request::Command::Append(..)
+            | request::Command::SetRange(..)
+            | request::Command::Incr(..)
+            | request::Command::IncrBy(..)
+            | request::Command::IncrByFloat(..)
+            | request::Command::Decr(..)
+            | request::Command::DecrBy(..)
+            | request::Command::LPush(..)
+            | request::Command::RPush(..)
+            | request::Command::LPop(..)
+            | request::Command::RPop(..)
+            | request::Command::BLPop(..)
+            | request::Command::BRPop(..)
+            | request::Command::LMove(..)
+            | request::Command::BLMove(..)
+            | request::Command::LMPop(..)
+            | request::Command::BLMPop(..)
+            | request::Command::LInsert(..)
+            | request::Command::LSet(..)
+            | request::Command::LRem(..)
+            | request::Command::LTrim(..)
+            | request::Command::HSet(..)
+            | request::Command::HDel(..)
+            | request::Command::Restore(..)
+            | request::Command::RestoreAsking(..)
+            | request::Command::Import(..)
+            | request::Command::JsonSet(..)
+            | request::Command::JsonDel(..)
+            | request::Command::JsonNumIncrBy(..)
+            | request::Command::JsonArrAppend(..)
+            | request::Command::BfReserve(..)
+            | request::Command::BfAdd(..)
+            | request::Command::BfMAdd(..)
+            | request::Command::CfReserve(..)
+            | request::Command::CfAdd(..)
+            | request::Command::CfAddNx(..)
+            | request::Command::CfDel(..)
+            | request::Command::CmsInitByDim(..)
+            | request::Command::CmsInitByProb(..)
+            | request::Command::CmsIncrBy(..)
+            | request::Command::CmsMerge(..)
+            | request::Command::TopkReserve(..)
+            | request::Command::TopkAdd(..)
+            | request::Command::TsAdd(..)) => apply_write(&database, request),
+            request @ request::Command::Extension(..) if is_write => {
+                apply_write(&database, request)
+            }
+            request::Command::Extension(name, args) => extension::call(&database, name, args),
+            request::Command::Help(name) => commands::help(name),
+            request::Command::Info => commands::get_info(&server, &database).await,
             request::Command::ReplConf(repl) => commands::replica_confirm(repl, 0),
-            request::Command::Psync(..) => commands::perform_psync(&server).await,
+            request::Command::Psync(replication_id, offset) => {
+                let replica = writer
+                    .peer_addr()
+                    .ok()
+                    .map(|peer| server::Address::of_replica(peer, session.listening_port));
+                commands::perform_psync(&server, &database, replication_id, offset, replica)
+                    .await
+                    .map(|(reply, rdb, offset)| {
+                        session.synced_offset = offset;
+                        snapshot = rdb;
+                        vec![reply]
+                    })
+            }
             request::Command::Wait(num_replicas, timeout) => {
                 commands::transmit_wait(&server, num_replicas, timeout).await
             }
@@ -74,105 +355,475 @@ pub async fn handle_stream(
             }
             request::Command::Keys(key_group) => commands::get_keys(&database, key_group),
             request::Command::Type(key) => commands::get_type(&database, key),
-            // TODO: Transmit stream to the replica.
-            request::Command::Xadd(command) => commands::add_stream(&database, command, sender),
+            request::Command::Object(command) => commands::inspect_object(&database, command),
             request::Command::Xrange(command) => commands::get_stream_range(&database, command),
-            request::Command::Xread(command) => {
-                commands::read_streams(&database, command, receiver).await
+            request::Command::Xread(command) => commands::read_streams(&database, command).await,
+            request::Command::Save => commands::save_database(&database, &server).await,
+            request::Command::BgSave => commands::background_save(&database, &server).await,
+            request::Command::LastSave => commands::last_save(&database),
+            request::Command::Export => commands::export_dataset(&database),
+            request::Command::HotKeys(count) => commands::hot_keys(&database, count),
+            request::Command::BgRewriteAof => {
+                commands::rewrite_append_only_file(&database, &server).await
             }
-            request::Command::Incr(key) => commands::increment_value_by_int(&database, key, 1),
-            request::Command::IncrBy(key, amount) => {
-                commands::increment_value_by_int(&database, key, amount)
+            request::Command::Shutdown(save) => commands::shutdown(&database, &server, save).await,
+            request::Command::Auth(user, password) => {
+                commands::authenticate(&server, &mut session, user, password).await
             }
-            request::Command::IncrByFloat(key, amount) => {
-                commands::increment_value_by_float(&database, key, amount)
+            request::Command::Cluster(command) => {
+                commands::cluster(&database, &server, command).await
             }
-            request::Command::Decr(key) => commands::increment_value_by_int(&database, key, -1),
-            request::Command::DecrBy(key, amount) => {
-                commands::increment_value_by_int(&database, key, -amount)
+            request::Command::Asking => commands::asking(&server, &mut session).await,
+            request::Command::Select(index) => {
+                commands::select_database(&server, &mut session, index).await
             }
-        }?;
+            request::Command::SelectNs(name) => commands::select_namespace(&mut session, name),
+            request::Command::Migrate(command) => {
+                commands::migrate(&database, &server, command).await
+            }
+            request::Command::JsonGet(key, paths) => commands::json_get(&database, key, paths),
+            request::Command::BfExists(key, item) => commands::bf_exists(&database, key, item),
+            request::Command::CfExists(key, item) => commands::cf_count(&database, key, item, true),
+            request::Command::CfCount(key, item) => commands::cf_count(&database, key, item, false),
+            request::Command::CmsQuery(key, items) => commands::cms_query(&database, key, items),
+            request::Command::TopkQuery(key, items) => {
+                commands::topk_query(&database, key, items, false)
+            }
+            request::Command::TopkCount(key, items) => {
+                commands::topk_query(&database, key, items, true)
+            }
+            request::Command::TopkList(key, with_counts) => {
+                commands::topk_list(&database, key, with_counts)
+            }
+            request::Command::TsGet(key) => commands::ts_get(&database, key),
+            request::Command::TsRange(command) => commands::ts_range(&database, command),
+            request::Command::TsMRange(command) => commands::ts_mrange(&database, command),
+        };
 
-        write_command_responses(&mut stream, command_responses).await?;
+        // A command that failed didn't change anything, so there's nothing to persist or replicate.
+        let command_responses = match command_responses {
+            Ok(command_responses) => command_responses,
+            Err(e) => {
+                e.to_value().encode_into(&mut replies);
+                continue;
+            }
+        };
+
+        let propagation = rewrite.encode(&command_responses);
+        let propagated = match &propagation {
+            propagation::Propagation::AsSent => Some(command),
+            propagation::Propagation::Rewritten(rewritten) => Some(rewritten.as_bytes()),
+            propagation::Propagation::Nothing => None,
+        };
+
+        if let (Some(aof_state), Some(command)) = (aof_state.as_mut(), propagated) {
+            aof_state.append(session.db, command)?;
+        }
+        drop(aof_state);
+
+        for response in command_responses {
+            queue_reply(&mut writer, &mut replies, response).await?;
+        }
 
         match command_type {
             CommandType::Other => continue,
-            CommandType::ToReplicate => server.replicate_command(command).await?,
+            // The connection is closed without a reply once the server is going down.
+            CommandType::Shutdown if server.is_shutting_down().await => {
+                flush_replies(&mut writer, &mut replies).await?;
+                return Ok(());
+            }
+            CommandType::Shutdown => continue,
+            CommandType::ToReplicate => {
+                if let Some(command) = propagated {
+                    server.replicate_command(session.db, command).await?
+                }
+            }
             CommandType::Psync => {
-                server.add_stream(stream).await;
+                flush_replies(&mut writer, &mut replies).await?;
+                if let Some(snapshot) = snapshot {
+                    write_to_stream(&mut writer, &snapshot).await?;
+                }
+                server
+                    .add_replica(
+                        connection.into_inner(),
+                        writer,
+                        session.listening_port,
+                        session.synced_offset,
+                    )
+                    .await?;
                 return Ok(());
             }
         }
     }
 }
 
-pub async fn handle_replica_stream(
-    mut stream: TcpStream,
-    database: data::Database,
-) -> Result<(), anyhow::Error> {
-    let mut buf = [0; 512];
-    let mut bytes_received: usize = 0;
+/// Waits until one of the keys a blocking pop or move is after holds a list, then takes the
+/// AOF lock if the pop is persisted. The lock isn't held while waiting, since whoever
+/// pushes to the list needs it, so if another client empties the list before the lock
+/// is taken it goes back to waiting. Once the timeout passes the lock is taken anyway,
+/// and the pop comes up empty.
+async fn wait_for_list<'a>(
+    database: &data::Database,
+    aof: &'a Aof,
+    is_persisted: bool,
+    keys: &[String],
+    timeout: Option<Duration>,
+) -> Result<Option<MutexGuard<'a, AofState>>, RedisError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let found = database.wait_for_list(keys, deadline).await?;
+        let aof_state = match is_persisted {
+            true => Some(aof.lock().await),
+            false => None,
+        };
+        if !found || database.has_list(keys)? {
+            return Ok(aof_state);
+        }
+    }
+}
 
+/// Resolves once the client has gone away, either closing the connection or being
+/// killed. Commands it pipelines meanwhile are buffered for after the one it's
+/// blocked on, and pushes are still delivered.
+async fn wait_for_disconnect(
+    connection: &mut FrameReader<OwnedReadHalf>,
+    pushes: &mut UnboundedReceiver<Push>,
+    writer: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), anyhow::Error> {
     loop {
-        let bytes_read = stream.read(&mut buf).await?;
-        let command = &buf[..bytes_read];
+        tokio::select! {
+            filled = connection.fill_buffer() => {
+                if !filled.unwrap_or(false) {
+                    return Ok(());
+                }
+            }
+            Some(push) = pushes.recv() => match push {
+                Push::Message(message) => write_to_stream(writer, &message).await?,
+                Push::Close => return Ok(()),
+            },
+        }
+    }
+}
+
+/// In cluster mode a command's keys have to share a slot, and the slot has to
+/// be served here. Otherwise the client is told where to go like redis does.
+async fn check_cluster_slot(
+    server: &server::RedisServer,
+    database: &data::Database,
+    request: &request::Command,
+    asking: bool,
+) -> Result<(), RedisError> {
+    let server = server.read().await;
+    let Some(cluster) = server.cluster.as_ref() else {
+        return Ok(());
+    };
+
+    let keys = request.keys();
+    let Some(first) = keys.first() else {
+        return Ok(());
+    };
+    cluster::check_same_slot(&keys)?;
 
-        if bytes_read == 0 {
-            return Ok(());
+    // MIGRATE moves whichever of the keys are still here, and RESTORE-ASKING
+    // is what MIGRATE sends to a node importing the slot.
+    let asking = asking || matches!(request, request::Command::RestoreAsking(..));
+    let keys_missing = !matches!(request, request::Command::Migrate(..))
+        && keys.iter().any(|key| !database.exists(key));
+    cluster.redirect(
+        cluster::key_hash_slot(first.as_bytes()),
+        keys_missing,
+        asking,
+    )
+}
+
+/// SWAPDB and MOVE name databases like SELECT does, so they're held to the same
+/// limits. The replication stream and the AOF only have commands that passed.
+async fn check_db_indexes(
+    server: &server::RedisServer,
+    request: &request::Command,
+) -> Result<(), RedisError> {
+    let indexes = match request {
+        request::Command::SwapDb(first, second) => vec![*first, *second],
+        request::Command::Move(_, index) => vec![*index],
+        _ => return Ok(()),
+    };
+
+    let server = server.read().await;
+    if server.cluster.is_some() {
+        return Err(RedisError::custom(format!(
+            "ERR {} is not allowed in cluster mode",
+            request.name().to_ascii_uppercase()
+        )));
+    }
+    if indexes
+        .iter()
+        .any(|index| *index >= server.config.databases)
+    {
+        return Err(RedisError::custom("ERR DB index is out of range"));
+    }
+
+    Ok(())
+}
+
+/// Applies a command that changes the dataset. Clients and the replication stream
+/// both go through here so a replica ends up with exactly what its master has.
+fn apply_write(
+    database: &data::Database,
+    request: request::Command,
+) -> Result<Vec<Value>, RedisError> {
+    match request {
+        request::Command::Set(set_command) => commands::set_value(database, set_command),
+        request::Command::MSet(pairs) => commands::set_multiple(database, pairs),
+        request::Command::MSetNx(pairs) => commands::set_multiple_if_none_exist(database, pairs),
+        request::Command::Del(keys) => commands::delete_keys(database, keys),
+        request::Command::Unlink(keys) => commands::unlink_keys(database, keys),
+        request::Command::FlushDb(asynchronously) => commands::flush(database, asynchronously),
+        request::Command::FlushAll(asynchronously) => commands::flush_all(database, asynchronously),
+        request::Command::SwapDb(first, second) => {
+            commands::swap_databases(database, first, second)
+        }
+        request::Command::Move(key, index) => commands::move_key(database, key, index),
+        request::Command::GetDel(key) => commands::get_delete_key(database, key),
+        request::Command::GetEx(key, expiry) => commands::update_expiration(database, key, expiry),
+        request::Command::Expire(key, expiration, condition) => {
+            commands::expire(database, key, expiration, condition)
+        }
+        request::Command::Persist(key) => commands::persist(database, key),
+        request::Command::Xadd(command) => commands::add_stream(database, command),
+        request::Command::Append(key, value) => commands::append_value(database, key, value),
+        request::Command::SetRange(key, offset, value) => {
+            commands::set_range(database, key, offset, value)
+        }
+        request::Command::Incr(key) => commands::increment_value_by_int(database, key, 1),
+        request::Command::IncrBy(key, amount) => {
+            commands::increment_value_by_int(database, key, amount)
+        }
+        request::Command::IncrByFloat(key, amount) => {
+            commands::increment_value_by_float(database, key, amount)
+        }
+        request::Command::Decr(key) => commands::increment_value_by_int(database, key, -1),
+        request::Command::DecrBy(key, amount) => {
+            commands::increment_value_by_int(database, key, -amount)
+        }
+        request::Command::LPush(key, values) => {
+            commands::push(database, key, values, request::ListEnd::Left)
+        }
+        request::Command::RPush(key, values) => {
+            commands::push(database, key, values, request::ListEnd::Right)
+        }
+        request::Command::LPop(key, count) => {
+            commands::pop(database, key, count, request::ListEnd::Left)
+        }
+        request::Command::RPop(key, count) => {
+            commands::pop(database, key, count, request::ListEnd::Right)
+        }
+        request::Command::BLPop(keys, _) => {
+            commands::blocking_pop(database, keys, request::ListEnd::Left)
+        }
+        request::Command::BRPop(keys, _) => {
+            commands::blocking_pop(database, keys, request::ListEnd::Right)
+        }
+        request::Command::LMove(command) | request::Command::BLMove(command, _) => {
+            commands::move_list_value(database, command)
+        }
+        request::Command::LMPop(command) | request::Command::BLMPop(command, _) => {
+            commands::pop_multiple(database, command)
+        }
+        request::Command::LInsert(key, position, pivot, value) => {
+            commands::insert_into_list(database, key, position, pivot, value)
+        }
+        request::Command::LSet(key, index, value) => {
+            commands::set_list_index(database, key, index, value)
+        }
+        request::Command::LRem(key, count, value) => {
+            commands::remove_from_list(database, key, count, value)
+        }
+        request::Command::LTrim(key, start, end) => commands::trim_list(database, key, start, end),
+        request::Command::HSet(key, pairs) => commands::set_hash_fields(database, key, pairs),
+        request::Command::HDel(key, fields) => commands::delete_hash_fields(database, key, fields),
+        request::Command::Restore(command) | request::Command::RestoreAsking(command) => {
+            commands::restore(database, command)
+        }
+        request::Command::Import(export, replace) => {
+            commands::import_dataset(database, export, replace)
+        }
+        request::Command::JsonSet(command) => commands::json_set(database, command),
+        request::Command::JsonDel(key, path) => commands::json_delete(database, key, path),
+        request::Command::JsonNumIncrBy(key, path, increment) => {
+            commands::json_increment(database, key, path, increment)
+        }
+        request::Command::JsonArrAppend(key, path, values) => {
+            commands::json_append(database, key, path, values)
+        }
+        request::Command::BfReserve(command) => commands::bf_reserve(database, command),
+        request::Command::BfAdd(key, item) => commands::bf_add(database, key, item),
+        request::Command::BfMAdd(key, items) => commands::bf_madd(database, key, items),
+        request::Command::CfReserve(command) => commands::cf_reserve(database, command),
+        request::Command::CfAdd(key, item) => commands::cf_add(database, key, item, false),
+        request::Command::CfAddNx(key, item) => commands::cf_add(database, key, item, true),
+        request::Command::CfDel(key, item) => commands::cf_delete(database, key, item),
+        request::Command::CmsInitByDim(key, width, depth)
+        | request::Command::CmsInitByProb(key, width, depth) => {
+            commands::cms_init(database, key, width, depth)
+        }
+        request::Command::CmsIncrBy(key, increments) => {
+            commands::cms_increment(database, key, increments)
         }
+        request::Command::CmsMerge(command) => commands::cms_merge(database, command),
+        request::Command::TopkReserve(command) => commands::topk_reserve(database, command),
+        request::Command::TopkAdd(key, items) => commands::topk_add(database, key, items),
+        request::Command::TsAdd(command) => commands::ts_add(database, command),
+        request::Command::Extension(name, args) => extension::call(database, name, args),
+        request => Err(RedisError::custom(format!(
+            "{:?} doesn't change the dataset",
+            request
+        ))),
+    }
+}
 
-        let mut cursor = Cursor::new(command);
+pub async fn handle_replica_stream(
+    stream: TcpStream,
+    database: data::Database,
+    link: Arc<server::MasterLink>,
+) -> Result<(), anyhow::Error> {
+    let mut connection = FrameReader::new(stream);
+    // Offsets are counted from where the master was when we synced
+    let mut bytes_received = link.offset() as usize;
+    let mut selected = database.select(link.selected_db());
 
-        loop {
-            let frame = utils::read_frame(&mut cursor)?;
+    // Acknowledge the offset every second, not only when asked, so the master
+    // can keep track of how far behind we are.
+    let mut heartbeat = interval(REPLICA_ACK_PERIOD);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The master pings an idle link, so a link this quiet has died
+    let mut last_received = Instant::now();
 
-            let frame = match frame {
-                None => break,
+    loop {
+        let frame = tokio::select! {
+            frame = connection.read_frame() => match frame? {
+                None => return Ok(()),
                 Some(frame) => frame,
-            };
+            },
+            _ = sleep_until(last_received + link.timeout()) => {
+                anyhow::bail!("Timeout receiving data from master {}", link.master().name());
+            }
+            _ = heartbeat.tick() => {
+                let ack = Value::bulk_array(&["REPLCONF", "ACK", &bytes_received.to_string()]);
+                write_to_stream(connection.get_mut(), &ack.encode()).await?;
+                continue;
+            }
+        };
 
-            let request = request::parse_request(frame.data)?;
+        last_received = Instant::now();
+        let request = request::parse_request(frame.data)?;
 
-            match request {
-                request::Command::Get(key) => commands::get_value(&database, key).map(|_| ()),
-                request::Command::Set(command) => {
-                    commands::set_value(&database, command).map(|_| ())
-                }
-                request::Command::Wait(..) => {
-                    let response = encoding::okay_string().as_bytes().to_vec();
-                    let response = vec![response];
+        match request {
+            request::Command::Select(index) => {
+                link.select_db(index);
+                selected = database.select(index);
+                Ok(())
+            }
+            request if request.is_write() => apply_write(&selected, request).map(|_| ()),
+            request::Command::Wait(..) => {
+                write_command_responses(connection.get_mut(), vec![Value::ok()]).await?;
+                Ok(())
+            }
+            request::Command::ReplConf(command)
+                if command == request::ReplicationCommand::GetAck =>
+            {
+                let command_responses = commands::replica_confirm(command, bytes_received)?;
+                write_command_responses(connection.get_mut(), command_responses).await?;
 
-                    write_command_responses(&mut stream, response).await?;
-                    Ok(())
-                }
-                request::Command::ReplConf(command)
-                    if command == request::ReplicationCommand::Ack =>
-                {
-                    let command_responses = commands::replica_confirm(command, bytes_received)?;
-                    write_command_responses(&mut stream, command_responses).await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }?;
 
-                    Ok(())
-                }
-                _ => Ok(()),
-            }?;
+        bytes_received += frame.bytes_processed;
+        link.set_offset(bytes_received as u64);
+    }
+}
 
-            bytes_received += frame.bytes_processed
+/// Reads the REPLCONF ACKs a replica sends back over its replication link and
+/// records the latest offset it has processed.
+pub async fn handle_replica_acks(
+    stream: OwnedReadHalf,
+    ack: Arc<server::ReplicaAck>,
+    acks: Arc<Notify>,
+) -> Result<(), anyhow::Error> {
+    let mut connection = FrameReader::new(stream);
+
+    while let Some(frame) = connection.read_frame().await? {
+        if let request::Command::ReplConf(request::ReplicationCommand::Ack(offset)) =
+            request::parse_request(frame.data)?
+        {
+            ack.record(offset);
+            acks.notify_waiters();
         }
     }
+
+    Ok(())
 }
 
-async fn write_command_responses(
-    stream: &mut TcpStream,
-    command_responses: Vec<Vec<u8>>,
+async fn write_command_responses<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    command_responses: Vec<Value>,
 ) -> Result<(), anyhow::Error> {
     for response in command_responses {
-        write_to_stream(stream, response.as_slice()).await?;
+        write_to_stream(stream, &response.encode()).await?;
+    }
+
+    Ok(())
+}
+
+/// Tells the client why its request was rejected before the connection is closed,
+/// since there's no telling where the next command would start.
+async fn reject_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    replies: &mut BytesMut,
+    error: anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    let Some(error) = error.downcast_ref::<errors::ProtocolError>() else {
+        return Err(error);
+    };
+
+    Value::error(format!("ERR {}", error)).encode_into(replies);
+    flush_replies(stream, replies).await
+}
+
+/// Encodes a reply onto the ones waiting to be written, writing them all out once
+/// enough has built up.
+async fn queue_reply<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    replies: &mut BytesMut,
+    reply: Value,
+) -> Result<(), anyhow::Error> {
+    reply.encode_into(replies);
+    if replies.len() < MAX_BUFFERED_REPLY {
+        return Ok(());
+    }
+
+    flush_replies(stream, replies).await
+}
+
+async fn flush_replies<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    replies: &mut BytesMut,
+) -> Result<(), anyhow::Error> {
+    if replies.is_empty() {
+        return Ok(());
     }
 
+    write_to_stream(stream, replies).await?;
+    replies.clear();
+
     Ok(())
 }
 
-async fn write_to_stream(stream: &mut TcpStream, message: &[u8]) -> Result<(), anyhow::Error> {
+async fn write_to_stream<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    message: &[u8],
+) -> Result<(), anyhow::Error> {
     stream
         .write_all(message)
         .await
@@ -0,0 +1,90 @@
+use std::env;
+use std::os::unix::net::UnixDatagram;
+
+use anyhow::Context;
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Supervised {
+    #[default]
+    No,
+    Systemd,
+}
+
+impl Supervised {
+    pub fn parse(value: &str) -> Result<Self, anyhow::Error> {
+        match value.to_ascii_lowercase().as_str() {
+            "no" => Ok(Self::No),
+            "systemd" => Ok(Self::Systemd),
+            // Redis falls back to systemd detection for `auto`. The only supervisor
+            // we know how to talk to is systemd, so we check for its socket.
+            "auto" => match env::var_os(NOTIFY_SOCKET) {
+                Some(_) => Ok(Self::Systemd),
+                None => Ok(Self::No),
+            },
+            other => anyhow::bail!("usage --supervised <no | systemd | auto>, got {}", other),
+        }
+    }
+}
+
+pub fn notify_ready() -> Result<(), anyhow::Error> {
+    notify("READY=1\nSTATUS=Ready to accept connections\n")
+}
+
+pub fn notify_stopping() -> Result<(), anyhow::Error> {
+    notify("STOPPING=1\n")
+}
+
+// https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html
+// If the unit was not started with Type=notify there is no socket,
+// and notifying is a no-op just like it is for sd_notify.
+fn notify(state: &str) -> Result<(), anyhow::Error> {
+    let socket_path = match env::var(NOTIFY_SOCKET) {
+        Ok(path) if !path.is_empty() => path,
+        _ => return Ok(()),
+    };
+
+    let socket = UnixDatagram::unbound().context("Creating systemd notify socket")?;
+
+    match socket_path.strip_prefix('@') {
+        Some(abstract_name) => send_to_abstract(&socket, abstract_name, state),
+        None => socket
+            .send_to(state.as_bytes(), &socket_path)
+            .map(|_| ())
+            .with_context(|| format!("Sending notification to {}", socket_path)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_to_abstract(socket: &UnixDatagram, name: &str, state: &str) -> Result<(), anyhow::Error> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket
+        .send_to_addr(state.as_bytes(), &address)
+        .with_context(|| format!("Sending notification to @{}", name))?;
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_to_abstract(_socket: &UnixDatagram, name: &str, _state: &str) -> Result<(), anyhow::Error> {
+    anyhow::bail!(
+        "Abstract notify socket @{} is only supported on linux",
+        name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_supervised_values() {
+        assert_eq!(Supervised::parse("no").unwrap(), Supervised::No);
+        assert_eq!(Supervised::parse("SYSTEMD").unwrap(), Supervised::Systemd);
+        assert!(Supervised::parse("upstart").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::task::{AbortHandle, JoinSet};
+
+/// Keeps track of the background tasks spawned on behalf of the server, like expiry
+/// timers, replica links and saves, so they can all be stopped on shutdown. A task
+/// that panics is logged under its name instead of disappearing without a trace.
+#[derive(Debug, Clone, Default)]
+pub struct TaskSupervisor(Arc<Mutex<JoinSet<()>>>);
+
+impl TaskSupervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn<F>(&self, name: &'static str, task: F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.0.lock().unwrap();
+        reap(&mut tasks);
+        tasks.spawn(Supervised {
+            name,
+            task: Box::pin(task),
+        })
+    }
+
+    pub fn spawn_blocking<F>(&self, name: &'static str, task: F) -> AbortHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut tasks = self.0.lock().unwrap();
+        reap(&mut tasks);
+        tasks.spawn_blocking(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(task)) {
+                log_panic(name, payload.as_ref());
+            }
+        })
+    }
+
+    /// The number of tasks that haven't finished yet.
+    pub fn len(&self) -> usize {
+        let mut tasks = self.0.lock().unwrap();
+        reap(&mut tasks);
+        tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops every task. Blocking tasks that have already started run to completion.
+    pub fn abort_all(&self) {
+        self.0.lock().unwrap().abort_all();
+    }
+}
+
+// Finished tasks stay in the set until they're joined, so clear them out as we go
+fn reap(tasks: &mut JoinSet<()>) {
+    while tasks.try_join_next().is_some() {}
+}
+
+/// Runs a task, catching a panic so it can be logged along with the task's name.
+struct Supervised<F> {
+    name: &'static str,
+    task: Pin<Box<F>>,
+}
+
+impl<F: Future<Output = ()>> Future for Supervised<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let name = self.name;
+        match panic::catch_unwind(AssertUnwindSafe(|| self.task.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                log_panic(name, payload.as_ref());
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+fn log_panic(name: &str, payload: &(dyn Any + Send)) {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    eprintln!("Background task {} panicked: {}", name, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{sleep, Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn tasks_are_tracked_until_they_finish_or_are_aborted() {
+        let tasks = TaskSupervisor::new();
+        tasks.spawn("finishes", async {});
+        tasks.spawn("panics", async { panic!("on purpose") });
+        tasks.spawn("sleeps", sleep(Duration::from_secs(60)));
+
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(tasks.len(), 1);
+
+        tasks.abort_all();
+        sleep(Duration::from_millis(50)).await;
+        assert!(tasks.is_empty());
+    }
+}
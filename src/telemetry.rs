@@ -1,9 +1,13 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::BunyanFormattingLayer;
 use tracing_log::LogTracer;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
-#[allow(dead_code)]
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
@@ -18,8 +22,63 @@ where
     Registry::default().with(env_filter).with(formatting_layer)
 }
 
-#[allow(dead_code)]
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger.");
     set_global_default(subscriber).expect("Failed to set subscriber.");
 }
+
+/// A log sink that can be pointed at a different file while the server is running,
+/// so a changed `logfile` takes effect on config reload. No file means stdout.
+#[derive(Clone, Default)]
+pub struct LogTarget(Arc<Mutex<Option<File>>>);
+
+impl LogTarget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_file(&self, path: Option<&str>) -> Result<(), anyhow::Error> {
+        let file = match path {
+            None => None,
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Opening log file {}", path))?;
+                Some(file)
+            }
+        };
+
+        *self.0.lock().map_err(|e| anyhow::anyhow!("{}", e))? = file;
+        Ok(())
+    }
+}
+
+pub struct LogTargetWriter(Arc<Mutex<Option<File>>>);
+
+impl Write for LogTargetWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut target = self.0.lock().map_err(|e| io::Error::other(e.to_string()))?;
+        match target.as_mut() {
+            Some(file) => file.write(buf),
+            None => io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut target = self.0.lock().map_err(|e| io::Error::other(e.to_string()))?;
+        match target.as_mut() {
+            Some(file) => file.flush(),
+            None => io::stdout().flush(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogTarget {
+    type Writer = LogTargetWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogTargetWriter(self.0.clone())
+    }
+}
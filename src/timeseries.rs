@@ -0,0 +1,316 @@
+//! Time series, like the ones the RedisTimeSeries module adds: samples of a
+//! floating point value ordered by a timestamp in milliseconds. Unlike a stream,
+//! which is an append only log, samples may arrive out of order, and a series
+//! can drop its oldest samples and be found by its labels.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+
+// Bytes per sample when saved: the timestamp then the value
+const SAMPLE_BYTES: usize = 16;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimeSeries {
+    samples: BTreeMap<u64, f64>,
+    /// How far behind the newest sample, in milliseconds, samples are kept.
+    /// 0 keeps them forever.
+    retention: u64,
+    labels: Vec<(String, String)>,
+}
+
+/// How TS.RANGE and TS.MRANGE sum up the samples in a bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "avg" => Some(Aggregation::Avg),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+/// A TS.MRANGE filter. A missing label counts as an empty one, so `label=`
+/// matches the series without it and `label!=` the ones with it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LabelFilter {
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl LabelFilter {
+    pub fn parse(filter: &str) -> Option<Self> {
+        if let Some((label, value)) = filter.split_once("!=") {
+            return Some(LabelFilter::NotEquals(label.into(), value.into()));
+        }
+        let (label, value) = filter.split_once('=')?;
+        Some(LabelFilter::Equals(label.into(), value.into()))
+    }
+
+    /// Whether the filter picks out some series rather than leaving them out.
+    pub fn is_matcher(&self) -> bool {
+        matches!(self, LabelFilter::Equals(_, value) if !value.is_empty())
+    }
+}
+
+impl TimeSeries {
+    pub fn new(retention: u64, labels: Vec<(String, String)>) -> Self {
+        TimeSeries {
+            samples: BTreeMap::new(),
+            retention,
+            labels,
+        }
+    }
+
+    pub fn labels(&self) -> &[(String, String)] {
+        &self.labels
+    }
+
+    pub fn last(&self) -> Option<(u64, f64)> {
+        self.samples
+            .last_key_value()
+            .map(|(timestamp, value)| (*timestamp, *value))
+    }
+
+    /// Adds a sample, dropping any that fall out of the retention window.
+    /// Samples can't be overwritten.
+    pub fn add(&mut self, timestamp: u64, value: f64) -> Result<(), RedisError> {
+        if self.samples.contains_key(&timestamp) {
+            return Err(RedisError::custom(
+                "ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode",
+            ));
+        }
+        if let Some(oldest) = self.oldest_kept() {
+            if timestamp < oldest {
+                return Err(RedisError::custom(
+                    "ERR TSDB: Timestamp is older than retention",
+                ));
+            }
+        }
+
+        self.samples.insert(timestamp, value);
+        if let Some(oldest) = self.oldest_kept() {
+            self.samples = self.samples.split_off(&oldest);
+        }
+
+        Ok(())
+    }
+
+    fn oldest_kept(&self) -> Option<u64> {
+        let (newest, _) = self.last()?;
+        match self.retention {
+            0 => None,
+            retention => Some(newest.saturating_sub(retention)),
+        }
+    }
+
+    /// The samples between the timestamps, or with an aggregation one sample
+    /// per bucket of that many milliseconds, stamped with the bucket's start.
+    pub fn range(
+        &self,
+        range: RangeInclusive<u64>,
+        aggregation: Option<(Aggregation, u64)>,
+    ) -> Vec<(u64, f64)> {
+        let samples = self
+            .samples
+            .range(range)
+            .map(|(timestamp, value)| (*timestamp, *value));
+        let Some((aggregation, bucket_duration)) = aggregation else {
+            return samples.collect();
+        };
+
+        let mut buckets: Vec<(u64, Vec<f64>)> = vec![];
+        for (timestamp, value) in samples {
+            let start = timestamp - timestamp % bucket_duration;
+            match buckets.last_mut() {
+                Some((bucket, values)) if *bucket == start => values.push(value),
+                _ => buckets.push((start, vec![value])),
+            }
+        }
+
+        buckets
+            .into_iter()
+            .map(|(start, values)| (start, aggregation.apply(&values)))
+            .collect()
+    }
+
+    pub fn matches(&self, filters: &[LabelFilter]) -> bool {
+        let label = |name: &str| {
+            self.labels
+                .iter()
+                .find(|(label, _)| label == name)
+                .map_or("", |(_, value)| value.as_str())
+        };
+
+        filters.iter().all(|filter| match filter {
+            LabelFilter::Equals(name, value) => label(name) == value,
+            LabelFilter::NotEquals(name, value) => label(name) != value,
+        })
+    }
+}
+
+/// Saved under RedisTimeSeries' type name: the retention, the labels, then the
+/// samples packed into a single string.
+impl ModuleValue for TimeSeries {
+    const TYPE_NAME: &'static str = "TSDB-TYPE";
+    const ENCODING_VERSION: u16 = 0;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::TimeSeries(series) => Some(series),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::TimeSeries(series) => Some(series),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::TimeSeries(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        let mut fields = vec![
+            ModuleField::Uint(self.retention),
+            ModuleField::Uint(self.labels.len() as u64),
+        ];
+        for (label, value) in self.labels.iter() {
+            fields.extend([
+                ModuleField::String(label.as_bytes().to_vec()),
+                ModuleField::String(value.as_bytes().to_vec()),
+            ]);
+        }
+        let samples = self
+            .samples
+            .iter()
+            .flat_map(|(timestamp, value)| {
+                timestamp
+                    .to_le_bytes()
+                    .into_iter()
+                    .chain(value.to_le_bytes())
+            })
+            .collect();
+        fields.push(ModuleField::String(samples));
+
+        fields
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [retention, num_labels, rest @ ..] = fields else {
+            anyhow::bail!("Time series is missing its settings");
+        };
+        let num_labels = num_labels.as_uint()? as usize;
+        if rest.len() != num_labels * 2 + 1 {
+            anyhow::bail!("Time series has the wrong number of fields");
+        }
+        let (labels, samples) = rest.split_at(num_labels * 2);
+
+        let labels = labels
+            .chunks(2)
+            .map(|label| {
+                Ok((
+                    String::from_utf8(label[0].as_bytes()?.to_vec())?,
+                    String::from_utf8(label[1].as_bytes()?.to_vec())?,
+                ))
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+        let samples = samples[0].as_bytes()?;
+        if samples.len() % SAMPLE_BYTES != 0 {
+            anyhow::bail!("Time series has a partial sample");
+        }
+        let samples = samples
+            .chunks(SAMPLE_BYTES)
+            .map(|sample| {
+                let (timestamp, value) = sample.split_at(8);
+                (
+                    u64::from_le_bytes(timestamp.try_into().expect("8 bytes")),
+                    f64::from_le_bytes(value.try_into().expect("8 bytes")),
+                )
+            })
+            .collect();
+
+        Ok(TimeSeries {
+            samples,
+            retention: retention.as_uint()?,
+            labels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_outside_the_retention_are_dropped() {
+        let mut series = TimeSeries::new(100, vec![]);
+        series.add(1000, 1.0).unwrap();
+        series.add(1050, 2.0).unwrap();
+        series.add(1020, 1.5).unwrap();
+        series.add(1120, 3.0).unwrap();
+
+        assert_eq!(
+            series.range(0..=u64::MAX, None),
+            [(1020, 1.5), (1050, 2.0), (1120, 3.0)]
+        );
+        assert!(series.add(1010, 0.0).is_err());
+        assert!(series.add(1050, 0.0).is_err());
+    }
+
+    #[test]
+    fn ranges_can_be_aggregated() {
+        let mut series = TimeSeries::default();
+        for (timestamp, value) in [(0, 1.0), (5, 3.0), (10, 4.0), (25, -1.0), (29, 7.0)] {
+            series.add(timestamp, value).unwrap();
+        }
+
+        let avg = series.range(0..=u64::MAX, Some((Aggregation::Avg, 10)));
+        assert_eq!(avg, [(0, 2.0), (10, 4.0), (20, 3.0)]);
+        let max = series.range(5..=25, Some((Aggregation::Max, 10)));
+        assert_eq!(max, [(0, 3.0), (10, 4.0), (20, -1.0)]);
+        let min = series.range(0..=u64::MAX, Some((Aggregation::Min, 100)));
+        assert_eq!(min, [(0, -1.0)]);
+    }
+
+    #[test]
+    fn series_are_filtered_by_label() {
+        let series = TimeSeries::new(0, vec![("sensor".into(), "temp".into())]);
+        let filters = |filters: &[&str]| -> Vec<LabelFilter> {
+            filters
+                .iter()
+                .map(|filter| LabelFilter::parse(filter).unwrap())
+                .collect()
+        };
+
+        assert!(series.matches(&filters(&["sensor=temp", "room="])));
+        assert!(series.matches(&filters(&["sensor!=", "room!=kitchen"])));
+        assert!(!series.matches(&filters(&["sensor=temp", "room=kitchen"])));
+        assert!(!series.matches(&filters(&["sensor="])));
+
+        let saved = TimeSeries::from_fields(&series.to_fields()).unwrap();
+        assert_eq!(saved, series);
+    }
+}
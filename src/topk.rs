@@ -0,0 +1,304 @@
+//! Top-K, like the one the RedisBloom module adds, which keeps the k most
+//! frequent items of a stream using HeavyKeeper. Every item has a bucket in each
+//! of `depth` rows, holding a fingerprint and a count. A bucket belonging to
+//! another item is decayed rather than taken over, with a chance that shrinks as
+//! its count grows, so only items that keep showing up hold on to their buckets.
+
+use crate::data::{DatabaseItem, ModuleValue};
+use crate::encoding::ModuleField;
+use crate::errors::RedisError;
+use crate::utils::item_hashes;
+
+/// What TOPK.RESERVE uses when only k is given.
+pub const DEFAULT_WIDTH: u64 = 8;
+pub const DEFAULT_DEPTH: u64 = 7;
+pub const DEFAULT_DECAY: f64 = 0.9;
+// Like for the filters, no structure is larger than a value a client could send
+const MAX_BUCKET_BYTES: u64 = 512 * 1024 * 1024;
+// Bytes per bucket when saved: the fingerprint then the count
+const BUCKET_BYTES: usize = 12;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Bucket {
+    fingerprint: u32,
+    count: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopK {
+    k: u64,
+    width: u64,
+    depth: u64,
+    decay: f64,
+    /// Row after row.
+    buckets: Vec<Bucket>,
+    /// The current top items with their estimated counts, in no particular order.
+    heap: Vec<(String, u64)>,
+    /// The state of the generator that decides whether a bucket decays. It's
+    /// saved and replicated along with the rest, so every replica decays the same
+    /// buckets.
+    seed: u64,
+}
+
+impl TopK {
+    pub fn new(k: u64, width: u64, depth: u64, decay: f64) -> Result<Self, RedisError> {
+        let size = width.saturating_mul(depth);
+        if size.saturating_mul(BUCKET_BYTES as u64) > MAX_BUCKET_BYTES {
+            return Err(RedisError::custom("ERR TopK: Insufficient memory"));
+        }
+
+        Ok(TopK {
+            k,
+            width,
+            depth,
+            decay,
+            buckets: vec![Bucket::default(); size as usize],
+            heap: vec![],
+            seed: 0,
+        })
+    }
+
+    /// A number between 0 and 1 from splitmix64.
+    fn next_random(&mut self) -> f64 {
+        self.seed = self.seed.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The item's fingerprint and the index of its bucket in every row.
+    fn buckets_of(&self, item: &str) -> (u32, Vec<usize>) {
+        let (h1, h2) = item_hashes(item);
+        let positions = (0..self.depth)
+            .map(|row| {
+                let column = h1.wrapping_add(row.wrapping_mul(h2)) % self.width;
+                (row * self.width + column) as usize
+            })
+            .collect();
+
+        ((h2 >> 32) as u32, positions)
+    }
+
+    /// Counts the item, and returns the item it pushed out of the top k if any.
+    pub fn add(&mut self, item: &str) -> Option<String> {
+        let (fingerprint, positions) = self.buckets_of(item);
+        let mut estimate = 0;
+        for position in positions {
+            let bucket = self.buckets[position];
+            let bucket = if bucket.count == 0 || bucket.fingerprint == fingerprint {
+                Bucket {
+                    fingerprint,
+                    count: bucket.count + 1,
+                }
+            } else if self.next_random() < self.decay.powf(bucket.count as f64) {
+                match bucket.count - 1 {
+                    0 => Bucket {
+                        fingerprint,
+                        count: 1,
+                    },
+                    count => Bucket { count, ..bucket },
+                }
+            } else {
+                bucket
+            };
+
+            if bucket.fingerprint == fingerprint {
+                estimate = estimate.max(bucket.count);
+            }
+            self.buckets[position] = bucket;
+        }
+
+        if let Some(entry) = self.heap.iter_mut().find(|(member, _)| member == item) {
+            entry.1 = estimate;
+            return None;
+        }
+        if (self.heap.len() as u64) < self.k {
+            self.heap.push((item.to_string(), estimate));
+            return None;
+        }
+
+        let (smallest, _) = self
+            .heap
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (_, count))| *count)?;
+        if estimate <= self.heap[smallest].1 {
+            return None;
+        }
+        let (expelled, _) =
+            std::mem::replace(&mut self.heap[smallest], (item.to_string(), estimate));
+        Some(expelled)
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.heap.iter().any(|(member, _)| member == item)
+    }
+
+    /// The item's estimated count, from the buckets that still hold it.
+    pub fn count(&self, item: &str) -> u64 {
+        let (fingerprint, positions) = self.buckets_of(item);
+        positions
+            .into_iter()
+            .map(|position| self.buckets[position])
+            .filter(|bucket| bucket.fingerprint == fingerprint)
+            .map(|bucket| bucket.count)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The top items, most frequent first.
+    pub fn list(&self) -> Vec<(String, u64)> {
+        let mut items = self.heap.clone();
+        items.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        items
+    }
+}
+
+/// Saved under RedisBloom's type name: the settings, the buckets packed into a
+/// single string, then the top items with their counts.
+impl ModuleValue for TopK {
+    const TYPE_NAME: &'static str = "TopK-TYPE";
+    const ENCODING_VERSION: u16 = 0;
+
+    fn from_item(item: &DatabaseItem) -> Option<&Self> {
+        match item {
+            DatabaseItem::TopK(topk) => Some(topk),
+            _ => None,
+        }
+    }
+
+    fn from_item_mut(item: &mut DatabaseItem) -> Option<&mut Self> {
+        match item {
+            DatabaseItem::TopK(topk) => Some(topk),
+            _ => None,
+        }
+    }
+
+    fn into_item(self) -> DatabaseItem {
+        DatabaseItem::TopK(self)
+    }
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        let buckets = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| {
+                bucket
+                    .fingerprint
+                    .to_le_bytes()
+                    .into_iter()
+                    .chain(bucket.count.to_le_bytes())
+            })
+            .collect();
+
+        let mut fields = vec![
+            ModuleField::Uint(self.k),
+            ModuleField::Uint(self.width),
+            ModuleField::Uint(self.depth),
+            ModuleField::Double(self.decay),
+            ModuleField::Uint(self.seed),
+            ModuleField::String(buckets),
+        ];
+        for (item, count) in self.heap.iter() {
+            fields.extend([
+                ModuleField::String(item.as_bytes().to_vec()),
+                ModuleField::Uint(*count),
+            ]);
+        }
+
+        fields
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [k, width, depth, decay, seed, buckets, heap @ ..] = fields else {
+            anyhow::bail!("Top-K is missing its settings");
+        };
+        let (width, depth) = (width.as_uint()?, depth.as_uint()?);
+        let buckets = buckets.as_bytes()?;
+        let bytes = width
+            .checked_mul(depth)
+            .and_then(|size| size.checked_mul(BUCKET_BYTES as u64))
+            .filter(|bytes| *bytes <= MAX_BUCKET_BYTES)
+            .ok_or_else(|| anyhow::anyhow!("Top-K is too big"))?;
+        if width == 0 || buckets.len() as u64 != bytes {
+            anyhow::bail!("Top-K has the wrong number of buckets");
+        }
+        if heap.len() % 2 != 0 {
+            anyhow::bail!("Top-K has an item without a count");
+        }
+
+        let buckets = buckets
+            .chunks(BUCKET_BYTES)
+            .map(|bucket| {
+                let (fingerprint, count) = bucket.split_at(4);
+                Bucket {
+                    fingerprint: u32::from_le_bytes(fingerprint.try_into().expect("4 bytes")),
+                    count: u64::from_le_bytes(count.try_into().expect("8 bytes")),
+                }
+            })
+            .collect();
+        let heap = heap
+            .chunks(2)
+            .map(|entry| {
+                let item = String::from_utf8(entry[0].as_bytes()?.to_vec())?;
+                Ok((item, entry[1].as_uint()?))
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+
+        Ok(TopK {
+            k: k.as_uint()?,
+            width,
+            depth,
+            decay: decay.as_double()?,
+            buckets,
+            heap,
+            seed: seed.as_uint()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequent_items_make_the_top() {
+        let mut topk = TopK::new(3, 50, 5, DEFAULT_DECAY).unwrap();
+        // Three heavy hitters among a long tail of items seen once
+        for i in 0..2000 {
+            topk.add(&format!("tail-{}", i));
+            if i % 10 == 0 {
+                for heavy in ["a", "b", "c"] {
+                    topk.add(heavy);
+                }
+            }
+            if i % 20 == 0 {
+                topk.add("a");
+            }
+        }
+
+        let top: Vec<String> = topk.list().into_iter().map(|(item, _)| item).collect();
+        assert_eq!(top, ["a", "b", "c"]);
+        assert!(topk.contains("a"));
+        assert!(!topk.contains("tail-7"));
+        assert!(topk.count("a") > topk.count("b"));
+
+        let saved = TopK::from_fields(&topk.to_fields()).unwrap();
+        assert_eq!(saved, topk);
+
+        let mut fields = topk.to_fields();
+        fields[1] = ModuleField::Uint(1 << 62);
+        assert!(TopK::from_fields(&fields).is_err());
+    }
+
+    #[test]
+    fn items_are_expelled_by_more_frequent_ones() {
+        let mut topk = TopK::new(1, 8, 7, DEFAULT_DECAY).unwrap();
+        assert_eq!(topk.add("a"), None);
+        assert_eq!(topk.add("b"), None);
+        assert_eq!(topk.add("b"), Some("a".to_string()));
+        assert_eq!(topk.list(), [("b".to_string(), 2)]);
+    }
+}
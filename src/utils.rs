@@ -3,48 +3,152 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use bytes::Buf;
+use bytes::Bytes;
+
+use crate::errors::ProtocolError;
+
+/// The largest request we're willing to buffer, so a client can't make us
+/// allocate as much as it likes.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameLimits {
+    pub max_bulk_len: usize,
+    pub max_multibulk_len: usize,
+    // How long a line can get before we give up waiting for its CRLF
+    pub max_inline_len: usize,
+}
+
+impl FrameLimits {
+    /// For data we trust, like the AOF or the replication stream from our master.
+    pub fn unlimited() -> Self {
+        FrameLimits {
+            max_bulk_len: usize::MAX,
+            max_multibulk_len: usize::MAX,
+            max_inline_len: usize::MAX,
+        }
+    }
+}
 
 pub struct Frame {
     pub data: Vec<String>,
     pub bytes_processed: usize,
+    // The frame exactly as it was received
+    pub raw: Bytes,
+}
+
+/// Parses the next frame from the cursor. Returns `None`, leaving the cursor where
+/// it was, if the frame hasn't been received in full yet.
+pub fn read_frame(
+    cursor: &mut Cursor<&[u8]>,
+    limits: &FrameLimits,
+) -> Result<Option<Frame>, anyhow::Error> {
+    let start = cursor.position();
+
+    match parse_frame(cursor, limits) {
+        Ok(frame) => Ok(Some(frame)),
+        Err(e) if is_incomplete(&e) => {
+            cursor.set_position(start);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
 }
 
-pub fn read_frame(cursor: &mut Cursor<&[u8]>) -> Result<Option<Frame>, anyhow::Error> {
+fn parse_frame(cursor: &mut Cursor<&[u8]>, limits: &FrameLimits) -> Result<Frame, anyhow::Error> {
+    let start = cursor.position() as usize;
     let mut bytes_processed = 0;
-    let (size, bytes) = match read_line(cursor) {
-        Ok(data) => Ok(data),
-        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
-        error => error,
-    }?;
+    let (size, bytes) = read_line(cursor, limits.max_inline_len)?;
 
     bytes_processed += bytes;
 
+    let parsed_size = parse_length(&size, b'*', "invalid multibulk length")?;
+    if parsed_size > limits.max_multibulk_len {
+        return Err(ProtocolError("invalid multibulk length".to_string()).into());
+    }
     let size = String::from_utf8(size)?;
-    let parsed_size: usize = str::parse(&size[1..])?;
 
-    let mut data: Vec<String> = Vec::with_capacity(parsed_size);
+    // The size comes from the client, so only trust it so far until the items arrive
+    let mut data: Vec<String> = Vec::with_capacity(parsed_size.min(1024) * 2 + 1);
 
     data.push(size);
 
-    for _ in 0..parsed_size * 2 {
-        let (line, bytes) = read_line(cursor)?;
-        let line = String::from_utf8(line)?;
+    for _ in 0..parsed_size {
+        let (length, bytes) = read_line(cursor, limits.max_inline_len)?;
+        bytes_processed += bytes;
 
+        // Bulk strings are read by their length, so they can hold anything, CRLF included.
+        let parsed_length = parse_length(&length, b'$', "invalid bulk length")?;
+        if parsed_length > limits.max_bulk_len {
+            return Err(ProtocolError("invalid bulk length".to_string()).into());
+        }
+        let length = String::from_utf8(length)?;
+        let (item, bytes) = read_bulk(cursor, parsed_length)?;
+        let item = String::from_utf8(item)
+            .map_err(|_| ProtocolError("bulk strings must be valid UTF-8".to_string()))?;
         bytes_processed += bytes;
 
-        data.push(line);
+        data.push(length);
+        data.push(item);
     }
 
+    let raw = Bytes::copy_from_slice(&cursor.get_ref()[start..start + bytes_processed]);
+
     let frame = Frame {
         bytes_processed,
         data,
+        raw,
     };
-    Ok(Some(frame))
+    Ok(frame)
+}
+
+/// Reads exactly `length` bytes followed by a CRLF.
+fn read_bulk(cursor: &mut Cursor<&[u8]>, length: usize) -> Result<(Vec<u8>, usize), anyhow::Error> {
+    // Don't allocate for a bulk string that hasn't fully arrived yet
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if remaining < (length as u64).saturating_add(2) {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+
+    let mut data = vec![0; length];
+    cursor.read_exact(&mut data)?;
+
+    let mut terminator: [u8; 2] = [0; 2];
+    cursor.read_exact(&mut terminator)?;
+    if &terminator != b"\r\n" {
+        return Err(ProtocolError("expected CRLF after bulk string".to_string()).into());
+    }
+
+    Ok((data, length + 2))
+}
+
+/// Parses a header line like `*3` or `$5`, which has to start with `prefix`.
+fn parse_length(line: &[u8], prefix: u8, error: &str) -> Result<usize, ProtocolError> {
+    match line.split_first() {
+        Some((first, length)) if *first == prefix => std::str::from_utf8(length)
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .ok_or_else(|| ProtocolError(error.to_string())),
+        Some((first, _)) => Err(ProtocolError(format!(
+            "expected '{}', got '{}'",
+            prefix as char, *first as char
+        ))),
+        None => Err(ProtocolError(format!(
+            "expected '{}', got an empty line",
+            prefix as char
+        ))),
+    }
+}
+
+fn is_incomplete(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
 }
 
 // Can this be genericized to work with a tokio::net::TcpStream?
-pub fn read_line(cursor: &mut Cursor<&[u8]>) -> Result<(Vec<u8>, usize), std::io::Error> {
+pub fn read_line(
+    cursor: &mut Cursor<&[u8]>,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), anyhow::Error> {
     let mut bytes_read = 0;
     let mut data: Vec<u8> = vec![];
     let mut next_char: [u8; 1] = [0; 1];
@@ -53,17 +157,24 @@ pub fn read_line(cursor: &mut Cursor<&[u8]>) -> Result<(Vec<u8>, usize), std::io
         cursor.read_exact(&mut next_char)?;
         bytes_read += 1;
 
-        let char_read = *next_char.first().ok_or_else(|| {
-            std::io::Error::new(ErrorKind::Other, "Expectd to read byte from cursor")
-        })?;
+        let char_read = *next_char
+            .first()
+            .ok_or_else(|| std::io::Error::other("Expectd to read byte from cursor"))?;
 
         if char_read == b'\r' {
-            cursor.advance(1);
+            // The \n may not have arrived yet
+            cursor.read_exact(&mut next_char)?;
             bytes_read += 1;
+            if next_char[0] != b'\n' {
+                return Err(ProtocolError("expected CRLF".to_string()).into());
+            }
             break;
         }
 
         data.push(char_read);
+        if data.len() > max_len {
+            return Err(ProtocolError("too big inline request".to_string()).into());
+        }
     }
 
     Ok((data, bytes_read))
@@ -84,3 +195,21 @@ pub fn current_unix_timestamp() -> Result<u128, anyhow::Error> {
 
     Ok(ms_time)
 }
+
+/// Two independent hashes of an item, which the probabilistic types combine into
+/// as many as they need: FNV-1a, and that run through the splitmix64 finalizer.
+/// They're the same on every server, so a replica or a reloaded AOF ends up
+/// with the same filters. The second is odd so multiples of it never repeat a
+/// position early.
+pub fn item_hashes(item: &str) -> (u64, u64) {
+    let h1 = item.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let mut h2 = h1.wrapping_add(0x9e3779b97f4a7c15);
+    h2 = (h2 ^ (h2 >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h2 = (h2 ^ (h2 >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h2 ^= h2 >> 31;
+
+    (h1, h2 | 1)
+}
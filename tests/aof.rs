@@ -0,0 +1,206 @@
+use std::env;
+use std::fs;
+
+use tokio::time::{sleep, Duration};
+
+use not_redis::aof::check_file;
+use not_redis::data::Database;
+use not_redis::encoding::{bulk_string, empty_string, simple_string};
+use not_redis::server::Config;
+
+use common::{encode_string, send_message, TestApp};
+
+mod common;
+
+fn temp_aof_config() -> Config {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut config = Config::new(Some(dir.to_string_lossy().to_string()), None);
+    config.append_only = true;
+    config
+}
+
+#[tokio::test]
+async fn writes_are_replayed_on_restart() {
+    let config = temp_aof_config();
+    let dir = config.aof_dir();
+    let test_app = TestApp::with_config(config.clone()).await;
+    let address = test_app.address.name();
+
+    for command in [
+        "set foo bar",
+        "set gone soon",
+        "incr counter",
+        "incrby counter 10",
+        "del gone",
+    ] {
+        let message = encode_string(command);
+        send_message(&address, &message).await;
+    }
+
+    // Reads aren't logged
+    let message = encode_string("get foo");
+    send_message(&address, &message).await;
+    let manifest = fs::read_to_string(dir.join("appendonly.aof.manifest")).unwrap();
+    assert_eq!(
+        manifest,
+        "file appendonly.aof.1.base.rdb seq 1 type b\nfile appendonly.aof.1.incr.aof seq 1 type i\n"
+    );
+    let contents = fs::read_to_string(dir.join("appendonly.aof.1.incr.aof")).unwrap();
+    assert!(contents.starts_with("*3\r\n$3\r\nset\r\n$3\r\nfoo\r\n$3\r\nbar\r\n"));
+    assert!(!contents.contains("get"));
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config).await;
+    let address = restored_app.address.name();
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    let message = encode_string("get counter");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("11"));
+
+    let message = encode_string("get gone");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn bgrewriteaof_compacts_the_log() {
+    let config = temp_aof_config();
+    let dir = config.aof_dir();
+    let test_app = TestApp::with_config(config.clone()).await;
+    let address = test_app.address.name();
+
+    for _ in 0..50 {
+        let message = encode_string("incr counter");
+        send_message(&address, &message).await;
+    }
+
+    let message = encode_string("bgrewriteaof");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        simple_string("Background append only file rewriting started")
+    );
+
+    sleep(Duration::from_millis(200)).await;
+
+    // The new base and incremental file replace everything before them
+    let manifest = fs::read_to_string(dir.join("appendonly.aof.manifest")).unwrap();
+    assert_eq!(
+        manifest,
+        "file appendonly.aof.2.base.rdb seq 2 type b\nfile appendonly.aof.2.incr.aof seq 2 type i\n"
+    );
+    assert!(!dir.join("appendonly.aof.1.base.rdb").exists());
+    assert!(!dir.join("appendonly.aof.1.incr.aof").exists());
+
+    // Writes after the rewrite go in the new incremental file
+    let message = encode_string("set after rewrite");
+    send_message(&address, &message).await;
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config).await;
+    let address = restored_app.address.name();
+
+    let message = encode_string("get counter");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("50"));
+
+    let message = encode_string("get after");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("rewrite"));
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn aof_is_rewritten_once_it_grows_enough() {
+    let mut config = temp_aof_config();
+    config.auto_aof_rewrite_min_size = 1000;
+    let dir = config.aof_dir();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    for _ in 0..50 {
+        let message = encode_string("set foo bar");
+        send_message(&address, &message).await;
+    }
+
+    sleep(Duration::from_millis(1500)).await;
+
+    assert!(dir.join("appendonly.aof.2.base.rdb").exists());
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("aof_enabled:1"));
+    assert!(resp.contains("aof_rewrite_in_progress:0"));
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn single_file_aof_becomes_the_base() {
+    let config = temp_aof_config();
+    let legacy_path = config.aof_path();
+    let dir = config.aof_dir();
+    fs::write(&legacy_path, encode_string("set foo bar")).unwrap();
+
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    assert!(!legacy_path.exists());
+    let manifest = fs::read_to_string(dir.join("appendonly.aof.manifest")).unwrap();
+    assert!(manifest.starts_with("file appendonly.aof.1.base.aof seq 1 type b\n"));
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn check_file_reports_the_first_command_that_cant_be_read() {
+    let mut commands = encode_string("set foo bar");
+    commands.extend(encode_string("incr counter"));
+    let valid_len = commands.len() as u64;
+
+    let check = check_file(commands.clone());
+    assert_eq!(check.commands, 2);
+    assert_eq!(check.corruption, None);
+
+    // Cut off halfway through appending
+    let mut truncated = commands.clone();
+    truncated.extend(&encode_string("del foo")[..8]);
+    let check = check_file(truncated);
+    assert_eq!(check.commands, 2);
+    let corruption = check.corruption.unwrap();
+    assert_eq!(corruption.offset, valid_len);
+    assert!(corruption.fixable);
+
+    let mut unknown = commands.clone();
+    unknown.extend(encode_string("frobnicate foo"));
+    let corruption = check_file(unknown).corruption.unwrap();
+    assert_eq!(corruption.offset, valid_len);
+
+    // Commands after an RDB preamble are counted from where it ends
+    let database = Database::new();
+    database.set_string("foo", "bar").unwrap();
+    let mut base = database.to_rdb(false).unwrap();
+    let preamble_len = base.len() as u64;
+    base.extend(commands);
+    base.extend(b"*1\r\n$3\r\nfo");
+    let check = check_file(base.clone());
+    assert_eq!(check.commands, 2);
+    assert_eq!(check.corruption.unwrap().offset, preamble_len + valid_len);
+
+    base[preamble_len as usize - 9] = 0;
+    let corruption = check_file(base).corruption.unwrap();
+    assert!(!corruption.fixable);
+}
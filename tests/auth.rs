@@ -0,0 +1,53 @@
+use not_redis::client::Client;
+use not_redis::errors::RedisError;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn commands_are_refused_until_the_client_authenticates() {
+    let mut config = Config::new(None, None);
+    config.requirepass = Some("secret".to_string());
+    let test_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["SET", "foo", "bar"]).await.unwrap();
+    assert_eq!(reply, Value::error("NOAUTH Authentication required."));
+
+    let reply = client.command(&["AUTH", "wrong"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("WRONGPASS invalid username-password pair or user is disabled.")
+    );
+
+    let reply = client
+        .command(&["AUTH", "default", "secret"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.get("foo").await.unwrap(), Some("bar".to_string()));
+
+    // Every connection has to authenticate on its own
+    let mut other = Client::connect(test_app.address.name()).await.unwrap();
+    let error = other.get("foo").await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RedisError>(),
+        Some(&RedisError::Custom(
+            "NOAUTH Authentication required.".to_string()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn auth_fails_without_a_password_configured() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["AUTH", "secret"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(message)
+        if message.starts_with("ERR AUTH <password> called without any password configured")));
+}
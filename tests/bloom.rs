@@ -0,0 +1,135 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn items_can_be_added_and_checked() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client.command(&["BF.ADD", "filter", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["BF.ADD", "filter", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client
+        .command(&["BF.MADD", "filter", "pear", "apple", "plum"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![
+            Value::Integer(1),
+            Value::Integer(0),
+            Value::Integer(1)
+        ])
+    );
+
+    for (item, exists) in [("apple", 1), ("plum", 1), ("cherry", 0)] {
+        let reply = client.command(&["BF.EXISTS", "filter", item]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(exists), "{}", item);
+    }
+    let reply = client.command(&["BF.EXISTS", "nothing", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client.command(&["TYPE", "filter"]).await;
+    assert_eq!(reply.unwrap(), Value::from("MBbloom--"));
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["BF.ADD", "string", "apple"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn filters_can_be_reserved() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for (args, error) in [
+        (vec!["nope", "100"], "ERR bad error rate"),
+        (vec!["1.5", "100"], "ERR (0 < error rate range < 1)"),
+        (vec!["0.01", "-1"], "ERR bad capacity"),
+        (vec!["0.01", "0"], "ERR (capacity should be larger than 0)"),
+        (vec!["0.01", "100", "EXPANSION", "0"], "ERR bad expansion"),
+        (vec!["0.01", "100", "SOMETHING"], "ERR syntax error"),
+    ] {
+        let mut command = vec!["BF.RESERVE", "filter"];
+        command.extend(args);
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+
+    let reply = client
+        .command(&["BF.RESERVE", "filter", "0.001", "3", "NONSCALING"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client
+        .command(&["BF.RESERVE", "filter", "0.01", "100"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR item exists"));
+
+    let reply = client
+        .command(&["BF.MADD", "filter", "a", "b", "c", "d"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![
+            Value::Integer(1),
+            Value::Integer(1),
+            Value::Integer(1),
+            Value::error("ERR non scaling filter is full"),
+        ])
+    );
+    let reply = client.command(&["BF.ADD", "filter", "e"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR non scaling filter is full")
+    );
+
+    let reply = client
+        .command(&["BF.RESERVE", "growing", "0.01", "2", "EXPANSION", "4"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    for i in 0..50 {
+        client
+            .command(&["BF.ADD", "growing", &i.to_string()])
+            .await
+            .unwrap();
+    }
+    let reply = client.command(&["BF.EXISTS", "growing", "49"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+}
+
+#[tokio::test]
+async fn filters_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    let items: Vec<String> = (0..500).map(|i| format!("item-{}", i)).collect();
+    let mut command = vec!["BF.MADD", "filter"];
+    command.extend(items.iter().map(String::as_str));
+    client.command(&command).await.unwrap();
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    for item in items.iter() {
+        let reply = client.command(&["BF.EXISTS", "filter", item]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(1));
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
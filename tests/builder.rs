@@ -0,0 +1,29 @@
+use tokio::time::{sleep, Duration};
+
+use common::{encode_string, send_message};
+use not_redis::app;
+use not_redis::encoding::{bulk_string, simple_string};
+use not_redis::server::ServerBuilder;
+
+mod common;
+
+#[tokio::test]
+async fn built_server_listens_on_the_port_the_os_picked() {
+    let (database, redis_server) = ServerBuilder::new().port(0).build().await.unwrap();
+    database.set_string("foo", "bar").unwrap();
+
+    let address = redis_server.address().await;
+    let (db, rs) = (database.clone(), redis_server.clone());
+    let server = tokio::spawn(async move { app::run(&address, db, rs).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let address = redis_server.address().await;
+    assert!(!address.ends_with(":0"));
+
+    let resp = send_message(&address, &encode_string("ping")).await;
+    assert_eq!(resp, simple_string("PONG"));
+    let resp = send_message(&address, &encode_string("get foo")).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    server.abort();
+}
@@ -0,0 +1,74 @@
+use not_redis::client::{Client, Pipeline};
+use not_redis::errors::RedisError;
+use not_redis::resp::Value;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn typed_helpers_round_trip_values() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    assert_eq!(client.ping().await.unwrap(), "PONG");
+    assert_eq!(client.get("foo").await.unwrap(), None);
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.get("foo").await.unwrap(), Some("bar".to_string()));
+
+    assert_eq!(client.incr("counter").await.unwrap(), 1);
+    assert_eq!(client.incr_by("counter", 9).await.unwrap(), 10);
+
+    let id = client
+        .xadd("stream", "1-1", &[("field", "value")])
+        .await
+        .unwrap();
+    assert_eq!(id, "1-1");
+
+    let mut keys = client.keys("*").await.unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["counter", "foo", "stream"]);
+
+    assert_eq!(client.del(&["foo", "missing"]).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn error_replies_become_typed_errors() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.set("foo", "bar").await.unwrap();
+    let error = client.incr("foo").await.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RedisError>(),
+        Some(&RedisError::NotAnInteger)
+    );
+
+    // Raw commands hand the error reply back as it is
+    let reply = client.command(&["frobnicate"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(message) if message.starts_with("ERR unknown command")));
+}
+
+#[tokio::test]
+async fn pipelines_read_back_every_reply_in_order() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let mut pipeline = Pipeline::new();
+    pipeline
+        .add(&["SET", "foo", "1"])
+        .add(&["INCR", "foo"])
+        .add(&["GET", "foo"])
+        .add(&["GET", "missing"]);
+
+    let replies = client.pipeline(&pipeline).await.unwrap();
+    assert_eq!(
+        replies,
+        vec![
+            Value::ok(),
+            Value::Integer(2),
+            Value::from("2"),
+            Value::Null
+        ]
+    );
+}
@@ -0,0 +1,416 @@
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+async fn cluster_node() -> (TestApp, Client) {
+    let mut config = Config::new(None, None);
+    config.cluster_enabled = true;
+
+    let test_app = TestApp::with_config(config).await;
+    let client = Client::connect(test_app.address.name()).await.unwrap();
+    (test_app, client)
+}
+
+fn info_field(info: &Value, field: &str) -> String {
+    info.as_str()
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn cluster_is_up_once_every_slot_is_assigned() {
+    let (_test_app, mut client) = cluster_node().await;
+
+    let info = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+    assert_eq!(info_field(&info, "cluster_state"), "fail");
+    assert_eq!(info_field(&info, "cluster_slots_assigned"), "0");
+    assert_eq!(info_field(&info, "cluster_known_nodes"), "1");
+
+    let reply = client
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "0", "8191"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client
+        .command(&["CLUSTER", "ADDSLOTS", "8192", "8193"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "8194", "16383"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    let info = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+    assert_eq!(info_field(&info, "cluster_state"), "ok");
+    assert_eq!(info_field(&info, "cluster_slots_assigned"), "16384");
+    assert_eq!(info_field(&info, "cluster_size"), "1");
+
+    let reply = client
+        .command(&["CLUSTER", "ADDSLOTS", "100"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::error("ERR Slot 100 is already busy"));
+
+    let reply = client
+        .command(&["CLUSTER", "DELSLOTSRANGE", "100", "199"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let info = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+    assert_eq!(info_field(&info, "cluster_state"), "fail");
+    assert_eq!(info_field(&info, "cluster_slots_assigned"), "16284");
+}
+
+#[tokio::test]
+async fn cluster_slots_and_shards_describe_this_node() {
+    let (test_app, mut client) = cluster_node().await;
+    let port = test_app.address.port() as i64;
+
+    let my_id = client.command(&["CLUSTER", "MYID"]).await.unwrap();
+    let my_id = my_id.as_str().unwrap().to_string();
+    assert_eq!(my_id.len(), 40);
+
+    client
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "0", "99", "200", "299"])
+        .await
+        .unwrap();
+
+    let node = Value::Array(vec![
+        Value::from("127.0.0.1"),
+        Value::Integer(port),
+        Value::from(my_id.as_str()),
+        Value::Array(vec![]),
+    ]);
+    let slots = client.command(&["CLUSTER", "SLOTS"]).await.unwrap();
+    assert_eq!(
+        slots,
+        Value::Array(vec![
+            Value::Array(vec![Value::Integer(0), Value::Integer(99), node.clone()]),
+            Value::Array(vec![Value::Integer(200), Value::Integer(299), node]),
+        ])
+    );
+
+    let Value::Array(shards) = client.command(&["CLUSTER", "SHARDS"]).await.unwrap() else {
+        panic!("Expected an array of shards");
+    };
+    assert_eq!(shards.len(), 1);
+    let Value::Array(shard) = &shards[0] else {
+        panic!("Expected a shard to be an array");
+    };
+    assert_eq!(shard[0], Value::from("slots"));
+    assert_eq!(
+        shard[1],
+        Value::Array([0, 99, 200, 299].into_iter().map(Value::Integer).collect())
+    );
+    let Value::Array(nodes) = &shard[3] else {
+        panic!("Expected the shard's nodes to be an array");
+    };
+    let Value::Array(node) = &nodes[0] else {
+        panic!("Expected a node to be an array");
+    };
+    assert_eq!(node[1], Value::from(my_id.as_str()));
+    assert_eq!(node[3], Value::Integer(port));
+
+    let info = client.command(&["INFO", "cluster"]).await.unwrap();
+    assert!(info.as_str().unwrap().contains("cluster_enabled:1"));
+}
+
+#[tokio::test]
+async fn cluster_commands_fail_when_cluster_mode_is_off() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR This instance has cluster support disabled")
+    );
+
+    let info = client.command(&["INFO", "cluster"]).await.unwrap();
+    assert!(info.as_str().unwrap().contains("cluster_enabled:0"));
+}
+
+#[tokio::test]
+async fn bad_slots_are_rejected() {
+    let (_test_app, mut client) = cluster_node().await;
+
+    for args in [
+        &["CLUSTER", "ADDSLOTS", "16384"][..],
+        &["CLUSTER", "ADDSLOTS", "-1"][..],
+        &["CLUSTER", "ADDSLOTSRANGE", "10", "5"][..],
+        &["CLUSTER", "ADDSLOTSRANGE", "10"][..],
+        &["CLUSTER", "ADDSLOTS", "1", "1"][..],
+        &["CLUSTER", "FROBNICATE"][..],
+    ] {
+        let reply = client.command(args).await.unwrap();
+        assert!(matches!(reply, Value::Error(_)), "{:?}: {:?}", args, reply);
+    }
+
+    let info = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+    assert_eq!(info_field(&info, "cluster_slots_assigned"), "0");
+}
+
+#[tokio::test]
+async fn keyslot_hashes_only_the_hash_tag() {
+    let (_test_app, mut client) = cluster_node().await;
+
+    let reply = client
+        .command(&["CLUSTER", "KEYSLOT", "foo"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(12182));
+
+    let tagged = client
+        .command(&["CLUSTER", "KEYSLOT", "{user1000}.following"])
+        .await
+        .unwrap();
+    let tag = client
+        .command(&["CLUSTER", "KEYSLOT", "user1000"])
+        .await
+        .unwrap();
+    assert_eq!(tagged, tag);
+}
+
+#[tokio::test]
+async fn multi_key_commands_need_every_key_in_one_slot() {
+    let (_test_app, mut client) = cluster_node().await;
+    client
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "0", "16383"])
+        .await
+        .unwrap();
+
+    let reply = client.command(&["DEL", "foo", "bar"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("CROSSSLOT Keys in request don't hash to the same slot")
+    );
+
+    client.command(&["SET", "{user}:a", "1"]).await.unwrap();
+    client.command(&["SET", "{user}:b", "2"]).await.unwrap();
+    let reply = client
+        .command(&["DEL", "{user}:a", "{user}:b", "{user}:c"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(2));
+
+    let reply = client
+        .command(&["XREAD", "STREAMS", "foo", "bar", "0", "0"])
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Value::error("CROSSSLOT Keys in request don't hash to the same slot")
+    );
+}
+
+#[tokio::test]
+async fn keys_can_be_in_any_slot_when_cluster_mode_is_off() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["DEL", "foo", "bar"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+}
+
+async fn bus_port(test_app: &TestApp) -> String {
+    test_app
+        .redis_server
+        .read()
+        .await
+        .config
+        .cluster_port
+        .to_string()
+}
+
+async fn meet(client: &mut Client, other: &TestApp) {
+    let port = other.address.port().to_string();
+    let reply = client
+        .command(&[
+            "CLUSTER",
+            "MEET",
+            "127.0.0.1",
+            &port,
+            &bus_port(other).await,
+        ])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+}
+
+/// Polls CLUSTER INFO until `field` has the expected value, since it takes a
+/// round of gossip for news to spread.
+async fn wait_for_info(client: &mut Client, field: &str, expected: &str) {
+    for _ in 0..50 {
+        let info = client.command(&["CLUSTER", "INFO"]).await.unwrap();
+        if info_field(&info, field) == expected {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    panic!("{} never became {}", field, expected);
+}
+
+async fn my_id(client: &mut Client) -> String {
+    let id = client.command(&["CLUSTER", "MYID"]).await.unwrap();
+    id.as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn nodes_that_meet_gossip_about_their_slots_and_other_nodes() {
+    let (a, mut client_a) = cluster_node().await;
+    let (b, mut client_b) = cluster_node().await;
+    let (_c, mut client_c) = cluster_node().await;
+
+    client_a
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "0", "8191"])
+        .await
+        .unwrap();
+    client_b
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "8192", "16383"])
+        .await
+        .unwrap();
+
+    // A introduces itself to B, and C to A. B hears of C from A.
+    meet(&mut client_a, &b).await;
+    meet(&mut client_c, &a).await;
+    for client in [&mut client_a, &mut client_b, &mut client_c] {
+        wait_for_info(client, "cluster_known_nodes", "3").await;
+        wait_for_info(client, "cluster_state", "ok").await;
+        wait_for_info(client, "cluster_size", "2").await;
+    }
+
+    let nodes = client_c.command(&["CLUSTER", "NODES"]).await.unwrap();
+    let b_id = my_id(&mut client_b).await;
+    let b_line = nodes
+        .as_str()
+        .unwrap()
+        .lines()
+        .find(|line| line.starts_with(&b_id))
+        .unwrap()
+        .to_string();
+    assert!(b_line.ends_with(" 8192-16383"), "{}", b_line);
+
+    // foo hashes to slot 12182, which B serves
+    let moved = Value::error(format!("MOVED 12182 {}", b.address.name()));
+    assert_eq!(client_a.command(&["GET", "foo"]).await.unwrap(), moved);
+    assert_eq!(client_c.command(&["SET", "foo", "1"]).await.unwrap(), moved);
+    assert_eq!(
+        client_b.command(&["SET", "foo", "1"]).await.unwrap(),
+        Value::ok()
+    );
+}
+
+#[tokio::test]
+async fn slots_can_be_migrated_between_nodes() {
+    let (a, mut client_a) = cluster_node().await;
+    let (b, mut client_b) = cluster_node().await;
+    client_a
+        .command(&["CLUSTER", "ADDSLOTSRANGE", "0", "16383"])
+        .await
+        .unwrap();
+    meet(&mut client_a, &b).await;
+    wait_for_info(&mut client_b, "cluster_state", "ok").await;
+
+    let (a_id, b_id) = (my_id(&mut client_a).await, my_id(&mut client_b).await);
+    client_a.set("foo", "bar").await.unwrap();
+    client_a.set("{foo}:other", "baz").await.unwrap();
+
+    let reply = client_b
+        .command(&["CLUSTER", "SETSLOT", "12182", "IMPORTING", &a_id])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client_a
+        .command(&["CLUSTER", "SETSLOT", "12182", "MIGRATING", &b_id])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    let keys = client_a
+        .command(&["CLUSTER", "COUNTKEYSINSLOT", "12182"])
+        .await
+        .unwrap();
+    assert_eq!(keys, Value::Integer(2));
+
+    let b_port = b.address.port().to_string();
+    let reply = client_a
+        .command(&["MIGRATE", "127.0.0.1", &b_port, "foo", "0", "5000"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // With one key gone, A sends clients that want it to B, which only
+    // serves it to clients that ask
+    let ask = Value::error(format!("ASK 12182 {}", b.address.name()));
+    assert_eq!(client_a.command(&["GET", "foo"]).await.unwrap(), ask);
+    assert_eq!(
+        client_a.command(&["GET", "{foo}:other"]).await.unwrap(),
+        Value::from("baz")
+    );
+    let moved_to_a = Value::error(format!("MOVED 12182 {}", a.address.name()));
+    assert_eq!(client_b.command(&["GET", "foo"]).await.unwrap(), moved_to_a);
+    client_b.command(&["ASKING"]).await.unwrap();
+    assert_eq!(
+        client_b.command(&["GET", "foo"]).await.unwrap(),
+        Value::from("bar")
+    );
+
+    let reply = client_a
+        .command(&["CLUSTER", "SETSLOT", "12182", "NODE", &b_id])
+        .await
+        .unwrap();
+    assert!(matches!(reply, Value::Error(_)), "{:?}", reply);
+
+    let reply = client_a
+        .command(&[
+            "MIGRATE",
+            "127.0.0.1",
+            &b_port,
+            "",
+            "0",
+            "5000",
+            "KEYS",
+            "{foo}:other",
+            "{foo}:missing",
+        ])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let keys = client_a
+        .command(&["CLUSTER", "GETKEYSINSLOT", "12182", "10"])
+        .await
+        .unwrap();
+    assert_eq!(keys, Value::Array(vec![]));
+
+    for client in [&mut client_b, &mut client_a] {
+        let reply = client
+            .command(&["CLUSTER", "SETSLOT", "12182", "NODE", &b_id])
+            .await
+            .unwrap();
+        assert_eq!(reply, Value::ok());
+    }
+
+    // B took the slot over with a new epoch, so A doesn't get it back from gossip
+    wait_for_info(&mut client_a, "cluster_current_epoch", "1").await;
+    let moved_to_b = Value::error(format!("MOVED 12182 {}", b.address.name()));
+    assert_eq!(client_a.command(&["GET", "foo"]).await.unwrap(), moved_to_b);
+    assert_eq!(
+        client_b.command(&["GET", "{foo}:other"]).await.unwrap(),
+        Value::from("baz")
+    );
+    // Every other slot is still A's
+    assert_eq!(
+        client_b.command(&["GET", "bar"]).await.unwrap(),
+        Value::error(format!("MOVED 5061 {}", a.address.name()))
+    );
+}
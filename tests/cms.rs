@@ -0,0 +1,234 @@
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+use not_redis::client::Client;
+use not_redis::encoding::{self, ModuleField};
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn integers(values: &[i64]) -> Value {
+    Value::Array(values.iter().copied().map(Value::Integer).collect())
+}
+
+#[tokio::test]
+async fn items_can_be_counted_and_queried() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client
+        .command(&["CMS.INITBYDIM", "sketch", "100", "4"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client
+        .command(&["CMS.INITBYPROB", "sketch", "0.01", "0.01"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR CMS: key already exists"));
+
+    let reply = client
+        .command(&[
+            "CMS.INCRBY",
+            "sketch",
+            "apple",
+            "3",
+            "pear",
+            "1",
+            "apple",
+            "2",
+        ])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[3, 1, 5]));
+    let reply = client
+        .command(&["CMS.QUERY", "sketch", "apple", "pear", "plum"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[5, 1, 0]));
+
+    let reply = client
+        .command(&["CMS.INCRBY", "sketch", "apple", "x"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR CMS: Cannot parse number"));
+    let reply = client
+        .command(&["CMS.INCRBY", "sketch", "apple", "1", "pear"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR syntax error"));
+    let reply = client
+        .command(&["CMS.INCRBY", "nothing", "apple", "1"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR CMS: key does not exist"));
+    let reply = client.command(&["CMS.QUERY", "nothing", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR CMS: key does not exist"));
+
+    for (command, error) in [
+        (
+            ["CMS.INITBYDIM", "other", "0", "4"],
+            "ERR CMS: invalid width",
+        ),
+        (
+            ["CMS.INITBYDIM", "other", "10", "x"],
+            "ERR CMS: invalid depth",
+        ),
+        (
+            ["CMS.INITBYPROB", "other", "1.5", "0.1"],
+            "ERR CMS: invalid overestimation value",
+        ),
+        (
+            ["CMS.INITBYPROB", "other", "0.1", "0"],
+            "ERR CMS: invalid prob value",
+        ),
+    ] {
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+
+    let reply = client.command(&["TYPE", "sketch"]).await;
+    assert_eq!(reply.unwrap(), Value::from("CMSk-TYPE"));
+}
+
+#[tokio::test]
+async fn sketches_can_be_merged() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for key in ["{sketch}a", "{sketch}b", "{sketch}c"] {
+        client
+            .command(&["CMS.INITBYDIM", key, "100", "4"])
+            .await
+            .unwrap();
+    }
+    client
+        .command(&["CMS.INCRBY", "{sketch}a", "apple", "2"])
+        .await
+        .unwrap();
+    client
+        .command(&["CMS.INCRBY", "{sketch}b", "apple", "1", "pear", "4"])
+        .await
+        .unwrap();
+
+    let reply = client
+        .command(&["CMS.MERGE", "{sketch}c", "2", "{sketch}a", "{sketch}b"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client
+        .command(&["CMS.QUERY", "{sketch}c", "apple", "pear"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[3, 4]));
+
+    // The destination can be one of the sources
+    let reply = client
+        .command(&[
+            "CMS.MERGE",
+            "{sketch}a",
+            "2",
+            "{sketch}a",
+            "{sketch}b",
+            "WEIGHTS",
+            "3",
+            "2",
+        ])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client
+        .command(&["CMS.QUERY", "{sketch}a", "apple", "pear"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[8, 8]));
+
+    client
+        .command(&["CMS.INITBYDIM", "{sketch}small", "10", "4"])
+        .await
+        .unwrap();
+    for (command, error) in [
+        (
+            vec!["CMS.MERGE", "{sketch}c", "1", "{sketch}small"],
+            "ERR CMS: width/depth is not equal",
+        ),
+        (
+            vec!["CMS.MERGE", "{sketch}c", "1", "{sketch}none"],
+            "ERR CMS: key does not exist",
+        ),
+        (
+            vec!["CMS.MERGE", "{sketch}none", "1", "{sketch}a"],
+            "ERR CMS: key does not exist",
+        ),
+        (
+            vec!["CMS.MERGE", "{sketch}c", "3", "{sketch}a"],
+            "ERR CMS: invalid numkeys",
+        ),
+        (
+            vec!["CMS.MERGE", "{sketch}c", "1", "{sketch}a", "WEIGHTS", "x"],
+            "ERR CMS: invalid weight value",
+        ),
+        (
+            vec!["CMS.MERGE", "{sketch}c", "1", "{sketch}a", "WEIGHTS"],
+            "ERR syntax error",
+        ),
+    ] {
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+}
+
+#[tokio::test]
+async fn sketches_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client
+        .command(&["CMS.INITBYPROB", "sketch", "0.001", "0.01"])
+        .await
+        .unwrap();
+    client
+        .command(&["CMS.INCRBY", "sketch", "apple", "7", "pear", "3"])
+        .await
+        .unwrap();
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client
+        .command(&["CMS.QUERY", "sketch", "apple", "pear"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[7, 3]));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn sketches_too_big_to_hold_are_not_restored() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+    client
+        .command(&["CMS.INITBYDIM", "sketch", "1", "1"])
+        .await
+        .unwrap();
+    let reply = client.command(&["DUMP", "sketch"]).await.unwrap();
+    let payload = hex::decode(reply.as_str().unwrap()).unwrap();
+
+    // (2^61 + 1) * 1 counters of 8 bytes wrap around to the one counter saved
+    let (body, footer) = payload.split_at(payload.len() - 10);
+    let mut cursor = Cursor::new(body[1..].to_vec());
+    let (name, version, mut fields) = encoding::decode_module_value(&mut cursor).unwrap();
+    fields[0] = ModuleField::Uint((1 << 61) + 1);
+    let mut forged = vec![body[0]];
+    forged.extend(encoding::encode_module_value(
+        &name, version, &fields, false,
+    ));
+    forged.extend(&footer[..2]);
+    forged.extend(encoding::crc64(0, &forged).to_le_bytes());
+
+    let reply = client
+        .command(&["RESTORE", "forged", "0", &hex::encode(forged)])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR DUMP payload version or checksum are wrong")
+    );
+    let reply = client.command(&["EXISTS", "forged"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
@@ -4,17 +4,17 @@ use std::sync::Mutex;
 
 use rand::Rng;
 use tokio::net::TcpListener;
-use tokio::sync::broadcast;
 use tokio::time;
 
 use not_redis::app;
+use not_redis::client::Client;
+use not_redis::clock::MockClock;
+use not_redis::extension::Extension;
 use not_redis::server::{
-    generate_random_sha1_hex, sync_to_master, Address, Config, RedisServer, Replication, Server,
-    ServerRole,
+    generate_random_sha1_hex, Address, Config, RedisServer, Replication, ServerBuilder, ServerRole,
 };
 
 use not_redis::data::Database;
-use not_redis::transmission::Transmission;
 
 const MIN_PORT: u16 = 2000;
 const MAX_PORT: u16 = u16::MAX;
@@ -24,7 +24,6 @@ pub struct TestApp {
     pub database: Database,
     pub redis_server: RedisServer,
     pub address: Address,
-    pub transmitter: broadcast::Sender<Transmission>,
     join_handle: tokio::task::JoinHandle<()>,
 }
 
@@ -35,53 +34,76 @@ enum TestAppRole {
 
 impl TestApp {
     pub async fn with_config(config: Config) -> TestApp {
-        TestApp::new(TestAppRole::Master, Some(config)).await
+        TestApp::new(TestAppRole::Master, Some(config), None, None).await
+    }
+
+    pub async fn with_extension(config: Config, extension: impl Extension + 'static) -> TestApp {
+        TestApp::new(
+            TestAppRole::Master,
+            Some(config),
+            Some(Box::new(extension)),
+            None,
+        )
+        .await
+    }
+
+    /// A master whose keys expire by `clock`, so tests can move time on instead of sleeping.
+    pub async fn with_clock(clock: MockClock) -> TestApp {
+        TestApp::new(TestAppRole::Master, None, None, Some(clock)).await
     }
 
     pub async fn master() -> TestApp {
-        TestApp::new(TestAppRole::Master, None).await
+        TestApp::new(TestAppRole::Master, None, None, None).await
+    }
+
+    /// A master and a client already connected to it.
+    pub async fn master_with_client() -> (TestApp, Client) {
+        let test_app = TestApp::master().await;
+        let client = Client::connect(test_app.address.name()).await.unwrap();
+        (test_app, client)
     }
 
     pub async fn slave(address: Address) -> TestApp {
-        TestApp::new(TestAppRole::Slave(address), None).await
+        TestApp::new(TestAppRole::Slave(address), None, None, None).await
     }
 
-    async fn new(role: TestAppRole, config: Option<Config>) -> TestApp {
-        let (tx, _) = broadcast::channel::<Transmission>(100);
+    pub async fn slave_with_config(address: Address, config: Config) -> TestApp {
+        TestApp::new(TestAppRole::Slave(address), Some(config), None, None).await
+    }
 
-        let config = config.unwrap_or_else(|| Config::new(None, None));
-        let database = match (&config.dir, &config.db_file_name) {
-            (Some(dir), Some(file_name)) => {
-                let path = PathBuf::from(dir).join(file_name);
-                Database::from_config(path).unwrap()
-            }
-            _ => Database::new(),
-        };
+    async fn new(
+        role: TestAppRole,
+        config: Option<Config>,
+        extension: Option<Box<dyn Extension>>,
+        clock: Option<MockClock>,
+    ) -> TestApp {
+        let mut config = config.unwrap_or_else(|| Config::new(None, None));
+        // The cluster bus would be on the port plus 10000, which may not even be valid
+        if config.cluster_enabled && config.cluster_port == 0 {
+            config.cluster_port = get_available_port().await;
+        }
 
         let port = get_available_port().await;
         let address = Address::new("127.0.0.1".into(), port);
 
-        let (replication, role) = match role {
-            TestAppRole::Master => master_server_role(),
-            TestAppRole::Slave(master_address) => {
-                sync_to_master(master_address, &address, database.clone())
-                    .await
-                    .expect("Failed to sync to master")
-            }
-        };
-
-        let settings = Server::new(config, role, address.clone(), replication);
-        let redis_server = RedisServer::new(settings);
+        let mut builder = ServerBuilder::new().config(config).port(port);
+        if let TestAppRole::Slave(master_address) = role {
+            builder = builder.replica_of(master_address);
+        }
+        if let Some(extension) = extension {
+            builder = builder.extension(extension);
+        }
+        if let Some(clock) = clock {
+            builder = builder.clock(clock);
+        }
+        let (database, redis_server) = builder.build().await.expect("Failed to build server");
 
         let addr = address.name().clone();
         let db = database.clone();
         let rs = redis_server.clone();
-        let transmitter = tx.clone();
 
         let join_handle = tokio::spawn(async move {
-            app::run(&addr, db, rs, transmitter)
-                .await
-                .expect("Failed to run app");
+            app::run(&addr, db, rs).await.expect("Failed to run app");
         });
 
         time::sleep(TIMEOUT).await;
@@ -92,7 +114,6 @@ impl TestApp {
             database,
             redis_server,
             address,
-            transmitter: tx,
             join_handle,
         };
 
@@ -130,7 +151,7 @@ pub fn master_server_role() -> (Replication, ServerRole) {
         id: generate_random_sha1_hex(),
         offset: 0,
     };
-    let role = ServerRole::Master(vec![], 0, 0);
+    let role = ServerRole::Master(vec![]);
     (replication, role)
 }
 
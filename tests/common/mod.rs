@@ -7,5 +7,6 @@ mod message;
 pub use app::TestApp;
 
 pub use message::{
-    encode_stream_items, encode_streams, encode_string, send_message, StreamData, StreamItem,
+    encode_stream_items, encode_streams, encode_string, encode_string_array, send_message,
+    StreamData, StreamItem,
 };
@@ -1,4 +1,7 @@
-use not_redis::encoding::{bulk_string, empty_string, encode_string_array};
+use not_redis::encoding::{
+    bulk_string, empty_string, encode_string_array, error_string, simple_string,
+};
+use not_redis::request::ConfigKey;
 use not_redis::server::Config;
 
 use common::{encode_string, send_message, TestApp};
@@ -50,3 +53,119 @@ async fn preset_values_from_valid_rdb() {
 
     assert_eq!(resp, bulk_string("baz"));
 }
+
+#[tokio::test]
+async fn set_config_validates_value() {
+    let test_app = TestApp::master().await;
+
+    let message = encode_string("config set maxclients 20");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("config get maxclients");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(resp, encode_string_array(&["maxclients", "20"]));
+
+    let message = encode_string("config set maxclients lots");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(
+        resp,
+        error_string("ERR CONFIG SET failed (possibly related to argument 'maxclients') - argument couldn't be parsed into an integer")
+    );
+
+    let message = encode_string("config get maxclients");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(resp, encode_string_array(&["maxclients", "20"]));
+}
+
+#[tokio::test]
+async fn set_config_rejects_immutable_options() {
+    let test_app = TestApp::master().await;
+
+    let message = encode_string("config set appendonly yes");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(
+        resp,
+        error_string("ERR CONFIG SET failed (possibly related to argument 'appendonly') - can't set immutable config")
+    );
+
+    let message = encode_string("config get appendonly");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(resp, encode_string_array(&["appendonly", "no"]));
+}
+
+#[tokio::test]
+async fn container_commands_list_their_subcommands() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("config help");
+    let resp = send_message(&address, &message).await;
+    let want = [
+        "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+        "GET <parameter>",
+        "    Return the value of <parameter>.",
+        "SET <parameter> <value>",
+        "    Set the configuration <parameter> to <value>.",
+        "HELP",
+        "    Print this help.",
+    ]
+    .iter()
+    .map(|line| simple_string(line))
+    .collect::<String>();
+    assert_eq!(resp, format!("*7\r\n{}", want));
+
+    let message = encode_string("object HELP");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.starts_with(&format!(
+        "*14\r\n{}{}",
+        simple_string("OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:"),
+        simple_string("CHUNKS <key>")
+    )));
+
+    let message = encode_string("cluster help");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains(&simple_string("KEYSLOT <key>")));
+
+    let message = encode_string("config rewrite");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        error_string("ERR unknown subcommand 'rewrite'. Try CONFIG HELP.")
+    );
+}
+
+#[tokio::test]
+async fn connections_are_filtered_by_address() {
+    let refused = error_string("ERR connections from this address aren't allowed");
+    let loopback = "127.0.0.0/8 ::1";
+
+    let mut config = Config::new(None, None);
+    config.set(&ConfigKey::IpDenylist, loopback).unwrap();
+    let test_app = TestApp::with_config(config).await;
+    let resp = send_message(&test_app.address.name(), &encode_string("ping")).await;
+    assert_eq!(resp, refused);
+
+    let mut config = Config::new(None, None);
+    config.set(&ConfigKey::IpAllowlist, loopback).unwrap();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+    let resp = send_message(&address, &encode_string("ping")).await;
+    assert_eq!(resp, simple_string("PONG"));
+
+    let message = encode_string_array(&["config", "set", "ip-denylist", "10.0.0.0/33"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert!(resp.starts_with("-ERR CONFIG SET failed"));
+    let resp = send_message(&address, &encode_string("config get ip-allowlist")).await;
+    assert_eq!(
+        resp,
+        encode_string_array(&["ip-allowlist", "127.0.0.0/8 ::1/128"])
+    );
+
+    // Only new connections are checked against the rules
+    let message = encode_string_array(&["config", "set", "ip-allowlist", "10.0.0.0/8"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(resp, simple_string("OK"));
+    let resp = send_message(&address, &encode_string("ping")).await;
+    assert_eq!(resp, refused);
+}
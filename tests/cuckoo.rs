@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn items_can_be_added_counted_and_deleted() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for _ in 0..2 {
+        let reply = client.command(&["CF.ADD", "filter", "apple"]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(1));
+    }
+    let reply = client.command(&["CF.ADDNX", "filter", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["CF.ADDNX", "filter", "pear"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+
+    for (item, count) in [("apple", 2), ("pear", 1), ("plum", 0)] {
+        let reply = client.command(&["CF.COUNT", "filter", item]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(count), "{}", item);
+        let reply = client.command(&["CF.EXISTS", "filter", item]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(count.min(1)), "{}", item);
+    }
+
+    let reply = client.command(&["CF.DEL", "filter", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["CF.COUNT", "filter", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["CF.DEL", "filter", "plum"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client.command(&["CF.EXISTS", "nothing", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["CF.COUNT", "nothing", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["CF.DEL", "nothing", "apple"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR Not found"));
+
+    let reply = client.command(&["TYPE", "filter"]).await;
+    assert_eq!(reply.unwrap(), Value::from("MBbloomCF"));
+
+    client.command(&["BF.ADD", "bloom", "apple"]).await.unwrap();
+    let reply = client.command(&["CF.ADD", "bloom", "apple"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn filters_can_be_reserved() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for (args, error) in [
+        (vec!["nope"], "ERR Bad capacity"),
+        (vec!["0"], "ERR Bad capacity"),
+        (vec!["100", "BUCKETSIZE", "0"], "ERR Bad bucket size"),
+        (vec!["100", "BUCKETSIZE", "256"], "ERR Bad bucket size"),
+        (vec!["100", "MAXITERATIONS", "0"], "ERR Bad maxIterations"),
+        (vec!["100", "EXPANSION", "40000"], "ERR Bad expansion"),
+        (vec!["100", "EXPANSION"], "ERR Bad expansion"),
+        (vec!["100", "SOMETHING", "1"], "ERR syntax error"),
+        (
+            vec!["10", "BUCKETSIZE", "6"],
+            "ERR Capacity must be at least (BucketSize * 2)",
+        ),
+    ] {
+        let mut command = vec!["CF.RESERVE", "filter"];
+        command.extend(args);
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+
+    let reply = client
+        .command(&[
+            "CF.RESERVE",
+            "filter",
+            "8",
+            "BUCKETSIZE",
+            "2",
+            "MAXITERATIONS",
+            "10",
+            "EXPANSION",
+            "0",
+        ])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["CF.RESERVE", "filter", "100"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR item exists"));
+
+    let mut full = None;
+    for i in 0..100 {
+        let reply = client
+            .command(&["CF.ADD", "filter", &i.to_string()])
+            .await
+            .unwrap();
+        if reply != Value::Integer(1) {
+            full = Some(reply);
+            break;
+        }
+    }
+    assert_eq!(full, Some(Value::error("ERR Filter is full")));
+
+    let reply = client
+        .command(&["CF.RESERVE", "growing", "8", "EXPANSION", "2"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    for i in 0..100 {
+        let reply = client.command(&["CF.ADD", "growing", &i.to_string()]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(1));
+    }
+}
+
+#[tokio::test]
+async fn filters_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    let items: Vec<String> = (0..300).map(|i| format!("item-{}", i)).collect();
+    for item in items.iter() {
+        client.command(&["CF.ADD", "filter", item]).await.unwrap();
+    }
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    for item in items.iter() {
+        let reply = client.command(&["CF.EXISTS", "filter", item]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(1));
+    }
+    let reply = client.command(&["CF.DEL", "filter", "item-0"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,256 @@
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use not_redis::client::Client;
+use not_redis::clock::MockClock;
+use not_redis::data::Database;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn temp_config() -> Config {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+
+    Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    )
+}
+
+#[tokio::test]
+async fn each_connection_works_on_the_database_it_selected() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    let mut other = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "first"]).await.unwrap();
+    let reply = client.command(&["SELECT", "1"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+    client.command(&["SET", "name", "second"]).await.unwrap();
+
+    // Other connections stay where they were
+    let reply = other.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::from("first"));
+    let database = &test_app.database;
+    assert_eq!(
+        database.select(1).get("name").unwrap(),
+        Some("second".into())
+    );
+
+    let reply = client.command(&["FLUSHDB"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = other.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::from("first"));
+
+    client.command(&["SET", "name", "second"]).await.unwrap();
+    let reply = other.command(&["FLUSHALL"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+}
+
+#[tokio::test]
+async fn swapping_databases_exchanges_their_keys_and_deadlines() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "first"]).await.unwrap();
+    client.command(&["SELECT", "1"]).await.unwrap();
+    client.command(&["SET", "name", "second"]).await.unwrap();
+    client
+        .command(&["SET", "temporary", "value", "PX", "100"])
+        .await
+        .unwrap();
+
+    let reply = client.command(&["SWAPDB", "1", "0"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::from("first"));
+    client.command(&["SELECT", "0"]).await.unwrap();
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::from("second"));
+
+    // The key expires from the database it was swapped into
+    clock.advance(Duration::from_millis(200));
+    let reply = client.command(&["EXISTS", "temporary"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = client.command(&["SWAPDB", "0", "16"]).await.unwrap();
+    assert_eq!(reply, Value::error("ERR DB index is out of range"));
+    let reply = client.command(&["SWAPDB", "first", "1"]).await.unwrap();
+    assert_eq!(reply, Value::error("ERR invalid first DB index"));
+    let reply = client.command(&["SWAPDB", "0", "-1"]).await.unwrap();
+    assert_eq!(reply, Value::error("ERR invalid second DB index"));
+}
+
+#[tokio::test]
+async fn keys_can_be_moved_to_a_database_that_doesnt_have_them() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["SET", "name", "first", "PX", "100"])
+        .await
+        .unwrap();
+    client.command(&["SET", "taken", "first"]).await.unwrap();
+    let database = &test_app.database;
+    database.select(2).set_string("taken", "third").unwrap();
+
+    let reply = client.command(&["MOVE", "name", "2"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["MOVE", "name", "2"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+    let reply = client.command(&["MOVE", "taken", "2"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+    assert_eq!(database.get("taken").unwrap(), Some("first".into()));
+    assert_eq!(
+        database.select(2).get("taken").unwrap(),
+        Some("third".into())
+    );
+
+    // The moved key keeps its deadline
+    client.command(&["SELECT", "2"]).await.unwrap();
+    let reply = client.command(&["PTTL", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(100));
+    clock.advance(Duration::from_millis(200));
+    let reply = client.command(&["EXISTS", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = client.command(&["MOVE", "taken", "2"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR source and destination objects are the same")
+    );
+    let reply = client.command(&["MOVE", "taken", "16"]).await.unwrap();
+    assert_eq!(reply, Value::error("ERR DB index is out of range"));
+}
+
+#[tokio::test]
+async fn only_the_configured_databases_can_be_selected() {
+    let mut config = Config::new(None, None);
+    config.databases = 4;
+    let test_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["SELECT", "3"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    for index in ["4", "-1"] {
+        let reply = client.command(&["SELECT", index]).await.unwrap();
+        assert_eq!(reply, Value::error("ERR DB index is out of range"));
+    }
+    let reply = client.command(&["SELECT", "first"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR value is not an integer or out of range")
+    );
+
+    let reply = client
+        .command(&["CONFIG", "GET", "databases"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::bulk_array(&["databases", "4"]));
+    let reply = client
+        .command(&["CONFIG", "SET", "databases", "8"])
+        .await
+        .unwrap();
+    assert!(matches!(reply, Value::Error(..)));
+}
+
+#[tokio::test]
+async fn every_database_is_saved_and_loaded() {
+    let config = temp_config();
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "first"]).await.unwrap();
+    client.command(&["SELECT", "3"]).await.unwrap();
+    client.command(&["SET", "name", "fourth"]).await.unwrap();
+    let reply = client.command(&["SAVE"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("name").unwrap(), Some("first".into()));
+    assert_eq!(
+        database.select(3).get("name").unwrap(),
+        Some("fourth".into())
+    );
+    assert!(database.select(1).is_empty());
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    client.command(&["SELECT", "3"]).await.unwrap();
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::from("fourth"));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn writes_are_replayed_from_the_aof_into_their_database() {
+    let mut config = temp_config();
+    config.append_only = true;
+    let dir = config.aof_dir();
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "first"]).await.unwrap();
+    client.command(&["SELECT", "2"]).await.unwrap();
+    client.command(&["SET", "name", "third"]).await.unwrap();
+    client.command(&["INCR", "counter"]).await.unwrap();
+    client.command(&["SELECT", "0"]).await.unwrap();
+    client.command(&["DEL", "name"]).await.unwrap();
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config).await;
+    let database = &restored_app.database;
+    assert_eq!(database.get("name").unwrap(), None);
+    assert_eq!(
+        database.select(2).get("name").unwrap(),
+        Some("third".into())
+    );
+    assert_eq!(database.select(2).get("counter").unwrap(), Some("1".into()));
+
+    fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn replicas_apply_writes_to_the_same_database() {
+    let master = TestApp::master().await;
+    let replica = TestApp::slave(master.address.clone()).await;
+    let mut client = Client::connect(master.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "first"]).await.unwrap();
+    client.command(&["SELECT", "5"]).await.unwrap();
+    client.command(&["SET", "name", "sixth"]).await.unwrap();
+    client.command(&["SELECT", "0"]).await.unwrap();
+    client.command(&["SET", "other", "first"]).await.unwrap();
+    client.command(&["SET", "moved", "first"]).await.unwrap();
+    client.command(&["MOVE", "moved", "7"]).await.unwrap();
+    let reply = client.command(&["WAIT", "1", "1000"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+
+    let database = &replica.database;
+    assert_eq!(database.get("name").unwrap(), Some("first".into()));
+    assert_eq!(database.get("other").unwrap(), Some("first".into()));
+    assert_eq!(
+        database.select(5).get("name").unwrap(),
+        Some("sixth".into())
+    );
+    assert_eq!(database.select(5).get("other").unwrap(), None);
+    assert_eq!(database.get("moved").unwrap(), None);
+    assert_eq!(
+        database.select(7).get("moved").unwrap(),
+        Some("first".into())
+    );
+}
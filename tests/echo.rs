@@ -1,5 +1,5 @@
 use common::{encode_string, send_message, TestApp};
-use not_redis::encoding::bulk_string;
+use not_redis::encoding::{bulk_string, error_string};
 
 mod common;
 
@@ -18,7 +18,10 @@ async fn echo_fail_if_short() {
 
     let message = encode_string("echo");
     let resp = send_message(&test_app.address.name(), &message).await;
-    assert_eq!(resp, "usage echo message");
+    assert_eq!(
+        resp,
+        error_string("ERR wrong number of arguments for 'echo' command")
+    );
 }
 
 #[tokio::test]
@@ -27,5 +30,8 @@ async fn echo_fail_if_long() {
 
     let message = encode_string("echo hello bye");
     let resp = send_message(&test_app.address.name(), &message).await;
-    assert_eq!(resp, "usage echo message");
+    assert_eq!(
+        resp,
+        error_string("ERR wrong number of arguments for 'echo' command")
+    );
 }
@@ -0,0 +1,129 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{sleep, Duration};
+
+use not_redis::clock::MockClock;
+use not_redis::data::{Database, StreamEntry, StreamId};
+use not_redis::hooks::KeyEvent;
+
+#[tokio::test]
+async fn strings_can_be_read_and_written_without_a_server() {
+    let database = Database::new();
+
+    assert_eq!(database.get_string("foo").unwrap(), None);
+    database.set_string("foo", "bar").unwrap();
+    assert_eq!(database.get_string("foo").unwrap(), Some("bar".to_string()));
+    assert_eq!(database.key_type("foo"), Some("string"));
+    assert_eq!(database.ttl("foo"), None);
+
+    assert!(database.delete("foo"));
+    assert!(!database.delete("foo"));
+    assert!(!database.exists("foo"));
+}
+
+#[tokio::test]
+async fn keys_set_with_a_ttl_expire() {
+    let database = Database::new();
+
+    database
+        .set_with_ttl("foo", "bar", Duration::from_millis(100))
+        .unwrap();
+    let ttl = database.ttl("foo").unwrap();
+    assert!(ttl > Duration::ZERO && ttl <= Duration::from_millis(100));
+
+    sleep(Duration::from_millis(200)).await;
+    assert_eq!(database.get_string("foo").unwrap(), None);
+}
+
+#[tokio::test]
+async fn keys_expire_by_the_database_clock() {
+    let clock = MockClock::starting_now();
+    let database = Database::new().with_clock(clock.clone());
+
+    database
+        .set_with_ttl("foo", "bar", Duration::from_secs(60))
+        .unwrap();
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(database.ttl("foo"), Some(Duration::from_secs(40)));
+
+    clock.advance(Duration::from_secs(40));
+    tokio::task::yield_now().await;
+    assert_eq!(database.get_string("foo").unwrap(), None);
+}
+
+#[tokio::test]
+async fn streams_hand_back_their_entries() {
+    let database = Database::new();
+
+    let first = database
+        .xadd(
+            "stream",
+            Some(StreamId::new(1, 1)),
+            vec![("a".to_string(), "1".to_string())],
+        )
+        .unwrap();
+    assert_eq!(first.to_string(), "1-1");
+
+    let second = database
+        .xadd("stream", None, vec![("b".to_string(), "2".to_string())])
+        .unwrap();
+    assert!(second > first);
+
+    let entries = database.xrange("stream", None, None).unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            StreamEntry {
+                id: first,
+                fields: vec![("a".to_string(), "1".to_string())],
+            },
+            StreamEntry {
+                id: second,
+                fields: vec![("b".to_string(), "2".to_string())],
+            },
+        ]
+    );
+
+    let entries = database.xrange("stream", Some(second), None).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    database.set_string("foo", "bar").unwrap();
+    assert!(database.xrange("foo", None, None).is_err());
+    assert!(database.xadd("foo", None, vec![]).is_err());
+}
+
+#[tokio::test]
+async fn hooks_are_told_about_changes_to_keys() {
+    let database = Database::new();
+    let events: Arc<Mutex<Vec<(String, KeyEvent)>>> = Arc::default();
+    let record = |events: &Arc<Mutex<Vec<(String, KeyEvent)>>>| {
+        let events = events.clone();
+        move |key: &str, event| events.lock().unwrap().push((key.to_string(), event))
+    };
+    database.on_set(record(&events));
+    database.on_delete(record(&events));
+    database.on_expire(record(&events));
+    database.on_xadd(record(&events));
+
+    database.set_string("foo", "bar").unwrap();
+    assert!(database.delete("foo"));
+    assert!(!database.delete("foo"));
+    database
+        .xadd("stream", None, vec![("a".to_string(), "1".to_string())])
+        .unwrap();
+    database
+        .set_with_ttl("temp", "value", Duration::from_millis(50))
+        .unwrap();
+    sleep(Duration::from_millis(150)).await;
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            ("foo".to_string(), KeyEvent::Set),
+            ("foo".to_string(), KeyEvent::Delete),
+            ("stream".to_string(), KeyEvent::Xadd),
+            ("temp".to_string(), KeyEvent::Set),
+            ("temp".to_string(), KeyEvent::Expire),
+        ]
+    );
+}
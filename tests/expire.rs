@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use not_redis::client::Client;
+use not_redis::clock::MockClock;
+use not_redis::resp::Value;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn keys_of_any_type_can_be_given_a_ttl() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["XADD", "events", "1-1", "field", "value"])
+        .await
+        .unwrap();
+    client.command(&["SET", "name", "value"]).await.unwrap();
+
+    let reply = client.command(&["EXPIRE", "events", "10"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["PEXPIRE", "name", "500"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["EXPIRE", "missing", "10"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+    let reply = client.command(&["PTTL", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(500));
+
+    clock.advance(Duration::from_millis(600));
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+    let reply = client.command(&["TYPE", "events"]).await.unwrap();
+    assert_eq!(reply, Value::from("stream"));
+
+    clock.advance(Duration::from_secs(10));
+    let reply = client.command(&["TYPE", "events"]).await.unwrap();
+    assert_eq!(reply, Value::from("none"));
+
+    // A TTL that isn't positive deletes the key there and then
+    client.command(&["SET", "name", "value"]).await.unwrap();
+    let reply = client.command(&["EXPIRE", "name", "-1"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["GET", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+}
+
+#[tokio::test]
+async fn keys_can_be_expired_at_a_point_in_time() {
+    let clock = MockClock::new(1_700_000_000_000);
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client.command(&["SET", "seconds", "value"]).await.unwrap();
+    client.command(&["SET", "millis", "value"]).await.unwrap();
+
+    let reply = client
+        .command(&["EXPIREAT", "seconds", "1700000010"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client
+        .command(&["PEXPIREAT", "millis", "1700000000500", "NX"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(1));
+
+    clock.advance(Duration::from_millis(500));
+    let reply = client.command(&["GET", "millis"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+    let reply = client.command(&["GET", "seconds"]).await.unwrap();
+    assert_eq!(reply, Value::from("value"));
+
+    // A time that's already gone deletes the key
+    let reply = client
+        .command(&["EXPIREAT", "seconds", "1600000000"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["GET", "seconds"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+}
+
+#[tokio::test]
+async fn ttls_are_only_set_when_the_options_allow() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client.command(&["SET", "key", "value"]).await.unwrap();
+
+    let expire = |ttl: &'static str, options: &'static [&'static str]| {
+        let mut args = vec!["EXPIRE", "key", ttl];
+        args.extend(options);
+        args
+    };
+    let cases: &[(&str, &[&str], i64)] = &[
+        ("100", &["XX"], 0),
+        ("100", &["GT"], 0),
+        ("100", &["NX"], 1),
+        ("50", &["NX"], 0),
+        ("200", &["LT"], 0),
+        ("50", &["lt"], 1),
+        ("20", &["GT"], 0),
+        ("80", &["XX", "GT"], 1),
+    ];
+    for (ttl, options, expected) in cases {
+        let reply = client.command(&expire(ttl, options)).await.unwrap();
+        assert_eq!(
+            reply,
+            Value::Integer(*expected),
+            "EXPIRE {} {:?}",
+            ttl,
+            options
+        );
+    }
+
+    // The TTL the options left the key with was 80 seconds
+    clock.advance(Duration::from_secs(79));
+    let reply = client.command(&["GET", "key"]).await.unwrap();
+    assert_eq!(reply, Value::from("value"));
+    clock.advance(Duration::from_secs(1));
+    let reply = client.command(&["GET", "key"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+
+    for options in [&["NX", "XX"][..], &["GT", "LT"], &["NX", "GT"], &["SOON"]] {
+        let reply = client.command(&expire("10", options)).await.unwrap();
+        assert!(matches!(reply, Value::Error(_)), "{:?}", options);
+    }
+}
+
+#[tokio::test]
+async fn ttls_report_how_long_keys_have_left() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["SET", "soon", "value", "PX", "10400"])
+        .await
+        .unwrap();
+    client.command(&["SET", "forever", "value"]).await.unwrap();
+
+    let reply = client.command(&["TTL", "soon"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(10));
+    let reply = client.command(&["PTTL", "soon"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(10_400));
+
+    clock.advance(Duration::from_millis(1_000));
+    let reply = client.command(&["PTTL", "soon"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(9_400));
+    // Rounded to the nearest second
+    clock.advance(Duration::from_millis(800));
+    let reply = client.command(&["TTL", "soon"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(9));
+
+    let reply = client.command(&["TTL", "forever"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(-1));
+    let reply = client.command(&["PTTL", "missing"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(-2));
+}
+
+#[tokio::test]
+async fn persisted_keys_stop_expiring() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["SET", "key", "value", "EX", "10"])
+        .await
+        .unwrap();
+    let reply = client.command(&["PERSIST", "key"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["PERSIST", "key"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+    let reply = client.command(&["PERSIST", "missing"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    clock.advance(Duration::from_secs(20));
+    let reply = client.command(&["GET", "key"]).await.unwrap();
+    assert_eq!(reply, Value::from("value"));
+    let reply = client.command(&["TTL", "key"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(-1));
+}
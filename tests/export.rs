@@ -0,0 +1,106 @@
+use not_redis::client::Client;
+use not_redis::resp::Value;
+
+use common::TestApp;
+
+mod common;
+
+async fn export(client: &mut Client) -> serde_json::Value {
+    let reply = client.command(&["EXPORT"]).await.unwrap();
+    serde_json::from_str(reply.as_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn exports_can_be_imported_into_another_server() {
+    let source = TestApp::master().await;
+    let mut client = Client::connect(source.address.name()).await.unwrap();
+    for command in [
+        vec!["SET", "greeting", "hello"],
+        vec!["SET", "temp", "soon", "PXAT", "4102444800000"],
+        vec!["XADD", "events", "1-1", "kind", "click", "x", "10"],
+        vec!["JSON.SET", "doc", "$", r#"{"a":[1,2]}"#],
+        vec!["BF.ADD", "seen", "alice"],
+    ] {
+        client.command(&command).await.unwrap();
+    }
+
+    let exported = export(&mut client).await;
+    assert_eq!(exported["version"], 1);
+    let keys = exported["keys"].as_array().unwrap();
+    let names: Vec<&str> = keys
+        .iter()
+        .map(|key| key["key"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["doc", "events", "greeting", "seen", "temp"]);
+    assert_eq!(
+        keys[1],
+        serde_json::json!({
+            "key": "events",
+            "type": "stream",
+            "expires_at": null,
+            "value": {
+                "last_id": "1-1",
+                "entries": [{ "id": "1-1", "fields": ["kind", "click", "x", "10"] }],
+            },
+        })
+    );
+    assert_eq!(keys[4]["expires_at"], 4102444800000u64);
+    assert_eq!(keys[3]["type"], "MBbloom--");
+    assert!(keys[3]["dump"].is_string());
+
+    let target = TestApp::master().await;
+    let mut target_client = Client::connect(target.address.name()).await.unwrap();
+    let export_text = exported.to_string();
+    let reply = target_client.command(&["IMPORT", &export_text]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(5));
+    assert_eq!(export(&mut target_client).await, exported);
+
+    let reply = target_client.command(&["GET", "greeting"]).await;
+    assert_eq!(reply.unwrap(), Value::from("hello"));
+    let reply = target_client.command(&["BF.EXISTS", "seen", "alice"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = target_client
+        .command(&["XADD", "events", "1-1", "a", "b"])
+        .await;
+    assert!(matches!(reply.unwrap(), Value::Error(_)));
+
+    // Existing keys are only overwritten when asked to
+    let reply = target_client.command(&["IMPORT", &export_text]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Error("BUSYKEY Target key name already exists.".into())
+    );
+    target_client
+        .command(&["SET", "greeting", "changed"])
+        .await
+        .unwrap();
+    let reply = target_client
+        .command(&["IMPORT", &export_text, "REPLACE"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(5));
+    let reply = target_client.command(&["GET", "greeting"]).await;
+    assert_eq!(reply.unwrap(), Value::from("hello"));
+}
+
+#[tokio::test]
+async fn malformed_exports_change_nothing() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let export = r#"{"version":1,"keys":[
+        {"key":"a","type":"string","expires_at":null,"value":"1"},
+        {"key":"b","type":"list","expires_at":null,"value":"not a list"}
+    ]}"#;
+    let reply = client.command(&["IMPORT", export]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Error("ERR Invalid export: key 'b' isn't a list".into())
+    );
+    let reply = client.command(&["GET", "a"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+
+    let reply = client
+        .command(&["IMPORT", r#"{"version":2,"keys":[]}"#])
+        .await;
+    assert!(matches!(reply.unwrap(), Value::Error(_)));
+}
@@ -0,0 +1,216 @@
+use std::env;
+use std::fs;
+use std::sync::Arc;
+
+use not_redis::client::Client;
+use not_redis::data::{Database, ModuleValue};
+use not_redis::encoding::ModuleField;
+use not_redis::errors::RedisError;
+use not_redis::extension::{Extension, ExtensionCommand, ExtensionType};
+use not_redis::request::CommandFlags;
+use not_redis::resp::Value;
+use not_redis::server::{Config, ServerBuilder};
+
+use common::TestApp;
+
+mod common;
+
+/// A value type an embedder might add: a counter that remembers how often it
+/// was bumped.
+#[derive(Debug, Clone, Default)]
+struct Tally {
+    count: u64,
+}
+
+impl ModuleValue for Tally {
+    const TYPE_NAME: &'static str = "TallyType";
+    const ENCODING_VERSION: u16 = 1;
+
+    fn to_fields(&self) -> Vec<ModuleField> {
+        vec![ModuleField::Uint(self.count)]
+    }
+
+    fn from_fields(fields: &[ModuleField]) -> Result<Self, anyhow::Error> {
+        let [count] = fields else {
+            anyhow::bail!("A tally is a single count");
+        };
+        Ok(Tally {
+            count: count.as_uint()?,
+        })
+    }
+}
+
+struct TallyBump;
+
+impl ExtensionCommand for TallyBump {
+    fn name(&self) -> &'static str {
+        "tally.bump"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::WRITE
+    }
+
+    fn keys<'a>(&self, args: &'a [String]) -> Vec<&'a str> {
+        vec![&args[0]]
+    }
+
+    fn call(&self, database: &Database, args: Vec<String>) -> Result<Value, RedisError> {
+        let count = database.update_module(&args[0], |tally: &mut Option<Tally>| {
+            let tally = tally.get_or_insert_with(Tally::default);
+            tally.count += 1;
+            Ok(tally.count)
+        })?;
+
+        Ok(Value::Integer(count as i64))
+    }
+}
+
+struct TallyGet;
+
+impl ExtensionCommand for TallyGet {
+    fn name(&self) -> &'static str {
+        "tally.get"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn flags(&self) -> CommandFlags {
+        CommandFlags::READONLY
+    }
+
+    fn call(&self, database: &Database, args: Vec<String>) -> Result<Value, RedisError> {
+        let count = database.read_module(&args[0], |tally: &Tally| tally.count)?;
+
+        Ok(Value::Integer(count.unwrap_or(0) as i64))
+    }
+}
+
+struct TallyExtension;
+
+impl Extension for TallyExtension {
+    fn name(&self) -> &'static str {
+        "tally"
+    }
+
+    fn commands(&self) -> Vec<Arc<dyn ExtensionCommand>> {
+        vec![Arc::new(TallyBump), Arc::new(TallyGet)]
+    }
+
+    fn types(&self) -> Vec<ExtensionType> {
+        vec![ExtensionType::of::<Tally>()]
+    }
+}
+
+/// Tries to take over a built in command.
+struct ClashingExtension;
+
+impl Extension for ClashingExtension {
+    fn name(&self) -> &'static str {
+        "clashing"
+    }
+
+    fn commands(&self) -> Vec<Arc<dyn ExtensionCommand>> {
+        struct Get;
+        impl ExtensionCommand for Get {
+            fn name(&self) -> &'static str {
+                "get"
+            }
+            fn arity(&self) -> i32 {
+                2
+            }
+            fn flags(&self) -> CommandFlags {
+                CommandFlags::READONLY
+            }
+            fn call(&self, _: &Database, _: Vec<String>) -> Result<Value, RedisError> {
+                Ok(Value::Null)
+            }
+        }
+
+        vec![Arc::new(Get)]
+    }
+}
+
+fn temp_config() -> (std::path::PathBuf, Config) {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+    (dir, config)
+}
+
+#[tokio::test]
+async fn extension_commands_run_like_built_in_ones() {
+    let (dir, config) = temp_config();
+    let test_app = TestApp::with_extension(config, TallyExtension).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    for expected in 1..=3 {
+        let reply = client.command(&["TALLY.BUMP", "visits"]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(expected));
+    }
+    let reply = client.command(&["tally.get", "visits"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(3));
+    let reply = client.command(&["TYPE", "visits"]).await;
+    assert_eq!(reply.unwrap(), Value::from("TallyType"));
+
+    let reply = client.command(&["TALLY.BUMP", "visits", "twice"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR wrong number of arguments for 'tally.bump' command")
+    );
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["TALLY.BUMP", "string"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+    let reply = client.command(&["GET", "visits"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn extension_values_survive_a_restart() {
+    let (dir, config) = temp_config();
+    let test_app = TestApp::with_extension(config.clone(), TallyExtension).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client.command(&["TALLY.BUMP", "visits"]).await.unwrap();
+    client.command(&["TALLY.BUMP", "visits"]).await.unwrap();
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_extension(config, TallyExtension).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client.command(&["TALLY.GET", "visits"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn extensions_cant_replace_built_in_commands() {
+    let Err(error) = ServerBuilder::new()
+        .extension(ClashingExtension)
+        .build()
+        .await
+    else {
+        panic!("The extension was loaded");
+    };
+
+    assert_eq!(
+        format!("{:#}", error),
+        "Loading extension clashing: Command get is already defined"
+    );
+}
@@ -1,9 +1,14 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use tokio::time::{sleep, Duration};
 
 use common::{encode_string, send_message, TestApp};
+use not_redis::client::Client;
+use not_redis::clock::MockClock;
 use not_redis::encoding::{
     bulk_string, empty_string, encode_integer, encode_string_array, error_string, simple_string,
 };
+use not_redis::resp::Value;
 
 mod common;
 
@@ -33,7 +38,10 @@ async fn set_missing_value() {
     let test_app = TestApp::master().await;
     let message = encode_string("set foo");
     let resp = send_message(&test_app.address.name(), &message).await;
-    assert_eq!(resp, "Missing value: SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |\n  EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]");
+    assert_eq!(
+        resp,
+        error_string("ERR wrong number of arguments for 'set' command")
+    );
 }
 
 #[tokio::test]
@@ -41,7 +49,25 @@ async fn set_missing_key_value() {
     let test_app = TestApp::master().await;
     let message = encode_string("set");
     let resp = send_message(&test_app.address.name(), &message).await;
-    assert_eq!(resp, "Missing key: SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |\n  EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]");
+    assert_eq!(
+        resp,
+        error_string("ERR wrong number of arguments for 'set' command")
+    );
+}
+
+#[tokio::test]
+async fn set_unknown_option() {
+    let test_app = TestApp::master().await;
+    let message = encode_string("set foo bar frobnicate");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(resp, error_string("ERR syntax error"));
+
+    let message = encode_string("set foo bar ex soon");
+    let resp = send_message(&test_app.address.name(), &message).await;
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
 }
 
 #[tokio::test]
@@ -117,7 +143,8 @@ async fn getdel_gets_then_deletes_key() {
 
 #[tokio::test]
 async fn set_overwrites_expiration_time() {
-    let test_app = TestApp::master().await;
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
     let address = test_app.address.name();
 
     let message = encode_string("set foo bar px 200");
@@ -128,16 +155,44 @@ async fn set_overwrites_expiration_time() {
     let resp = send_message(&address, &message).await;
     assert_eq!(resp, simple_string("OK"));
 
-    sleep(Duration::from_millis(300)).await;
+    clock.advance(Duration::from_millis(300));
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("baz"));
+}
+
+#[tokio::test]
+async fn earlier_expiration_does_not_remove_key_set_again() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo bar px 200");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("set foo baz px 800");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    clock.advance(Duration::from_millis(400));
 
     let message = encode_string("get foo");
     let resp = send_message(&address, &message).await;
     assert_eq!(resp, bulk_string("baz"));
+
+    clock.advance(Duration::from_millis(400));
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
 }
 
 #[tokio::test]
 async fn set_keepttl_does_not_overwrite_expiration_time() {
-    let test_app = TestApp::master().await;
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
     let address = test_app.address.name();
 
     let message = encode_string("set foo bar px 500");
@@ -148,7 +203,7 @@ async fn set_keepttl_does_not_overwrite_expiration_time() {
     let resp = send_message(&address, &message).await;
     assert_eq!(resp, simple_string("OK"));
 
-    sleep(Duration::from_millis(500)).await;
+    clock.advance(Duration::from_millis(500));
 
     let message = encode_string("get foo");
     let resp = send_message(&address, &message).await;
@@ -297,6 +352,69 @@ async fn getex_changes_item_expiration() {
     assert_eq!(resp, bulk_string("bar"));
 }
 
+#[tokio::test]
+async fn getex_without_options_keeps_item_expiration() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo bar px 300");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("getex foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
+}
+
+#[tokio::test]
+async fn getex_sets_absolute_expiration() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string(&format!("getex foo pxat {}", now + 300));
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    // A deadline that has already passed expires the key right away
+    let message = encode_string("set baz qux");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string(&format!("getex baz exat {}", now / 1000 - 10));
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("qux"));
+
+    sleep(Duration::from_millis(50)).await;
+
+    let message = encode_string("get baz");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
+}
+
 #[tokio::test]
 async fn incr_decr_num_string() {
     let test_app = TestApp::master().await;
@@ -362,19 +480,31 @@ async fn cannot_set_invalid_expiration_time() {
 
     let message = encode_string(&format!("set cool cooler ex {}", 10e100));
     let resp = send_message(&address, &message).await;
-    assert_eq!(resp, "ERR value is not an integer or out of range");
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
 
     let message = encode_string(&format!("getex cool px {}", 10e100));
     let resp = send_message(&address, &message).await;
-    assert_eq!(resp, "ERR value is not an integer or out of range");
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
 
     let message = encode_string(&format!("set cool cooler pxat {}", 10e100));
     let resp = send_message(&address, &message).await;
-    assert_eq!(resp, "ERR value is not an integer or out of range");
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
 
     let message = encode_string("set cool cooler exat hello");
     let resp = send_message(&address, &message).await;
-    assert_eq!(resp, "ERR value is not an integer or out of range");
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
 }
 
 #[tokio::test]
@@ -407,3 +537,311 @@ async fn cannot_get_item_not_a_string() {
         error_string("WRONGTYPE Operation against a key holding the wrong kind of value")
     );
 }
+
+#[tokio::test]
+async fn append_adds_to_the_end_of_strings() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("append foo hello");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(5));
+
+    let message = encode_string("set foo 12 px 100000");
+    send_message(&address, &message).await;
+    let message = encode_string("append foo 34");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(4));
+
+    let message = encode_string("incr foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(1235));
+    assert!(test_app.database.ttl("foo").is_some());
+
+    let message = encode_string("xadd stream 1-1 one two");
+    send_message(&address, &message).await;
+    let message = encode_string("append stream more");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        error_string("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn getrange_reads_part_of_a_string() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo hello-world");
+    send_message(&address, &message).await;
+
+    for (start, end, want) in [
+        ("0", "4", "hello"),
+        ("-5", "-1", "world"),
+        ("6", "100", "world"),
+        ("-100", "1", "he"),
+        ("5", "2", ""),
+        ("50", "60", ""),
+    ] {
+        let message = encode_string_array(&["getrange", "foo", start, end]);
+        let resp = send_message(&address, message.as_bytes()).await;
+        assert_eq!(resp, bulk_string(want), "{} {}", start, end);
+    }
+
+    let message = encode_string("getrange missing 0 -1");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string(""));
+
+    let message = encode_string("getrange foo a 1");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        error_string("ERR value is not an integer or out of range")
+    );
+}
+
+#[tokio::test]
+async fn setrange_overwrites_part_of_a_string() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo hello-world px 100000");
+    send_message(&address, &message).await;
+    let message = encode_string("setrange foo 6 redis");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(11));
+    let message = encode_string("get foo");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("hello-redis"));
+    assert!(test_app.database.ttl("foo").is_some());
+
+    // Past the end the gap is filled with zero bytes
+    let message = encode_string("setrange padded 3 abc");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(6));
+    let message = encode_string("get padded");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("\0\0\0abc"));
+
+    let message = encode_string_array(&["setrange", "empty", "5", ""]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(resp, encode_integer(0));
+    assert!(!test_app.database.exists("empty"));
+
+    let message = encode_string("setrange foo -1 x");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, error_string("ERR offset is out of range"));
+
+    // Whole characters can be replaced, but not part of one
+    let message = encode_string_array(&["set", "accents", "café-olé"]);
+    send_message(&address, message.as_bytes()).await;
+    let message = encode_string_array(&["setrange", "accents", "3", "é"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(resp, encode_integer(10));
+    for offset in ["4", "8"] {
+        let message = encode_string_array(&["setrange", "accents", offset, "x"]);
+        let resp = send_message(&address, message.as_bytes()).await;
+        assert_eq!(
+            resp,
+            error_string("ERR offset would split a multibyte character")
+        );
+    }
+    let message = encode_string_array(&["setrange", "accents", "2", "xy"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(
+        resp,
+        error_string("ERR offset would split a multibyte character")
+    );
+    let message = encode_string("get accents");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("café-olé"));
+
+    // Reading part of a character fails the same way
+    let message = encode_string_array(&["getrange", "accents", "0", "4"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(resp, bulk_string("café"));
+    let message = encode_string_array(&["getrange", "accents", "0", "3"]);
+    let resp = send_message(&address, message.as_bytes()).await;
+    assert_eq!(
+        resp,
+        error_string("ERR offset would split a multibyte character")
+    );
+}
+
+#[tokio::test]
+async fn mget_reads_several_keys() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "foo", "1"]).await.unwrap();
+    client.command(&["SET", "bar", "2"]).await.unwrap();
+    client
+        .command(&["XADD", "stream", "1-1", "field", "value"])
+        .await
+        .unwrap();
+
+    let reply = client
+        .command(&["MGET", "foo", "missing", "stream", "bar"])
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Value::Array(vec![
+            Value::from("1"),
+            Value::Null,
+            Value::Null,
+            Value::from("2")
+        ])
+    );
+
+    let reply = client.command(&["MGET"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(_)));
+}
+
+#[tokio::test]
+async fn mset_sets_every_key() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["SET", "foo", "old", "PX", "100000"])
+        .await
+        .unwrap();
+    let reply = client
+        .command(&["MSET", "foo", "1", "bar", "2"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    let reply = client.command(&["MGET", "foo", "bar"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::Array(vec![Value::from("1"), Value::from("2")])
+    );
+    assert_eq!(test_app.database.ttl("foo"), None);
+
+    let reply = client.command(&["MSET", "foo", "1", "bar"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR wrong number of arguments for 'mset' command")
+    );
+}
+
+#[tokio::test]
+async fn msetnx_sets_every_key_or_none() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client
+        .command(&["MSETNX", "foo", "1", "bar", "2"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client
+        .command(&["MSETNX", "bar", "3", "baz", "4"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(0));
+    let reply = client.command(&["MGET", "bar", "baz"]).await.unwrap();
+    assert_eq!(reply, Value::Array(vec![Value::from("2"), Value::Null]));
+
+    // Only one of the clients racing for the same key gets to set it
+    let mut racers = vec![];
+    for i in 0..10 {
+        let address = test_app.address.name();
+        racers.push(tokio::spawn(async move {
+            let mut client = Client::connect(address).await.unwrap();
+            let own_key = format!("own{}", i);
+            client
+                .command(&["MSETNX", &own_key, "1", "contested", &i.to_string()])
+                .await
+                .unwrap()
+        }));
+    }
+    let mut winners = 0;
+    for racer in racers {
+        if racer.await.unwrap() == Value::Integer(1) {
+            winners += 1;
+        }
+    }
+    assert_eq!(winners, 1);
+}
+
+#[tokio::test]
+async fn setex_and_psetex_set_keys_that_expire() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client
+        .command(&["SETEX", "foo", "10", "bar"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client
+        .command(&["PSETEX", "baz", "1500", "qux"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    assert_eq!(test_app.database.ttl("foo"), Some(Duration::from_secs(10)));
+    assert_eq!(
+        test_app.database.ttl("baz"),
+        Some(Duration::from_millis(1500))
+    );
+
+    clock.advance(Duration::from_secs(2));
+    let reply = client.command(&["MGET", "foo", "baz"]).await.unwrap();
+    assert_eq!(reply, Value::Array(vec![Value::from("bar"), Value::Null]));
+
+    for expiry in ["0", "-5"] {
+        let reply = client
+            .command(&["SETEX", "foo", expiry, "bar"])
+            .await
+            .unwrap();
+        assert_eq!(
+            reply,
+            Value::error("ERR invalid expire time in 'setex' command")
+        );
+    }
+    let reply = client
+        .command(&["PSETEX", "foo", "soon", "bar"])
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR value is not an integer or out of range")
+    );
+}
+
+#[tokio::test]
+async fn getset_and_substr_work_like_the_commands_they_became() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["GETSET", "foo", "hello"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+    client
+        .command(&["SET", "foo", "hello-world", "PX", "100000"])
+        .await
+        .unwrap();
+    let reply = client.command(&["GETSET", "foo", "bye"]).await.unwrap();
+    assert_eq!(reply, Value::from("hello-world"));
+    assert_eq!(test_app.database.ttl("foo"), None);
+
+    let reply = client
+        .command(&["SUBSTR", "foo", "-2", "-1"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::from("ye"));
+
+    client
+        .command(&["XADD", "stream", "1-1", "field", "value"])
+        .await
+        .unwrap();
+    let reply = client
+        .command(&["GETSET", "stream", "value"])
+        .await
+        .unwrap();
+    assert!(matches!(reply, Value::Error(message) if message.starts_with("WRONGTYPE")));
+}
@@ -0,0 +1,190 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn fields_are_set_and_read() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client
+        .command(&["HSET", "user", "name", "ada", "lang", "rust"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+    // Only new fields are counted
+    let reply = client
+        .command(&["HSET", "user", "lang", "ocaml", "year", "1815"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["TYPE", "user"]).await;
+    assert_eq!(reply.unwrap(), Value::from("hash"));
+
+    let reply = client.command(&["HGET", "user", "lang"]).await;
+    assert_eq!(reply.unwrap(), Value::from("ocaml"));
+    let reply = client.command(&["HGET", "user", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    let reply = client.command(&["HGET", "missing", "lang"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+
+    let reply = client.command(&["HGETALL", "user"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::bulk_array(&["name", "ada", "lang", "ocaml", "year", "1815"])
+    );
+    let reply = client.command(&["HKEYS", "user"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["name", "lang", "year"]));
+    let reply = client.command(&["HVALS", "user"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["ada", "ocaml", "1815"]));
+    let reply = client.command(&["HLEN", "user"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(3));
+
+    let reply = client
+        .command(&["HMGET", "user", "year", "missing", "name"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![Value::from("1815"), Value::Null, Value::from("ada")])
+    );
+    let reply = client.command(&["HMGET", "missing", "a", "b"]).await;
+    assert_eq!(reply.unwrap(), Value::Array(vec![Value::Null, Value::Null]));
+
+    let reply = client.command(&["HEXISTS", "user", "name"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["HEXISTS", "user", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    for key in ["HGETALL", "HKEYS", "HVALS"] {
+        let reply = client.command(&[key, "missing"]).await;
+        assert_eq!(reply.unwrap(), Value::Array(vec![]));
+    }
+    let reply = client.command(&["HLEN", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client.command(&["HSET", "user", "name"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR wrong number of arguments for 'hset' command")
+    );
+    let reply = client.command(&["HSET", "user", "name", "a", "lang"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR wrong number of arguments for 'hset' command")
+    );
+}
+
+#[tokio::test]
+async fn fields_are_deleted_until_the_hash_is_gone() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["HSET", "hash", "a", "1", "b", "2", "c", "3"])
+        .await
+        .unwrap();
+    client.command(&["EXPIRE", "hash", "100"]).await.unwrap();
+
+    let reply = client.command(&["HDEL", "hash", "a", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["HSET", "hash", "d", "4"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+
+    // Changing the hash doesn't change its expiration
+    let reply = client.command(&["TTL", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(100));
+
+    let reply = client.command(&["HDEL", "hash", "b", "c", "d"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(3));
+    let reply = client.command(&["EXISTS", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["HDEL", "hash", "a"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn big_hashes_are_kept_in_a_hash_table() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client.command(&["HSET", "hash", "a", "1"]).await.unwrap();
+    let reply = client.command(&["OBJECT", "ENCODING", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::from("listpack"));
+
+    let fields: Vec<String> = (0..200)
+        .flat_map(|i| [i.to_string(), i.to_string()])
+        .collect();
+    let mut command = vec!["HSET", "hash"];
+    command.extend(fields.iter().map(String::as_str));
+    let reply = client.command(&command).await;
+    assert_eq!(reply.unwrap(), Value::Integer(200));
+
+    let reply = client.command(&["OBJECT", "ENCODING", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::from("hashtable"));
+    let reply = client.command(&["HLEN", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(201));
+    let reply = client.command(&["HGET", "hash", "150"]).await;
+    assert_eq!(reply.unwrap(), Value::from("150"));
+}
+
+#[tokio::test]
+async fn hash_commands_only_work_on_hashes() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+    let wrong_type =
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    for command in [
+        &["HSET", "string", "a", "1"][..],
+        &["HGET", "string", "a"],
+        &["HDEL", "string", "a"],
+        &["HGETALL", "string"],
+        &["HEXISTS", "string", "a"],
+        &["HLEN", "string"],
+        &["HKEYS", "string"],
+        &["HVALS", "string"],
+        &["HMGET", "string", "a"],
+    ] {
+        let reply = client.command(command).await;
+        assert_eq!(reply.unwrap(), wrong_type, "{:?}", command);
+    }
+
+    client.command(&["HSET", "hash", "a", "1"]).await.unwrap();
+    for command in [
+        &["GET", "hash"][..],
+        &["LPUSH", "hash", "a"],
+        &["INCR", "hash"],
+    ] {
+        let reply = client.command(command).await;
+        assert_eq!(reply.unwrap(), wrong_type, "{:?}", command);
+    }
+}
+
+#[tokio::test]
+async fn hashes_are_saved_and_loaded() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["HSET", "hash", "a", "1", "b", "2"])
+        .await
+        .unwrap();
+    let reply = client.command(&["SAVE"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client.command(&["HGETALL", "hash"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a", "1", "b", "2"]));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,34 @@
+use not_redis::client::Client;
+use not_redis::resp::Value;
+
+use common::TestApp;
+
+mod common;
+
+fn hot_key(key: &str, count: i64) -> Value {
+    Value::Array(vec![Value::from(key), Value::Integer(count)])
+}
+
+#[tokio::test]
+async fn busiest_keys_are_listed_first() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    for _ in 0..5 {
+        client.command(&["INCR", "busy"]).await.unwrap();
+    }
+    client.command(&["SET", "quiet", "1"]).await.unwrap();
+    client.command(&["GET", "quiet"]).await.unwrap();
+    client.command(&["GET", "once"]).await.unwrap();
+
+    let reply = client.command(&["HOTKEYS", "COUNT", "2"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::Array(vec![hot_key("busy", 5), hot_key("quiet", 2)])
+    );
+    let reply = client.command(&["HOTKEYS"]).await.unwrap();
+    assert!(matches!(reply, Value::Array(keys) if keys.len() == 3));
+
+    let reply = client.command(&["HOTKEYS", "COUNT", "-1"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(_)));
+}
@@ -0,0 +1,26 @@
+use not_redis::encoding::{bulk_string, simple_string};
+use not_redis::server::Config;
+
+use common::{encode_string, send_message, TestApp};
+
+mod common;
+
+#[tokio::test]
+async fn connections_shared_across_io_threads() {
+    let mut config = Config::new(None, None);
+    config.io_threads = 4;
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    for i in 0..20 {
+        let message = encode_string(&format!("set foo{} bar{}", i, i));
+        let resp = send_message(&address, &message).await;
+        assert_eq!(resp, simple_string("OK"));
+    }
+
+    for i in 0..20 {
+        let message = encode_string(&format!("get foo{}", i));
+        let resp = send_message(&address, &message).await;
+        assert_eq!(resp, bulk_string(&format!("bar{}", i)));
+    }
+}
@@ -0,0 +1,187 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn bulk(value: &str) -> Value {
+    Value::from(value)
+}
+
+#[tokio::test]
+async fn documents_can_be_set_and_read_by_path() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let document =
+        r#"{"name":"widget","tags":["a","b"],"parts":{"bolt":{"count":4},"nut":{"count":2}}}"#;
+    let reply = client.command(&["JSON.SET", "doc", "$", document]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    let reply = client.command(&["JSON.GET", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk(document));
+
+    let reply = client.command(&["JSON.GET", "doc", "$.name"]).await;
+    assert_eq!(reply.unwrap(), bulk(r#"["widget"]"#));
+    let reply = client.command(&["JSON.GET", "doc", ".name"]).await;
+    assert_eq!(reply.unwrap(), bulk(r#""widget""#));
+    let reply = client.command(&["JSON.GET", "doc", "$..count"]).await;
+    assert_eq!(reply.unwrap(), bulk("[4,2]"));
+    let reply = client
+        .command(&["JSON.GET", "doc", "$.tags[-1]", "$.missing"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        bulk(r#"{"$.tags[-1]":["b"],"$.missing":[]}"#)
+    );
+
+    let reply = client.command(&["JSON.GET", "doc", ".missing"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR Path '.missing' does not exist")
+    );
+    let reply = client.command(&["JSON.GET", "nothing", "$"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+
+    let reply = client.command(&["TYPE", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk("ReJSON-RL"));
+}
+
+#[tokio::test]
+async fn set_creates_members_and_respects_nx_and_xx() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client.command(&["JSON.SET", "doc", "$.a", "1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR new objects must be created at the root")
+    );
+    let reply = client.command(&["JSON.SET", "doc", "$", "{}", "XX"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    let reply = client
+        .command(&["JSON.SET", "doc", "$", r#"{"a":1}"#, "NX"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    let reply = client.command(&["JSON.SET", "doc", "$.a", "2", "NX"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    let reply = client
+        .command(&["JSON.SET", "doc", "$.b", "[1]", "XX"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    let reply = client
+        .command(&["JSON.SET", "doc", "$.b", "[1]", "NX"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["JSON.SET", "doc", "$.a", r#""x""#]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    let reply = client.command(&["JSON.GET", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk(r#"{"a":"x","b":[1]}"#));
+
+    let reply = client.command(&["JSON.SET", "doc", "$", "{not json"]).await;
+    assert!(matches!(reply.unwrap(), Value::Error(e) if e.starts_with("ERR ")));
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["JSON.GET", "string"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn values_can_be_deleted_incremented_and_appended_to() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let document = r#"{"a":1,"b":{"a":2.5,"list":[1]},"c":"three"}"#;
+    client
+        .command(&["JSON.SET", "doc", "$", document])
+        .await
+        .unwrap();
+
+    let reply = client
+        .command(&["JSON.NUMINCRBY", "doc", "$..a", "2"])
+        .await;
+    assert_eq!(reply.unwrap(), bulk("[3,4.5]"));
+    let reply = client.command(&["JSON.NUMINCRBY", "doc", ".a", "-1"]).await;
+    assert_eq!(reply.unwrap(), bulk("2"));
+    let reply = client.command(&["JSON.NUMINCRBY", "doc", "$.*", "1"]).await;
+    assert_eq!(reply.unwrap(), bulk("[3,null,null]"));
+    let reply = client.command(&["JSON.NUMINCRBY", "doc", ".c", "1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR WRONGTYPE wrong type of path value - expected a number but found string")
+    );
+
+    let reply = client
+        .command(&["JSON.ARRAPPEND", "doc", "$.b.list", "2", r#""x""#])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Array(vec![Value::Integer(3)]));
+    let reply = client.command(&["JSON.ARRAPPEND", "doc", "$.*", "0"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![Value::Null, Value::Null, Value::Null])
+    );
+    let reply = client
+        .command(&["JSON.ARRAPPEND", "doc", ".b.list", "null"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(4));
+    let reply = client
+        .command(&["JSON.ARRAPPEND", "nothing", "$", "1"])
+        .await;
+    assert!(matches!(reply.unwrap(), Value::Error(_)));
+
+    let reply = client.command(&["JSON.GET", "doc", "$.b.list"]).await;
+    assert_eq!(reply.unwrap(), bulk(r#"[[1,2,"x",null]]"#));
+
+    let reply = client.command(&["JSON.DEL", "doc", "$.b.list[*]"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(4));
+    let reply = client.command(&["JSON.DEL", "doc", "$..a"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+    let reply = client.command(&["JSON.GET", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk(r#"{"b":{"list":[]},"c":"three"}"#));
+
+    let reply = client.command(&["JSON.DEL", "doc"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["TYPE", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk("none"));
+    let reply = client.command(&["JSON.DEL", "doc"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn documents_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    let document = r#"{"z":1,"a":[true,null,"12"],"m":{"n":1.5}}"#;
+    client
+        .command(&["JSON.SET", "doc", "$", document])
+        .await
+        .unwrap();
+    client
+        .command(&["JSON.SET", "number", ".", "12"])
+        .await
+        .unwrap();
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client.command(&["JSON.GET", "doc"]).await.unwrap();
+    assert_eq!(reply, bulk(document));
+    let reply = client.command(&["JSON.GET", "number"]).await.unwrap();
+    assert_eq!(reply, bulk("12"));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,316 @@
+use std::time::Duration;
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use not_redis::client::Client;
+use not_redis::clock::{Clock, MockClock};
+use not_redis::resp::Value;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn exists_counts_every_key_given_that_exists() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "name", "value"]).await.unwrap();
+    client
+        .command(&["XADD", "events", "1-1", "field", "value"])
+        .await
+        .unwrap();
+
+    let reply = client.command(&["EXISTS", "name"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client
+        .command(&["EXISTS", "name", "events", "missing", "name"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(3));
+    let reply = client.command(&["EXISTS", "missing"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = client.command(&["EXISTS"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(_)));
+}
+
+#[tokio::test]
+async fn random_keys_are_picked_from_those_there_are() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client.command(&["RANDOMKEY"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+
+    let keys = ["first", "second", "third"];
+    for key in keys {
+        client.command(&["SET", key, "value"]).await.unwrap();
+    }
+    let mut picked = std::collections::HashSet::new();
+    for _ in 0..100 {
+        let reply = client.command(&["RANDOMKEY"]).await.unwrap();
+        let key = reply.as_str().unwrap().to_string();
+        assert!(keys.contains(&key.as_str()));
+        picked.insert(key);
+    }
+    assert_eq!(picked.len(), keys.len());
+}
+
+#[tokio::test]
+async fn keys_remember_when_they_were_last_accessed() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "read", "value"]).await.unwrap();
+    client.command(&["SET", "touched", "value"]).await.unwrap();
+    client.command(&["SET", "idle", "value"]).await.unwrap();
+    let set_at = clock.now();
+
+    clock.advance(Duration::from_secs(10));
+    client.command(&["GET", "read"]).await.unwrap();
+    // Writing to a key doesn't count as reading it
+    client.command(&["EXPIRE", "idle", "100"]).await.unwrap();
+    let reply = client
+        .command(&["TOUCH", "touched", "missing", "touched"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(2));
+
+    let database = &test_app.database;
+    assert_eq!(database.last_accessed("read"), Some(clock.now()));
+    assert_eq!(database.last_accessed("touched"), Some(clock.now()));
+    assert_eq!(database.last_accessed("idle"), Some(set_at));
+    assert_eq!(database.last_accessed("missing"), None);
+}
+
+#[tokio::test]
+async fn object_reports_how_long_keys_have_been_idle_and_how_often_used() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "busy", "value"]).await.unwrap();
+    client.command(&["SET", "idle", "value"]).await.unwrap();
+
+    let reply = client.command(&["OBJECT", "FREQ", "busy"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(5));
+    let reply = client
+        .command(&["OBJECT", "REFCOUNT", "busy"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(1));
+
+    clock.advance(Duration::from_secs(90));
+    // The first access always counts
+    client.command(&["GET", "busy"]).await.unwrap();
+    for _ in 0..100 {
+        client.command(&["GET", "busy"]).await.unwrap();
+    }
+
+    // Asking about a key doesn't count as accessing it
+    let reply = client
+        .command(&["OBJECT", "IDLETIME", "idle"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(90));
+    let reply = client
+        .command(&["OBJECT", "IDLETIME", "idle"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(90));
+    let reply = client
+        .command(&["OBJECT", "IDLETIME", "busy"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    let Value::Integer(busy) = client.command(&["OBJECT", "FREQ", "busy"]).await.unwrap() else {
+        panic!("OBJECT FREQ should reply with an integer");
+    };
+    assert!(busy > 5);
+    // The idle key has gone a minute and a half without being used
+    let reply = client.command(&["OBJECT", "FREQ", "idle"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(4));
+
+    for subcommand in ["ENCODING", "REFCOUNT", "IDLETIME", "FREQ"] {
+        let reply = client
+            .command(&["OBJECT", subcommand, "missing"])
+            .await
+            .unwrap();
+        assert_eq!(reply, Value::Null);
+    }
+}
+
+#[tokio::test]
+async fn unlinked_keys_are_gone_straight_away() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let fields: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let mut xadd = vec!["XADD", "events", "1-1"];
+    for field in fields.iter() {
+        xadd.extend([field.as_str(), "value"]);
+    }
+    client.command(&xadd).await.unwrap();
+    client
+        .command(&["SET", "temporary", "value", "PX", "100"])
+        .await
+        .unwrap();
+
+    let reply = client
+        .command(&["UNLINK", "events", "temporary", "missing", "events"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(2));
+    let reply = client
+        .command(&["EXISTS", "events", "temporary"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    // The old timer doesn't take the key set in its place
+    client
+        .command(&["SET", "temporary", "again"])
+        .await
+        .unwrap();
+    clock.advance(Duration::from_millis(200));
+    let reply = client.command(&["GET", "temporary"]).await.unwrap();
+    assert_eq!(reply, Value::from("again"));
+}
+
+#[tokio::test]
+async fn flushing_removes_every_key_and_stops_their_timers() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    for mode in [None, Some("ASYNC"), Some("sync")] {
+        client.command(&["SET", "name", "value"]).await.unwrap();
+        client
+            .command(&["SET", "temporary", "value", "PX", "100"])
+            .await
+            .unwrap();
+
+        let mut flush = vec!["FLUSHDB"];
+        flush.extend(mode);
+        let reply = client.command(&flush).await.unwrap();
+        assert_eq!(reply, Value::ok());
+        let reply = client
+            .command(&["EXISTS", "name", "temporary"])
+            .await
+            .unwrap();
+        assert_eq!(reply, Value::Integer(0));
+
+        // The flushed key's timer doesn't take the one set in its place
+        client
+            .command(&["SET", "temporary", "again"])
+            .await
+            .unwrap();
+        clock.advance(Duration::from_millis(200));
+        let reply = client.command(&["GET", "temporary"]).await.unwrap();
+        assert_eq!(reply, Value::from("again"));
+    }
+
+    let reply = client.command(&["FLUSHALL", "ASYNC"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["EXISTS", "temporary"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(0));
+
+    let reply = client.command(&["FLUSHALL", "LATER"]).await.unwrap();
+    assert_eq!(reply, Value::error("ERR syntax error"));
+}
+
+#[tokio::test]
+async fn dumped_values_can_be_restored_under_another_key() {
+    let clock = MockClock::starting_now();
+    let test_app = TestApp::with_clock(clock.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["XADD", "events", "1-1", "field", "value"])
+        .await
+        .unwrap();
+    let reply = client.command(&["DUMP", "events"]).await.unwrap();
+    let payload = reply.as_str().unwrap().to_string();
+    let reply = client.command(&["DUMP", "missing"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+
+    let reply = client
+        .command(&["RESTORE", "copy", "1000", &payload])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["XRANGE", "copy", "-", "+"]).await.unwrap();
+    let original = client
+        .command(&["XRANGE", "events", "-", "+"])
+        .await
+        .unwrap();
+    assert_eq!(reply, original);
+    let reply = client.command(&["PTTL", "copy"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1000));
+
+    let reply = client
+        .command(&["RESTORE", "events", "0", &payload])
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Value::error("BUSYKEY Target key name already exists.")
+    );
+    let reply = client
+        .command(&["RESTORE", "events", "0", &payload, "REPLACE"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // Any change to the payload is caught by its checksum
+    let mut corrupted = payload.into_bytes();
+    corrupted[4] = if corrupted[4] == b'0' { b'1' } else { b'0' };
+    let corrupted = String::from_utf8(corrupted).unwrap();
+    let reply = client
+        .command(&["RESTORE", "other", "0", &corrupted])
+        .await
+        .unwrap();
+    assert_eq!(
+        reply,
+        Value::error("ERR DUMP payload version or checksum are wrong")
+    );
+}
+
+#[tokio::test]
+async fn writes_are_refused_once_the_dataset_is_past_maxmemory() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+    let reply = client
+        .command(&["CONFIG", "SET", "maxmemory", "300"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // Random so it doesn't compress down to nothing
+    let big: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(200)
+        .map(char::from)
+        .collect();
+    client.command(&["SET", "a", &big]).await.unwrap();
+    client.command(&["SELECT", "1"]).await.unwrap();
+    let reply = client.command(&["SET", "b", &big]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // Every database counts towards the limit
+    let reply = client.command(&["SET", "c", "1"]).await.unwrap();
+    assert_eq!(
+        reply,
+        Value::error("OOM command not allowed when used memory > 'maxmemory'.")
+    );
+    let reply = client.command(&["GET", "b"]).await.unwrap();
+    assert_eq!(reply, Value::from(big.as_str()));
+    let reply = client.command(&["DEL", "b"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["SET", "c", "1"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+}
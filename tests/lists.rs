@@ -0,0 +1,619 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::data::Database;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn values_are_pushed_onto_either_end() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client.command(&["LPUSH", "list", "a", "b", "c"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(3));
+    let reply = client.command(&["RPUSH", "list", "d", "e"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(5));
+    let reply = client.command(&["TYPE", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::from("list"));
+    let reply = client.command(&["LLEN", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(5));
+
+    let cases: [(&str, &str, &[&str]); 6] = [
+        ("0", "-1", &["c", "b", "a", "d", "e"]),
+        ("1", "2", &["b", "a"]),
+        ("-2", "-1", &["d", "e"]),
+        ("-100", "0", &["c"]),
+        ("3", "100", &["d", "e"]),
+        ("4", "1", &[]),
+    ];
+    for (start, end, expected) in cases {
+        let reply = client.command(&["LRANGE", "list", start, end]).await;
+        assert_eq!(reply.unwrap(), Value::bulk_array(expected));
+    }
+
+    let reply = client.command(&["LLEN", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["LRANGE", "missing", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::Array(vec![]));
+    let reply = client.command(&["LRANGE", "list", "first", "-1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR value is not an integer or out of range")
+    );
+}
+
+#[tokio::test]
+async fn values_are_popped_off_either_end_until_the_list_is_gone() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "list", "a", "b", "c", "d", "e"])
+        .await
+        .unwrap();
+    client.command(&["EXPIRE", "list", "100"]).await.unwrap();
+
+    let reply = client.command(&["LPOP", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::from("a"));
+    let reply = client.command(&["RPOP", "list", "2"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["e", "d"]));
+    let reply = client.command(&["LPOP", "list", "0"]).await;
+    assert_eq!(reply.unwrap(), Value::Array(vec![]));
+
+    // Changing the list doesn't change its expiration
+    let reply = client.command(&["TTL", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(100));
+
+    let reply = client.command(&["LPOP", "list", "10"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["b", "c"]));
+    let reply = client.command(&["EXISTS", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client.command(&["RPOP", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    let reply = client.command(&["RPOP", "list", "2"]).await;
+    assert_eq!(reply.unwrap(), Value::NullArray);
+    let reply = client.command(&["LPOP", "list", "-1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR value is out of range, must be positive")
+    );
+}
+
+#[tokio::test]
+async fn values_are_inserted_next_to_a_pivot_or_replaced() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "list", "a", "b", "a"])
+        .await
+        .unwrap();
+    let reply = client
+        .command(&["LINSERT", "list", "BEFORE", "a", "x"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(4));
+    let reply = client
+        .command(&["LINSERT", "list", "after", "b", "y"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(5));
+    let reply = client
+        .command(&["LINSERT", "list", "AFTER", "z", "y"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(-1));
+    let reply = client
+        .command(&["LINSERT", "missing", "AFTER", "a", "y"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client
+        .command(&["LINSERT", "list", "BESIDE", "a", "y"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR syntax error"));
+
+    let reply = client.command(&["LSET", "list", "0", "first"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["LSET", "list", "-1", "last"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::bulk_array(&["first", "a", "b", "y", "last"])
+    );
+
+    for index in ["5", "-6"] {
+        let reply = client.command(&["LSET", "list", index, "value"]).await;
+        assert_eq!(reply.unwrap(), Value::error("ERR index out of range"));
+    }
+    let reply = client.command(&["LSET", "missing", "0", "value"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR no such key"));
+    let reply = client.command(&["EXISTS", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn values_are_removed_from_either_end() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "list", "a", "b", "a", "c", "a", "b", "a"])
+        .await
+        .unwrap();
+    let reply = client.command(&["LREM", "list", "2", "a"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+    let reply = client.command(&["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::bulk_array(&["b", "c", "a", "b", "a"])
+    );
+
+    let reply = client.command(&["LREM", "list", "-1", "b"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["b", "c", "a", "a"]));
+
+    let reply = client.command(&["LREM", "list", "0", "a"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+    let reply = client.command(&["LREM", "list", "0", "z"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["LREM", "missing", "0", "z"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+
+    let reply = client.command(&["LREM", "list", "0", "b"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["LREM", "list", "-5", "c"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    let reply = client.command(&["EXISTS", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn trimming_keeps_a_capped_log() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for entry in ["1", "2", "3", "4", "5"] {
+        client.command(&["LPUSH", "log", entry]).await.unwrap();
+        let reply = client.command(&["LTRIM", "log", "0", "2"]).await;
+        assert_eq!(reply.unwrap(), Value::ok());
+    }
+    let reply = client.command(&["LRANGE", "log", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["5", "4", "3"]));
+
+    let reply = client.command(&["LTRIM", "log", "-2", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["LRANGE", "log", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["4", "3"]));
+
+    // Trimming everything away removes the key
+    let reply = client.command(&["LTRIM", "log", "5", "10"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["EXISTS", "log"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client.command(&["LTRIM", "missing", "0", "1"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+}
+
+#[tokio::test]
+async fn blocked_pops_wait_for_a_push() {
+    let (test_app, mut client) = TestApp::master_with_client().await;
+    let mut blocked = Client::connect(test_app.address.name()).await.unwrap();
+
+    let pop =
+        tokio::spawn(async move { blocked.command(&["BLPOP", "first", "second", "0"]).await });
+    sleep(Duration::from_millis(100)).await;
+    assert!(!pop.is_finished());
+
+    // The push isn't held up by the blocked pop
+    let reply = timeout(
+        Duration::from_secs(1),
+        client.command(&["RPUSH", "second", "a", "b"]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(reply.unwrap(), Value::Integer(2));
+
+    let reply = timeout(Duration::from_secs(1), pop).await.unwrap().unwrap();
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["second", "a"]));
+    let reply = client.command(&["LRANGE", "second", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["b"]));
+}
+
+#[tokio::test]
+async fn blocked_pops_wait_for_a_restored_list() {
+    let (test_app, mut client) = TestApp::master_with_client().await;
+    let mut blocked = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["RPUSH", "source", "a", "b"])
+        .await
+        .unwrap();
+    let reply = client.command(&["DUMP", "source"]).await.unwrap();
+    let payload = reply.as_str().unwrap().to_string();
+
+    let pop = tokio::spawn(async move { blocked.command(&["BLPOP", "restored", "0"]).await });
+    sleep(Duration::from_millis(100)).await;
+    assert!(!pop.is_finished());
+
+    let reply = client
+        .command(&["RESTORE", "restored", "0", &payload])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    let reply = timeout(Duration::from_secs(1), pop).await.unwrap().unwrap();
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["restored", "a"]));
+}
+
+#[tokio::test]
+async fn blocked_clients_that_disconnect_leave_the_list_alone() {
+    let (test_app, mut client) = TestApp::master_with_client().await;
+
+    let mut waiting = vec![];
+    for command in [
+        &["BLPOP", "queue", "0"][..],
+        &["BLMOVE", "queue", "done", "LEFT", "RIGHT", "0"],
+        &["BLMPOP", "0", "1", "queue", "LEFT"],
+    ] {
+        let mut blocked = Client::connect(test_app.address.name()).await.unwrap();
+        waiting.push(tokio::spawn(async move { blocked.command(command).await }));
+    }
+    sleep(Duration::from_millis(100)).await;
+    // Dropping the clients closes their connections
+    for pop in waiting {
+        pop.abort();
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    let reply = client.command(&["RPUSH", "queue", "job"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+    sleep(Duration::from_millis(100)).await;
+    let reply = client.command(&["LRANGE", "queue", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["job"]));
+    let reply = client.command(&["EXISTS", "done"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+#[tokio::test]
+async fn blocking_pops_return_straight_away_or_time_out() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "second", "a", "b"])
+        .await
+        .unwrap();
+    let reply = client.command(&["BRPOP", "first", "second", "1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["second", "b"]));
+
+    let started = Instant::now();
+    let reply = client.command(&["BLPOP", "first", "0.1"]).await;
+    assert_eq!(reply.unwrap(), Value::NullArray);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let reply = client.command(&["BLPOP", "first", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR timeout is negative"));
+    let reply = client.command(&["BLPOP", "first", "soon"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR timeout is not a float or out of range")
+    );
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["BLPOP", "string", "second", "0"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn replicas_pop_what_a_blocked_pop_got() {
+    let master = TestApp::master().await;
+    let replica = TestApp::slave(master.address.clone()).await;
+    let mut client = Client::connect(master.address.name()).await.unwrap();
+    let mut blocked = Client::connect(master.address.name()).await.unwrap();
+
+    let pop = tokio::spawn(async move { blocked.command(&["BRPOP", "list", "0"]).await });
+    sleep(Duration::from_millis(100)).await;
+    client
+        .command(&["RPUSH", "list", "a", "b", "c"])
+        .await
+        .unwrap();
+    let reply = timeout(Duration::from_secs(1), pop).await.unwrap().unwrap();
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["list", "c"]));
+
+    let reply = client.command(&["WAIT", "1", "1000"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let mut client = Client::connect(replica.address.name()).await.unwrap();
+    let reply = client.command(&["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a", "b"]));
+}
+
+#[tokio::test]
+async fn values_are_moved_between_lists() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "pending", "a", "b", "c"])
+        .await
+        .unwrap();
+    let reply = client
+        .command(&["LMOVE", "pending", "processing", "LEFT", "RIGHT"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::from("a"));
+    let reply = client
+        .command(&["RPOPLPUSH", "pending", "processing"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::from("c"));
+    let reply = client.command(&["LRANGE", "processing", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["c", "a"]));
+
+    // A list can be rotated onto itself
+    let reply = client
+        .command(&["LMOVE", "processing", "processing", "left", "right"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::from("c"));
+    let reply = client.command(&["LRANGE", "processing", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a", "c"]));
+
+    let reply = client
+        .command(&["RPOPLPUSH", "pending", "processing"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::from("b"));
+    let reply = client.command(&["EXISTS", "pending"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+    let reply = client
+        .command(&["RPOPLPUSH", "pending", "processing"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Null);
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["RPOPLPUSH", "processing", "string"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+    let reply = client.command(&["LLEN", "processing"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(3));
+    let reply = client
+        .command(&["LMOVE", "processing", "other", "UP", "LEFT"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::error("ERR syntax error"));
+}
+
+#[tokio::test]
+async fn blocked_moves_wait_for_the_source() {
+    let (test_app, mut client) = TestApp::master_with_client().await;
+    let mut blocked = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client
+        .command(&["BLMOVE", "pending", "processing", "LEFT", "LEFT", "0.1"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Null);
+
+    let moved = tokio::spawn(async move {
+        blocked
+            .command(&["BLMOVE", "pending", "processing", "RIGHT", "LEFT", "0"])
+            .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    assert!(!moved.is_finished());
+    client.command(&["LPUSH", "pending", "a"]).await.unwrap();
+
+    let reply = timeout(Duration::from_secs(1), moved)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply.unwrap(), Value::from("a"));
+    let reply = client.command(&["LRANGE", "processing", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a"]));
+    let reply = client.command(&["EXISTS", "pending"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(0));
+}
+
+fn popped(key: &str, values: &[&str]) -> Value {
+    Value::Array(vec![Value::from(key), Value::bulk_array(values)])
+}
+
+#[tokio::test]
+async fn several_values_are_popped_off_the_first_list() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["RPUSH", "second", "a", "b", "c"])
+        .await
+        .unwrap();
+    client.command(&["RPUSH", "third", "d"]).await.unwrap();
+    let reply = client
+        .command(&["LMPOP", "3", "first", "second", "third", "LEFT"])
+        .await;
+    assert_eq!(reply.unwrap(), popped("second", &["a"]));
+    let reply = client
+        .command(&["LMPOP", "2", "second", "third", "right", "COUNT", "5"])
+        .await;
+    assert_eq!(reply.unwrap(), popped("second", &["c", "b"]));
+    let reply = client.command(&["LMPOP", "1", "second", "LEFT"]).await;
+    assert_eq!(reply.unwrap(), Value::NullArray);
+
+    let cases = [
+        (
+            &["LMPOP", "0", "third", "LEFT"][..],
+            "ERR numkeys should be greater than 0",
+        ),
+        (&["LMPOP", "3", "third", "LEFT"], "ERR syntax error"),
+        (&["LMPOP", "1", "third", "UP"], "ERR syntax error"),
+        (
+            &["LMPOP", "1", "third", "LEFT", "COUNT", "0"],
+            "ERR count should be greater than 0",
+        ),
+        (
+            &["LMPOP", "1", "third", "LEFT", "COUNT"],
+            "ERR syntax error",
+        ),
+    ];
+    for (command, error) in cases {
+        let reply = client.command(command).await;
+        assert_eq!(reply.unwrap(), Value::error(error), "{:?}", command);
+    }
+    let reply = client.command(&["LLEN", "third"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+}
+
+#[tokio::test]
+async fn blocked_multiple_pops_wait_for_any_of_the_lists() {
+    let (test_app, mut client) = TestApp::master_with_client().await;
+    let mut blocked = Client::connect(test_app.address.name()).await.unwrap();
+
+    let reply = client
+        .command(&["BLMPOP", "0.1", "2", "first", "second", "LEFT"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::NullArray);
+
+    let pop = tokio::spawn(async move {
+        blocked
+            .command(&["BLMPOP", "0", "2", "first", "second", "RIGHT", "COUNT", "2"])
+            .await
+    });
+    sleep(Duration::from_millis(100)).await;
+    assert!(!pop.is_finished());
+    client
+        .command(&["RPUSH", "second", "a", "b", "c"])
+        .await
+        .unwrap();
+
+    let reply = timeout(Duration::from_secs(1), pop).await.unwrap().unwrap();
+    assert_eq!(reply.unwrap(), popped("second", &["c", "b"]));
+    let reply = client.command(&["LRANGE", "second", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a"]));
+}
+
+#[tokio::test]
+async fn long_lists_are_kept_in_chunks() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client.command(&["RPUSH", "list", "a"]).await.unwrap();
+    let reply = client.command(&["OBJECT", "ENCODING", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::from("listpack"));
+
+    let values: Vec<String> = (1..1_000).map(|i| i.to_string()).collect();
+    let mut command = vec!["RPUSH", "list"];
+    command.extend(values.iter().map(String::as_str));
+    let reply = client.command(&command).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1_000));
+    let reply = client.command(&["OBJECT", "ENCODING", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::from("quicklist"));
+    let Value::Array(chunks) = client.command(&["OBJECT", "CHUNKS", "list"]).await.unwrap() else {
+        panic!("OBJECT CHUNKS should reply with an array");
+    };
+    assert_eq!(chunks.len(), 8);
+    let bytes = 1 + values.iter().map(String::len).sum::<usize>();
+    assert_eq!(
+        chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Value::Integer(size) => *size as usize,
+                _ => panic!("chunk sizes should be integers"),
+            })
+            .sum::<usize>(),
+        bytes
+    );
+
+    let reply = client
+        .command(&["LINSERT", "list", "AFTER", "500", "x"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::Integer(1_001));
+    let reply = client.command(&["LRANGE", "list", "499", "502"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::bulk_array(&["499", "500", "x", "501"])
+    );
+    let reply = client.command(&["LREM", "list", "0", "x"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(1));
+
+    let reply = client.command(&["LTRIM", "list", "-300", "-201"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["LLEN", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(100));
+    let reply = client.command(&["LRANGE", "list", "0", "0"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["700"]));
+    let reply = client.command(&["LRANGE", "list", "-1", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["799"]));
+
+    // What's left of the chunks either side of the trim fits in one
+    let reply = client.command(&["OBJECT", "CHUNKS", "list"]).await;
+    assert_eq!(reply.unwrap(), Value::Array(vec![Value::Integer(300)]));
+    let reply = client.command(&["OBJECT", "CHUNKS", "missing"]).await;
+    assert_eq!(reply.unwrap(), Value::Null);
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    let reply = client.command(&["OBJECT", "CHUNKS", "string"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn list_commands_only_work_on_lists() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+    let wrong_type =
+        Value::error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    client.command(&["SET", "string", "value"]).await.unwrap();
+    for command in [
+        &["LPUSH", "string", "a"][..],
+        &["RPUSH", "string", "a"],
+        &["LPOP", "string"],
+        &["RPOP", "string", "1"],
+        &["LLEN", "string"],
+        &["LRANGE", "string", "0", "-1"],
+        &["LINSERT", "string", "BEFORE", "a", "b"],
+        &["LSET", "string", "0", "a"],
+        &["LREM", "string", "0", "a"],
+        &["LTRIM", "string", "0", "1"],
+    ] {
+        let reply = client.command(command).await;
+        assert_eq!(reply.unwrap(), wrong_type, "{:?}", command);
+    }
+
+    client.command(&["RPUSH", "list", "a"]).await.unwrap();
+    for command in [
+        &["GET", "list"][..],
+        &["APPEND", "list", "a"],
+        &["INCR", "list"],
+        &["XADD", "list", "*", "a", "b"],
+    ] {
+        let reply = client.command(command).await;
+        assert_eq!(reply.unwrap(), wrong_type, "{:?}", command);
+    }
+}
+
+#[tokio::test]
+async fn lists_are_saved_and_loaded() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    client
+        .command(&["RPUSH", "list", "a", "b", "c"])
+        .await
+        .unwrap();
+    let reply = client.command(&["SAVE"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+
+    drop(test_app);
+    let restored_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client.command(&["LRANGE", "list", "0", "-1"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["a", "b", "c"]));
+
+    let database = Database::from_config(config.rdb_path()).unwrap();
+    assert_eq!(database.key_type("list"), Some("list"));
+
+    fs::remove_dir_all(dir).unwrap();
+}
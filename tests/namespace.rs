@@ -0,0 +1,100 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use not_redis::client::Client;
+use not_redis::request::ConfigKey;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+#[tokio::test]
+async fn namespaces_keep_their_keys_apart() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    let mut tenant = Client::connect(test_app.address.name()).await.unwrap();
+
+    client.command(&["SET", "foo", "shared"]).await.unwrap();
+
+    let reply = tenant.command(&["SELECTNS", "tenant"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = tenant.command(&["GET", "foo"]).await.unwrap();
+    assert_eq!(reply, Value::Null);
+    tenant.command(&["SET", "foo", "tenant"]).await.unwrap();
+    let reply = tenant.command(&["KEYS", "*"]).await.unwrap();
+    assert_eq!(reply, Value::Array(vec![Value::from("foo")]));
+
+    let reply = client.command(&["GET", "foo"]).await.unwrap();
+    assert_eq!(reply, Value::from("shared"));
+    assert_eq!(
+        test_app.database.get_string("foo").unwrap().unwrap(),
+        "shared"
+    );
+
+    // Another connection in the same namespace sees the same keys
+    let mut colleague = Client::connect(test_app.address.name()).await.unwrap();
+    colleague.command(&["SELECTNS", "tenant"]).await.unwrap();
+    let reply = colleague.command(&["GET", "foo"]).await.unwrap();
+    assert_eq!(reply, Value::from("tenant"));
+
+    tenant.command(&["SELECTNS", "default"]).await.unwrap();
+    let reply = tenant.command(&["GET", "foo"]).await.unwrap();
+    assert_eq!(reply, Value::from("shared"));
+}
+
+#[tokio::test]
+async fn namespaces_are_held_to_their_limits() {
+    let mut config = Config::new(None, None);
+    config.set(&ConfigKey::NamespaceMaxKeys, "2").unwrap();
+    let test_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client.command(&["SELECTNS", "tenant"]).await.unwrap();
+
+    for key in ["a", "b"] {
+        let reply = client.command(&["SET", key, "1"]).await.unwrap();
+        assert_eq!(reply, Value::ok());
+    }
+    let reply = client.command(&["SET", "c", "1"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(_)));
+    let reply = client.command(&["INCR", "a"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(2));
+    client.command(&["DEL", "a"]).await.unwrap();
+    let reply = client.command(&["SET", "c", "1"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+
+    let reply = client
+        .command(&["CONFIG", "SET", "namespace-max-memory", "100"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    // Random so it doesn't compress down to nothing
+    let big: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(200)
+        .map(char::from)
+        .collect();
+    client.command(&["SET", "c", &big]).await.unwrap();
+    let reply = client.command(&["SET", "b", "2"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(message) if message.starts_with("OOM ")));
+    let reply = client.command(&["DEL", "c"]).await.unwrap();
+    assert_eq!(reply, Value::Integer(1));
+    let reply = client.command(&["SET", "b", "2"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // Values that grow where they are count as much as new ones
+    client.command(&["APPEND", "b", &big]).await.unwrap();
+    let reply = client.command(&["SET", "d", "2"]).await.unwrap();
+    assert!(matches!(reply, Value::Error(message) if message.starts_with("OOM ")));
+    client.command(&["DEL", "b"]).await.unwrap();
+    let reply = client.command(&["SET", "d", "2"]).await.unwrap();
+    assert_eq!(reply, Value::ok());
+
+    // The default namespace isn't limited
+    client.command(&["SELECTNS", "default"]).await.unwrap();
+    for key in ["a", "b", "c"] {
+        let reply = client.command(&["SET", key, &big]).await.unwrap();
+        assert_eq!(reply, Value::ok());
+    }
+}
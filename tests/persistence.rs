@@ -0,0 +1,402 @@
+use std::env;
+use std::fs;
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+
+use not_redis::config::SaveRule;
+use not_redis::data::{inspect_rdb, Database, RdbChecksum};
+use not_redis::encoding::{bulk_string, empty_string, simple_string};
+use not_redis::server::Config;
+
+use common::{encode_stream_items, encode_string, send_message, StreamItem, TestApp};
+
+mod common;
+
+fn temp_rdb_config() -> Config {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+
+    Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    )
+}
+
+#[tokio::test]
+async fn save_then_restore_strings() {
+    let config = temp_rdb_config();
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    for command in [
+        "set foo bar",
+        "set num 12345",
+        "set expiring soon px 100000",
+        "set expired gone px 1",
+    ] {
+        let message = encode_string(command);
+        let resp = send_message(&address, &message).await;
+        assert_eq!(resp, simple_string("OK"));
+    }
+
+    sleep(Duration::from_millis(10)).await;
+
+    let message = encode_string("save");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("foo").unwrap(), Some("bar".to_string()));
+    assert_eq!(database.get("num").unwrap(), Some("12345".to_string()));
+    assert_eq!(database.get("expiring").unwrap(), Some("soon".to_string()));
+    assert_eq!(database.get("expired").unwrap(), None);
+
+    let config = Config::new(
+        Some(path.parent().unwrap().to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+    let restored_app = TestApp::with_config(config).await;
+
+    let message = encode_string("get foo");
+    let resp = send_message(&restored_app.address.name(), &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    let message = encode_string("get expired");
+    let resp = send_message(&restored_app.address.name(), &message).await;
+    assert_eq!(resp, empty_string());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn save_rules_trigger_a_save_and_update_lastsave() {
+    let mut config = temp_rdb_config();
+    config.save = vec![SaveRule {
+        seconds: 1,
+        changes: 1,
+    }];
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("lastsave");
+    let started_at = send_message(&address, &message).await;
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("rdb_changes_since_last_save:1"));
+
+    sleep(Duration::from_millis(3500)).await;
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("foo").unwrap(), Some("bar".to_string()));
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("rdb_changes_since_last_save:0"));
+
+    let message = encode_string("lastsave");
+    let resp = send_message(&address, &message).await;
+    assert_ne!(resp, started_at);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn bgsave_saves_in_the_background() {
+    let config = temp_rdb_config();
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    for i in 0..100 {
+        let message = encode_string(&format!("set key{} value{}", i, i));
+        send_message(&address, &message).await;
+    }
+
+    let message = encode_string("bgsave");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("Background saving started"));
+
+    // Writes aren't held up by the save and don't end up in it
+    let message = encode_string("set late write");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("info replication");
+    let mut resp = send_message(&address, &message).await;
+    for _ in 0..50 {
+        if resp.contains("rdb_bgsave_in_progress:0") {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+        resp = send_message(&address, &message).await;
+    }
+    assert!(resp.contains("rdb_bgsave_in_progress:0"));
+    assert!(resp.contains("rdb_last_bgsave_status:ok"));
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("key42").unwrap(), Some("value42".to_string()));
+    assert_eq!(database.keys().unwrap().len(), 100);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+fn rdb_string(value: &str) -> Vec<u8> {
+    let mut encoded = vec![value.len() as u8];
+    encoded.extend(value.as_bytes());
+    encoded
+}
+
+#[tokio::test]
+async fn load_list_set_hash_and_sorted_set() {
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.extend([0xFE, 0x00, 0xFB, 0x04, 0x00]);
+
+    // List
+    rdb.push(1);
+    rdb.extend(rdb_string("list"));
+    rdb.push(2);
+    rdb.extend(rdb_string("a"));
+    rdb.extend(rdb_string("b"));
+
+    // Set
+    rdb.push(2);
+    rdb.extend(rdb_string("set"));
+    rdb.push(1);
+    rdb.extend(rdb_string("member"));
+
+    // Hash
+    rdb.push(4);
+    rdb.extend(rdb_string("hash"));
+    rdb.push(1);
+    rdb.extend(rdb_string("field"));
+    rdb.extend(rdb_string("value"));
+
+    // Sorted set with string scores
+    rdb.push(3);
+    rdb.extend(rdb_string("zset"));
+    rdb.push(2);
+    rdb.extend(rdb_string("one"));
+    rdb.extend(rdb_string("1.5"));
+    rdb.extend(rdb_string("infinite"));
+    rdb.push(254);
+
+    rdb.push(0xFF);
+    rdb.extend([0; 8]);
+
+    let config = temp_rdb_config();
+    let path = config.rdb_path();
+    fs::write(&path, rdb).unwrap();
+
+    let database = Database::from_config(path.clone()).unwrap();
+    for (key, data_type) in [
+        ("list", "list"),
+        ("set", "set"),
+        ("hash", "hash"),
+        ("zset", "zset"),
+    ] {
+        assert_eq!(database.get_type(key), Some(data_type));
+    }
+
+    // Writing the database back out should load the same types again.
+    database.save(&path, true).unwrap();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("type zset");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("zset"));
+
+    let message = encode_string("get list");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.starts_with("-WRONGTYPE"));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn save_compresses_large_strings_unless_disabled() {
+    let config = temp_rdb_config();
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let value = "abcd".repeat(100);
+    let message = encode_string(&format!("set big {}", value));
+    send_message(&address, &message).await;
+
+    let message = encode_string("save");
+    send_message(&address, &message).await;
+    assert!(fs::metadata(&path).unwrap().len() < value.len() as u64);
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("big").unwrap(), Some(value.clone()));
+
+    let message = encode_string("config set rdbcompression no");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("save");
+    send_message(&address, &message).await;
+    assert!(fs::metadata(&path).unwrap().len() > value.len() as u64);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn save_then_restore_streams() {
+    let config = temp_rdb_config();
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    for command in [
+        "xadd cool 100-50 one two three four",
+        "xadd cool 100-100 one 5 three six",
+        "xadd cool 101-1 seven eight",
+    ] {
+        let message = encode_string(command);
+        send_message(&address, &message).await;
+    }
+
+    let message = encode_string("save");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let config = Config::new(
+        Some(path.parent().unwrap().to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+    let restored_app = TestApp::with_config(config).await;
+    let address = restored_app.address.name();
+
+    let message = encode_string("xrange cool - +");
+    let resp = send_message(&address, &message).await;
+    let want = encode_stream_items(vec![
+        StreamItem {
+            id: "100-50",
+            items: vec!["one", "two", "three", "four"],
+        },
+        StreamItem {
+            id: "100-100",
+            items: vec!["one", "5", "three", "six"],
+        },
+        StreamItem {
+            id: "101-1",
+            items: vec!["seven", "eight"],
+        },
+    ]);
+    assert_eq!(resp, want);
+
+    let message = encode_string("xadd cool 101-1 nine ten");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.starts_with("-ERR The ID specified in XADD is equal or smaller"));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_saves_when_save_points_are_configured() {
+    let mut config = temp_rdb_config();
+    config.save = vec![SaveRule {
+        seconds: 3600,
+        changes: 100,
+    }];
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    // The connection is closed without a reply
+    let message = encode_string("shutdown");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, "");
+
+    let database = Database::from_config(path.clone()).unwrap();
+    assert_eq!(database.get("foo").unwrap(), Some("bar".to_string()));
+
+    let leftovers: Vec<_> = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name != "dump.rdb")
+        .collect();
+    assert!(leftovers.is_empty(), "unexpected files: {:?}", leftovers);
+
+    sleep(Duration::from_millis(50)).await;
+    assert!(TcpStream::connect(&address).await.is_err());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_nosave_skips_the_final_save() {
+    let mut config = temp_rdb_config();
+    config.save = vec![SaveRule {
+        seconds: 3600,
+        changes: 100,
+    }];
+    let path = config.rdb_path();
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("shutdown nosave");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, "");
+    assert!(!path.exists());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn inspecting_an_rdb_file_reports_keys_and_checks_the_checksum() {
+    let contents = fs::read("tests/test_data/dump_2.rdb").unwrap();
+    let summary = inspect_rdb(contents.clone()).unwrap();
+
+    assert_eq!(summary.version, 11);
+    assert!(summary
+        .aux_fields
+        .iter()
+        .any(|aux| aux.key == "redis-ver" && aux.value == "7.2.4"));
+    // Unlike loading the file, expired keys are still reported.
+    let mut keys: Vec<(&str, &str, bool)> = summary
+        .keys
+        .iter()
+        .map(|key| (key.key.as_str(), key.key_type, key.expires_at.is_some()))
+        .collect();
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec![("baz", "string", true), ("foo", "string", false)]
+    );
+    assert!(matches!(summary.checksum, RdbChecksum::Valid(_)));
+
+    let mut corrupted = contents.clone();
+    let last_value = corrupted.len() - 10;
+    corrupted[last_value] ^= 0x01;
+    let summary = inspect_rdb(corrupted).unwrap();
+    assert!(matches!(summary.checksum, RdbChecksum::Mismatch { .. }));
+
+    let truncated = contents[..contents.len() - 12].to_vec();
+    let error = format!("{:#}", inspect_rdb(truncated).unwrap_err());
+    assert!(error.contains("offset"), "{}", error);
+
+    // Files we write ourselves check out too
+    let database = Database::new();
+    database.set_string("foo", "bar").unwrap();
+    let summary = inspect_rdb(database.to_rdb(true).unwrap()).unwrap();
+    assert_eq!(summary.keys.len(), 1);
+    assert!(matches!(summary.checksum, RdbChecksum::Valid(_)));
+}
@@ -0,0 +1,335 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+use common::{encode_string, encode_string_array, send_message, TestApp};
+use not_redis::encoding::{bulk_string, encode_integer, error_string, simple_string};
+use not_redis::server::Config;
+use not_redis::session::Push;
+
+mod common;
+
+/// Reads from the connection until exactly `len` bytes have arrived.
+async fn read_exactly(connection: &mut TcpStream, len: usize) -> String {
+    let mut response = vec![0; len];
+    timeout(Duration::from_secs(1), connection.read_exact(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+
+    String::from_utf8(response).unwrap()
+}
+
+#[tokio::test]
+async fn values_larger_than_a_single_read_are_stored_whole() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+    let value = "a".repeat(64 * 1024);
+
+    let message = encode_string(&format!("set foo {}", value));
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("get foo");
+    connection.write_all(&message).await.unwrap();
+
+    let want = bulk_string(&value);
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+}
+
+#[tokio::test]
+async fn commands_split_across_writes_are_parsed_once_complete() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("set foo bar");
+    for chunk in message.chunks(5) {
+        connection.write_all(chunk).await.unwrap();
+        connection.flush().await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let want = simple_string("OK");
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+
+    let message = encode_string("get foo");
+    connection.write_all(&message).await.unwrap();
+
+    let want = bulk_string("bar");
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+}
+
+#[tokio::test]
+async fn pipelined_commands_are_all_answered_in_order() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = [
+        encode_string("set foo 1"),
+        encode_string("incr foo"),
+        encode_string("get foo"),
+        encode_string("echo done"),
+    ]
+    .concat();
+    connection.write_all(&message).await.unwrap();
+
+    let want = format!(
+        "{}{}{}{}",
+        simple_string("OK"),
+        encode_integer(2),
+        bulk_string("2"),
+        bulk_string("done")
+    );
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+}
+
+#[tokio::test]
+async fn large_replies_keep_their_place_among_pipelined_replies() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+    let value = "a".repeat(64 * 1024);
+
+    let message = encode_string(&format!("set foo {}", value));
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = [
+        encode_string("ping"),
+        encode_string("get foo"),
+        encode_string("echo done"),
+    ]
+    .concat();
+    connection.write_all(&message).await.unwrap();
+
+    let want = format!(
+        "{}{}{}",
+        simple_string("PONG"),
+        bulk_string(&value),
+        bulk_string("done")
+    );
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+}
+
+#[tokio::test]
+async fn bulk_strings_are_read_by_their_length() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+    let value = "hello world\r\n*1\r\n$4\r\nPING\r\n";
+
+    let message = encode_string_array(vec!["set", "key with spaces", value]);
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string_array(vec!["get", "key with spaces"]);
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string(value));
+}
+
+#[tokio::test]
+async fn unknown_commands_are_rejected_without_closing_the_connection() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("frobnicate foo bar");
+    connection.write_all(&message).await.unwrap();
+
+    let want =
+        error_string("ERR unknown command 'frobnicate', with args beginning with: 'foo' 'bar' ");
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+
+    let message = encode_string("incr");
+    connection.write_all(&message).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let bytes_read = connection.read(&mut buf).await.unwrap();
+    assert!(buf[..bytes_read].starts_with(b"-ERR "));
+
+    let message = encode_string("ping");
+    connection.write_all(&message).await.unwrap();
+
+    let want = simple_string("PONG");
+    let resp = read_exactly(&mut connection, want.len()).await;
+    assert_eq!(resp, want);
+}
+
+#[tokio::test]
+async fn commands_are_checked_against_their_arity() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    for (command, name) in [
+        ("get", "get"),
+        ("GET foo bar", "get"),
+        ("incrby foo", "incrby"),
+        ("xadd stream *", "xadd"),
+        ("lastsave now", "lastsave"),
+    ] {
+        let resp = send_message(&address, &encode_string(command)).await;
+        assert_eq!(
+            resp,
+            error_string(&format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ))
+        );
+    }
+
+    let resp = send_message(&address, &encode_string("ping")).await;
+    assert_eq!(resp, simple_string("PONG"));
+}
+
+#[tokio::test]
+async fn server_can_push_to_idle_connections() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("ping");
+    connection.write_all(&message).await.unwrap();
+    let want = simple_string("PONG");
+    read_exactly(&mut connection, want.len()).await;
+
+    let client_id = {
+        let server = test_app.redis_server.read().await;
+        assert_eq!(server.clients.len(), 1);
+        *server.clients.keys().next().unwrap()
+    };
+
+    let pushed = simple_string("hello");
+    let push = Push::Message(pushed.as_bytes().to_vec());
+    assert!(test_app.redis_server.push_to_client(client_id, push).await);
+    let resp = read_exactly(&mut connection, pushed.len()).await;
+    assert_eq!(resp, pushed);
+
+    assert!(
+        test_app
+            .redis_server
+            .push_to_client(client_id, Push::Close)
+            .await
+    );
+    let mut buf = vec![0; 16];
+    let bytes_read = timeout(Duration::from_secs(1), connection.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(bytes_read, 0);
+
+    sleep(Duration::from_millis(50)).await;
+    assert!(test_app.redis_server.read().await.clients.is_empty());
+}
+
+/// Sends a request and expects the server to reject it and hang up.
+async fn assert_rejected(address: &str, message: &[u8], want: &str) {
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    connection.write_all(message).await.unwrap();
+
+    let mut response = vec![];
+    timeout(
+        Duration::from_secs(1),
+        connection.read_to_end(&mut response),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(String::from_utf8(response).unwrap(), error_string(want));
+}
+
+#[tokio::test]
+async fn requests_over_the_protocol_limits_are_rejected() {
+    let mut config = Config::new(None, None);
+    config.proto_max_bulk_len = 1024 * 1024;
+    config.proto_max_multibulk_len = 3;
+    config.proto_max_inline_len = 1024;
+    let test_app = TestApp::with_config(config).await;
+    let address = test_app.address.name();
+
+    // Rejected as soon as the length arrives, without waiting for the value
+    let message = format!("*3\r\n$3\r\nset\r\n$3\r\nfoo\r\n${}\r\n", 1024 * 1024 + 1);
+    assert_rejected(
+        &address,
+        message.as_bytes(),
+        "ERR Protocol error: invalid bulk length",
+    )
+    .await;
+
+    let message = encode_string("del a b c");
+    assert_rejected(
+        &address,
+        &message,
+        "ERR Protocol error: invalid multibulk length",
+    )
+    .await;
+
+    let message = format!("*1\r\n${}", "1".repeat(2048));
+    assert_rejected(
+        &address,
+        message.as_bytes(),
+        "ERR Protocol error: too big inline request",
+    )
+    .await;
+
+    let message = encode_string("config get proto-max-bulk-len");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        String::from_utf8(encode_string_array(vec!["proto-max-bulk-len", "1048576"])).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn malformed_frames_get_a_protocol_error() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let cases = [
+        ("PING\r\n", "ERR Protocol error: expected '*', got 'P'"),
+        ("*x\r\n", "ERR Protocol error: invalid multibulk length"),
+        (
+            "*1\r\n:4\r\nPING\r\n",
+            "ERR Protocol error: expected '$', got ':'",
+        ),
+        (
+            "*1\r\n$-4\r\nPING\r\n",
+            "ERR Protocol error: invalid bulk length",
+        ),
+        (
+            "*1\r\n$2\r\nPING\r\n",
+            "ERR Protocol error: expected CRLF after bulk string",
+        ),
+        ("*1\r\n$4\rxPING\r\n", "ERR Protocol error: expected CRLF"),
+    ];
+
+    for (message, want) in cases {
+        assert_rejected(&address, message.as_bytes(), want).await;
+    }
+
+    // Replies to the commands before the bad frame still go out
+    let message = [encode_string("ping"), b"*?\r\n".to_vec()].concat();
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    connection.write_all(&message).await.unwrap();
+
+    let mut response = vec![];
+    timeout(
+        Duration::from_secs(1),
+        connection.read_to_end(&mut response),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let want = format!(
+        "{}{}",
+        simple_string("PONG"),
+        error_string("ERR Protocol error: invalid multibulk length")
+    );
+    assert_eq!(String::from_utf8(response).unwrap(), want);
+}
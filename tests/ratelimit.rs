@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+use not_redis::client::Client;
+use not_redis::request::ConfigKey;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn limited_config(commands_per_second: &str, policy: &str) -> Config {
+    let mut config = Config::new(None, None);
+    config
+        .set(&ConfigKey::ClientMaxCommandsPerSecond, commands_per_second)
+        .unwrap();
+    config
+        .set(&ConfigKey::ClientRateLimitPolicy, policy)
+        .unwrap();
+    config
+}
+
+#[tokio::test]
+async fn clients_over_their_limit_are_throttled() {
+    let test_app = TestApp::with_config(limited_config("5", "reject")).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let mut replies = vec![];
+    for _ in 0..8 {
+        replies.push(client.command(&["PING"]).await.unwrap());
+    }
+    // The allowance refills while the commands run, so a sixth may squeeze in
+    assert_eq!(replies[..5], vec![Value::simple("PONG"); 5]);
+    assert!(replies[6..]
+        .iter()
+        .all(|reply| matches!(reply, Value::Error(message) if message.starts_with("THROTTLED "))));
+
+    // Other clients have allowances of their own
+    let mut other = Client::connect(test_app.address.name()).await.unwrap();
+    let reply = other.command(&["PING"]).await.unwrap();
+    assert_eq!(reply, Value::simple("PONG"));
+
+    let reply = other
+        .command(&["CONFIG", "SET", "client-max-commands-per-second", "0"])
+        .await
+        .unwrap();
+    assert_eq!(reply, Value::ok());
+    let reply = client.command(&["PING"]).await.unwrap();
+    assert_eq!(reply, Value::simple("PONG"));
+}
+
+#[tokio::test]
+async fn clients_can_be_slowed_down_instead() {
+    let test_app = TestApp::with_config(limited_config("4", "delay")).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let start = Instant::now();
+    for _ in 0..8 {
+        let reply = client.command(&["PING"]).await.unwrap();
+        assert_eq!(reply, Value::simple("PONG"));
+    }
+    assert!(start.elapsed().as_millis() >= 900);
+}
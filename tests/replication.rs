@@ -1,5 +1,15 @@
+use std::io::Cursor;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, timeout, Duration, Instant};
+
 use common::{encode_string, send_message, TestApp};
-use not_redis::encoding::{bulk_string, simple_string};
+use not_redis::data::Database;
+use not_redis::encoding::{bulk_string, empty_string, encode_integer, error_string, simple_string};
+use not_redis::server::{Address, Config};
+use not_redis::utils::current_unix_timestamp;
 
 mod common;
 
@@ -26,6 +36,31 @@ pub async fn info_master() {
     assert!(resp.contains(&want_repl_offset));
 }
 
+#[tokio::test]
+pub async fn info_master_lists_connected_slaves() {
+    let test_app_master = TestApp::master().await;
+    let test_app_slave = TestApp::slave(test_app_master.address.clone()).await;
+    let address = test_app_master.address.name();
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    // Give the replica time to send its next heartbeat
+    sleep(Duration::from_millis(1500)).await;
+
+    let master_offset = test_app_master.redis_server.read().await.replication.offset;
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+
+    assert!(resp.contains("connected_slaves:1"));
+    let want_slave = format!(
+        "slave0:ip=127.0.0.1,port={},state=online,offset={},lag=",
+        test_app_slave.address.port(),
+        master_offset
+    );
+    assert!(resp.contains(&want_slave), "{}", resp);
+}
+
 #[tokio::test]
 pub async fn info_slave() {
     let test_app_master = TestApp::master().await;
@@ -63,4 +98,694 @@ pub async fn set_replicated_to_slave() {
     assert_eq!(resp, bulk_string("bar"));
 }
 
-// TODO: Test wait when it's fixed
+#[tokio::test]
+pub async fn every_write_is_replicated_to_slave() {
+    let test_app_master = TestApp::master().await;
+    let test_app_slave = TestApp::slave(test_app_master.address.clone()).await;
+    let master_address = test_app_master.address.name();
+    let slave_address = test_app_slave.address.name();
+
+    for command in [
+        "set foo bar",
+        "setrange foo 1 ee",
+        "mset first 1 second 2",
+        "set gone soon",
+        "del gone",
+        "set counter 10",
+        "incr counter",
+        "incrby counter 5",
+        "decr counter",
+        "set taken now",
+        "getdel taken",
+        "set stale yes",
+        "pexpireat stale 1",
+        "set later yes",
+        "expire later 1000",
+        "xadd stream 1-1 field value",
+    ] {
+        let message = encode_string(command);
+        send_message(&master_address, &message).await;
+    }
+
+    // Reads aren't replicated, so only the writes above count towards the offset
+    let message = encode_string("get foo");
+    send_message(&master_address, &message).await;
+
+    let message = encode_string("wait 1 1000");
+    let resp = send_message(&master_address, &message).await;
+    assert_eq!(resp, encode_integer(1));
+
+    let message = encode_string("get gone");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    let message = encode_string("get foo");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("bee"));
+
+    let message = encode_string("get second");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("2"));
+
+    let message = encode_string("get taken");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    let message = encode_string("get stale");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    let message = encode_string("get later");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("yes"));
+
+    let message = encode_string("get counter");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("15"));
+
+    let message = encode_string("type stream");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("stream"));
+
+    let master_offset = test_app_master.redis_server.read().await.replication.offset;
+    let message = encode_string("info replication");
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains(&format!("master_repl_offset:{}", master_offset)));
+}
+
+#[tokio::test]
+pub async fn slave_rejects_writes_from_clients() {
+    let test_app_master = TestApp::master().await;
+    let test_app_slave = TestApp::slave(test_app_master.address.clone()).await;
+    let address = test_app_slave.address.name();
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(
+        resp,
+        error_string("READONLY You can't write against a read only replica")
+    );
+
+    let message = encode_string("config set replica-read-only no");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+}
+
+#[tokio::test]
+pub async fn slave_starts_with_the_masters_dataset() {
+    let test_app_master = TestApp::master().await;
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&test_app_master.address.name(), &message).await;
+    assert_eq!(resp, simple_string("OK"));
+
+    let test_app_slave = TestApp::slave(test_app_master.address.clone()).await;
+
+    let message = encode_string("get foo");
+    let resp = send_message(&test_app_slave.address.name(), &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+}
+
+#[tokio::test]
+pub async fn wait_without_writes_counts_every_replica() {
+    let test_app_master = TestApp::master().await;
+    let _slave_one = TestApp::slave(test_app_master.address.clone()).await;
+    let _slave_two = TestApp::slave(test_app_master.address.clone()).await;
+
+    let message = encode_string("wait 2 500");
+    let resp = send_message(&test_app_master.address.name(), &message).await;
+
+    assert_eq!(resp, encode_integer(2));
+}
+
+#[tokio::test]
+pub async fn wait_returns_once_replicas_acknowledge_writes() {
+    let test_app_master = TestApp::master().await;
+    let _slave_one = TestApp::slave(test_app_master.address.clone()).await;
+    let _slave_two = TestApp::slave(test_app_master.address.clone()).await;
+    let address = test_app_master.address.name();
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    let started = Instant::now();
+    let message = encode_string("wait 2 5000");
+    let resp = send_message(&address, &message).await;
+
+    assert_eq!(resp, encode_integer(2));
+    assert!(started.elapsed() < Duration::from_millis(5000));
+
+    // Acknowledging the GETACK doesn't count as a write that needs waiting for
+    let message = encode_string("wait 2 5000");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, encode_integer(2));
+}
+
+#[tokio::test]
+pub async fn wait_times_out_with_the_replicas_that_acknowledged() {
+    let test_app_master = TestApp::master().await;
+    let _slave = TestApp::slave(test_app_master.address.clone()).await;
+    let address = test_app_master.address.name();
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    let started = Instant::now();
+    let message = encode_string("wait 3 300");
+    let resp = send_message(&address, &message).await;
+
+    assert_eq!(resp, encode_integer(1));
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+pub async fn psync_sends_the_current_dataset() {
+    let test_app_master = TestApp::master().await;
+    let address = test_app_master.address.name();
+
+    for command in ["set foo bar", "set num 42"] {
+        let message = encode_string(command);
+        send_message(&address, &message).await;
+    }
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("psync ? -1");
+    connection.write_all(&message).await.unwrap();
+
+    let mut response = vec![];
+    let rdb = loop {
+        let mut buf = vec![0; 1024];
+        let bytes_read = timeout(Duration::from_secs(1), connection.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(bytes_read, 0);
+        response.extend(&buf[..bytes_read]);
+
+        let text = String::from_utf8_lossy(&response).to_string();
+        let mut lines = text.splitn(3, "\r\n");
+        let (Some(header), Some(size)) = (lines.next(), lines.next()) else {
+            continue;
+        };
+        assert!(header.starts_with("+FULLRESYNC"));
+
+        let start = header.len() + size.len() + 4;
+        let size: usize = size[1..].parse().unwrap();
+        if response.len() >= start + size {
+            break response[start..start + size].to_vec();
+        }
+    };
+
+    let database = Database::from_rdb(&mut Cursor::new(rdb)).unwrap();
+    assert_eq!(database.get("foo").unwrap(), Some("bar".to_string()));
+    assert_eq!(database.get("num").unwrap(), Some("42".to_string()));
+}
+
+#[tokio::test]
+pub async fn slave_acknowledges_its_offset_every_second() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master_address = listener.local_addr().unwrap();
+
+    let fake_master = tokio::spawn(async move {
+        let (mut connection, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 1024];
+
+        for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+            let _ = connection.read(&mut buf).await.unwrap();
+            connection.write_all(reply.as_bytes()).await.unwrap();
+        }
+
+        let _ = connection.read(&mut buf).await.unwrap();
+        let rdb = Database::new().to_rdb(false).unwrap();
+        let mut response = format!("+FULLRESYNC {} 0\r\n${}\r\n", "a".repeat(40), rdb.len())
+            .as_bytes()
+            .to_vec();
+        response.extend(rdb);
+        connection.write_all(&response).await.unwrap();
+
+        // Two acknowledgements arrive without ever sending a GETACK
+        let mut acks = String::new();
+        while acks.matches("ACK").count() < 2 {
+            let bytes_read = connection.read(&mut buf).await.unwrap();
+            assert_ne!(bytes_read, 0);
+            acks.push_str(&String::from_utf8_lossy(&buf[..bytes_read]));
+        }
+        acks
+    });
+
+    let address = Address::new("127.0.0.1".into(), master_address.port());
+    let _test_app_slave = TestApp::slave(address).await;
+
+    let acks = timeout(Duration::from_secs(3), fake_master)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(acks.starts_with(&String::from_utf8(encode_string("REPLCONF ACK 0")).unwrap()));
+}
+
+#[tokio::test]
+pub async fn disconnected_slaves_are_dropped() {
+    let test_app_master = TestApp::master().await;
+    let address = test_app_master.address.name();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string("psync ? -1");
+    connection.write_all(&message).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let _ = connection.read(&mut buf).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("connected_slaves:1"));
+
+    drop(connection);
+    sleep(Duration::from_millis(1500)).await;
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("connected_slaves:0"));
+
+    let message = encode_string("set foo bar");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, simple_string("OK"));
+}
+
+#[tokio::test]
+pub async fn psync_continues_from_the_backlog() {
+    let test_app_master = TestApp::master().await;
+    let address = test_app_master.address.name();
+    let repl_id = test_app_master
+        .redis_server
+        .read()
+        .await
+        .replication
+        .id
+        .clone();
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string(&format!("psync {} 0", repl_id));
+    connection.write_all(&message).await.unwrap();
+
+    let want = format!(
+        "{}{}",
+        simple_string("CONTINUE"),
+        String::from_utf8(encode_string("set foo bar")).unwrap()
+    );
+    let mut response = vec![];
+    while response.len() < want.len() {
+        let mut buf = vec![0; 1024];
+        let bytes_read = timeout(Duration::from_secs(1), connection.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(bytes_read, 0);
+        response.extend(&buf[..bytes_read]);
+    }
+
+    assert_eq!(String::from_utf8(response).unwrap(), want);
+}
+
+/// Registers as a replica listening on `port` and returns the link along with the
+/// first line of the master's reply to PSYNC.
+async fn psync_as_replica(address: &str, port: u16, repl_id: &str) -> (TcpStream, String) {
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    let message = encode_string(&format!("replconf listening-port {}", port));
+    connection.write_all(&message).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let _ = connection.read(&mut buf).await.unwrap();
+
+    let message = encode_string(&format!("psync {} 0", repl_id));
+    connection.write_all(&message).await.unwrap();
+    let bytes_read = connection.read(&mut buf).await.unwrap();
+    let reply = String::from_utf8_lossy(&buf[..bytes_read]);
+    let first_line = reply.split("\r\n").next().unwrap().to_string();
+
+    (connection, first_line)
+}
+
+#[tokio::test]
+pub async fn replicas_out_of_sync_are_made_to_resync() {
+    let test_app_master = TestApp::master().await;
+    let address = test_app_master.address.name();
+    let repl_id = test_app_master
+        .redis_server
+        .read()
+        .await
+        .replication
+        .id
+        .clone();
+
+    let message = encode_string("set foo bar");
+    send_message(&address, &message).await;
+
+    let (mut connection, reply) = psync_as_replica(&address, 7001, &repl_id).await;
+    assert_eq!(reply, "+CONTINUE");
+
+    // Further than the master has ever got
+    let message = encode_string("replconf ack 1000000");
+    connection.write_all(&message).await.unwrap();
+    sleep(Duration::from_millis(1500)).await;
+
+    let message = encode_string("info replication");
+    let resp = send_message(&address, &message).await;
+    assert!(resp.contains("connected_slaves:0"));
+    assert!(resp.contains("replica_desyncs:1"));
+
+    let (_connection, reply) = psync_as_replica(&address, 7001, &repl_id).await;
+    assert!(reply.starts_with("+FULLRESYNC"));
+
+    // Only the one resync is forced
+    let (_connection, reply) = psync_as_replica(&address, 7001, &repl_id).await;
+    assert_eq!(reply, "+CONTINUE");
+}
+
+#[tokio::test]
+pub async fn slave_reconnects_and_continues_where_it_left_off() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master_address = listener.local_addr().unwrap();
+    let repl_id = "b".repeat(40);
+    let first_write = encode_string("set foo bar");
+
+    let fake_master = {
+        let repl_id = repl_id.clone();
+        let first_write = first_write.clone();
+        tokio::spawn(async move {
+            let mut connection = accept_replica(&listener).await;
+            let rdb = Database::new().to_rdb(false).unwrap();
+            let mut response = format!("+FULLRESYNC {} 0\r\n${}\r\n", repl_id, rdb.len())
+                .as_bytes()
+                .to_vec();
+            response.extend(rdb);
+            response.extend(&first_write);
+            connection.write_all(&response).await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            drop(connection);
+
+            // Leave the link down for a bit before letting the replica back in
+            sleep(Duration::from_millis(600)).await;
+            let mut connection = accept_replica(&listener).await;
+            let mut response = simple_string("CONTINUE").as_bytes().to_vec();
+            response.extend(encode_string("set baz qux"));
+            connection.write_all(&response).await.unwrap();
+            sleep(Duration::from_secs(5)).await;
+        })
+    };
+
+    let address = Address::new("127.0.0.1".into(), master_address.port());
+    let test_app_slave = TestApp::slave(address).await;
+    let slave_address = test_app_slave.address.name();
+
+    let message = encode_string("info replication");
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains("master_link_status:down"));
+
+    sleep(Duration::from_millis(1000)).await;
+
+    let message = encode_string("info replication");
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains("master_link_status:up"));
+    let want_offset = format!(
+        "master_repl_offset:{}",
+        first_write.len() + encode_string("set baz qux").len()
+    );
+    assert!(resp.contains(&want_offset), "{}", resp);
+
+    for (key, value) in [("foo", "bar"), ("baz", "qux")] {
+        let message = encode_string(&format!("get {}", key));
+        let resp = send_message(&slave_address, &message).await;
+        assert_eq!(resp, bulk_string(value));
+    }
+
+    fake_master.abort();
+}
+
+#[tokio::test]
+pub async fn slave_refuses_stale_reads_once_the_master_is_down() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master_address = listener.local_addr().unwrap();
+
+    let fake_master = tokio::spawn(async move {
+        let mut connection = accept_replica(&listener).await;
+        let rdb = Database::new().to_rdb(false).unwrap();
+        let mut response = format!("+FULLRESYNC {} 0\r\n${}\r\n", "d".repeat(40), rdb.len())
+            .as_bytes()
+            .to_vec();
+        response.extend(rdb);
+        response.extend(encode_string("set foo bar"));
+        connection.write_all(&response).await.unwrap();
+        sleep(Duration::from_millis(800)).await;
+        drop(connection);
+
+        // Keep the replica from getting back in
+        sleep(Duration::from_secs(5)).await;
+    });
+
+    let mut config = Config::new(None, None);
+    config.replica_serve_stale_data = false;
+    let address = Address::new("127.0.0.1".into(), master_address.port());
+    let test_app_slave = TestApp::slave_with_config(address, config).await;
+    let slave_address = test_app_slave.address.name();
+
+    let message = encode_string("get foo");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    sleep(Duration::from_millis(600)).await;
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(
+        resp,
+        error_string(
+            "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'."
+        )
+    );
+
+    let message = encode_string("info replication");
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains("master_link_status:down"));
+
+    fake_master.abort();
+}
+
+#[tokio::test]
+pub async fn master_pings_idle_replicas() {
+    let mut config = Config::new(None, None);
+    config.repl_ping_replica_period = 1;
+    let test_app_master = TestApp::with_config(config).await;
+    let repl_id = test_app_master
+        .redis_server
+        .read()
+        .await
+        .replication
+        .id
+        .clone();
+
+    let mut connection = TcpStream::connect(&test_app_master.address.name())
+        .await
+        .unwrap();
+    let message = encode_string(&format!("psync {} 0", repl_id));
+    connection.write_all(&message).await.unwrap();
+
+    let want = format!(
+        "{}{}",
+        simple_string("CONTINUE"),
+        String::from_utf8(encode_string("PING")).unwrap()
+    );
+    let mut response = vec![];
+    while response.len() < want.len() {
+        let mut buf = vec![0; 1024];
+        let bytes_read = timeout(Duration::from_secs(3), connection.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(bytes_read, 0);
+        response.extend(&buf[..bytes_read]);
+    }
+
+    assert_eq!(String::from_utf8(response).unwrap(), want);
+}
+
+#[tokio::test]
+pub async fn slave_drops_a_silent_master() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master_address = listener.local_addr().unwrap();
+
+    let fake_master = tokio::spawn(async move {
+        let mut connection = accept_replica(&listener).await;
+        let rdb = Database::new().to_rdb(false).unwrap();
+        let mut response = format!("+FULLRESYNC {} 0\r\n${}\r\n", "e".repeat(40), rdb.len())
+            .as_bytes()
+            .to_vec();
+        response.extend(rdb);
+        connection.write_all(&response).await.unwrap();
+
+        // Hold the link open without sending anything
+        sleep(Duration::from_secs(5)).await;
+    });
+
+    let mut config = Config::new(None, None);
+    config.repl_timeout = 1;
+    let address = Address::new("127.0.0.1".into(), master_address.port());
+    let test_app_slave = TestApp::slave_with_config(address, config).await;
+    let slave_address = test_app_slave.address.name();
+
+    let message = encode_string("info replication");
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains("master_link_status:up"));
+
+    sleep(Duration::from_millis(1000)).await;
+    let resp = send_message(&slave_address, &message).await;
+    assert!(resp.contains("master_link_status:down"));
+
+    fake_master.abort();
+}
+
+/// Plays the master's side of the handshake up to PSYNC, checking that a replica
+/// which has synced before asks to continue from its offset.
+async fn accept_replica(listener: &TcpListener) -> TcpStream {
+    let (mut connection, _) = listener.accept().await.unwrap();
+    let mut buf = vec![0; 1024];
+
+    for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+        let _ = connection.read(&mut buf).await.unwrap();
+        connection.write_all(reply.as_bytes()).await.unwrap();
+    }
+
+    let bytes_read = connection.read(&mut buf).await.unwrap();
+    let psync = String::from_utf8_lossy(&buf[..bytes_read]).to_string();
+    assert!(psync.contains("PSYNC"));
+
+    connection
+}
+
+#[tokio::test]
+pub async fn expired_keys_are_deleted_on_replicas() {
+    let test_app_master = TestApp::master().await;
+    let address = test_app_master.address.name();
+    let repl_id = test_app_master
+        .redis_server
+        .read()
+        .await
+        .replication
+        .id
+        .clone();
+
+    let before = current_unix_timestamp().unwrap();
+    let message = encode_string("set foo bar px 100");
+    send_message(&address, &message).await;
+    let after = current_unix_timestamp().unwrap();
+
+    let mut connection = TcpStream::connect(&address).await.unwrap();
+    let message = encode_string(&format!("psync {} 0", repl_id));
+    connection.write_all(&message).await.unwrap();
+
+    // The expiry is sent as the deadline the master worked out
+    let want = |deadline: u128| {
+        format!(
+            "{}{}{}",
+            simple_string("CONTINUE"),
+            String::from_utf8(encode_string(&format!("SET foo bar PXAT {}", deadline))).unwrap(),
+            String::from_utf8(encode_string("DEL foo")).unwrap()
+        )
+    };
+    let mut response = vec![];
+    while response.len() < want(before + 100).len() {
+        let mut buf = vec![0; 1024];
+        let bytes_read = timeout(Duration::from_secs(1), connection.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(bytes_read, 0);
+        response.extend(&buf[..bytes_read]);
+    }
+
+    let response = String::from_utf8(response).unwrap();
+    assert!(
+        (before + 100..=after + 100).any(|deadline| response == want(deadline)),
+        "{}",
+        response
+    );
+}
+
+#[tokio::test]
+pub async fn writes_are_replicated_with_what_the_master_worked_out() {
+    let test_app_master = TestApp::master().await;
+    let test_app_slave = TestApp::slave(test_app_master.address.clone()).await;
+    let master_address = test_app_master.address.name();
+    let slave_address = test_app_slave.address.name();
+
+    for command in [
+        "set float 1.5",
+        "incrbyfloat float 0.1",
+        "xadd stream * field value",
+        "set expiring soon ex 100",
+    ] {
+        let message = encode_string(command);
+        send_message(&master_address, &message).await;
+    }
+
+    let message = encode_string("wait 1 1000");
+    let resp = send_message(&master_address, &message).await;
+    assert_eq!(resp, encode_integer(1));
+
+    let message = encode_string("get float");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("1.6"));
+
+    // The entry has the ID the master generated, not one the replica came up with
+    let message = encode_string("xrange stream - +");
+    let master_entries = send_message(&master_address, &message).await;
+    let slave_entries = send_message(&slave_address, &message).await;
+    assert_eq!(master_entries, slave_entries);
+
+    assert!(test_app_slave.database.ttl("expiring").is_some());
+}
+
+#[tokio::test]
+pub async fn slave_waits_for_the_master_to_expire_keys() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let master_address = listener.local_addr().unwrap();
+    let (expire_tx, expire_rx) = oneshot::channel::<()>();
+
+    let fake_master = tokio::spawn(async move {
+        let mut connection = accept_replica(&listener).await;
+        let rdb = Database::new().to_rdb(false).unwrap();
+        let mut response = format!("+FULLRESYNC {} 0\r\n${}\r\n", "c".repeat(40), rdb.len())
+            .as_bytes()
+            .to_vec();
+        response.extend(rdb);
+        response.extend(encode_string("set foo bar px 100"));
+        connection.write_all(&response).await.unwrap();
+
+        expire_rx.await.unwrap();
+        connection
+            .write_all(&encode_string("DEL foo"))
+            .await
+            .unwrap();
+        sleep(Duration::from_secs(5)).await;
+    });
+
+    let address = Address::new("127.0.0.1".into(), master_address.port());
+    let test_app_slave = TestApp::slave(address).await;
+    let slave_address = test_app_slave.address.name();
+
+    // Well past the key's expiry, but the master hasn't said so yet
+    sleep(Duration::from_millis(200)).await;
+    let message = encode_string("get foo");
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, bulk_string("bar"));
+
+    expire_tx.send(()).unwrap();
+    sleep(Duration::from_millis(100)).await;
+    let resp = send_message(&slave_address, &message).await;
+    assert_eq!(resp, empty_string());
+
+    fake_master.abort();
+}
@@ -7,6 +7,7 @@ use common::{
     encode_stream_items, encode_streams, encode_string, send_message, StreamData, StreamItem,
     TestApp,
 };
+use not_redis::client::{Client, Pipeline};
 use not_redis::encoding::{bulk_string, empty_string, error_string};
 
 mod common;
@@ -59,6 +60,10 @@ async fn unspecified_stream_id_be_greater_than_previous() {
     let message = encode_string("xadd cool 100-* one two");
     let resp = send_message(&address, &message).await;
     assert_eq!(resp, bulk_string("100-1"));
+
+    let message = encode_string("xadd cool 100-* one two");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("100-2"));
 }
 
 #[tokio::test]
@@ -86,6 +91,27 @@ async fn autogenerate_consecutive_stream_ids() {
     assert!(got_id_2 > got_id_1);
 }
 
+#[tokio::test]
+async fn autogenerated_ids_in_the_same_millisecond_keep_increasing() {
+    let test_app = TestApp::master().await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+
+    let mut pipeline = Pipeline::new();
+    for _ in 0..100 {
+        pipeline.add(&["XADD", "cool", "*", "one", "two"]);
+    }
+    let replies = client.pipeline(&pipeline).await.unwrap();
+
+    let ids: Vec<(u128, usize)> = replies
+        .iter()
+        .map(|reply| {
+            let (ms_time, sequence_number) = reply.as_str().unwrap().split_once('-').unwrap();
+            (ms_time.parse().unwrap(), sequence_number.parse().unwrap())
+        })
+        .collect();
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
 #[tokio::test]
 async fn xrange_read_specified_range_from_stream() {
     let test_app = TestApp::master().await;
@@ -515,6 +541,45 @@ async fn block_reads_with_no_id_specified_returns_all_new_entries() {
     assert_eq!(block_resp, want_streams);
 }
 
+#[tokio::test]
+async fn blocked_reads_are_only_woken_by_writes_to_their_streams() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let message = encode_string("xadd cool 1-0 one two");
+    send_message(&address, &message).await;
+
+    let addr = address.clone();
+    let join_handle = tokio::spawn(async move {
+        let message = encode_string("xread block 0 streams cool other $ $");
+        send_message(&addr, &message).await
+    });
+
+    sleep(Duration::from_millis(100)).await;
+
+    let message = encode_string("xadd unrelated 1-0 three four");
+    send_message(&address, &message).await;
+
+    sleep(Duration::from_millis(100)).await;
+    assert!(!join_handle.is_finished());
+
+    let message = encode_string("xadd other 5-0 five six");
+    send_message(&address, &message).await;
+
+    let block_resp = join_handle.await.unwrap();
+
+    let stream_data = StreamData {
+        name: "other",
+        items: vec![StreamItem {
+            id: "5-0",
+            items: vec!["five", "six"],
+        }],
+    };
+    let want_streams = encode_streams(vec![stream_data]);
+
+    assert_eq!(block_resp, want_streams);
+}
+
 #[tokio::test]
 async fn receive_errors_if_item_not_stream() {
     let test_app = TestApp::master().await;
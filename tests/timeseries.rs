@@ -0,0 +1,262 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn samples(samples: &[(i64, &str)]) -> Value {
+    Value::Array(
+        samples
+            .iter()
+            .map(|(timestamp, value)| {
+                Value::Array(vec![Value::Integer(*timestamp), Value::from(*value)])
+            })
+            .collect(),
+    )
+}
+
+#[tokio::test]
+async fn samples_can_be_added_and_read() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for (timestamp, value) in [
+        ("1000", "1"),
+        ("1010", "2.5"),
+        ("1005", "4"),
+        ("1030", "-3"),
+    ] {
+        let reply = client.command(&["TS.ADD", "temp", timestamp, value]).await;
+        assert_eq!(reply.unwrap(), Value::Integer(timestamp.parse().unwrap()));
+    }
+
+    let reply = client.command(&["TS.GET", "temp"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![Value::Integer(1030), Value::from("-3")])
+    );
+    let reply = client.command(&["TS.RANGE", "temp", "-", "+"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        samples(&[(1000, "1"), (1005, "4"), (1010, "2.5"), (1030, "-3")])
+    );
+    let reply = client.command(&["TS.RANGE", "temp", "1001", "1010"]).await;
+    assert_eq!(reply.unwrap(), samples(&[(1005, "4"), (1010, "2.5")]));
+
+    let reply = client
+        .command(&["TS.RANGE", "temp", "-", "+", "AGGREGATION", "avg", "10"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        samples(&[(1000, "2.5"), (1010, "2.5"), (1030, "-3")])
+    );
+    let reply = client
+        .command(&["TS.RANGE", "temp", "-", "+", "AGGREGATION", "MAX", "20"])
+        .await;
+    assert_eq!(reply.unwrap(), samples(&[(1000, "4"), (1020, "-3")]));
+    let reply = client
+        .command(&["TS.RANGE", "temp", "-", "+", "AGGREGATION", "min", "1000"])
+        .await;
+    assert_eq!(reply.unwrap(), samples(&[(1000, "-3")]));
+
+    let reply = client.command(&["TS.ADD", "temp", "1000", "9"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode")
+    );
+    for (command, error) in [
+        (
+            vec!["TS.ADD", "temp", "soon", "1"],
+            "ERR TSDB: invalid timestamp",
+        ),
+        (vec!["TS.ADD", "temp", "1", "x"], "ERR TSDB: invalid value"),
+        (
+            vec!["TS.RANGE", "temp", "x", "+"],
+            "ERR TSDB: wrong fromTimestamp",
+        ),
+        (
+            vec!["TS.RANGE", "temp", "-", "x"],
+            "ERR TSDB: wrong toTimestamp",
+        ),
+        (
+            vec!["TS.RANGE", "temp", "-", "+", "AGGREGATION", "sum", "10"],
+            "ERR TSDB: Unknown aggregation type",
+        ),
+        (
+            vec!["TS.RANGE", "temp", "-", "+", "AGGREGATION", "avg", "0"],
+            "ERR TSDB: bucketDuration must be greater than zero",
+        ),
+        (
+            vec!["TS.GET", "nothing"],
+            "ERR TSDB: the key does not exist",
+        ),
+        (
+            vec!["TS.RANGE", "nothing", "-", "+"],
+            "ERR TSDB: the key does not exist",
+        ),
+    ] {
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+
+    let reply = client.command(&["TS.ADD", "now", "*", "1"]).await.unwrap();
+    assert!(matches!(reply, Value::Integer(timestamp) if timestamp > 1_600_000_000_000));
+    let reply = client.command(&["TYPE", "temp"]).await;
+    assert_eq!(reply.unwrap(), Value::from("TSDB-TYPE"));
+}
+
+#[tokio::test]
+async fn old_samples_fall_out_of_the_retention() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    client
+        .command(&["TS.ADD", "temp", "1000", "1", "RETENTION", "100"])
+        .await
+        .unwrap();
+    client
+        .command(&["TS.ADD", "temp", "1050", "2"])
+        .await
+        .unwrap();
+    client
+        .command(&["TS.ADD", "temp", "1150", "3"])
+        .await
+        .unwrap();
+
+    let reply = client.command(&["TS.RANGE", "temp", "-", "+"]).await;
+    assert_eq!(reply.unwrap(), samples(&[(1050, "2"), (1150, "3")]));
+    let reply = client.command(&["TS.ADD", "temp", "1020", "4"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR TSDB: Timestamp is older than retention")
+    );
+}
+
+#[tokio::test]
+async fn series_are_ranged_over_by_label() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for (key, labels) in [
+        ("kitchen", ["type", "temp", "room", "kitchen"]),
+        ("hall", ["type", "temp", "room", "hall"]),
+        ("humidity", ["type", "humidity", "room", "kitchen"]),
+    ] {
+        for (timestamp, value) in [("10", "1"), ("20", "3")] {
+            let mut command = vec!["TS.ADD", key, timestamp, value, "LABELS"];
+            command.extend(labels);
+            client.command(&command).await.unwrap();
+        }
+    }
+    client.command(&["SET", "string", "value"]).await.unwrap();
+
+    let reply = client
+        .command(&["TS.MRANGE", "-", "+", "FILTER", "type=temp"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![
+            Value::Array(vec![
+                Value::from("hall"),
+                Value::Array(vec![]),
+                samples(&[(10, "1"), (20, "3")]),
+            ]),
+            Value::Array(vec![
+                Value::from("kitchen"),
+                Value::Array(vec![]),
+                samples(&[(10, "1"), (20, "3")]),
+            ]),
+        ])
+    );
+
+    let reply = client
+        .command(&[
+            "TS.MRANGE",
+            "0",
+            "100",
+            "WITHLABELS",
+            "AGGREGATION",
+            "avg",
+            "100",
+            "FILTER",
+            "room=kitchen",
+            "type!=temp",
+        ])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![Value::Array(vec![
+            Value::from("humidity"),
+            Value::Array(vec![
+                Value::bulk_array(&["type", "humidity"]),
+                Value::bulk_array(&["room", "kitchen"]),
+            ]),
+            samples(&[(0, "2")]),
+        ])])
+    );
+
+    let reply = client
+        .command(&["TS.MRANGE", "-", "+", "FILTER", "room="])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR TSDB: please provide at least one matcher")
+    );
+    let reply = client
+        .command(&["TS.MRANGE", "-", "+", "FILTER", "room"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::error("ERR TSDB: failed parsing labels")
+    );
+}
+
+#[tokio::test]
+async fn series_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client
+        .command(&[
+            "TS.ADD",
+            "temp",
+            "10",
+            "1.5",
+            "RETENTION",
+            "50",
+            "LABELS",
+            "room",
+            "hall",
+        ])
+        .await
+        .unwrap();
+    client
+        .command(&["TS.ADD", "temp", "20", "2"])
+        .await
+        .unwrap();
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let reply = client.command(&["TS.RANGE", "temp", "-", "+"]).await;
+    assert_eq!(reply.unwrap(), samples(&[(10, "1.5"), (20, "2")]));
+    let reply = client
+        .command(&["TS.MRANGE", "-", "+", "FILTER", "room=hall"])
+        .await
+        .unwrap();
+    assert!(matches!(reply, Value::Array(series) if series.len() == 1));
+    let reply = client.command(&["TS.ADD", "temp", "80", "3"]).await;
+    assert_eq!(reply.unwrap(), Value::Integer(80));
+    let reply = client.command(&["TS.RANGE", "temp", "-", "+"]).await;
+    assert_eq!(reply.unwrap(), samples(&[(80, "3")]));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::fs;
+
+use not_redis::client::Client;
+use not_redis::resp::Value;
+use not_redis::server::Config;
+
+use common::TestApp;
+
+mod common;
+
+fn integers(values: &[i64]) -> Value {
+    Value::Array(values.iter().copied().map(Value::Integer).collect())
+}
+
+#[tokio::test]
+async fn the_most_frequent_items_are_kept() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    let reply = client.command(&["TOPK.RESERVE", "top", "2"]).await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["TOPK.RESERVE", "top", "3"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR TopK: key already exists"));
+
+    let reply = client
+        .command(&["TOPK.ADD", "top", "apple", "pear", "plum", "plum"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::from("apple")
+        ])
+    );
+
+    let reply = client
+        .command(&["TOPK.QUERY", "top", "plum", "pear", "apple"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[1, 1, 0]));
+    let reply = client
+        .command(&["TOPK.COUNT", "top", "plum", "pear", "cherry"])
+        .await;
+    assert_eq!(reply.unwrap(), integers(&[2, 1, 0]));
+
+    let reply = client.command(&["TOPK.LIST", "top"]).await;
+    assert_eq!(reply.unwrap(), Value::bulk_array(&["plum", "pear"]));
+    let reply = client.command(&["TOPK.LIST", "top", "WITHCOUNT"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        Value::Array(vec![
+            Value::from("plum"),
+            Value::Integer(2),
+            Value::from("pear"),
+            Value::Integer(1),
+        ])
+    );
+
+    let reply = client.command(&["TYPE", "top"]).await;
+    assert_eq!(reply.unwrap(), Value::from("TopK-TYPE"));
+    for command in [
+        vec!["TOPK.ADD", "nothing", "apple"],
+        vec!["TOPK.QUERY", "nothing", "apple"],
+        vec!["TOPK.LIST", "nothing"],
+    ] {
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error("ERR TopK: key does not exist"));
+    }
+}
+
+#[tokio::test]
+async fn reserve_checks_its_arguments() {
+    let (_test_app, mut client) = TestApp::master_with_client().await;
+
+    for (args, error) in [
+        (vec!["0"], "ERR TopK: invalid k"),
+        (vec!["5", "8"], "ERR syntax error"),
+        (vec!["5", "0", "7", "0.9"], "ERR TopK: invalid width"),
+        (vec!["5", "8", "x", "0.9"], "ERR TopK: invalid depth"),
+        (
+            vec!["5", "8", "7", "1.5"],
+            "ERR TopK: invalid decay value. must be '<= 1' & '> 0'",
+        ),
+    ] {
+        let mut command = vec!["TOPK.RESERVE", "top"];
+        command.extend(args);
+        let reply = client.command(&command).await;
+        assert_eq!(reply.unwrap(), Value::error(error));
+    }
+
+    let reply = client
+        .command(&["TOPK.RESERVE", "top", "5", "100", "5", "0.95"])
+        .await;
+    assert_eq!(reply.unwrap(), Value::ok());
+    let reply = client.command(&["TOPK.LIST", "top", "SOMETHING"]).await;
+    assert_eq!(reply.unwrap(), Value::error("ERR syntax error"));
+}
+
+#[tokio::test]
+async fn top_items_survive_a_restart() {
+    let dir = env::temp_dir().join(format!("not-redis-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = Config::new(
+        Some(dir.to_string_lossy().to_string()),
+        Some("dump.rdb".into()),
+    );
+
+    let test_app = TestApp::with_config(config.clone()).await;
+    let mut client = Client::connect(test_app.address.name()).await.unwrap();
+    client.command(&["TOPK.RESERVE", "top", "3"]).await.unwrap();
+    client
+        .command(&["TOPK.ADD", "top", "a", "b", "a", "c", "a", "b"])
+        .await
+        .unwrap();
+    let before = client.command(&["TOPK.LIST", "top", "WITHCOUNT"]).await;
+    assert_eq!(client.command(&["SAVE"]).await.unwrap(), Value::ok());
+
+    let restored_app = TestApp::with_config(config).await;
+    let mut client = Client::connect(restored_app.address.name()).await.unwrap();
+    let after = client.command(&["TOPK.LIST", "top", "WITHCOUNT"]).await;
+    assert_eq!(after.unwrap(), before.unwrap());
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -1,5 +1,5 @@
 use common::{encode_string, send_message, TestApp};
-use not_redis::encoding::{bulk_string, simple_string};
+use not_redis::encoding::{bulk_string, empty_string, simple_string};
 
 mod common;
 
@@ -40,3 +40,38 @@ async fn get_type_missing() {
     let resp = send_message(&address, &message).await;
     assert_eq!(resp, bulk_string("none"));
 }
+
+#[tokio::test]
+async fn object_encoding_reflects_how_values_are_stored() {
+    let test_app = TestApp::master().await;
+    let address = test_app.address.name();
+
+    let long_value = "a".repeat(45);
+    let cases = [
+        ("set num 123", "object encoding num", "int"),
+        ("incrby num 10", "object encoding num", "int"),
+        ("incrbyfloat num 0.5", "object encoding num", "embstr"),
+        ("set padded 0123", "object encoding padded", "embstr"),
+        ("set short hello", "object encoding short", "embstr"),
+        (
+            &format!("set long {}", long_value),
+            "object encoding long",
+            "raw",
+        ),
+        ("xadd cool 0-1 foo bar", "object encoding cool", "stream"),
+    ];
+
+    for (write, object, want) in cases {
+        send_message(&address, &encode_string(write)).await;
+        let resp = send_message(&address, &encode_string(object)).await;
+        assert_eq!(resp, bulk_string(want), "{}", write);
+    }
+
+    let message = encode_string("get num");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, bulk_string("133.5"));
+
+    let message = encode_string("object encoding missing");
+    let resp = send_message(&address, &message).await;
+    assert_eq!(resp, empty_string());
+}